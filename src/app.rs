@@ -1,6 +1,5 @@
 //! This module handles the glue between the windowing system winit and the rest of the
 //! application.
-use std::ops::Deref;
 use std::time::Instant;
 
 use winit::application::ApplicationHandler;
@@ -8,15 +7,17 @@ use winit::dpi::PhysicalSize;
 use winit::error::OsError;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
+use winit::monitor::MonitorHandle;
 use winit::window::{Fullscreen, Window, WindowId};
 
 use crate::game::{Game, MainMenu};
 use crate::render::Renderer;
-use crate::settings;
+use crate::settings::{ResolutionState, SettingsSnapshot, VisualSettings};
 
 struct TaikoAppInner {
     game: Game,
     renderer: Renderer,
+    settings: SettingsSnapshot,
 }
 
 pub struct TaikoApp {
@@ -35,22 +36,65 @@ impl TaikoApp {
     }
 }
 
-fn create_window(
-    event_loop: &ActiveEventLoop,
-    settings: impl Deref<Target = settings::Settings>,
-) -> Result<Window, OsError> {
-    let (resolution, fullscreen) = match settings.visual.resolution {
-        settings::ResolutionState::BorderlessFullscreen => {
-            (None, Some(Fullscreen::Borderless(None)))
+/// The fullscreen mode the settings ask for, or the size of the window if they don't ask for one.
+///
+/// `current` is the monitor the window is on (or the primary monitor, before there's a window),
+/// which is used if the settings don't name one of the `monitors`.
+fn window_mode(
+    visual: &VisualSettings,
+    monitors: impl Iterator<Item = MonitorHandle>,
+    current: Option<MonitorHandle>,
+) -> (Option<PhysicalSize<u32>>, Option<Fullscreen>) {
+    let chosen = visual.monitor.as_ref().and_then(|name| {
+        let monitor = monitors
+            .into_iter()
+            .find(|monitor| monitor.name().as_ref() == Some(name));
+
+        if monitor.is_none() {
+            log::warn!("there's no monitor called {name}, so the current one will be used");
         }
-        settings::ResolutionState::Windowed(width, height) => {
-            (Some(PhysicalSize::new(width, height)), None)
-        }
-        settings::ResolutionState::Fullscreen { .. } => {
-            // TODO: support this i guess? I dunno
-            todo!()
+
+        monitor
+    });
+
+    match visual.resolution {
+        ResolutionState::Windowed(width, height) => (Some(PhysicalSize::new(width, height)), None),
+
+        ResolutionState::BorderlessFullscreen => (None, Some(Fullscreen::Borderless(chosen))),
+
+        ResolutionState::Fullscreen(width, height) => {
+            // Exclusive fullscreen needs a video mode of a particular monitor, so pick the one
+            // closest to the size asked for, with the highest refresh rate
+            let video_mode = chosen.or(current).and_then(|monitor| {
+                monitor.video_modes().min_by_key(|mode| {
+                    let size = mode.size();
+                    (
+                        size.width.abs_diff(width) + size.height.abs_diff(height),
+                        std::cmp::Reverse(mode.refresh_rate_millihertz()),
+                    )
+                })
+            });
+
+            match video_mode {
+                Some(video_mode) => (None, Some(Fullscreen::Exclusive(video_mode))),
+                None => {
+                    log::warn!(
+                        "couldn't find a video mode for {width}x{height}, so the game will be \
+                        borderless instead"
+                    );
+                    (None, Some(Fullscreen::Borderless(None)))
+                }
+            }
         }
-    };
+    }
+}
+
+fn create_window(event_loop: &ActiveEventLoop, visual: &VisualSettings) -> Result<Window, OsError> {
+    let (resolution, fullscreen) = window_mode(
+        visual,
+        event_loop.available_monitors(),
+        event_loop.primary_monitor(),
+    );
 
     let mut attributes = Window::default_attributes()
         .with_title("Unnamed taiko simulator!!")
@@ -63,21 +107,49 @@ fn create_window(
     event_loop.create_window(attributes)
 }
 
+/// Changes the window and the renderer to match any change to the display settings.
+fn apply_display_settings(renderer: &mut Renderer, old: &VisualSettings, new: &VisualSettings) {
+    renderer.set_present_mode(new.present_mode);
+
+    let Some(window) = renderer.window() else {
+        return;
+    };
+
+    if old.resolution == new.resolution && old.monitor == new.monitor {
+        return;
+    }
+
+    let (resolution, fullscreen) =
+        window_mode(new, window.available_monitors(), window.current_monitor());
+    window.set_fullscreen(fullscreen);
+
+    if let Some(resolution) = resolution {
+        // If the window can't be resized straight away it sends a resize event once it has been
+        let _ = window.request_inner_size(resolution);
+    }
+}
+
 impl ApplicationHandler for TaikoApp {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.inner.is_none() {
+            let settings = SettingsSnapshot::new();
             let window =
-                create_window(event_loop, settings::settings()).expect("Couldn't create window");
+                create_window(event_loop, &settings.visual).expect("Couldn't create window");
             // The window has to stay for the entire duration of the program so this is fine
             // just lets us get around wgpu's surface lifetime limitation
             let window = Box::leak(Box::new(window));
-            let mut renderer = Renderer::new(window).expect("Couldn't construct renderer");
+            let mut renderer = Renderer::new(window, settings.visual.present_mode)
+                .expect("Couldn't construct renderer");
             let game = Game::new(&mut renderer, |renderer, textures| {
                 Box::new(MainMenu::new(textures, renderer).unwrap())
             })
             .expect("Couldn't initialise game");
 
-            self.inner = Some(TaikoAppInner { renderer, game });
+            self.inner = Some(TaikoAppInner {
+                renderer,
+                game,
+                settings,
+            });
         }
     }
 
@@ -87,16 +159,21 @@ impl ApplicationHandler for TaikoApp {
         _window_id: WindowId,
         event: WindowEvent,
     ) {
+        // Taken before anything else, so that inputs are timed as closely as they can be to when
+        // they happened. winit doesn't say when the OS saw them, so this is the best there is.
+        let received = Instant::now();
+
         let Some(TaikoAppInner {
             ref mut game,
             ref mut renderer,
+            ..
         }) = self.inner
         else {
             return;
         };
 
         if !renderer.handle_event(&event) {
-            game.handle_event(&event, renderer);
+            game.handle_event(&event, received, renderer);
 
             match event {
                 WindowEvent::CloseRequested => {
@@ -116,21 +193,57 @@ impl ApplicationHandler for TaikoApp {
         let Some(TaikoAppInner {
             ref mut game,
             ref mut renderer,
+            ref mut settings,
         }) = self.inner
         else {
             return;
         };
 
+        if renderer.is_device_lost() {
+            log::warn!("recreating renderer after the GPU device was lost");
+
+            match renderer.recreate() {
+                Ok(()) => game.recreate_gpu_resources(renderer),
+                Err(e) => {
+                    log::error!("couldn't recreate renderer: {e}");
+                    event_loop.exit();
+                    return;
+                }
+            }
+        }
+
         game.update(self.delta, renderer, event_loop);
+
+        // Cloning the snapshot only clones an Arc
+        let old_settings = settings.clone();
+        if settings.refresh() {
+            apply_display_settings(renderer, &old_settings.visual, &settings.visual);
+        }
         match renderer.render(game) {
             Ok(_) => {}
 
+            // The surface needs to be reconfigured, then we can try again next frame
             Err(wgpu::SurfaceError::Lost) | Err(wgpu::SurfaceError::Outdated) => {
                 let size = renderer.size();
                 renderer.resize(*size);
             }
-            Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
-            Err(e) => log::error!("error while rendering: {e:?}"),
+
+            // Nothing to do but skip this frame
+            Err(wgpu::SurfaceError::Timeout) => {
+                log::warn!("timed out waiting for the next frame");
+            }
+
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                if game.is_showing_fatal_error() {
+                    // We can't even draw the error screen, so all we can do is give up.
+                    log::error!("out of GPU memory");
+                    event_loop.exit();
+                } else {
+                    game.show_fatal_error(
+                        "The game ran out of graphics memory and can't continue.",
+                    );
+                }
+            }
         }
 
         let time = Instant::now();
@@ -0,0 +1,528 @@
+//! The backend that kira plays the game's audio through.
+//!
+//! This works like kira's own cpal backend, except that the size of the buffer the audio is played
+//! in can be chosen. Smaller buffers mean less delay between a sound being played and it being
+//! heard (some setups add 40ms or more by default), but if the buffer is too small the audio will
+//! crackle. The backend also keeps track of the buffer size it actually ended up with and how long
+//! audio takes to come out of the speakers, so that they can be shown to the player.
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, Device, OutputCallbackInfo, Stream, StreamConfig, StreamError, SupportedBufferSize,
+    SupportedStreamConfig,
+};
+use kira::manager::backend::{Backend, Renderer};
+
+/// The audio manager used throughout the game.
+pub type AudioManager = kira::manager::AudioManager<AudioBackend>;
+
+/// The buffer sizes (in frames) the player can choose between, as well as the device's default.
+pub const BUFFER_SIZES: [u32; 6] = [64, 128, 256, 512, 1024, 2048];
+
+/// How often the stream thread checks whether the stream needs to be restarted.
+const CHECK_STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Used in place of a latency that hasn't been measured (yet).
+const UNKNOWN_LATENCY: u64 = u64::MAX;
+
+/// What the backend and the stream it plays through know about each other.
+#[derive(Debug)]
+struct Shared {
+    /// The buffer size that was asked for, in frames, or 0 for the device's default.
+    requested_buffer_size: AtomicU32,
+    /// Whether the device couldn't use the requested buffer size, so a different size was used.
+    requested_size_unsupported: AtomicBool,
+    /// The number of frames the last data callback asked for, or 0 if there hasn't been one yet.
+    buffer_size: AtomicU32,
+    sample_rate: AtomicU32,
+    /// The time between the last data callback and when its audio is expected to be heard, in
+    /// microseconds.
+    output_latency: AtomicU64,
+    /// The name of the device the stream was last opened on.
+    device_name: Mutex<Option<String>>,
+    /// Set when the backend is dropped, to stop the stream thread.
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn record_callback(&self, frames: usize, info: &OutputCallbackInfo) {
+        let timestamp = info.timestamp();
+        let latency = timestamp
+            .playback
+            .duration_since(&timestamp.callback)
+            .map_or(UNKNOWN_LATENCY, |latency| latency.as_micros() as u64);
+
+        self.buffer_size.store(frames as u32, Ordering::Relaxed);
+        self.output_latency.store(latency, Ordering::Relaxed);
+    }
+}
+
+/// What the audio output is actually doing, as opposed to what was asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioStatus {
+    /// The buffer size that was asked for in frames, or None for the device's default.
+    pub requested_buffer_size: Option<u32>,
+    /// Whether the device couldn't use the requested buffer size, so a different one is being
+    /// used.
+    pub requested_size_unsupported: bool,
+    /// The number of frames the device asks for at a time, if it's asked for any yet.
+    pub buffer_size: Option<u32>,
+    pub sample_rate: u32,
+    /// How long it takes from when the device asks for audio until it's heard, if the device
+    /// reports it.
+    pub output_latency: Option<Duration>,
+}
+
+impl AudioStatus {
+    /// How long the audio in one buffer lasts, which is roughly how much delay the buffer adds.
+    pub fn buffer_latency(&self) -> Option<Duration> {
+        self.buffer_size
+            .filter(|_| self.sample_rate > 0)
+            .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64))
+    }
+
+    /// Describes the status in a line, for showing to the player.
+    pub fn summary(&self) -> String {
+        let buffer = match (self.buffer_size, self.buffer_latency()) {
+            (Some(frames), Some(latency)) => {
+                format!("{frames} frames ({:.1} ms)", latency.as_secs_f64() * 1000.)
+            }
+            _ => "unknown".to_string(),
+        };
+
+        let output = match self.output_latency {
+            Some(latency) => format!("{:.1} ms", latency.as_secs_f64() * 1000.),
+            None => "not reported".to_string(),
+        };
+
+        format!(
+            "buffer: {buffer} at {} Hz, output latency: {output}",
+            self.sample_rate
+        )
+    }
+}
+
+/// Works out what buffer size to ask the device for, given the size the player asked for (or 0
+/// for the default) and the sizes the device supports. Also returns whether the requested size had
+/// to be changed to one the device supports.
+fn choose_buffer_size(requested: u32, supported: &SupportedBufferSize) -> (BufferSize, bool) {
+    match (requested, supported) {
+        (0, _) => (BufferSize::Default, false),
+        (requested, SupportedBufferSize::Range { min, max }) => {
+            let size = requested.clamp(*min, *max);
+            (BufferSize::Fixed(size), size != requested)
+        }
+        (requested, SupportedBufferSize::Unknown) => (BufferSize::Fixed(requested), false),
+    }
+}
+
+fn default_device() -> anyhow::Result<(Device, SupportedStreamConfig)> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(anyhow::format_err!("no default audio output device"))?;
+    let config = device.default_output_config()?;
+    Ok((device, config))
+}
+
+fn device_name(device: &Device) -> String {
+    device
+        .name()
+        .unwrap_or_else(|_| "device name unavailable".to_string())
+}
+
+/// Holds the renderer while a stream is using it, and sends it back when the stream is dropped,
+/// so that it can be used again for the next stream.
+struct ReturningRenderer {
+    renderer: Option<Renderer>,
+    sender: mpsc::Sender<Renderer>,
+}
+
+impl Drop for ReturningRenderer {
+    fn drop(&mut self) {
+        if let Some(renderer) = self.renderer.take() {
+            let _ = self.sender.send(renderer);
+        }
+    }
+}
+
+fn process(renderer: &mut Renderer, data: &mut [f32], channels: usize) {
+    renderer.on_start_processing();
+
+    for frame in data.chunks_exact_mut(channels) {
+        let out = renderer.process();
+
+        if channels == 1 {
+            frame[0] = (out.left + out.right) / 2.0;
+        } else {
+            frame[0] = out.left;
+            frame[1] = out.right;
+
+            // Anything else has to be silenced, or it'll play whatever was in the buffer before
+            for channel in frame.iter_mut().skip(2) {
+                *channel = 0.0;
+            }
+        }
+    }
+}
+
+/// A stream that is playing audio from the renderer.
+struct OpenStream {
+    stream: Stream,
+    errors: mpsc::Receiver<StreamError>,
+    renderer: mpsc::Receiver<Renderer>,
+    device_name: String,
+    sample_rate: u32,
+    requested_buffer_size: u32,
+}
+
+impl OpenStream {
+    /// Opens a stream on the default device. If that fails, the renderer is given back along with
+    /// the error.
+    ///
+    /// The renderer is big, but it's only moved around when the stream is opened, so it's fine to
+    /// pass it back by value.
+    #[allow(clippy::result_large_err)]
+    fn open(
+        mut renderer: Renderer,
+        shared: &Arc<Shared>,
+    ) -> Result<Self, (Renderer, anyhow::Error)> {
+        let (device, supported) = match default_device() {
+            Ok(device) => device,
+            Err(e) => return Err((renderer, e)),
+        };
+
+        let requested_buffer_size = shared.requested_buffer_size.load(Ordering::Relaxed);
+        let (buffer_size, unsupported) =
+            choose_buffer_size(requested_buffer_size, supported.buffer_size());
+
+        let mut config = supported.config();
+        config.buffer_size = buffer_size;
+
+        let sample_rate = config.sample_rate.0;
+        if sample_rate != shared.sample_rate.swap(sample_rate, Ordering::Relaxed) {
+            renderer.on_change_sample_rate(sample_rate);
+        }
+
+        shared.buffer_size.store(0, Ordering::Relaxed);
+        shared
+            .output_latency
+            .store(UNKNOWN_LATENCY, Ordering::Relaxed);
+        shared
+            .requested_size_unsupported
+            .store(unsupported, Ordering::Relaxed);
+
+        let result = match Self::build(&device, &config, renderer, shared) {
+            // Some devices claim to support sizes that they don't, so fall back to the default
+            Err((renderer, e)) if config.buffer_size != BufferSize::Default => {
+                log::warn!(
+                    "couldn't open audio stream with a buffer size of {:?}: {e}. Using the default instead",
+                    config.buffer_size
+                );
+
+                shared
+                    .requested_size_unsupported
+                    .store(true, Ordering::Relaxed);
+                config.buffer_size = BufferSize::Default;
+                Self::build(&device, &config, renderer, shared)
+            }
+
+            result => result,
+        };
+
+        result.map(|(stream, errors, renderer)| {
+            log::info!(
+                "opened audio stream on {} with buffer size {:?}",
+                device_name(&device),
+                config.buffer_size
+            );
+
+            *shared.device_name.lock().unwrap() = Some(device_name(&device));
+
+            Self {
+                stream,
+                errors,
+                renderer,
+                device_name: device_name(&device),
+                sample_rate,
+                requested_buffer_size,
+            }
+        })
+    }
+
+    #[allow(clippy::type_complexity, clippy::result_large_err)]
+    fn build(
+        device: &Device,
+        config: &StreamConfig,
+        renderer: Renderer,
+        shared: &Arc<Shared>,
+    ) -> Result<
+        (
+            Stream,
+            mpsc::Receiver<StreamError>,
+            mpsc::Receiver<Renderer>,
+        ),
+        (Renderer, anyhow::Error),
+    > {
+        let (renderer_sender, renderer_receiver) = mpsc::channel();
+        let (error_sender, error_receiver) = mpsc::channel();
+
+        let mut renderer = ReturningRenderer {
+            renderer: Some(renderer),
+            sender: renderer_sender,
+        };
+
+        let channels = config.channels as usize;
+        let callback_shared = Arc::clone(shared);
+
+        let stream = device
+            .build_output_stream(
+                config,
+                move |data: &mut [f32], info: &OutputCallbackInfo| {
+                    callback_shared.record_callback(data.len() / channels, info);
+
+                    if let Some(renderer) = renderer.renderer.as_mut() {
+                        process(renderer, data, channels);
+                    }
+                },
+                move |error| {
+                    let _ = error_sender.send(error);
+                },
+                None,
+            )
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| {
+                stream.play()?;
+                Ok(stream)
+            });
+
+        match stream {
+            Ok(stream) => Ok((stream, error_receiver, renderer_receiver)),
+            // The callback (and the renderer with it) was dropped along with the stream
+            Err(e) => Err((
+                renderer_receiver
+                    .recv()
+                    .expect("the renderer should be returned when the stream is dropped"),
+                e,
+            )),
+        }
+    }
+
+    /// Whether the stream has to be opened again, because the device was lost or changed or a
+    /// different buffer size was requested.
+    fn needs_restart(&self, shared: &Shared) -> bool {
+        if let Ok(StreamError::DeviceNotAvailable) = self.errors.try_recv() {
+            log::warn!("audio device was lost, reopening the audio stream");
+            return true;
+        }
+
+        if shared.requested_buffer_size.load(Ordering::Relaxed) != self.requested_buffer_size {
+            return true;
+        }
+
+        // Like kira, don't check for device changes on macos, since querying the device while
+        // audio is playing there causes artifacts. See https://github.com/tesselode/kira/issues/38
+        #[cfg(not(target_os = "macos"))]
+        if let Ok((device, config)) = default_device() {
+            if device_name(&device) != self.device_name
+                || config.sample_rate().0 != self.sample_rate
+            {
+                log::info!("default audio device changed, reopening the audio stream");
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Stops the stream and takes the renderer back.
+    fn close(self) -> Renderer {
+        drop(self.stream);
+        self.renderer
+            .recv()
+            .expect("the renderer should be returned when the stream is dropped")
+    }
+}
+
+/// Keeps the stream going for as long as the backend exists, opening it again whenever the device
+/// is lost or changes, or a different buffer size is requested.
+///
+/// Streams can't be sent between threads, so this opens the first stream too, and sends whether
+/// that worked through `opened`. If it didn't, this gives up straight away.
+fn run_stream(renderer: Renderer, shared: Arc<Shared>, opened: mpsc::Sender<anyhow::Result<()>>) {
+    #[allow(clippy::large_enum_variant)]
+    enum State {
+        Idle(Renderer),
+        Running(OpenStream),
+    }
+
+    let mut state = match OpenStream::open(renderer, &shared) {
+        Ok(stream) => {
+            let _ = opened.send(Ok(()));
+            State::Running(stream)
+        }
+        Err((_, e)) => {
+            let _ = opened.send(Err(e));
+            return;
+        }
+    };
+    let mut logged_failure = false;
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        std::thread::sleep(CHECK_STREAM_INTERVAL);
+
+        state = match state {
+            State::Running(stream) if stream.needs_restart(&shared) => State::Idle(stream.close()),
+            state => state,
+        };
+
+        if let State::Idle(renderer) = state {
+            state = match OpenStream::open(renderer, &shared) {
+                Ok(stream) => {
+                    logged_failure = false;
+                    State::Running(stream)
+                }
+                Err((renderer, e)) => {
+                    // This keeps being tried until it works, so don't fill the log with it
+                    if !std::mem::replace(&mut logged_failure, true) {
+                        log::error!("couldn't open audio stream: {e}");
+                    }
+
+                    State::Idle(renderer)
+                }
+            };
+        }
+    }
+}
+
+/// The settings the backend is created with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioBackendSettings {
+    /// The buffer size to ask the device for in frames, or None for the device's default.
+    pub buffer_size: Option<u32>,
+}
+
+pub struct AudioBackend {
+    shared: Arc<Shared>,
+}
+
+impl AudioBackend {
+    /// Asks for a different buffer size (or the device's default, if None). The stream is opened
+    /// again with the new size shortly after.
+    pub fn set_buffer_size(&self, buffer_size: Option<u32>) {
+        self.shared
+            .requested_buffer_size
+            .store(buffer_size.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The name of the device audio is being played on. This changes when the stream is opened on
+    /// a different device, e.g. when the player plugs in headphones.
+    pub fn device_name(&self) -> Option<String> {
+        self.shared.device_name.lock().unwrap().clone()
+    }
+
+    pub fn status(&self) -> AudioStatus {
+        let shared = &self.shared;
+        let requested = shared.requested_buffer_size.load(Ordering::Relaxed);
+        let buffer_size = shared.buffer_size.load(Ordering::Relaxed);
+        let output_latency = shared.output_latency.load(Ordering::Relaxed);
+
+        AudioStatus {
+            requested_buffer_size: (requested != 0).then_some(requested),
+            requested_size_unsupported: shared.requested_size_unsupported.load(Ordering::Relaxed),
+            buffer_size: (buffer_size != 0).then_some(buffer_size),
+            sample_rate: shared.sample_rate.load(Ordering::Relaxed),
+            output_latency: (output_latency != UNKNOWN_LATENCY)
+                .then(|| Duration::from_micros(output_latency)),
+        }
+    }
+}
+
+impl Backend for AudioBackend {
+    type Settings = AudioBackendSettings;
+    type Error = anyhow::Error;
+
+    fn setup(settings: Self::Settings) -> anyhow::Result<(Self, u32)> {
+        let (_, config) = default_device()?;
+        let sample_rate = config.sample_rate().0;
+
+        let shared = Arc::new(Shared {
+            requested_buffer_size: AtomicU32::new(settings.buffer_size.unwrap_or(0)),
+            requested_size_unsupported: AtomicBool::new(false),
+            buffer_size: AtomicU32::new(0),
+            sample_rate: AtomicU32::new(sample_rate),
+            output_latency: AtomicU64::new(UNKNOWN_LATENCY),
+            device_name: Mutex::new(None),
+            stop: AtomicBool::new(false),
+        });
+
+        Ok((Self { shared }, sample_rate))
+    }
+
+    fn start(&mut self, renderer: Renderer) -> anyhow::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let shared = Arc::clone(&self.shared);
+        std::thread::spawn(move || run_stream(renderer, shared, sender));
+
+        // Wait for the first stream to open, so that if there's no audio at all, the game finds out
+        receiver.recv()?
+    }
+}
+
+impl Drop for AudioBackend {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_choose_buffer_size() {
+        let range = SupportedBufferSize::Range {
+            min: 128,
+            max: 4096,
+        };
+
+        assert_eq!(choose_buffer_size(0, &range), (BufferSize::Default, false));
+        assert_eq!(
+            choose_buffer_size(256, &range),
+            (BufferSize::Fixed(256), false)
+        );
+        assert_eq!(
+            choose_buffer_size(64, &range),
+            (BufferSize::Fixed(128), true)
+        );
+        assert_eq!(
+            choose_buffer_size(64, &SupportedBufferSize::Unknown),
+            (BufferSize::Fixed(64), false)
+        );
+    }
+
+    #[test]
+    fn test_buffer_latency() {
+        let status = AudioStatus {
+            requested_buffer_size: None,
+            requested_size_unsupported: false,
+            buffer_size: Some(480),
+            sample_rate: 48000,
+            output_latency: None,
+        };
+
+        assert_eq!(status.buffer_latency(), Some(Duration::from_millis(10)));
+        assert_eq!(
+            status.summary(),
+            "buffer: 480 frames (10.0 ms) at 48000 Hz, output latency: not reported"
+        );
+
+        let unknown = AudioStatus {
+            buffer_size: None,
+            ..status
+        };
+        assert_eq!(unknown.buffer_latency(), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Information about the five difficulties a song can have.
+//!
+//! Anything that shows a difficulty to the player (or otherwise treats the difficulties
+//! differently) should get what it needs from [DifficultyInfo] rather than hardcoding names or
+//! colours, so that the difficulties look and behave the same everywhere.
+use crate::render::rgb;
+
+// I have to credit OpenTaiko as that's where I got these values.
+// (and also for inspiring me to give making my own simulator a red-hot go)
+// They're in whole milliseconds so that they're exact, and are only converted to seconds (once)
+// when the game starts. See [DifficultyInfo::timing_windows].
+pub const EASY_NORMAL_TIMING_MS: [u32; 3] = [42, 108, 125];
+pub const HARD_EXTREME_TIMING_MS: [u32; 3] = [25, 75, 108];
+
+#[derive(Debug, PartialEq)]
+pub struct DifficultyInfo {
+    /// The index of the difficulty, as used in [Song::difficulties](crate::notechart_parser::Song).
+    pub index: usize,
+    /// A stable identifier for the difficulty. Once there are translations, this is the key the
+    /// name will be looked up with.
+    pub name_key: &'static str,
+    /// The name of the difficulty.
+    pub name: &'static str,
+    /// An abbreviation of the name, for where space is tight.
+    pub short_name: &'static str,
+    pub colour: [f32; 4],
+    /// The timing windows for notes on this difficulty in milliseconds, indexed by judgement.
+    pub timing_windows_ms: [u32; 3],
+}
+
+pub const DIFFICULTIES: [DifficultyInfo; 5] = [
+    DifficultyInfo {
+        index: 0,
+        name_key: "difficulty.easy",
+        name: "Easy",
+        short_name: "E",
+        colour: rgb!(0xF2, 0x6B, 0x1D),
+        timing_windows_ms: EASY_NORMAL_TIMING_MS,
+    },
+    DifficultyInfo {
+        index: 1,
+        name_key: "difficulty.normal",
+        name: "Normal",
+        short_name: "N",
+        colour: rgb!(0x5C, 0xB8, 0x2E),
+        timing_windows_ms: EASY_NORMAL_TIMING_MS,
+    },
+    DifficultyInfo {
+        index: 2,
+        name_key: "difficulty.hard",
+        name: "Hard",
+        short_name: "H",
+        colour: rgb!(0x3D, 0x8F, 0xC4),
+        timing_windows_ms: HARD_EXTREME_TIMING_MS,
+    },
+    DifficultyInfo {
+        index: 3,
+        name_key: "difficulty.oni",
+        name: "Oni",
+        short_name: "O",
+        colour: rgb!(0xE0, 0x35, 0x7E),
+        timing_windows_ms: HARD_EXTREME_TIMING_MS,
+    },
+    DifficultyInfo {
+        index: 4,
+        name_key: "difficulty.ura",
+        name: "Ura",
+        short_name: "U",
+        colour: rgb!(0x7B, 0x3F, 0xC9),
+        timing_windows_ms: HARD_EXTREME_TIMING_MS,
+    },
+];
+
+impl DifficultyInfo {
+    /// Returns the info for the difficulty with the given index, or None if it's out of range.
+    pub fn from_index(index: usize) -> Option<&'static DifficultyInfo> {
+        DIFFICULTIES.get(index)
+    }
+
+    /// Returns the name of the difficulty with the given index, or "Unknown" if it's out of range.
+    pub fn name_of(index: usize) -> &'static str {
+        Self::from_index(index).map_or("Unknown", |info| info.name)
+    }
+
+    /// The timing windows for notes on this difficulty in seconds, indexed by judgement.
+    pub fn timing_windows(&self) -> [f64; 3] {
+        self.timing_windows_ms.map(|ms| ms as f64 / 1000.)
+    }
+
+    /// Returns the timing windows for the difficulty with the given index in seconds, or those of
+    /// the hardest difficulty if it's out of range.
+    pub fn timing_windows_of(index: usize) -> [f64; 3] {
+        Self::from_index(index)
+            .unwrap_or(&DIFFICULTIES[DIFFICULTIES.len() - 1])
+            .timing_windows()
+    }
+
+    /// The colour of the difficulty, for use in egui.
+    pub fn egui_colour(&self) -> egui::Color32 {
+        let [r, g, b, _] = self.colour.map(|c| (c * 255.).round() as u8);
+        egui::Color32::from_rgb(r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table_covers_every_difficulty() {
+        for index in 0..5 {
+            let info = DifficultyInfo::from_index(index).unwrap();
+            assert_eq!(info.index, index);
+            assert!(!info.name.is_empty());
+            assert!(info.name.starts_with(info.short_name));
+            assert_eq!(crate::localisation::tr(info.name_key), info.name);
+        }
+
+        assert_eq!(DifficultyInfo::from_index(5), None);
+        assert_eq!(DifficultyInfo::name_of(5), "Unknown");
+        assert_eq!(
+            DifficultyInfo::timing_windows_of(5),
+            DIFFICULTIES[4].timing_windows()
+        );
+        assert_eq!(
+            DifficultyInfo::timing_windows_of(usize::MAX),
+            [0.025, 0.075, 0.108]
+        );
+    }
+
+    #[test]
+    fn test_egui_colour() {
+        assert_eq!(
+            DIFFICULTIES[0].egui_colour(),
+            egui::Color32::from_rgb(0xF2, 0x6B, 0x1D)
+        );
+    }
+}
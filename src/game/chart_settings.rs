@@ -0,0 +1,297 @@
+//! Settings the player has chosen for a single chart, which are used instead of the global ones
+//! whenever that chart is played: how it's displayed, and an offset for charts that are off-sync.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::notechart_parser::ChartHash;
+use crate::settings::VisualSettings;
+
+/// The file per-chart settings are saved to.
+pub const CHART_SETTINGS_PATH: &str = "chart_settings.toml";
+
+/// The settings a chart overrides. Anything left as None uses the global setting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChartOverrides {
+    /// How much the background is darkened, from 0 (not at all) to 1 (completely black).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_dim: Option<f32>,
+    /// How opaque the header and note field are, from 0 (invisible) to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hud_opacity: Option<f32>,
+    /// How many milliseconds later the notes come, on top of the global offset. Some charts are
+    /// consistently off-sync with their audio, which this corrects for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f32>,
+}
+
+impl ChartOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The display settings a chart is actually played with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChartDisplay {
+    pub background_dim: f32,
+    pub hud_opacity: f32,
+}
+
+impl ChartDisplay {
+    /// Works out the settings to play a chart with: whatever the chart overrides, and the global
+    /// settings for everything else.
+    pub fn resolve(overrides: &ChartOverrides, visual: &VisualSettings) -> Self {
+        Self {
+            background_dim: overrides
+                .background_dim
+                .unwrap_or(visual.background_dim)
+                .clamp(0.0, 1.0),
+            hud_opacity: overrides
+                .hud_opacity
+                .unwrap_or(visual.hud_opacity)
+                .clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// A chart's overrides as they're stored, along with when they were last changed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct StoredOverrides {
+    #[serde(flatten)]
+    overrides: ChartOverrides,
+    /// When the overrides were last changed, in seconds since the unix epoch. Overrides saved
+    /// before this was recorded don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changed_at: Option<i64>,
+}
+
+/// Every chart's overrides, stored by the chart's hash so that they follow the chart if its file
+/// is moved, and stop applying if its notes are changed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChartSettings {
+    #[serde(default)]
+    charts: BTreeMap<String, StoredOverrides>,
+}
+
+impl ChartSettings {
+    /// Reads the per-chart settings file, starting with no overrides if it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The overrides for a chart, which are empty if it doesn't have any.
+    pub fn get(&self, chart: &ChartHash) -> ChartOverrides {
+        self.charts
+            .get(&chart.to_string())
+            .map(|stored| stored.overrides)
+            .unwrap_or_default()
+    }
+
+    /// Sets the overrides for a chart. Charts with no overrides left aren't stored at all.
+    pub fn set(&mut self, chart: &ChartHash, overrides: ChartOverrides) {
+        self.set_at(chart, overrides, chrono::Utc::now().timestamp());
+    }
+
+    /// Sets the overrides for a chart, recording that they were changed at the given time.
+    fn set_at(&mut self, chart: &ChartHash, overrides: ChartOverrides, changed_at: i64) {
+        if overrides.is_empty() {
+            self.charts.remove(&chart.to_string());
+        } else {
+            self.charts.insert(
+                chart.to_string(),
+                StoredOverrides {
+                    overrides,
+                    changed_at: Some(changed_at),
+                },
+            );
+        }
+    }
+
+    /// Takes in the overrides from another copy of the per-chart settings. Charts only the other
+    /// copy has overrides for are added, and charts both have overrides for keep whichever were
+    /// changed more recently. Overrides with no record of when they were changed count as older
+    /// than any that do, and if neither can be told apart, these ones are kept.
+    ///
+    /// Returns how many charts' overrides were added, and how many were replaced.
+    pub fn merge(&mut self, other: &ChartSettings) -> (usize, usize) {
+        let mut added = 0;
+        let mut replaced = 0;
+
+        for (chart, theirs) in other.charts.iter() {
+            match self.charts.get_mut(chart) {
+                None => {
+                    self.charts.insert(chart.clone(), *theirs);
+                    added += 1;
+                }
+                Some(ours) => {
+                    if theirs.changed_at > ours.changed_at && theirs.overrides != ours.overrides {
+                        *ours = *theirs;
+                        replaced += 1;
+                    }
+                }
+            }
+        }
+
+        (added, replaced)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(byte: u8) -> ChartHash {
+        ChartHash {
+            version: 1,
+            digest: [byte; 32],
+        }
+    }
+
+    #[test]
+    fn test_resolve_falls_back_to_global_settings() {
+        let visual = VisualSettings {
+            background_dim: 0.4,
+            hud_opacity: 0.8,
+            ..Default::default()
+        };
+
+        // Nothing overridden uses the global settings
+        let display = ChartDisplay::resolve(&ChartOverrides::default(), &visual);
+        assert_eq!(display.background_dim, 0.4);
+        assert_eq!(display.hud_opacity, 0.8);
+
+        // Each setting falls back on its own
+        let overrides = ChartOverrides {
+            background_dim: Some(0.9),
+            hud_opacity: None,
+            offset: None,
+        };
+        let display = ChartDisplay::resolve(&overrides, &visual);
+        assert_eq!(display.background_dim, 0.9);
+        assert_eq!(display.hud_opacity, 0.8);
+
+        // A chart with no stored overrides uses the global settings too
+        let mut settings = ChartSettings::default();
+        settings.set(&hash(1), overrides);
+        let display = ChartDisplay::resolve(&settings.get(&hash(2)), &visual);
+        assert_eq!(display.background_dim, 0.4);
+        assert_eq!(
+            ChartDisplay::resolve(&settings.get(&hash(1)), &visual).background_dim,
+            0.9
+        );
+
+        // Values out of range are clamped
+        let overrides = ChartOverrides {
+            background_dim: Some(1.5),
+            hud_opacity: Some(-1.0),
+            offset: None,
+        };
+        let display = ChartDisplay::resolve(&overrides, &visual);
+        assert_eq!(display.background_dim, 1.0);
+        assert_eq!(display.hud_opacity, 0.0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("taiko-chart-settings-{}", std::process::id()));
+        assert_eq!(
+            ChartSettings::load(&path).unwrap(),
+            ChartSettings::default()
+        );
+
+        let mut settings = ChartSettings::default();
+        settings.set(
+            &hash(1),
+            ChartOverrides {
+                background_dim: Some(0.25),
+                hud_opacity: None,
+                offset: None,
+            },
+        );
+        settings.set(
+            &hash(2),
+            ChartOverrides {
+                background_dim: None,
+                hud_opacity: Some(0.5),
+                offset: Some(-15.0),
+            },
+        );
+        settings.save(&path).unwrap();
+
+        let loaded = ChartSettings::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, settings);
+
+        // Clearing every override forgets the chart
+        settings.set(&hash(1), ChartOverrides::default());
+        assert_eq!(settings.charts.len(), 1);
+    }
+
+    #[test]
+    fn test_overrides_without_a_change_time_still_load() {
+        let settings: ChartSettings = toml::from_str(&format!(
+            "[charts.\"{}\"]\nbackground_dim = 0.75\n",
+            hash(1)
+        ))
+        .unwrap();
+
+        assert_eq!(settings.get(&hash(1)).background_dim, Some(0.75));
+        assert_eq!(settings.charts[&hash(1).to_string()].changed_at, None);
+    }
+
+    #[test]
+    fn test_merge_keeps_newer_overrides() {
+        let dim = |value| ChartOverrides {
+            background_dim: Some(value),
+            hud_opacity: None,
+            offset: None,
+        };
+
+        let mut ours = ChartSettings::default();
+        ours.set_at(&hash(1), dim(0.1), 100);
+        ours.set_at(&hash(2), dim(0.2), 300);
+        ours.set_at(&hash(3), dim(0.3), 100);
+        ours.charts.insert(
+            hash(4).to_string(),
+            StoredOverrides {
+                overrides: dim(0.4),
+                changed_at: None,
+            },
+        );
+
+        let mut theirs = ChartSettings::default();
+        // Newer, so it replaces ours
+        theirs.set_at(&hash(1), dim(0.9), 200);
+        // Older, so ours stays
+        theirs.set_at(&hash(2), dim(0.9), 200);
+        // Changed at the same time, so ours stays
+        theirs.set_at(&hash(3), dim(0.9), 100);
+        // Ours has no change time, so it counts as older
+        theirs.set_at(&hash(4), dim(0.9), 50);
+        // Only they have it, so it's added
+        theirs.set_at(&hash(5), dim(0.5), 0);
+
+        assert_eq!(ours.merge(&theirs), (1, 2));
+        assert_eq!(ours.get(&hash(1)), dim(0.9));
+        assert_eq!(ours.get(&hash(2)), dim(0.2));
+        assert_eq!(ours.get(&hash(3)), dim(0.3));
+        assert_eq!(ours.get(&hash(4)), dim(0.9));
+        assert_eq!(ours.get(&hash(5)), dim(0.5));
+
+        // Merging the same settings again changes nothing
+        assert_eq!(ours.merge(&theirs), (0, 0));
+    }
+}
@@ -0,0 +1,197 @@
+//! Estimating how likely the player is to clear a chart, from how they've done on charts of other
+//! levels.
+//!
+//! The chance of clearing is modelled as a logistic curve over the star level, fitted to the
+//! player's recent plays. The curve is pulled gently towards each star making a clear a bit less
+//! likely, so that a history of nothing but clears (or nothing but fails) still gives a sensible
+//! curve rather than a flat one.
+use crate::notechart_parser::ESTIMATE_TOLERANCE;
+
+use super::scores::ScoreDatabase;
+
+/// Fewer plays than this aren't enough to say anything.
+pub const MIN_PLAYS: usize = 10;
+
+/// Only this many of the most recent plays are used, so that the estimate keeps up as the player
+/// gets better.
+pub const RECENT_PLAYS: usize = 50;
+
+/// How much the log-odds of clearing are expected to drop with each star, before looking at any
+/// plays.
+const PRIOR_SLOPE: f64 = -1.0;
+
+/// How far the fitted slope and intercept are allowed to stray from the prior. Smaller values pull
+/// them in harder.
+const SLOPE_SPREAD: f64 = 1.0;
+const INTERCEPT_SPREAD: f64 = 3.0;
+
+/// The most steps taken to fit the curve. It usually settles in a handful.
+const MAX_ITERATIONS: usize = 50;
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A fitted curve giving the chance of clearing a chart of a given level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearModel {
+    intercept: f64,
+    slope: f64,
+    /// The average level of the plays, which levels are measured from so that the intercept is
+    /// the log-odds of clearing a typical chart for the player.
+    centre: f64,
+}
+
+impl ClearModel {
+    /// Fits the model to (star level, cleared) pairs. Returns None if there are fewer than
+    /// [MIN_PLAYS] of them.
+    pub fn fit(plays: &[(f64, bool)]) -> Option<Self> {
+        if plays.len() < MIN_PLAYS {
+            return None;
+        }
+
+        let centre = plays.iter().map(|(level, _)| level).sum::<f64>() / plays.len() as f64;
+        let (mut intercept, mut slope) = (0.0, PRIOR_SLOPE);
+
+        // Newton's method on the log posterior, which only has the one peak
+        for _ in 0..MAX_ITERATIONS {
+            let mut gradient = [
+                intercept / INTERCEPT_SPREAD.powi(2),
+                (slope - PRIOR_SLOPE) / SLOPE_SPREAD.powi(2),
+            ];
+            let mut hessian = [
+                1.0 / INTERCEPT_SPREAD.powi(2),
+                0.0,
+                1.0 / SLOPE_SPREAD.powi(2),
+            ];
+
+            for &(level, cleared) in plays {
+                let x = level - centre;
+                let p = sigmoid(intercept + slope * x);
+                let error = p - if cleared { 1.0 } else { 0.0 };
+                let weight = p * (1.0 - p);
+
+                gradient[0] += error;
+                gradient[1] += error * x;
+                hessian[0] += weight;
+                hessian[1] += weight * x;
+                hessian[2] += weight * x * x;
+            }
+
+            let determinant = hessian[0] * hessian[2] - hessian[1] * hessian[1];
+            let step = [
+                (hessian[2] * gradient[0] - hessian[1] * gradient[1]) / determinant,
+                (hessian[0] * gradient[1] - hessian[1] * gradient[0]) / determinant,
+            ];
+
+            intercept -= step[0];
+            slope -= step[1];
+
+            if step[0].abs() < 1e-9 && step[1].abs() < 1e-9 {
+                break;
+            }
+        }
+
+        Some(Self {
+            intercept,
+            slope,
+            centre,
+        })
+    }
+
+    /// Fits the model to the most recent [RECENT_PLAYS] plays in the score database.
+    pub fn from_history(scores: &ScoreDatabase) -> Option<Self> {
+        let pairs = scores
+            .recent(RECENT_PLAYS)
+            .iter()
+            .map(|play| (play.star_level as f64, play.cleared))
+            .collect::<Vec<_>>();
+
+        Self::fit(&pairs)
+    }
+
+    /// The chance of clearing a chart of the given level, from 0 to 1.
+    pub fn clear_chance(&self, level: f64) -> f64 {
+        sigmoid(self.intercept + self.slope * (level - self.centre))
+    }
+}
+
+/// The level to judge a chart by. This is its declared star level, unless the level estimated from
+/// its notes is so far off that the declared level is probably wrong.
+pub fn judged_level(star_level: u8, estimated_level: f32) -> f64 {
+    if (estimated_level - star_level as f32).abs() > ESTIMATE_TOLERANCE {
+        estimated_level as f64
+    } else {
+        star_level as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn history(plays: &[(u8, bool)], repeats: usize) -> Vec<(f64, bool)> {
+        plays
+            .iter()
+            .map(|&(level, cleared)| (level as f64, cleared))
+            .cycle()
+            .take(plays.len() * repeats)
+            .collect()
+    }
+
+    #[test]
+    fn test_not_enough_plays() {
+        assert_eq!(ClearModel::fit(&history(&[(5, true)], MIN_PLAYS - 1)), None);
+        assert!(ClearModel::fit(&history(&[(5, true)], MIN_PLAYS)).is_some());
+    }
+
+    #[test]
+    fn test_all_clears() {
+        let model = ClearModel::fit(&history(&[(4, true), (5, true), (6, true)], 5)).unwrap();
+
+        assert!(model.clear_chance(5.0) > 0.9, "{model:?}");
+        // Harder than anything played is still less certain
+        assert!(model.clear_chance(10.0) < model.clear_chance(5.0));
+        assert!(model.clear_chance(10.0).is_finite());
+    }
+
+    #[test]
+    fn test_all_fails() {
+        let model = ClearModel::fit(&history(&[(7, false), (8, false)], 6)).unwrap();
+
+        assert!(model.clear_chance(8.0) < 0.1, "{model:?}");
+        assert!(model.clear_chance(3.0) > model.clear_chance(8.0));
+    }
+
+    #[test]
+    fn test_mixed_history() {
+        let model = ClearModel::fit(&history(
+            &[
+                (4, true),
+                (5, true),
+                (6, true),
+                (7, true),
+                (7, false),
+                (8, false),
+                (9, false),
+            ],
+            4,
+        ))
+        .unwrap();
+
+        assert!(model.clear_chance(5.0) > 0.8, "{model:?}");
+        assert!(model.clear_chance(9.0) < 0.2, "{model:?}");
+        assert!((model.clear_chance(7.0) - 0.5).abs() < 0.2, "{model:?}");
+
+        let chances = (1..=10)
+            .map(|level| model.clear_chance(level as f64))
+            .collect::<Vec<_>>();
+        assert!(chances.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn test_judged_level() {
+        assert_eq!(judged_level(7, 7.8), 7.0);
+        assert_eq!(judged_level(3, 8.5), 8.5);
+    }
+}
@@ -0,0 +1,231 @@
+//! The player's favourite songs, and any collections of songs they've put together.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::notechart_parser::Song;
+
+/// The file favourites and collections are saved to.
+pub const COLLECTIONS_PATH: &str = "collections.toml";
+
+/// A named, ordered list of songs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    pub name: String,
+    pub songs: Vec<PathBuf>,
+}
+
+/// Songs are stored by the path to their chart, so a song that's been moved or deleted just won't
+/// be found any more rather than turning into a different song.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collections {
+    #[serde(default)]
+    pub favourites: Vec<PathBuf>,
+    #[serde(default)]
+    pub collections: Vec<Collection>,
+}
+
+/// What merging in another set of collections added (see [Collections::merge]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollectionsMerge {
+    /// How many songs were favourited.
+    pub favourites: usize,
+    /// How many collections were added.
+    pub collections: usize,
+    /// How many songs were added to collections, new or not.
+    pub songs: usize,
+}
+
+/// The path a song is stored under. Songs that weren't loaded from a chart file fall back to their
+/// audio file, which is at least unique to the song.
+pub fn song_key(song: &Song) -> PathBuf {
+    song.chart_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(&song.audio_filename))
+}
+
+impl Collections {
+    /// Reads the collections file, starting with no collections if it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_favourite(&self, song: &Path) -> bool {
+        self.favourites.iter().any(|s| s == song)
+    }
+
+    /// Favourites a song if it isn't already, or unfavourites it if it is. Returns whether the song
+    /// is now a favourite.
+    pub fn toggle_favourite(&mut self, song: &Path) -> bool {
+        if self.is_favourite(song) {
+            self.favourites.retain(|s| s != song);
+            false
+        } else {
+            self.favourites.push(song.to_owned());
+            true
+        }
+    }
+
+    /// Adds a song to the end of a collection. Returns false if the song was already in it.
+    pub fn add_to(&mut self, collection: usize, song: &Path) -> bool {
+        let songs = &mut self.collections[collection].songs;
+
+        if songs.iter().any(|s| s == song) {
+            false
+        } else {
+            songs.push(song.to_owned());
+            true
+        }
+    }
+
+    /// Takes in the favourites and collections from another set of collections (e.g. from another
+    /// computer). Songs that are favourited in either are favourited in both, and collections with
+    /// the same name are combined, with any songs only the other one has added to the end.
+    pub fn merge(&mut self, other: &Collections) -> CollectionsMerge {
+        let mut merge = CollectionsMerge::default();
+
+        for song in other.favourites.iter() {
+            if !self.is_favourite(song) {
+                self.favourites.push(song.clone());
+                merge.favourites += 1;
+            }
+        }
+
+        for theirs in other.collections.iter() {
+            let index = match self.collections.iter().position(|c| c.name == theirs.name) {
+                Some(index) => index,
+                None => {
+                    self.collections.push(Collection {
+                        name: theirs.name.clone(),
+                        songs: Vec::new(),
+                    });
+                    merge.collections += 1;
+                    self.collections.len() - 1
+                }
+            };
+
+            for song in theirs.songs.iter() {
+                if self.add_to(index, song) {
+                    merge.songs += 1;
+                }
+            }
+        }
+
+        merge
+    }
+
+    /// Makes a new, empty collection with a name that isn't taken yet, returning its index.
+    pub fn new_collection(&mut self) -> usize {
+        let name = (1..)
+            .map(|i| format!("Collection {i}"))
+            .find(|name| self.collections.iter().all(|c| &c.name != name))
+            .unwrap();
+
+        self.collections.push(Collection {
+            name,
+            songs: Vec::new(),
+        });
+        self.collections.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_favourites_and_collections() {
+        let mut collections = Collections::default();
+        let song = Path::new("songs/a/a.tja");
+
+        assert!(collections.toggle_favourite(song));
+        assert!(collections.is_favourite(song));
+        assert!(!collections.toggle_favourite(song));
+        assert!(!collections.is_favourite(song));
+
+        let first = collections.new_collection();
+        let second = collections.new_collection();
+        assert_eq!(collections.collections[first].name, "Collection 1");
+        assert_eq!(collections.collections[second].name, "Collection 2");
+
+        assert!(collections.add_to(first, song));
+        assert!(!collections.add_to(first, song));
+        assert_eq!(collections.collections[first].songs, vec![song.to_owned()]);
+        assert!(collections.collections[second].songs.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let song = |name: &str| PathBuf::from(format!("songs/{name}/{name}.tja"));
+        let collection = |name: &str, songs: &[&str]| Collection {
+            name: name.to_string(),
+            songs: songs.iter().map(|s| song(s)).collect(),
+        };
+
+        let mut ours = Collections {
+            favourites: vec![song("a"), song("b")],
+            collections: vec![
+                collection("Warmup", &["a", "b"]),
+                collection("Only ours", &["c"]),
+            ],
+        };
+        let theirs = Collections {
+            favourites: vec![song("b"), song("d")],
+            collections: vec![
+                collection("Only theirs", &["e", "e"]),
+                collection("Warmup", &["c", "a"]),
+            ],
+        };
+
+        assert_eq!(
+            ours.merge(&theirs),
+            CollectionsMerge {
+                favourites: 1,
+                collections: 1,
+                songs: 2,
+            }
+        );
+
+        assert_eq!(ours.favourites, vec![song("a"), song("b"), song("d")]);
+        assert_eq!(
+            ours.collections,
+            vec![
+                // Songs only they have go on the end, in their order
+                collection("Warmup", &["a", "b", "c"]),
+                collection("Only ours", &["c"]),
+                // A song can only be in a collection once
+                collection("Only theirs", &["e"]),
+            ]
+        );
+
+        // Merging the same collections again changes nothing
+        let merged = ours.clone();
+        assert_eq!(ours.merge(&theirs), CollectionsMerge::default());
+        assert_eq!(ours, merged);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("taiko-collections-{}", std::process::id()));
+        assert_eq!(Collections::load(&path).unwrap(), Collections::default());
+
+        let mut collections = Collections::default();
+        collections.toggle_favourite(Path::new("songs/a/a.tja"));
+        let index = collections.new_collection();
+        collections.add_to(index, Path::new("songs/b/b.tja"));
+
+        collections.save(&path).unwrap();
+        assert_eq!(Collections::load(&path).unwrap(), collections);
+        fs::remove_file(path).unwrap();
+    }
+}
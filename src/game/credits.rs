@@ -1,7 +1,7 @@
 use egui::RichText;
-use kira::manager::AudioManager;
 
-use crate::game::{Context, GameState, StateTransition};
+use crate::audio::AudioManager;
+use crate::game::{Context, GameState, SoundEffect, StateTransition};
 
 pub struct CreditsScreen {
     exit: bool,
@@ -14,8 +14,9 @@ impl CreditsScreen {
 }
 
 impl GameState for CreditsScreen {
-    fn update(&mut self, _ctx: &mut Context, _dt: f32) -> StateTransition {
+    fn update(&mut self, ctx: &mut Context, _dt: f32) -> StateTransition {
         if self.exit {
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
             StateTransition::Pop
         } else {
             StateTransition::Continue
@@ -36,4 +37,8 @@ impl GameState for CreditsScreen {
                 }
             });
     }
+
+    fn recreate_gpu_resources(&mut self, _ctx: &mut Context) -> bool {
+        true
+    }
 }
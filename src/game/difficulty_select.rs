@@ -0,0 +1,249 @@
+//! The difficulty select, which opens in song select when a song is chosen. It shows a card for
+//! each of the song's difficulties with its level in stars, how well the player has done on it and
+//! a graph of how busy its chart is, and the difficulty chosen is the one that's played.
+use egui::{pos2, vec2, Color32, Rect, RichText, Sense, Stroke};
+
+use crate::difficulty::DIFFICULTIES;
+use crate::game::scores::{BestRecord, Crown};
+use crate::notechart_parser::{Branch, Difficulty, NoteChart, Song};
+
+/// How many bars the note density graph of each difficulty has.
+const DENSITY_BARS: usize = 40;
+
+/// The most stars a level is shown with. Levels above this are still written out.
+const MAX_STARS: u8 = 10;
+
+const CARD_WIDTH: f32 = 170.0;
+const GRAPH_HEIGHT: f32 = 40.0;
+
+const STAR_COLOUR: Color32 = Color32::from_rgb(0xFF, 0xD0, 0x30);
+const DONDERFUL_COLOUR: Color32 = Color32::from_rgb(0xFF, 0x80, 0xC0);
+const FULL_COMBO_COLOUR: Color32 = Color32::from_rgb(0xFF, 0xC8, 0x40);
+const CLEAR_COLOUR: Color32 = Color32::from_rgb(0xD0, 0xD8, 0xE8);
+const FAILED_COLOUR: Color32 = Color32::from_gray(120);
+
+/// The colour a crown is shown in, here and on the results.
+pub fn crown_colour(crown: Crown) -> Color32 {
+    match crown {
+        Crown::Failed => FAILED_COLOUR,
+        Crown::Clear => CLEAR_COLOUR,
+        Crown::FullCombo => FULL_COMBO_COLOUR,
+        Crown::Donderful => DONDERFUL_COLOUR,
+    }
+}
+
+/// How many of a chart's notes are in each of `bars` equal parts of it, from its first note to its
+/// last, as a fraction of the number in the busiest part. Returns None if the chart has no notes.
+fn note_density(chart: &NoteChart, bars: usize) -> Option<Vec<f32>> {
+    // Branched charts are shown as they're played on the master branch, the busiest way through
+    let notes = chart.on_branch(Branch::Master).notes;
+    let first = notes.first()?.time;
+    let length = (notes.last()?.time - first).max(f64::EPSILON);
+
+    let mut counts = vec![0u32; bars];
+    for note in notes.iter() {
+        let bar = ((note.time - first) / length * bars as f64) as usize;
+        counts[bar.min(bars - 1)] += 1;
+    }
+
+    let busiest = *counts.iter().max()? as f32;
+    Some(
+        counts
+            .into_iter()
+            .map(|count| count as f32 / busiest)
+            .collect(),
+    )
+}
+
+/// The next difficulty the song has after `difficulty` (or before it, if `forwards` isn't set), or
+/// `difficulty` itself if there isn't one.
+pub fn step_difficulty(song: &Song, difficulty: usize, forwards: bool) -> usize {
+    let has = |i: &usize| song.difficulties[*i].is_some();
+
+    let next = if forwards {
+        (difficulty + 1..song.difficulties.len()).find(has)
+    } else {
+        (0..difficulty).rev().find(has)
+    };
+
+    next.unwrap_or(difficulty)
+}
+
+/// The difficulty to start off highlighting for a song: `preferred` if the song has it, or else
+/// its hardest difficulty.
+pub fn initial_difficulty(song: &Song, preferred: usize) -> usize {
+    if song
+        .difficulties
+        .get(preferred)
+        .is_some_and(Option::is_some)
+    {
+        preferred
+    } else {
+        (0..song.difficulties.len())
+            .rev()
+            .find(|&i| song.difficulties[i].is_some())
+            .unwrap_or(preferred)
+    }
+}
+
+/// The note density graphs of the selected song's difficulties. Working them out means going
+/// through every note, so they're only worked out again when a different song is selected.
+#[derive(Debug, Default)]
+pub struct DifficultySelect {
+    /// The id of the song the graphs are for
+    song_id: Option<usize>,
+    densities: Vec<Option<Vec<f32>>>,
+}
+
+impl DifficultySelect {
+    /// Works out the graphs for the given song (and its id), unless they're already for it.
+    pub fn update(&mut self, song: Option<(usize, &Song)>) {
+        let song_id = song.map(|(id, _)| id);
+        if song_id == self.song_id {
+            return;
+        }
+
+        self.song_id = song_id;
+        self.densities = song.map_or_else(Vec::new, |(_, song)| {
+            song.difficulties
+                .iter()
+                .map(|d| {
+                    d.as_ref()
+                        .and_then(|d| note_density(&d.chart, DENSITY_BARS))
+                })
+                .collect()
+        });
+    }
+
+    /// Shows the card of one difficulty. Clicking on it chooses the difficulty.
+    pub fn card_ui(
+        &self,
+        ui: &mut egui::Ui,
+        difficulty: usize,
+        course: &Difficulty,
+        best: Option<&BestRecord>,
+        highlighted: bool,
+    ) -> egui::Response {
+        let info = &DIFFICULTIES[difficulty];
+        let stroke = if highlighted {
+            Stroke::new(3.0, Color32::WHITE)
+        } else {
+            Stroke::new(1.0, Color32::from_gray(90))
+        };
+
+        egui::Frame::group(ui.style())
+            .stroke(stroke)
+            .show(ui, |ui| {
+                ui.set_width(CARD_WIDTH);
+
+                ui.label(
+                    RichText::new(info.name)
+                        .color(info.egui_colour())
+                        .size(20.0)
+                        .strong(),
+                );
+
+                let stars = course.star_level.min(MAX_STARS) as usize;
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    ui.label(RichText::new("★".repeat(stars)).color(STAR_COLOUR));
+                    ui.label(
+                        RichText::new("★".repeat(MAX_STARS as usize - stars))
+                            .color(Color32::from_gray(70)),
+                    );
+                    ui.add_space(6.0);
+                    ui.label(course.star_level.to_string());
+                });
+
+                match best {
+                    Some(best) => {
+                        ui.label(
+                            RichText::new(best.crown.name())
+                                .color(crown_colour(best.crown))
+                                .strong(),
+                        );
+                        ui.label(format!("High score: {}", best.score));
+                    }
+                    None => {
+                        ui.label(RichText::new("Not played").weak());
+                        ui.label("");
+                    }
+                }
+
+                let density = self.densities.get(difficulty).and_then(Option::as_deref);
+                density_graph_ui(ui, density, info.egui_colour());
+            })
+            .response
+            .interact(Sense::click())
+    }
+}
+
+/// A bar graph of how busy a chart is over time (see [note_density]).
+fn density_graph_ui(ui: &mut egui::Ui, density: Option<&[f32]>, colour: Color32) {
+    let (rect, response) = ui.allocate_exact_size(vec2(CARD_WIDTH, GRAPH_HEIGHT), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, Color32::from_gray(25));
+
+    let Some(density) = density else {
+        return;
+    };
+
+    let bar_width = rect.width() / density.len() as f32;
+    for (i, amount) in density.iter().enumerate() {
+        let left = rect.left() + i as f32 * bar_width;
+        let bar = Rect::from_min_max(
+            pos2(left, rect.bottom() - amount * rect.height()),
+            pos2(left + bar_width - 1.0, rect.bottom()),
+        );
+        painter.rect_filled(bar, 0.0, colour);
+    }
+
+    response.on_hover_text("How many notes there are over the course of the chart");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::{Note, NoteType};
+
+    #[test]
+    fn test_note_density() {
+        let note = |time| Note {
+            note_type: NoteType::Don,
+            time,
+            scroll_speed: 1.0,
+            vertical_speed: 0.0,
+        };
+        let chart = NoteChart {
+            notes: [0.0, 1.0, 2.0, 3.0, 3.5].map(note).to_vec(),
+            ..Default::default()
+        };
+
+        let density = note_density(&chart, 2).unwrap();
+        assert_eq!(density, [2.0 / 3.0, 1.0]);
+        assert_eq!(note_density(&NoteChart::default(), 2), None);
+    }
+
+    #[test]
+    fn test_step_difficulty() {
+        let mut song = Song::default();
+        for i in [1, 3] {
+            song.difficulties[i] = Some(Difficulty {
+                star_level: 5,
+                estimated_level: 5.0,
+                notes_designer: None,
+                chart: NoteChart::default(),
+                player_charts: None,
+            });
+        }
+
+        assert_eq!(step_difficulty(&song, 1, true), 3);
+        assert_eq!(step_difficulty(&song, 3, true), 3);
+        assert_eq!(step_difficulty(&song, 3, false), 1);
+        assert_eq!(step_difficulty(&song, 1, false), 1);
+
+        assert_eq!(initial_difficulty(&song, 1), 1);
+        assert_eq!(initial_difficulty(&song, 4), 3);
+        assert_eq!(initial_difficulty(&song, 0), 3);
+    }
+}
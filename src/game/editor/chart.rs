@@ -0,0 +1,554 @@
+//! The chart being edited, and the history of edits made to it.
+//!
+//! While editing, notes are kept on a grid of [TICKS_PER_MEASURE] ticks per measure instead of at
+//! times in seconds, so that placing and deleting notes is always exact. The chart is only turned
+//! into times when it's played or saved.
+use std::collections::BTreeMap;
+
+use crate::notechart_parser::{
+    Barline, BpmChange, Difficulty, Note, NoteChart, NoteType, ScrollMode,
+};
+
+/// How many ticks each measure is split into. This is divisible by every snap in [SNAPS].
+pub const TICKS_PER_MEASURE: u32 = 192;
+
+/// The grids notes can be snapped to, as the number of divisions per measure.
+pub const SNAPS: [u32; 8] = [4, 8, 12, 16, 24, 32, 48, 64];
+
+/// A note that can be placed in the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorNote {
+    Don,
+    Kat,
+    BigDon,
+    BigKat,
+    /// A drumroll lasting `length` ticks.
+    Roll {
+        length: u32,
+    },
+    BigRoll {
+        length: u32,
+    },
+}
+
+impl EditorNote {
+    /// Every kind of note. The rolls in here don't have a length yet: that's chosen when they're
+    /// placed.
+    pub const ALL: [EditorNote; 6] = [
+        EditorNote::Don,
+        EditorNote::Kat,
+        EditorNote::BigDon,
+        EditorNote::BigKat,
+        EditorNote::Roll { length: 0 },
+        EditorNote::BigRoll { length: 0 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EditorNote::Don => "Don",
+            EditorNote::Kat => "Kat",
+            EditorNote::BigDon => "Big don",
+            EditorNote::BigKat => "Big kat",
+            EditorNote::Roll { .. } => "Drumroll",
+            EditorNote::BigRoll { .. } => "Big drumroll",
+        }
+    }
+
+    pub fn is_big(&self) -> bool {
+        matches!(
+            self,
+            EditorNote::BigDon | EditorNote::BigKat | EditorNote::BigRoll { .. }
+        )
+    }
+
+    pub fn is_don(&self) -> bool {
+        matches!(self, EditorNote::Don | EditorNote::BigDon)
+    }
+
+    /// How many ticks the note lasts for, which is 0 for anything but a roll.
+    pub fn length(&self) -> u32 {
+        match *self {
+            EditorNote::Roll { length } | EditorNote::BigRoll { length } => length,
+            _ => 0,
+        }
+    }
+
+    /// The same kind of note, lasting for `length` ticks if it's a roll.
+    pub fn with_length(self, length: u32) -> Self {
+        match self {
+            EditorNote::Roll { .. } => EditorNote::Roll { length },
+            EditorNote::BigRoll { .. } => EditorNote::BigRoll { length },
+            note => note,
+        }
+    }
+
+    pub fn is_roll(&self) -> bool {
+        matches!(self, EditorNote::Roll { .. } | EditorNote::BigRoll { .. })
+    }
+
+    /// The editor's version of a note, with a roll lasting however many ticks `ticks_of` says its
+    /// length in seconds is. Notes the editor can't handle yet (like balloons) are None.
+    fn from_note_type(note_type: NoteType, ticks_of: impl Fn(f32) -> u32) -> Option<Self> {
+        match note_type {
+            NoteType::Don => Some(EditorNote::Don),
+            NoteType::Kat => Some(EditorNote::Kat),
+            NoteType::BigDon => Some(EditorNote::BigDon),
+            NoteType::BigKat => Some(EditorNote::BigKat),
+            NoteType::Roll(length) => Some(EditorNote::Roll {
+                length: ticks_of(length),
+            }),
+            NoteType::BigRoll(length) => Some(EditorNote::BigRoll {
+                length: ticks_of(length),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The note as it's played, with a roll lasting `seconds_of` its length in ticks.
+    fn note_type(&self, seconds_of: impl Fn(u32) -> f32) -> NoteType {
+        match *self {
+            EditorNote::Don => NoteType::Don,
+            EditorNote::Kat => NoteType::Kat,
+            EditorNote::BigDon => NoteType::BigDon,
+            EditorNote::BigKat => NoteType::BigKat,
+            EditorNote::Roll { length } => NoteType::Roll(seconds_of(length)),
+            EditorNote::BigRoll { length } => NoteType::BigRoll(seconds_of(length)),
+        }
+    }
+}
+
+/// A single change to the chart, with enough information to undo it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    /// A note was placed, replacing whatever was there before.
+    Place {
+        tick: u32,
+        note: EditorNote,
+        replaced: Option<EditorNote>,
+    },
+    Delete {
+        tick: u32,
+        note: EditorNote,
+    },
+}
+
+/// A chart with a constant BPM in 4/4 time, which is all the editor can make so far.
+///
+/// Nothing can be placed under a drumroll: notes have to come after the tick the roll ends on.
+#[derive(Debug, Clone)]
+pub struct EditorChart {
+    bpm: f32,
+    offset: f32,
+    measures: u32,
+    notes: BTreeMap<u32, EditorNote>,
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl EditorChart {
+    /// Creates an empty chart with the given number of measures. `bpm` and `offset` are those of
+    /// the song.
+    pub fn new(bpm: f32, offset: f32, measures: u32) -> Self {
+        Self {
+            bpm,
+            offset,
+            measures: measures.max(1),
+            notes: BTreeMap::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+        }
+    }
+
+    /// Loads the notes of an existing chart, with at least `measures` measures. Notes the editor
+    /// can't handle yet (like balloons, or anything in a branch) are left out, and the number left
+    /// out is returned along with the chart.
+    pub fn from_difficulty(
+        bpm: f32,
+        offset: f32,
+        measures: u32,
+        difficulty: &Difficulty,
+    ) -> (Self, usize) {
+        let chart_measures = difficulty.chart.barlines.len().saturating_sub(1) as u32;
+        let mut chart = Self::new(bpm, offset, measures.max(chart_measures));
+        let mut skipped = difficulty.chart.all_notes().len() - difficulty.chart.notes.len();
+
+        for note in difficulty.chart.notes.iter() {
+            let tick = chart.tick_at(note.time);
+            let ticks_of =
+                |length: f32| (chart.tick_at(note.time + length as f64) - tick).round() as u32;
+
+            match EditorNote::from_note_type(note.note_type, ticks_of) {
+                Some(editor_note) if tick >= 0.0 => {
+                    let tick = tick.round() as u32;
+                    let end = tick + editor_note.length();
+                    chart.measures = chart.measures.max(end / TICKS_PER_MEASURE + 1);
+                    chart.notes.insert(tick, editor_note);
+                }
+                _ => skipped += 1,
+            }
+        }
+
+        (chart, skipped)
+    }
+
+    pub fn measures(&self) -> u32 {
+        self.measures
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// Changes the BPM of the chart, keeping every note on the same beat (so they move in time).
+    /// The chart is made at least `measures` long, in case it no longer covers the whole song.
+    ///
+    /// This can't be undone, as it isn't an edit to the notes.
+    pub fn set_bpm(&mut self, bpm: f32, measures: u32) {
+        self.bpm = bpm;
+        self.measures = self.measures.max(measures);
+    }
+
+    /// Changes the offset of the chart, moving every note along with the grid. Like
+    /// [set_bpm](Self::set_bpm), the chart is made at least `measures` long, and this can't be
+    /// undone.
+    pub fn set_offset(&mut self, offset: f32, measures: u32) {
+        self.offset = offset;
+        self.measures = self.measures.max(measures);
+    }
+
+    /// The last tick a note can be placed on.
+    pub fn last_tick(&self) -> u32 {
+        self.measures * TICKS_PER_MEASURE - 1
+    }
+
+    /// The length of a measure, in seconds.
+    pub fn measure_length(&self) -> f64 {
+        240.0 / self.bpm as f64
+    }
+
+    /// The time in the song of a tick, in seconds.
+    pub fn time_of(&self, tick: u32) -> f64 {
+        self.time_at(tick as f64)
+    }
+
+    /// The time in the song of a fractional tick, in seconds.
+    pub fn time_at(&self, tick: f64) -> f64 {
+        tick / TICKS_PER_MEASURE as f64 * self.measure_length() - self.offset as f64
+    }
+
+    /// The (fractional) tick at a time in the song.
+    pub fn tick_at(&self, time: f64) -> f64 {
+        (time + self.offset as f64) / self.measure_length() * TICKS_PER_MEASURE as f64
+    }
+
+    pub fn note_at(&self, tick: u32) -> Option<EditorNote> {
+        self.notes.get(&tick).copied()
+    }
+
+    /// The notes with ticks in the given range, in order, along with any roll that started
+    /// before the range but is still going in it.
+    pub fn notes_in(
+        &self,
+        ticks: std::ops::Range<u32>,
+    ) -> impl Iterator<Item = (u32, EditorNote)> + '_ {
+        let ongoing = self
+            .roll_covering(ticks.start)
+            .filter(|&(tick, _)| tick < ticks.start);
+
+        ongoing
+            .into_iter()
+            .chain(self.notes.range(ticks).map(|(&tick, &note)| (tick, note)))
+    }
+
+    /// The roll that's still going at a tick (after the tick it starts on), if there is one.
+    pub fn roll_covering(&self, tick: u32) -> Option<(u32, EditorNote)> {
+        let (&start, &note) = self.notes.range(..tick).next_back()?;
+        (start + note.length() >= tick).then_some((start, note))
+    }
+
+    /// Whether a note could be placed at a tick: it has to fit in the chart, a roll has to last at
+    /// least one tick, and it can't overlap a roll (or, if it's a roll, any other note).
+    pub fn can_place(&self, tick: u32, note: EditorNote) -> bool {
+        let end = tick + note.length();
+
+        let fits_under = |end| self.notes.range(tick + 1..=end).next().is_none();
+
+        end <= self.last_tick()
+            && self.roll_covering(tick).is_none()
+            && match note.length() {
+                0 => !note.is_roll(),
+                _ => fits_under(end),
+            }
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.notes.len()
+    }
+
+    fn apply(&mut self, edit: Edit) {
+        match edit {
+            Edit::Place { tick, note, .. } => {
+                self.notes.insert(tick, note);
+            }
+            Edit::Delete { tick, .. } => {
+                self.notes.remove(&tick);
+            }
+        }
+    }
+
+    fn revert(&mut self, edit: Edit) {
+        match edit {
+            Edit::Place { tick, replaced, .. } => match replaced {
+                Some(replaced) => {
+                    self.notes.insert(tick, replaced);
+                }
+                None => {
+                    self.notes.remove(&tick);
+                }
+            },
+            Edit::Delete { tick, note } => {
+                self.notes.insert(tick, note);
+            }
+        }
+    }
+
+    /// Makes an edit and records it so it can be undone. Anything that was undone can't be redone
+    /// after this.
+    fn edit(&mut self, edit: Edit) {
+        self.apply(edit);
+        self.undo_stack.push(edit);
+        self.redo_stack.clear();
+    }
+
+    /// Places a note, replacing any note already at that tick. Returns false (and does nothing) if
+    /// the same note is already there, or if it can't go there (see [can_place](Self::can_place)).
+    pub fn place(&mut self, tick: u32, note: EditorNote) -> bool {
+        let replaced = self.note_at(tick);
+        if replaced == Some(note) || !self.can_place(tick, note) {
+            return false;
+        }
+
+        self.edit(Edit::Place {
+            tick,
+            note,
+            replaced,
+        });
+        true
+    }
+
+    /// Deletes the note at a tick, or the roll going on at it. Returns false if there wasn't one.
+    pub fn delete(&mut self, tick: u32) -> bool {
+        let found = self
+            .note_at(tick)
+            .map(|note| (tick, note))
+            .or_else(|| self.roll_covering(tick));
+
+        match found {
+            Some((tick, note)) => {
+                self.edit(Edit::Delete { tick, note });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Undoes the last edit. Returns the tick it was at, or None if there was nothing to undo.
+    pub fn undo(&mut self) -> Option<u32> {
+        let edit = self.undo_stack.pop()?;
+        self.revert(edit);
+        self.redo_stack.push(edit);
+        Some(edit.tick())
+    }
+
+    /// Redoes the last edit that was undone. Returns the tick it was at, or None if there was
+    /// nothing to redo.
+    pub fn redo(&mut self) -> Option<u32> {
+        let edit = self.redo_stack.pop()?;
+        self.apply(edit);
+        self.undo_stack.push(edit);
+        Some(edit.tick())
+    }
+
+    /// Turns the chart into one that can be played or saved.
+    pub fn to_note_chart(&self) -> NoteChart {
+        let scroll_speed = self.bpm / 120.0;
+
+        NoteChart {
+            notes: self
+                .notes
+                .iter()
+                .map(|(&tick, note)| Note {
+                    note_type: note.note_type(|length| {
+                        (self.time_of(tick + length) - self.time_of(tick)) as f32
+                    }),
+                    time: self.time_of(tick),
+                    scroll_speed,
+                    vertical_speed: 0.0,
+                })
+                .collect(),
+            barlines: (0..=self.measures)
+                .map(|measure| Barline {
+                    time: self.time_of(measure * TICKS_PER_MEASURE) as f32,
+                    scroll_speed,
+                    vertical_speed: 0.0,
+                })
+                .collect(),
+            // The editor doesn't have go-go time or branches yet
+            gogo_regions: Vec::new(),
+            branch_sections: Vec::new(),
+            bpm_changes: vec![BpmChange {
+                time: self.time_of(0) as f32,
+                bpm: self.bpm,
+            }],
+            delays: Vec::new(),
+            scroll_mode: ScrollMode::Normal,
+        }
+    }
+}
+
+impl Edit {
+    fn tick(&self) -> u32 {
+        match *self {
+            Edit::Place { tick, .. } | Edit::Delete { tick, .. } => tick,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::parse_tja_file;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut chart = EditorChart::new(120., 0., 4);
+
+        assert!(chart.place(0, EditorNote::Don));
+        assert!(chart.place(48, EditorNote::Kat));
+        assert!(!chart.place(48, EditorNote::Kat));
+        assert!(chart.place(48, EditorNote::BigKat));
+        assert!(chart.delete(0));
+        assert!(!chart.delete(0));
+        assert_eq!(chart.note_count(), 1);
+
+        assert_eq!(chart.undo(), Some(0));
+        assert_eq!(chart.note_at(0), Some(EditorNote::Don));
+        assert_eq!(chart.undo(), Some(48));
+        assert_eq!(chart.note_at(48), Some(EditorNote::Kat));
+
+        assert_eq!(chart.redo(), Some(48));
+        assert_eq!(chart.note_at(48), Some(EditorNote::BigKat));
+
+        // A new edit throws away whatever could have been redone
+        assert!(chart.place(96, EditorNote::Don));
+        assert_eq!(chart.redo(), None);
+
+        while chart.undo().is_some() {}
+        assert_eq!(chart.note_count(), 0);
+    }
+
+    #[test]
+    fn test_notes_stay_inside_the_chart() {
+        let mut chart = EditorChart::new(120., 0., 2);
+        assert_eq!(chart.last_tick(), 383);
+        assert!(chart.place(383, EditorNote::Don));
+        assert!(!chart.place(384, EditorNote::Don));
+    }
+
+    #[test]
+    fn test_ticks_and_times() {
+        // At 150 BPM a measure lasts 1.6 seconds
+        let chart = EditorChart::new(150., 0.5, 4);
+        assert!((chart.time_of(0) + 0.5).abs() < 1e-9);
+        assert!((chart.time_of(TICKS_PER_MEASURE) - 1.1).abs() < 1e-9);
+        assert!((chart.tick_at(1.1) - TICKS_PER_MEASURE as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_set_bpm() {
+        let mut chart = EditorChart::new(120., 0., 2);
+        chart.place(TICKS_PER_MEASURE, EditorNote::Don);
+        assert_eq!(chart.to_note_chart().notes[0].time, 2.0);
+
+        // The note stays at the start of the second measure, which now comes sooner
+        chart.set_bpm(240., 4);
+        assert_eq!(chart.note_at(TICKS_PER_MEASURE), Some(EditorNote::Don));
+        assert_eq!(chart.to_note_chart().notes[0].time, 1.0);
+        assert_eq!(chart.measures(), 4);
+
+        // The chart never gets shorter
+        chart.set_bpm(60., 1);
+        assert_eq!(chart.measures(), 4);
+    }
+
+    #[test]
+    fn test_load_existing_chart() {
+        let song = parse_tja_file(
+            "TITLE:Editor
+BPM:120
+WAVE:song.ogg
+OFFSET:-0.25
+LEVEL:3
+BALLOON:5
+
+#START
+1234,
+5008,
+7008,
+#END
+",
+        )
+        .unwrap();
+
+        let (chart, skipped) =
+            EditorChart::from_difficulty(120., -0.25, 1, song.difficulties[3].as_ref().unwrap());
+
+        // The balloon is left out
+        assert_eq!(skipped, 1);
+        assert_eq!(chart.measures(), 3);
+        assert_eq!(
+            chart.notes_in(0..TICKS_PER_MEASURE * 2).collect::<Vec<_>>(),
+            vec![
+                (0, EditorNote::Don),
+                (48, EditorNote::Kat),
+                (96, EditorNote::BigDon),
+                (144, EditorNote::BigKat),
+                (192, EditorNote::Roll { length: 144 }),
+            ]
+        );
+
+        let note_chart = chart.to_note_chart();
+        assert_eq!(note_chart.notes[1].time, 0.75);
+        assert_eq!(note_chart.notes[4].note_type, NoteType::Roll(1.5));
+        assert_eq!(note_chart.barlines.len(), 4);
+    }
+
+    #[test]
+    fn test_rolls() {
+        let mut chart = EditorChart::new(120., 0., 2);
+        let roll = EditorNote::Roll { length: 96 };
+
+        assert!(chart.place(48, roll));
+        // Nothing can go under the roll, including on the tick it ends on
+        assert!(!chart.place(96, EditorNote::Don));
+        assert!(!chart.place(144, EditorNote::Don));
+        assert!(chart.place(145, EditorNote::Don));
+        // And a roll can't be placed over other notes, or without a length
+        assert!(!chart.place(0, EditorNote::BigRoll { length: 150 }));
+        assert!(!chart.place(0, EditorNote::BigRoll { length: 0 }));
+        assert!(!chart.place(300, EditorNote::Roll { length: 100 }));
+
+        // Rolls that started before the range are still in it
+        assert_eq!(chart.notes_in(100..200).next(), Some((48, roll)));
+
+        // Deleting anywhere along the roll deletes it
+        assert!(chart.delete(100));
+        assert_eq!(chart.note_at(48), None);
+        assert_eq!(chart.undo(), Some(48));
+        assert_eq!(chart.note_at(48), Some(roll));
+    }
+}
@@ -0,0 +1,868 @@
+//! A minimal chart editor.
+//!
+//! Notes can be placed on and deleted from a grid that follows the BPM of the song, either with
+//! the keyboard or by clicking on the timeline, which shows the song's waveform to line them up
+//! with. The chart can be listened to from any point, played through properly in the normal
+//! gameplay scene, and saved back to the song's TJA file.
+//!
+//! Dons, kats and drumrolls (big or small) can be edited so far, but not balloons, BPM changes or
+//! other commands. The BPM and offset of the whole song can be changed, and the BPM can be found
+//! with the tap tempo tool.
+use std::time::Instant;
+
+use egui::{Color32, RichText, Stroke};
+use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings};
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::audio::AudioManager;
+use crate::difficulty::{DifficultyInfo, DIFFICULTIES};
+use crate::game::taiko_mode::{autoplay_inputs, simulate, TaikoMode};
+use crate::game::ui_elements::{TapTempo, TAP_KEY};
+use crate::game::{Context, GameState, KeyboardState, StateTransition};
+use crate::notechart_parser::{estimate_difficulty, write_tja, Difficulty, Song};
+use crate::render::waveform::Peaks;
+use crate::settings::SettingsSnapshot;
+
+mod chart;
+
+use chart::{EditorChart, EditorNote, SNAPS, TICKS_PER_MEASURE};
+
+/// How many ticks of the chart fit on the timeline at once.
+const VISIBLE_TICKS: f32 = 2.5 * TICKS_PER_MEASURE as f32;
+/// Where on the timeline the cursor (or the playhead, while playing) is kept, as a fraction of its
+/// width.
+const FOCUS_POSITION: f32 = 0.25;
+const TIMELINE_HEIGHT: f32 = 160.0;
+const NOTE_RADIUS: f32 = 20.0;
+const BIG_NOTE_RADIUS: f32 = 30.0;
+
+const DON_COLOUR: Color32 = Color32::from_rgb(0xF8, 0x48, 0x28);
+const KAT_COLOUR: Color32 = Color32::from_rgb(0x68, 0xC0, 0xC0);
+const ROLL_COLOUR: Color32 = Color32::from_rgb(0xF8, 0xB8, 0x00);
+const WAVEFORM_COLOUR: Color32 = Color32::from_gray(60);
+/// How many pixels wide each line of the waveform is.
+const WAVEFORM_STEP: f32 = 2.0;
+const CURSOR_COLOUR: Color32 = Color32::from_rgb(0xFF, 0xC8, 0x1E);
+
+/// How many measures at the given BPM it takes to cover a song of the given length.
+fn measures_to_cover(bpm: f32, offset: f32, duration: f64) -> u32 {
+    let measure_length = 240.0 / bpm as f64;
+    ((duration + offset as f64) / measure_length)
+        .ceil()
+        .max(1.0) as u32
+}
+
+/// The song being listened to from some point in the chart.
+struct Playback {
+    handle: StaticSoundHandle,
+    started: Instant,
+    /// The time in the song playback started from.
+    from: f64,
+}
+
+impl Playback {
+    fn time(&self) -> f64 {
+        self.from + self.started.elapsed().as_secs_f64()
+    }
+}
+
+pub struct Editor {
+    song: Song,
+    difficulty: usize,
+    chart: EditorChart,
+    sound_data: StaticSoundData,
+    waveform: Peaks,
+
+    /// The tick notes are placed at with the keyboard.
+    cursor: u32,
+    /// Which of [SNAPS] the cursor and mouse snap to.
+    snap: usize,
+    /// The note placed by clicking on the timeline.
+    mouse_note: EditorNote,
+    /// Where the roll being placed with the keyboard starts, once its start has been chosen.
+    roll_start: Option<u32>,
+    /// Where the roll being dragged out on the timeline starts.
+    drag_start: Option<u32>,
+
+    playback: Option<Playback>,
+    /// Whether there are edits that haven't been saved.
+    unsaved: bool,
+    status: Option<String>,
+    confirm_exit: bool,
+    exit: bool,
+    test_play: bool,
+    save_requested: bool,
+    toggle_playback: bool,
+    /// For the drum keys, which place notes
+    settings: SettingsSnapshot,
+    tap_tempo: TapTempo,
+    show_tap_tempo: bool,
+}
+
+impl Editor {
+    /// Opens the editor for a difficulty of a song. If the song doesn't have that difficulty yet,
+    /// the editor starts with an empty chart.
+    pub fn new(song: &Song, difficulty: usize) -> anyhow::Result<Self> {
+        let sound_data = StaticSoundData::from_file(&song.audio_filename, Default::default())?;
+
+        // Enough measures to cover the whole song
+        let measures =
+            measures_to_cover(song.bpm, song.offset, sound_data.duration().as_secs_f64());
+
+        let (chart, status) = match &song.difficulties[difficulty] {
+            Some(existing) => {
+                let (chart, skipped) =
+                    EditorChart::from_difficulty(song.bpm, song.offset, measures, existing);
+
+                let status = (skipped > 0).then(|| {
+                    format!(
+                        "{skipped} notes (like balloons) can't be edited yet, and will be left out \
+                        when saving"
+                    )
+                });
+
+                (chart, status)
+            }
+            None => (EditorChart::new(song.bpm, song.offset, measures), None),
+        };
+
+        Ok(Self {
+            song: song.clone(),
+            difficulty,
+            chart,
+            waveform: Peaks::new(&sound_data),
+            sound_data,
+            cursor: 0,
+            snap: 3,
+            mouse_note: EditorNote::Don,
+            roll_start: None,
+            drag_start: None,
+            playback: None,
+            unsaved: false,
+            status,
+            confirm_exit: false,
+            exit: false,
+            test_play: false,
+            save_requested: false,
+            toggle_playback: false,
+            settings: SettingsSnapshot::new(),
+            tap_tempo: TapTempo::default(),
+            show_tap_tempo: false,
+        })
+    }
+
+    /// The number of ticks between each position on the current snap grid.
+    fn snap_ticks(&self) -> u32 {
+        TICKS_PER_MEASURE / SNAPS[self.snap]
+    }
+
+    /// Rounds a (fractional) tick to the nearest position on the snap grid within the chart.
+    fn snapped(&self, tick: f32) -> u32 {
+        let step = self.snap_ticks() as f32;
+        let tick = ((tick / step).round() * step).max(0.0) as u32;
+        tick.min(self.chart.last_tick() / self.snap_ticks() * self.snap_ticks())
+    }
+
+    /// Moves the cursor by a number of snap grid positions, landing on the grid.
+    fn move_cursor(&mut self, steps: i32) {
+        let step = self.snap_ticks() as i64;
+        let on_grid = self.cursor as i64 / step * step;
+        let moved = if steps < 0 && on_grid != self.cursor as i64 {
+            // Moving back from between grid positions lands on the one just before
+            on_grid + (steps as i64 + 1) * step
+        } else {
+            on_grid + steps as i64 * step
+        };
+
+        self.cursor = self.snapped(moved.max(0) as f32);
+    }
+
+    fn place(&mut self, tick: u32, note: EditorNote) {
+        if !self.chart.can_place(tick, note) {
+            self.status = Some(if note.is_roll() {
+                "Drumrolls can't go over other notes or past the end of the chart".to_string()
+            } else {
+                "Notes can't go under drumrolls".to_string()
+            });
+        } else if self.chart.place(tick, note) {
+            self.unsaved = true;
+        }
+    }
+
+    /// Places a roll between two ticks, whichever order they're in.
+    fn place_roll(&mut self, from: u32, to: u32, big: bool) {
+        let note = if big {
+            EditorNote::BigRoll { length: 0 }
+        } else {
+            EditorNote::Roll { length: 0 }
+        };
+
+        self.place(from.min(to), note.with_length(from.abs_diff(to)));
+    }
+
+    fn delete(&mut self, tick: u32) {
+        if self.chart.delete(tick) {
+            self.unsaved = true;
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(tick) = self.chart.undo() {
+            self.cursor = tick;
+            self.unsaved = true;
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(tick) = self.chart.redo() {
+            self.cursor = tick;
+            self.unsaved = true;
+        }
+    }
+
+    /// Starts listening to the song from the cursor.
+    fn start_playback(&mut self, audio: &mut AudioManager) {
+        let from = self.chart.time_of(self.cursor).max(0.0);
+        let sound_data = self
+            .sound_data
+            .with_settings(StaticSoundSettings::new().playback_region(from..));
+
+        match audio.play(sound_data) {
+            Ok(handle) => {
+                self.playback = Some(Playback {
+                    handle,
+                    started: Instant::now(),
+                    from,
+                })
+            }
+            Err(e) => {
+                log::error!("couldn't play song in editor: {e}");
+                self.status = Some(format!("Couldn't play the song: {e}"));
+            }
+        }
+    }
+
+    fn stop_playback(&mut self) {
+        if let Some(mut playback) = self.playback.take() {
+            let _ = playback.handle.stop(Default::default());
+        }
+    }
+
+    /// Changes the BPM of the song, keeping the notes on the same beats.
+    fn set_bpm(&mut self, bpm: f32) {
+        let measures = measures_to_cover(
+            bpm,
+            self.chart.offset(),
+            self.sound_data.duration().as_secs_f64(),
+        );
+
+        self.chart.set_bpm(bpm, measures);
+        self.timing_changed(format!("Changed the BPM to {bpm}"));
+    }
+
+    /// Changes the offset of the song, moving the notes along with the grid.
+    fn set_offset(&mut self, offset: f32) {
+        let measures = measures_to_cover(
+            self.chart.bpm(),
+            offset,
+            self.sound_data.duration().as_secs_f64(),
+        );
+
+        self.chart.set_offset(offset, measures);
+        self.timing_changed(format!("Changed the offset to {offset}"));
+    }
+
+    /// Says what changed about the timing of the song, and warns that its other difficulties (which
+    /// share the timing) won't line up any more if it has any.
+    fn timing_changed(&mut self, change: String) {
+        self.unsaved = true;
+
+        let other_charts = (self.song.difficulties.iter().enumerate())
+            .any(|(i, difficulty)| i != self.difficulty && difficulty.is_some());
+        self.status = Some(if other_charts {
+            format!(
+                "{change}. The song's other difficulties won't line up with it, so they'll need to \
+                be retimed before the song can be saved."
+            )
+        } else {
+            change
+        });
+    }
+
+    /// The song with the chart being edited in place of the original.
+    fn edited_song(&self) -> Song {
+        let mut song = self.song.clone();
+        song.bpm = self.chart.bpm();
+        song.offset = self.chart.offset();
+        let chart = self.chart.to_note_chart();
+        let existing = self.song.difficulties[self.difficulty].as_ref();
+
+        song.difficulties[self.difficulty] = Some(Difficulty {
+            star_level: existing.map_or(1, |difficulty| difficulty.star_level),
+            estimated_level: estimate_difficulty(&chart),
+            notes_designer: existing.and_then(|difficulty| difficulty.notes_designer.clone()),
+            chart,
+            // Kept so that saving fails rather than losing them, as they can't be written yet
+            player_charts: existing.and_then(|difficulty| difficulty.player_charts.clone()),
+        });
+
+        song
+    }
+
+    /// Plays through the chart with autoplay, and describes how many notes it couldn't hit on time.
+    /// A perfect player can't hit those notes either, e.g. because they're too close together.
+    fn autoplay_check(&self) -> String {
+        let chart = self.chart.to_note_chart();
+        let result = simulate(
+            &chart,
+            self.difficulty,
+            &self.settings.game.rules(),
+            &autoplay_inputs(&chart, self.difficulty),
+        );
+        let missed = result.okays() + result.bads() + result.misses();
+
+        if missed == 0 {
+            "Autoplay check: every note can be hit".to_string()
+        } else {
+            format!("Autoplay check: {missed} notes can't be hit on time")
+        }
+    }
+
+    /// Saves the song to the TJA file it was loaded from. The first time a file is saved over, a
+    /// copy of the original is kept next to it, in case the editor loses something it shouldn't.
+    fn save(&mut self) -> anyhow::Result<()> {
+        let path = self
+            .song
+            .chart_path
+            .clone()
+            .ok_or_else(|| anyhow::format_err!("the song wasn't loaded from a file"))?;
+
+        let song = self.edited_song();
+        let contents = write_tja(&song)?;
+
+        let mut backup = path.clone().into_os_string();
+        backup.push(".bak");
+        if path.is_file() && !std::path::Path::new(&backup).exists() {
+            std::fs::copy(&path, &backup)?;
+        }
+
+        std::fs::write(&path, contents)?;
+        self.song = song;
+        self.unsaved = false;
+        Ok(())
+    }
+
+    fn handle_keys(&mut self, keyboard: &mut KeyboardState) {
+        let key = |code| PhysicalKey::Code(code);
+        let ctrl = keyboard.is_pressed(key(KeyCode::ControlLeft))
+            || keyboard.is_pressed(key(KeyCode::ControlRight));
+        let shift = keyboard.is_pressed(key(KeyCode::ShiftLeft))
+            || keyboard.is_pressed(key(KeyCode::ShiftRight));
+
+        if keyboard.is_just_pressed(key(KeyCode::Space)) {
+            self.toggle_playback = true;
+        }
+
+        if keyboard.is_just_pressed(key(KeyCode::Escape)) {
+            if self.roll_start.is_some() {
+                self.roll_start = None;
+                self.status = None;
+            } else if self.playback.is_some() {
+                self.stop_playback();
+            } else if self.unsaved {
+                self.confirm_exit = true;
+            } else {
+                self.exit = true;
+            }
+        }
+
+        if ctrl {
+            if keyboard.is_just_pressed(key(KeyCode::KeyZ)) {
+                if shift {
+                    self.redo();
+                } else {
+                    self.undo();
+                }
+            }
+
+            if keyboard.is_just_pressed(key(KeyCode::KeyY)) {
+                self.redo();
+            }
+
+            if keyboard.is_just_pressed(key(KeyCode::KeyS)) {
+                self.save_requested = true;
+            }
+
+            return;
+        }
+
+        if keyboard.is_just_pressed(key(KeyCode::F5)) {
+            self.test_play = true;
+        }
+
+        for (code, steps) in [
+            (KeyCode::ArrowLeft, -1),
+            (KeyCode::ArrowRight, 1),
+            (KeyCode::PageUp, -(SNAPS[self.snap] as i32)),
+            (KeyCode::PageDown, SNAPS[self.snap] as i32),
+        ] {
+            if keyboard.is_just_pressed(key(code)) {
+                self.move_cursor(steps);
+            }
+        }
+
+        if keyboard.is_just_pressed(key(KeyCode::ArrowUp)) {
+            self.snap = (self.snap + 1).min(SNAPS.len() - 1);
+        }
+
+        if keyboard.is_just_pressed(key(KeyCode::ArrowDown)) {
+            self.snap = self.snap.saturating_sub(1);
+        }
+
+        let key_mappings = self.settings.game.key_mappings.clone();
+        let dons = [key_mappings.left_don, key_mappings.right_don];
+        let kats = [key_mappings.left_kat, key_mappings.right_kat];
+
+        if dons.iter().any(|&key| keyboard.is_just_pressed(key)) {
+            let note = if shift {
+                EditorNote::BigDon
+            } else {
+                EditorNote::Don
+            };
+            self.place(self.cursor, note);
+        }
+
+        if kats.iter().any(|&key| keyboard.is_just_pressed(key)) {
+            let note = if shift {
+                EditorNote::BigKat
+            } else {
+                EditorNote::Kat
+            };
+            self.place(self.cursor, note);
+        }
+
+        // The first press marks where the roll starts, and the second places it
+        if keyboard.is_just_pressed(key(KeyCode::KeyR)) {
+            match self.roll_start.take() {
+                Some(start) => {
+                    self.status = None;
+                    self.place_roll(start, self.cursor, shift);
+                }
+                None => {
+                    self.roll_start = Some(self.cursor);
+                    self.status = Some(
+                        "Move to where the drumroll ends and press R again (hold shift for a big \
+                        one), or Escape to cancel"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+
+        if keyboard.is_just_pressed(key(KeyCode::Delete))
+            || keyboard.is_just_pressed(key(KeyCode::Backspace))
+        {
+            self.delete(self.cursor);
+        }
+    }
+
+    fn toolbar_ui(&mut self, ui: &mut egui::Ui) {
+        let info = DifficultyInfo::from_index(self.difficulty).unwrap_or(&DIFFICULTIES[0]);
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(&self.song.title).size(24.0).strong());
+            ui.label(
+                RichText::new(info.name)
+                    .size(24.0)
+                    .color(info.egui_colour()),
+            );
+
+            if self.unsaved {
+                ui.label(RichText::new("(unsaved)").italics());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Snap:");
+            egui::ComboBox::from_id_source("editor snap")
+                .selected_text(format!("1/{}", SNAPS[self.snap]))
+                .show_ui(ui, |ui| {
+                    for (i, snap) in SNAPS.iter().enumerate() {
+                        ui.selectable_value(&mut self.snap, i, format!("1/{snap}"));
+                    }
+                });
+
+            ui.separator();
+            ui.label("Click to place:");
+            for note in EditorNote::ALL {
+                ui.radio_value(&mut self.mouse_note, note, note.name());
+            }
+
+            ui.separator();
+
+            let play_text = if self.playback.is_some() {
+                "Stop"
+            } else {
+                "Listen"
+            };
+            if ui.button(play_text).on_hover_text("Space").clicked() {
+                self.toggle_playback = true;
+            }
+
+            if ui.button("Test play").on_hover_text("F5").clicked() {
+                self.test_play = true;
+            }
+
+            if ui
+                .button("Check")
+                .on_hover_text("Play the chart with autoplay, to find notes that can't be hit")
+                .clicked()
+            {
+                self.status = Some(self.autoplay_check());
+            }
+
+            if ui.button("Save").on_hover_text("Ctrl+S").clicked() {
+                self.save_requested = true;
+            }
+
+            if ui.button("Undo").on_hover_text("Ctrl+Z").clicked() {
+                self.undo();
+            }
+
+            if ui.button("Redo").on_hover_text("Ctrl+Y").clicked() {
+                self.redo();
+            }
+
+            if ui
+                .button("Tap tempo")
+                .on_hover_text("Find the BPM of the song by tapping along to it")
+                .clicked()
+            {
+                self.show_tap_tempo = !self.show_tap_tempo;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let mut bpm = self.chart.bpm();
+            ui.label("BPM:");
+            let bpm_changed = ui
+                .add(
+                    egui::DragValue::new(&mut bpm)
+                        .speed(0.1)
+                        .range(1.0..=1000.0),
+                )
+                .changed();
+            if bpm_changed {
+                self.set_bpm(bpm);
+            }
+
+            let mut offset = self.chart.offset();
+            ui.label("Offset:");
+            let offset_changed = ui
+                .add(egui::DragValue::new(&mut offset).speed(0.001).suffix(" s"))
+                .on_hover_text("How much earlier than the audio the notes are")
+                .changed();
+            if offset_changed {
+                self.set_offset(offset);
+            }
+        });
+
+        let measure = self.cursor / TICKS_PER_MEASURE + 1;
+        let position = self.cursor % TICKS_PER_MEASURE;
+        ui.label(format!(
+            "Measure {measure}/{}, {position}/{TICKS_PER_MEASURE} - {} notes",
+            self.chart.measures(),
+            self.chart.note_count()
+        ));
+
+        ui.label(
+            RichText::new(
+                "Arrows: move / change snap. Don and kat keys: place (hold shift for big). \
+                R: start and end a drumroll. Delete: remove. Right click: remove. Drag: place a \
+                drumroll.",
+            )
+            .italics(),
+        );
+    }
+
+    fn timeline_ui(&mut self, ui: &mut egui::Ui) {
+        let (response, painter) = ui.allocate_painter(
+            egui::vec2(ui.available_width(), TIMELINE_HEIGHT),
+            egui::Sense::click_and_drag(),
+        );
+        let rect = response.rect;
+        let ticks_per_pixel = VISIBLE_TICKS / rect.width();
+
+        let focus = match &self.playback {
+            Some(playback) => self.chart.tick_at(playback.time()) as f32,
+            None => self.cursor as f32,
+        };
+        let view_start = focus - VISIBLE_TICKS * FOCUS_POSITION;
+        let x_of = |tick: f32| rect.left() + (tick - view_start) / ticks_per_pixel;
+        let tick_of = |x: f32| view_start + (x - rect.left()) * ticks_per_pixel;
+
+        painter.rect_filled(rect, 4.0, Color32::from_gray(30));
+
+        // The waveform goes underneath everything else
+        let mut x = rect.left();
+        while x < rect.right() {
+            let times = self.chart.time_at(tick_of(x) as f64)
+                ..self.chart.time_at(tick_of(x + WAVEFORM_STEP) as f64);
+
+            if let Some((low, high)) = self.waveform.peak(times) {
+                let y_of =
+                    |sample: f32| rect.center().y - sample.clamp(-1.0, 1.0) * rect.height() / 2.0;
+                painter.line_segment(
+                    [egui::pos2(x, y_of(high)), egui::pos2(x, y_of(low))],
+                    Stroke::new(WAVEFORM_STEP, WAVEFORM_COLOUR),
+                );
+            }
+
+            x += WAVEFORM_STEP;
+        }
+
+        // The grid, with measures and beats drawn more brightly
+        let step = self.snap_ticks();
+        let first = (view_start.max(0.0) as u32).div_ceil(step) * step;
+        let last = (view_start + VISIBLE_TICKS).min(self.chart.last_tick() as f32 + 1.0) as u32;
+
+        for tick in (first..=last).step_by(step as usize) {
+            let (colour, width) = if tick % TICKS_PER_MEASURE == 0 {
+                (Color32::WHITE, 2.0)
+            } else if tick % (TICKS_PER_MEASURE / 4) == 0 {
+                (Color32::from_gray(140), 1.0)
+            } else {
+                (Color32::from_gray(70), 1.0)
+            };
+
+            let x = x_of(tick as f32);
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                Stroke::new(width, colour),
+            );
+        }
+
+        let draw_note = |tick: u32, note: EditorNote, alpha: u8| {
+            let radius = if note.is_big() {
+                BIG_NOTE_RADIUS
+            } else {
+                NOTE_RADIUS
+            };
+            let colour = if note.is_roll() {
+                ROLL_COLOUR
+            } else if note.is_don() {
+                DON_COLOUR
+            } else {
+                KAT_COLOUR
+            };
+            let fill = Color32::from_rgba_unmultiplied(colour.r(), colour.g(), colour.b(), alpha);
+            let stroke = Stroke::new(3.0, Color32::from_white_alpha(alpha));
+
+            let centre = egui::pos2(x_of(tick as f32), rect.center().y);
+            if note.is_roll() {
+                let end = egui::pos2(x_of((tick + note.length()) as f32), centre.y);
+                let body = egui::Rect::from_two_pos(
+                    centre - egui::vec2(0.0, radius),
+                    end + egui::vec2(0.0, radius),
+                )
+                .expand2(egui::vec2(radius, 0.0));
+                painter.rect(body, radius, fill, stroke);
+            }
+            painter.circle(centre, radius, fill, stroke);
+        };
+
+        // Later notes are drawn underneath earlier ones, like in the game
+        let visible = view_start.max(0.0) as u32..(view_start + VISIBLE_TICKS).max(0.0) as u32 + 1;
+        let notes = self.chart.notes_in(visible).collect::<Vec<_>>();
+        for &(tick, note) in notes.iter().rev() {
+            draw_note(tick, note, 255);
+        }
+
+        // Show where a click (or a drag, for rolls) would put a note
+        let pointer_tick = ui
+            .ctx()
+            .pointer_latest_pos()
+            .map(|pointer| self.snapped(tick_of(pointer.x)));
+
+        if let Some(tick) = pointer_tick.filter(|_| response.hovered() || response.dragged()) {
+            match self.drag_start {
+                Some(start) => {
+                    let length = start.abs_diff(tick);
+                    draw_note(start.min(tick), self.mouse_note.with_length(length), 80);
+                }
+                None => draw_note(tick, self.mouse_note.with_length(0), 80),
+            }
+        }
+
+        let cursor_x = x_of(self.cursor as f32);
+        painter.line_segment(
+            [
+                egui::pos2(cursor_x, rect.top()),
+                egui::pos2(cursor_x, rect.bottom()),
+            ],
+            Stroke::new(3.0, CURSOR_COLOUR),
+        );
+
+        if self.playback.is_some() {
+            let x = x_of(focus);
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                Stroke::new(2.0, Color32::WHITE),
+            );
+        }
+
+        let Some(tick) = pointer_tick else {
+            return;
+        };
+
+        if response.secondary_clicked() {
+            self.delete(tick);
+        } else if !self.mouse_note.is_roll() {
+            if response.clicked() {
+                self.cursor = tick;
+                self.place(tick, self.mouse_note);
+            }
+        } else if response.drag_started_by(egui::PointerButton::Primary) {
+            self.drag_start = Some(tick);
+        } else if response.drag_stopped() {
+            if let Some(start) = self.drag_start.take() {
+                self.cursor = start.min(tick);
+                self.place_roll(start, tick, self.mouse_note.is_big());
+            }
+        } else if response.clicked() {
+            // Clicking without dragging makes a roll one beat long
+            self.cursor = tick;
+            self.place_roll(tick, tick + TICKS_PER_MEASURE / 4, self.mouse_note.is_big());
+        }
+    }
+
+    fn confirm_exit_ui(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Unsaved changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("The chart has changes that haven't been saved.");
+                ui.horizontal(|ui| {
+                    if ui.button("Save and quit").clicked() {
+                        self.save_requested = true;
+                        self.exit = true;
+                    }
+
+                    if ui.button("Quit without saving").clicked() {
+                        self.exit = true;
+                    }
+
+                    if ui.button("Keep editing").clicked() {
+                        self.confirm_exit = false;
+                    }
+                });
+            });
+    }
+}
+
+impl GameState for Editor {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        self.settings.refresh();
+        self.handle_keys(ctx.keyboard);
+
+        // Playback is started here rather than in the UI, where the audio manager is available
+        if std::mem::take(&mut self.toggle_playback) {
+            if self.playback.is_some() {
+                self.stop_playback();
+            } else {
+                self.start_playback(ctx.audio);
+            }
+        }
+
+        if self.playback.as_ref().is_some_and(|playback| {
+            self.chart.tick_at(playback.time()) > self.chart.last_tick() as f64
+        }) {
+            self.stop_playback();
+        }
+
+        if std::mem::take(&mut self.save_requested) {
+            self.status = Some(match self.save() {
+                Ok(()) => {
+                    "Saved! The song list will show the changes next time it's loaded.".to_string()
+                }
+                Err(e) => {
+                    log::error!("couldn't save chart: {e}");
+                    // Don't quit if the chart couldn't be saved
+                    self.exit = false;
+                    format!("Couldn't save: {e}")
+                }
+            });
+        }
+
+        if self.exit {
+            self.stop_playback();
+            return StateTransition::Pop;
+        }
+
+        if std::mem::take(&mut self.test_play) {
+            self.stop_playback();
+
+            let song = self.edited_song();
+            match TaikoMode::new(&song, self.sound_data.clone(), self.difficulty, 1.0, ctx) {
+                Ok(scene) => return StateTransition::Push(Box::new(scene)),
+                Err(e) => {
+                    log::error!("couldn't start test play: {e}");
+                    self.status = Some(format!("Couldn't start test play: {e}"));
+                }
+            }
+        }
+
+        StateTransition::Continue
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            self.toolbar_ui(ui);
+            ui.add_space(20.0);
+            self.timeline_ui(ui);
+            ui.add_space(10.0);
+
+            if let Some(status) = &self.status {
+                ui.label(RichText::new(status).size(16.0));
+            }
+        });
+
+        if self.show_tap_tempo {
+            let mut apply = None;
+
+            egui::Window::new("Tap tempo")
+                .open(&mut self.show_tap_tempo)
+                .resizable(false)
+                .show(&ctx, |ui| {
+                    apply = self.tap_tempo.ui(ui, Some(self.chart.bpm()), true);
+                });
+
+            if let Some(bpm) = apply {
+                self.set_bpm(bpm as f32);
+            }
+        }
+
+        if self.confirm_exit {
+            self.confirm_exit_ui(&ctx);
+        }
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        // Taps are timed by when the key was pressed, rather than the next frame
+        if let WindowEvent::KeyboardInput { event, .. } = event {
+            if self.show_tap_tempo
+                && event.state == ElementState::Pressed
+                && !event.repeat
+                && event.physical_key == PhysicalKey::Code(TAP_KEY)
+            {
+                self.tap_tempo.tap(ctx.event_time);
+            }
+        }
+    }
+
+    fn recreate_gpu_resources(&mut self, _ctx: &mut Context) -> bool {
+        true
+    }
+}
@@ -0,0 +1,54 @@
+use egui::RichText;
+
+use crate::audio::AudioManager;
+use crate::game::{Context, GameState, StateTransition, TransitionStyle};
+
+/// A screen shown when something has gone so wrong that the game can't continue, so that the
+/// player at least gets told what happened instead of the game just vanishing.
+pub struct ErrorScreen {
+    message: String,
+    exit: bool,
+}
+
+impl ErrorScreen {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            exit: false,
+        }
+    }
+}
+
+impl GameState for ErrorScreen {
+    fn update(&mut self, _ctx: &mut Context, _dt: f32) -> StateTransition {
+        if self.exit {
+            StateTransition::Exit
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn transition_style(&self) -> TransitionStyle {
+        // Something's gone wrong, so there's no time to waste
+        TransitionStyle::Cut
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::Area::new("Error".into())
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(&ctx, |ui| {
+                ui.label(RichText::new("Something went wrong :(").size(50.0));
+                ui.label(RichText::new(&self.message).size(25.0));
+
+                ui.add_space(100.0);
+
+                if ui.button(RichText::new("quit").size(20.0)).clicked() {
+                    self.exit = true;
+                }
+            });
+    }
+
+    fn recreate_gpu_resources(&mut self, _ctx: &mut Context) -> bool {
+        true
+    }
+}
@@ -0,0 +1,306 @@
+//! Exporting everything the game remembers about the player (their scores, favourites and
+//! collections, and per-chart settings) to a single file, and merging such a file back in, so that
+//! progress can be carried between computers.
+//!
+//! Importing never throws anything away: the file is merged with what's already here (see
+//! [PlayerData::merge]), and everything is backed up to [BACKUPS_DIR] before the merge is saved.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::chart_settings::{ChartSettings, CHART_SETTINGS_PATH};
+use super::collections::{Collections, COLLECTIONS_PATH};
+use super::scores::{save_scores, ScoreDatabase, SCORES_PATH};
+
+/// The version of the export format. Files from a newer version of the game might have things in
+/// them this version doesn't know about, so they aren't imported.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Where the file is exported to and imported from, unless the player picks somewhere else.
+pub const DEFAULT_EXPORT_PATH: &str = "taiko_player_data.toml";
+
+/// The folder the player's data is backed up to before an import is saved.
+pub const BACKUPS_DIR: &str = "backups";
+
+/// Everything the game remembers about the player, as it's exported.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerData {
+    pub version: u32,
+    /// When the data was exported, in seconds since the unix epoch.
+    #[serde(default)]
+    pub exported_at: i64,
+    #[serde(default)]
+    pub scores: ScoreDatabase,
+    #[serde(default)]
+    pub collections: Collections,
+    #[serde(default)]
+    pub chart_settings: ChartSettings,
+}
+
+/// What merging in someone else's data changed (see [PlayerData::merge]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub new_plays: usize,
+    /// How many difficulties of charts got a better best score.
+    pub new_bests: usize,
+    pub new_favourites: usize,
+    pub new_collections: usize,
+    /// How many songs were added to collections, new or not.
+    pub collection_songs: usize,
+    pub chart_settings_added: usize,
+    pub chart_settings_replaced: usize,
+}
+
+impl MergeSummary {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// A line describing each thing that changed, for showing the player before the merge is
+    /// saved.
+    pub fn lines(&self) -> Vec<String> {
+        let count =
+            |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+
+        [
+            (self.new_plays, "play", "plays", "added"),
+            (self.new_bests, "best score", "best scores", "improved"),
+            (self.new_favourites, "favourite", "favourites", "added"),
+            (self.new_collections, "collection", "collections", "added"),
+            (
+                self.collection_songs,
+                "song",
+                "songs",
+                "added to collections",
+            ),
+            (
+                self.chart_settings_added,
+                "chart's settings",
+                "charts' settings",
+                "added",
+            ),
+            (
+                self.chart_settings_replaced,
+                "chart's settings",
+                "charts' settings",
+                "replaced with newer ones",
+            ),
+        ]
+        .into_iter()
+        .filter(|(n, ..)| *n > 0)
+        .map(|(n, one, many, what)| format!("{} {what}", count(n, one, many)))
+        .collect()
+    }
+}
+
+impl PlayerData {
+    /// Reads everything the game has saved about the player.
+    pub fn load_current() -> anyhow::Result<Self> {
+        Ok(Self {
+            version: EXPORT_VERSION,
+            exported_at: chrono::Utc::now().timestamp(),
+            scores: ScoreDatabase::load(SCORES_PATH)?,
+            collections: Collections::load(COLLECTIONS_PATH)?,
+            chart_settings: ChartSettings::load(CHART_SETTINGS_PATH)?,
+        })
+    }
+
+    /// Writes the data over what the game has saved about the player.
+    pub fn save_current(&self) -> anyhow::Result<()> {
+        save_scores(&self.scores)?;
+        self.collections.save(COLLECTIONS_PATH)?;
+        self.chart_settings.save(CHART_SETTINGS_PATH)
+    }
+
+    /// Reads an exported file.
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let data: Self = toml::from_str(&fs::read_to_string(path)?)?;
+
+        if data.version > EXPORT_VERSION {
+            anyhow::bail!("the file was exported by a newer version of the game");
+        }
+
+        Ok(data)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Takes in someone else's data (e.g. exported from another computer) without losing
+    /// anything from this one:
+    ///
+    /// - Every play from both is kept, so each chart ends up with the better best score and the
+    ///   total play count (see [ScoreDatabase::merge]).
+    /// - Favourites and collections from both are combined (see [Collections::merge]).
+    /// - Where both have settings for a chart, the ones changed more recently are kept (see
+    ///   [ChartSettings::merge]).
+    ///
+    /// Merging the same data in twice changes nothing the second time.
+    pub fn merge(&mut self, other: &PlayerData) -> MergeSummary {
+        let new_bests = {
+            let ours = self.scores.bests();
+            other
+                .scores
+                .bests()
+                .into_iter()
+                .filter(|(chart, score)| ours.get(chart).is_none_or(|best| score > best))
+                .count()
+        };
+
+        let new_plays = self.scores.merge(&other.scores);
+        let collections = self.collections.merge(&other.collections);
+        let (chart_settings_added, chart_settings_replaced) =
+            self.chart_settings.merge(&other.chart_settings);
+
+        MergeSummary {
+            new_plays,
+            new_bests,
+            new_favourites: collections.favourites,
+            new_collections: collections.collections,
+            collection_songs: collections.songs,
+            chart_settings_added,
+            chart_settings_replaced,
+        }
+    }
+}
+
+/// Exports everything the game has saved about the player to a file.
+pub fn export_player_data(path: impl AsRef<Path>) -> anyhow::Result<()> {
+    PlayerData::load_current()?.write(path)
+}
+
+/// An exported file that's been merged with the player's data, but not saved yet, so the player
+/// can see what will change first.
+#[derive(Debug)]
+pub struct PendingImport {
+    merged: PlayerData,
+    pub summary: MergeSummary,
+}
+
+impl PendingImport {
+    /// Reads an exported file and works out what merging it in would do.
+    pub fn preview(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let incoming = PlayerData::read(path)?;
+        let mut merged = PlayerData::load_current()?;
+        let summary = merged.merge(&incoming);
+
+        Ok(Self { merged, summary })
+    }
+
+    /// Backs up the player's data as it is now, then saves the merged data over it. Returns the
+    /// path of the backup.
+    pub fn apply(self) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(BACKUPS_DIR)?;
+        let backup = Path::new(BACKUPS_DIR).join(format!(
+            "before_import_{}.toml",
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        ));
+
+        export_player_data(&backup)?;
+        self.merged.save_current()?;
+        Ok(backup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::scores::PlayRecord;
+    use crate::game::taiko_mode::ScoreInt;
+
+    fn play(chart: &str, score: ScoreInt, played_at: i64) -> PlayRecord {
+        PlayRecord {
+            chart: chart.to_string(),
+            difficulty: 3,
+            star_level: 8,
+            score,
+            cleared: true,
+            full_combo: false,
+            max_combo: 0,
+            judgements: None,
+            played_at,
+            rules: None,
+            rate: None,
+            mods: Default::default(),
+            crown: None,
+            replay: None,
+        }
+    }
+
+    fn data(plays: Vec<PlayRecord>, favourites: &[&str]) -> PlayerData {
+        PlayerData {
+            version: EXPORT_VERSION,
+            exported_at: 0,
+            scores: ScoreDatabase { plays },
+            collections: Collections {
+                favourites: favourites.iter().map(PathBuf::from).collect(),
+                collections: Vec::new(),
+            },
+            chart_settings: ChartSettings::default(),
+        }
+    }
+
+    #[test]
+    fn test_merge_summary() {
+        let mut ours = data(
+            vec![play("v1:aa", 500000, 1), play("v1:bb", 900000, 2)],
+            &["songs/a.tja"],
+        );
+        let theirs = data(
+            vec![
+                play("v1:aa", 500000, 1),
+                play("v1:aa", 600000, 3),
+                play("v1:bb", 100000, 4),
+                play("v1:cc", 100000, 5),
+            ],
+            &["songs/a.tja", "songs/b.tja"],
+        );
+
+        let summary = ours.merge(&theirs);
+        assert_eq!(
+            summary,
+            MergeSummary {
+                new_plays: 3,
+                // Better on aa, and cc is new. bb was worse.
+                new_bests: 2,
+                new_favourites: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            summary.lines(),
+            vec![
+                "3 plays added",
+                "2 best scores improved",
+                "1 favourite added"
+            ]
+        );
+
+        // The second time around there's nothing to do
+        let summary = ours.merge(&theirs);
+        assert!(summary.is_empty());
+        assert!(summary.lines().is_empty());
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = std::env::temp_dir().join(format!("taiko-export-{}", std::process::id()));
+        let exported = data(vec![play("v1:aa", 500000, 1)], &["songs/a.tja"]);
+        exported.write(&path).unwrap();
+        assert_eq!(PlayerData::read(&path).unwrap(), exported);
+
+        // Files from a newer version of the game aren't read
+        PlayerData {
+            version: EXPORT_VERSION + 1,
+            ..exported
+        }
+        .write(&path)
+        .unwrap();
+        let error = PlayerData::read(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("newer version"), "{error}");
+    }
+}
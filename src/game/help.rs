@@ -0,0 +1,324 @@
+//! An overlay explaining the controls and rules of the game, which can be opened from anywhere.
+use egui::RichText;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use super::taiko_mode::{clear_threshold, MAX_HEALTH};
+use super::KeyboardState;
+use crate::difficulty::{DifficultyInfo, DIFFICULTIES};
+use crate::localisation::{tr, tr_with};
+use crate::settings::{key_name, settings};
+
+/// How long F1 has to be held to open the help overlay, in seconds. Tapping it toggles the FPS
+/// counter instead.
+pub const HELP_HOLD_TIME: f32 = 0.5;
+
+/// The pages of the help overlay, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HelpPage {
+    Controls,
+    Timing,
+    Gauge,
+    Scoring,
+}
+
+const PAGES: [HelpPage; 4] = [
+    HelpPage::Controls,
+    HelpPage::Timing,
+    HelpPage::Gauge,
+    HelpPage::Scoring,
+];
+
+impl HelpPage {
+    /// The prefix of the language keys for this page's text.
+    fn key(&self) -> &'static str {
+        match self {
+            HelpPage::Controls => "help.controls",
+            HelpPage::Timing => "help.timing",
+            HelpPage::Gauge => "help.gauge",
+            HelpPage::Scoring => "help.scoring",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HelpOverlay {
+    open: bool,
+    page: usize,
+    /// The difficulty of the song that was being played when the overlay was opened, if any.
+    difficulty: Option<usize>,
+    /// Whether a state has asked for the overlay to be opened.
+    requested: bool,
+}
+
+impl HelpOverlay {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Asks for the overlay to be opened at the end of the frame. This is how states (e.g. a menu
+    /// with a help button) should open it, so that the state underneath is dealt with properly.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Returns whether the overlay was asked to open since the last time this was called.
+    pub(super) fn take_request(&mut self) -> bool {
+        std::mem::take(&mut self.requested)
+    }
+
+    /// Opens the overlay on the first page. If a song is being played, `difficulty` is its
+    /// difficulty, so that the timing windows shown are the ones that apply to it.
+    pub(super) fn open(&mut self, difficulty: Option<usize>) {
+        self.open = true;
+        self.page = 0;
+        self.difficulty = difficulty;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    fn turn_page(&mut self, forwards: bool) {
+        self.page = if forwards {
+            (self.page + 1).min(PAGES.len() - 1)
+        } else {
+            self.page.saturating_sub(1)
+        };
+    }
+
+    /// Turns the pages with the arrow keys, and closes the overlay with escape.
+    pub fn handle_input(&mut self, keyboard: &KeyboardState) {
+        if keyboard.is_just_pressed(PhysicalKey::Code(KeyCode::ArrowLeft)) {
+            self.turn_page(false);
+        }
+
+        if keyboard.is_just_pressed(PhysicalKey::Code(KeyCode::ArrowRight)) {
+            self.turn_page(true);
+        }
+
+        if keyboard.is_just_pressed(PhysicalKey::Code(KeyCode::Escape)) {
+            self.close();
+        }
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        if !self.open {
+            return;
+        }
+
+        let page = PAGES[self.page];
+        let mut open = true;
+
+        egui::Window::new(tr("help.title"))
+            .open(&mut open)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .collapsible(false)
+            .resizable(false)
+            .default_width(700.0)
+            .show(ctx, |ui| {
+                ui.label(RichText::new(tr(&format!("{}.title", page.key()))).size(30.0));
+                ui.add_space(10.0);
+                ui.label(RichText::new(tr(&format!("{}.body", page.key()))).size(18.0));
+                ui.add_space(10.0);
+
+                match page {
+                    HelpPage::Controls => controls_table(ui),
+                    HelpPage::Timing => self.timing_table(ui),
+                    HelpPage::Gauge => self.gauge_table(ui),
+                    HelpPage::Scoring => {}
+                }
+
+                ui.add_space(20.0);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(self.page > 0, egui::Button::new(tr("help.previous")))
+                        .clicked()
+                    {
+                        self.turn_page(false);
+                    }
+
+                    ui.label(tr_with(
+                        "help.page",
+                        &[("page", &(self.page + 1)), ("pages", &PAGES.len())],
+                    ));
+
+                    if ui
+                        .add_enabled(
+                            self.page + 1 < PAGES.len(),
+                            egui::Button::new(tr("help.next")),
+                        )
+                        .clicked()
+                    {
+                        self.turn_page(true);
+                    }
+                });
+                ui.label(RichText::new(tr("help.navigation")).italics());
+            });
+
+        if !open {
+            self.close();
+        }
+    }
+
+    /// The difficulties to show information for: just the one being played if there is one, or
+    /// all of them otherwise.
+    fn difficulties(&self) -> Vec<&'static DifficultyInfo> {
+        match self.difficulty.and_then(DifficultyInfo::from_index) {
+            Some(info) => vec![info],
+            None => DIFFICULTIES.iter().collect(),
+        }
+    }
+
+    fn timing_table(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("help timing windows")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(tr("help.timing.difficulty"));
+                ui.strong(tr("judgement.good"));
+                ui.strong(tr("judgement.ok"));
+                ui.strong(tr("judgement.bad"));
+                ui.end_row();
+
+                for info in self.difficulties() {
+                    ui.label(RichText::new(tr(info.name_key)).color(info.egui_colour()));
+
+                    for ms in info.timing_windows_ms {
+                        ui.label(tr_with("help.timing.window", &[("ms", &ms)]));
+                    }
+
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn gauge_table(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("help clear lines")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(tr("help.timing.difficulty"));
+                ui.strong(tr("help.gauge.clear_line"));
+                ui.end_row();
+
+                for info in self.difficulties() {
+                    let percent = clear_threshold(info.index) * 100 / MAX_HEALTH;
+
+                    ui.label(RichText::new(tr(info.name_key)).color(info.egui_colour()));
+                    ui.label(format!("{percent}%"));
+                    ui.end_row();
+                }
+            });
+    }
+}
+
+/// Shows what every key does. The drum keys are read from the settings each time, so they're
+/// always the ones that are actually in use.
+fn controls_table(ui: &mut egui::Ui) {
+    let key_mappings = settings().game.key_mappings.clone();
+
+    let rows = [
+        ("help.controls.left_don", key_name(key_mappings.left_don)),
+        ("help.controls.right_don", key_name(key_mappings.right_don)),
+        ("help.controls.left_kat", key_name(key_mappings.left_kat)),
+        ("help.controls.right_kat", key_name(key_mappings.right_kat)),
+        (
+            "help.controls.pause",
+            key_name(PhysicalKey::Code(KeyCode::Escape)),
+        ),
+        (
+            "help.controls.bookmark",
+            key_name(PhysicalKey::Code(KeyCode::F3)),
+        ),
+        (
+            "help.controls.retry",
+            key_name(PhysicalKey::Code(KeyCode::KeyR)),
+        ),
+        (
+            "help.controls.chart_offset",
+            format!(
+                "{} / {}",
+                key_name(PhysicalKey::Code(KeyCode::Minus)),
+                key_name(PhysicalKey::Code(KeyCode::Equal))
+            ),
+        ),
+        (
+            "help.controls.loop_start",
+            key_name(PhysicalKey::Code(KeyCode::F4)),
+        ),
+        (
+            "help.controls.loop_end",
+            key_name(PhysicalKey::Code(KeyCode::F5)),
+        ),
+        (
+            "help.controls.clear_loop",
+            key_name(PhysicalKey::Code(KeyCode::F6)),
+        ),
+        (
+            "help.controls.waveform_zoom",
+            format!(
+                "{} / {}",
+                key_name(PhysicalKey::Code(KeyCode::F7)),
+                key_name(PhysicalKey::Code(KeyCode::F8))
+            ),
+        ),
+        (
+            "help.controls.help",
+            key_name(PhysicalKey::Code(KeyCode::F1)),
+        ),
+        (
+            "help.controls.fps",
+            key_name(PhysicalKey::Code(KeyCode::F1)),
+        ),
+        (
+            "help.controls.streamer_mode",
+            key_name(PhysicalKey::Code(KeyCode::F2)),
+        ),
+        (
+            "help.controls.fullscreen",
+            key_name(PhysicalKey::Code(KeyCode::F11)),
+        ),
+    ];
+
+    egui::Grid::new("help controls")
+        .striped(true)
+        .show(ui, |ui| {
+            for (action, key) in rows {
+                ui.label(tr(action));
+                ui.strong(key);
+                ui.end_row();
+            }
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_turning_pages_stops_at_the_ends() {
+        let mut help = HelpOverlay::default();
+        help.open(None);
+
+        help.turn_page(false);
+        assert_eq!(help.page, 0);
+
+        for _ in 0..10 {
+            help.turn_page(true);
+        }
+        assert_eq!(help.page, PAGES.len() - 1);
+
+        // Opening it again starts from the beginning
+        help.open(Some(3));
+        assert_eq!(help.page, 0);
+    }
+
+    #[test]
+    fn test_every_page_has_text() {
+        for page in PAGES {
+            for part in ["title", "body"] {
+                let key = format!("{}.{part}", page.key());
+                assert_ne!(tr(&key), key);
+            }
+        }
+    }
+}
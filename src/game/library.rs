@@ -0,0 +1,781 @@
+//! Finding the songs in the songs folder, and keeping a record of anything that went wrong along
+//! the way.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use anyhow::Context;
+
+use super::song_index::{FileStamp, IndexedSong, SongIndex, SONG_INDEX_PATH};
+use super::tasks::TaskContext;
+use crate::notechart_parser::{
+    decode_chart, parse_osu_file, parse_tja_file, parse_tja_file_with_options, song_from_beatmaps,
+    CourseMask, OsuParseError, ParseOptions, Song, TJAParseError,
+};
+
+/// The file the import report is exported to.
+pub const IMPORT_REPORT_PATH: &str = "import_report.txt";
+
+/// A song folder that couldn't be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedSong {
+    pub path: PathBuf,
+    /// What went wrong, with as much detail as we have.
+    pub message: String,
+}
+
+/// A summary of what happened while scanning the songs folder.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub songs_added: usize,
+    /// Songs that couldn't be loaded because of an error, grouped by the kind of error.
+    pub skipped: BTreeMap<&'static str, Vec<SkippedSong>>,
+    /// Songs whose chart loaded fine, but whose audio file doesn't exist. These aren't added, as
+    /// they can't be played.
+    pub missing_audio: Vec<PathBuf>,
+    /// Songs whose charts are exactly the same as a song that was already added, along with the
+    /// path of that song. These aren't added either.
+    pub duplicates: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ImportReport {
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.values().map(Vec::len).sum()
+    }
+
+    /// Whether any songs weren't added.
+    pub fn has_problems(&self) -> bool {
+        !self.skipped.is_empty() || !self.missing_audio.is_empty() || !self.duplicates.is_empty()
+    }
+
+    /// The groups of skipped songs, with the most common kind of error first.
+    pub fn skipped_groups(&self) -> Vec<(&'static str, &[SkippedSong])> {
+        let mut groups = self
+            .skipped
+            .iter()
+            .map(|(kind, songs)| (*kind, songs.as_slice()))
+            .collect::<Vec<_>>();
+
+        // This is a stable sort, so groups of the same size stay in alphabetical order
+        groups.sort_by_key(|(_, songs)| std::cmp::Reverse(songs.len()));
+        groups
+    }
+
+    /// Formats the full report as a block of plain text.
+    pub fn to_plain_text(&self) -> String {
+        let mut text = String::new();
+
+        // Writing to a string can't fail
+        let _ = writeln!(text, "Songs added: {}", self.songs_added);
+        let _ = writeln!(
+            text,
+            "Songs skipped due to errors: {}",
+            self.skipped_count()
+        );
+
+        for (kind, songs) in self.skipped_groups() {
+            let _ = writeln!(text, "  {kind} ({}):", songs.len());
+
+            for song in songs {
+                let _ = writeln!(text, "    {}: {}", song.path.display(), song.message);
+            }
+        }
+
+        let _ = writeln!(text, "Songs missing audio: {}", self.missing_audio.len());
+        for path in self.missing_audio.iter() {
+            let _ = writeln!(text, "  {}", path.display());
+        }
+
+        let _ = writeln!(text, "Duplicate songs: {}", self.duplicates.len());
+        for (path, original) in self.duplicates.iter() {
+            let _ = writeln!(
+                text,
+                "  {} (same as {})",
+                path.display(),
+                original.display()
+            );
+        }
+
+        text
+    }
+
+    /// Writes the full report to a text file.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_plain_text())
+    }
+}
+
+/// Describes the kind of error an error is, for grouping errors in the [ImportReport].
+fn error_kind(error: &anyhow::Error) -> &'static str {
+    if let Some(error) = error.downcast_ref::<TJAParseError>() {
+        error.kind.name()
+    } else if let Some(error) = error.downcast_ref::<OsuParseError>() {
+        error.kind.name()
+    } else if error.downcast_ref::<io::Error>().is_some() {
+        "couldn't read chart file"
+    } else {
+        "other error"
+    }
+}
+
+/// Scans the songs folder (see [scan_songs]) using the song index saved in [SONG_INDEX_PATH], and
+/// saves the index again afterwards. If the index can't be read, every song is read from its chart
+/// instead.
+pub fn scan_library<P: AsRef<Path>>(
+    path: P,
+    task: &TaskContext,
+) -> anyhow::Result<(Vec<Song>, ImportReport)> {
+    let mut index = SongIndex::load(SONG_INDEX_PATH).unwrap_or_else(|e| {
+        log::error!("couldn't read the song index, so reading every song: {e}");
+        SongIndex::default()
+    });
+
+    let result = scan_songs(path, &mut index, task)?;
+
+    if let Err(e) = index.save(SONG_INDEX_PATH) {
+        log::error!("couldn't save the song index: {e}");
+    }
+
+    Ok(result)
+}
+
+/// Reads every song in the given folder. Each song is a folder containing a TJA file of the same
+/// name, or an osu!taiko beatmap set (see [read_beatmap_set]), along with its audio. Songs can be
+/// sorted into folders inside the songs folder (see [find_song_dirs]).
+///
+/// Songs whose TJA file hasn't changed since it was put in the song index aren't read again, and
+/// only have their metadata (see [Song::metadata_only]). The index is updated with every other TJA
+/// file that's read, and forgets the songs that weren't found.
+///
+/// Songs that can't be loaded are skipped, and recorded in the returned report. This only returns
+/// an error if the folder itself can't be read, or if the scan is cancelled (it's run as a
+/// background task, which it reports its progress to).
+pub fn scan_songs<P: AsRef<Path>>(
+    path: P,
+    index: &mut SongIndex,
+    task: &TaskContext,
+) -> anyhow::Result<(Vec<Song>, ImportReport)> {
+    let mut report = ImportReport::default();
+    let mut songs = Vec::new();
+    // The hashes of every difficulty of every song added so far, for finding duplicates
+    let mut hashes: HashMap<Vec<Option<String>>, PathBuf> = HashMap::new();
+    let mut chart_paths = HashSet::new();
+
+    // The order of the songs in the folder isn't guaranteed, so they're sorted so that duplicates
+    // are always found the same way around.
+    let mut song_dirs = Vec::new();
+    find_song_dirs(path.as_ref(), &mut song_dirs)?;
+
+    let song_count = song_dirs.len();
+
+    for (i, song_dir) in song_dirs.into_iter().enumerate() {
+        task.check_cancelled()?;
+        task.set_progress(
+            i as f32 / song_count as f32,
+            song_dir.file_name().unwrap_or_default().to_string_lossy(),
+        );
+
+        let chart_path = tja_path(&song_dir);
+        let stamp = FileStamp::of(&chart_path).ok();
+        let indexed = stamp.and_then(|stamp| index.get(&chart_path, stamp));
+
+        let (song, song_hashes) = match indexed {
+            Some(indexed) => (indexed.to_song(&chart_path), indexed.hashes()),
+            None => match read_song_dir(&song_dir) {
+                Ok(song) => {
+                    let indexed = IndexedSong::new(&song, stamp.unwrap_or_default());
+                    let song_hashes = indexed.hashes();
+
+                    // Songs that weren't read from a TJA file aren't indexed
+                    if let (Some(path), Some(_)) = (&song.chart_path, stamp) {
+                        index.insert(path, indexed);
+                    }
+
+                    (song, song_hashes)
+                }
+                Err(e) => {
+                    log::error!(
+                        "error encountered while trying to read song at directory {}: {e}",
+                        song_dir.to_string_lossy()
+                    );
+
+                    report
+                        .skipped
+                        .entry(error_kind(&e))
+                        .or_default()
+                        .push(SkippedSong {
+                            path: song_dir,
+                            message: format!("{e:#}"),
+                        });
+                    continue;
+                }
+            },
+        };
+
+        if let Some(path) = &song.chart_path {
+            chart_paths.insert(path.clone());
+        }
+
+        if !Path::new(&song.audio_filename).is_file() {
+            log::error!("audio file {} doesn't exist", song.audio_filename);
+            report.missing_audio.push(song.audio_filename.into());
+            continue;
+        }
+
+        if let Some(original) = hashes.get(&song_hashes) {
+            log::warn!(
+                "song at {} is a duplicate of {}",
+                song_dir.to_string_lossy(),
+                original.to_string_lossy()
+            );
+            report.duplicates.push((song_dir, original.clone()));
+            continue;
+        }
+
+        hashes.insert(song_hashes, song_dir);
+        songs.push(song);
+        report.songs_added += 1;
+    }
+
+    index.retain(&chart_paths);
+    Ok((songs, report))
+}
+
+/// Finds every song folder in the given folder, adding them to `song_dirs` in order of their
+/// paths. A folder with no chart of its own but with folders inside it is a folder of songs (e.g.
+/// a genre, or a pack someone put together), so the songs in it are found too.
+fn find_song_dirs(dir: &Path, song_dirs: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut dirs = fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+
+    for dir in dirs {
+        if !is_folder_of_songs(&dir) {
+            song_dirs.push(dir);
+        } else if let Err(e) = find_song_dirs(&dir, song_dirs) {
+            log::error!("couldn't look for songs in {}: {e}", dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a folder holds other songs, rather than being a song itself.
+fn is_folder_of_songs(dir: &Path) -> bool {
+    let has_subfolders = fs::read_dir(dir).is_ok_and(|mut entries| {
+        entries.any(|entry| entry.is_ok_and(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir())))
+    });
+
+    has_subfolders
+        && !tja_path(dir).exists()
+        && beatmap_files(dir).is_ok_and(|beatmaps| beatmaps.is_empty())
+}
+
+/// The TJA file a song folder's chart is read from, which has the same name as the folder.
+fn tja_path(dir: &Path) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.join(format!("{name}.tja"))
+}
+
+/// Reads the charts of a song that only has its metadata (see [Song::metadata_only]). Other songs
+/// are given back as they are.
+pub fn load_charts(song: &Song) -> anyhow::Result<Song> {
+    match &song.chart_path {
+        Some(path) if song.metadata_only => {
+            read_song_dir(path.parent().context("the chart isn't in a folder")?)
+        }
+        _ => Ok(song.clone()),
+    }
+}
+
+/// Why a course couldn't be read again from its chart file.
+#[derive(Debug)]
+pub enum ReloadError {
+    /// The chart has a mistake in it. The excerpt is the part of the chart around the mistake (see
+    /// [TJAParseError::excerpt]).
+    Parse {
+        error: TJAParseError,
+        excerpt: String,
+    },
+    /// Anything else, e.g. the chart file couldn't be read.
+    Other(anyhow::Error),
+}
+
+/// Reads one course of a song from its chart file again, without building the song's other
+/// courses. The course will be missing from the returned song if the chart doesn't have it any
+/// more.
+///
+/// In lenient mode, mistakes in the chart are worked around where possible, and recorded in the
+/// song's warnings (see [ParseOptions::lenient]).
+///
+/// Songs that weren't loaded from a TJA file (e.g. osu! beatmap sets) can't be read again one
+/// course at a time, so they're given back as they are.
+pub fn reload_course(song: &Song, difficulty: usize, lenient: bool) -> Result<Song, ReloadError> {
+    let Some(path) = song.chart_path.as_ref() else {
+        return Ok(song.clone());
+    };
+    let source = read_tja(path).map_err(|e| ReloadError::Other(e.into()))?;
+
+    let options = ParseOptions {
+        courses: CourseMask::only(difficulty),
+        lenient,
+        ..Default::default()
+    };
+
+    parse_tja_file_with_options(&source, &options).map_err(|error| ReloadError::Parse {
+        excerpt: error.excerpt(&source),
+        error,
+    })
+}
+
+/// Reads a TJA file, whatever encoding it's in (see [decode_chart]).
+fn read_tja(path: &Path) -> io::Result<String> {
+    Ok(decode_chart(&fs::read(path)?))
+}
+
+fn read_song_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
+    if path.as_ref().file_name().is_none() {
+        return Err(
+            io::Error::new(io::ErrorKind::InvalidData, "couldn't read directory name").into(),
+        );
+    }
+
+    let tja_file_path = tja_path(path.as_ref());
+    let beatmaps = if tja_file_path.exists() {
+        Vec::new()
+    } else {
+        beatmap_files(path.as_ref())?
+    };
+
+    let mut song = if beatmaps.is_empty() {
+        let mut song = parse_tja_file(&read_tja(&tja_file_path)?)?;
+        song.chart_path = Some(tja_file_path);
+        song
+    } else {
+        read_beatmap_set(&beatmaps)?
+    };
+
+    // Courses with no notes are left out, which can leave nothing to play
+    if song.difficulties.iter().all(Option::is_none) {
+        anyhow::bail!("the chart has no courses with notes in it");
+    }
+
+    let audio_filename = path
+        .as_ref()
+        .join(&song.audio_filename)
+        .to_string_lossy()
+        .into_owned();
+
+    song.audio_filename = audio_filename;
+    Ok(song)
+}
+
+/// The `.osu` files in a folder, in order of their names.
+fn beatmap_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "osu"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads an osu!taiko beatmap set into a song, from the `.osu` file for each of its difficulties.
+///
+/// Sets often have beatmaps for other modes alongside the taiko ones, so beatmaps that can't be
+/// read are left out, as long as at least one can be. The song isn't given a chart path, as the
+/// editor can only save TJA files.
+fn read_beatmap_set(files: &[PathBuf]) -> anyhow::Result<Song> {
+    let mut beatmaps = Vec::new();
+    let mut first_error = None;
+
+    for file in files {
+        let beatmap = fs::read_to_string(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(parse_osu_file(&contents)?))
+            .with_context(|| format!("in {}", file.display()));
+
+        match beatmap {
+            Ok(beatmap) => beatmaps.push(beatmap),
+            Err(e) => {
+                log::warn!("leaving a beatmap out: {e:#}");
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if beatmaps.is_empty() {
+        return Err(first_error.unwrap_or_else(|| anyhow::format_err!("no beatmaps in the set")));
+    }
+
+    let (song, left_out) = song_from_beatmaps(beatmaps);
+
+    for version in left_out {
+        log::warn!("left the {version:?} beatmap of {} out", song.title);
+    }
+
+    Ok(song)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::TJAParseErrorKind;
+
+    const GOOD_CHART: &str = "TITLE:Good
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:5
+#START
+1020,
+#END
+";
+
+    /// A folder full of songs that is deleted when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("taiko-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn add_song(&self, name: &str, chart: &str, audio: bool) {
+            let dir = self.0.join(name);
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join(format!("{name}.tja")), chart).unwrap();
+
+            if audio {
+                fs::write(dir.join("song.ogg"), []).unwrap();
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_reload_broken_course() {
+        let fixture = Fixture::new("reload-broken");
+        fixture.add_song("edited", GOOD_CHART, true);
+        let (songs, _) = scan_songs(
+            &fixture.0,
+            &mut SongIndex::default(),
+            &TaskContext::default(),
+        )
+        .unwrap();
+
+        // The chart was fine when it was scanned, but has since been changed to one that can only
+        // be loaded in lenient mode
+        let lenient_only = include_str!("../../tests/fixtures/tja/lenient_invalid_measures.tja");
+        fs::write(fixture.0.join("edited/edited.tja"), lenient_only).unwrap();
+
+        let Err(ReloadError::Parse { error, excerpt }) = reload_course(&songs[0], 3, false) else {
+            panic!("the chart should only load in lenient mode");
+        };
+        assert_eq!(error.kind, TJAParseErrorKind::InvalidTimeSignature(0, 4));
+        assert!(excerpt.contains(">   10 | #MEASURE 0/4"), "{excerpt}");
+
+        let song = reload_course(&songs[0], 3, true).unwrap();
+        assert!(song.difficulties[3].is_some());
+        assert_eq!(song.warnings.len(), 2);
+
+        // Other problems aren't the chart's fault
+        fs::remove_file(fixture.0.join("edited/edited.tja")).unwrap();
+        assert!(matches!(
+            reload_course(&songs[0], 3, true),
+            Err(ReloadError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_scan_beatmap_sets() {
+        let beatmap = |mode: u8, version: &str| {
+            format!(
+                "osu file format v14
+[General]
+AudioFilename: song.ogg
+Mode: {mode}
+[Metadata]
+Title:Beatmap
+Version:{version}
+[TimingPoints]
+0,500,4,1,0,100,1,0
+[HitObjects]
+256,192,0,1,0,0:0:0:0:
+256,192,500,1,2,0:0:0:0:
+"
+            )
+        };
+
+        let fixture = Fixture::new("beatmap-sets");
+        let add_set = |name: &str, beatmaps: &[(u8, &str)]| {
+            let dir = fixture.0.join(name);
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("song.ogg"), []).unwrap();
+
+            for &(mode, version) in beatmaps {
+                fs::write(dir.join(format!("{version}.osu")), beatmap(mode, version)).unwrap();
+            }
+        };
+
+        // Beatmaps for other modes are left out of a set with taiko ones in it
+        add_set("mixed", &[(1, "Muzukashii"), (0, "Insane")]);
+        add_set("standard", &[(0, "Insane")]);
+
+        let (songs, report) = scan_songs(
+            &fixture.0,
+            &mut SongIndex::default(),
+            &TaskContext::default(),
+        )
+        .unwrap();
+
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].title, "Beatmap");
+        assert_eq!(songs[0].chart_path, None);
+        assert!(songs[0].audio_filename.ends_with("song.ogg"));
+        let difficulties = songs[0]
+            .difficulties
+            .iter()
+            .map(Option::is_some)
+            .collect::<Vec<_>>();
+        assert_eq!(difficulties, [false, false, true, false, false]);
+
+        // There's nothing to read again, so the song stays as it was scanned
+        let reloaded = reload_course(&songs[0], 2, false).unwrap();
+        assert_eq!(
+            reloaded.difficulties[2].as_ref().unwrap().chart.notes.len(),
+            2
+        );
+
+        let groups = report.skipped_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, "beatmap isn't for osu!taiko");
+        assert!(
+            groups[0].1[0]
+                .message
+                .contains("Insane.osu: the beatmap is for osu!standard"),
+            "{}",
+            groups[0].1[0].message
+        );
+    }
+
+    #[test]
+    fn test_songs_with_only_empty_courses_are_skipped() {
+        let fixture = Fixture::new("empty-courses");
+        fixture.add_song("placeholder", &GOOD_CHART.replace("1020,", "0000,"), true);
+        fixture.add_song(
+            "one placeholder",
+            include_str!("../../tests/fixtures/tja/command_only_course.tja"),
+            true,
+        );
+
+        let (songs, report) = scan_songs(
+            &fixture.0,
+            &mut SongIndex::default(),
+            &TaskContext::default(),
+        )
+        .unwrap();
+
+        assert_eq!(songs.len(), 1);
+        assert!(songs[0].difficulties[3].is_none());
+        assert!(songs[0].difficulties[0].is_some());
+
+        let groups = report.skipped_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, "other error");
+        assert!(groups[0].1[0].message.contains("no courses"));
+    }
+
+    #[test]
+    fn test_scan_nested_folders() {
+        let fixture = Fixture::new("nested-folders");
+        fixture.add_song("top level", GOOD_CHART, true);
+        fs::create_dir_all(fixture.0.join("pack/genre")).unwrap();
+
+        let pack = Fixture(fixture.0.join("pack/genre"));
+        pack.add_song("deep", &GOOD_CHART.replace("1020,", "1120,"), true);
+        // Folders inside a song's folder aren't searched
+        pack.add_song("has extras", &GOOD_CHART.replace("1020,", "2020,"), true);
+        fs::create_dir(pack.0.join("has extras/videos")).unwrap();
+
+        let (songs, report) = scan_songs(
+            &fixture.0,
+            &mut SongIndex::default(),
+            &TaskContext::default(),
+        )
+        .unwrap();
+
+        assert!(!report.has_problems(), "{}", report.to_plain_text());
+        let charts = songs
+            .iter()
+            .map(|song| song.chart_path.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            charts,
+            [
+                pack.0.join("deep/deep.tja"),
+                pack.0.join("has extras/has extras.tja"),
+                fixture.0.join("top level/top level.tja"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_with_index() {
+        let fixture = Fixture::new("scan-index");
+        fixture.add_song("a", GOOD_CHART, true);
+        fixture.add_song("b", &GOOD_CHART.replace("1020,", "1120,"), true);
+        fixture.add_song("broken", &GOOD_CHART.replace("#END", ""), true);
+
+        let scan =
+            |index: &mut SongIndex| scan_songs(&fixture.0, index, &TaskContext::default()).unwrap();
+
+        // Nothing is indexed the first time around, so every chart is read
+        let mut index = SongIndex::default();
+        let (songs, report) = scan(&mut index);
+        assert_eq!(songs.len(), 2);
+        assert!(songs.iter().all(|song| !song.metadata_only));
+
+        // Now only the songs that were read fine come from the index
+        let (songs, second_report) = scan(&mut index);
+        assert_eq!(second_report, report);
+        assert!(songs.iter().all(|song| song.metadata_only));
+        assert_eq!(songs[0].title, "Good");
+        let course = songs[0].difficulties[3].as_ref().unwrap();
+        assert_eq!(course.star_level, 5);
+        assert!(course.chart.notes.is_empty());
+
+        // The charts can be read when they're needed
+        let loaded = load_charts(&songs[0]).unwrap();
+        assert!(!loaded.metadata_only);
+        assert_eq!(loaded.audio_filename, songs[0].audio_filename);
+        assert_eq!(
+            loaded.difficulties[3].as_ref().unwrap().chart.notes.len(),
+            2
+        );
+
+        // Charts that have changed are read again. Songs that are found to be duplicates from the
+        // index alone are still caught.
+        fs::write(
+            fixture.0.join("b/b.tja"),
+            GOOD_CHART.replace("TITLE:Good", "TITLE:Changed"),
+        )
+        .unwrap();
+        fs::remove_dir_all(fixture.0.join("broken")).unwrap();
+
+        let (songs, report) = scan(&mut index);
+        assert_eq!(songs.len(), 1);
+        assert!(songs[0].metadata_only);
+        assert_eq!(
+            report.duplicates,
+            [(fixture.0.join("b"), fixture.0.join("a"))]
+        );
+
+        // Songs that weren't found any more are forgotten, and nothing else is
+        fs::remove_dir_all(fixture.0.join("a")).unwrap();
+        let (songs, _) = scan(&mut index);
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].title, "Changed");
+        assert!(songs[0].metadata_only);
+    }
+
+    #[test]
+    fn test_scan_report() {
+        let fixture = Fixture::new("scan-report");
+
+        fixture.add_song("a good song", GOOD_CHART, true);
+        fixture.add_song(
+            "another good song",
+            &GOOD_CHART.replace("1020,", "1120,"),
+            true,
+        );
+        // Same notes as the first song, with different metadata
+        fixture.add_song(
+            "copy of a good song",
+            &GOOD_CHART.replace("TITLE:Good", "TITLE:Copy"),
+            true,
+        );
+        fixture.add_song("no audio", &GOOD_CHART.replace("1020,", "2000,"), false);
+        fixture.add_song("no end", &GOOD_CHART.replace("#END", ""), true);
+        fixture.add_song(
+            "syntax error 1",
+            &GOOD_CHART.replace("1020,", "1x20,"),
+            true,
+        );
+        fixture.add_song("syntax error 2", &GOOD_CHART.replace("1020,", "?"), true);
+        // A folder with no chart in it at all
+        fs::create_dir(fixture.0.join("empty")).unwrap();
+
+        let (songs, report) = scan_songs(
+            &fixture.0,
+            &mut SongIndex::default(),
+            &TaskContext::default(),
+        )
+        .unwrap();
+
+        assert_eq!(songs.len(), 2);
+        assert_eq!(report.songs_added, 2);
+        assert!(report.has_problems());
+
+        assert_eq!(report.skipped_count(), 4);
+        let groups = report
+            .skipped_groups()
+            .into_iter()
+            .map(|(kind, songs)| {
+                let mut names = songs
+                    .iter()
+                    .map(|song| {
+                        song.path
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect::<Vec<_>>();
+                names.sort();
+                (kind, names)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            groups,
+            [
+                (
+                    "syntax error",
+                    vec!["syntax error 1".to_string(), "syntax error 2".to_string()]
+                ),
+                ("couldn't read chart file", vec!["empty".to_string()]),
+                ("expected #END command", vec!["no end".to_string()]),
+            ]
+        );
+
+        assert_eq!(
+            report.missing_audio,
+            [fixture.0.join("no audio").join("song.ogg")]
+        );
+        assert_eq!(
+            report.duplicates,
+            [(
+                fixture.0.join("copy of a good song"),
+                fixture.0.join("a good song")
+            )]
+        );
+
+        let text = report.to_plain_text();
+        assert!(text.starts_with("Songs added: 2\nSongs skipped due to errors: 4\n"));
+        assert!(text.contains("  syntax error (2):\n"));
+        assert!(text.contains("Duplicate songs: 1\n"));
+    }
+}
@@ -0,0 +1,124 @@
+//! The screen shown between choosing a song and playing it.
+//!
+//! The song's audio is decoded (and stretched, if it needs to be) on a background task while this
+//! screen shows how far along it is, so that a long song doesn't freeze the game. Once the audio
+//! is ready, the state that plays it is built, notes and all, and takes this screen's place.
+//! Escape goes back to song select, cancelling the loading.
+use egui::RichText;
+use kira::sound::static_sound::StaticSoundData;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::audio::AudioManager;
+use crate::game::tasks::Task;
+use crate::game::{Context, GameState, SoundEffect, StateTransition, TransitionStyle};
+
+/// Builds the state that plays the song, once its audio has loaded.
+pub type StartSong =
+    Box<dyn FnOnce(StaticSoundData, &mut Context) -> anyhow::Result<Box<dyn GameState>>>;
+
+pub struct LoadingScreen {
+    title: String,
+    /// What's being played, e.g. the difficulty, shown under the title
+    detail: String,
+    task: Task<StaticSoundData>,
+    start: Option<StartSong>,
+    /// Why the song couldn't be played, if it couldn't
+    error: Option<String>,
+    back: bool,
+}
+
+impl LoadingScreen {
+    pub fn new(
+        title: impl Into<String>,
+        detail: impl Into<String>,
+        task: Task<StaticSoundData>,
+        start: StartSong,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            detail: detail.into(),
+            task,
+            start: Some(start),
+            error: None,
+            back: false,
+        }
+    }
+}
+
+impl GameState for LoadingScreen {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        let escape = ctx
+            .keyboard
+            .is_just_pressed(PhysicalKey::Code(KeyCode::Escape));
+
+        if escape || self.back {
+            // Dropping the task along with the screen cancels it
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
+            return StateTransition::Pop;
+        }
+
+        let Some(result) = self.task.poll() else {
+            return StateTransition::Continue;
+        };
+        let Some(start) = self.start.take() else {
+            return StateTransition::Continue;
+        };
+
+        match result.and_then(|sound_data| start(sound_data, ctx)) {
+            Ok(state) => StateTransition::Swap(state),
+            Err(e) => {
+                log::error!("couldn't load the song: {e:#}");
+                self.error = Some(format!("{e:#}"));
+                StateTransition::Continue
+            }
+        }
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::Area::new("Loading".into())
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(&ctx, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.label(RichText::new(&self.title).size(40.0).strong());
+                    ui.label(RichText::new(&self.detail).size(20.0));
+                    ui.add_space(30.0);
+
+                    match &self.error {
+                        Some(error) => {
+                            ui.label(RichText::new("Couldn't load the song").size(20.0));
+                            ui.label(error);
+                            ui.add_space(20.0);
+
+                            if ui.button("Back").clicked() {
+                                self.back = true;
+                            }
+                        }
+                        None => {
+                            let (progress, label) = self.task.progress();
+
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(label);
+                            });
+                            ui.add(
+                                egui::ProgressBar::new(progress)
+                                    .desired_width(400.0)
+                                    .show_percentage(),
+                            );
+                            ui.add_space(20.0);
+                            ui.label(RichText::new("Press escape to go back").weak());
+                        }
+                    }
+                });
+            });
+    }
+
+    fn recreate_gpu_resources(&mut self, _ctx: &mut Context) -> bool {
+        // Everything on the screen is drawn with egui
+        true
+    }
+
+    fn transition_style(&self) -> TransitionStyle {
+        TransitionStyle::Crossfade
+    }
+}
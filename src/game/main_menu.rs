@@ -1,26 +1,28 @@
-use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
+use kaku::{FontSize, HorizontalAlignment, TextBuilder, VerticalAlignment};
 
 use crate::{
     game::{
         ui_elements::{Button, ButtonOptions},
-        Context, GameState, RenderContext, StateTransition, TextureCache,
+        Context, GameState, RenderContext, SoundEffect, StateTransition, TextureCache,
     },
     render::{
         rgb,
         shapes::{LinearGradient, Shape, ShapeBuilder, SolidColour},
+        text::{BuildTextWithRenderer, TrackedText},
         texture::{Sprite, SpriteBuilder},
         Renderer,
     },
 };
 
-use super::SongSelect;
+use super::{SettingsScreen, SongSelect};
 
 pub struct MainMenu {
     background: Sprite,
     menu_frame: Shape,
-    title: Text,
+    title: TrackedText,
     taiko_mode_button: Button,
     settings_button: Button,
+    help_button: Button,
     exit_button: Button,
 }
 
@@ -71,11 +73,7 @@ impl MainMenu {
         .horizontal_align(HorizontalAlignment::Center)
         .color([1.; 4])
         .outlined(rgb!(0x14, 0x10, 0x6D), 3.)
-        .build(
-            &renderer.device,
-            &renderer.queue,
-            &mut renderer.text_renderer,
-        );
+        .build_text(renderer);
 
         let taiko_mode_button = Button::new(
             "Taiko Mode",
@@ -99,6 +97,17 @@ impl MainMenu {
             renderer,
         )?;
 
+        let help_button = Button::new(
+            "Help",
+            [120., 560.],
+            ButtonOptions {
+                colour: rgb!(0x1E, 0x9B, 0xD7),
+                text_outline_colour: rgb!(0x0B, 0x3C, 0x5D),
+                ..Default::default()
+            },
+            renderer,
+        )?;
+
         let exit_button = Button::new(
             "Exit",
             [120., 940.],
@@ -124,6 +133,7 @@ impl MainMenu {
             title,
             taiko_mode_button,
             settings_button,
+            help_button,
             exit_button,
         })
     }
@@ -136,22 +146,54 @@ impl GameState for MainMenu {
         ctx.render(&self.title);
         ctx.render(&self.taiko_mode_button);
         ctx.render(&self.settings_button);
+        ctx.render(&self.help_button);
         ctx.render(&self.exit_button);
     }
 
     fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        ctx.sounds.play_menu_music(ctx.audio);
+
         self.taiko_mode_button.update(ctx);
         self.settings_button.update(ctx);
+        self.help_button.update(ctx);
         self.exit_button.update(ctx);
 
         if self.taiko_mode_button.is_clicked(ctx) {
+            ctx.sounds.play(ctx.audio, SoundEffect::Select);
             StateTransition::Push(Box::new(
-                SongSelect::new(ctx.textures, ctx.renderer).unwrap(),
+                SongSelect::new(ctx.textures, ctx.renderer, ctx.tasks).unwrap(),
             ))
+        } else if self.settings_button.is_clicked(ctx) {
+            ctx.sounds.play(ctx.audio, SoundEffect::Select);
+            match SettingsScreen::new(ctx.renderer, ctx.textures) {
+                Ok(screen) => StateTransition::Push(Box::new(screen)),
+                Err(e) => {
+                    log::error!("couldn't open settings: {e}");
+                    StateTransition::Continue
+                }
+            }
+        } else if self.help_button.is_clicked(ctx) {
+            ctx.sounds.play(ctx.audio, SoundEffect::Select);
+            ctx.help.request();
+            StateTransition::Continue
         } else if self.exit_button.is_clicked(ctx) {
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
             StateTransition::Exit
         } else {
             StateTransition::Continue
         }
     }
+
+    fn recreate_gpu_resources(&mut self, ctx: &mut Context) -> bool {
+        match MainMenu::new(ctx.textures, ctx.renderer) {
+            Ok(menu) => {
+                *self = menu;
+                true
+            }
+            Err(e) => {
+                log::error!("couldn't recreate main menu: {e}");
+                false
+            }
+        }
+    }
 }
@@ -1,17 +1,51 @@
+mod chart_settings;
+mod clear_chance;
+mod collections;
 mod credits;
+mod difficulty_select;
+mod editor;
+mod error_screen;
+mod export;
+mod help;
+mod library;
+mod loading_screen;
 mod main_menu;
+mod presence;
+mod replay;
 mod score_screen;
+mod scores;
+mod search;
+mod settings_screen;
+mod song_index;
+mod song_preview;
 mod song_select;
+mod sound;
 mod taiko_mode;
+mod tasks;
+mod time_stretch;
+mod timestep;
+mod transition;
 mod ui_elements;
 
-use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
+use error_screen::ErrorScreen;
+use help::{HelpOverlay, HELP_HOLD_TIME};
+use kaku::{FontSize, HorizontalAlignment, TextBuilder, VerticalAlignment};
 pub use main_menu::MainMenu;
+use presence::{Presence, RichPresence};
+pub use settings_screen::SettingsScreen;
 pub use song_select::SongSelect;
+pub use sound::{SoundEffect, Sounds};
+use tasks::Tasks;
+use timestep::{FixedTimestep, FIXED_TIMESTEP};
+pub use transition::TransitionStyle;
+use transition::{Transition, TransitionDirection};
+use ui_elements::{TapTempo, TAP_KEY};
 
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Instant;
 
-use kira::manager::{backend::DefaultBackend, AudioManager};
+use kira::manager::AudioManagerSettings;
 use std::collections::HashMap;
 
 use winit::{
@@ -20,10 +54,42 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
 };
 
-use crate::render::{self, texture::Texture, Renderable, Renderer};
+use crate::audio::{AudioBackendSettings, AudioManager};
+use crate::render::text::{BuildTextWithRenderer, TrackedText};
+use crate::render::texture::{load_image, SpriteBuilder, TextureAtlasBuilder};
+use crate::render::{self, resources, texture::Texture, Renderable, Renderer};
+use crate::settings::{save_settings, settings, settings_mut, SettingsSnapshot};
+use crate::skin::{load_skin, skin};
 
 const FPS_POLL_TIME: f32 = 0.5;
 const SPRITES_PATH: &str = "assets/images";
+// Some important textures that are loaded as soon as the game starts
+const PRELOADED_TEXTURES: [&str; 6] = [
+    "don.png",
+    "kat.png",
+    "big_don.png",
+    "big_kat.png",
+    "drumroll_start.png",
+    "big_drumroll_start.png",
+];
+/// The small textures used while playing, which are packed into one atlas the first time any of
+/// them is needed, so that notes of every type can be drawn together.
+const ATLAS_TEXTURES: [&str; 10] = [
+    "don.png",
+    "kat.png",
+    "big_don.png",
+    "big_kat.png",
+    "drumroll_start.png",
+    "big_drumroll_start.png",
+    "balloon 1.png",
+    "balloon 3.png",
+    "balloon 5.png",
+    "balloon speech bubble.png",
+];
+/// How many pixels across the texture atlas can be at most.
+const ATLAS_MAX_SIZE: u32 = 2048;
+
+type CreateStateFn = dyn Fn(&mut render::Renderer, &mut TextureCache) -> Box<dyn GameState>;
 
 pub enum StateTransition {
     Continue,
@@ -36,13 +102,19 @@ pub enum StateTransition {
 pub struct Context<'ctx> {
     pub audio: &'ctx mut AudioManager,
     pub renderer: &'ctx mut Renderer,
-    pub keyboard: &'ctx KeyboardState,
+    pub keyboard: &'ctx mut KeyboardState,
     pub textures: &'ctx mut TextureCache,
     pub mouse: &'ctx MouseState,
+    pub help: &'ctx mut HelpOverlay,
+    pub tasks: &'ctx mut Tasks,
+    pub sounds: &'ctx mut Sounds,
+    /// When the event being handled reached the game (see [GameState::handle_event]). Inputs
+    /// should be timed by this rather than by when they're handled, which could be a little
+    /// later. Outside of handling an event, this is just when the context was made.
+    pub event_time: Instant,
 }
 
 pub struct RenderContext<'ctx, 'pass> {
-    pub audio: &'ctx mut AudioManager,
     pub renderer: &'pass Renderer,
     pub textures: &'ctx mut TextureCache,
     pub keyboard: &'ctx KeyboardState,
@@ -62,24 +134,119 @@ pub trait GameState {
         StateTransition::Continue
     }
 
+    /// Called once for every fixed step of the game's timing logic (see [timestep]) that's come
+    /// due since the last frame, just before [update](GameState::update). `time` is when the step
+    /// was due, which is always in the past by the time it's run. Anything that has to happen at a
+    /// particular time, rather than whenever the next frame is, belongs here.
+    fn fixed_update(&mut self, _ctx: &mut Context, _time: Instant) {}
+
     // TODO: Fix this up.
     fn debug_ui(&mut self, _ctx: egui::Context, _audio: &mut AudioManager) {}
 
     fn render<'pass>(&'pass mut self, _ctx: &mut RenderContext<'_, 'pass>) {}
 
     fn handle_event(&mut self, _ctx: &mut Context, _event: &WindowEvent) {}
+
+    /// Called after the GPU device was lost and the renderer had to be recreated. Every sprite,
+    /// shape and text belonging to the state was created with the old device, so they all have to
+    /// be built again.
+    ///
+    /// Returns whether the state was able to recreate its resources. If it wasn't (which is what
+    /// the default implementation says), the state is thrown away along with every state above
+    /// it.
+    fn recreate_gpu_resources(&mut self, _ctx: &mut Context) -> bool {
+        false
+    }
+
+    /// Called when an overlay (like the help overlay) is opened on top of the state. While it's
+    /// open, the state still gets updated, but doesn't receive any keyboard input. States that
+    /// shouldn't keep going without the player, like gameplay, should pause here.
+    fn overlay_opened(&mut self, _ctx: &mut Context) {}
+
+    /// Called when the window loses focus or is minimised. Every key and mouse button has already
+    /// been released by the time this is called, as their releases might never arrive. States
+    /// where the player would miss something while away, like gameplay, should pause here.
+    fn focus_lost(&mut self, _ctx: &mut Context) {}
+
+    /// The difficulty of the song being played in this state, if there is one.
+    fn difficulty(&self) -> Option<usize> {
+        None
+    }
+
+    /// How the game should animate switching to this state, and back out of it when it's popped.
+    fn transition_style(&self) -> TransitionStyle {
+        TransitionStyle::default()
+    }
+
+    /// The name of the state, used to tell states apart in debugging tools. Defaults to the name
+    /// of the type.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// What the player is doing in this state, for showing on Discord (see [presence]). Defaults
+    /// to going by the state's [name](GameState::name).
+    fn presence(&self) -> Presence {
+        Presence::for_state(self.name())
+    }
 }
 
 /// A struct that keeps track of the state of the keyboard at each frame.
 ///
 /// Each keycode is mapped to a tuple containing two booleans; the first indicates whether the key
 /// was pressed last frame, the second indicates whether the key is pressed this frame.
+#[derive(Default)]
 pub struct KeyboardState(HashMap<PhysicalKey, (bool, bool)>);
 
 impl KeyboardState {
     fn handle_input(&mut self, event: &KeyEvent) {
-        let pressed = event.state == ElementState::Pressed;
-        self.0.entry(event.physical_key).or_insert((false, false)).1 = pressed;
+        self.handle_key(
+            event.physical_key,
+            event.state == ElementState::Pressed,
+            event.repeat,
+        );
+    }
+
+    fn handle_key(&mut self, key: PhysicalKey, pressed: bool, repeat: bool) {
+        // A key that's repeating was already pressed, unless its press happened while the window
+        // was unfocused, in which case it shouldn't suddenly count as pressed now.
+        if !repeat {
+            self.set_pressed(key, pressed);
+        }
+    }
+
+    /// Forgets every key that's being held down, without them counting as just released. This is
+    /// for when the window loses focus, as the key releases might never arrive, and a key that's
+    /// stuck down would stop the next real press from counting as one.
+    fn release_all(&mut self) {
+        self.0.clear();
+    }
+
+    fn set_pressed(&mut self, key: PhysicalKey, pressed: bool) {
+        self.0.entry(key).or_insert((false, false)).1 = pressed;
+    }
+
+    /// Moves on to the next frame, so that the keys pressed this frame are no longer considered
+    /// "just pressed".
+    fn end_frame(&mut self) {
+        for (last_frame, this_frame) in self.0.values_mut() {
+            *last_frame = *this_frame;
+        }
+    }
+
+    /// Makes it so that the given key is no longer considered just pressed or just released this
+    /// frame. Use this once a keypress has been acted upon, so that nothing else acts on it too.
+    pub fn consume_key(&mut self, key: PhysicalKey) {
+        if let Some((last_frame, this_frame)) = self.0.get_mut(&key) {
+            *last_frame = *this_frame;
+        }
+    }
+
+    /// Consumes every key that was just pressed or released this frame (see
+    /// [KeyboardState::consume_key]). Whether or not each key is held down is unaffected.
+    pub fn clear_edges(&mut self) {
+        self.end_frame();
     }
 
     /// Returns whether or not the given key is pressed this frame.
@@ -104,16 +271,19 @@ impl KeyboardState {
     }
 }
 
+#[derive(Default)]
 pub struct MouseState {
     position: Option<(f32, f32)>,
     button_map: HashMap<MouseButton, (bool, bool)>,
 }
 
 impl MouseState {
-    fn handle_input(&mut self, event: &WindowEvent) {
+    /// Cursor positions are stored in virtual screen coordinates, so they can be compared with
+    /// the positions of things on screen.
+    fn handle_input(&mut self, event: &WindowEvent, viewport: render::Viewport) {
         match *event {
             WindowEvent::CursorMoved { position, .. } => {
-                self.position = Some((position.x as f32, position.y as f32));
+                self.position = Some(viewport.to_virtual((position.x as f32, position.y as f32)));
             }
 
             WindowEvent::CursorLeft { .. } => {
@@ -130,6 +300,11 @@ impl MouseState {
         }
     }
 
+    /// Forgets every button that's being held down (see [KeyboardState::release_all]).
+    fn release_all(&mut self) {
+        self.button_map.clear();
+    }
+
     /// Returns whether or not the given button is pressed this frame.
     pub fn is_pressed(&self, button: MouseButton) -> bool {
         self.button_map
@@ -161,32 +336,106 @@ impl MouseState {
 #[derive(Default)]
 pub struct TextureCache {
     cache: HashMap<&'static str, Rc<Texture>>,
+    /// Whether a song is currently being played. Loading a texture from disk in the middle of a
+    /// song causes a noticeable stutter, so in debug builds we panic if that ever happens.
+    gameplay_active: bool,
 }
 
 impl TextureCache {
+    /// Loads all of the given textures into the cache, if they aren't already loaded.
+    pub fn preload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        filenames: impl IntoIterator<Item = &'static str>,
+    ) -> anyhow::Result<()> {
+        for filename in filenames {
+            self.get(device, queue, filename)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets every texture, so that they're loaded again from the new skin. Anything already
+    /// using the old ones keeps them.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Marks whether a song is currently being played. While this is set, every texture is
+    /// expected to have been preloaded.
+    pub fn set_gameplay_active(&mut self, active: bool) {
+        self.gameplay_active = active;
+    }
+
+    /// Returns the texture with the given filename, loading it if it hasn't been loaded yet.
+    ///
+    /// Textures on the atlas (see [ATLAS_TEXTURES]) are all loaded together the first time any of
+    /// them is asked for, and come back as parts of the atlas.
     pub fn get(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         filename: &'static str,
     ) -> anyhow::Result<Rc<Texture>> {
-        match self.cache.get(&filename) {
-            Some(tex) => Ok(Rc::clone(tex)),
-            None => {
-                let tex = Rc::new(Texture::from_file(
-                    format!("{SPRITES_PATH}/{filename}"),
-                    device,
-                    queue,
-                )?);
-                self.cache.insert(filename, Rc::clone(&tex));
-                Ok(tex)
+        if let Some(tex) = self.cache.get(&filename) {
+            return Ok(Rc::clone(tex));
+        }
+
+        debug_assert!(
+            !self.gameplay_active,
+            "texture \"{filename}\" was loaded from disk during gameplay"
+        );
+
+        if ATLAS_TEXTURES.contains(&filename) {
+            self.load_atlas(device, queue);
+
+            if let Some(tex) = self.cache.get(&filename) {
+                return Ok(Rc::clone(tex));
+            }
+        }
+
+        let tex = Rc::new(Texture::from_file(texture_path(filename), device, queue)?);
+        self.cache.insert(filename, Rc::clone(&tex));
+        Ok(tex)
+    }
+
+    /// Packs every texture on the atlas that hasn't been loaded yet into a new atlas. Any that
+    /// can't be read are left out, to be loaded (and fail) on their own.
+    fn load_atlas(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut atlas = TextureAtlasBuilder::new(ATLAS_MAX_SIZE);
+
+        for filename in ATLAS_TEXTURES {
+            if self.cache.contains_key(filename) {
+                continue;
+            }
+
+            match load_image(texture_path(filename)) {
+                Ok(image) => atlas.add(filename, image),
+                Err(e) => log::warn!("couldn't add \"{filename}\" to the texture atlas: {e}"),
             }
         }
+
+        for (filename, texture) in atlas.build("texture atlas", device, queue) {
+            self.cache.insert(filename, Rc::new(texture));
+        }
     }
 }
 
+/// Where the texture with the given filename is loaded from: the skin's replacement if it has
+/// one, or the game's own otherwise.
+fn texture_path(filename: &str) -> PathBuf {
+    skin()
+        .texture_path(filename)
+        .unwrap_or_else(|| Path::new(SPRITES_PATH).join(filename))
+}
+
 pub struct Game {
     audio_manager: AudioManager,
+    sounds: Sounds,
+    /// The settings, refreshed once a frame. The audio backend is given the settings again whenever
+    /// they change.
+    settings: SettingsSnapshot,
     state: Vec<Box<dyn GameState>>,
     keyboard: KeyboardState,
     mouse: MouseState,
@@ -196,75 +445,219 @@ pub struct Game {
     frames_counted: u32,
     fps: f32,
     show_fps_counter: bool,
+    /// The tap tempo tool in the debug overlay, for checking the BPM of whatever is playing.
+    tap_tempo: TapTempo,
+    /// Shows what the player is doing on Discord, if that's turned on.
+    rich_presence: RichPresence,
+    /// Work being done in the background.
+    tasks: Tasks,
+    /// The steps of the states' timing logic that have been run.
+    timestep: FixedTimestep,
+
+    version_text: TrackedText,
+
+    help: HelpOverlay,
+    /// How long F1 has been held for, if it's being held and hasn't opened the help overlay yet.
+    f1_held_for: Option<f32>,
+
+    /// Creates the first state of the game. This is kept around so that the game can be started
+    /// again from scratch if the states can't recover from the GPU device being lost.
+    create_root_state: Box<CreateStateFn>,
+    /// Whether the game is showing an unrecoverable error.
+    fatal_error: bool,
+
+    /// A state change that needs the screen from before it captured before it can be animated.
+    pending_transition: Option<PendingTransition>,
+    /// The animation from the last state to the current one, if it's still playing.
+    transition: Option<Transition>,
+}
+
+/// A transition that's been started, but hasn't had the old screen captured yet. This happens in
+/// the next frame's render (see [Game::render_transition_capture]).
+struct PendingTransition {
+    style: TransitionStyle,
+    direction: TransitionDirection,
+    /// The state that was switched away from, if it's no longer on the stack. It's kept around
+    /// until it's been drawn one last time for the capture. If this is None, the old state is the
+    /// one just below the top of the stack.
+    outgoing: Option<Box<dyn GameState>>,
+}
+
+/// Shows roughly how much GPU memory each state is using (see [resources]).
+fn gpu_resources_ui(ctx: &egui::Context) {
+    let report = resources::report();
+
+    egui::Window::new("GPU resources")
+        .default_open(false)
+        .default_pos([10.0, 10.0])
+        .show(ctx, |ui| {
+            egui::Grid::new("gpu resources grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Owner");
+                    ui.strong("Buffers");
+                    ui.strong("Textures");
+                    ui.strong("Size");
+                    ui.end_row();
+
+                    for (owner, usage) in report.iter() {
+                        ui.label(*owner);
+                        ui.label(format!(
+                            "{} ({})",
+                            usage.buffers,
+                            resources::format_bytes(usage.buffer_bytes)
+                        ));
+                        ui.label(format!(
+                            "{} ({})",
+                            usage.textures,
+                            resources::format_bytes(usage.texture_bytes)
+                        ));
+                        ui.label(resources::format_bytes(usage.total_bytes()));
+                        ui.end_row();
+                    }
+                });
+
+            let total = report.iter().map(|(_, usage)| usage.total_bytes()).sum();
+            ui.label(format!("Total: {}", resources::format_bytes(total)));
+        });
+}
+
+fn create_version_text(renderer: &mut render::Renderer) -> TrackedText {
+    #[cfg(debug_assertions)]
+    let build = "debug";
+    #[cfg(not(debug_assertions))]
+    let build = "release";
+
+    let version_text = format!(
+        "luna's taiko sim - version {} ({})",
+        env!("CARGO_PKG_VERSION"),
+        build
+    );
 
-    version_text: Text,
+    TextBuilder::new(version_text, renderer.font("mplus regular"), [1910., 1070.])
+        .horizontal_align(HorizontalAlignment::Right)
+        .vertical_align(VerticalAlignment::Bottom)
+        .font_size(Some(FontSize::Px(18.)))
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 2.)
+        .build_text(renderer)
 }
 
 impl Game {
     pub fn new<F>(renderer: &mut render::Renderer, create_state: F) -> anyhow::Result<Self>
     where
-        F: FnOnce(&mut render::Renderer, &mut TextureCache) -> Box<dyn GameState>,
+        F: Fn(&mut render::Renderer, &mut TextureCache) -> Box<dyn GameState> + 'static,
     {
-        let audio_manager = AudioManager::<DefaultBackend>::new(Default::default())?;
+        let mut audio_manager = AudioManager::new(AudioManagerSettings {
+            backend_settings: AudioBackendSettings {
+                buffer_size: settings().game.audio_buffer_size,
+            },
+            ..Default::default()
+        })?;
+        let sounds = Sounds::new(&mut audio_manager, &settings().game)?;
         let mut textures = TextureCache::default();
         // Let's load some important textures first
-        for tex in [
-            "don.png",
-            "kat.png",
-            "big_don.png",
-            "big_kat.png",
-            "drumroll_start.png",
-            "big_drumroll_start.png",
-        ] {
-            textures
-                .get(&renderer.device, &renderer.queue, tex)
-                .unwrap();
-        }
+        textures
+            .preload(&renderer.device, &renderer.queue, PRELOADED_TEXTURES)
+            .unwrap();
 
+        let mark = resources::mark();
         let state = create_state(renderer, &mut textures);
-
-        #[cfg(debug_assertions)]
-        let build = "debug";
-        #[cfg(not(debug_assertions))]
-        let build = "release";
-
-        let version_text = format!(
-            "luna's taiko sim - version {} ({})",
-            env!("CARGO_PKG_VERSION"),
-            build
-        );
-
-        let version_text =
-            TextBuilder::new(version_text, renderer.font("mplus regular"), [1910., 1070.])
-                .horizontal_align(HorizontalAlignment::Right)
-                .vertical_align(VerticalAlignment::Bottom)
-                .font_size(Some(FontSize::Px(18.)))
-                .color([1.; 4])
-                .outlined([0., 0., 0., 1.], 2.)
-                .build(
-                    &renderer.device,
-                    &renderer.queue,
-                    &mut renderer.text_renderer,
-                );
+        resources::reassign_since(mark, state.name());
+        let version_text = create_version_text(renderer);
 
         Ok(Game {
             audio_manager,
+            sounds,
+            settings: SettingsSnapshot::new(),
             state: vec![state],
-            keyboard: KeyboardState(HashMap::new()),
-            mouse: MouseState {
-                position: None,
-                button_map: HashMap::new(),
-            },
+            keyboard: KeyboardState::default(),
+            mouse: MouseState::default(),
             textures,
 
             fps_timer: 0.0,
             frames_counted: 0,
             fps: 0.0,
             show_fps_counter: false,
+            tap_tempo: TapTempo::default(),
+            rich_presence: RichPresence::new(),
+            tasks: Tasks::default(),
+            timestep: FixedTimestep::new(FIXED_TIMESTEP, Instant::now()),
             version_text,
+            help: HelpOverlay::default(),
+            f1_held_for: None,
+            create_root_state: Box::new(create_state),
+            fatal_error: false,
+            pending_transition: None,
+            transition: None,
         })
     }
 
+    /// Rebuilds everything that was created with the GPU device, after the renderer has been
+    /// recreated because the old device was lost.
+    ///
+    /// Each state (from the bottom of the stack up) gets a chance to rebuild its own resources. If
+    /// one of them can't, it and every state above it are thrown away, and if that leaves no
+    /// states at all, the game starts again from the first state.
+    pub fn recreate_gpu_resources(&mut self, renderer: &mut render::Renderer) {
+        self.textures = TextureCache::default();
+        if let Err(e) = self
+            .textures
+            .preload(&renderer.device, &renderer.queue, PRELOADED_TEXTURES)
+        {
+            log::error!("couldn't reload textures: {e}");
+        }
+
+        self.version_text = create_version_text(renderer);
+
+        // The captured frame was made with the old device
+        self.pending_transition = None;
+        self.transition = None;
+
+        let mut ctx = Context {
+            audio: &mut self.audio_manager,
+            renderer,
+            keyboard: &mut self.keyboard,
+            mouse: &self.mouse,
+            textures: &mut self.textures,
+            help: &mut self.help,
+            tasks: &mut self.tasks,
+            sounds: &mut self.sounds,
+            event_time: Instant::now(),
+        };
+
+        if let Some(index) = self.state.iter_mut().position(|state| {
+            !resources::with_owner(state.name(), || state.recreate_gpu_resources(&mut ctx))
+        }) {
+            log::warn!(
+                "{} state(s) couldn't recover from the device being lost",
+                self.state.len() - index
+            );
+            self.state.truncate(index);
+        }
+
+        if self.state.is_empty() {
+            let mark = resources::mark();
+            let state = (self.create_root_state)(renderer, &mut self.textures);
+            resources::reassign_since(mark, state.name());
+            self.state.push(state);
+        }
+    }
+
+    /// Throws away every state and shows an error message instead. The only thing the player can
+    /// do from there is quit.
+    pub fn show_fatal_error(&mut self, message: impl Into<String>) {
+        self.state = vec![Box::new(ErrorScreen::new(message))];
+        self.fatal_error = true;
+        self.pending_transition = None;
+        self.transition = None;
+    }
+
+    /// Returns whether the game is currently showing an unrecoverable error.
+    pub fn is_showing_fatal_error(&self) -> bool {
+        self.fatal_error
+    }
+
     pub fn update(
         &mut self,
         delta: f32,
@@ -280,42 +673,282 @@ impl Game {
             self.frames_counted = 0;
         }
 
+        // The audio stream is reopened with the new buffer size if it's changed
+        let old_skin = self.settings.visual.skin.clone();
+        if self.settings.refresh() {
+            self.audio_manager
+                .backend_mut()
+                .set_buffer_size(self.settings.game.audio_buffer_size);
+            self.sounds
+                .set_volumes(&mut self.audio_manager, &self.settings.game);
+
+            if self.settings.visual.skin != old_skin {
+                if let Err(e) = load_skin(self.settings.visual.skin.as_deref()) {
+                    log::error!("couldn't load skin: {e}");
+                }
+
+                self.textures.clear();
+            }
+        }
+
+        if let Some(transition) = self.transition.as_mut() {
+            transition.update(delta, renderer);
+
+            if transition.is_finished() {
+                self.transition = None;
+            }
+        }
+
+        self.tasks.update();
+
+        if let Some(held_for) = self.f1_held_for.as_mut() {
+            *held_for += delta;
+
+            if *held_for >= HELP_HOLD_TIME {
+                self.f1_held_for = None;
+                self.help.request();
+            }
+        }
+
+        // The new state doesn't start until it can be seen
+        let transitioning = self.is_transitioning();
+        // Steps that come due during a transition are skipped along with the updates
+        let steps = self.timestep.due(Instant::now());
+
         let mut ctx = Context {
             audio: &mut self.audio_manager,
             renderer,
-            keyboard: &self.keyboard,
+            keyboard: &mut self.keyboard,
             mouse: &self.mouse,
             textures: &mut self.textures,
+            help: &mut self.help,
+            tasks: &mut self.tasks,
+            sounds: &mut self.sounds,
+            event_time: Instant::now(),
+        };
+
+        // The state underneath the help overlay keeps going, but the keys are for the overlay
+        if ctx.help.is_open() {
+            ctx.help.handle_input(ctx.keyboard);
+            ctx.keyboard.clear_edges();
+        }
+
+        let transition = if transitioning {
+            StateTransition::Continue
+        } else {
+            // New states are usually created by the state before them, so whatever was created
+            // during the update belongs to the new state
+            let current = self.state.last_mut().unwrap();
+            let mark = resources::mark();
+            let transition = resources::with_owner(current.name(), || {
+                for &time in &steps {
+                    current.fixed_update(&mut ctx, time);
+                }
+
+                current.update(&mut ctx, delta)
+            });
+
+            if let StateTransition::Push(state) | StateTransition::Swap(state) = &transition {
+                resources::reassign_since(mark, state.name());
+            }
+
+            transition
         };
 
-        match self.state.last_mut().unwrap().update(&mut ctx, delta) {
-            StateTransition::Push(state) => self.state.push(state),
+        match transition {
+            StateTransition::Push(state) => {
+                let style = state.transition_style();
+                self.state.push(state);
+                self.start_transition(style, TransitionDirection::Forwards, None);
+            }
             StateTransition::Pop => {
-                self.state
+                let state = self
+                    .state
                     .pop()
                     .expect("found no previous state to return to!");
+                let style = state.transition_style();
+                self.start_transition(style, TransitionDirection::Backwards, Some(state));
+            }
+            StateTransition::Swap(state) => {
+                let style = state.transition_style();
+                let old = std::mem::replace(self.state.last_mut().unwrap(), state);
+                self.start_transition(style, TransitionDirection::Forwards, Some(old));
             }
-            StateTransition::Swap(state) => *self.state.last_mut().unwrap() = state,
             StateTransition::Exit => event_loop.exit(),
             StateTransition::Continue => {}
         }
+
+        if self.help.take_request() {
+            self.open_help(renderer);
+        }
+
+        // This goes by the top of the stack, so it catches state changes as soon as they happen
+        if let Some(state) = self.state.last() {
+            self.rich_presence
+                .update(self.settings.game.discord_presence, || state.presence());
+        }
+
+        self.keyboard.end_frame();
+    }
+
+    /// Starts animating from the old state to the new one, which is already at the top of the
+    /// stack. `outgoing` is the old state if it's been taken off the stack.
+    ///
+    /// If another transition is still playing, the new one starts from whatever's on screen, so
+    /// the old animation is cut short rather than jumping.
+    fn start_transition(
+        &mut self,
+        style: TransitionStyle,
+        direction: TransitionDirection,
+        outgoing: Option<Box<dyn GameState>>,
+    ) {
+        if style == TransitionStyle::Cut {
+            self.pending_transition = None;
+            self.transition = None;
+            return;
+        }
+
+        self.pending_transition = Some(PendingTransition {
+            style,
+            direction,
+            outgoing,
+        });
+    }
+
+    /// Whether the screen is in the middle of changing from one state to another. The new state
+    /// isn't updated and doesn't get any input until it's done.
+    fn is_transitioning(&self) -> bool {
+        self.pending_transition.is_some() || self.transition.is_some()
+    }
+
+    /// Whether a transition is waiting for the screen to be captured (see
+    /// [Game::render_transition_capture]).
+    pub fn needs_transition_capture(&self) -> bool {
+        self.pending_transition.is_some()
+    }
+
+    /// Draws what was on screen before the state changed, for the transition to animate away.
+    /// This is drawn into a texture by the renderer, which then passes it back to
+    /// [Game::finish_transition_capture].
+    ///
+    /// If a transition was already playing, it's drawn too, so that the new transition starts
+    /// from exactly what was on screen.
+    pub fn render_transition_capture<'pass>(
+        &'pass mut self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        let Some(pending) = self.pending_transition.as_mut() else {
+            return;
+        };
+
+        let mut ctx = RenderContext {
+            renderer,
+            keyboard: &self.keyboard,
+            mouse: &self.mouse,
+            textures: &mut self.textures,
+            render_pass,
+        };
+
+        let state = match pending.outgoing.as_mut() {
+            Some(state) => state,
+            None => {
+                let below_top = self.state.len() - 2;
+                &mut self.state[below_top]
+            }
+        };
+
+        if self
+            .transition
+            .as_ref()
+            .is_none_or(Transition::shows_new_state)
+        {
+            resources::with_owner(state.name(), || state.render(&mut ctx));
+        }
+
+        if let Some(transition) = &self.transition {
+            ctx.render(transition);
+        }
+    }
+
+    /// Starts playing the pending transition, now that the old screen has been captured.
+    pub fn finish_transition_capture(&mut self, capture: Texture, renderer: &Renderer) {
+        if let Some(pending) = self.pending_transition.take() {
+            let width = capture.dimensions.0 as f32;
+            let sprite = SpriteBuilder::new(Rc::new(capture)).build(renderer);
+            self.transition = Some(Transition::new(
+                pending.style,
+                pending.direction,
+                sprite,
+                width,
+            ));
+        }
+    }
+
+    /// Gives up on the pending transition, if the old screen couldn't be captured.
+    pub fn cancel_transition(&mut self) {
+        self.pending_transition = None;
+    }
+
+    /// Opens the help overlay on top of the current state.
+    fn open_help(&mut self, renderer: &mut render::Renderer) {
+        let state = self.state.last_mut().unwrap();
+        self.help.open(state.difficulty());
+
+        let mut ctx = Context {
+            audio: &mut self.audio_manager,
+            renderer,
+            keyboard: &mut self.keyboard,
+            mouse: &self.mouse,
+            textures: &mut self.textures,
+            help: &mut self.help,
+            tasks: &mut self.tasks,
+            sounds: &mut self.sounds,
+            event_time: Instant::now(),
+        };
+
+        resources::with_owner(state.name(), || state.overlay_opened(&mut ctx));
     }
 
     pub fn debug_ui(&mut self, ctx: egui::Context) {
-        self.state
-            .last_mut()
-            .unwrap()
-            .debug_ui(ctx.clone(), &mut self.audio_manager);
+        if !self.is_transitioning() {
+            self.state
+                .last_mut()
+                .unwrap()
+                .debug_ui(ctx.clone(), &mut self.audio_manager);
+        }
+
+        self.help.ui(&ctx);
+
+        if self.show_fps_counter && !self.settings.visual.streamer_mode {
+            let audio_status = self.audio_manager.backend_mut().status();
 
-        if self.show_fps_counter {
             egui::Area::new("fps counter".into())
-                .fixed_pos(egui::pos2(1800.0, 0.0))
+                .anchor(egui::Align2::RIGHT_TOP, [-10.0, 0.0])
                 .show(&ctx, |ui| {
-                    ui.label(
-                        egui::RichText::new(format!("fps: {:.2}", self.fps))
-                            .color(egui::Color32::from_rgb(255, 0, 255))
-                            .size(20.0),
-                    );
+                    ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                        ui.label(
+                            egui::RichText::new(format!("fps: {:.2}", self.fps))
+                                .color(egui::Color32::from_rgb(255, 0, 255))
+                                .size(20.0),
+                        );
+                        ui.label(
+                            egui::RichText::new(format!("audio {}", audio_status.summary()))
+                                .color(egui::Color32::from_rgb(255, 0, 255))
+                                .size(14.0),
+                        );
+                    });
+                });
+
+            gpu_resources_ui(&ctx);
+            self.tasks.ui(&ctx);
+
+            egui::Window::new("Tap tempo")
+                .default_open(false)
+                .default_pos([10.0, 60.0])
+                .resizable(false)
+                .show(&ctx, |ui| {
+                    self.tap_tempo.ui(ui, None, false);
                 });
         }
     }
@@ -326,7 +959,6 @@ impl Game {
         render_pass: &mut wgpu::RenderPass<'pass>,
     ) {
         let mut ctx = RenderContext {
-            audio: &mut self.audio_manager,
             renderer,
             keyboard: &self.keyboard,
             mouse: &self.mouse,
@@ -334,23 +966,71 @@ impl Game {
             render_pass,
         };
 
-        self.state.last_mut().unwrap().render(&mut ctx);
-        ctx.render(&self.version_text);
+        let state = self.state.last_mut().unwrap();
+
+        match &self.transition {
+            Some(transition) => {
+                if transition.shows_new_state() {
+                    resources::with_owner(state.name(), || state.render(&mut ctx));
+                }
+
+                ctx.render(transition);
+            }
+            None => resources::with_owner(state.name(), || state.render(&mut ctx)),
+        }
+
+        if !self.settings.visual.streamer_mode {
+            ctx.render(&self.version_text);
+        }
     }
 
-    pub fn handle_event(&mut self, event: &WindowEvent, renderer: &mut render::Renderer) {
+    /// Handles an event from the window. `received` is when it arrived, which is what inputs are
+    /// timed by.
+    pub fn handle_event(
+        &mut self,
+        event: &WindowEvent,
+        received: Instant,
+        renderer: &mut render::Renderer,
+    ) {
+        let focus_lost = matches!(
+            event,
+            WindowEvent::Focused(false) | WindowEvent::Occluded(true)
+        );
+        if focus_lost {
+            self.keyboard.release_all();
+            self.mouse.release_all();
+            self.f1_held_for = None;
+        }
+
         // We make the current state handle input before the keyboard can update state,
         // so that the event is able to know what the state of the keyboard was before
         // the new input.
+        let transitioning = self.is_transitioning();
         let mut ctx = Context {
             audio: &mut self.audio_manager,
             renderer,
-            keyboard: &self.keyboard,
+            keyboard: &mut self.keyboard,
             mouse: &self.mouse,
             textures: &mut self.textures,
+            help: &mut self.help,
+            tasks: &mut self.tasks,
+            sounds: &mut self.sounds,
+            event_time: received,
         };
 
-        self.state.last_mut().unwrap().handle_event(&mut ctx, event);
+        // While the help overlay is open, it gets all the keyboard input. Nothing gets any input
+        // while the screen is changing to a new state.
+        let is_keyboard_input = matches!(event, WindowEvent::KeyboardInput { .. });
+        let help_has_keyboard = ctx.help.is_open() && is_keyboard_input;
+        if !(transitioning || help_has_keyboard) {
+            let state = self.state.last_mut().unwrap();
+            resources::with_owner(state.name(), || state.handle_event(&mut ctx, event));
+        }
+
+        if focus_lost {
+            let state = self.state.last_mut().unwrap();
+            resources::with_owner(state.name(), || state.focus_lost(&mut ctx));
+        }
 
         if let WindowEvent::KeyboardInput {
             event,
@@ -360,14 +1040,166 @@ impl Game {
         {
             self.keyboard.handle_input(event);
 
-            if self
-                .keyboard
-                .is_just_pressed(PhysicalKey::Code(KeyCode::F1))
+            // Tapping F1 toggles the FPS counter, holding it opens the help overlay (see
+            // Game::update), and pressing it while the help overlay is open closes it.
+            let f1 = PhysicalKey::Code(KeyCode::F1);
+            if self.keyboard.is_just_pressed(f1) {
+                if self.help.is_open() {
+                    self.help.close();
+                } else {
+                    self.f1_held_for = Some(0.0);
+                }
+            } else if self.keyboard.is_just_released(f1)
+                && self.f1_held_for.take().is_some()
+                && !self.settings.visual.streamer_mode
             {
                 self.show_fps_counter = !self.show_fps_counter;
             }
+
+            // Taps are timed as soon as the key is pressed, rather than on the next frame
+            if self.show_fps_counter
+                && !event.repeat
+                && self.keyboard.is_just_pressed(PhysicalKey::Code(TAP_KEY))
+            {
+                self.tap_tempo.tap(received);
+            }
+
+            if !event.repeat
+                && self
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(KeyCode::F2))
+            {
+                let mut settings = settings_mut();
+                settings.visual.streamer_mode = !settings.visual.streamer_mode;
+            }
+
+            if !event.repeat
+                && self
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(KeyCode::F11))
+            {
+                {
+                    let mut settings = settings_mut();
+                    settings.visual.resolution = settings.visual.resolution.toggle_fullscreen();
+                }
+
+                if let Err(e) = save_settings() {
+                    log::error!("couldn't save settings: {e}");
+                }
+            }
         }
 
-        self.mouse.handle_input(event);
+        self.mouse.handle_input(event, renderer.viewport());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_edges() {
+        let space = PhysicalKey::Code(KeyCode::Space);
+        let mut keyboard = KeyboardState(HashMap::new());
+
+        keyboard.set_pressed(space, true);
+        assert!(keyboard.is_just_pressed(space));
+
+        keyboard.end_frame();
+        assert!(keyboard.is_pressed(space));
+        assert!(!keyboard.is_just_pressed(space));
+
+        keyboard.set_pressed(space, false);
+        assert!(keyboard.is_just_released(space));
+    }
+
+    #[test]
+    fn test_consumed_keys_stay_held() {
+        let space = PhysicalKey::Code(KeyCode::Space);
+        let enter = PhysicalKey::Code(KeyCode::Enter);
+        let mut keyboard = KeyboardState(HashMap::new());
+
+        keyboard.set_pressed(space, true);
+        keyboard.set_pressed(enter, true);
+        keyboard.consume_key(space);
+
+        assert!(!keyboard.is_just_pressed(space));
+        assert!(keyboard.is_pressed(space));
+        assert!(keyboard.is_just_pressed(enter));
+
+        keyboard.clear_edges();
+        assert!(!keyboard.is_just_pressed(enter));
+        assert!(keyboard.is_pressed(enter));
+    }
+
+    #[test]
+    fn test_release_lost_while_unfocused() {
+        let don = PhysicalKey::Code(KeyCode::KeyF);
+        let mut keyboard = KeyboardState(HashMap::new());
+
+        keyboard.handle_key(don, true, false);
+        keyboard.end_frame();
+
+        // The window loses focus while the key is held, and the release never arrives
+        keyboard.release_all();
+        keyboard.end_frame();
+        assert!(!keyboard.is_pressed(don));
+
+        // Back in the window, the next press has to count
+        keyboard.handle_key(don, true, false);
+        assert!(keyboard.is_just_pressed(don));
+    }
+
+    #[test]
+    fn test_release_arrives_after_focus_loss() {
+        let don = PhysicalKey::Code(KeyCode::KeyF);
+        let mut keyboard = KeyboardState(HashMap::new());
+
+        keyboard.handle_key(don, true, false);
+        keyboard.end_frame();
+        keyboard.release_all();
+
+        // The release is delivered late, after the focus event
+        keyboard.handle_key(don, false, false);
+        assert!(!keyboard.is_just_released(don));
+        keyboard.end_frame();
+
+        keyboard.handle_key(don, true, false);
+        assert!(keyboard.is_just_pressed(don));
+    }
+
+    #[test]
+    fn test_key_held_through_focus_loss() {
+        let don = PhysicalKey::Code(KeyCode::KeyF);
+        let mut keyboard = KeyboardState(HashMap::new());
+
+        keyboard.handle_key(don, true, false);
+        keyboard.end_frame();
+        keyboard.release_all();
+
+        // The key is still held when the window comes back, so it keeps repeating. That isn't a
+        // new press.
+        keyboard.handle_key(don, true, true);
+        assert!(!keyboard.is_pressed(don));
+        assert!(!keyboard.is_just_pressed(don));
+
+        // Letting go and pressing it again is
+        keyboard.handle_key(don, false, false);
+        keyboard.end_frame();
+        keyboard.handle_key(don, true, false);
+        assert!(keyboard.is_just_pressed(don));
+    }
+
+    #[test]
+    fn test_mouse_released_on_focus_loss() {
+        let mut mouse = MouseState {
+            position: None,
+            button_map: HashMap::new(),
+        };
+
+        mouse.button_map.insert(MouseButton::Left, (true, true));
+        mouse.release_all();
+        assert!(!mouse.is_pressed(MouseButton::Left));
+        assert!(!mouse.is_just_released(MouseButton::Left));
     }
 }
@@ -0,0 +1,379 @@
+//! Showing what the player is doing on Discord (rich presence).
+//!
+//! Each frame the state on top of the stack is asked what it's doing (see
+//! [GameState::presence](super::GameState::presence)), and whenever that changes it's sent to a
+//! background thread that talks to Discord. Nothing here ever waits on Discord, so the game keeps
+//! going even if Discord isn't running or stops responding.
+//!
+//! Talking to Discord needs the `discord` feature. Without it, presence is never shown.
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::difficulty::DifficultyInfo;
+
+/// How often the progress through a song is updated while it's being played.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The most characters Discord shows in each line of a presence.
+const MAX_FIELD_LENGTH: usize = 128;
+
+/// What the player is doing, as shown on Discord.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Presence {
+    Menus,
+    Browsing,
+    Editing,
+    Playing {
+        title: String,
+        difficulty: usize,
+        level: u8,
+        /// How far through the song the player is, from 0 to 1.
+        progress: f32,
+    },
+    Results,
+}
+
+impl Presence {
+    /// What a state is doing, going by its name (see [GameState::name](super::GameState::name)).
+    /// States that have more to say, like gameplay, give their own presence instead.
+    pub fn for_state(name: &str) -> Self {
+        match name {
+            "SongSelect" => Presence::Browsing,
+            "Editor" => Presence::Editing,
+            "ScoreScreen" => Presence::Results,
+            _ => Presence::Menus,
+        }
+    }
+
+    /// Whether two presences are the same activity, so that the elapsed time shouldn't be reset
+    /// when going from one to the other. Progress through a song doesn't count as a new activity.
+    fn same_activity(&self, other: &Presence) -> bool {
+        match (self, other) {
+            (
+                Presence::Playing {
+                    title, difficulty, ..
+                },
+                Presence::Playing {
+                    title: other_title,
+                    difficulty: other_difficulty,
+                    ..
+                },
+            ) => title == other_title && difficulty == other_difficulty,
+            _ => self == other,
+        }
+    }
+
+    /// The first line of the presence.
+    pub fn details(&self) -> String {
+        match self {
+            Presence::Menus => "In the menus".to_string(),
+            Presence::Browsing => "Browsing songs".to_string(),
+            Presence::Editing => "Editing a chart".to_string(),
+            Presence::Results => "On the results screen".to_string(),
+            Presence::Playing {
+                title,
+                difficulty,
+                level,
+                ..
+            } => {
+                let suffix = format!(" ({} ★{level})", DifficultyInfo::name_of(*difficulty));
+                let title_length = MAX_FIELD_LENGTH - "Playing ".len() - suffix.chars().count();
+                format!("Playing {}{suffix}", truncate(title, title_length))
+            }
+        }
+    }
+
+    /// The second line of the presence, if there is one.
+    pub fn state(&self) -> Option<String> {
+        match self {
+            Presence::Playing { progress, .. } => Some(format!(
+                "{:.0}% through",
+                (progress * 100.0).clamp(0.0, 100.0)
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Shortens text to at most `max` characters, ending it with an ellipsis if anything was cut off.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        let mut truncated = text
+            .chars()
+            .take(max.saturating_sub(1))
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        truncated.push('…');
+        truncated
+    }
+}
+
+/// A presence to show, as it's sent to the background thread.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "discord"), allow(dead_code))]
+struct PresenceUpdate {
+    details: String,
+    state: Option<String>,
+    /// When the activity started, in seconds since the unix epoch, so Discord can show how long
+    /// it's been going.
+    started_at: i64,
+}
+
+/// Keeps Discord up to date with what the player is doing.
+pub struct RichPresence {
+    /// Sends presences to the background thread, or clears the presence if given None. This is
+    /// None if presence is turned off or couldn't be started.
+    worker: Option<Sender<Option<PresenceUpdate>>>,
+    /// Whether starting the background thread has already been tried, so that it isn't tried
+    /// again every frame if it can't be started.
+    tried_starting: bool,
+    current: Option<Presence>,
+    started_at: i64,
+    last_sent: Instant,
+}
+
+impl RichPresence {
+    pub fn new() -> Self {
+        Self {
+            worker: None,
+            tried_starting: false,
+            current: None,
+            started_at: 0,
+            last_sent: Instant::now(),
+        }
+    }
+
+    /// Called once a frame with whether presence is turned on in the settings, and what the
+    /// player is doing. The presence is only sent when the activity changes, or every
+    /// [UPDATE_INTERVAL] while playing a song so that the progress stays roughly right.
+    pub fn update(&mut self, enabled: bool, presence: impl FnOnce() -> Presence) {
+        if !enabled {
+            // Dropping the sender stops the thread, which clears the presence on its way out
+            self.worker = None;
+            self.tried_starting = false;
+            self.current = None;
+            return;
+        }
+
+        if !self.tried_starting {
+            self.tried_starting = true;
+            self.worker = worker::spawn();
+        }
+
+        let Some(worker) = &self.worker else {
+            return;
+        };
+
+        let presence = presence();
+        let changed = self
+            .current
+            .as_ref()
+            .is_none_or(|current| !current.same_activity(&presence));
+        let due = matches!(presence, Presence::Playing { .. })
+            && self.last_sent.elapsed() >= UPDATE_INTERVAL;
+
+        if changed {
+            self.started_at = unix_time();
+        }
+
+        if changed || due {
+            let update = PresenceUpdate {
+                details: presence.details(),
+                state: presence.state(),
+                started_at: self.started_at,
+            };
+
+            if worker.send(Some(update)).is_err() {
+                log::warn!("the discord presence thread stopped");
+                self.worker = None;
+            }
+
+            self.last_sent = Instant::now();
+        }
+
+        self.current = Some(presence);
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+#[cfg(feature = "discord")]
+mod worker {
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+    use std::time::{Duration, Instant};
+
+    use discord_rich_presence::activity::{Activity, Timestamps};
+    use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+
+    use super::PresenceUpdate;
+
+    /// The id of the Discord application the presence is shown as. This has to be given when the
+    /// game is built, as `TAIKO_DISCORD_CLIENT_ID`.
+    const CLIENT_ID: Option<&str> = option_env!("TAIKO_DISCORD_CLIENT_ID");
+
+    /// How long to wait between attempts to connect to Discord, if it isn't running or was
+    /// restarted.
+    const RECONNECT_INTERVAL: Duration = Duration::from_secs(15);
+
+    /// Starts the thread that talks to Discord, returning the channel to send it presences on.
+    pub(super) fn spawn() -> Option<Sender<Option<PresenceUpdate>>> {
+        let Some(client_id) = CLIENT_ID else {
+            log::warn!("discord presence is turned on, but the game was built without a client id");
+            return None;
+        };
+
+        let (sender, receiver) = mpsc::channel();
+
+        match std::thread::Builder::new()
+            .name("discord presence".to_string())
+            .spawn(move || run(client_id, receiver))
+        {
+            Ok(_) => Some(sender),
+            Err(e) => {
+                log::error!("couldn't start the discord presence thread: {e}");
+                None
+            }
+        }
+    }
+
+    fn activity(update: &PresenceUpdate) -> Activity<'_> {
+        let activity = Activity::new()
+            .details(update.details.as_str())
+            .timestamps(Timestamps::new().start(update.started_at));
+
+        match &update.state {
+            Some(state) => activity.state(state.as_str()),
+            None => activity,
+        }
+    }
+
+    /// Sends the newest presence to Discord whenever one comes in, until the game stops sending
+    /// them. If Discord isn't running, or goes away partway through, it keeps trying to connect
+    /// again and sends the newest presence once it can.
+    fn run(client_id: &str, updates: Receiver<Option<PresenceUpdate>>) {
+        let mut client = DiscordIpcClient::new(client_id);
+        let mut connected = false;
+        let mut last_attempt: Option<Instant> = None;
+        let mut latest = None;
+        let mut unsent = false;
+
+        loop {
+            match updates.recv_timeout(RECONNECT_INTERVAL) {
+                Ok(update) => {
+                    latest = update;
+                    unsent = true;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            // Only the newest presence matters
+            while let Ok(update) = updates.try_recv() {
+                latest = update;
+                unsent = true;
+            }
+
+            if !connected && last_attempt.is_none_or(|at| at.elapsed() >= RECONNECT_INTERVAL) {
+                last_attempt = Some(Instant::now());
+
+                if client.connect().is_ok() {
+                    log::info!("connected to discord");
+                    connected = true;
+                    unsent = true;
+                }
+            }
+
+            if connected && unsent {
+                let result = match &latest {
+                    Some(update) => client.set_activity(activity(update)),
+                    None => client.clear_activity(),
+                };
+
+                match result {
+                    Ok(()) => unsent = false,
+                    Err(e) => {
+                        log::warn!("lost the connection to discord: {e}");
+                        let _ = client.close();
+                        connected = false;
+                    }
+                }
+            }
+        }
+
+        if connected {
+            let _ = client.clear_activity();
+            let _ = client.close();
+        }
+    }
+}
+
+#[cfg(not(feature = "discord"))]
+mod worker {
+    use std::sync::mpsc::Sender;
+
+    use super::PresenceUpdate;
+
+    pub(super) fn spawn() -> Option<Sender<Option<PresenceUpdate>>> {
+        log::warn!("discord presence is turned on, but the game was built without discord support");
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(title: &str, progress: f32) -> Presence {
+        Presence::Playing {
+            title: title.to_string(),
+            difficulty: 3,
+            level: 9,
+            progress,
+        }
+    }
+
+    #[test]
+    fn test_presence_text() {
+        let presence = playing("POP TEAM EPIC", 0.874);
+        assert_eq!(presence.details(), "Playing POP TEAM EPIC (Oni ★9)");
+        assert_eq!(presence.state().as_deref(), Some("87% through"));
+
+        assert_eq!(
+            Presence::for_state("SongSelect").details(),
+            "Browsing songs"
+        );
+        assert_eq!(
+            Presence::for_state("ScoreScreen").details(),
+            "On the results screen"
+        );
+        assert_eq!(Presence::for_state("MainMenu"), Presence::Menus);
+        assert_eq!(Presence::Menus.state(), None);
+    }
+
+    #[test]
+    fn test_long_titles_are_truncated() {
+        let title = "あ".repeat(300);
+        let details = playing(&title, 0.0).details();
+
+        assert_eq!(details.chars().count(), MAX_FIELD_LENGTH);
+        assert!(details.ends_with("… (Oni ★9)"), "{details}");
+
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("a bit too long", 6), "a bit…");
+    }
+
+    #[test]
+    fn test_progress_isnt_a_new_activity() {
+        assert!(playing("a", 0.1).same_activity(&playing("a", 0.9)));
+        assert!(!playing("a", 0.1).same_activity(&playing("b", 0.1)));
+        assert!(!playing("a", 0.1).same_activity(&Presence::Results));
+        assert!(Presence::Browsing.same_activity(&Presence::Browsing));
+    }
+}
@@ -0,0 +1,110 @@
+//! Recordings of plays: every drum input the player made, saved alongside the play's score so that
+//! the play can be watched again later.
+//!
+//! A replay is watched by feeding its inputs back into the gameplay scene at the times they were
+//! made (see [TaikoMode::with_replay](super::taiko_mode::TaikoMode::with_replay)). They're judged
+//! with the same rules as the play was, in the same way as
+//! [simulate](super::taiko_mode::simulate), so the replay ends with the same result.
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::taiko_mode::{Mods, TimedInput};
+use crate::settings::{RulesConfig, RulesPreset};
+
+/// The folder replays are saved in.
+pub const REPLAYS_DIR: &str = "replays";
+
+/// Everything needed to watch a play again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    /// The hash of the chart that was played, as given by
+    /// [chart_hash](crate::notechart_parser::chart_hash). A replay can only be watched on the same
+    /// chart, as the inputs wouldn't line up with any other notes.
+    pub chart: String,
+    pub difficulty: usize,
+    /// How fast the song was played.
+    pub rate: f32,
+    /// The rules the play was scored by, and the preset they came from.
+    pub rules: RulesConfig,
+    pub rules_preset: RulesPreset,
+    /// The mods the play was played with, including the seed its notes were shuffled with.
+    /// Replays saved before there were mods don't have this, and were played without any.
+    #[serde(default)]
+    pub mods: Mods,
+    /// When the play finished, in seconds since the unix epoch.
+    pub played_at: i64,
+    /// Every input that was judged, in the order they were made.
+    pub inputs: Vec<TimedInput>,
+}
+
+impl Replay {
+    /// The name of the file the replay is saved as.
+    pub fn file_name(&self) -> String {
+        // Colons aren't allowed in file names on windows
+        format!(
+            "{}_{}_{}.toml",
+            self.played_at,
+            self.chart.replace(':', "-"),
+            self.difficulty
+        )
+    }
+
+    /// Saves the replay in the given folder, returning the name of the file it was saved as.
+    pub fn save(&self, dir: impl AsRef<Path>) -> anyhow::Result<String> {
+        let file_name = self.file_name();
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.as_ref().join(&file_name), toml::to_string(self)?)?;
+        Ok(file_name)
+    }
+
+    /// Reads the replay saved in the given folder with the given file name.
+    pub fn load(dir: impl AsRef<Path>, file_name: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(
+            dir.as_ref().join(file_name),
+        )?)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::DrumInput;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("taiko-replays-{}", std::process::id()));
+        let replay = Replay {
+            chart: "v1:0a1b".to_string(),
+            difficulty: 3,
+            rate: 1.0,
+            rules: RulesConfig::CLASSIC,
+            rules_preset: RulesPreset::Classic,
+            mods: Mods {
+                inverse: true,
+                shuffle: Some(u32::MAX),
+                ..Default::default()
+            },
+            played_at: 1700000000,
+            inputs: vec![
+                TimedInput {
+                    time: 0.25,
+                    input: DrumInput::LeftDon,
+                },
+                TimedInput {
+                    time: 1.0 / 3.0,
+                    input: DrumInput::RightKat,
+                },
+            ],
+        };
+
+        let file_name = replay.save(&dir).unwrap();
+        assert_eq!(file_name, "1700000000_v1-0a1b_3.toml");
+
+        let loaded = Replay::load(&dir, &file_name);
+        fs::remove_dir_all(&dir).unwrap();
+        // The times are read back exactly, so the inputs are judged exactly the same way
+        assert_eq!(loaded.unwrap(), replay);
+    }
+}
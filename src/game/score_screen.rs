@@ -1,7 +1,75 @@
-use kira::manager::AudioManager;
+use std::f32::consts::PI;
+use std::time::Instant;
 
-use crate::game::taiko_mode::PlayResult;
-use crate::game::{Context, GameState, StateTransition};
+use chrono::{DateTime, Local};
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::audio::AudioManager;
+use crate::difficulty::DifficultyInfo;
+use crate::game::difficulty_select::crown_colour;
+use crate::game::scores::{BestRecord, Crown};
+use crate::game::taiko_mode::{
+    timing_windows, HandStats, HitErrorStats, PlayResult, ReplaySetup, ScoreInt, Verification,
+};
+use crate::game::{
+    Context, GameState, RenderContext, SoundEffect, StateTransition, TransitionStyle,
+};
+use crate::render::particles::{Particles, SpawnConfig};
+use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
+use crate::render::{rgb, Renderer, VIRTUAL_HEIGHT, VIRTUAL_WIDTH};
+use crate::rng::Rng;
+use crate::settings::{settings, RulesPreset};
+
+/// How long a clipboard status message stays on screen, in seconds.
+const CLIPBOARD_MESSAGE_TIME: f32 = 3.0;
+
+/// The size of the roll speed sparkline, in points.
+const SPARKLINE_SIZE: [f32; 2] = [240.0, 40.0];
+
+/// The size of the graphs of hit offsets, in points.
+const HIT_ERROR_GRAPH_SIZE: [f32; 2] = [360.0, 100.0];
+
+/// How many bars the histogram of hit offsets has. It's odd so that there's a bar in the middle.
+const HIT_ERROR_HISTOGRAM_BARS: usize = 25;
+
+const GOOD_COLOUR: egui::Color32 = egui::Color32::from_rgb(255, 202, 14);
+const OK_COLOUR: egui::Color32 = egui::Color32::WHITE;
+const BAD_COLOUR: egui::Color32 = egui::Color32::from_rgb(46, 103, 209);
+
+/// The red the screen is washed with when the stage was failed.
+const STAGE_FAILED_TINT: [f32; 4] = [0.45, 0.0, 0.05, 0.5];
+
+/// The most pieces of confetti that can be on screen at once.
+const CONFETTI_BUDGET: usize = 400;
+
+const CONFETTI_COLOURS: &[[f32; 4]] = &[
+    rgb!(0xF0, 0x34, 0x2C),
+    rgb!(0xFF, 0xC8, 0x1E),
+    rgb!(0x5C, 0xB8, 0x2E),
+    rgb!(0x3D, 0x8F, 0xC4),
+    rgb!(0xE0, 0x35, 0x7E),
+    rgb!(0xFF, 0xFF, 0xFF),
+];
+
+/// A burst of confetti fired from the bottom left corner of the screen. The bottom right corner
+/// fires the same burst, mirrored.
+const CONFETTI_CANNON: SpawnConfig = SpawnConfig {
+    count: 180,
+    origin: [0., VIRTUAL_HEIGHT],
+    spread: [20., 20.],
+    direction: -PI / 3.,
+    angle_spread: PI / 10.,
+    speed: 900.0..1700.0,
+    lifetime: 2.5..4.0,
+    width: 10.0..18.0,
+    height: 6.0..10.0,
+    spin: 6.,
+    flip_speed: 4.0..12.0,
+    gravity: 900.,
+    drag: 1.2,
+    colours: CONFETTI_COLOURS,
+};
 
 struct Score {
     // Some precomputed values to display
@@ -10,56 +78,786 @@ struct Score {
     bads: usize,
     max_combo: usize,
     drumrolls: u64,
+    /// Average and best drumroll speeds, in hits per second
+    mean_roll_rate: Option<f32>,
+    best_roll_rate: Option<f32>,
+    /// The speed of each roll over the song, for the sparkline
+    roll_rates: Vec<f32>,
+    hand_stats: Option<HandStats>,
+    /// When each don and kat note that was hit should have been hit, and how far off the hit was,
+    /// both in seconds
+    hits: Vec<(f32, f32)>,
+    hit_error_stats: Option<HitErrorStats>,
+    /// The good, ok and bad timing windows of the chart, in seconds
+    timing_windows: [f32; 3],
 }
 
 impl Score {
-    fn from_result(result: &PlayResult) -> Self {
+    fn from_result(result: &PlayResult, difficulty: usize) -> Self {
         Self {
             goods: result.goods(),
             okays: result.okays(),
             bads: result.bads() + result.misses(),
             drumrolls: result.drumrolls(),
             max_combo: result.max_combo(),
+            mean_roll_rate: result.mean_roll_rate(),
+            best_roll_rate: result.best_roll_rate(),
+            roll_rates: result
+                .rolls()
+                .iter()
+                .filter_map(|roll| roll.hits_per_second())
+                .collect(),
+            hand_stats: result.hand_stats(),
+            hits: result
+                .hits()
+                .iter()
+                .map(|hit| (hit.time as f32, hit.offset))
+                .collect(),
+            hit_error_stats: result.hit_error_stats(),
+            timing_windows: timing_windows(difficulty).map(|window| window as f32),
+        }
+    }
+
+    /// The colour of a hit with the given offset, by the judgement it would get.
+    fn offset_colour(&self, offset: f32) -> egui::Color32 {
+        let [good, ok, _] = self.timing_windows;
+
+        if offset.abs() <= good {
+            GOOD_COLOUR
+        } else if offset.abs() <= ok {
+            OK_COLOUR
+        } else {
+            BAD_COLOUR
+        }
+    }
+}
+
+/// Draws a dot for every hit, with how far off it was going up and when in the song it was going
+/// along, so that the player can see whether their timing drifted. The good and ok windows are
+/// shaded in behind.
+fn hit_error_scatter(ui: &mut egui::Ui, score: &Score) -> egui::Response {
+    let (response, painter) =
+        ui.allocate_painter(HIT_ERROR_GRAPH_SIZE.into(), egui::Sense::hover());
+    let rect = response.rect;
+    let [good, ok, bad] = score.timing_windows;
+
+    // Early hits go above the line, and late hits below it
+    let y_of = |offset: f32| rect.center().y + rect.height() / 2.0 * (offset / bad).clamp(-1., 1.);
+    let band = |window: f32, colour| {
+        painter.rect_filled(
+            egui::Rect::from_x_y_ranges(rect.x_range(), y_of(-window)..=y_of(window)),
+            0.0,
+            colour,
+        );
+    };
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    band(ok, egui::Color32::from_white_alpha(16));
+    band(
+        good,
+        egui::Color32::from_rgba_unmultiplied(255, 202, 14, 24),
+    );
+    painter.hline(
+        rect.x_range(),
+        rect.center().y,
+        egui::Stroke::new(1.0, egui::Color32::GRAY),
+    );
+
+    let (start, end) = score.hits.iter().fold(
+        (f32::INFINITY, f32::NEG_INFINITY),
+        |(start, end), (time, _)| (start.min(*time), end.max(*time)),
+    );
+    let length = (end - start).max(f32::EPSILON);
+
+    for &(time, offset) in score.hits.iter() {
+        let x = rect.left() + rect.width() * (time - start) / length;
+        painter.circle_filled(
+            egui::pos2(x, y_of(offset)),
+            1.5,
+            score.offset_colour(offset),
+        );
+    }
+
+    response
+}
+
+/// Draws how many hits there were with each offset, from early on the left to late on the right.
+fn hit_error_histogram(ui: &mut egui::Ui, score: &Score) -> egui::Response {
+    let (response, painter) =
+        ui.allocate_painter(HIT_ERROR_GRAPH_SIZE.into(), egui::Sense::hover());
+    let rect = response.rect;
+    let bad = score.timing_windows[2];
+    let bar_width = bad * 2.0 / HIT_ERROR_HISTOGRAM_BARS as f32;
+
+    let mut counts = [0usize; HIT_ERROR_HISTOGRAM_BARS];
+    for &(_, offset) in score.hits.iter() {
+        let bar = ((offset + bad) / bar_width).floor().max(0.0) as usize;
+        counts[bar.min(HIT_ERROR_HISTOGRAM_BARS - 1)] += 1;
+    }
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    painter.vline(
+        rect.center().x,
+        rect.y_range(),
+        egui::Stroke::new(1.0, egui::Color32::GRAY),
+    );
+
+    let max = counts.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return response;
+    }
+
+    let width = rect.width() / HIT_ERROR_HISTOGRAM_BARS as f32;
+    for (i, &count) in counts.iter().enumerate() {
+        let left = rect.left() + width * i as f32;
+        let height = rect.height() * count as f32 / max as f32;
+        let centre = -bad + bar_width * (i as f32 + 0.5);
+
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left + 1.0, rect.bottom() - height),
+                egui::pos2(left + width - 1.0, rect.bottom()),
+            ),
+            0.0,
+            score.offset_colour(centre),
+        );
+    }
+
+    response
+}
+
+/// Describes an offset in milliseconds for the player, e.g. "3.2ms late".
+/// The shape that washes the whole screen red when the stage was failed.
+fn create_failed_tint(renderer: &Renderer) -> anyhow::Result<Shape> {
+    Ok(ShapeBuilder::new()
+        .filled_rectangle(
+            [0., 0.],
+            [VIRTUAL_WIDTH, VIRTUAL_HEIGHT],
+            SolidColour::new(STAGE_FAILED_TINT),
+        )?
+        .build(&renderer.device))
+}
+
+fn describe_offset(ms: f32) -> String {
+    if ms.abs() < 0.05 {
+        "on time".to_string()
+    } else if ms < 0.0 {
+        format!("{:.1}ms early", -ms)
+    } else {
+        format!("{ms:.1}ms late")
+    }
+}
+
+/// Draws a small line graph of the given values, scaled to fit.
+fn sparkline(ui: &mut egui::Ui, values: &[f32]) -> egui::Response {
+    let (response, painter) = ui.allocate_painter(SPARKLINE_SIZE.into(), egui::Sense::hover());
+    let rect = response.rect;
+
+    let max = values.iter().copied().fold(0.0, f32::max);
+    if values.len() < 2 || max <= 0.0 {
+        return response;
+    }
+
+    let points = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            egui::pos2(
+                rect.left() + rect.width() * i as f32 / (values.len() - 1) as f32,
+                rect.bottom() - rect.height() * value / max,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 195, 44)),
+    ));
+
+    response
+}
+
+/// A plain-text friendly summary of a single play.
+///
+/// This is what gets copied to the clipboard from the score screen, so that it can be pasted into
+/// a chat message or similar. Anything else that wants to write out a play as text (e.g. a session
+/// log) should go through [ResultSummary::to_plain_text] so that the output is always identical.
+#[derive(Clone, Debug)]
+pub struct ResultSummary {
+    pub title: String,
+    pub difficulty: String,
+    pub score: ScoreInt,
+    pub accuracy: f32,
+    pub goods: usize,
+    pub okays: usize,
+    pub bads: usize,
+    pub misses: usize,
+    pub drumrolls: u64,
+    pub max_combo: usize,
+    pub played_at: DateTime<Local>,
+    pub modifiers: Vec<String>,
+    /// The rules the play was scored by.
+    pub rules: RulesPreset,
+    /// How many times the audio skipped during the play and the notes had to be resynced to it.
+    pub audio_skips: usize,
+}
+
+impl ResultSummary {
+    pub fn new(
+        title: String,
+        difficulty: usize,
+        result: &PlayResult,
+        played_at: DateTime<Local>,
+    ) -> Self {
+        Self {
+            title,
+            difficulty: DifficultyInfo::name_of(difficulty).to_string(),
+            score: result.score(),
+            accuracy: result.accuracy(),
+            goods: result.goods(),
+            okays: result.okays(),
+            bads: result.bads(),
+            misses: result.misses(),
+            drumrolls: result.drumrolls(),
+            max_combo: result.max_combo(),
+            played_at,
+            modifiers: Vec::new(),
+            rules: RulesPreset::default(),
+            audio_skips: 0,
+        }
+    }
+
+    /// Formats the summary as a block of plain text.
+    pub fn to_plain_text(&self) -> String {
+        let modifiers = if self.modifiers.is_empty() {
+            "none".to_string()
+        } else {
+            self.modifiers.join(", ")
+        };
+
+        let mut text = format!(
+            "{} [{}]\n\
+            Score: {}\n\
+            Accuracy: {:.2}%\n\
+            Good: {} / Ok: {} / Bad: {} / Miss: {}\n\
+            Drumrolls: {}\n\
+            Max Combo: {}\n\
+            Modifiers: {}\n\
+            Rules: {}\n\
+            Played: {}",
+            self.title,
+            self.difficulty,
+            self.score,
+            self.accuracy,
+            self.goods,
+            self.okays,
+            self.bads,
+            self.misses,
+            self.drumrolls,
+            self.max_combo,
+            modifiers,
+            self.rules.name(),
+            self.played_at.format("%Y-%m-%d %H:%M"),
+        );
+
+        if self.audio_skips > 0 {
+            text.push_str(&format!("\nAudio skipped: {}", self.audio_skips));
         }
+
+        text
     }
 }
 
 pub struct ScoreScreen {
     score: Score,
-    song_name: String,
+    summary: ResultSummary,
+    /// The clipboard is kept alive for as long as the score screen is, as on some platforms
+    /// (notably X11 and Wayland) the copied text disappears once the clipboard handle is dropped.
+    clipboard: Option<arboard::Clipboard>,
+    clipboard_message: Option<(&'static str, Instant)>,
+    copy_requested: bool,
     exit: bool,
+    /// The crown the play earned.
+    crown: Crown,
+    /// Whether the song was cut short because the gauge ran out in sudden death (see
+    /// [FailBehaviour](crate::settings::FailBehaviour)).
+    stage_failed: bool,
+    /// Washes the screen red when the stage was failed.
+    failed_tint: Option<Shape>,
+    /// How fast a roll was hit with a single key, if it was fast enough that a key might have been
+    /// stuck (see [GameSettings::mash_warning_rate](crate::settings::GameSettings)).
+    mash_warning: Option<f32>,
+    /// Whether the result matched its inputs when they were played again, if it was checked.
+    verification: Option<Verification>,
+    /// Confetti to celebrate a full combo.
+    confetti: Particles,
+    /// What's needed to watch the play again, if its inputs were kept.
+    replay: Option<ReplaySetup>,
+    watch_replay: bool,
+    /// Why the replay couldn't be started, if it couldn't.
+    replay_error: Option<String>,
+    /// The best the player had done on the chart before this play, if they'd played it before.
+    high_score: Option<BestRecord>,
+    /// Whether this play was recorded with a better score than any before it.
+    new_high_score: bool,
 }
 
 impl ScoreScreen {
-    pub fn new(_ctx: &mut Context, song_name: String, result: PlayResult) -> Self {
+    /// Creates the score screen for a play, given whether its gauge reached the clear line. `rng`
+    /// is used for the celebration if the play was a full combo.
+    pub fn new(
+        renderer: &Renderer,
+        song_name: String,
+        difficulty: usize,
+        result: PlayResult,
+        cleared: bool,
+        rng: Rng,
+    ) -> Self {
+        let crown = Crown::of(&result, cleared);
+        let mash_warning_rate = settings().game.mash_warning_rate;
+        let mut confetti = Particles::new(renderer, CONFETTI_BUDGET, rng);
+
+        if crown >= Crown::FullCombo {
+            confetti.spawn(&CONFETTI_CANNON);
+            confetti.spawn(&SpawnConfig {
+                origin: [VIRTUAL_WIDTH, VIRTUAL_HEIGHT],
+                direction: -PI - CONFETTI_CANNON.direction,
+                ..CONFETTI_CANNON
+            });
+        }
+
         Self {
-            score: Score::from_result(&result),
-            song_name,
+            score: Score::from_result(&result, difficulty),
+            summary: ResultSummary::new(song_name, difficulty, &result, Local::now()),
+            clipboard: None,
+            clipboard_message: None,
+            copy_requested: false,
             exit: false,
+            crown,
+            stage_failed: false,
+            failed_tint: None,
+            mash_warning: result
+                .best_single_input_roll_rate()
+                .filter(|&rate| rate > mash_warning_rate),
+            verification: None,
+            confetti,
+            replay: None,
+            watch_replay: false,
+            replay_error: None,
+            high_score: None,
+            new_high_score: false,
         }
     }
-}
 
-impl GameState for ScoreScreen {
-    fn update(&mut self, _ctx: &mut Context, _delta_time: f32) -> StateTransition {
-        if self.exit {
-            StateTransition::Pop
-        } else {
-            StateTransition::Continue
+    /// Sets the modifiers the song was played with, e.g. a practice rate.
+    pub fn with_modifiers(mut self, modifiers: Vec<String>) -> Self {
+        self.summary.modifiers = modifiers;
+        self
+    }
+
+    /// Sets the rules the play was scored by.
+    pub fn with_rules(mut self, rules: RulesPreset) -> Self {
+        self.summary.rules = rules;
+        self
+    }
+
+    /// Sets how many times the audio skipped during the play, so that the player knows the play had
+    /// audio problems.
+    pub fn with_audio_skips(mut self, skips: usize) -> Self {
+        self.summary.audio_skips = skips;
+        self
+    }
+
+    /// Shows the player's high score on the chart from before this play. If the play was recorded
+    /// and beat it, it's celebrated as a new high score.
+    pub fn with_high_score(mut self, high_score: Option<BestRecord>, recorded: bool) -> Self {
+        self.new_high_score = recorded
+            && high_score
+                .as_ref()
+                .is_none_or(|best| self.summary.score > best.score);
+        self.high_score = high_score;
+        self
+    }
+
+    /// Lets the player watch the play again.
+    pub fn with_replay(mut self, replay: Option<ReplaySetup>) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    /// Sets whether the result was verified by playing its inputs again (see [Verification]).
+    pub fn with_verification(mut self, verification: Option<Verification>) -> Self {
+        if let Some(Verification::Mismatch(differences)) = &verification {
+            log::warn!(
+                "{} played back differently: {}",
+                self.summary.title,
+                differences.join(", ")
+            );
+        }
+
+        self.verification = verification;
+        self
+    }
+
+    /// Shows that the stage was failed, if it was, rather than how the play went at the end.
+    pub fn with_stage_failed(mut self, stage_failed: bool, renderer: &Renderer) -> Self {
+        self.stage_failed = stage_failed;
+        if stage_failed {
+            self.failed_tint = create_failed_tint(renderer)
+                .inspect_err(|e| log::error!("couldn't create the stage failed tint: {e}"))
+                .ok();
         }
+
+        self
     }
 
-    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
-        egui::Window::new("Let's see your results!").show(&ctx, |ui| {
-            ui.label(egui::RichText::new(&self.song_name).size(20.0).strong());
+    fn copy_to_clipboard(&mut self) {
+        let text = self.summary.to_plain_text();
+
+        let clipboard = match self.clipboard.as_mut() {
+            Some(clipboard) => Ok(clipboard),
+            None => arboard::Clipboard::new().map(|clipboard| self.clipboard.insert(clipboard)),
+        };
+
+        let message = match clipboard.and_then(|clipboard| clipboard.set_text(text)) {
+            Ok(()) => "Copied result to clipboard!",
+            Err(e) => {
+                log::warn!("couldn't copy result to clipboard: {e}");
+                "Couldn't access clipboard"
+            }
+        };
+
+        self.clipboard_message = Some((message, Instant::now()));
+    }
+
+    /// The window showing how the play went.
+    fn results_ui(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Let's see your results!").show(ctx, |ui| {
+            ui.label(egui::RichText::new(&self.summary.title).size(20.0).strong());
+
+            if self.stage_failed {
+                ui.label(
+                    egui::RichText::new("Stage failed")
+                        .size(26.0)
+                        .strong()
+                        .color(egui::Color32::from_rgb(230, 50, 50)),
+                );
+            } else {
+                let crown = match self.crown {
+                    Crown::Failed => self.crown.name().to_string(),
+                    crown => format!("{}!", crown.name()),
+                };
+                ui.label(
+                    egui::RichText::new(crown)
+                        .size(20.0)
+                        .color(crown_colour(self.crown)),
+                );
+            }
+
+            ui.add_space(10.0);
+            ui.label(egui::RichText::new(format!("Score: {}", self.summary.score)).size(18.0));
+
+            let previous = self.high_score.as_ref().map(|best| best.score);
+            if self.new_high_score {
+                let text = match previous {
+                    Some(previous) => format!("New high score! (was {previous})"),
+                    None => "New high score!".to_string(),
+                };
+                ui.label(egui::RichText::new(text).color(egui::Color32::from_rgb(255, 200, 30)));
+            } else if let Some(previous) = previous {
+                ui.label(egui::RichText::new(format!("High score: {previous}")).weak());
+            }
+
             ui.add_space(10.0);
             ui.label(format!("Good: {}", self.score.goods));
             ui.label(format!("Ok: {}", self.score.okays));
             ui.label(format!("Bad: {}", self.score.bads));
             ui.label(format!("Drumrolls: {}", self.score.drumrolls));
+
+            if let Some(mean) = self.score.mean_roll_rate {
+                ui.label(format!("Roll speed: {mean:.1} hits/s"));
+            }
+
+            if let Some(best) = self.score.best_roll_rate {
+                ui.label(format!("Best roll speed: {best:.1} hits/s"));
+            }
+
+            if self.score.roll_rates.len() >= 2 {
+                sparkline(ui, &self.score.roll_rates).on_hover_text("Roll speed over the song");
+            }
+
             ui.label(format!("Max Combo: {}", self.score.max_combo));
+            ui.label(format!("Rules: {}", self.summary.rules.name()));
+
+            if let Some(rate) = self.mash_warning {
+                ui.label(
+                    egui::RichText::new(format!(
+                        "A drumroll was hit {rate:.0} times a second with one key. \
+                        Is a key stuck?"
+                    ))
+                    .color(egui::Color32::YELLOW),
+                );
+            }
+
+            match &self.verification {
+                Some(Verification::Verified) => {
+                    ui.label(egui::RichText::new("Verified").color(egui::Color32::LIGHT_GREEN))
+                        .on_hover_text("Playing the inputs again gave the same result");
+                }
+                Some(Verification::Mismatch(differences)) => {
+                    ui.label(egui::RichText::new("Unverified").color(egui::Color32::YELLOW))
+                        .on_hover_text(format!(
+                            "Playing the inputs again gave a different {}",
+                            differences.join(", ")
+                        ));
+                }
+                None => {}
+            }
+
+            if self.summary.audio_skips > 0 {
+                let times = match self.summary.audio_skips {
+                    1 => "once".to_string(),
+                    skips => format!("{skips} times"),
+                };
+
+                ui.label(
+                    egui::RichText::new(format!(
+                        "* The audio skipped {times} during this play. The notes were put back \
+                        in time with it, but some hits near a skip may have been affected."
+                    ))
+                    .small()
+                    .weak(),
+                );
+            }
+
+            if let Some(stats) = self.score.hit_error_stats {
+                ui.collapsing("Timing", |ui| {
+                    ui.label(format!("Mean: {}", describe_offset(stats.mean)));
+                    ui.label(format!("Median: {}", describe_offset(stats.median)));
+                    ui.label(format!("Unstable rate: {:.1}", stats.unstable_rate))
+                        .on_hover_text(
+                            "Ten times the standard deviation of your hits in milliseconds. The \
+                            lower it is, the more consistent your timing was",
+                        );
+
+                    hit_error_scatter(ui, &self.score)
+                        .on_hover_text("How early (up) or late (down) each hit was over the song");
+                    hit_error_histogram(ui, &self.score).on_hover_text(
+                        "How many hits were early (left) or late (right) by how much",
+                    );
+                });
+            }
+
+            if let Some(hands) = self.score.hand_stats {
+                ui.collapsing("Details", |ui| {
+                    ui.label(format!("Alternating hands: {:.0}%", hands.alternation));
+                    ui.label(format!(
+                        "Most hits in a row with one hand: {}",
+                        hands.longest_streak
+                    ));
+                });
+            }
+
+            ui.add_space(10.0);
+
+            if ui
+                .button("Copy result to clipboard")
+                .on_hover_text("Ctrl+C")
+                .clicked()
+            {
+                self.copy_requested = true;
+            }
+
+            if let Some((message, _)) = self.clipboard_message {
+                ui.label(message);
+            }
+
+            if self.replay.is_some() {
+                self.watch_replay = ui.button("Watch replay").clicked();
+            }
+
+            if let Some(error) = &self.replay_error {
+                ui.label(error);
+            }
 
             self.exit = ui.button("Back to menu").clicked();
         });
     }
 }
+
+impl GameState for ScoreScreen {
+    fn update(&mut self, ctx: &mut Context, delta_time: f32) -> StateTransition {
+        self.confetti.update(delta_time);
+
+        if std::mem::take(&mut self.watch_replay) {
+            if let Some(replay) = &self.replay {
+                match replay.start(ctx) {
+                    Ok(scene) => return StateTransition::Swap(Box::new(scene)),
+                    Err(e) => {
+                        log::error!("couldn't start the replay: {e}");
+                        self.replay_error = Some(format!("Couldn't start the replay: {e}"));
+                    }
+                }
+            }
+        }
+
+        if self.copy_requested {
+            self.copy_requested = false;
+            self.copy_to_clipboard();
+        }
+
+        if self
+            .clipboard_message
+            .is_some_and(|(_, time)| time.elapsed().as_secs_f32() > CLIPBOARD_MESSAGE_TIME)
+        {
+            self.clipboard_message = None;
+        }
+
+        if self.exit {
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
+            StateTransition::Pop
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        self.results_ui(&ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        if let WindowEvent::KeyboardInput { event, .. } = event {
+            let ctrl_held = ctx
+                .keyboard
+                .is_pressed(PhysicalKey::Code(KeyCode::ControlLeft))
+                || ctx
+                    .keyboard
+                    .is_pressed(PhysicalKey::Code(KeyCode::ControlRight));
+
+            if ctrl_held
+                && event.physical_key == PhysicalKey::Code(KeyCode::KeyC)
+                && event.state == ElementState::Pressed
+                && !event.repeat
+            {
+                self.copy_requested = true;
+            }
+        }
+    }
+
+    fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+        if let Some(tint) = &self.failed_tint {
+            ctx.render(tint);
+        }
+        ctx.render(&self.confetti);
+    }
+
+    fn transition_style(&self) -> TransitionStyle {
+        TransitionStyle::Crossfade
+    }
+
+    fn recreate_gpu_resources(&mut self, ctx: &mut Context) -> bool {
+        self.confetti.recreate_gpu_resources(ctx.renderer);
+        if self.failed_tint.is_some() {
+            self.failed_tint = create_failed_tint(ctx.renderer).ok();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn example_summary() -> ResultSummary {
+        ResultSummary {
+            title: "POP TEAM EPIC".to_string(),
+            difficulty: "Oni".to_string(),
+            score: 1000000,
+            accuracy: 98.5,
+            goods: 400,
+            okays: 12,
+            bads: 1,
+            misses: 2,
+            drumrolls: 57,
+            max_combo: 300,
+            played_at: Local.with_ymd_and_hms(2024, 3, 1, 18, 30, 0).unwrap(),
+            modifiers: Vec::new(),
+            rules: RulesPreset::Modern,
+            audio_skips: 0,
+        }
+    }
+
+    #[test]
+    fn test_plain_text_summary() {
+        assert_eq!(
+            example_summary().to_plain_text(),
+            "POP TEAM EPIC [Oni]\n\
+            Score: 1000000\n\
+            Accuracy: 98.50%\n\
+            Good: 400 / Ok: 12 / Bad: 1 / Miss: 2\n\
+            Drumrolls: 57\n\
+            Max Combo: 300\n\
+            Modifiers: none\n\
+            Rules: Modern\n\
+            Played: 2024-03-01 18:30"
+        );
+    }
+
+    #[test]
+    fn test_plain_text_summary_modifiers() {
+        let summary = ResultSummary {
+            modifiers: vec!["Hidden".to_string(), "x1.5".to_string()],
+            ..example_summary()
+        };
+
+        assert!(summary
+            .to_plain_text()
+            .contains("\nModifiers: Hidden, x1.5\n"));
+    }
+
+    #[test]
+    fn test_plain_text_summary_audio_skips() {
+        let summary = ResultSummary {
+            audio_skips: 2,
+            ..example_summary()
+        };
+
+        assert!(summary.to_plain_text().ends_with("\nAudio skipped: 2"));
+    }
+}
+
+#[cfg(all(test, feature = "visual-tests"))]
+mod visual_test {
+    use super::*;
+    use crate::game::taiko_mode::{autoplay_inputs, simulate};
+    use crate::notechart_parser::parse_tja_file;
+    use crate::render::visual_test::{assert_matches_golden, VisualTest};
+    use crate::settings::RulesConfig;
+
+    #[test]
+    fn test_score_screen() {
+        let song =
+            parse_tja_file(include_str!("../../tests/fixtures/visual/note_field.tja")).unwrap();
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+        let result = simulate(chart, 3, &RulesConfig::MODERN, &autoplay_inputs(chart, 3));
+        assert!(result.is_full_combo());
+
+        let mut test = VisualTest::new();
+        let mut screen =
+            ScoreScreen::new(&test.renderer, song.title, 3, result, true, Rng::new(2513))
+                .with_modifiers(vec!["x1.5".to_string()]);
+
+        // Partway through the confetti, in steps the size of a frame
+        for _ in 0..30 {
+            screen.confetti.update(1.0 / 60.0);
+        }
+
+        let image = test.render(
+            &mut screen,
+            |screen, ctx| screen.results_ui(ctx),
+            |screen, ctx| screen.render(ctx),
+        );
+        assert_matches_golden("score_screen", &image);
+    }
+}
@@ -0,0 +1,639 @@
+//! A record of every song the player has finished.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use super::taiko_mode::{Mods, PlayResult, ScoreInt};
+use crate::settings::RulesPreset;
+
+/// The file plays are saved to.
+pub const SCORES_PATH: &str = "scores.toml";
+
+static SCORES_REVISION: AtomicU64 = AtomicU64::new(0);
+
+/// One finished play of a chart.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayRecord {
+    /// The hash of the chart that was played, as given by
+    /// [chart_hash](crate::notechart_parser::chart_hash).
+    pub chart: String,
+    pub difficulty: usize,
+    /// The star level the chart declared.
+    pub star_level: u8,
+    pub score: ScoreInt,
+    /// Whether the health gauge was past the clear threshold at the end of the song.
+    pub cleared: bool,
+    /// Whether every note was hit without breaking the combo. Plays recorded before this was kept
+    /// don't count as full combos.
+    #[serde(default)]
+    pub full_combo: bool,
+    #[serde(default)]
+    pub max_combo: usize,
+    /// How many notes got each judgement. Plays recorded before these were kept don't have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub judgements: Option<JudgementCounts>,
+    /// When the play finished, in seconds since the unix epoch.
+    pub played_at: i64,
+    /// The rules the play was scored by. Plays recorded before there was a choice of rules don't
+    /// have this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<RulesPreset>,
+    /// How fast the song was played (see [SPEED_MODS](super::song_select::SPEED_MODS)), if it
+    /// wasn't played at the normal speed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f32>,
+    /// The mods the play was played with, if any.
+    #[serde(default, skip_serializing_if = "Mods::is_empty")]
+    pub mods: Mods,
+    /// The crown the play earned. Plays recorded before crowns were kept don't have this, and
+    /// their crown is worked out from the rest of the record instead (see [PlayRecord::crown]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crown: Option<Crown>,
+    /// The name of the file the play's replay was saved as in
+    /// [REPLAYS_DIR](super::replay::REPLAYS_DIR), if it was saved. Replays aren't exported with
+    /// the player's data, so this might not be there on another computer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay: Option<String>,
+}
+
+/// How well a play went, from failing to hitting every note perfectly. Each crown is better than
+/// the ones before it, so the best of several plays is the greatest.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Crown {
+    /// The gauge didn't reach the clear line.
+    #[default]
+    Failed,
+    /// The gauge reached the clear line.
+    Clear,
+    /// Every note was hit without breaking the combo.
+    FullCombo,
+    /// Every note was hit with a Good.
+    Donderful,
+}
+
+impl Crown {
+    /// The crown a play earned, given whether its gauge reached the clear line. A song with no
+    /// notes can be cleared, but can't be full comboed.
+    pub fn of(result: &PlayResult, cleared: bool) -> Self {
+        if result.is_full_combo() && result.okays() == 0 {
+            Crown::Donderful
+        } else if result.is_full_combo() {
+            Crown::FullCombo
+        } else if cleared {
+            Crown::Clear
+        } else {
+            Crown::Failed
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Crown::Failed => "Not cleared",
+            Crown::Clear => "Cleared",
+            Crown::FullCombo => "Full combo",
+            Crown::Donderful => "Donderful combo",
+        }
+    }
+}
+
+/// How many notes of a play got each judgement.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JudgementCounts {
+    pub goods: usize,
+    pub okays: usize,
+    pub bads: usize,
+    pub misses: usize,
+}
+
+impl JudgementCounts {
+    pub fn of(result: &PlayResult) -> Self {
+        Self {
+            goods: result.goods(),
+            okays: result.okays(),
+            bads: result.bads(),
+            misses: result.misses(),
+        }
+    }
+}
+
+impl PlayRecord {
+    /// The crown the play earned. If it wasn't recorded, it's worked out from whether the play
+    /// was cleared and full comboed, and from its judgements if they were kept.
+    pub fn crown(&self) -> Crown {
+        if let Some(crown) = self.crown {
+            return crown;
+        }
+
+        // A full combo has no bads or misses, so it's donderful if it has no okays either
+        let all_goods = self.judgements.is_some_and(|counts| counts.okays == 0);
+
+        if self.full_combo && all_goods {
+            Crown::Donderful
+        } else if self.full_combo {
+            Crown::FullCombo
+        } else if self.cleared {
+            Crown::Clear
+        } else {
+            Crown::Failed
+        }
+    }
+
+    /// Whether the song was played at its normal speed. Slowing it down makes it easier, so only
+    /// these plays count towards the player's best scores and crowns.
+    pub fn at_normal_speed(&self) -> bool {
+        self.rate.is_none()
+    }
+}
+
+/// The best the player has done on one difficulty of a chart, over every play of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BestRecord {
+    pub score: ScoreInt,
+    /// The judgements of the play that got the best score, if they were kept.
+    pub judgements: Option<JudgementCounts>,
+    /// The longest combo of any play, which might not be from the play with the best score.
+    pub max_combo: usize,
+    pub cleared: bool,
+    pub full_combo: bool,
+    /// The best crown of any play.
+    pub crown: Crown,
+    pub plays: usize,
+}
+
+impl BestRecord {
+    fn new(play: &PlayRecord) -> Self {
+        Self {
+            score: play.score,
+            judgements: play.judgements,
+            max_combo: play.max_combo,
+            cleared: play.cleared,
+            full_combo: play.full_combo,
+            crown: play.crown(),
+            plays: 1,
+        }
+    }
+
+    fn add(&mut self, play: &PlayRecord) {
+        if play.score > self.score {
+            self.score = play.score;
+            self.judgements = play.judgements;
+        }
+
+        self.max_combo = self.max_combo.max(play.max_combo);
+        self.cleared |= play.cleared;
+        self.full_combo |= play.full_combo;
+        self.crown = self.crown.max(play.crown());
+        self.plays += 1;
+    }
+}
+
+/// Every play the player has finished, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScoreDatabase {
+    #[serde(default)]
+    pub plays: Vec<PlayRecord>,
+}
+
+impl ScoreDatabase {
+    /// Reads the scores file, starting with no plays if it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The most recent plays, up to `count` of them, oldest first.
+    pub fn recent(&self, count: usize) -> &[PlayRecord] {
+        &self.plays[self.plays.len().saturating_sub(count)..]
+    }
+
+    /// The best score on each difficulty of each chart that's been played, keyed by the chart's
+    /// hash and the difficulty.
+    pub fn bests(&self) -> HashMap<(&str, usize), ScoreInt> {
+        let mut bests = HashMap::new();
+
+        for play in self.plays.iter() {
+            let best = bests
+                .entry((play.chart.as_str(), play.difficulty))
+                .or_insert(play.score);
+            *best = (*best).max(play.score);
+        }
+
+        bests
+    }
+
+    /// The best the player has done on each difficulty of each chart that's been played, keyed by
+    /// the chart's hash and the difficulty. Only plays at the normal speed count (see
+    /// [PlayRecord::at_normal_speed]).
+    pub fn best_records(&self) -> HashMap<(String, usize), BestRecord> {
+        let mut bests: HashMap<_, BestRecord> = HashMap::new();
+
+        for play in self.plays.iter().filter(|play| play.at_normal_speed()) {
+            bests
+                .entry((play.chart.clone(), play.difficulty))
+                .and_modify(|best| best.add(play))
+                .or_insert_with(|| BestRecord::new(play));
+        }
+
+        bests
+    }
+
+    /// The best the player has done on one difficulty of a chart, if they've played it at the
+    /// normal speed.
+    pub fn best_record(&self, chart: &str, difficulty: usize) -> Option<BestRecord> {
+        let mut plays = self.plays.iter().filter(|play| {
+            play.chart == chart && play.difficulty == difficulty && play.at_normal_speed()
+        });
+
+        let mut best = BestRecord::new(plays.next()?);
+        plays.for_each(|play| best.add(play));
+        Some(best)
+    }
+
+    /// The replay of the most recent play of each difficulty of each chart that has one, keyed by
+    /// the chart's hash and the difficulty.
+    pub fn latest_replays(&self) -> HashMap<(String, usize), String> {
+        self.plays
+            .iter()
+            .filter_map(|play| {
+                let replay = play.replay.clone()?;
+                Some(((play.chart.clone(), play.difficulty), replay))
+            })
+            .collect()
+    }
+
+    /// When each chart that's been played was last played (on any difficulty), keyed by the
+    /// chart's hash.
+    pub fn last_played(&self) -> HashMap<String, i64> {
+        let mut last_played = HashMap::new();
+
+        for play in self.plays.iter() {
+            let time = last_played
+                .entry(play.chart.clone())
+                .or_insert(play.played_at);
+            *time = (*time).max(play.played_at);
+        }
+
+        last_played
+    }
+
+    /// Takes in the plays from another score database (e.g. from another computer) that aren't
+    /// already in this one, keeping every play in the order it finished. Returns how many plays
+    /// were added.
+    ///
+    /// A play that's exactly the same as one already here is the same play, so merging the same
+    /// scores twice doesn't count anything twice. As every play is kept, each chart's best score
+    /// ends up being the better of the two and its play count the total of both.
+    pub fn merge(&mut self, other: &ScoreDatabase) -> usize {
+        let before = self.plays.len();
+
+        for play in other.plays.iter() {
+            if !self.plays.contains(play) {
+                self.plays.push(play.clone());
+            }
+        }
+
+        // The sort is stable, so plays that finished at the same time stay in the same order
+        self.plays.sort_by_key(|play| play.played_at);
+        self.plays.len() - before
+    }
+}
+
+/// A number that changes every time a play is recorded, so that anything worked out from the
+/// scores knows to load them again.
+pub fn scores_revision() -> u64 {
+    SCORES_REVISION.load(Ordering::Acquire)
+}
+
+/// Adds a play to the scores file.
+pub fn record_play(play: PlayRecord) -> anyhow::Result<()> {
+    let mut scores = ScoreDatabase::load(SCORES_PATH)?;
+    scores.plays.push(play);
+    save_scores(&scores)
+}
+
+/// Replaces everything in the scores file with the given scores.
+pub fn save_scores(scores: &ScoreDatabase) -> anyhow::Result<()> {
+    scores.save(SCORES_PATH)?;
+
+    SCORES_REVISION.fetch_add(1, Ordering::Release);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn play_of(chart: &str, difficulty: usize, score: ScoreInt, played_at: i64) -> PlayRecord {
+        PlayRecord {
+            chart: chart.to_string(),
+            difficulty,
+            star_level: 7,
+            score,
+            cleared: true,
+            full_combo: false,
+            max_combo: 0,
+            judgements: None,
+            played_at,
+            rules: None,
+            rate: None,
+            mods: Mods::default(),
+            crown: None,
+            replay: None,
+        }
+    }
+
+    fn play(star_level: u8, cleared: bool) -> PlayRecord {
+        PlayRecord {
+            chart: "v1:00".to_string(),
+            difficulty: 3,
+            star_level,
+            score: 100000,
+            cleared,
+            full_combo: false,
+            max_combo: 0,
+            judgements: None,
+            played_at: 1700000000,
+            rules: None,
+            rate: None,
+            mods: Mods::default(),
+            crown: None,
+            replay: None,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("taiko-scores-{}", std::process::id()));
+        assert_eq!(
+            ScoreDatabase::load(&path).unwrap(),
+            ScoreDatabase::default()
+        );
+
+        let scores = ScoreDatabase {
+            plays: vec![play(5, true), play(8, false)],
+        };
+        scores.save(&path).unwrap();
+
+        let loaded = ScoreDatabase::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, scores);
+    }
+
+    #[test]
+    fn test_recent() {
+        let scores = ScoreDatabase {
+            plays: (1..=5).map(|level| play(level, true)).collect(),
+        };
+
+        let levels = |plays: &[PlayRecord]| plays.iter().map(|p| p.star_level).collect::<Vec<_>>();
+        assert_eq!(levels(scores.recent(2)), vec![4, 5]);
+        assert_eq!(levels(scores.recent(10)), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_best_records() {
+        let counts = |goods, bads| JudgementCounts {
+            goods,
+            okays: 0,
+            bads,
+            misses: 0,
+        };
+
+        let scores = ScoreDatabase {
+            plays: vec![
+                PlayRecord {
+                    max_combo: 300,
+                    judgements: Some(counts(290, 10)),
+                    ..play_of("v1:aa", 3, 700000, 100)
+                },
+                PlayRecord {
+                    cleared: false,
+                    max_combo: 120,
+                    judgements: Some(counts(250, 50)),
+                    ..play_of("v1:aa", 3, 600000, 200)
+                },
+                PlayRecord {
+                    full_combo: true,
+                    max_combo: 400,
+                    judgements: Some(counts(400, 0)),
+                    ..play_of("v1:aa", 3, 650000, 300)
+                },
+                play_of("v1:aa", 2, 900000, 400),
+            ],
+        };
+
+        let best = scores.best_record("v1:aa", 3).unwrap();
+        assert_eq!(
+            best,
+            BestRecord {
+                score: 700000,
+                // The judgements are from the best scoring play, but the combo is the longest one
+                judgements: Some(counts(290, 10)),
+                max_combo: 400,
+                cleared: true,
+                full_combo: true,
+                crown: Crown::Donderful,
+                plays: 3,
+            }
+        );
+
+        let records = scores.best_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[&("v1:aa".to_string(), 3)], best);
+        assert_eq!(records[&("v1:aa".to_string(), 2)].score, 900000);
+        assert!(scores.best_record("v1:bb", 3).is_none());
+
+        // Plays saved before the judgements were kept can still be read
+        let old = "[[plays]]\nchart = \"v1:aa\"\ndifficulty = 3\nstar_level = 7\nscore = 1\n\
+                   cleared = true\nplayed_at = 1\n";
+        let old: ScoreDatabase = toml::from_str(old).unwrap();
+        assert_eq!(old.plays[0].judgements, None);
+        assert!(!old.plays[0].full_combo);
+    }
+
+    #[test]
+    fn test_slowed_plays_are_not_best_records() {
+        let scores = ScoreDatabase {
+            plays: vec![
+                play_of("v1:aa", 3, 500000, 100),
+                PlayRecord {
+                    full_combo: true,
+                    rate: Some(0.75),
+                    ..play_of("v1:aa", 3, 900000, 200)
+                },
+                PlayRecord {
+                    rate: Some(0.5),
+                    ..play_of("v1:bb", 3, 900000, 300)
+                },
+            ],
+        };
+
+        let best = scores.best_record("v1:aa", 3).unwrap();
+        assert_eq!(best.score, 500000);
+        assert_eq!(best.crown, Crown::Clear);
+        assert_eq!(best.plays, 1);
+        assert!(scores.best_record("v1:bb", 3).is_none());
+
+        let records = scores.best_records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[&("v1:aa".to_string(), 3)], best);
+    }
+
+    #[test]
+    fn test_crowns() {
+        use crate::game::taiko_mode::{autoplay_inputs, simulate};
+        use crate::notechart_parser::{Note, NoteChart, NoteType};
+        use crate::settings::RulesConfig;
+
+        let chart = NoteChart {
+            notes: [1.0, 2.0, 3.0]
+                .map(|time| Note {
+                    note_type: NoteType::Don,
+                    time,
+                    scroll_speed: 1.0,
+                    vertical_speed: 0.0,
+                })
+                .to_vec(),
+            ..Default::default()
+        };
+        let rules = RulesConfig::default();
+
+        let perfect = simulate(&chart, 3, &rules, &autoplay_inputs(&chart, 3));
+        assert_eq!(Crown::of(&perfect, true), Crown::Donderful);
+
+        let missed = simulate(&chart, 3, &rules, &[]);
+        assert_eq!(Crown::of(&missed, true), Crown::Clear);
+        assert_eq!(Crown::of(&missed, false), Crown::Failed);
+
+        // Plays recorded before crowns were kept
+        let counts = |okays| {
+            Some(JudgementCounts {
+                goods: 10,
+                okays,
+                bads: 0,
+                misses: 0,
+            })
+        };
+        let full_combo = |judgements| PlayRecord {
+            full_combo: true,
+            judgements,
+            ..play(5, true)
+        };
+        assert_eq!(full_combo(counts(0)).crown(), Crown::Donderful);
+        assert_eq!(full_combo(counts(2)).crown(), Crown::FullCombo);
+        assert_eq!(full_combo(None).crown(), Crown::FullCombo);
+        assert_eq!(play(5, true).crown(), Crown::Clear);
+        assert_eq!(play(5, false).crown(), Crown::Failed);
+
+        let recorded = PlayRecord {
+            crown: Some(Crown::FullCombo),
+            ..play(5, true)
+        };
+        assert_eq!(recorded.crown(), Crown::FullCombo);
+    }
+
+    #[test]
+    fn test_latest_replays() {
+        let with_replay = |play: PlayRecord, replay: &str| PlayRecord {
+            replay: Some(replay.to_string()),
+            ..play
+        };
+
+        let scores = ScoreDatabase {
+            plays: vec![
+                with_replay(play_of("v1:aa", 3, 500000, 100), "first"),
+                with_replay(play_of("v1:aa", 3, 600000, 200), "second"),
+                // Plays without a replay don't hide the ones before them
+                play_of("v1:aa", 3, 700000, 300),
+                with_replay(play_of("v1:aa", 2, 500000, 400), "other difficulty"),
+                play_of("v1:bb", 3, 500000, 500),
+            ],
+        };
+
+        let replays = scores.latest_replays();
+        assert_eq!(replays.len(), 2);
+        assert_eq!(replays[&("v1:aa".to_string(), 3)], "second");
+        assert_eq!(replays[&("v1:aa".to_string(), 2)], "other difficulty");
+    }
+
+    #[test]
+    fn test_last_played() {
+        let scores = ScoreDatabase {
+            plays: vec![
+                play_of("v1:aa", 3, 500000, 300),
+                // Merged plays aren't always in the order they were played
+                play_of("v1:aa", 2, 500000, 100),
+                play_of("v1:bb", 3, 500000, 200),
+            ],
+        };
+
+        let last_played = scores.last_played();
+        assert_eq!(last_played.len(), 2);
+        assert_eq!(last_played["v1:aa"], 300);
+        assert_eq!(last_played["v1:bb"], 200);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut ours = ScoreDatabase {
+            plays: vec![
+                play_of("v1:aa", 3, 500000, 100),
+                play_of("v1:aa", 3, 700000, 300),
+                play_of("v1:bb", 1, 900000, 500),
+            ],
+        };
+
+        let theirs = ScoreDatabase {
+            plays: vec![
+                // Already here
+                play_of("v1:aa", 3, 500000, 100),
+                // A better score on a chart we've both played
+                play_of("v1:aa", 3, 800000, 200),
+                // A worse one
+                play_of("v1:bb", 1, 400000, 600),
+                // A difficulty only they've played
+                play_of("v1:aa", 2, 600000, 400),
+                // Looks like a play we have, but finished at a different time
+                play_of("v1:bb", 1, 900000, 501),
+            ],
+        };
+
+        assert_eq!(ours.merge(&theirs), 4);
+
+        // Plays stay in the order they finished
+        let times = ours.plays.iter().map(|p| p.played_at).collect::<Vec<_>>();
+        assert_eq!(times, vec![100, 200, 300, 400, 500, 501, 600]);
+
+        // The better score is kept, and the play counts add up
+        let bests = ours.bests();
+        assert_eq!(bests[&("v1:aa", 3)], 800000);
+        assert_eq!(bests[&("v1:aa", 2)], 600000);
+        assert_eq!(bests[&("v1:bb", 1)], 900000);
+        let count = |chart, difficulty| {
+            ours.plays
+                .iter()
+                .filter(|p| p.chart == chart && p.difficulty == difficulty)
+                .count()
+        };
+        assert_eq!(count("v1:aa", 3), 3);
+        assert_eq!(count("v1:bb", 1), 3);
+
+        // Merging the same scores again changes nothing
+        let merged = ours.clone();
+        assert_eq!(ours.merge(&theirs), 0);
+        assert_eq!(ours, merged);
+    }
+}
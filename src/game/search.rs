@@ -0,0 +1,358 @@
+//! Searching for songs by what's typed in song select.
+//!
+//! Titles are compared with the differences that don't matter when typing folded away (see
+//! [normalise]), so that "ｒｅａｄｙ" finds "Ready to" and "ﾊﾟﾝ" finds "パン". Anything typed in
+//! romaji is also looked for as kana, so songs with Japanese titles can be found without a
+//! Japanese keyboard.
+use crate::notechart_parser::Song;
+
+/// The full width versions of the half width katakana, from U+FF61 to U+FF9F.
+const HALF_WIDTH_KATAKANA: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+/// Romaji syllables, and the kana they're written with.
+const ROMAJI: &[(&str, &str)] = &[
+    ("a", "あ"),
+    ("i", "い"),
+    ("u", "う"),
+    ("e", "え"),
+    ("o", "お"),
+    ("ka", "か"),
+    ("ki", "き"),
+    ("ku", "く"),
+    ("ke", "け"),
+    ("ko", "こ"),
+    ("kya", "きゃ"),
+    ("kyu", "きゅ"),
+    ("kyo", "きょ"),
+    ("ga", "が"),
+    ("gi", "ぎ"),
+    ("gu", "ぐ"),
+    ("ge", "げ"),
+    ("go", "ご"),
+    ("gya", "ぎゃ"),
+    ("gyu", "ぎゅ"),
+    ("gyo", "ぎょ"),
+    ("sa", "さ"),
+    ("si", "し"),
+    ("shi", "し"),
+    ("su", "す"),
+    ("se", "せ"),
+    ("so", "そ"),
+    ("sha", "しゃ"),
+    ("shu", "しゅ"),
+    ("she", "しぇ"),
+    ("sho", "しょ"),
+    ("sya", "しゃ"),
+    ("syu", "しゅ"),
+    ("syo", "しょ"),
+    ("za", "ざ"),
+    ("zi", "じ"),
+    ("ji", "じ"),
+    ("zu", "ず"),
+    ("ze", "ぜ"),
+    ("zo", "ぞ"),
+    ("ja", "じゃ"),
+    ("ju", "じゅ"),
+    ("je", "じぇ"),
+    ("jo", "じょ"),
+    ("jya", "じゃ"),
+    ("jyu", "じゅ"),
+    ("jyo", "じょ"),
+    ("zya", "じゃ"),
+    ("zyu", "じゅ"),
+    ("zyo", "じょ"),
+    ("ta", "た"),
+    ("ti", "ち"),
+    ("chi", "ち"),
+    ("tu", "つ"),
+    ("tsu", "つ"),
+    ("te", "て"),
+    ("to", "と"),
+    ("cha", "ちゃ"),
+    ("chu", "ちゅ"),
+    ("che", "ちぇ"),
+    ("cho", "ちょ"),
+    ("tya", "ちゃ"),
+    ("tyu", "ちゅ"),
+    ("tyo", "ちょ"),
+    ("da", "だ"),
+    ("di", "ぢ"),
+    ("du", "づ"),
+    ("de", "で"),
+    ("do", "ど"),
+    ("na", "な"),
+    ("ni", "に"),
+    ("nu", "ぬ"),
+    ("ne", "ね"),
+    ("no", "の"),
+    ("nya", "にゃ"),
+    ("nyu", "にゅ"),
+    ("nyo", "にょ"),
+    ("ha", "は"),
+    ("hi", "ひ"),
+    ("hu", "ふ"),
+    ("fu", "ふ"),
+    ("he", "へ"),
+    ("ho", "ほ"),
+    ("hya", "ひゃ"),
+    ("hyu", "ひゅ"),
+    ("hyo", "ひょ"),
+    ("fa", "ふぁ"),
+    ("fi", "ふぃ"),
+    ("fe", "ふぇ"),
+    ("fo", "ふぉ"),
+    ("ba", "ば"),
+    ("bi", "び"),
+    ("bu", "ぶ"),
+    ("be", "べ"),
+    ("bo", "ぼ"),
+    ("bya", "びゃ"),
+    ("byu", "びゅ"),
+    ("byo", "びょ"),
+    ("pa", "ぱ"),
+    ("pi", "ぴ"),
+    ("pu", "ぷ"),
+    ("pe", "ぺ"),
+    ("po", "ぽ"),
+    ("pya", "ぴゃ"),
+    ("pyu", "ぴゅ"),
+    ("pyo", "ぴょ"),
+    ("ma", "ま"),
+    ("mi", "み"),
+    ("mu", "む"),
+    ("me", "め"),
+    ("mo", "も"),
+    ("mya", "みゃ"),
+    ("myu", "みゅ"),
+    ("myo", "みょ"),
+    ("ya", "や"),
+    ("yu", "ゆ"),
+    ("yo", "よ"),
+    ("ra", "ら"),
+    ("ri", "り"),
+    ("ru", "る"),
+    ("re", "れ"),
+    ("ro", "ろ"),
+    ("rya", "りゃ"),
+    ("ryu", "りゅ"),
+    ("ryo", "りょ"),
+    ("wa", "わ"),
+    ("wo", "を"),
+    ("vu", "ゔ"),
+    ("-", "ー"),
+];
+
+/// The dakuten and handakuten, both as their own characters and as combining marks.
+const VOICED_MARKS: [char; 2] = ['゛', '\u{3099}'];
+const SEMI_VOICED_MARKS: [char; 2] = ['゜', '\u{309A}'];
+
+/// Adds a dakuten (or a handakuten, if `semi` is set) to a hiragana character, if it can have one.
+fn voice(kana: char, semi: bool) -> Option<char> {
+    // The voiced versions of these come straight after them
+    let offset = if semi {
+        "はひふへほ".contains(kana).then_some(2)?
+    } else if kana == 'う' {
+        return Some('ゔ');
+    } else {
+        "かきくけこさしすせそたちつてとはひふへほ"
+            .contains(kana)
+            .then_some(1)?
+    };
+
+    char::from_u32(kana as u32 + offset)
+}
+
+/// Folds away the differences between two pieces of text that shouldn't stop one being found in
+/// the other: case, full and half width characters, katakana and hiragana, and spaces.
+pub fn normalise(text: &str) -> String {
+    let mut normalised = String::new();
+
+    for c in text.chars() {
+        let c = match c {
+            // Full width ASCII
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '\u{FF61}'..='\u{FF9F}' => HALF_WIDTH_KATAKANA
+                .chars()
+                .nth(c as usize - 0xFF61)
+                .unwrap_or(c),
+            _ => c,
+        };
+
+        // Katakana, apart from the ones there isn't a hiragana of
+        let c = match c {
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        };
+
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let semi = SEMI_VOICED_MARKS.contains(&c);
+        if semi || VOICED_MARKS.contains(&c) {
+            if let Some(voiced) = normalised.chars().last().and_then(|last| voice(last, semi)) {
+                normalised.pop();
+                normalised.push(voiced);
+                continue;
+            }
+        }
+
+        normalised.extend(c.to_lowercase());
+    }
+
+    normalised
+}
+
+/// Reads normalised text (see [normalise]) as romaji, and writes it in hiragana. Anything that
+/// isn't a letter is left as it is. Returns None if the text can't be romaji.
+///
+/// Letters at the end that don't make a whole syllable yet are left off, so that a song can be
+/// found while its title is still being typed.
+fn romaji_to_kana(text: &str) -> Option<String> {
+    if !text.is_ascii() || !text.bytes().any(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut kana = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let next = rest[1..].chars().next();
+
+        // Doubled consonants are written with a small tsu
+        let doubled = next == Some(c) && c.is_ascii_alphabetic() && !"aeioun".contains(c);
+        if doubled || rest.starts_with("tch") {
+            kana.push('っ');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let syllable = (1..=3)
+            .rev()
+            .filter_map(|len| rest.get(..len))
+            .find_map(|start| ROMAJI.iter().find(|(romaji, _)| *romaji == start));
+
+        if let Some((romaji, syllable)) = syllable {
+            kana.push_str(syllable);
+            rest = &rest[romaji.len()..];
+        } else if c == 'n' && next.is_some_and(|next| !"aeiouy".contains(next)) {
+            kana.push('ん');
+            rest = rest[1..].strip_prefix('\'').unwrap_or(&rest[1..]);
+        } else if !c.is_ascii_alphabetic() {
+            kana.push(c);
+            rest = &rest[1..];
+        } else if rest.len() <= 3 && rest.bytes().all(|b| b.is_ascii_alphabetic()) {
+            // The start of a syllable that's still being typed
+            break;
+        } else {
+            return None;
+        }
+    }
+
+    Some(kana)
+}
+
+/// What's been typed into the search, ready to look for in songs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SearchQuery {
+    text: String,
+    /// The text as kana, if it could be romaji
+    kana: Option<String>,
+}
+
+impl SearchQuery {
+    pub fn new(query: &str) -> Self {
+        let text = normalise(query);
+        let kana = romaji_to_kana(&text).filter(|kana| !kana.is_empty());
+        Self { text, kana }
+    }
+
+    /// Whether a song's title or subtitle, in any language, has the query in it. Every song matches
+    /// an empty query.
+    pub fn matches(&self, song: &Song) -> bool {
+        if self.text.is_empty() {
+            return true;
+        }
+
+        song.all_titles().map(normalise).any(|text| {
+            text.contains(&self.text) || self.kana.as_ref().is_some_and(|kana| text.contains(kana))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::LocalisedTitle;
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(HALF_WIDTH_KATAKANA.chars().count(), 0xFF9F - 0xFF61 + 1);
+
+        assert_eq!(normalise("Ready to"), "readyto");
+        assert_eq!(normalise("ＲＥＡＤＹ　ｔｏ"), "readyto");
+        assert_eq!(normalise("パン"), "ぱん");
+        assert_eq!(normalise("ﾊﾟﾝ ｶﾞｰﾃﾞﾝ"), "ぱんがーでん");
+        assert_eq!(normalise("ウ\u{3099}ィーナス"), "ゔぃーなす");
+        // Marks that can't go on the character before them are kept
+        assert_eq!(normalise("ア゜"), "あ゜");
+    }
+
+    #[test]
+    fn test_romaji_to_kana() {
+        let kana = |text| romaji_to_kana(text);
+
+        assert_eq!(kana("yorunikakeru").as_deref(), Some("よるにかける"));
+        assert_eq!(kana("konnichiha").as_deref(), Some("こんにちは"));
+        assert_eq!(kana("kitto").as_deref(), Some("きっと"));
+        assert_eq!(kana("matcha").as_deref(), Some("まっちゃ"));
+        assert_eq!(kana("shin'ya").as_deref(), Some("しんや"));
+        assert_eq!(kana("senbonzakura2").as_deref(), Some("せんぼんざくら2"));
+        // Half typed syllables are left off
+        assert_eq!(kana("yoruk").as_deref(), Some("よる"));
+        assert_eq!(kana("kon").as_deref(), Some("こ"));
+
+        assert_eq!(kana("xylophone"), None);
+        assert_eq!(kana("よる"), None);
+        assert_eq!(kana("2000"), None);
+    }
+
+    #[test]
+    fn test_search_matches() {
+        let song = |title: &str, subtitle: Option<&str>| Song {
+            title: title.to_string(),
+            subtitle: subtitle.map(str::to_string),
+            ..Default::default()
+        };
+        let matches = |query, song: &Song| SearchQuery::new(query).matches(song);
+
+        let ready = song("Ready to", Some("--Someone"));
+        assert!(matches("", &ready));
+        assert!(matches("READY", &ready));
+        assert!(matches("ｒｅａｄｙ ｔｏ", &ready));
+        assert!(matches("someone", &ready));
+        assert!(!matches("ready go", &ready));
+
+        let yoru = song("夜に駆ける", Some("YOASOBI"));
+        assert!(matches("夜に", &yoru));
+        assert!(matches("yoasobi", &yoru));
+        assert!(!matches("yoru", &yoru));
+
+        // Titles in other languages are searched too
+        let mut yoru = yoru;
+        yoru.localised_titles.insert(
+            "en".to_string(),
+            LocalisedTitle {
+                title: Some("Racing into the Night".to_string()),
+                subtitle: None,
+            },
+        );
+        assert!(matches("into the night", &yoru));
+
+        let kana = song("ヒバナ", None);
+        assert!(matches("ひばな", &kana));
+        assert!(matches("ﾋﾊﾞﾅ", &kana));
+        assert!(matches("hiban", &kana));
+        assert!(!matches("hibana2", &kana));
+    }
+}
@@ -0,0 +1,691 @@
+use egui::RichText;
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::audio::{AudioManager, BUFFER_SIZES};
+use crate::game::export::{export_player_data, PendingImport, DEFAULT_EXPORT_PATH};
+use crate::game::taiko_mode::NotePreview;
+use crate::game::ui_elements::{offset_profile_combo_box, percentage_slider};
+use crate::game::{Context, GameState, RenderContext, SoundEffect, StateTransition, TextureCache};
+use crate::notechart_parser::TITLE_LANGUAGES;
+use crate::render::texture::{Sprite, SpriteBuilder};
+use crate::render::Renderer;
+use crate::settings::{
+    key_name, save_settings, settings, settings_mut, DrumInput, FailBehaviour, GameSettings,
+    JudgementStyle, KeyMap, NoteScoring, PresentMode, ResolutionState, RulesPreset, VisualSettings,
+    DEFAULT_WINDOW_SIZE,
+};
+use crate::skin::{available_skins, SKINS_PATH};
+
+/// The range of note speeds that can be chosen.
+const NOTE_SPEED_RANGE: std::ops::RangeInclusive<f32> = 0.5..=4.0;
+
+/// The window sizes offered if the monitors can't be asked what they support.
+const FALLBACK_SIZES: [(u32, u32); 4] = [(1280, 720), (1600, 900), (1920, 1080), (2560, 1440)];
+
+/// A monitor the game can go fullscreen on.
+struct Monitor {
+    name: String,
+    /// The resolutions the monitor supports, biggest first
+    sizes: Vec<(u32, u32)>,
+}
+
+/// A screen for changing the settings, with a preview of the note field that shows the effect of
+/// the visual settings straight away.
+pub struct SettingsScreen {
+    background: Sprite,
+    preview: NotePreview,
+    /// Whether the game window is focused. The preview stops moving while it isn't.
+    focused: bool,
+    /// The drum input waiting for the player to press the key it should be mapped to, and which
+    /// player's input it is (0 or 1)
+    binding: Option<(usize, DrumInput)>,
+    exit: bool,
+    /// The file player data is exported to and imported from
+    data_path: String,
+    /// An import the player is being shown the changes of, before it's saved
+    pending_import: Option<PendingImport>,
+    /// The result of the last export or import
+    data_message: Option<String>,
+    monitors: Vec<Monitor>,
+    /// The skins in the skins folder
+    skins: Vec<String>,
+}
+
+impl SettingsScreen {
+    pub fn new(renderer: &mut Renderer, textures: &mut TextureCache) -> anyhow::Result<Self> {
+        let background = SpriteBuilder::new(textures.get(
+            &renderer.device,
+            &renderer.queue,
+            "song_select_bg.jpg",
+        )?)
+        .build(renderer);
+
+        Ok(Self {
+            background,
+            preview: NotePreview::new(renderer, textures)?,
+            focused: true,
+            binding: None,
+            exit: false,
+            data_path: DEFAULT_EXPORT_PATH.to_string(),
+            pending_import: None,
+            data_message: None,
+            monitors: monitors(renderer),
+            skins: available_skins(),
+        })
+    }
+
+    /// The window mode, monitor, resolution and present mode.
+    fn window_ui(&self, ui: &mut egui::Ui, visual: &mut VisualSettings) {
+        let monitor = visual
+            .monitor
+            .as_ref()
+            .and_then(|name| self.monitors.iter().find(|monitor| &monitor.name == name))
+            .or(self.monitors.first());
+        let sizes = monitor
+            .map(|monitor| &monitor.sizes[..])
+            .filter(|sizes| !sizes.is_empty())
+            .unwrap_or(&FALLBACK_SIZES);
+        let biggest = sizes.first().copied().unwrap_or(DEFAULT_WINDOW_SIZE);
+
+        egui::ComboBox::from_label("Window")
+            .selected_text(visual.resolution.name())
+            .show_ui(ui, |ui| {
+                for mode in [
+                    ResolutionState::BorderlessFullscreen,
+                    ResolutionState::Windowed(DEFAULT_WINDOW_SIZE.0, DEFAULT_WINDOW_SIZE.1),
+                    ResolutionState::Fullscreen(biggest.0, biggest.1),
+                ] {
+                    let selected =
+                        std::mem::discriminant(&mode) == std::mem::discriminant(&visual.resolution);
+
+                    if ui.selectable_label(selected, mode.name()).clicked() && !selected {
+                        visual.resolution = mode;
+                    }
+                }
+            })
+            .response
+            .on_hover_text(format!(
+                "{} switches between fullscreen and a window",
+                key_name(PhysicalKey::Code(KeyCode::F11))
+            ));
+
+        if let ResolutionState::Windowed(width, height)
+        | ResolutionState::Fullscreen(width, height) = &mut visual.resolution
+        {
+            egui::ComboBox::from_label("Resolution")
+                .selected_text(format!("{width}x{height}"))
+                .show_ui(ui, |ui| {
+                    for &(w, h) in sizes {
+                        if ui
+                            .selectable_label((*width, *height) == (w, h), format!("{w}x{h}"))
+                            .clicked()
+                        {
+                            (*width, *height) = (w, h);
+                        }
+                    }
+                });
+        }
+
+        if visual.resolution.is_fullscreen() && !self.monitors.is_empty() {
+            egui::ComboBox::from_label("Monitor")
+                .selected_text(visual.monitor.as_deref().unwrap_or("Current monitor"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut visual.monitor, None, "Current monitor");
+
+                    for monitor in &self.monitors {
+                        ui.selectable_value(
+                            &mut visual.monitor,
+                            Some(monitor.name.clone()),
+                            &monitor.name,
+                        );
+                    }
+                });
+        }
+
+        egui::ComboBox::from_label("Present mode")
+            .selected_text(visual.present_mode.name())
+            .show_ui(ui, |ui| {
+                for mode in PresentMode::ALL {
+                    ui.selectable_value(&mut visual.present_mode, mode, mode.name());
+                }
+            })
+            .response
+            .on_hover_text(
+                "Vsync never tears. Mailbox has less delay, but isn't supported everywhere. \
+                Immediate has the least delay, but might tear.",
+            );
+    }
+
+    /// A button for each drum input of both players showing the key it's mapped to, which waits
+    /// for a new key to be pressed when it's clicked.
+    fn key_mappings_ui(&mut self, ui: &mut egui::Ui, game: &mut GameSettings) {
+        egui::Grid::new("key mappings")
+            .show(ui, |ui| {
+                ui.label("");
+                ui.label("Player 1");
+                ui.label("Player 2");
+                ui.end_row();
+
+                for input in DrumInput::ALL {
+                    ui.label(input.name());
+
+                    for player in 0..2 {
+                        let text = if self.binding == Some((player, input)) {
+                            "Press a key...".to_string()
+                        } else {
+                            key_name(game.player_key_mappings(player).key(input))
+                        };
+
+                        if ui.button(text).clicked() {
+                            self.binding = Some((player, input));
+                        }
+                    }
+
+                    ui.end_row();
+                }
+            })
+            .response
+            .on_hover_text("Player 2's keys are only used when two people play together");
+
+        ui.horizontal(|ui| {
+            if self.binding.is_some() {
+                ui.label("Press Esc to cancel");
+            }
+
+            if ui.button("Default keys").clicked() {
+                game.key_mappings = KeyMap::default();
+                game.player2_key_mappings = KeyMap::player2();
+                self.binding = None;
+            }
+        });
+    }
+
+    /// The controls for exporting the player's scores, collections and per-chart settings, and
+    /// merging in ones exported from somewhere else.
+    fn player_data_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label(RichText::new("Player data").size(20.0).strong());
+
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.data_path);
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("Export")
+                .on_hover_text("Save your scores, collections and per-chart settings to the file")
+                .clicked()
+            {
+                self.data_message = Some(match export_player_data(&self.data_path) {
+                    Ok(()) => format!("Exported to {}", self.data_path),
+                    Err(e) => {
+                        log::error!("couldn't export player data: {e}");
+                        format!("Couldn't export: {e}")
+                    }
+                });
+            }
+
+            if ui
+                .button("Import")
+                .on_hover_text("Merge in data exported from another computer")
+                .clicked()
+            {
+                match PendingImport::preview(&self.data_path) {
+                    Ok(import) => {
+                        self.pending_import = Some(import);
+                        self.data_message = None;
+                    }
+                    Err(e) => {
+                        log::error!("couldn't read player data to import: {e}");
+                        self.data_message = Some(format!("Couldn't import: {e}"));
+                    }
+                }
+            }
+        });
+
+        if let Some(message) = &self.data_message {
+            ui.label(message);
+        }
+    }
+
+    /// Shows what importing the chosen file will change, and asks the player whether to go ahead.
+    fn import_preview_ui(&mut self, ctx: &egui::Context) {
+        let Some(import) = &self.pending_import else {
+            return;
+        };
+
+        let mut merge = false;
+        let mut cancel = false;
+
+        egui::Window::new("Import player data")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                if import.summary.is_empty() {
+                    ui.label("Everything in the file is already here, so nothing will change.");
+                } else {
+                    ui.label("Importing will merge the file with your data:");
+
+                    for line in import.summary.lines() {
+                        ui.label(format!("• {line}"));
+                    }
+
+                    ui.label(
+                        RichText::new("Nothing is removed, and your data is backed up first.")
+                            .weak(),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    merge = ui
+                        .add_enabled(!import.summary.is_empty(), egui::Button::new("Merge"))
+                        .clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        if cancel {
+            self.pending_import = None;
+        } else if merge {
+            let import = self.pending_import.take().unwrap();
+
+            self.data_message = Some(match import.apply() {
+                Ok(backup) => format!(
+                    "Imported! Your old data was backed up to {}",
+                    backup.display()
+                ),
+                Err(e) => {
+                    log::error!("couldn't import player data: {e}");
+                    format!("Couldn't import: {e}")
+                }
+            });
+        }
+    }
+}
+
+/// The monitors the window could go fullscreen on, and the resolutions they support.
+fn monitors(renderer: &Renderer) -> Vec<Monitor> {
+    let Some(window) = renderer.window() else {
+        return Vec::new();
+    };
+
+    window
+        .available_monitors()
+        .filter_map(|monitor| {
+            let name = monitor.name()?;
+            let mut sizes: Vec<_> = monitor
+                .video_modes()
+                .map(|mode| (mode.size().width, mode.size().height))
+                .collect();
+            sizes.sort_unstable_by(|a, b| b.cmp(a));
+            sizes.dedup();
+
+            Some(Monitor { name, sizes })
+        })
+        .collect()
+}
+
+impl GameState for SettingsScreen {
+    fn update(&mut self, ctx: &mut Context, delta_time: f32) -> StateTransition {
+        if self.exit {
+            if let Err(e) = save_settings() {
+                log::error!("couldn't save settings: {e}");
+            }
+
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
+            return StateTransition::Pop;
+        }
+
+        let delta_time = if self.focused { delta_time } else { 0.0 };
+        if let Err(e) = self.preview.update(delta_time, ctx.renderer, ctx.textures) {
+            log::error!("couldn't update note preview: {e}");
+        }
+
+        StateTransition::Continue
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, audio: &mut AudioManager) {
+        // Only write to the settings when something actually changes, since every write means
+        // anything depending on the settings (like the preview) has to be rebuilt.
+        let mut visual = settings().visual.clone();
+        let mut game = settings().game.clone();
+        let audio_status = audio.backend_mut().status();
+
+        egui::Window::new("Settings")
+            .fixed_pos(egui::pos2(480.0, 600.0))
+            .show(&ctx, |ui| {
+                ui.label(RichText::new("Gameplay").size(20.0).strong());
+
+                self.key_mappings_ui(ui, &mut game);
+
+                ui.add(
+                    egui::Slider::new(&mut visual.note_speed, NOTE_SPEED_RANGE)
+                        .step_by(0.1)
+                        .text("Note speed"),
+                );
+
+                offset_profile_combo_box(
+                    ui,
+                    &game.offset_profiles,
+                    &mut game.active_offset_profile,
+                )
+                .on_hover_text("Offsets for different audio setups, e.g. bluetooth headphones");
+
+                ui.horizontal(|ui| {
+                    if ui.button("New profile").clicked() {
+                        let profile = game.new_offset_profile();
+                        game.switch_offset_profile(Some(profile), None);
+                    }
+
+                    if let Some(profile) = game.active_offset_profile {
+                        if ui.button("Delete profile").clicked() {
+                            game.remove_offset_profile(profile);
+                        }
+                    }
+                });
+
+                if let Some(profile) = game
+                    .active_offset_profile
+                    .and_then(|i| game.offset_profiles.get_mut(i))
+                {
+                    ui.horizontal(|ui| {
+                        ui.label("Profile name:");
+                        ui.text_edit_singleline(&mut profile.name);
+                    });
+                }
+
+                ui.add(
+                    egui::Slider::new(game.note_offset_mut(), -200.0..=300.0)
+                        .step_by(1.0)
+                        .text("Note offset (ms)"),
+                );
+
+                ui.checkbox(&mut game.preserve_pitch, "Keep pitch at practice rates")
+                    .on_hover_text(
+                        "Stretch songs played slower or faster so they don't change pitch",
+                    );
+
+                ui.add(
+                    egui::Slider::new(&mut game.mash_warning_rate, 15.0..=60.0)
+                        .step_by(1.0)
+                        .text("Stuck key warning (hits/s)"),
+                )
+                .on_hover_text(
+                    "Warn on the results screen if a drumroll is hit this fast with a single key",
+                );
+
+                ui.add(
+                    egui::Slider::new(&mut game.outro_delay, 0.0..=10.0)
+                        .step_by(0.5)
+                        .text("Outro (s)"),
+                )
+                .on_hover_text("How long the song plays on after the last note before the results");
+
+                egui::ComboBox::from_label("Rules")
+                    .selected_text(game.rules_preset.name())
+                    .show_ui(ui, |ui| {
+                        for preset in RulesPreset::ALL {
+                            ui.selectable_value(&mut game.rules_preset, preset, preset.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "How notes, drumrolls and balloons count towards the score and gauge",
+                    );
+
+                if game.rules_preset == RulesPreset::Custom {
+                    let rules = &mut game.custom_rules;
+
+                    egui::Grid::new("custom rules").show(ui, |ui| {
+                        ui.label("Note scoring");
+                        egui::ComboBox::from_id_source("note scoring")
+                            .selected_text(rules.note_scoring.name())
+                            .show_ui(ui, |ui| {
+                                for scoring in NoteScoring::ALL {
+                                    ui.selectable_value(
+                                        &mut rules.note_scoring,
+                                        scoring,
+                                        scoring.name(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        for (label, value) in [
+                            ("Drumroll hit score", &mut rules.roll_hit_score),
+                            ("Big drumroll hit score", &mut rules.big_roll_hit_score),
+                            ("Balloon hit score", &mut rules.balloon_hit_score),
+                            ("Balloon pop score", &mut rules.balloon_pop_score),
+                        ] {
+                            ui.label(label);
+                            ui.add(egui::DragValue::new(value).range(0..=10000));
+                            ui.end_row();
+                        }
+
+                        for (label, value) in [
+                            ("Drumroll hit gauge", &mut rules.roll_hit_health),
+                            ("Balloon pop gauge", &mut rules.balloon_pop_health),
+                        ] {
+                            ui.label(label);
+                            ui.add(egui::DragValue::new(value).range(0..=1000));
+                            ui.end_row();
+                        }
+                    });
+                }
+
+                egui::ComboBox::from_label("When the gauge runs out")
+                    .selected_text(game.fail_behaviour.name())
+                    .show_ui(ui, |ui| {
+                        for behaviour in FailBehaviour::ALL {
+                            ui.selectable_value(
+                                &mut game.fail_behaviour,
+                                behaviour,
+                                behaviour.name(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Sudden death fails the stage as soon as a bad or a miss empties the gauge",
+                    );
+
+                ui.horizontal(|ui| {
+                    ui.label("Songs folder:");
+                    ui.text_edit_singleline(&mut game.songs_dir);
+                })
+                .response
+                .on_hover_text("Used the next time the songs are scanned");
+
+                #[cfg(feature = "discord")]
+                ui.checkbox(
+                    &mut game.discord_presence,
+                    "Show what I'm playing on Discord",
+                );
+
+                ui.add_space(10.0);
+                ui.label(RichText::new("Audio").size(20.0).strong());
+
+                ui.add(percentage_slider(&mut game.master_volume, "Master volume"));
+                ui.add(percentage_slider(&mut game.music_volume, "Music volume"))
+                    .on_hover_text("Songs and the menu music");
+                ui.add(percentage_slider(
+                    &mut game.effects_volume,
+                    "Sound effect volume",
+                ));
+
+                ui.collapsing("Drum sounds", |ui| {
+                    let volumes = &mut game.hit_sound_volumes;
+
+                    for (value, label) in [
+                        (&mut volumes.don, "Don"),
+                        (&mut volumes.kat, "Kat"),
+                        (&mut volumes.big_don, "Big don"),
+                        (&mut volumes.big_kat, "Big kat"),
+                        (&mut volumes.balloon_pop, "Balloon pop"),
+                    ] {
+                        ui.add(percentage_slider(value, label));
+                    }
+                });
+
+                let buffer_size_text = |size: Option<u32>| match size {
+                    Some(frames) => format!("{frames} frames"),
+                    None => "Device default".to_string(),
+                };
+
+                egui::ComboBox::from_label("Buffer size")
+                    .selected_text(buffer_size_text(game.audio_buffer_size))
+                    .show_ui(ui, |ui| {
+                        for size in std::iter::once(None).chain(BUFFER_SIZES.map(Some)) {
+                            ui.selectable_value(
+                                &mut game.audio_buffer_size,
+                                size,
+                                buffer_size_text(size),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Smaller buffers make the audio play sooner, but might make it crackle",
+                    );
+
+                ui.label(format!("Current {}", audio_status.summary()));
+                if audio_status.requested_size_unsupported {
+                    ui.label(
+                        RichText::new("The audio device doesn't support this buffer size")
+                            .color(egui::Color32::YELLOW),
+                    );
+                }
+
+                ui.add_space(10.0);
+                ui.label(RichText::new("Display").size(20.0).strong());
+                self.window_ui(ui, &mut visual);
+
+                egui::ComboBox::from_label("Skin")
+                    .selected_text(visual.skin.as_deref().unwrap_or("Default"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut visual.skin, None, "Default");
+
+                        for skin in &self.skins {
+                            ui.selectable_value(&mut visual.skin, Some(skin.clone()), skin);
+                        }
+                    })
+                    .response
+                    .on_hover_text(format!(
+                        "Skins go in the \"{SKINS_PATH}\" folder. Fonts change the next time the \
+                        game is started."
+                    ));
+
+                let title_language = game.title_language.as_deref().and_then(|language| {
+                    TITLE_LANGUAGES
+                        .iter()
+                        .find(|(code, _)| code.eq_ignore_ascii_case(language))
+                });
+                egui::ComboBox::from_label("Song titles")
+                    .selected_text(title_language.map_or("As written", |(_, name)| name))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut game.title_language, None, "As written");
+
+                        for (code, name) in TITLE_LANGUAGES {
+                            ui.selectable_value(
+                                &mut game.title_language,
+                                Some(code.to_lowercase()),
+                                name,
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text("Songs whose charts have their title in this language show it");
+                ui.horizontal(|ui| {
+                    ui.label("Judgements:");
+                    ui.radio_value(&mut visual.judgement_style, JudgementStyle::Text, "Text");
+                    ui.radio_value(&mut visual.judgement_style, JudgementStyle::Burst, "Burst");
+                });
+                ui.checkbox(&mut visual.timing_meter, "Timing meter");
+                ui.checkbox(&mut visual.mirror_playfield, "Mirror playfield")
+                    .on_hover_text(
+                        "Notes travel from left to right, towards a receptacle on the right",
+                    );
+                ui.add(percentage_slider(
+                    &mut visual.background_dim,
+                    "Background dim",
+                ))
+                .on_hover_text("Can be changed for each chart from the pause menu");
+                ui.add(percentage_slider(&mut visual.hud_opacity, "HUD opacity"))
+                    .on_hover_text("Can be changed for each chart from the pause menu");
+                ui.checkbox(&mut visual.streamer_mode, "Streamer mode");
+                ui.checkbox(&mut visual.streamer_readout, "Streamer mode combo readout");
+
+                ui.add_space(10.0);
+                self.player_data_ui(ui);
+
+                ui.add_space(10.0);
+
+                if ui.button("Back").clicked() {
+                    self.exit = true;
+                }
+            });
+
+        self.import_preview_ui(&ctx);
+
+        let changed = {
+            let current = settings();
+            visual != current.visual || game != current.game
+        };
+
+        if changed {
+            let mut settings = settings_mut();
+            settings.visual = visual;
+            settings.game = game;
+        }
+    }
+
+    fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+        ctx.render(&self.background);
+        self.preview.render(ctx);
+    }
+
+    fn handle_event(&mut self, _ctx: &mut Context, event: &WindowEvent) {
+        match event {
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+
+                // The key that was about to be pressed went to some other window
+                if !focused {
+                    self.binding = None;
+                }
+            }
+
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed && !event.repeat =>
+            {
+                let Some((player, input)) = self.binding.take() else {
+                    return;
+                };
+
+                // Escape is kept for leaving menus, so it cancels instead of being bound
+                if event.physical_key != PhysicalKey::Code(KeyCode::Escape) {
+                    settings_mut()
+                        .game
+                        .bind_key(player, input, event.physical_key);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn recreate_gpu_resources(&mut self, ctx: &mut Context) -> bool {
+        match SettingsScreen::new(ctx.renderer, ctx.textures) {
+            Ok(screen) => {
+                *self = screen;
+                true
+            }
+            Err(e) => {
+                log::error!("couldn't recreate settings screen: {e}");
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! A record of the metadata of every song found the last time the songs folder was scanned, so
+//! that scanning it again only has to read the charts that have changed since.
+//!
+//! Only songs read from TJA files are kept in the index. A song found in it is given back with
+//! only its metadata (see [Song::metadata_only]), and its charts are read when it's played.
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::notechart_parser::{chart_hash, Difficulty, LocalisedTitle, Song, SongSide};
+
+/// The file the song index is saved to.
+pub const SONG_INDEX_PATH: &str = "song_index.toml";
+
+/// The version of the index format. This should be bumped whenever what's stored would come out
+/// differently for the same chart (e.g. the difficulty estimate or the chart hash changes), so
+/// that every chart is read again.
+pub const SONG_INDEX_VERSION: u32 = 3;
+
+/// What a chart file looked like when it was read, for telling whether it's changed since.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    /// When the file was last modified, in nanoseconds since the unix epoch.
+    pub modified: i64,
+    pub size: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as i64);
+
+        Ok(Self {
+            modified,
+            size: metadata.len(),
+        })
+    }
+}
+
+/// The metadata of one course of an indexed song.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexedCourse {
+    /// Which difficulty the course is (an index into [Song::difficulties]).
+    pub difficulty: usize,
+    pub star_level: u8,
+    pub estimated_level: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes_designer: Option<String>,
+    /// The course's [chart_hash], for finding duplicate songs without reading their charts.
+    pub hash: String,
+}
+
+/// The metadata of a song, as it's stored in the index.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexedSong {
+    pub stamp: FileStamp,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub localised_titles: BTreeMap<String, LocalisedTitle>,
+    /// The path of the song's audio, relative to the game (not the chart).
+    pub audio_filename: String,
+    pub bpm: f32,
+    pub offset: f32,
+    pub demostart: f32,
+    pub song_volume: f32,
+    pub effects_volume: f32,
+    pub side: SongSide,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    pub courses: Vec<IndexedCourse>,
+}
+
+impl IndexedSong {
+    /// Records the metadata of a song that's just been read from a chart file with the given
+    /// stamp.
+    pub fn new(song: &Song, stamp: FileStamp) -> Self {
+        let courses = song
+            .difficulties
+            .iter()
+            .enumerate()
+            .filter_map(|(i, d)| d.as_ref().map(|d| (i, d)))
+            .map(|(difficulty, d)| IndexedCourse {
+                difficulty,
+                star_level: d.star_level,
+                estimated_level: d.estimated_level,
+                notes_designer: d.notes_designer.clone(),
+                hash: chart_hash(&d.chart).to_string(),
+            })
+            .collect();
+
+        Self {
+            stamp,
+            title: song.title.clone(),
+            subtitle: song.subtitle.clone(),
+            localised_titles: song.localised_titles.clone(),
+            audio_filename: song.audio_filename.clone(),
+            bpm: song.bpm,
+            offset: song.offset,
+            demostart: song.demostart,
+            song_volume: song.song_volume,
+            effects_volume: song.effects_volume,
+            side: song.side,
+            genre: song.genre.clone(),
+            courses,
+        }
+    }
+
+    /// The song, with only its metadata (see [Song::metadata_only]).
+    pub fn to_song(&self, chart_path: &Path) -> Song {
+        let mut song = Song {
+            title: self.title.clone(),
+            subtitle: self.subtitle.clone(),
+            localised_titles: self.localised_titles.clone(),
+            audio_filename: self.audio_filename.clone(),
+            bpm: self.bpm,
+            offset: self.offset,
+            demostart: self.demostart,
+            song_volume: self.song_volume,
+            effects_volume: self.effects_volume,
+            side: self.side,
+            genre: self.genre.clone(),
+            chart_path: Some(chart_path.to_path_buf()),
+            metadata_only: true,
+            chart_hashes: self.hashes(),
+            ..Default::default()
+        };
+
+        for course in self.courses.iter() {
+            if let Some(difficulty) = song.difficulties.get_mut(course.difficulty) {
+                *difficulty = Some(Difficulty {
+                    star_level: course.star_level,
+                    estimated_level: course.estimated_level,
+                    notes_designer: course.notes_designer.clone(),
+                    chart: Default::default(),
+                    player_charts: None,
+                });
+            }
+        }
+
+        song
+    }
+
+    /// The hashes of every difficulty of the song, for finding duplicates.
+    pub fn hashes(&self) -> Vec<Option<String>> {
+        let mut hashes = vec![None; 5];
+
+        for course in self.courses.iter() {
+            if let Some(hash) = hashes.get_mut(course.difficulty) {
+                *hash = Some(course.hash.clone());
+            }
+        }
+
+        hashes
+    }
+}
+
+/// Every indexed song, stored by the path of its chart file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SongIndex {
+    pub version: u32,
+    #[serde(default)]
+    songs: BTreeMap<String, IndexedSong>,
+}
+
+impl Default for SongIndex {
+    fn default() -> Self {
+        Self {
+            version: SONG_INDEX_VERSION,
+            songs: BTreeMap::new(),
+        }
+    }
+}
+
+impl SongIndex {
+    /// Reads the song index, starting with an empty one if it doesn't exist yet or was saved by a
+    /// different version of the game.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let index: Self = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        if index.version == SONG_INDEX_VERSION {
+            Ok(index)
+        } else {
+            log::info!("the song index is from a different version, so every song will be read");
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The indexed song for a chart file, if the file hasn't changed since it was indexed.
+    pub fn get(&self, chart_path: &Path, stamp: FileStamp) -> Option<&IndexedSong> {
+        self.songs
+            .get(&*chart_path.to_string_lossy())
+            .filter(|song| song.stamp == stamp)
+    }
+
+    pub fn insert(&mut self, chart_path: &Path, song: IndexedSong) {
+        self.songs
+            .insert(chart_path.to_string_lossy().into_owned(), song);
+    }
+
+    /// Forgets every song whose chart file isn't one of the given ones, e.g. because it's been
+    /// deleted.
+    pub fn retain(&mut self, chart_paths: &HashSet<PathBuf>) {
+        self.songs
+            .retain(|path, _| chart_paths.contains(Path::new(path)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn indexed(title: &str, modified: i64) -> IndexedSong {
+        let song = Song {
+            title: title.to_string(),
+            audio_filename: "songs/a/song.ogg".to_string(),
+            ..Default::default()
+        };
+
+        IndexedSong::new(&song, FileStamp { modified, size: 10 })
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("taiko-song-index-{}", std::process::id()));
+        assert_eq!(SongIndex::load(&path).unwrap(), SongIndex::default());
+
+        let chart = Path::new("songs/a/a.tja");
+        let mut index = SongIndex::default();
+        index.insert(chart, indexed("A", 100));
+        index.save(&path).unwrap();
+
+        let loaded = SongIndex::load(&path).unwrap();
+        assert_eq!(loaded, index);
+
+        // Charts that have changed since they were indexed aren't found
+        let stamp = |modified| FileStamp { modified, size: 10 };
+        assert_eq!(loaded.get(chart, stamp(100)).unwrap().title, "A");
+        assert!(loaded.get(chart, stamp(101)).is_none());
+
+        // An index from another version is thrown away
+        SongIndex {
+            version: SONG_INDEX_VERSION + 1,
+            ..index
+        }
+        .save(&path)
+        .unwrap();
+        let loaded = SongIndex::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, SongIndex::default());
+    }
+}
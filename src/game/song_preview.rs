@@ -0,0 +1,102 @@
+//! Playing a bit of the selected song in song select, from the chart's `DEMOSTART` onwards.
+//!
+//! The song is streamed from its file rather than loaded all at once, so that moving quickly
+//! through the song list doesn't hold anything up. When the selection moves on, the old song fades
+//! out while the new one fades in.
+use kira::{
+    sound::{
+        streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
+        FromFileError,
+    },
+    track::TrackHandle,
+    tween::Tween,
+};
+use lazy_static::lazy_static;
+
+use crate::{audio::AudioManager, notechart_parser::Song};
+
+type SongHandle = StreamingSoundHandle<FromFileError>;
+
+lazy_static! {
+    static ref IN_TWEEN: Tween = Tween {
+        start_time: kira::StartTime::Immediate,
+        duration: std::time::Duration::from_secs_f32(0.2),
+        easing: kira::tween::Easing::OutPowi(2),
+    };
+    static ref OUT_TWEEN: Tween = Tween {
+        start_time: kira::StartTime::Immediate,
+        duration: std::time::Duration::from_secs_f32(0.2),
+        easing: kira::tween::Easing::InPowi(2),
+    };
+}
+
+/// The song preview playing in song select, if there is one.
+#[derive(Default)]
+pub struct SongPreview {
+    handle: Option<SongHandle>,
+    /// The id of the song being previewed
+    song_id: Option<usize>,
+}
+
+impl SongPreview {
+    /// Cross-fades to a preview of the given song, unless it's already the one playing. The song
+    /// loops back round to its `DEMOSTART` when it ends.
+    pub fn play(
+        &mut self,
+        audio: &mut AudioManager,
+        track: &TrackHandle,
+        song_id: usize,
+        song: &Song,
+    ) {
+        if self.song_id == Some(song_id) {
+            return;
+        }
+
+        // The old preview keeps fading out after its handle is dropped
+        self.stop();
+        self.song_id = Some(song_id);
+        self.handle = match start_preview(audio, track, song) {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                log::error!("couldn't play song preview: {e}");
+                None
+            }
+        };
+    }
+
+    /// Fades the preview out.
+    pub fn stop(&mut self) {
+        self.stop_with(*OUT_TWEEN);
+    }
+
+    /// Stops the preview straight away, e.g. because the song is about to be played properly.
+    pub fn stop_now(&mut self) {
+        self.stop_with(Tween::default());
+    }
+
+    fn stop_with(&mut self, tween: Tween) {
+        if let Some(mut handle) = self.handle.take() {
+            if let Err(e) = handle.stop(tween) {
+                log::error!("couldn't stop song preview: {e}");
+            }
+        }
+
+        self.song_id = None;
+    }
+}
+
+fn start_preview(
+    audio: &mut AudioManager,
+    track: &TrackHandle,
+    song: &Song,
+) -> anyhow::Result<SongHandle> {
+    let settings = StreamingSoundSettings::default()
+        .playback_region(song.demostart as f64..)
+        .fade_in_tween(Some(*IN_TWEEN))
+        .loop_region(song.demostart as f64..)
+        .output_destination(track);
+
+    let data = StreamingSoundData::from_file(&song.audio_filename, settings)?;
+
+    Ok(audio.play(data)?)
+}
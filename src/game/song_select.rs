@@ -1,105 +1,423 @@
-use std::{io, path::Path, rc::Rc};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use crate::{
+    audio::AudioManager,
+    difficulty::DIFFICULTIES,
+    game::clear_chance::{judged_level, ClearModel, RECENT_PLAYS},
+    game::collections::{song_key, Collections, COLLECTIONS_PATH},
     game::credits::CreditsScreen,
-    notechart_parser::{parse_tja_file, Song},
+    game::difficulty_select::{
+        crown_colour, initial_difficulty, step_difficulty, DifficultySelect,
+    },
+    game::editor::Editor,
+    game::library::{
+        load_charts, reload_course, scan_library, ImportReport, ReloadError, IMPORT_REPORT_PATH,
+    },
+    game::loading_screen::{LoadingScreen, StartSong},
+    game::replay::{Replay, REPLAYS_DIR},
+    game::scores::{scores_revision, BestRecord, Crown, ScoreDatabase, SCORES_PATH},
+    game::search::SearchQuery,
+    game::song_preview::SongPreview,
+    game::tasks::{Task, Tasks},
+    game::time_stretch::{cached_stretch, stretch_song},
+    notechart_parser::{chart_hash, Song, TJAParseError, TJAParseWarning},
     render::texture::SpriteBuilder,
+    settings::{save_settings, settings, settings_mut},
 };
 
 use crate::render::{texture::Sprite, Renderer};
 
 use egui::RichText;
-use kira::{
-    manager::AudioManager,
-    sound::{
-        static_sound::{StaticSoundData, StaticSoundSettings},
-        streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
-        FromFileError,
-    },
-    tween::Tween,
-};
-use lazy_static::lazy_static;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
 
 use crate::game::{
-    taiko_mode::TaikoMode, Context, GameState, RenderContext, StateTransition, TextureCache,
+    taiko_mode::{Mods, ScoreInt, TaikoMode, VersusMode},
+    ui_elements::{marker_y, move_item, offset_profile_combo_box, DifficultyBadges, DragReorder},
+    Context, GameState, RenderContext, SoundEffect, StateTransition, TextureCache,
 };
 
-type SongHandle = StreamingSoundHandle<FromFileError>;
+/// Changes how fast a song plays the simple way, which also changes its pitch.
+fn change_playback_rate(sound_data: &StaticSoundData, rate: f32) -> StaticSoundData {
+    sound_data.with_modified_settings(|settings| settings.playback_rate(rate as f64))
+}
 
-lazy_static! {
-    static ref IN_TWEEN: Tween = Tween {
-        start_time: kira::StartTime::Immediate,
-        duration: std::time::Duration::from_secs_f32(0.2),
-        easing: kira::tween::Easing::OutPowi(2),
-    };
-    static ref OUT_TWEEN: Tween = Tween {
-        start_time: kira::StartTime::Immediate,
-        duration: std::time::Duration::from_secs_f32(0.2),
-        easing: kira::tween::Easing::InPowi(2),
+/// Starts scanning the songs folder chosen in the settings.
+fn spawn_scan(tasks: &mut Tasks) -> Task<(Vec<Song>, ImportReport)> {
+    let songs_dir = settings().game.songs_dir.clone();
+    tasks.spawn("scan songs", move |task| scan_library(songs_dir, task))
+}
+
+/// The rates songs can be practised at.
+const PRACTICE_RATE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=1.5;
+
+/// The speeds songs can be played at, chosen on the difficulty select. Unlike practising at a
+/// different rate, plays at these speeds are recorded, along with the speed.
+pub const SPEED_MODS: [f32; 6] = [0.75, 1.0, 1.25, 1.5, 1.75, 2.0];
+
+/// The next speed in [SPEED_MODS] after `speed` (or before it, if `faster` isn't set), or `speed`
+/// itself if it's already the fastest (or slowest).
+fn step_speed(speed: f32, faster: bool) -> f32 {
+    let next = if faster {
+        SPEED_MODS.iter().find(|&&mod_speed| mod_speed > speed)
+    } else {
+        SPEED_MODS
+            .iter()
+            .rev()
+            .find(|&&mod_speed| mod_speed < speed)
     };
+
+    next.copied().unwrap_or(speed)
 }
 
-// Potentially this could go in config but i'm not sure that's necessary
-const SONGS_DIR: &str = "songs";
+/// How long the pointer has to rest on a song before its preview starts, in seconds. This stops
+/// every song the pointer passes over from starting to play.
+const PREVIEW_DEBOUNCE: f32 = 0.4;
 
-pub struct SongSelect {
-    songs: Vec<Song>,
-    selected: Option<usize>,
-    difficulty: usize,
-    song_preview_handle: Option<SongHandle>,
-    bg_sprite: Rc<Sprite>,
-    go_to_credits: bool,
-    exit: bool,
-    go_to_song: Option<(usize, usize)>,
+/// How long toast messages stay on screen, in seconds.
+const TOAST_DURATION: f32 = 2.5;
+
+/// The genre songs are grouped under when they don't have one, and aren't in a folder of their own
+/// in the songs folder either.
+const OTHER_GENRE: &str = "Other";
+
+/// The orders the song list can be sorted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SongSort {
+    #[default]
+    Title,
+    /// By the level of each song's hardest course, easiest first
+    Level,
+    /// The songs played most recently first
+    RecentlyPlayed,
+    /// By the best score on each song's hardest course, highest first
+    BestScore,
 }
 
-fn read_song_list_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Song>> {
-    let dir = std::fs::read_dir(path)?;
-    let mut res = Vec::new();
+impl SongSort {
+    const ALL: [SongSort; 4] = [
+        SongSort::Title,
+        SongSort::Level,
+        SongSort::RecentlyPlayed,
+        SongSort::BestScore,
+    ];
 
-    for file in dir.flatten() {
-        if file.file_type().map(|ty| ty.is_dir()).unwrap_or(false) {
-            let subdir_path = file.path();
+    fn name(self) -> &'static str {
+        match self {
+            SongSort::Title => "Title",
+            SongSort::Level => "Level",
+            SongSort::RecentlyPlayed => "Recently played",
+            SongSort::BestScore => "Best score",
+        }
+    }
+}
 
-            match read_song_dir(&subdir_path) {
-                Ok(song) => res.push(song),
-                Err(e) => log::error!(
-                    "error encountered while trying to read song at directory {}: {e}",
-                    subdir_path.to_string_lossy()
-                ),
-            }
+/// Some of the songs in the song list, shown together under a header that can be collapsed.
+#[derive(Debug, Clone, PartialEq)]
+struct SongGroup {
+    /// The genre of the songs. This is None if the songs aren't grouped, in which case there's only
+    /// one group, and it doesn't have a header.
+    genre: Option<String>,
+    songs: Vec<usize>,
+}
+
+/// The genre a song is grouped under: the one its chart gives, or else the name of the folder its
+/// own folder is in.
+fn song_genre(song: &Song, songs_dir: &Path) -> String {
+    let key = song_key(song);
+    let folder = key
+        .parent()
+        .and_then(Path::parent)
+        .filter(|folder| *folder != songs_dir)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned());
+
+    song.genre
+        .clone()
+        .or(folder)
+        .unwrap_or_else(|| OTHER_GENRE.to_string())
+}
+
+/// The difficulty of a song's hardest course, and its level.
+fn hardest_course(song: &Song) -> Option<(usize, u8)> {
+    song.difficulties
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, difficulty)| difficulty.as_ref().map(|d| (i, d.star_level)))
+}
+
+/// Sorts the songs that match the search into the order they're listed in, grouping them by genre
+/// if asked to. The genres are in alphabetical order, apart from [OTHER_GENRE], which comes last.
+///
+/// `best_score` and `last_played` give the best score on a song's hardest course and when it was
+/// last played, if it's been played. They're only called when the songs are sorted by them.
+fn song_groups(
+    songs: &[Song],
+    sort: SongSort,
+    group_by_genre: bool,
+    songs_dir: &Path,
+    search: &SearchQuery,
+    best_score: impl Fn(usize) -> Option<ScoreInt>,
+    last_played: impl Fn(usize) -> Option<i64>,
+) -> Vec<SongGroup> {
+    let mut ids: Vec<usize> = (0..songs.len())
+        .filter(|&id| search.matches(&songs[id]))
+        .collect();
+    // Titles are sorted as they're shown
+    let language = settings().game.title_language.clone();
+    let title = |id: &usize| songs[*id].title_in(language.as_deref()).to_lowercase();
+
+    // Songs that haven't been played come after the ones that have
+    match sort {
+        SongSort::Title => ids.sort_by_cached_key(title),
+        SongSort::Level => ids.sort_by_cached_key(|id| {
+            let level = hardest_course(&songs[*id]).map(|(_, level)| level);
+            (level, title(id))
+        }),
+        SongSort::RecentlyPlayed => {
+            ids.sort_by_cached_key(|id| (Reverse(last_played(*id)), title(id)))
         }
+        SongSort::BestScore => ids.sort_by_cached_key(|id| (Reverse(best_score(*id)), title(id))),
+    }
+
+    if !group_by_genre {
+        return vec![SongGroup {
+            genre: None,
+            songs: ids,
+        }];
     }
 
-    Ok(res)
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for id in ids {
+        groups
+            .entry(song_genre(&songs[id], songs_dir))
+            .or_default()
+            .push(id);
+    }
+
+    let other = groups.remove_entry(OTHER_GENRE);
+    groups
+        .into_iter()
+        .chain(other)
+        .map(|(genre, songs)| SongGroup {
+            genre: Some(genre),
+            songs,
+        })
+        .collect()
 }
 
-fn read_song_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
-    let dir_name = path.as_ref().file_name().ok_or(io::Error::new(
-        io::ErrorKind::InvalidData,
-        "couldn't read directory name",
-    ))?;
+/// Opens a folder in the system's file browser.
+fn show_folder(path: &Path) -> std::io::Result<()> {
+    let program = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+
+    std::process::Command::new(program).arg(path).spawn()?;
+    Ok(())
+}
 
-    let tja_file_path = path
-        .as_ref()
-        .join(format!("{}.tja", dir_name.to_string_lossy()));
-    let tja_file_contents = std::fs::read_to_string(tja_file_path)?;
+/// The songs folder being scanned in the background.
+struct LibraryScan {
+    task: Task<(Vec<Song>, ImportReport)>,
+    /// Whether the player asked for the scan, rather than it being the first one
+    rescan: bool,
+}
 
-    let mut song = parse_tja_file(&tja_file_contents)?;
+/// Loads a song's audio, ready to be played at the given rate. If it's being played at a different
+/// rate and `preserve_pitch` is set, it's stretched, which can take a while.
+fn load_song_task(
+    tasks: &mut Tasks,
+    path: String,
+    rate: f32,
+    preserve_pitch: bool,
+) -> Task<StaticSoundData> {
+    tasks.spawn("load song", move |task| {
+        if rate != 1.0 && preserve_pitch {
+            if let Some(stretched) = cached_stretch(&path, rate) {
+                return Ok(stretched);
+            }
+        }
 
-    let audio_filename = path
-        .as_ref()
-        .join(&song.audio_filename)
-        .to_string_lossy()
-        .into_owned();
+        task.set_progress(0.0, "Loading audio");
+        let sound_data = StaticSoundData::from_file(&path, StaticSoundSettings::default())?;
 
-    song.audio_filename = audio_filename;
-    Ok(song)
+        if rate == 1.0 {
+            Ok(sound_data)
+        } else if !preserve_pitch {
+            Ok(change_playback_rate(&sound_data, rate))
+        } else {
+            task.check_cancelled()?;
+            Ok(stretch_song(&path, &sound_data, rate, |progress| {
+                task.set_progress(progress, format!("Stretching to {rate:.2}x"))
+            }))
+        }
+    })
+}
+
+/// The ways a song can be played, picked on the difficulty select.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct PlayOptions {
+    /// Whether the song is played in practice mode (see [TaikoMode::with_practice])
+    practice: bool,
+    /// Whether the game plays the song by itself (see [TaikoMode::with_autoplay])
+    autoplay: bool,
+    /// Whether the song is played by two players together (see [VersusMode])
+    versus: bool,
+    /// The difficulty whose notes are shown as ghosts while playing, if any (see
+    /// [TaikoMode::with_ghost_notes])
+    ghost_difficulty: Option<usize>,
+    /// The mods the song is played with (see [TaikoMode::with_mods]). Shuffling gets a new seed
+    /// each time a song is played.
+    mods: Mods,
+}
+
+impl PlayOptions {
+    /// The options that apply to playing the given difficulty. Watching a replay plays it back
+    /// the way it was played, so none of them apply, and two players ignore everything else.
+    /// Ghosts of the chart being played would just sit on top of its notes, so they're left out.
+    fn for_play(&self, difficulty: usize, replay: bool) -> PlayOptions {
+        if replay {
+            return PlayOptions::default();
+        }
+
+        if self.versus {
+            return PlayOptions {
+                versus: true,
+                ..Default::default()
+            };
+        }
+
+        PlayOptions {
+            ghost_difficulty: self.ghost_difficulty.filter(|&ghost| ghost != difficulty),
+            ..*self
+        }
+    }
+
+    /// Sets up a scene to be played with these options.
+    fn set_up(self, mut scene: TaikoMode, song: &Song, ctx: &mut Context) -> TaikoMode {
+        scene = scene.with_mods(self.mods, ctx.renderer, ctx.textures);
+
+        if self.autoplay {
+            scene = scene.with_autoplay();
+        }
+
+        if self.practice {
+            scene = scene.with_practice(ctx.renderer);
+        }
+
+        if let Some(ghost) = self.ghost_difficulty {
+            scene = scene.with_ghost_notes(song, ghost, ctx.renderer, ctx.textures);
+        }
+
+        scene
+    }
+}
+
+/// A course that couldn't be read from its chart file when it was about to be played.
+struct LoadFailure {
+    song_id: usize,
+    difficulty: usize,
+    error: TJAParseError,
+    /// The part of the chart the error is in
+    excerpt: String,
+    /// Whether the chart was already being read in lenient mode
+    lenient: bool,
+}
+
+pub struct SongSelect {
+    songs: Vec<Song>,
+    selected: Option<usize>,
+    difficulty: usize,
+    preview: SongPreview,
+    /// The song under the pointer, and how long it's been there
+    hovered: Option<(usize, f32)>,
+    /// Whether a different song was chosen in the song list, so the cursor sound should play
+    cursor_moved: bool,
+    bg_sprite: Rc<Sprite>,
+    difficulty_badges: DifficultyBadges,
+    /// The difficulty select for the selected song, which is open whenever a song is selected
+    difficulty_select: DifficultySelect,
+    go_to_credits: bool,
+    exit: bool,
+    go_to_song: Option<(usize, usize)>,
+    /// Whether the song about to be played should be read in lenient mode
+    lenient: bool,
+    /// The song the player tried to play, if its chart couldn't be read
+    load_failure: Option<LoadFailure>,
+    /// The song and difficulty to open in the chart editor
+    edit_song: Option<(usize, usize)>,
+    /// The song and difficulty to watch a replay of, and the replay's file
+    watch_replay: Option<(usize, usize, String)>,
+    /// How fast the song will be played when practising
+    practice_rate: f32,
+    /// How fast the song will be played otherwise, one of [SPEED_MODS]
+    speed: f32,
+    /// How songs are played, as picked on the difficulty select
+    options: PlayOptions,
+    /// The songs folder being scanned, if it is. Starting another scan cancels this one.
+    scan: Option<LibraryScan>,
+    /// Whether the player asked for the songs folder to be scanned again
+    rescan: bool,
+    import_report: ImportReport,
+    show_import_report: bool,
+    /// The result of the last attempt to export the import report
+    export_message: Option<String>,
+    collections: Collections,
+    show_collections: bool,
+    /// The collection being shown in the collections window
+    managed_collection: usize,
+    collection_drag: DragReorder,
+    /// A short message to show the player, and how much longer to show it for
+    toast: Option<(String, f32)>,
+    /// The audio device the game was playing through when it was last checked
+    audio_device: Option<String>,
+    /// An audio device the player is being asked to choose an offset profile for
+    device_prompt: Option<String>,
+    /// The offset profile chosen in the device prompt
+    prompt_profile: Option<usize>,
+    /// How likely the player is to clear a chart of each level, going by their recent plays. This
+    /// is None if there aren't enough plays to tell.
+    clear_model: Option<ClearModel>,
+    /// The [scores_revision] the clear model was fitted at
+    scores_revision: u64,
+    /// The replay of the most recent play of each chart and difficulty (see
+    /// [ScoreDatabase::latest_replays])
+    replays: HashMap<(String, usize), String>,
+    /// The best the player has done on each chart and difficulty (see
+    /// [ScoreDatabase::best_records])
+    best_records: HashMap<(String, usize), BestRecord>,
+    /// When each chart was last played (see [ScoreDatabase::last_played])
+    last_played: HashMap<String, i64>,
+    sort: SongSort,
+    /// Whether the song list is split up by genre
+    group_by_genre: bool,
+    /// The songs, in the order they're listed in
+    song_list: Vec<SongGroup>,
+    /// Whether the order of the song list has to be worked out again, e.g. because the songs or
+    /// how they're sorted have changed
+    song_list_stale: bool,
+    /// What's been typed to search for songs. Only the songs that match it are listed.
+    search: String,
+    /// The language song titles are shown (and sorted) in, as it was when the song list was last
+    /// worked out (see [GameSettings::title_language](crate::settings::GameSettings))
+    title_language: Option<String>,
 }
 
 impl SongSelect {
-    pub fn new(textures: &mut TextureCache, renderer: &Renderer) -> anyhow::Result<Self> {
-        let test_tracks = read_song_list_dir(SONGS_DIR)?;
+    pub fn new(
+        textures: &mut TextureCache,
+        renderer: &mut Renderer,
+        tasks: &mut Tasks,
+    ) -> anyhow::Result<Self> {
         let bg_sprite = SpriteBuilder::new(textures.get(
             &renderer.device,
             &renderer.queue,
@@ -107,83 +425,1349 @@ impl SongSelect {
         )?)
         .build(renderer);
 
+        let collections = Collections::load(COLLECTIONS_PATH).unwrap_or_else(|e| {
+            log::error!("couldn't load collections, starting without any: {e}");
+            Collections::default()
+        });
+
         Ok(SongSelect {
-            songs: test_tracks,
+            songs: Vec::new(),
             bg_sprite: Rc::new(bg_sprite),
+            difficulty_badges: DifficultyBadges::new(renderer)?,
+            difficulty_select: DifficultySelect::default(),
             selected: None,
             difficulty: 0,
-            song_preview_handle: None,
+            preview: SongPreview::default(),
+            hovered: None,
+            cursor_moved: false,
             go_to_credits: false,
             exit: false,
             go_to_song: None,
+            lenient: false,
+            load_failure: None,
+            edit_song: None,
+            watch_replay: None,
+            practice_rate: 1.0,
+            speed: 1.0,
+            options: PlayOptions::default(),
+            scan: Some(LibraryScan {
+                task: spawn_scan(tasks),
+                rescan: false,
+            }),
+            rescan: false,
+            show_import_report: false,
+            import_report: ImportReport::default(),
+            export_message: None,
+            collections,
+            show_collections: false,
+            managed_collection: 0,
+            collection_drag: DragReorder::default(),
+            toast: None,
+            audio_device: None,
+            device_prompt: None,
+            prompt_profile: None,
+            clear_model: None,
+            // Different to any real revision, so the model is fitted on the first update
+            scores_revision: u64::MAX,
+            replays: HashMap::new(),
+            best_records: HashMap::new(),
+            last_played: HashMap::new(),
+            sort: SongSort::default(),
+            group_by_genre: false,
+            song_list: Vec::new(),
+            song_list_stale: true,
+            search: String::new(),
+            title_language: None,
         })
     }
 
-    fn play_preview(
+    /// Fits the clear model again, and finds the latest replays and best records, if a play has
+    /// been recorded since they were last worked out.
+    fn refresh_clear_model(&mut self) {
+        let revision = scores_revision();
+        if revision == self.scores_revision {
+            return;
+        }
+
+        self.scores_revision = revision;
+        // The songs might be sorted by their scores
+        self.song_list_stale = true;
+        (
+            self.clear_model,
+            self.replays,
+            self.best_records,
+            self.last_played,
+        ) = match ScoreDatabase::load(SCORES_PATH) {
+            Ok(scores) => (
+                ClearModel::from_history(&scores),
+                scores.latest_replays(),
+                scores.best_records(),
+                scores.last_played(),
+            ),
+            Err(e) => {
+                log::error!("couldn't load scores: {e}");
+                (None, HashMap::new(), HashMap::new(), HashMap::new())
+            }
+        };
+    }
+
+    /// Works out the order of the song list again, if it needs to be.
+    fn refresh_song_list(&mut self) {
+        let title_language = settings().game.title_language.clone();
+        if title_language != self.title_language {
+            self.title_language = title_language;
+            self.song_list_stale = true;
+        }
+
+        if !std::mem::take(&mut self.song_list_stale) {
+            return;
+        }
+
+        let songs_dir = PathBuf::from(&settings().game.songs_dir);
+        let best_score = |id: usize| self.hardest_course_record(id).map(|best| best.score);
+        let last_played = |id: usize| {
+            (0..DIFFICULTIES.len())
+                .filter_map(|difficulty| {
+                    let hash = self.songs[id].course_hash(difficulty)?;
+                    self.last_played.get(&hash).copied()
+                })
+                .max()
+        };
+
+        self.song_list = song_groups(
+            &self.songs,
+            self.sort,
+            self.group_by_genre,
+            &songs_dir,
+            &SearchQuery::new(&self.search),
+            best_score,
+            last_played,
+        );
+    }
+
+    /// Swaps in the songs from a finished scan of the songs folder. Everything that refers to a
+    /// song in the old list is reset, as the songs might not be in the same places any more.
+    fn finish_scan(
+        &mut self,
+        scan: LibraryScan,
+        result: anyhow::Result<(Vec<Song>, ImportReport)>,
+    ) {
+        let (songs, report) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!("couldn't scan the songs folder: {e}");
+                self.show_toast(format!("Couldn't scan the songs folder: {e}"));
+                return;
+            }
+        };
+
+        self.preview.stop();
+        self.hovered = None;
+        self.selected = None;
+        self.go_to_song = None;
+        self.edit_song = None;
+        self.watch_replay = None;
+        self.load_failure = None;
+
+        self.songs = songs;
+        self.song_list_stale = true;
+        self.show_import_report = report.has_problems();
+        self.import_report = report;
+        self.export_message = None;
+
+        if scan.rescan {
+            self.show_toast(format!("Found {} songs", self.songs.len()));
+        }
+    }
+
+    /// Stops the preview and starts loading a song's audio at the given rate, showing the loading
+    /// screen until it's ready to be played (or a replay of it watched).
+    fn start_loading(
+        &mut self,
+        ctx: &mut Context,
+        song_id: usize,
+        difficulty: usize,
+        rate: f32,
+        warnings: Vec<TJAParseWarning>,
+        replay: Option<Replay>,
+    ) -> StateTransition {
+        self.preview.stop_now();
+
+        let song = self.songs[song_id].clone();
+        let task = load_song_task(
+            ctx.tasks,
+            song.audio_filename.clone(),
+            rate,
+            settings().game.preserve_pitch,
+        );
+
+        let difficulty_name = DIFFICULTIES[difficulty].name;
+        let detail = if replay.is_some() {
+            format!("{difficulty_name} (replay)")
+        } else {
+            difficulty_name.to_string()
+        };
+        let title = song.title_in(self.title_language.as_deref()).to_string();
+        let start = self.start_song(song, difficulty, rate, warnings, replay);
+
+        StateTransition::Push(Box::new(LoadingScreen::new(title, detail, task, start)))
+    }
+
+    /// Sets up how a song will be played with the options picked in song select, once its audio
+    /// has loaded. If the song is being played at a different rate, the audio must already be set
+    /// up to play at that rate.
+    fn start_song(
+        &mut self,
+        song: Song,
+        difficulty: usize,
+        rate: f32,
+        warnings: Vec<TJAParseWarning>,
+        replay: Option<Replay>,
+    ) -> StartSong {
+        let mut options = self.options.for_play(difficulty, replay.is_some());
+
+        if let Some(ghost) = options.ghost_difficulty {
+            if song.difficulties[ghost].is_none() {
+                self.show_toast(format!(
+                    "This song has no {} chart, so ghost notes are off",
+                    DIFFICULTIES[ghost].name
+                ));
+                options.ghost_difficulty = None;
+            }
+        }
+
+        // Each shuffled play should get different notes, so the seed can't come from anything
+        // stable. It's kept with the replay, so the play can still be watched again.
+        options.mods = options
+            .mods
+            .reshuffled(chrono::Utc::now().timestamp_subsec_nanos());
+
+        Box::new(move |sound_data, ctx| {
+            if options.versus {
+                let scene = VersusMode::new(&song, sound_data, difficulty, ctx)?;
+                return Ok(Box::new(scene));
+            }
+
+            let scene = TaikoMode::new(&song, sound_data, difficulty, rate, ctx)?
+                .with_chart_warnings(warnings);
+
+            if let Some(replay) = replay {
+                let scene = scene
+                    .with_mods(replay.mods, ctx.renderer, ctx.textures)
+                    .with_replay(replay);
+                return Ok(Box::new(scene));
+            }
+
+            Ok(Box::new(options.set_up(scene, &song, ctx)))
+        })
+    }
+
+    /// Reads the charts of a song that only has its metadata from the song index, which has to be
+    /// done before it can be played or edited.
+    fn load_charts(&mut self, song_id: usize) -> anyhow::Result<()> {
+        if self.songs[song_id].metadata_only {
+            self.songs[song_id] = load_charts(&self.songs[song_id])?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the course that's about to be played from the chart file again, so that any changes
+    /// made to it since the songs were scanned (e.g. in the chart editor) are played. Returns any
+    /// problems that were worked around to read it in lenient mode.
+    ///
+    /// If the chart has a mistake in it, the player is asked what to do about it instead. If it
+    /// can't be read for any other reason, the course from the scan is played.
+    fn refresh_course(
         &mut self,
-        audio: &mut AudioManager,
-        selected: usize,
-    ) -> anyhow::Result<StreamingSoundHandle<FromFileError>> {
-        let selected = &self.songs[selected];
+        song_id: usize,
+        difficulty: usize,
+        lenient: bool,
+    ) -> Result<Vec<TJAParseWarning>, LoadFailure> {
+        match reload_course(&self.songs[song_id], difficulty, lenient) {
+            Ok(mut song) => {
+                match song.difficulties[difficulty].take() {
+                    Some(course) => self.songs[song_id].difficulties[difficulty] = Some(course),
+                    None => log::warn!("the course being played isn't in its chart file any more"),
+                }
+
+                Ok(song.warnings)
+            }
+            Err(ReloadError::Parse { error, excerpt }) => Err(LoadFailure {
+                song_id,
+                difficulty,
+                error,
+                excerpt,
+                lenient,
+            }),
+            Err(ReloadError::Other(e)) => {
+                log::error!("couldn't reload the chart, so using the scanned one: {e}");
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Switches the song preview to the given song, unless it's already playing.
+    fn preview(&mut self, ctx: &mut Context, song_id: usize) {
+        self.preview.play(
+            ctx.audio,
+            ctx.sounds.music_track(),
+            song_id,
+            &self.songs[song_id],
+        );
+    }
+
+    fn show_toast(&mut self, message: impl Into<String>) {
+        self.toast = Some((message.into(), TOAST_DURATION));
+    }
+
+    /// Saves the collections, letting the player know with `message` if it worked.
+    fn save_collections(&mut self, message: impl Into<String>) {
+        match self.collections.save(COLLECTIONS_PATH) {
+            Ok(()) => self.show_toast(message),
+            Err(e) => {
+                log::error!("couldn't save collections: {e}");
+                self.show_toast(format!("Couldn't save collections: {e}"));
+            }
+        }
+    }
+
+    /// The list of songs. Hovering over a song previews it, clicking on it shows its difficulties
+    /// and right clicking on it opens a menu of things to do with it.
+    fn song_list_ui(&mut self, ui: &mut egui::Ui) {
+        let mut hovered = None;
+
+        if let Some(scan) = &self.scan {
+            let (progress, folder) = scan.task.progress();
+            ui.label("Scanning songs...");
+            ui.add(egui::ProgressBar::new(progress).text(folder));
+        }
+
+        ui.horizontal(|ui| {
+            let sort = self.sort;
+            egui::ComboBox::from_label("Sort")
+                .selected_text(self.sort.name())
+                .show_ui(ui, |ui| {
+                    for sort in SongSort::ALL {
+                        ui.selectable_value(&mut self.sort, sort, sort.name());
+                    }
+                });
+
+            let grouped = ui
+                .checkbox(&mut self.group_by_genre, "Group by genre")
+                .on_hover_text(
+                    "Songs are grouped by the genre their chart gives, or else by the folder \
+                    they're in",
+                );
+
+            if self.sort != sort || grouped.changed() {
+                self.song_list_stale = true;
+            }
+        });
+
+        if self.search.is_empty() {
+            ui.label(RichText::new("Type to search").weak());
+        } else {
+            ui.label(RichText::new(format!("Search: {}", self.search)).size(17.0))
+                .on_hover_text("Press escape to clear the search");
+        }
+
+        self.refresh_song_list();
+
+        if self.song_list.iter().all(|group| group.songs.is_empty()) && !self.search.is_empty() {
+            ui.label(RichText::new("No songs match the search").weak());
+        }
 
-        let settings = StreamingSoundSettings::default()
-            .playback_region(selected.demostart as f64..)
-            .fade_in_tween(Some(*IN_TWEEN))
-            .loop_region(selected.demostart as f64..);
+        egui::ScrollArea::vertical()
+            .max_height(ui.available_height() - 150.0)
+            .show(ui, |ui| {
+                // Taken out so the songs can be shown while it's being looked through. Nothing
+                // shown here changes the order of the songs.
+                let song_list = std::mem::take(&mut self.song_list);
 
-        let song = StreamingSoundData::from_file(&selected.audio_filename, settings)?;
+                for group in song_list.iter() {
+                    let Some(genre) = &group.genre else {
+                        for &id in group.songs.iter() {
+                            self.song_entry_ui(ui, id, &mut hovered);
+                        }
+                        continue;
+                    };
+
+                    let header = format!("{genre} ({})", group.songs.len());
+                    egui::CollapsingHeader::new(RichText::new(header).size(19.0).strong())
+                        .id_source(genre)
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            for &id in group.songs.iter() {
+                                self.song_entry_ui(ui, id, &mut hovered);
+                            }
+                        });
+                }
+
+                self.song_list = song_list;
+            });
 
-        Ok(audio.play(song)?)
+        // Moving onto a different song starts the wait before its preview all over again
+        match (hovered, self.hovered) {
+            (Some(id), Some((old_id, _))) if id == old_id => {}
+            (hovered, _) => self.hovered = hovered.map(|id| (id, 0.0)),
+        }
+    }
+
+    /// One song in the song list, and its difficulties if it's selected. `hovered` is set to the
+    /// song if the pointer is over it.
+    fn song_entry_ui(&mut self, ui: &mut egui::Ui, id: usize, hovered: &mut Option<usize>) {
+        let song = &self.songs[id];
+        let title = song.title_in(self.title_language.as_deref());
+        let title = if self.collections.is_favourite(&song_key(song)) {
+            format!("★ {title}")
+        } else {
+            title.to_string()
+        };
+
+        let crown = self.hardest_course_record(id).map(|best| best.crown);
+        let response = ui
+            .horizontal(|ui| {
+                // The crown of the hardest difficulty, with a gap kept for songs without one so
+                // the titles line up
+                let colour = crown
+                    .filter(|&crown| crown > Crown::Failed)
+                    .map_or(egui::Color32::TRANSPARENT, crown_colour);
+                ui.label(RichText::new("👑").size(17.0).color(colour))
+                    .on_hover_text(crown.map_or("Not played", |crown| crown.name()));
+
+                ui.selectable_label(self.selected == Some(id), RichText::new(title).size(17.0))
+            })
+            .inner;
+
+        if response.hovered() {
+            *hovered = Some(id);
+        }
+
+        if response.clicked() {
+            if self.selected == Some(id) {
+                self.selected = None;
+            } else {
+                self.select_song(id);
+            }
+
+            // Skips the wait, so the preview starts on the next update
+            self.hovered = Some((id, PREVIEW_DEBOUNCE));
+            self.cursor_moved = true;
+        }
+
+        response.context_menu(|ui| self.song_context_menu(ui, id));
+    }
+
+    /// The best the player has done on the hardest difficulty of a song, which is what the song
+    /// list shows and sorts by.
+    fn hardest_course_record(&self, id: usize) -> Option<&BestRecord> {
+        let (difficulty, _) = hardest_course(&self.songs[id])?;
+        let key = (self.songs[id].course_hash(difficulty)?, difficulty);
+        self.best_records.get(&key)
+    }
+
+    /// Selects a song, which opens its difficulty select.
+    fn select_song(&mut self, id: usize) {
+        // The charts are needed to show how busy they are and to find the song's replays. If they
+        // can't be read, that's dealt with when the song is played.
+        if let Err(e) = self.load_charts(id) {
+            let title = &self.songs[id].title;
+            log::warn!("couldn't read the charts of {title}: {e}");
+        }
+
+        self.selected = Some(id);
+        self.difficulty = initial_difficulty(&self.songs[id], self.difficulty);
+    }
+
+    /// The difficulty select for the selected song (see [DifficultySelect]), and the ways it can be
+    /// played.
+    fn difficulty_select_ui(&mut self, ctx: &egui::Context) {
+        let Some(song_id) = self.selected else {
+            return;
+        };
+
+        let mut open = true;
+        let language = self.title_language.clone();
+        let title = RichText::new(self.songs[song_id].title_in(language.as_deref())).size(22.0);
+
+        egui::Window::new(title)
+            .id(egui::Id::new("difficulty select"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::RIGHT_TOP, [-40.0, 40.0])
+            .show(ctx, |ui| {
+                let song = &self.songs[song_id];
+                for detail in [song.subtitle_in(language.as_deref()), song.genre.as_deref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    ui.label(RichText::new(detail).weak());
+                }
+
+                // Resting the pointer on a card shouldn't undo choosing a difficulty with the keys
+                let pointer_moved = ui.input(|input| input.pointer.is_moving());
+
+                ui.horizontal(|ui| {
+                    for i in 0..DIFFICULTIES.len() {
+                        let Some(course) = &self.songs[song_id].difficulties[i] else {
+                            continue;
+                        };
+
+                        let best = self.best_record(song_id, i);
+                        let card = self.difficulty_select.card_ui(
+                            ui,
+                            i,
+                            course,
+                            best,
+                            i == self.difficulty,
+                        );
+
+                        if card.hovered() && pointer_moved && self.difficulty != i {
+                            self.difficulty = i;
+                            self.cursor_moved = true;
+                        }
+
+                        if card.clicked() {
+                            self.go_to_song = Some((song_id, i));
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Speed:");
+                    ui.add_enabled_ui(!self.options.practice, |ui| {
+                        for speed in SPEED_MODS {
+                            ui.selectable_value(&mut self.speed, speed, format!("x{speed}"));
+                        }
+                    });
+                })
+                .response
+                .on_hover_text(
+                    "Plays the song faster or slower, notes and all. Plays at any speed are \
+                    recorded",
+                );
+
+                ui.label(
+                    RichText::new(
+                        "Left and right choose a difficulty, up and down the speed, and enter \
+                        plays it",
+                    )
+                    .weak(),
+                );
+
+                self.play_options_ui(ui, song_id);
+            });
+
+        if !open {
+            self.selected = None;
+        }
+    }
+
+    /// More about how the player has done on the highlighted difficulty, and the ways it can be
+    /// played.
+    fn play_options_ui(&mut self, ui: &mut egui::Ui, song_id: usize) {
+        if let Some(best) = self.best_record(song_id, self.difficulty) {
+            ui.label(format!(
+                "Max combo: {}, played {} {}",
+                best.max_combo,
+                best.plays,
+                if best.plays == 1 { "time" } else { "times" }
+            ));
+
+            if let Some(judgements) = best.judgements {
+                ui.label(
+                    RichText::new(format!(
+                        "Good {} / Ok {} / Bad {} / Miss {}",
+                        judgements.goods, judgements.okays, judgements.bads, judgements.misses
+                    ))
+                    .weak(),
+                );
+            }
+        }
+
+        let highlighted = self.songs[song_id].difficulties[self.difficulty].as_ref();
+        if let Some((model, difficulty)) = self.clear_model.zip(highlighted) {
+            let level = judged_level(difficulty.star_level, difficulty.estimated_level);
+
+            ui.label(
+                RichText::new(format!(
+                    "Clear chance: ~{:.0}%",
+                    model.clear_chance(level) * 100.0
+                ))
+                .weak(),
+            )
+            .on_hover_text(format!(
+                "A rough estimate, from whether you cleared charts of different levels in \
+                your recent plays (up to the last {RECENT_PLAYS})"
+            ));
+        }
+
+        ui.checkbox(&mut self.options.practice, "Practice mode")
+            .on_hover_text(
+                "Shows the whole chart along the bottom of the screen, which you can click on to \
+            jump around it, and lets you loop over part of the song (the keys are in the \
+            help). Practice plays aren't recorded",
+            );
+
+        if self.options.practice {
+            ui.add(
+                egui::Slider::new(&mut self.practice_rate, PRACTICE_RATE_RANGE)
+                    .step_by(0.05)
+                    .text("Practice rate"),
+            );
+        }
+
+        let ghost_name = |ghost: Option<usize>| ghost.map_or("Off", |i| DIFFICULTIES[i].name);
+        egui::ComboBox::from_label("Ghost notes")
+            .selected_text(ghost_name(self.options.ghost_difficulty))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.options.ghost_difficulty, None, ghost_name(None));
+
+                for i in 0..DIFFICULTIES.len() {
+                    ui.selectable_value(
+                        &mut self.options.ghost_difficulty,
+                        Some(i),
+                        ghost_name(Some(i)),
+                    );
+                }
+            })
+            .response
+            .on_hover_text(
+                "Shows the notes of another difficulty faintly above the chart you're \
+                playing, to help you get to know it",
+            );
+
+        ui.horizontal(|ui| {
+            ui.label("Mods:");
+            ui.checkbox(&mut self.options.mods.hidden, "Hidden")
+                .on_hover_text("Notes fade out before they reach the drum");
+            ui.checkbox(&mut self.options.mods.sudden, "Sudden")
+                .on_hover_text("Notes only appear once they're close to the drum");
+            ui.checkbox(&mut self.options.mods.inverse, "Inverse")
+                .on_hover_text("Every don is a kat, and every kat is a don");
+
+            let mut shuffle = self.options.mods.shuffle.is_some();
+            if ui
+                .checkbox(&mut shuffle, "Shuffle")
+                .on_hover_text("Every don and kat is randomly a don or a kat")
+                .changed()
+            {
+                self.options.mods.shuffle = shuffle.then_some(0);
+            }
+        });
+
+        ui.checkbox(&mut self.options.autoplay, "Autoplay")
+            .on_hover_text(
+                "The game plays the chart by itself, hitting every note perfectly. Plays like \
+            this aren't recorded",
+            );
+
+        ui.checkbox(&mut self.options.versus, "Two players")
+            .on_hover_text(
+                "Two people play the song together, each with their own note field and keys \
+            (player 2's keys are in the settings). Charts with a separate chart for each \
+            player use those. The rate, practice mode, ghost notes, mods and autoplay are ignored, \
+            and plays like this aren't recorded",
+            );
+
+        if ui
+            .button(format!("Edit {} chart", DIFFICULTIES[self.difficulty].name))
+            .clicked()
+        {
+            self.edit_song = Some((song_id, self.difficulty));
+        }
+
+        if let Some(file) = self.latest_replay(song_id, self.difficulty) {
+            if ui.button("Watch last replay").clicked() {
+                self.watch_replay = Some((song_id, self.difficulty, file));
+            }
+        }
+    }
+
+    /// What a difficulty of a song is stored under in the scores: its chart's hash and the
+    /// difficulty. This is None until the song's charts have been read.
+    fn score_key(&self, song_id: usize, difficulty: usize) -> Option<(String, usize)> {
+        let song = &self.songs[song_id];
+        let course = song.difficulties[difficulty]
+            .as_ref()
+            .filter(|_| !song.metadata_only)?;
+
+        Some((chart_hash(&course.chart).to_string(), difficulty))
+    }
+
+    /// The file of the replay of the most recent play of a chart, if it has one.
+    fn latest_replay(&self, song_id: usize, difficulty: usize) -> Option<String> {
+        self.replays
+            .get(&self.score_key(song_id, difficulty)?)
+            .cloned()
+    }
+
+    /// The best the player has done on a chart, if they've played it.
+    fn best_record(&self, song_id: usize, difficulty: usize) -> Option<&BestRecord> {
+        self.best_records.get(&self.score_key(song_id, difficulty)?)
+    }
+
+    fn song_context_menu(&mut self, ui: &mut egui::Ui, song_id: usize) {
+        let key = song_key(&self.songs[song_id]);
+
+        let favourite_text = if self.collections.is_favourite(&key) {
+            "Unfavourite"
+        } else {
+            "Favourite"
+        };
+
+        if ui.button(favourite_text).clicked() {
+            let message = if self.collections.toggle_favourite(&key) {
+                "Added to favourites"
+            } else {
+                "Removed from favourites"
+            };
+
+            self.save_collections(message);
+            ui.close_menu();
+        }
+
+        ui.menu_button("Add to collection", |ui| {
+            let mut add_to = None;
+
+            for (i, collection) in self.collections.collections.iter().enumerate() {
+                if ui.button(&collection.name).clicked() {
+                    add_to = Some(i);
+                }
+            }
+
+            if !self.collections.collections.is_empty() {
+                ui.separator();
+            }
+
+            if ui.button("New collection").clicked() {
+                add_to = Some(self.collections.new_collection());
+            }
+
+            if let Some(i) = add_to {
+                let name = self.collections.collections[i].name.clone();
+                let message = if self.collections.add_to(i, &key) {
+                    format!("Added to {name}")
+                } else {
+                    format!("Already in {name}")
+                };
+
+                self.save_collections(message);
+                ui.close_menu();
+            }
+        });
+
+        if ui.button("Show folder").clicked() {
+            if let Err(e) = key.parent().map_or(Ok(()), show_folder) {
+                log::error!("couldn't show song folder: {e}");
+                self.show_toast(format!("Couldn't show folder: {e}"));
+            }
+
+            ui.close_menu();
+        }
+    }
+
+    /// A window for looking through collections and changing the order of the songs in them, by
+    /// dragging them around.
+    fn collections_ui(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_collections;
+        let mut changed = false;
+
+        egui::Window::new("Collections")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if self.collections.collections.is_empty() {
+                    ui.label("Right click on a song to add it to a collection.");
+                    return;
+                }
+
+                self.managed_collection = self
+                    .managed_collection
+                    .min(self.collections.collections.len() - 1);
+
+                let mut delete = false;
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("managed collection")
+                        .selected_text(&self.collections.collections[self.managed_collection].name)
+                        .show_ui(ui, |ui| {
+                            for (i, collection) in self.collections.collections.iter().enumerate() {
+                                ui.selectable_value(
+                                    &mut self.managed_collection,
+                                    i,
+                                    &collection.name,
+                                );
+                            }
+                        });
+
+                    delete = ui.button("Delete").clicked();
+                });
+
+                if delete {
+                    self.collections.collections.remove(self.managed_collection);
+                    self.collection_drag.cancel();
+                    changed = true;
+                    return;
+                }
+
+                let collection = &mut self.collections.collections[self.managed_collection];
+                changed |= ui.text_edit_singleline(&mut collection.name).lost_focus();
+                ui.separator();
+
+                let mut rows = Vec::with_capacity(collection.songs.len());
+                let mut remove = None;
+
+                for (i, path) in collection.songs.iter().enumerate() {
+                    let title = self
+                        .songs
+                        .iter()
+                        .find(|song| song_key(song) == *path)
+                        .map(|song| song.title_in(self.title_language.as_deref()).to_string())
+                        .unwrap_or_else(|| format!("{} (missing)", path.display()));
+
+                    let row = ui.horizontal(|ui| {
+                        let handle = ui
+                            .add(egui::Label::new("☰").sense(egui::Sense::drag()))
+                            .on_hover_cursor(egui::CursorIcon::Grab);
+
+                        let text = RichText::new(title);
+                        if self.collection_drag.dragging() == Some(i) {
+                            ui.label(text.strong());
+                        } else {
+                            ui.label(text);
+                        }
+
+                        if ui.small_button("x").clicked() {
+                            remove = Some(i);
+                        }
+
+                        handle
+                    });
+
+                    if row.inner.drag_started() {
+                        self.collection_drag.start(i);
+                    }
+
+                    rows.push(row.response.rect);
+                }
+
+                if let Some(pointer) = ui.input(|i| i.pointer.interact_pos()) {
+                    self.collection_drag.hover(pointer, &rows);
+                }
+
+                if let Some(y) = self
+                    .collection_drag
+                    .insertion_marker()
+                    .and_then(|gap| marker_y(gap, &rows))
+                {
+                    ui.painter().hline(
+                        ui.min_rect().x_range(),
+                        y,
+                        egui::Stroke::new(2.0, ui.visuals().selection.bg_fill),
+                    );
+                }
+
+                if self.collection_drag.dragging().is_some()
+                    && ui.input(|i| i.pointer.any_released())
+                {
+                    if let Some((from, gap)) = self.collection_drag.finish() {
+                        move_item(&mut collection.songs, from, gap);
+                        changed = true;
+                    }
+                }
+
+                if let Some(i) = remove {
+                    collection.songs.remove(i);
+                    changed = true;
+                }
+            });
+
+        self.show_collections = open;
+
+        if changed {
+            self.save_collections("Saved collections");
+        }
+    }
+
+    /// Shows why the song the player tried to play couldn't be loaded, and lets them decide what
+    /// to do about it.
+    fn load_failure_ui(&mut self, ctx: &egui::Context) {
+        let Some(failure) = &self.load_failure else {
+            return;
+        };
+
+        let song = &self.songs[failure.song_id];
+        let folder = song
+            .chart_path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf);
+
+        let (mut open_folder, mut retry, mut back) = (false, false, false);
+
+        egui::Window::new("Couldn't load chart")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The {} chart of {} has a mistake in it:",
+                    DIFFICULTIES[failure.difficulty].name,
+                    song.title_in(self.title_language.as_deref())
+                ));
+                ui.label(RichText::new(failure.error.to_string()).strong());
+                ui.add_space(5.0);
+
+                egui::Frame::group(ui.style()).show(ui, |ui| {
+                    ui.label(RichText::new(&failure.excerpt).monospace());
+                });
+
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if folder.is_some() && ui.button("Show folder").clicked() {
+                        open_folder = true;
+                    }
+
+                    if !failure.lenient
+                        && ui
+                            .button("Retry in lenient mode")
+                            .on_hover_text("Skip over mistakes in the chart where possible")
+                            .clicked()
+                    {
+                        retry = true;
+                    }
+
+                    if ui.button("Back").clicked() {
+                        back = true;
+                    }
+                });
+            });
+
+        if open_folder {
+            if let Err(e) = show_folder(folder.as_deref().unwrap()) {
+                log::error!("couldn't open the song's folder: {e}");
+                self.show_toast("Couldn't open the song's folder");
+            }
+        }
+
+        if retry {
+            let failure = self.load_failure.take().unwrap();
+            self.go_to_song = Some((failure.song_id, failure.difficulty));
+            self.lenient = true;
+        } else if back {
+            self.load_failure = None;
+        }
+    }
+
+    /// Notices when audio starts coming out of a different device, and asks the player whether to
+    /// switch offset profiles if the device might need a different one.
+    ///
+    /// When the game starts, this only asks if a profile was chosen for the device before, so that
+    /// players who always use the same device aren't asked every time.
+    fn check_audio_device(&mut self, audio: &mut AudioManager) {
+        let device = audio.backend_mut().device_name();
+        if device == self.audio_device {
+            return;
+        }
+
+        if let Some(name) = &device {
+            let game = &settings().game;
+            let known = game
+                .offset_profiles
+                .iter()
+                .any(|profile| profile.device.as_ref() == Some(name));
+            let changed = self.audio_device.is_some();
+
+            if game.suggested_offset_profile(name).is_some()
+                || (changed && !known && !game.offset_profiles.is_empty())
+            {
+                self.prompt_profile = game
+                    .suggested_offset_profile(name)
+                    .or(game.active_offset_profile);
+                self.device_prompt = Some(name.clone());
+            }
+        }
+
+        self.audio_device = device;
+    }
+
+    fn device_prompt_ui(&mut self, ctx: &egui::Context) {
+        let Some(device) = self.device_prompt.clone() else {
+            return;
+        };
+
+        let profiles = settings().game.offset_profiles.clone();
+        let (mut switch, mut dismiss) = (false, false);
+
+        egui::Window::new("Audio device changed")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 20.0])
+            .show(ctx, |ui| {
+                ui.label(format!("Audio is now playing through {device}."));
+                ui.label("Which offset profile should be used with it?");
+                offset_profile_combo_box(ui, &profiles, &mut self.prompt_profile);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Switch").clicked() {
+                        switch = true;
+                    }
+
+                    if ui.button("Not now").clicked() {
+                        dismiss = true;
+                    }
+                });
+            });
+
+        if switch {
+            settings_mut()
+                .game
+                .switch_offset_profile(self.prompt_profile, Some(&device));
+
+            match save_settings() {
+                Ok(()) => {
+                    let label = settings().game.offset_profile_label();
+                    self.show_toast(match label {
+                        Some(label) => format!("Using offset profile {label}"),
+                        None => "Using the global offset".to_string(),
+                    });
+                }
+                Err(e) => {
+                    log::error!("couldn't save settings: {e}");
+                    self.show_toast(format!("Couldn't save settings: {e}"));
+                }
+            }
+        }
+
+        if switch || dismiss {
+            self.device_prompt = None;
+        }
+    }
+
+    fn toast_ui(&self, ctx: &egui::Context) {
+        if let Some((message, _)) = &self.toast {
+            egui::Area::new(egui::Id::new("toast"))
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -30.0])
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(RichText::new(message).size(17.0));
+                    });
+                });
+        }
+    }
+
+    /// Shows what happened while scanning the songs folder: how many songs were added, and which
+    /// ones weren't and why.
+    fn import_report_ui(&mut self, ctx: &egui::Context) {
+        let report = &self.import_report;
+        let mut open = self.show_import_report;
+
+        egui::Window::new(format!(
+            "Import report: {} songs added, {} not added",
+            report.songs_added,
+            report.skipped_count() + report.missing_audio.len() + report.duplicates.len()
+        ))
+        .id(egui::Id::new("import report"))
+        .open(&mut open)
+        .default_open(false)
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(500.0)
+                .show(ui, |ui| {
+                    ui.label(format!(
+                        "Songs skipped due to errors: {}",
+                        report.skipped_count()
+                    ));
+
+                    for (kind, songs) in report.skipped_groups() {
+                        ui.collapsing(format!("{kind} ({})", songs.len()), |ui| {
+                            for song in songs {
+                                ui.label(song.path.to_string_lossy())
+                                    .on_hover_text(&song.message);
+                            }
+                        });
+                    }
+
+                    ui.collapsing(
+                        format!("Songs missing audio: {}", report.missing_audio.len()),
+                        |ui| {
+                            for path in report.missing_audio.iter() {
+                                ui.label(path.to_string_lossy());
+                            }
+                        },
+                    );
+
+                    ui.collapsing(
+                        format!("Duplicate songs: {}", report.duplicates.len()),
+                        |ui| {
+                            for (path, original) in report.duplicates.iter() {
+                                ui.label(format!(
+                                    "{} (same as {})",
+                                    path.to_string_lossy(),
+                                    original.to_string_lossy()
+                                ));
+                            }
+                        },
+                    );
+                });
+
+            ui.add_space(10.0);
+
+            if ui.button("Export full report").clicked() {
+                self.export_message = Some(match report.export(IMPORT_REPORT_PATH) {
+                    Ok(()) => format!("Exported report to {IMPORT_REPORT_PATH}"),
+                    Err(e) => {
+                        log::error!("couldn't export import report: {e}");
+                        format!("Couldn't export report: {e}")
+                    }
+                });
+            }
+
+            if let Some(message) = &self.export_message {
+                ui.label(message);
+            }
+        });
+
+        self.show_import_report = open;
     }
 }
 
 impl GameState for SongSelect {
-    fn update(&mut self, ctx: &mut Context, _dt: f32) -> StateTransition {
-        if self.go_to_credits {
-            if let Some(handle) = self.song_preview_handle.as_mut() {
-                handle.stop(*OUT_TWEEN).unwrap();
+    fn update(&mut self, ctx: &mut Context, dt: f32) -> StateTransition {
+        self.refresh_clear_model();
+        // The song previews take over from the menu music
+        ctx.sounds.stop_menu_music();
+
+        if std::mem::take(&mut self.cursor_moved) {
+            ctx.sounds.play(ctx.audio, SoundEffect::Cursor);
+        }
+
+        // Replacing the scan cancels the one that was running
+        if std::mem::take(&mut self.rescan) {
+            self.scan = Some(LibraryScan {
+                task: spawn_scan(ctx.tasks),
+                rescan: true,
+            });
+        }
+
+        if let Some(result) = self.scan.as_ref().and_then(|scan| scan.task.poll()) {
+            let scan = self.scan.take().unwrap();
+            self.finish_scan(scan, result);
+        }
+
+        if let Some((song_id, hover_time)) = self.hovered.as_mut() {
+            *hover_time += dt;
+
+            if *hover_time >= PREVIEW_DEBOUNCE {
+                let song_id = *song_id;
+                self.preview(ctx, song_id);
             }
+        }
+
+        if let Some((_, time_left)) = self.toast.as_mut() {
+            *time_left -= dt;
+
+            if *time_left <= 0.0 {
+                self.toast = None;
+            }
+        }
+
+        self.difficulty_select
+            .update(self.selected.map(|id| (id, &self.songs[id])));
+
+        if let Err(e) = self.difficulty_badges.update(
+            self.selected.map(|id| (id, &self.songs[id])),
+            self.difficulty,
+            ctx.renderer,
+        ) {
+            log::error!("couldn't build difficulty badges: {e}");
+        }
 
+        if self.go_to_credits {
+            ctx.sounds.play(ctx.audio, SoundEffect::Select);
+            self.preview.stop();
             self.go_to_credits = false;
             StateTransition::Push(Box::new(CreditsScreen::new()))
-        } else if let Some((song_id, difficulty)) = self.go_to_song {
-            let sound_data = StaticSoundData::from_file(
-                &self.songs[song_id].audio_filename,
-                StaticSoundSettings::default(),
-            )
-            .unwrap();
+        } else if let Some((song_id, difficulty)) = self.go_to_song.take() {
+            ctx.sounds.play(ctx.audio, SoundEffect::Select);
 
-            self.go_to_song = None;
+            if let Err(e) = self.load_charts(song_id) {
+                // Mistakes in the chart are dealt with when the course is read again below
+                if !e.is::<TJAParseError>() {
+                    log::error!("couldn't read the chart: {e}");
+                    self.show_toast(format!("Couldn't read the chart: {e:#}"));
+                    return StateTransition::Continue;
+                }
+            }
+
+            let lenient = std::mem::take(&mut self.lenient);
+            let warnings = match self.refresh_course(song_id, difficulty, lenient) {
+                Ok(warnings) => warnings,
+                Err(failure) => {
+                    self.load_failure = Some(failure);
+                    return StateTransition::Continue;
+                }
+            };
 
-            if let Some(handle) = self.song_preview_handle.as_mut() {
-                handle.stop(Default::default()).unwrap();
+            // Two players always play at the normal rate
+            let rate = if self.options.versus {
+                1.0
+            } else if self.options.practice {
+                self.practice_rate
+            } else {
+                self.speed
+            };
+            self.start_loading(ctx, song_id, difficulty, rate, warnings, None)
+        } else if let Some((song_id, difficulty, file)) = self.watch_replay.take() {
+            let replay = match Replay::load(REPLAYS_DIR, &file) {
+                Ok(replay) => replay,
+                Err(e) => {
+                    log::error!("couldn't read the replay {file}: {e}");
+                    self.show_toast(format!("Couldn't read the replay: {e}"));
+                    return StateTransition::Continue;
+                }
+            };
+
+            self.start_loading(
+                ctx,
+                song_id,
+                difficulty,
+                replay.rate,
+                Vec::new(),
+                Some(replay),
+            )
+        } else if let Some((song_id, difficulty)) = self.edit_song.take() {
+            if let Err(e) = self.load_charts(song_id) {
+                log::error!("couldn't read the chart to edit: {e}");
+                self.show_toast(format!("Couldn't read the chart: {e:#}"));
+                return StateTransition::Continue;
             }
 
-            StateTransition::Push(Box::new(
-                TaikoMode::new(
-                    &self.songs[song_id],
-                    sound_data,
-                    ctx.audio,
-                    difficulty,
-                    ctx.renderer,
-                    ctx.textures,
-                )
-                .expect("error creating taiko mode scene"),
-            ))
+            match Editor::new(&self.songs[song_id], difficulty) {
+                Ok(editor) => {
+                    self.preview.stop_now();
+                    StateTransition::Push(Box::new(editor))
+                }
+                Err(e) => {
+                    log::error!("couldn't open the chart editor: {e}");
+                    StateTransition::Continue
+                }
+            }
         } else if self.exit {
+            ctx.sounds.play(ctx.audio, SoundEffect::Back);
             StateTransition::Pop
         } else {
             StateTransition::Continue
         }
     }
     fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
-        ctx.render(self.bg_sprite.as_ref())
+        ctx.render(self.bg_sprite.as_ref());
+        ctx.render(&self.difficulty_badges);
+    }
+
+    fn recreate_gpu_resources(&mut self, ctx: &mut Context) -> bool {
+        let result = ctx
+            .textures
+            .get(
+                &ctx.renderer.device,
+                &ctx.renderer.queue,
+                "song_select_bg.jpg",
+            )
+            .and_then(|texture| {
+                self.bg_sprite = Rc::new(SpriteBuilder::new(texture).build(ctx.renderer));
+                self.difficulty_badges = DifficultyBadges::new(ctx.renderer)?;
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("couldn't recreate song select: {e}");
+                false
+            }
+        }
     }
 
     fn debug_ui(&mut self, ctx: egui::Context, audio: &mut AudioManager) {
+        self.check_audio_device(audio);
+        self.menu_ui(&ctx);
+    }
+
+    /// Typing searches for songs. Backspace takes the last character off the search, and escape
+    /// clears it. While a song is selected, the keys choose its difficulty instead.
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        let WindowEvent::KeyboardInput { event, .. } = event else {
+            return;
+        };
+
+        if event.state != ElementState::Pressed {
+            return;
+        }
+
+        if let Some(song_id) = self.selected {
+            let song = &self.songs[song_id];
+
+            match event.physical_key {
+                PhysicalKey::Code(KeyCode::Escape) => self.selected = None,
+                PhysicalKey::Code(key @ (KeyCode::ArrowLeft | KeyCode::ArrowRight)) => {
+                    let difficulty =
+                        step_difficulty(song, self.difficulty, key == KeyCode::ArrowRight);
+                    self.cursor_moved |= difficulty != self.difficulty;
+                    self.difficulty = difficulty;
+                }
+                PhysicalKey::Code(key @ (KeyCode::ArrowUp | KeyCode::ArrowDown))
+                    if !self.options.practice =>
+                {
+                    let speed = step_speed(self.speed, key == KeyCode::ArrowUp);
+                    self.cursor_moved |= speed != self.speed;
+                    self.speed = speed;
+                }
+                PhysicalKey::Code(KeyCode::Enter | KeyCode::NumpadEnter) if !event.repeat => {
+                    self.go_to_song = Some((song_id, self.difficulty));
+                }
+                _ => {}
+            }
+
+            return;
+        }
+
+        let ctrl_held = ctx
+            .keyboard
+            .is_pressed(PhysicalKey::Code(KeyCode::ControlLeft))
+            || ctx
+                .keyboard
+                .is_pressed(PhysicalKey::Code(KeyCode::ControlRight));
+
+        match event.physical_key {
+            PhysicalKey::Code(KeyCode::Escape) => self.search.clear(),
+            PhysicalKey::Code(KeyCode::Backspace) => {
+                self.search.pop();
+            }
+            _ => {
+                let typed = event.text.as_deref().unwrap_or_default();
+                if ctrl_held || typed.chars().all(char::is_control) {
+                    return;
+                }
+
+                self.search
+                    .extend(typed.chars().filter(|c| !c.is_control()));
+            }
+        }
+
+        self.song_list_stale = true;
+    }
+}
+
+impl SongSelect {
+    /// The menu down the side of the screen, and the windows that can be opened from it.
+    fn menu_ui(&mut self, ctx: &egui::Context) {
         egui::SidePanel::left("main menu")
             .resizable(false)
-            .show(&ctx, |ui| {
+            .show(ctx, |ui| {
                 ui.label(
                     RichText::new("Taiko Clone Demo!")
                         .text_style(egui::TextStyle::Heading)
@@ -194,42 +1778,7 @@ impl GameState for SongSelect {
 
                 ui.add_space(50.0);
 
-                let old_song = self.selected;
-
-                egui::ComboBox::from_label("Song select")
-                    .selected_text(
-                        RichText::new(
-                            self.selected
-                                .map(|id| self.songs[id].title.as_str())
-                                .unwrap_or("None"),
-                        )
-                        .size(20.0),
-                    )
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(
-                            &mut self.selected,
-                            None,
-                            RichText::new("none").size(15.0),
-                        );
-
-                        for (id, song) in self.songs.iter().enumerate() {
-                            ui.selectable_value(
-                                &mut self.selected,
-                                Some(id),
-                                RichText::new(&song.title).size(15.0),
-                            );
-                        }
-                    });
-
-                if self.selected != old_song {
-                    if let Some(handle) = self.song_preview_handle.as_mut() {
-                        handle.stop(*OUT_TWEEN).unwrap();
-                    }
-
-                    self.song_preview_handle = self
-                        .selected
-                        .map(|id| self.play_preview(audio, id).unwrap());
-                }
+                self.song_list_ui(ui);
 
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::Min), |ui| {
                     ui.add_space(10.0);
@@ -243,39 +1792,216 @@ impl GameState for SongSelect {
                     if ui.button(RichText::new("credits").size(20.0)).clicked() {
                         self.go_to_credits = true;
                     }
-                });
-            });
 
-        if let Some(song_index) = self.selected {
-            egui::Window::new("difficulty select").show(&ctx, |ui| {
-                const DIFFICULTY_NAMES: [&str; 5] = ["Easy", "Normal", "Hard", "Oni", "Ura"];
+                    ui.add_space(10.0);
 
-                egui::TopBottomPanel::top("difficulty select panel").show_inside(ui, |ui| {
-                    for (i, difficulty) in self.songs[song_index]
-                        .difficulties
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(i, d)| d.as_ref().map(|dinner| (i, dinner)))
+                    if ui
+                        .button(RichText::new("import report").size(20.0))
+                        .clicked()
                     {
-                        egui::SidePanel::left(format!("{} difficulty block", DIFFICULTY_NAMES[i]))
-                            .show_inside(ui, |ui| {
-                                ui.selectable_value(
-                                    &mut self.difficulty,
-                                    i,
-                                    RichText::new(format!(
-                                        "{}\n{}★",
-                                        DIFFICULTY_NAMES[i], difficulty.star_level
-                                    ))
-                                    .size(20.0),
-                                );
-                            });
+                        self.show_import_report = true;
                     }
-                });
 
-                if ui.button(RichText::new("Play!").size(17.0)).clicked() {
-                    self.go_to_song = Some((song_index, self.difficulty));
-                }
+                    ui.add_space(10.0);
+
+                    if ui.button(RichText::new("collections").size(20.0)).clicked() {
+                        self.show_collections = true;
+                    }
+
+                    ui.add_space(10.0);
+
+                    if ui
+                        .button(RichText::new("rescan songs").size(20.0))
+                        .clicked()
+                    {
+                        self.rescan = true;
+                    }
+                });
             });
-        }
+
+        self.difficulty_select_ui(ctx);
+        self.import_report_ui(ctx);
+        self.collections_ui(ctx);
+        self.load_failure_ui(ctx);
+        self.device_prompt_ui(ctx);
+        self.toast_ui(ctx);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::Difficulty;
+
+    fn song(title: &str, folder: &str, genre: Option<&str>, level: u8) -> Song {
+        let mut song = Song {
+            title: title.to_string(),
+            genre: genre.map(str::to_string),
+            chart_path: Some(PathBuf::from(format!("songs/{folder}/{title}/{title}.tja"))),
+            ..Default::default()
+        };
+        song.difficulties[3] = Some(Difficulty {
+            star_level: level,
+            estimated_level: level as f32,
+            notes_designer: None,
+            chart: Default::default(),
+            player_charts: None,
+        });
+        song
+    }
+
+    #[test]
+    fn test_step_speed() {
+        assert_eq!(step_speed(1.0, true), 1.25);
+        assert_eq!(step_speed(1.0, false), 0.75);
+        assert_eq!(step_speed(2.0, true), 2.0);
+        assert_eq!(step_speed(0.75, false), 0.75);
+    }
+
+    #[test]
+    fn test_play_options() {
+        let picked = PlayOptions {
+            autoplay: true,
+            ghost_difficulty: Some(2),
+            ..Default::default()
+        };
+
+        // Turning autoplay on reaches the scene
+        let play = picked.for_play(3, false);
+        assert!(play.autoplay);
+        assert_eq!(play.ghost_difficulty, Some(2));
+
+        // Ghosts of the chart being played are left out
+        assert_eq!(picked.for_play(2, false).ghost_difficulty, None);
+
+        // Replays are played back the way they were played, and two players ignore the rest
+        assert_eq!(picked.for_play(3, true), PlayOptions::default());
+        let versus = PlayOptions {
+            versus: true,
+            ..picked
+        };
+        assert_eq!(
+            versus.for_play(3, false),
+            PlayOptions {
+                versus: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_song_groups() {
+        let songs = [
+            song("b", "anime", None, 8),
+            song("A", "anime", Some("Pops"), 5),
+            // Songs straight in the songs folder don't have a folder to be grouped by
+            Song {
+                chart_path: Some(PathBuf::from("songs/c/c.tja")),
+                ..song("c", "", None, 10)
+            },
+            song("d", "anime", None, 3),
+        ];
+        let songs_dir = Path::new("songs");
+        let best_score = |id| [Some(500), None, Some(900), Some(700)][id];
+        let last_played = |id| [Some(30), Some(10), None, Some(20)][id];
+
+        let search = SearchQuery::default();
+        let order = |sort| {
+            let groups = song_groups(
+                &songs,
+                sort,
+                false,
+                songs_dir,
+                &search,
+                best_score,
+                last_played,
+            );
+            assert_eq!(groups.len(), 1);
+            groups[0].songs.clone()
+        };
+        assert_eq!(order(SongSort::Title), [1, 0, 2, 3]);
+        assert_eq!(order(SongSort::Level), [3, 1, 0, 2]);
+        assert_eq!(order(SongSort::RecentlyPlayed), [0, 3, 1, 2]);
+        assert_eq!(order(SongSort::BestScore), [2, 3, 0, 1]);
+
+        let groups = song_groups(
+            &songs,
+            SongSort::Title,
+            true,
+            songs_dir,
+            &search,
+            best_score,
+            last_played,
+        );
+        let group = |genre: &str, songs: Vec<usize>| SongGroup {
+            genre: Some(genre.to_string()),
+            songs,
+        };
+        assert_eq!(
+            groups,
+            [
+                group("Pops", vec![1]),
+                group("anime", vec![0, 3]),
+                group(OTHER_GENRE, vec![2]),
+            ]
+        );
+
+        // Only the songs that match the search are listed, and genres without any are left out
+        let groups = song_groups(
+            &songs,
+            SongSort::Title,
+            true,
+            songs_dir,
+            &SearchQuery::new("D"),
+            best_score,
+            last_played,
+        );
+        assert_eq!(groups, [group("anime", vec![3])]);
+    }
+}
+
+#[cfg(all(test, feature = "visual-tests"))]
+mod visual_test {
+    use super::*;
+    use crate::notechart_parser::parse_tja_file;
+    use crate::render::visual_test::{assert_matches_golden, VisualTest};
+
+    #[test]
+    fn test_song_select() {
+        let mut test = VisualTest::new();
+        let mut select = SongSelect::new(
+            &mut test.textures,
+            &mut test.renderer,
+            &mut Tasks::default(),
+        )
+        .unwrap();
+
+        // The songs come from the fixtures rather than the songs folder, and the player's own
+        // collections are left out
+        select.scan = None;
+        select.collections = Collections::default();
+        select.songs = [
+            include_str!("../../tests/fixtures/visual/note_field.tja"),
+            include_str!("../../tests/fixtures/tja/balloon_edge_cases.tja"),
+            include_str!("../../tests/fixtures/tja/inline_commands.tja"),
+            include_str!("../../tests/fixtures/tja/empty_measures_bpm_change.tja"),
+        ]
+        .into_iter()
+        .map(|chart| parse_tja_file(chart).unwrap())
+        .collect();
+        select.selected = Some(0);
+        select.difficulty = 3;
+        select.difficulty_select.update(Some((0, &select.songs[0])));
+        select
+            .difficulty_badges
+            .update(Some((0, &select.songs[0])), 3, &mut test.renderer)
+            .unwrap();
+
+        let image = test.render(
+            &mut select,
+            |select, ctx| select.menu_ui(ctx),
+            |select, ctx| select.render(ctx),
+        );
+        assert_matches_golden("song_select", &image);
     }
 }
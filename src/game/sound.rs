@@ -0,0 +1,252 @@
+//! The music and sound effects: the menu music, the sounds the menus make when they're used, and
+//! the sounds of the drum during gameplay.
+//!
+//! Everything is played through one of two tracks, so that music and sound effects can be turned
+//! up and down separately (see [Sounds::set_volumes]). Songs themselves go through the music
+//! track too.
+//!
+//! The sound effects are loaded from [SOUNDS_PATH] when the game starts, so that nothing has to be
+//! read from disk when the drum is hit in the middle of a song. The menu music is only
+//! played if there's a file for it there ([MENU_MUSIC_FILE]), since the game doesn't come with any.
+use std::collections::HashMap;
+use std::path::Path;
+
+use kira::{
+    sound::{
+        static_sound::StaticSoundData,
+        streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
+        FromFileError, PlaybackState,
+    },
+    track::{TrackBuilder, TrackHandle},
+    tween::Tween,
+};
+
+use crate::{
+    audio::AudioManager,
+    settings::{GameSettings, HitSoundVolumes},
+};
+
+/// The folder sounds are loaded from.
+pub const SOUNDS_PATH: &str = "assets/sounds";
+
+/// The file in [SOUNDS_PATH] that's played on a loop in the menus, if it's there.
+pub const MENU_MUSIC_FILE: &str = "menu_music.ogg";
+
+/// The sound effects that can be played with [Sounds::play].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    /// Moving onto something different in a menu
+    Cursor,
+    /// Choosing something in a menu
+    Select,
+    /// Leaving a menu
+    Back,
+    Don,
+    Kat,
+    /// Hitting a big note or big drumroll
+    BigDon,
+    BigKat,
+    BalloonPop,
+    /// Reaching a combo milestone, like 100 combo
+    ComboMilestone,
+}
+
+impl SoundEffect {
+    pub const ALL: [SoundEffect; 9] = [
+        SoundEffect::Cursor,
+        SoundEffect::Select,
+        SoundEffect::Back,
+        SoundEffect::Don,
+        SoundEffect::Kat,
+        SoundEffect::BigDon,
+        SoundEffect::BigKat,
+        SoundEffect::BalloonPop,
+        SoundEffect::ComboMilestone,
+    ];
+
+    /// The name of the effect's file in [SOUNDS_PATH].
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            SoundEffect::Cursor => "cursor.wav",
+            SoundEffect::Select => "select.wav",
+            SoundEffect::Back => "back.wav",
+            SoundEffect::Don => "don.wav",
+            SoundEffect::Kat => "kat.wav",
+            SoundEffect::BigDon => "big_don.wav",
+            SoundEffect::BigKat => "big_kat.wav",
+            SoundEffect::BalloonPop => "balloon_pop.wav",
+            SoundEffect::ComboMilestone => "combo_milestone.wav",
+        }
+    }
+
+    /// How loud the effect should be, on top of the sound effect volume.
+    pub fn volume(&self, hit_volumes: &HitSoundVolumes) -> f32 {
+        match self {
+            SoundEffect::Cursor
+            | SoundEffect::Select
+            | SoundEffect::Back
+            | SoundEffect::ComboMilestone => 1.0,
+            SoundEffect::Don => hit_volumes.don,
+            SoundEffect::Kat => hit_volumes.kat,
+            SoundEffect::BigDon => hit_volumes.big_don,
+            SoundEffect::BigKat => hit_volumes.big_kat,
+            SoundEffect::BalloonPop => hit_volumes.balloon_pop,
+        }
+    }
+
+    fn load(&self) -> Result<StaticSoundData, FromFileError> {
+        StaticSoundData::from_file(
+            Path::new(SOUNDS_PATH).join(self.file_name()),
+            Default::default(),
+        )
+    }
+}
+
+/// The music and sound effect tracks, and the sound effects to play on them.
+pub struct Sounds {
+    music: TrackHandle,
+    effects: TrackHandle,
+    samples: HashMap<SoundEffect, StaticSoundData>,
+    hit_volumes: HitSoundVolumes,
+    menu_music: Option<StreamingSoundHandle<FromFileError>>,
+    /// Whether the menu music couldn't be played, so that it isn't tried again every frame
+    menu_music_failed: bool,
+}
+
+impl Sounds {
+    /// Creates the tracks and loads every sound effect. An effect that can't be loaded is left
+    /// silent rather than stopping the game from starting.
+    pub fn new(audio: &mut AudioManager, settings: &GameSettings) -> anyhow::Result<Self> {
+        let music = audio.add_sub_track(TrackBuilder::new())?;
+        let effects = audio.add_sub_track(TrackBuilder::new())?;
+
+        let samples = SoundEffect::ALL
+            .into_iter()
+            .filter_map(|effect| match effect.load() {
+                Ok(data) => Some((effect, data)),
+                Err(e) => {
+                    log::error!("couldn't load sound {}: {e}", effect.file_name());
+                    None
+                }
+            })
+            .collect();
+
+        let mut sounds = Self {
+            music,
+            effects,
+            samples,
+            hit_volumes: settings.hit_sound_volumes,
+            menu_music: None,
+            menu_music_failed: false,
+        };
+        sounds.set_volumes(audio, settings);
+
+        Ok(sounds)
+    }
+
+    /// The track music (including songs) should be played on.
+    pub fn music_track(&self) -> &TrackHandle {
+        &self.music
+    }
+
+    /// Sets the volume of everything to what the settings say.
+    pub fn set_volumes(&mut self, audio: &mut AudioManager, settings: &GameSettings) {
+        self.hit_volumes = settings.hit_sound_volumes;
+
+        let results = [
+            audio
+                .main_track()
+                .set_volume(settings.master_volume as f64, Tween::default()),
+            self.music
+                .set_volume(settings.music_volume as f64, Tween::default()),
+            self.effects
+                .set_volume(settings.effects_volume as f64, Tween::default()),
+        ];
+
+        for result in results {
+            if let Err(e) = result {
+                log::error!("couldn't set the volume: {e}");
+            }
+        }
+    }
+
+    /// Plays a sound effect.
+    pub fn play(&self, audio: &mut AudioManager, effect: SoundEffect) {
+        self.play_scaled(audio, effect, 1.0);
+    }
+
+    /// Plays a sound effect louder or quieter than usual, e.g. for a song that asks for the drum
+    /// to be quieter.
+    pub fn play_scaled(&self, audio: &mut AudioManager, effect: SoundEffect, scale: f32) {
+        let Some(data) = self.samples.get(&effect) else {
+            return;
+        };
+
+        let volume = (effect.volume(&self.hit_volumes) * scale) as f64;
+        let data = data.with_modified_settings(|settings| {
+            settings.output_destination(&self.effects).volume(volume)
+        });
+
+        if let Err(e) = audio.play(data) {
+            log::error!("couldn't play sound {}: {e}", effect.file_name());
+        }
+    }
+
+    /// Starts the menu music, unless it's already playing.
+    pub fn play_menu_music(&mut self, audio: &mut AudioManager) {
+        let playing = self
+            .menu_music
+            .as_ref()
+            .is_some_and(|handle| handle.state() != PlaybackState::Stopped);
+
+        if playing || self.menu_music_failed {
+            return;
+        }
+
+        let path = Path::new(SOUNDS_PATH).join(MENU_MUSIC_FILE);
+        if !path.exists() {
+            log::info!("there's no menu music, so the menus will be quiet");
+            self.menu_music_failed = true;
+            return;
+        }
+
+        let settings = StreamingSoundSettings::default()
+            .loop_region(..)
+            .output_destination(&self.music)
+            .fade_in_tween(Some(Tween::default()));
+
+        let result = StreamingSoundData::from_file(path, settings)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(audio.play(data)?));
+
+        match result {
+            Ok(handle) => self.menu_music = Some(handle),
+            Err(e) => {
+                log::error!("couldn't play the menu music: {e}");
+                self.menu_music_failed = true;
+            }
+        }
+    }
+
+    /// Fades the menu music out, if it's playing.
+    pub fn stop_menu_music(&mut self) {
+        if let Some(mut handle) = self.menu_music.take() {
+            if let Err(e) = handle.stop(Tween::default()) {
+                log::error!("couldn't stop the menu music: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sound_effects_load() {
+        for effect in SoundEffect::ALL {
+            let data = effect.load().unwrap();
+            assert!(data.duration().as_secs_f64() > 0.0, "{effect:?}");
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Keeping the note clock in line with the song's audio.
+//!
+//! The notes are timed with an [Instant](std::time::Instant) rather than the audio's position, as
+//! the position only moves once per audio buffer and notes timed with it would stutter. Both move
+//! at the same speed, so normally the gap between them hardly changes. But if the audio thread
+//! can't keep up (an underrun), the audio skips and falls behind, and without correcting for it the
+//! notes would be off from the music for the rest of the song.
+
+/// How much the gap between the audio and the note clock has to change between two observations
+/// for it to count as the audio skipping, in seconds. The audio's position jumping around by a
+/// buffer at a time is well under this.
+pub const SKIP_THRESHOLD: f64 = 0.05;
+
+/// Watches the gap between where the audio is and where the note clock says it should be, and
+/// notices when it suddenly jumps.
+///
+/// The gap slowly drifting is left alone: only a jump of more than [SKIP_THRESHOLD] from one
+/// observation to the next is treated as a skip.
+#[derive(Debug, Default)]
+pub struct SkipDetector {
+    /// The gap between the audio and the clock the last time they were observed, in seconds.
+    last_gap: Option<f64>,
+    /// How many skips have been detected.
+    skips: usize,
+}
+
+impl SkipDetector {
+    /// Compares where the audio is with where the clock is (both in real seconds through the song).
+    /// If the audio has skipped, returns how many seconds to add to the clock to put it back in line
+    /// with the audio.
+    ///
+    /// The clock is only moved by the size of the jump, not all the way to the audio's position,
+    /// so that the usual small gap between them (from the audio being reported a buffer at a time)
+    /// is kept.
+    pub fn observe(&mut self, audio_position: f64, clock_position: f64) -> Option<f64> {
+        let gap = audio_position - clock_position;
+
+        let Some(last_gap) = self.last_gap else {
+            self.last_gap = Some(gap);
+            return None;
+        };
+
+        let jump = gap - last_gap;
+
+        if jump.abs() > SKIP_THRESHOLD {
+            // Once the clock is corrected, the gap is back to what it was
+            self.skips += 1;
+            Some(jump)
+        } else {
+            self.last_gap = Some(gap);
+            None
+        }
+    }
+
+    /// Forgets the gap, for when the audio and the clock stop and start again separately, like
+    /// after pausing or seeking. The next observation starts afresh.
+    pub fn reset(&mut self) {
+        self.last_gap = None;
+    }
+
+    pub fn skips(&self) -> usize {
+        self.skips
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Feeds the detector observations every 1/60th of a second, with the audio's position moving
+    /// a 10ms buffer at a time. The audio stalls for `stall` seconds at `stall_at`, and the clock is
+    /// corrected whenever a skip is detected. Returns the corrections made.
+    fn run(stall_at: f64, stall: f64, drift_per_second: f64) -> (Vec<f64>, SkipDetector) {
+        let mut detector = SkipDetector::default();
+        let mut corrections = Vec::new();
+        let mut clock_adjustment = 0.0;
+
+        for frame in 0..600 {
+            let time = frame as f64 / 60.0;
+
+            let mut audio = time * (1.0 + drift_per_second);
+            if time >= stall_at {
+                audio -= stall;
+            }
+            let audio = (audio / 0.01).floor() * 0.01;
+
+            let clock = time + clock_adjustment;
+            if let Some(correction) = detector.observe(audio, clock) {
+                clock_adjustment += correction;
+                corrections.push(correction);
+            }
+        }
+
+        (corrections, detector)
+    }
+
+    #[test]
+    fn test_steady_audio_is_left_alone() {
+        let (corrections, detector) = run(f64::INFINITY, 0.0, 0.0);
+        assert!(corrections.is_empty());
+        assert_eq!(detector.skips(), 0);
+
+        // Drifting slowly apart isn't a skip either
+        let (corrections, _) = run(f64::INFINITY, 0.0, 0.005);
+        assert!(corrections.is_empty());
+    }
+
+    #[test]
+    fn test_skips_are_corrected_once() {
+        let (corrections, detector) = run(4.0, 0.2, 0.0);
+        assert_eq!(detector.skips(), 1);
+        assert_eq!(corrections.len(), 1);
+        assert!((corrections[0] + 0.2).abs() < 0.02, "{corrections:?}");
+
+        // A stall just under the threshold is too small to tell apart from the usual jitter
+        let (corrections, _) = run(4.0, 0.03, 0.0);
+        assert!(corrections.is_empty());
+    }
+
+    #[test]
+    fn test_reset_starts_afresh() {
+        let mut detector = SkipDetector::default();
+        assert_eq!(detector.observe(1.0, 1.0), None);
+
+        // After seeking, the gap can be anything
+        detector.reset();
+        assert_eq!(detector.observe(5.0, 4.5), None);
+        assert_eq!(detector.observe(5.1, 4.6), None);
+
+        let correction = detector.observe(5.2, 4.9).unwrap();
+        assert!((correction + 0.2).abs() < 1e-9);
+        assert_eq!(detector.skips(), 1);
+    }
+}
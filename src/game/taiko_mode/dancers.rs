@@ -0,0 +1,113 @@
+//! Dancers below the note field, who dance along to the beat of the song and get twice as excited
+//! in go-go time.
+//!
+//! The game doesn't come with any dancers, so they only appear when the skin has the frames of
+//! their dance ([DANCER_TEXTURES]). Each frame lasts a fraction of a beat at the tempo the chart is
+//! at, so the dance keeps up with tempo changes.
+use wgpu::RenderPass;
+
+use crate::game::TextureCache;
+use crate::notechart_parser::NoteChart;
+use crate::render::texture::{AnimatedSprite, AnimatedSpriteBuilder, Frame, PlaybackState};
+use crate::render::{Renderable, Renderer};
+
+/// The frames of the dance, in order. The dance loops back round to the start once it's done.
+const DANCER_TEXTURES: [&str; 4] = [
+    "dancer 1.png",
+    "dancer 2.png",
+    "dancer 3.png",
+    "dancer 4.png",
+];
+
+/// Where the dancers' feet are.
+const DANCER_XS: [f32; 3] = [560., 960., 1360.];
+const DANCER_Y: f32 = 960.;
+
+/// How many frames of the dance there are to each beat.
+const FRAMES_PER_BEAT: f32 = 2.;
+
+/// How much faster the dance is in go-go time.
+const GOGO_SPEED_UP: f32 = 2.;
+
+/// How long each frame of the dance lasts, in seconds of the chart.
+fn frame_time(bpm: f32, gogo: bool) -> f32 {
+    let speed_up = if gogo { GOGO_SPEED_UP } else { 1. };
+    60. / bpm / FRAMES_PER_BEAT / speed_up
+}
+
+pub struct Dancers {
+    sprites: Vec<AnimatedSprite>,
+    /// The tempo to dance at if the chart doesn't say what its tempo is
+    default_bpm: f32,
+}
+
+impl Dancers {
+    /// Creates the dancers, or returns None if the skin doesn't have any.
+    pub fn new(renderer: &Renderer, textures: &mut TextureCache, default_bpm: f32) -> Option<Self> {
+        let frames = DANCER_TEXTURES
+            .into_iter()
+            .map(|filename| {
+                let texture = textures.get(&renderer.device, &renderer.queue, filename)?;
+                // Each frame stands on the same spot
+                let (width, height) = texture.dimensions;
+                Ok(Frame::new(texture, [width as f32 / 2., height as f32]))
+            })
+            .collect::<anyhow::Result<Vec<_>>>();
+
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => {
+                log::info!("there won't be any dancers: {e}");
+                return None;
+            }
+        };
+
+        let sprites = DANCER_XS
+            .into_iter()
+            .map(|x| {
+                AnimatedSpriteBuilder::new(frames.clone())
+                    .position([x, DANCER_Y])
+                    .looping(true)
+                    .build(renderer)
+            })
+            .collect();
+
+        Some(Self {
+            sprites,
+            default_bpm,
+        })
+    }
+
+    /// Moves the dance along. `time` is the time in the chart, and `delta_time` is how much of the
+    /// chart has been played since the last update.
+    pub fn update(&mut self, chart: &NoteChart, time: f32, delta_time: f32, renderer: &Renderer) {
+        let bpm = chart.bpm_at(time).unwrap_or(self.default_bpm);
+        let frame_time = frame_time(bpm, chart.is_gogo(time));
+
+        for sprite in &mut self.sprites {
+            sprite.set_playback_state(PlaybackState::Playing { frame_time });
+            sprite.update(delta_time, renderer);
+        }
+    }
+}
+
+impl Renderable for Dancers {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for sprite in &self.sprites {
+            sprite.render(renderer, render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_time() {
+        // Two frames to each beat at 120 BPM
+        assert_eq!(frame_time(120., false), 0.25);
+        assert_eq!(frame_time(120., true), 0.125);
+        assert_eq!(frame_time(60., false), 0.5);
+    }
+}
@@ -0,0 +1,348 @@
+//! Short-lived visual effects during gameplay, like the explosion at the receptacle when a note is
+//! hit and the note flying off towards the soul gauge afterwards.
+//!
+//! Effects are spawned by the scene as things happen, moved along every frame with
+//! [Effects::update], and thrown away once they've finished. Everything they're drawn with is
+//! built up front, so spawning an effect never touches the GPU.
+use std::f32::consts::PI;
+
+use lyon::geom::point;
+use lyon::lyon_tessellation::{BuffersBuilder, StrokeOptions};
+use wgpu::RenderPass;
+
+use super::gameplay::{BasicNoteType, NoteColour, NoteJudgement};
+use super::note::note_textures;
+use super::ui::{mirror_x, NOTE_HIT_X, NOTE_Y};
+use crate::game::TextureCache;
+use crate::notechart_parser::NoteType;
+use crate::render::particles::{Particles, SpawnConfig};
+use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
+use crate::render::texture::{Sprite, SpriteBuilder};
+use crate::render::{rgb, Renderable, Renderer, VIRTUAL_WIDTH};
+use crate::rng::Rng;
+use crate::skin::skin;
+
+/// How long a hit explosion lasts, in seconds.
+const EXPLOSION_DURATION: f32 = 0.25;
+
+/// How many steps the ring of an explosion grows in.
+const EXPLOSION_FRAMES: usize = 8;
+
+/// How much bigger than the note the ring ends up.
+const EXPLOSION_GROWTH: f32 = 0.6;
+
+/// How long a hit note takes to fly off the screen, in seconds.
+const FLYING_NOTE_DURATION: f32 = 0.5;
+
+/// The most notes of each kind that can be flying at once. Drumrolls can be hit fast enough to
+/// reach this, in which case the note that's been flying the longest is taken off early.
+const FLYING_NOTE_LIMIT: usize = 16;
+
+/// The most sparks that can be on screen at once.
+const SPARK_BUDGET: usize = 128;
+
+/// The sparks that fly out of a good hit.
+const GOOD_SPARKS: SpawnConfig = SpawnConfig {
+    count: 10,
+    origin: [NOTE_HIT_X, NOTE_Y],
+    spread: [10., 10.],
+    direction: -PI / 2.,
+    angle_spread: PI,
+    speed: 300.0..650.0,
+    lifetime: 0.15..0.3,
+    width: 5.0..9.0,
+    height: 5.0..9.0,
+    spin: 0.,
+    flip_speed: 0.0..0.0,
+    gravity: 0.,
+    drag: 4.,
+    colours: &[rgb!(0xFF, 0xE0, 0x60), rgb!(0xFF, 0xFF, 0xFF)],
+};
+
+/// An explosion that's still being shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HitExplosion {
+    /// Which set of rings it's drawn with (see [Effects::rings])
+    kind: usize,
+    age: f32,
+}
+
+impl HitExplosion {
+    fn frame(&self) -> usize {
+        ((self.age / EXPLOSION_DURATION * EXPLOSION_FRAMES as f32) as usize)
+            .min(EXPLOSION_FRAMES - 1)
+    }
+}
+
+/// A note that was hit, flying in an arc from the receptacle off the top of the screen, towards
+/// the soul gauge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FlyingNote {
+    /// Which kind of note it is (see [effect_kind])
+    kind: usize,
+    /// Which of the sprites for its kind it's drawn with
+    slot: usize,
+    age: f32,
+}
+
+/// Which kind of note an effect is for, as an index into the shapes and sprites for each kind:
+/// don, kat, big don then big kat.
+fn effect_kind(note: BasicNoteType) -> usize {
+    usize::from(note.colour == NoteColour::Kat) + 2 * usize::from(note.big)
+}
+
+/// The point `t` of the way along a quadratic bezier curve.
+fn bezier(start: [f32; 2], control: [f32; 2], end: [f32; 2], t: f32) -> [f32; 2] {
+    let u = 1. - t;
+    [0, 1].map(|i| u * u * start[i] + 2. * u * t * control[i] + t * t * end[i])
+}
+
+/// Spawns, updates and draws the effects.
+pub struct Effects {
+    /// Every step of the ring of each kind of explosion (see [effect_kind]). Each step is faded
+    /// and positioned when it's built, so any number of explosions can share them.
+    rings: Vec<Vec<Shape>>,
+    sparks: Particles,
+    explosions: Vec<HitExplosion>,
+    /// [FLYING_NOTE_LIMIT] sprites for each kind of note, which flying notes are drawn with
+    note_sprites: Vec<Vec<Sprite>>,
+    flying_notes: Vec<FlyingNote>,
+    /// The control point and end of the path flying notes take from the receptacle
+    flight_path: [[f32; 2]; 2],
+    hit_x: f32,
+}
+
+impl Effects {
+    pub fn new(
+        renderer: &Renderer,
+        textures: &mut TextureCache,
+        rng: Rng,
+        mirrored: bool,
+    ) -> anyhow::Result<Self> {
+        let hit_x = mirror_x(NOTE_HIT_X, mirrored);
+        let colours = skin().colours;
+
+        let rings = [
+            (colours.don.0, 50.),
+            (colours.kat.0, 50.),
+            (colours.don.0, 75.),
+            (colours.kat.0, 75.),
+        ]
+        .into_iter()
+        .map(|(colour, radius)| {
+            (0..EXPLOSION_FRAMES)
+                .map(|frame| ring(renderer, [hit_x, NOTE_Y], colour, radius, frame))
+                .collect()
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+        let note_sprites = [
+            NoteType::Don,
+            NoteType::Kat,
+            NoteType::BigDon,
+            NoteType::BigKat,
+        ]
+        .into_iter()
+        .map(|note_type| {
+            let texture = textures.get(
+                &renderer.device,
+                &renderer.queue,
+                note_textures(note_type)[0],
+            )?;
+
+            Ok((0..FLYING_NOTE_LIMIT)
+                .map(|_| SpriteBuilder::new(texture.clone()).centre().build(renderer))
+                .collect())
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            rings,
+            sparks: Particles::new(renderer, SPARK_BUDGET, rng),
+            explosions: Vec::new(),
+            note_sprites,
+            flying_notes: Vec::new(),
+            // Up and away from the receptacle, then off the top of the screen on the gauge's side
+            flight_path: [
+                [mirror_x(NOTE_HIT_X + 300., mirrored), NOTE_Y - 500.],
+                [mirror_x(VIRTUAL_WIDTH + 100., mirrored), -100.],
+            ],
+            hit_x,
+        })
+    }
+
+    /// Shows that a don or kat note was hit. The note flies off, and good hits get sparks as well
+    /// as the ring.
+    pub fn hit(&mut self, note: BasicNoteType, judgement: NoteJudgement) {
+        if judgement == NoteJudgement::Bad {
+            return;
+        }
+
+        self.explosions.push(HitExplosion {
+            kind: effect_kind(note),
+            age: 0.,
+        });
+        self.fly(note);
+
+        if judgement == NoteJudgement::Good {
+            self.sparks.spawn(&SpawnConfig {
+                origin: [self.hit_x, NOTE_Y],
+                count: if note.big {
+                    GOOD_SPARKS.count * 2
+                } else {
+                    GOOD_SPARKS.count
+                },
+                ..GOOD_SPARKS
+            });
+        }
+    }
+
+    /// Shows that a drumroll was hit, by sending a note of the colour it was hit with flying off.
+    pub fn drumroll(&mut self, roll_note: BasicNoteType) {
+        self.fly(roll_note);
+    }
+
+    fn fly(&mut self, note: BasicNoteType) {
+        let kind = effect_kind(note);
+        let free_slot = (0..FLYING_NOTE_LIMIT).find(|slot| {
+            !self
+                .flying_notes
+                .iter()
+                .any(|flying| flying.kind == kind && flying.slot == *slot)
+        });
+
+        let slot = match free_slot {
+            Some(slot) => slot,
+            None => {
+                // Notes are added in order, so the first one of this kind is the oldest
+                let oldest = self
+                    .flying_notes
+                    .iter()
+                    .position(|flying| flying.kind == kind)
+                    .expect("every slot is taken, so there's a note of this kind flying");
+                self.flying_notes.remove(oldest).slot
+            }
+        };
+
+        self.flying_notes.push(FlyingNote {
+            kind,
+            slot,
+            age: 0.,
+        });
+    }
+
+    /// Moves every effect along, and throws away the ones that have finished.
+    pub fn update(&mut self, delta_time: f32, renderer: &Renderer) {
+        for explosion in &mut self.explosions {
+            explosion.age += delta_time;
+        }
+
+        self.explosions
+            .retain(|explosion| explosion.age < EXPLOSION_DURATION);
+        self.sparks.update(delta_time);
+
+        for flying in &mut self.flying_notes {
+            flying.age += delta_time;
+        }
+
+        self.flying_notes
+            .retain(|flying| flying.age < FLYING_NOTE_DURATION);
+
+        let [control, end] = self.flight_path;
+        for flying in &self.flying_notes {
+            let position = bezier(
+                [self.hit_x, NOTE_Y],
+                control,
+                end,
+                flying.age / FLYING_NOTE_DURATION,
+            );
+            self.note_sprites[flying.kind][flying.slot].set_position(position, renderer);
+        }
+    }
+
+    /// Gets rid of every effect, e.g. after seeking.
+    pub fn clear(&mut self) {
+        self.explosions.clear();
+        self.flying_notes.clear();
+    }
+}
+
+/// One step of the ring of an explosion, which grows and fades as it goes.
+fn ring(
+    renderer: &Renderer,
+    centre: [f32; 2],
+    colour: [f32; 4],
+    radius: f32,
+    frame: usize,
+) -> anyhow::Result<Shape> {
+    let progress = (frame as f32 + 0.5) / EXPLOSION_FRAMES as f32;
+    // Quick at first, then slowing down
+    let growth = 1. - (1. - progress).powi(2);
+    let radius = radius * (1. + EXPLOSION_GROWTH * growth);
+    let [r, g, b, a] = colour;
+    let colour = [r, g, b, a * (1. - progress)];
+
+    Ok(ShapeBuilder::new()
+        .stroke_shape(|tess, out| {
+            let options = StrokeOptions::DEFAULT.with_line_width(12. * (1. - progress) + 2.);
+            tess.tessellate_circle(
+                point(0., 0.),
+                radius,
+                &options,
+                &mut BuffersBuilder::new(out, SolidColour::new(colour)),
+            )?;
+            Ok(())
+        })?
+        .position([centre[0], centre[1], 0.])
+        .build(&renderer.device))
+}
+
+impl Renderable for Effects {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for explosion in &self.explosions {
+            self.rings[explosion.kind][explosion.frame()].render(renderer, render_pass);
+        }
+
+        self.sparks.render(renderer, render_pass);
+
+        for flying in &self.flying_notes {
+            self.note_sprites[flying.kind][flying.slot].render(renderer, render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_explosion_frames() {
+        let mut explosion = HitExplosion { kind: 0, age: 0. };
+        assert_eq!(explosion.frame(), 0);
+
+        explosion.age = EXPLOSION_DURATION / 2.;
+        assert_eq!(explosion.frame(), EXPLOSION_FRAMES / 2);
+
+        // The last frame stays up until the explosion is thrown away
+        explosion.age = EXPLOSION_DURATION;
+        assert_eq!(explosion.frame(), EXPLOSION_FRAMES - 1);
+
+        let kinds = [
+            (NoteColour::Don, false),
+            (NoteColour::Kat, false),
+            (NoteColour::Don, true),
+            (NoteColour::Kat, true),
+        ]
+        .map(|(colour, big)| effect_kind(BasicNoteType { colour, big }));
+        assert_eq!(kinds, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_bezier() {
+        let (start, control, end) = ([0., 0.], [100., 0.], [100., 100.]);
+
+        assert_eq!(bezier(start, control, end, 0.), start);
+        assert_eq!(bezier(start, control, end, 1.), end);
+        assert_eq!(bezier(start, control, end, 0.5), [75., 25.]);
+    }
+}
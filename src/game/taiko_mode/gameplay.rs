@@ -0,0 +1,2049 @@
+//! The rules of taiko mode, kept separate from anything visual.
+//!
+//! [GameplayCore] keeps track of which notes have been hit, judges the player's inputs and keeps
+//! the play result and soul gauge up to date. It doesn't know anything about sprites or audio, so
+//! the scene tells it what the player pressed and when, and then updates its visuals according to
+//! the [GameplayEvent]s it gets back.
+//!
+//! Charts with branches have the notes of every branch in the core, but only the notes of the
+//! branch the player is on are in play. The rest can't be hit or missed, and the scene hides them.
+use std::ops::Range;
+
+use crate::difficulty::DifficultyInfo;
+use crate::notechart_parser::{Barline, Branch, BranchCondition, Note, NoteChart, NoteType};
+use crate::render::VIRTUAL_HEIGHT;
+use crate::settings::{DrumInput, RulesConfig};
+
+use super::health::{Health, HealthInt};
+use super::note::{BAD, GOOD, OK};
+use super::scoring::NoteScorer;
+use super::stats::{
+    best_roll_rate, best_single_input_roll_rate, hand_stats, hit_error_stats, mean_roll_rate,
+    HandStats, HitErrorStats, HitRecord, RollRecord,
+};
+use super::ui::NOTE_FIELD_HEIGHT;
+
+pub type ScoreInt = u64;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NoteJudgement {
+    Bad,
+    Ok,
+    Good,
+}
+
+impl NoteJudgement {
+    fn from_offset(offset: f64, timing_windows: &[f64; 3]) -> Option<Self> {
+        let abs_offset = offset.abs();
+        if abs_offset < timing_windows[GOOD] {
+            Some(Self::Good)
+        } else if abs_offset < timing_windows[OK] {
+            Some(Self::Ok)
+        } else if abs_offset < timing_windows[BAD] {
+            Some(Self::Bad)
+        } else {
+            None
+        }
+    }
+}
+
+impl NoteJudgement {
+    pub fn index(&self) -> usize {
+        match self {
+            NoteJudgement::Bad => BAD,
+            NoteJudgement::Ok => OK,
+            NoteJudgement::Good => GOOD,
+        }
+    }
+}
+
+/// A record containing statistics about how the player has done.
+///
+/// This struct will slowly collate data as the game progresses, and will be passed to the score
+/// screen at the end.
+///
+/// Contains more information than is usually collected in taiko games. I want this sim to be able
+/// to display a bunch of interesting gameplay statistics, and all that will be stored here.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct PlayResult {
+    /// A vector containing the judgements for every note recorded.
+    /// A None value indicates a miss.
+    judgements: Vec<Option<NoteJudgement>>,
+    drumrolls: u64,
+    score: ScoreInt,
+    current_combo: usize,
+    max_combo: usize,
+    /// For all the notes that were hit (good, okay, or bad), records the difference between when
+    /// the note was hit and when the note should have been hit, and what it was hit with.
+    hits: Vec<HitRecord>,
+    /// Every hit on each drumroll and balloon, in the order they were played.
+    rolls: Vec<RollRecord>,
+}
+
+impl PlayResult {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn current_combo(&self) -> usize {
+        self.current_combo
+    }
+
+    fn push_judgement(&mut self, judgement: Option<NoteJudgement>) {
+        self.judgements.push(judgement);
+
+        if matches!(
+            judgement,
+            Some(NoteJudgement::Good) | Some(NoteJudgement::Ok)
+        ) {
+            self.current_combo += 1;
+            self.max_combo = std::cmp::max(self.current_combo, self.max_combo);
+        } else {
+            self.current_combo = 0;
+        }
+    }
+
+    fn count_for_judgement(&self, judgement: Option<NoteJudgement>) -> usize {
+        self.judgements.iter().filter(|j| **j == judgement).count()
+    }
+
+    pub fn goods(&self) -> usize {
+        self.count_for_judgement(Some(NoteJudgement::Good))
+    }
+
+    pub fn okays(&self) -> usize {
+        self.count_for_judgement(Some(NoteJudgement::Ok))
+    }
+
+    pub fn bads(&self) -> usize {
+        self.count_for_judgement(Some(NoteJudgement::Bad))
+    }
+
+    pub fn misses(&self) -> usize {
+        self.count_for_judgement(None)
+    }
+
+    /// Whether every note was hit without breaking the combo (i.e. no bads or misses). A song with
+    /// no notes doesn't count.
+    pub fn is_full_combo(&self) -> bool {
+        !self.judgements.is_empty() && self.bads() == 0 && self.misses() == 0
+    }
+
+    pub fn drumrolls(&self) -> u64 {
+        self.drumrolls
+    }
+
+    pub fn rolls(&self) -> &[RollRecord] {
+        &self.rolls
+    }
+
+    /// The average speed the player hit drumrolls and balloons at, in hits per second.
+    pub fn mean_roll_rate(&self) -> Option<f32> {
+        mean_roll_rate(&self.rolls)
+    }
+
+    /// The fastest speed the player hit any single drumroll or balloon at, in hits per second.
+    pub fn best_roll_rate(&self) -> Option<f32> {
+        best_roll_rate(&self.rolls)
+    }
+
+    /// The fastest any drumroll or balloon was hit with one key, in hits per second. See
+    /// [best_single_input_roll_rate].
+    pub fn best_single_input_roll_rate(&self) -> Option<f32> {
+        best_single_input_roll_rate(&self.rolls)
+    }
+
+    /// Every hit on a don or kat note, in the order they were made.
+    pub fn hits(&self) -> &[HitRecord] {
+        &self.hits
+    }
+
+    /// How early or late the player hit notes, and how consistently.
+    pub fn hit_error_stats(&self) -> Option<HitErrorStats> {
+        hit_error_stats(&self.hits)
+    }
+
+    /// How the player's hits on don and kat notes were shared between their hands.
+    pub fn hand_stats(&self) -> Option<HandStats> {
+        hand_stats(self.hits.iter().map(|hit| hit.input))
+    }
+
+    pub fn max_combo(&self) -> usize {
+        self.max_combo
+    }
+
+    pub fn score(&self) -> ScoreInt {
+        self.score
+    }
+
+    /// Which parts of this result are different in `other`, by name. Nothing is left out, so two
+    /// results with no differences are exactly the same.
+    pub fn differences(&self, other: &PlayResult) -> Vec<&'static str> {
+        [
+            ("score", self.score != other.score),
+            ("judgements", self.judgements != other.judgements),
+            ("hit timing", self.hits != other.hits),
+            ("drumrolls", self.drumrolls != other.drumrolls),
+            ("roll hits", self.rolls != other.rolls),
+            ("max combo", self.max_combo != other.max_combo),
+            ("combo", self.current_combo != other.current_combo),
+        ]
+        .into_iter()
+        .filter(|&(_, differs)| differs)
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// The player's accuracy as a percentage, where a Good counts for a full note and an Ok counts
+    /// for half a note. Returns 100% if there were no notes to judge.
+    pub fn accuracy(&self) -> f32 {
+        if self.judgements.is_empty() {
+            return 100.0;
+        }
+
+        let weighted = self.goods() as f32 + self.okays() as f32 * 0.5;
+        weighted / self.judgements.len() as f32 * 100.0
+    }
+}
+
+/// Returns the timing windows to use for a difficulty.
+pub fn timing_windows(difficulty: usize) -> [f64; 3] {
+    DifficultyInfo::timing_windows_of(difficulty)
+}
+
+/// Whether notes of the given type take part in gameplay at all.
+///
+/// The scene only creates visuals for these notes, and [GameplayCore] only keeps track of these
+/// notes, so that the index of a note is the same in both.
+pub fn is_playable(note_type: NoteType) -> bool {
+    // TODO: Special rolls aren't supported yet
+    !matches!(note_type, NoteType::SpecialRoll(..))
+}
+
+// I wonder if these two types could fit into the parser module
+// They're obviously pretty important but, it seems they're not that useful in the parser module
+// itself, since that module has the more general NoteType enum.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NoteColour {
+    Don,
+    Kat,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BasicNoteType {
+    pub colour: NoteColour,
+    pub big: bool,
+}
+
+impl BasicNoteType {
+    fn is_hit_by(&self, input: DrumInput) -> bool {
+        match self.colour {
+            NoteColour::Don => input.is_don(),
+            NoteColour::Kat => !input.is_don(),
+        }
+    }
+}
+
+impl TryFrom<NoteType> for BasicNoteType {
+    type Error = ();
+
+    fn try_from(value: NoteType) -> Result<Self, Self::Error> {
+        match value {
+            NoteType::Don | NoteType::CoopDon => Ok(Self {
+                colour: NoteColour::Don,
+                big: false,
+            }),
+            NoteType::Kat | NoteType::CoopKat => Ok(Self {
+                colour: NoteColour::Kat,
+                big: false,
+            }),
+            NoteType::BigDon => Ok(Self {
+                colour: NoteColour::Don,
+                big: true,
+            }),
+            NoteType::BigKat => Ok(Self {
+                colour: NoteColour::Kat,
+                big: true,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Different ways a note can respond to a keypress
+/// See [CoreNote::receive_input]
+#[derive(Debug, Copy, Clone)]
+enum NoteKeypressReaction {
+    /// Don was pressed but this note is Kat, or vice versa
+    /// Basically, do absolutely nothing.
+    WrongColour,
+    /// The keypress is too early, so the note is not yet able to be hit.
+    ///
+    /// *This variant is more important than WrongColour*. If a keypress is both too early and the
+    /// wrong colour, this is the one you should return, since the calling code uses this variant
+    /// to determine where to stop calling [CoreNote::receive_input]
+    TooEarly,
+    /// The note was hit, with the given time offset
+    ///
+    /// The offset is calculated as input_time - note_time. That is to say, it is *relative to the
+    /// note time*. For example, if you hit 15ms before you should have, the offset will be -0.015,
+    /// that is to say, 0.015 seconds *early*.
+    Hit { offset: f64 },
+    /// The note was hit, and is a drumroll.
+    Drumroll { roll_note: BasicNoteType },
+    /// The note was hit, and is a balloon.
+    BalloonRoll { hits_left: u32, hit_target: u32 },
+    /// The note cannot be hit anymore.
+    TooLate,
+}
+
+/// The state of a note as far as the rules are concerned.
+#[derive(Debug, Clone)]
+enum NoteState {
+    Note {
+        kind: BasicNoteType,
+        /// Whether the note has been hit or missed already
+        judged: bool,
+    },
+    Roll {
+        big: bool,
+        duration: f64,
+    },
+    Balloon {
+        hit_target: u32,
+        hits_left: u32,
+        duration: f64,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct CoreNote {
+    state: NoteState,
+    time: f64,
+    /// The branched section and branch the note is in, if it's in one.
+    branch: Option<(usize, Branch)>,
+    /// Whether the note is on the branch being played. Notes that aren't can't be hit or missed.
+    in_play: bool,
+    /// What the note was hit with, if it's a don or kat note that's been hit.
+    judgement: Option<NoteJudgement>,
+    /// Whether the note is in go-go time, which can make it worth more (see [NoteScorer]).
+    gogo: bool,
+}
+
+impl CoreNote {
+    fn new(note: &Note) -> Option<Self> {
+        let state = match note.note_type {
+            NoteType::Don
+            | NoteType::Kat
+            | NoteType::BigDon
+            | NoteType::CoopDon
+            | NoteType::BigKat
+            | NoteType::CoopKat => NoteState::Note {
+                kind: note.note_type.try_into().unwrap(),
+                judged: false,
+            },
+
+            NoteType::Roll(duration) | NoteType::BigRoll(duration) => NoteState::Roll {
+                big: matches!(note.note_type, NoteType::BigRoll(_)),
+                duration: duration as f64,
+            },
+
+            NoteType::BalloonRoll(duration, hit_target) => NoteState::Balloon {
+                hit_target,
+                hits_left: hit_target,
+                duration: duration as f64,
+            },
+
+            NoteType::SpecialRoll(..) => return None,
+        };
+
+        Some(Self {
+            state,
+            time: note.time,
+            branch: None,
+            in_play: true,
+            judgement: None,
+            gogo: false,
+        })
+    }
+
+    /// Whether this note is a don/kat note that awards judgement and must be hit.
+    fn is_don_or_kat(&self) -> bool {
+        matches!(self.state, NoteState::Note { .. })
+    }
+
+    /// Whether the note is (or will at some point be) hittable.
+    ///
+    /// When checking if a note has been hit by the player, we start checking from the first
+    /// hittable note. If the note can be hit now or at some point in the future, it is considered
+    /// "hittable". If it is past its time, however, it is not hittable.
+    ///
+    /// Notes on other branches can't really be hit ([GameplayCore::press] passes over them), but they count as
+    /// hittable until they've gone by, since their branch might still be chosen before then.
+    fn is_hittable(&self, time: f64, timing_windows: &[f64; 3]) -> bool {
+        if !self.in_play {
+            return self.time + timing_windows[BAD] > time;
+        }
+
+        match self.state {
+            NoteState::Note { judged, .. } => {
+                // If the note is hit (or missed), obviously it won't be hittable again.
+                // If the latest the note could ever be hit is later than the current time, then
+                // there's still a chance it's hittable.
+                !judged && self.time + timing_windows[BAD] > time
+            }
+            NoteState::Roll { duration, .. } => self.time + duration > time,
+            NoteState::Balloon {
+                duration,
+                hits_left,
+                ..
+            } => hits_left > 0 && self.time + duration > time,
+        }
+    }
+
+    /// Whether this is a drumroll or balloon that can be hit at the given time.
+    fn is_active_roll(&self, time: f64) -> bool {
+        if !self.in_play {
+            return false;
+        }
+
+        match self.state {
+            NoteState::Note { .. } => false,
+            NoteState::Roll { duration, .. } => self.time <= time && time < self.time + duration,
+            NoteState::Balloon {
+                duration,
+                hits_left,
+                ..
+            } => hits_left > 0 && self.time <= time && time <= self.time + duration,
+        }
+    }
+
+    /// Whether this is a don/kat note that would be hit by the given input at the given time.
+    /// Unlike [CoreNote::receive_input], this doesn't change the note.
+    fn would_be_hit_by(&self, input: DrumInput, time: f64, timing_windows: &[f64; 3]) -> bool {
+        match self.state {
+            NoteState::Note { kind, judged } => {
+                self.in_play
+                    && !judged
+                    && (time - self.time).abs() < timing_windows[BAD]
+                    && kind.is_hit_by(input)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reacts to an input.
+    fn receive_input(
+        &mut self,
+        input: DrumInput,
+        time: f64,
+        timing_windows: &[f64; 3],
+    ) -> NoteKeypressReaction {
+        if !self.is_hittable(time, timing_windows) {
+            return NoteKeypressReaction::TooLate;
+        }
+
+        match &mut self.state {
+            NoteState::Note { kind, judged } => {
+                if self.time - timing_windows[BAD] > time {
+                    // If the earliest the note could ever be hit is later (greater than) the current
+                    // time, then we are too early.
+                    NoteKeypressReaction::TooEarly
+                } else if kind.is_hit_by(input) {
+                    // We know the note is not too late (hittable), we know the note is not
+                    // too early, so this means the note is hit! Return the timing difference.
+                    *judged = true;
+                    NoteKeypressReaction::Hit {
+                        offset: time - self.time,
+                    }
+                } else {
+                    NoteKeypressReaction::WrongColour
+                }
+            }
+
+            NoteState::Roll { duration, big } => {
+                let relative_time = time - self.time;
+                if relative_time < 0.0 {
+                    // This is before the drumroll
+                    NoteKeypressReaction::TooEarly
+                } else if relative_time >= *duration {
+                    // This is after
+                    NoteKeypressReaction::TooLate
+                } else {
+                    // This is just right
+                    let colour = if input.is_don() {
+                        NoteColour::Don
+                    } else {
+                        NoteColour::Kat
+                    };
+
+                    let roll_note = BasicNoteType { colour, big: *big };
+
+                    NoteKeypressReaction::Drumroll { roll_note }
+                }
+            }
+
+            NoteState::Balloon {
+                duration,
+                hits_left,
+                hit_target,
+            } => {
+                if self.time > time {
+                    NoteKeypressReaction::TooEarly
+                } else if self.time + *duration < time || *hits_left == 0 {
+                    NoteKeypressReaction::TooLate
+                } else if input.is_don() {
+                    *hits_left -= 1;
+                    NoteKeypressReaction::BalloonRoll {
+                        hits_left: *hits_left,
+                        hit_target: *hit_target,
+                    }
+                } else {
+                    NoteKeypressReaction::WrongColour
+                }
+            }
+        }
+    }
+}
+
+/// Something that happened to a note as a result of an input or the passing of time. The `note`
+/// of each event is the index of the note among the chart's playable notes (see [is_playable]).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GameplayEvent {
+    /// A don or kat note was hit. The offset is only precise enough for display, so anything
+    /// that needs to be exact should use the judgement instead.
+    Hit {
+        note: usize,
+        judgement: NoteJudgement,
+        offset: f32,
+        big: bool,
+    },
+    /// A don or kat note went by without being hit.
+    Miss { note: usize },
+    /// A drumroll was hit, for the `hits`th time.
+    Drumroll {
+        note: usize,
+        roll_note: BasicNoteType,
+        hits: usize,
+    },
+    /// A balloon was hit.
+    BalloonHit {
+        note: usize,
+        hits_left: u32,
+        hit_target: u32,
+    },
+    /// A balloon went by without being popped.
+    BalloonMissed { note: usize },
+    /// The player moved onto a different branch of the chart.
+    BranchChanged { branch: Branch },
+}
+
+/// Whether the game is being played, or is paused.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PlayState {
+    Playing,
+    /// The pause menu is open.
+    Paused,
+    /// The player chose to resume, and the game is counting down before the song continues.
+    /// Inputs during the countdown are never judged, so that a press meant for the pause menu
+    /// (or a nervous tap before the song starts again) can't hit or break a note.
+    Countdown,
+}
+
+/// Keeps track of the notes of a chart and judges inputs against them.
+#[derive(Debug, Clone)]
+pub struct GameplayCore {
+    notes: Vec<CoreNote>,
+    /// The index of the next note to be played
+    next_note_index: usize,
+    timing_windows: [f64; 3],
+    state: PlayState,
+    /// An ongoing record of the player's performance.
+    results: PlayResult,
+    /// How full the soul gauge is
+    health: Health,
+    /// The index of the roll that was last hit, so we know when a new roll has started.
+    current_roll: Option<usize>,
+    /// How notes, drumrolls and balloons count towards the score and the gauge.
+    rules: RulesConfig,
+    /// The points for hitting each note, which depend on the rules.
+    scorer: NoteScorer,
+    difficulty: usize,
+    /// How many don and kat notes the normal branch of the chart has.
+    note_count: usize,
+    /// When the branch of each of the chart's branched sections is chosen, and what decides it.
+    branch_decisions: Vec<BranchDecision>,
+    /// The branch each section is played on. Sections whose branch hasn't been chosen yet are on
+    /// the branch the player is on now.
+    section_branches: Vec<Branch>,
+    /// The index of the next branch decision to make.
+    next_branch_decision: usize,
+    /// The branch the player is on.
+    branch: Branch,
+}
+
+/// When the branch of a section is chosen, and what it's chosen by (see
+/// [BranchSection](crate::notechart_parser::BranchSection)).
+#[derive(Debug, Clone, Copy)]
+struct BranchDecision {
+    condition: BranchCondition,
+    time: f64,
+    measured_from: f64,
+}
+
+impl GameplayCore {
+    /// Creates a core for a chart, including the notes of every branch. The notes are in the order
+    /// of [NoteChart::all_notes] (leaving out the ones that aren't playable), and the player starts
+    /// on the normal branch.
+    pub fn new(chart: &NoteChart, difficulty: usize) -> Self {
+        let notes: Vec<CoreNote> = chart
+            .all_notes()
+            .iter()
+            .filter_map(|(note, branch)| {
+                CoreNote::new(note).map(|core_note| CoreNote {
+                    branch: *branch,
+                    in_play: branch.is_none_or(|(_, branch)| branch == Branch::Normal),
+                    gogo: chart.is_gogo(note.time as f32),
+                    ..core_note
+                })
+            })
+            .collect();
+
+        // The gauge is filled by the normal branch of the chart, whichever branches are played
+        let note_count = notes
+            .iter()
+            .filter(|note| note.in_play && note.is_don_or_kat())
+            .count();
+
+        Self {
+            notes,
+            next_note_index: 0,
+            timing_windows: timing_windows(difficulty),
+            state: PlayState::Playing,
+            results: PlayResult::new(),
+            health: Health::new(difficulty, note_count),
+            current_roll: None,
+            rules: RulesConfig::default(),
+            scorer: NoteScorer::new(RulesConfig::default().note_scoring, difficulty, note_count),
+            difficulty,
+            note_count,
+            branch_decisions: chart
+                .branch_sections
+                .iter()
+                .map(|section| BranchDecision {
+                    condition: section.condition,
+                    time: section.decision_time,
+                    measured_from: section.measured_from,
+                })
+                .collect(),
+            section_branches: vec![Branch::Normal; chart.branch_sections.len()],
+            next_branch_decision: 0,
+            branch: Branch::Normal,
+        }
+    }
+
+    /// When the chart is over: the moment its last note (on any branch) has gone by and can't be
+    /// hit any more. Returns None if the chart has nothing to judge.
+    pub fn end_time(&self) -> Option<f64> {
+        self.notes
+            .iter()
+            .map(|note| match note.state {
+                NoteState::Note { .. } => note.time + self.timing_windows[BAD],
+                NoteState::Roll { duration, .. } | NoteState::Balloon { duration, .. } => {
+                    note.time + duration
+                }
+            })
+            .max_by(f64::total_cmp)
+    }
+
+    /// Sets the rules notes, drumrolls and balloons are scored by. Without this, the default rules
+    /// are used.
+    pub fn with_rules(mut self, rules: RulesConfig) -> Self {
+        self.rules = rules;
+        self.scorer = NoteScorer::new(rules.note_scoring, self.difficulty, self.note_count);
+        self
+    }
+
+    pub fn results(&self) -> &PlayResult {
+        &self.results
+    }
+
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    pub fn timing_windows(&self) -> &[f64; 3] {
+        &self.timing_windows
+    }
+
+    pub fn state(&self) -> PlayState {
+        self.state
+    }
+
+    /// Whether the note with the given index is on the branch being played, and so should be
+    /// shown.
+    pub fn is_in_play(&self, note_index: usize) -> bool {
+        self.notes[note_index].in_play
+    }
+
+    /// Whether something in the given branched section and branch (from
+    /// [NoteChart::all_barlines], for example) is on the branch being played. Things that aren't in
+    /// a branch always are.
+    pub fn is_on_played_branch(&self, branch: Option<(usize, Branch)>) -> bool {
+        branch.is_none_or(|(section, branch)| self.section_branches[section] == branch)
+    }
+
+    /// Opens the pause menu. Inputs are ignored until [GameplayCore::resume] is called.
+    pub fn pause(&mut self) {
+        self.state = PlayState::Paused;
+    }
+
+    /// Starts the countdown before the game continues. Inputs are still ignored until
+    /// [GameplayCore::resume] is called.
+    pub fn start_countdown(&mut self) {
+        self.state = PlayState::Countdown;
+    }
+
+    /// Continues the game.
+    pub fn resume(&mut self) {
+        self.state = PlayState::Playing;
+    }
+
+    /// Judges an input made at the given time (relative to the notes).
+    ///
+    /// Unless the game is being played, this does nothing and returns no events.
+    pub fn press(&mut self, input: DrumInput, time: f64) -> Vec<GameplayEvent> {
+        let mut events = Vec::new();
+
+        if self.state != PlayState::Playing {
+            return events;
+        }
+
+        self.choose_branches(time, &mut events);
+
+        // We now have to go through all the notes starting from the next one, and see if any of
+        // them react to this keypress. If any of them react, or any of them are too far away to
+        // react, then we stop.
+        let mut note_index = self.next_note_index;
+
+        // If there's no next note, we don't need to react.
+        while let Some(note) = self.notes.get(note_index) {
+            // Notes on other branches are passed over as if they weren't there
+            if !note.in_play {
+                note_index += 1;
+                continue;
+            }
+
+            // Charts sometimes put notes inside a drumroll. A note that can be hit with this input
+            // takes priority over the roll around it, and otherwise the roll gets the input.
+            if note.is_active_roll(time) {
+                if let Some(inner_index) = self.note_inside_roll(note_index, input, time) {
+                    if let NoteKeypressReaction::Hit { offset } =
+                        self.notes[inner_index].receive_input(input, time, &self.timing_windows)
+                    {
+                        // The roll is still going, so it stays as the next note.
+                        self.judge_hit(inner_index, input, offset, &mut events);
+                    }
+                    break;
+                }
+            }
+
+            match self.notes[note_index].receive_input(input, time, &self.timing_windows) {
+                // If it's the wrong colour, we'll keep checking to see if there's a note of the
+                // right colour in scope.
+                NoteKeypressReaction::WrongColour => {}
+
+                NoteKeypressReaction::TooEarly => {
+                    // Now we're only looking at notes that are unhittable, so stop here.
+                    break;
+                }
+                NoteKeypressReaction::Hit { offset } => {
+                    self.judge_hit(note_index, input, offset, &mut events);
+                    self.next_note_index = note_index + 1;
+
+                    // Ensure you only ever hit one note at a time
+                    break;
+                }
+                NoteKeypressReaction::Drumroll { roll_note } => {
+                    self.results.drumrolls += 1;
+                    let score = if roll_note.big {
+                        self.rules.big_roll_hit_score
+                    } else {
+                        self.rules.roll_hit_score
+                    };
+                    self.award_roll(score, self.rules.roll_hit_health);
+                    let hits = self.record_roll_hit(note_index, input, time);
+                    events.push(GameplayEvent::Drumroll {
+                        note: note_index,
+                        roll_note,
+                        hits,
+                    });
+                    break;
+                }
+                NoteKeypressReaction::BalloonRoll {
+                    hits_left,
+                    hit_target,
+                } => {
+                    self.results.drumrolls += 1;
+                    self.award_roll(self.rules.balloon_hit_score, self.rules.roll_hit_health);
+                    if hits_left == 0 {
+                        self.award_roll(
+                            self.rules.balloon_pop_score,
+                            self.rules.balloon_pop_health,
+                        );
+                    }
+
+                    self.record_roll_hit(note_index, input, time);
+                    events.push(GameplayEvent::BalloonHit {
+                        note: note_index,
+                        hits_left,
+                        hit_target,
+                    });
+
+                    if hits_left == 0 {
+                        self.next_note_index = note_index + 1;
+                    }
+                    break;
+                }
+                NoteKeypressReaction::TooLate => {
+                    self.skip_next_note(&mut events);
+                }
+            }
+
+            note_index += 1;
+        }
+
+        events
+    }
+
+    /// Advances our position in the list of notes as far as we can go, missing every note that
+    /// can no longer be hit at the given time.
+    pub fn advance(&mut self, time: f64) -> Vec<GameplayEvent> {
+        let mut events = Vec::new();
+        self.choose_branches(time, &mut events);
+
+        while let Some(note) = self.notes.get(self.next_note_index) {
+            if note.is_hittable(time, &self.timing_windows) {
+                break;
+            }
+
+            self.skip_next_note(&mut events);
+        }
+
+        // If we stopped at a roll that's still going, the notes inside it can still be missed
+        // before the roll is over.
+        for note_index in self.next_note_index + 1..self.notes.len() {
+            let note = &self.notes[note_index];
+            if note.time > time {
+                break;
+            }
+
+            if !note.is_hittable(time, &self.timing_windows) {
+                self.miss_note(note_index, &mut events);
+            }
+        }
+
+        events
+    }
+
+    /// Carries on judging from the given time as if the song had started there, for practising part
+    /// of a chart. Notes that can't be hit any more are skipped without counting as misses.
+    pub fn skip_to(&mut self, time: f64) {
+        while let Some(note) = self.notes.get(self.next_note_index) {
+            if note.is_hittable(time, &self.timing_windows) {
+                break;
+            }
+
+            self.next_note_index += 1;
+        }
+
+        // The same goes for notes inside a roll that's still going
+        for note in self.notes.iter_mut().skip(self.next_note_index + 1) {
+            if note.time > time {
+                break;
+            }
+
+            if !note.is_hittable(time, &self.timing_windows) {
+                if let NoteState::Note { judged, .. } = &mut note.state {
+                    *judged = true;
+                }
+            }
+        }
+    }
+
+    /// Chooses the branch of every section whose decision time has come by the given time, and puts
+    /// the notes of the chosen branches in play.
+    fn choose_branches(&mut self, time: f64, events: &mut Vec<GameplayEvent>) {
+        while let Some(&decision) = self.branch_decisions.get(self.next_branch_decision) {
+            if decision.time > time {
+                break;
+            }
+
+            let section = self.next_branch_decision;
+            let branch = self.branch_earned(&decision);
+            self.next_branch_decision += 1;
+
+            // Later sections stay on this branch until their own branch is chosen
+            for section_branch in self.section_branches[section..].iter_mut() {
+                *section_branch = branch;
+            }
+
+            for note in self.notes.iter_mut() {
+                if let Some((note_section, note_branch)) = note.branch {
+                    if note_section >= section {
+                        note.in_play = note_branch == branch;
+                    }
+                }
+            }
+
+            if branch != self.branch {
+                self.branch = branch;
+                events.push(GameplayEvent::BranchChanged { branch });
+            }
+        }
+    }
+
+    /// The branch the player's performance so far earns them at a branch decision. Only notes and
+    /// drumroll hits between the start of the decision's measured stretch and the decision itself
+    /// count.
+    fn branch_earned(&self, decision: &BranchDecision) -> Branch {
+        let measured = |time: f64| (decision.measured_from..decision.time).contains(&time);
+
+        let (value, expert, master) = match decision.condition {
+            BranchCondition::Accuracy { expert, master } => {
+                let judged = self.notes.iter().filter(|note| {
+                    note.in_play
+                        && measured(note.time)
+                        && matches!(note.state, NoteState::Note { judged: true, .. })
+                });
+
+                let (count, weighted) =
+                    judged.fold((0, 0.0), |(count, weighted), note| match note.judgement {
+                        Some(NoteJudgement::Good) => (count + 1, weighted + 1.0),
+                        Some(NoteJudgement::Ok) => (count + 1, weighted + 0.5),
+                        _ => (count + 1, weighted),
+                    });
+
+                let accuracy = if count == 0 {
+                    0.0
+                } else {
+                    weighted / count as f32 * 100.0
+                };
+
+                (accuracy, expert, master)
+            }
+            BranchCondition::Drumrolls { expert, master } => {
+                let hits = self
+                    .results
+                    .rolls
+                    .iter()
+                    .flat_map(|roll| roll.hit_times.iter())
+                    .filter(|&&time| measured(time))
+                    .count();
+
+                (hits as f32, expert, master)
+            }
+        };
+
+        if value >= master {
+            Branch::Master
+        } else if value >= expert {
+            Branch::Expert
+        } else {
+            Branch::Normal
+        }
+    }
+
+    /// Finds a note inside the roll at `roll_index` that would be hit by the given input.
+    fn note_inside_roll(&self, roll_index: usize, input: DrumInput, time: f64) -> Option<usize> {
+        self.notes
+            .iter()
+            .enumerate()
+            .skip(roll_index + 1)
+            .take_while(|(_, note)| note.time - self.timing_windows[BAD] < time)
+            .find(|(_, note)| note.would_be_hit_by(input, time, &self.timing_windows))
+            .map(|(note_index, _)| note_index)
+    }
+
+    /// Records a hit on a don or kat note.
+    fn judge_hit(
+        &mut self,
+        note_index: usize,
+        input: DrumInput,
+        offset: f64,
+        events: &mut Vec<GameplayEvent>,
+    ) {
+        let judgement = NoteJudgement::from_offset(offset, &self.timing_windows).unwrap();
+
+        let note = &mut self.notes[note_index];
+        note.judgement = Some(judgement);
+        let big = matches!(note.state, NoteState::Note { kind, .. } if kind.big);
+        let gogo = note.gogo;
+        let time = note.time;
+
+        self.results.push_judgement(Some(judgement));
+        self.results.score += self
+            .scorer
+            .score(judgement, big, gogo, self.results.current_combo());
+        self.results.hits.push(HitRecord {
+            time,
+            offset: offset as f32,
+            input,
+        });
+        self.health.apply_judgement(Some(judgement));
+
+        events.push(GameplayEvent::Hit {
+            note: note_index,
+            judgement,
+            offset: offset as f32,
+            big,
+        });
+    }
+
+    /// Adds the points and health the rules give for a hit on a drumroll or balloon.
+    fn award_roll(&mut self, score: ScoreInt, health: HealthInt) {
+        self.results.score += score;
+        self.health.add(health);
+    }
+
+    /// Records a hit on a drumroll or balloon, and returns how many times it has been hit so far.
+    fn record_roll_hit(&mut self, note_index: usize, input: DrumInput, time: f64) -> usize {
+        if self.current_roll != Some(note_index) {
+            self.current_roll = Some(note_index);
+            self.results
+                .rolls
+                .push(RollRecord::new(self.notes[note_index].time));
+        }
+
+        let roll = self.results.rolls.last_mut().unwrap();
+        roll.push(time, input);
+        roll.hits()
+    }
+
+    /// Considers the next note to have been missed. Updates the index of the next note, and adds a
+    /// miss to the play result if appropriate.
+    fn skip_next_note(&mut self, events: &mut Vec<GameplayEvent>) {
+        let note_index = self.next_note_index;
+
+        if let Some(note) = self.notes.get(note_index) {
+            self.next_note_index += 1;
+
+            if !note.in_play {
+                return;
+            }
+
+            if matches!(note.state, NoteState::Balloon { .. }) {
+                events.push(GameplayEvent::BalloonMissed { note: note_index });
+            } else {
+                self.miss_note(note_index, events);
+            }
+        }
+    }
+
+    /// Adds a miss to the play result for a don or kat note, unless it has already been judged.
+    fn miss_note(&mut self, note_index: usize, events: &mut Vec<GameplayEvent>) {
+        let note = &mut self.notes[note_index];
+
+        if let NoteState::Note { judged, .. } = &mut note.state {
+            if note.in_play && !*judged {
+                *judged = true;
+                self.results.push_judgement(None);
+                self.health.apply_judgement(None);
+                events.push(GameplayEvent::Miss { note: note_index });
+            }
+        }
+    }
+}
+
+/// Where notes travel across the screen and how big they are, which decides when they're on screen.
+/// All of these are in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteLayout {
+    /// Where notes are when they should be hit.
+    pub hit_x: f32,
+    /// The height notes travel along, at their centre.
+    pub y: f32,
+    /// The left edge of the part of the screen notes are drawn in.
+    pub field_start: f32,
+    /// The right edge of the part of the screen notes are drawn in.
+    pub field_end: f32,
+    /// How far a note with a scroll speed of 1 moves each second.
+    pub velocity: f32,
+    /// Half the width of a small note, or the head of a drumroll.
+    pub note_radius: f32,
+    /// Half the width of a big note.
+    pub big_note_radius: f32,
+    /// How far a balloon reaches to the right of its notehead.
+    pub balloon_reach: f32,
+}
+
+/// Player settings that change when notes are on screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VisibilitySettings {
+    /// SUDDEN: if set, notes only appear once they're this many pixels from where they're hit.
+    pub sudden: Option<f32>,
+    /// HIDDEN: if set, notes are gone once they're this many pixels from where they're hit. They
+    /// fade out on the way there (see [HIDDEN_FADE_LENGTH]).
+    pub hidden: Option<f32>,
+}
+
+/// How many pixels notes take to fade out with HIDDEN, before they're gone.
+pub const HIDDEN_FADE_LENGTH: f32 = 150.0;
+
+impl VisibilitySettings {
+    /// How opaque a note should be drawn when it's `distance` pixels from where it's hit, which
+    /// is only ever less than 1 with HIDDEN.
+    pub fn opacity(&self, distance: f32) -> f32 {
+        match self.hidden {
+            Some(hidden) => ((distance - hidden) / HIDDEN_FADE_LENGTH).clamp(0.0, 1.0),
+            None => 1.0,
+        }
+    }
+}
+
+/// Returns the times (in the same time as the note) at which a note first appears on screen and
+/// then leaves it again. The note is on screen for any time `t` with `appear <= t < vanish`.
+///
+/// This is the one definition of when a note is on screen, which is used for culling notes while
+/// they're drawn. It covers the whole note: a drumroll stays on screen until the end of its body
+/// has left, and a balloon stays for as long as it can be popped, as it waits at the hit position
+/// until then. Whether a note has been hit or popped isn't taken into account, as that only makes
+/// it disappear sooner.
+///
+/// Notes with a scroll speed of 0 never move, so they're on screen the whole time (unless they're
+/// stuck off the edge of the screen, in which case they never are). Notes with a negative scroll
+/// speed come from the left instead. Notes that also move up or down the screen (see
+/// [Note::vertical_speed]) are only on screen while they're between the top and bottom of it too.
+pub fn visibility_interval(
+    note: &Note,
+    layout: &NoteLayout,
+    settings: &VisibilitySettings,
+) -> (f32, f32) {
+    let speed = layout.velocity * note.scroll_speed;
+
+    // How far the note reaches to the left and right of its position, and how long it waits at the
+    // hit position before it starts moving again
+    let (reach_left, reach_right, wait) = match note.note_type {
+        NoteType::Don | NoteType::Kat => (layout.note_radius, layout.note_radius, 0.0),
+        NoteType::BigDon | NoteType::BigKat | NoteType::CoopDon | NoteType::CoopKat => {
+            (layout.big_note_radius, layout.big_note_radius, 0.0)
+        }
+        NoteType::Roll(length) | NoteType::BigRoll(length) => {
+            let body = speed * length;
+            (
+                layout.note_radius.max(-body),
+                layout.note_radius.max(body),
+                0.0,
+            )
+        }
+        NoteType::BalloonRoll(duration, _) | NoteType::SpecialRoll(duration, _) => {
+            (layout.note_radius, layout.balloon_reach, duration)
+        }
+    };
+
+    let across = interval_on_screen(
+        note.time as f32,
+        speed,
+        (reach_left, reach_right),
+        wait,
+        layout,
+        settings,
+    );
+
+    if note.vertical_speed == 0.0 {
+        return across;
+    }
+
+    // Every note is round apart from its tail, which only ever reaches across
+    let radius = reach_left.min(reach_right);
+    let down = vertical_interval(note.time as f32, note.vertical_speed, radius, wait, layout);
+
+    let appear = across.0.max(down.0);
+    (appear, across.1.min(down.1).max(appear))
+}
+
+/// Returns the times at which a barline appears on screen and leaves it again, like
+/// [visibility_interval] does for notes. Barlines have no width.
+pub fn barline_visibility_interval(
+    barline: &Barline,
+    layout: &NoteLayout,
+    settings: &VisibilitySettings,
+) -> (f32, f32) {
+    let speed = layout.velocity * barline.scroll_speed;
+    let across = interval_on_screen(barline.time, speed, (0.0, 0.0), 0.0, layout, settings);
+
+    if barline.vertical_speed == 0.0 {
+        return across;
+    }
+
+    let radius = NOTE_FIELD_HEIGHT / 2.0;
+    let down = vertical_interval(barline.time, barline.vertical_speed, radius, 0.0, layout);
+
+    let appear = across.0.max(down.0);
+    (appear, across.1.min(down.1).max(appear))
+}
+
+/// Returns the times at which something moving down the screen (see [Note::vertical_speed]) is
+/// between the top and bottom of it, reaching `radius` above and below its centre.
+fn vertical_interval(
+    time: f32,
+    vertical_speed: f32,
+    radius: f32,
+    wait: f32,
+    layout: &NoteLayout,
+) -> (f32, f32) {
+    // This is the same as moving across the screen, turned on its side
+    let sideways = NoteLayout {
+        hit_x: layout.y,
+        field_start: 0.0,
+        field_end: VIRTUAL_HEIGHT,
+        ..*layout
+    };
+    let speed = -layout.velocity.abs() * vertical_speed;
+
+    interval_on_screen(
+        time,
+        speed,
+        (radius, radius),
+        wait,
+        &sideways,
+        &VisibilitySettings::default(),
+    )
+}
+
+/// Finds which of a list of notes (or barlines) could be on screen at a given time, without
+/// checking every one of them.
+///
+/// Notes are mostly in the order they appear in, but not quite, since notes with different scroll
+/// speeds can overtake each other. So rather than searching the intervals themselves, this searches
+/// the latest time any note up to each one vanishes, and the earliest time any note from each one
+/// on appears, which are always in order. Every note on screen is in the range it finds, but not
+/// every note in the range is on screen, so they still need checking.
+#[derive(Debug, Clone, Default)]
+pub struct VisibilityWindow {
+    /// The latest any note up to and including each one vanishes
+    latest_vanish: Vec<f32>,
+    /// The earliest any note from each one onwards appears
+    earliest_appear: Vec<f32>,
+}
+
+impl VisibilityWindow {
+    /// Creates a window over notes with the given visibility intervals (see
+    /// [visibility_interval]), in the same order as the notes.
+    pub fn new(intervals: impl IntoIterator<Item = (f32, f32)>) -> Self {
+        let (appear, vanish): (Vec<f32>, Vec<f32>) = intervals.into_iter().unzip();
+
+        let latest_vanish = vanish
+            .iter()
+            .scan(f32::NEG_INFINITY, |latest, &vanish| {
+                *latest = latest.max(vanish);
+                Some(*latest)
+            })
+            .collect();
+
+        let mut earliest_appear: Vec<f32> = appear
+            .iter()
+            .rev()
+            .scan(f32::INFINITY, |earliest, &appear| {
+                *earliest = earliest.min(appear);
+                Some(*earliest)
+            })
+            .collect();
+        earliest_appear.reverse();
+
+        Self {
+            latest_vanish,
+            earliest_appear,
+        }
+    }
+
+    /// The indices of the notes that could be on screen at the given time.
+    pub fn range(&self, time: f32) -> Range<usize> {
+        // Every note before the start has already vanished...
+        let start = self.latest_vanish.partition_point(|&vanish| vanish <= time);
+        // ...and every note from the end on is yet to appear
+        let end = self
+            .earliest_appear
+            .partition_point(|&appear| appear <= time);
+
+        start..end.max(start)
+    }
+}
+
+/// Solves for when something at `time` moving at `speed` pixels per second is on screen, given how
+/// far it reaches to the left and right of its position and how long it waits at the hit position.
+fn interval_on_screen(
+    time: f32,
+    speed: f32,
+    (reach_left, reach_right): (f32, f32),
+    wait: f32,
+    layout: &NoteLayout,
+    settings: &VisibilitySettings,
+) -> (f32, f32) {
+    if speed == 0.0 {
+        let on_screen = layout.hit_x - reach_left < layout.field_end
+            && layout.hit_x + reach_right >= layout.field_start;
+
+        return if on_screen {
+            (f32::NEG_INFINITY, f32::INFINITY)
+        } else {
+            (f32::INFINITY, f32::INFINITY)
+        };
+    }
+
+    // The note is at hit_x + speed * (time - t). It's on screen while its left edge is left of
+    // the end of the field and its right edge is right of the start, so we solve for the times
+    // each edge crosses the other side of the field.
+    let left_edge_at_end = time - (layout.field_end - layout.hit_x + reach_left) / speed;
+    let right_edge_at_start = time - (layout.field_start - layout.hit_x - reach_right) / speed;
+
+    let mut appear = left_edge_at_end.min(right_edge_at_start);
+    let mut vanish = left_edge_at_end.max(right_edge_at_start) + wait;
+
+    if let Some(sudden) = settings.sudden {
+        appear = appear.max(time - sudden / speed.abs());
+    }
+    if let Some(hidden) = settings.hidden {
+        vanish = vanish.min(time - hidden / speed.abs());
+    }
+
+    (appear, vanish.max(appear))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::GogoRegion;
+
+    fn chart(notes: &[(NoteType, f64)]) -> NoteChart {
+        NoteChart {
+            notes: notes
+                .iter()
+                .map(|&(note_type, time)| Note {
+                    note_type,
+                    time,
+                    scroll_speed: 1.0,
+                    vertical_speed: 0.0,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_press_judges_notes() {
+        let mut core = GameplayCore::new(&chart(&[(NoteType::Don, 1.0), (NoteType::Kat, 2.0)]), 3);
+
+        // Wrong colour
+        assert_eq!(core.press(DrumInput::LeftKat, 1.0), vec![]);
+
+        assert_eq!(
+            core.press(DrumInput::LeftDon, 1.0),
+            vec![GameplayEvent::Hit {
+                note: 0,
+                judgement: NoteJudgement::Good,
+                offset: 0.0,
+                big: false,
+            }]
+        );
+
+        assert_eq!(core.advance(3.0), vec![GameplayEvent::Miss { note: 1 }]);
+        assert_eq!(core.results().goods(), 1);
+        assert_eq!(core.results().misses(), 1);
+        assert!(!core.results().is_full_combo());
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let mut core = GameplayCore::new(
+            &chart(&[
+                (NoteType::Don, 1.0),
+                (NoteType::Roll(2.0), 2.0),
+                (NoteType::Kat, 2.5),
+                (NoteType::Don, 3.5),
+                (NoteType::Don, 5.0),
+            ]),
+            3,
+        );
+
+        // The roll is still going, but the note inside it has gone by
+        core.skip_to(3.0);
+        assert_eq!(core.results().misses(), 0);
+
+        assert!(matches!(
+            core.press(DrumInput::LeftKat, 3.2).as_slice(),
+            [GameplayEvent::Drumroll { note: 1, .. }]
+        ));
+        core.press(DrumInput::LeftDon, 3.5);
+        core.advance(6.0);
+
+        assert_eq!(core.results().goods(), 1);
+        assert_eq!(core.results().misses(), 1);
+    }
+
+    #[test]
+    fn test_end_time() {
+        let core = GameplayCore::new(
+            &chart(&[
+                (NoteType::Don, 1.0),
+                (NoteType::Roll(1.0), 2.0),
+                (NoteType::Kat, 3.5),
+            ]),
+            3,
+        );
+        assert_eq!(core.end_time(), Some(3.5 + timing_windows(3)[BAD]));
+
+        // A roll that outlasts the last note
+        let core = GameplayCore::new(
+            &chart(&[(NoteType::Don, 1.0), (NoteType::BalloonRoll(3.0, 10), 1.5)]),
+            3,
+        );
+        assert_eq!(core.end_time(), Some(4.5));
+
+        assert_eq!(GameplayCore::new(&chart(&[]), 3).end_time(), None);
+    }
+
+    #[test]
+    fn test_full_combo() {
+        let mut core = GameplayCore::new(&chart(&[(NoteType::Don, 1.0), (NoteType::Kat, 2.0)]), 3);
+        assert!(!core.results().is_full_combo());
+
+        core.press(DrumInput::LeftDon, 1.0);
+        core.press(DrumInput::LeftKat, 2.05);
+        core.advance(3.0);
+
+        assert_eq!(core.results().okays(), 1);
+        assert!(core.results().is_full_combo());
+    }
+
+    #[test]
+    fn test_chart_with_nothing_to_judge() {
+        // A course with only rolls has no combo or soul gauge to speak of, but can still be played
+        let mut core = GameplayCore::new(&chart(&[(NoteType::Roll(1.0), 1.0)]), 3);
+
+        core.press(DrumInput::LeftDon, 1.5);
+        assert_eq!(core.advance(5.0), vec![]);
+
+        let results = core.results();
+        assert_eq!(results.accuracy(), 100.0);
+        assert_eq!(results.max_combo(), 0);
+        assert_eq!(core.health().value(), 0);
+        assert!(!core.health().is_clear());
+    }
+
+    #[test]
+    fn test_empty_course_fixture() {
+        let song = crate::notechart_parser::parse_tja_file(include_str!(
+            "../../../tests/fixtures/tja/command_only_course.tja"
+        ))
+        .unwrap();
+
+        // The placeholder course is left out, and the real one plays as normal
+        assert!(song.difficulties[3].is_none());
+        let easy = song.difficulties[0].as_ref().unwrap();
+        let mut core = GameplayCore::new(&easy.chart, 0);
+
+        for note in &easy.chart.notes {
+            let input = if note.note_type.is_don() {
+                DrumInput::LeftDon
+            } else {
+                DrumInput::LeftKat
+            };
+            core.press(input, note.time);
+        }
+        core.advance(10.0);
+
+        assert_eq!(core.results().goods(), 3);
+        assert_eq!(core.results().accuracy(), 100.0);
+        assert!(core.health().is_clear());
+    }
+
+    #[test]
+    fn test_branches() {
+        let song = crate::notechart_parser::parse_tja_file(
+            "TITLE:Branches\nBPM:120\nWAVE:song.ogg\nCOURSE:Oni\nLEVEL:5\n#START\n\
+             1111,\n1111,\n#BRANCHSTART p,60,90\n#N\n1,\n#E\n2,\n#M\n3,\n#BRANCHEND\n1,\n#END\n",
+        )
+        .unwrap();
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+        // The branch is chosen by the first measure, at the start of the second. The three
+        // branches' notes come after the second measure, in order, and then the last note.
+        let play = |offsets: [f64; 4]| {
+            let mut core = GameplayCore::new(chart, 3);
+            let mut events = Vec::new();
+
+            for (i, offset) in offsets.into_iter().enumerate() {
+                core.press(DrumInput::LeftDon, i as f64 * 0.5 + offset);
+            }
+
+            events.extend(core.advance(2.0));
+            events.extend(core.advance(10.0));
+            (core, events)
+        };
+
+        // Perfect accuracy gets the master branch
+        let (core, events) = play([0.0; 4]);
+        assert!(events.contains(&GameplayEvent::BranchChanged {
+            branch: Branch::Master
+        }));
+        assert_eq!(
+            (8..11).map(|i| core.is_in_play(i)).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+        assert!(core.is_on_played_branch(Some((0, Branch::Master))));
+        assert!(!core.is_on_played_branch(Some((0, Branch::Normal))));
+
+        // Notes on other branches aren't missed, and only the branch being played can be hit
+        assert!(!events.contains(&GameplayEvent::Miss { note: 8 }));
+        assert!(events.contains(&GameplayEvent::Miss { note: 10 }));
+        assert_eq!(core.results().misses(), 6);
+
+        // Two Goods and two Oks make 75%, which is enough for expert
+        let (core, events) = play([0.0, 0.05, 0.0, -0.05]);
+        assert!(events.contains(&GameplayEvent::BranchChanged {
+            branch: Branch::Expert
+        }));
+        assert!(core.is_in_play(9));
+        assert!(events.contains(&GameplayEvent::Miss { note: 9 }));
+
+        // Missing everything stays on the normal branch, without changing anything
+        let (core, events) = play([-10.0; 4]);
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, GameplayEvent::BranchChanged { .. })));
+        assert!(core.is_in_play(8) && !core.is_in_play(9) && !core.is_in_play(10));
+
+        let mut core = GameplayCore::new(chart, 3);
+        core.advance(3.9);
+        assert!(matches!(
+            core.press(DrumInput::LeftDon, 4.0)[..],
+            [GameplayEvent::Hit { note: 8, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_press_at_pause_boundary_is_ignored() {
+        let mut core = GameplayCore::new(&chart(&[(NoteType::Don, 1.0)]), 3);
+
+        // The press arrives in the same instant the game is paused, so it's meant for the pause
+        // menu and shouldn't count.
+        core.pause();
+        assert_eq!(core.press(DrumInput::LeftDon, 1.0), vec![]);
+
+        // Once the game resumes, the note is still there to be hit.
+        core.start_countdown();
+        core.resume();
+        assert!(matches!(
+            core.press(DrumInput::LeftDon, 1.0)[..],
+            [GameplayEvent::Hit { note: 0, .. }]
+        ));
+        assert_eq!(core.results().goods(), 1);
+    }
+
+    #[test]
+    fn test_press_during_countdown_is_ignored() {
+        let mut core = GameplayCore::new(
+            &chart(&[(NoteType::Don, 1.0), (NoteType::BalloonRoll(1.0, 5), 2.0)]),
+            3,
+        );
+
+        core.pause();
+        core.start_countdown();
+        assert_eq!(core.state(), PlayState::Countdown);
+
+        // Neither the note nor the balloon react
+        assert_eq!(core.press(DrumInput::LeftDon, 1.0), vec![]);
+        assert_eq!(core.press(DrumInput::RightDon, 2.5), vec![]);
+        assert_eq!(core.results().goods(), 0);
+        assert_eq!(core.results().drumrolls(), 0);
+        assert_eq!(core.health().value(), 0);
+
+        core.resume();
+        assert!(matches!(
+            core.press(DrumInput::LeftDon, 1.0)[..],
+            [GameplayEvent::Hit { note: 0, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_roll_hits_are_recorded() {
+        let mut core = GameplayCore::new(
+            &chart(&[
+                (NoteType::Roll(1.0), 1.0),
+                (NoteType::BalloonRoll(1.0, 10), 3.0),
+            ]),
+            3,
+        );
+
+        for (i, time) in [1.0, 1.1, 1.2].into_iter().enumerate() {
+            let events = core.press(DrumInput::LeftKat, time);
+            assert!(
+                matches!(events[..], [GameplayEvent::Drumroll { hits, .. }] if hits == i + 1),
+                "{events:?}"
+            );
+        }
+
+        core.press(DrumInput::LeftDon, 3.0);
+        core.press(DrumInput::RightDon, 3.05);
+
+        let rolls = core.results().rolls();
+        assert_eq!(rolls.len(), 2);
+        assert_eq!(rolls[0].start_time, 1.0);
+        assert_eq!(rolls[0].hit_times, vec![1.0, 1.1, 1.2]);
+        assert_eq!(rolls[1].start_time, 3.0);
+        assert_eq!(rolls[1].hit_times, vec![3.0, 3.05]);
+        assert_eq!(
+            rolls[1].hit_inputs,
+            vec![DrumInput::LeftDon, DrumInput::RightDon]
+        );
+        assert_eq!(core.results().drumrolls(), 5);
+    }
+
+    #[test]
+    fn test_rules_presets() {
+        let notes = chart(&[
+            (NoteType::Roll(1.0), 1.0),
+            (NoteType::BigRoll(1.0), 3.0),
+            (NoteType::BalloonRoll(1.0, 4), 5.0),
+        ]);
+
+        let play = |rules: RulesConfig| {
+            let mut core = GameplayCore::new(&notes, 3).with_rules(rules);
+            let hits = [1.0, 1.1, 1.2, 1.3, 1.4, 3.0, 3.1, 3.2, 5.0, 5.1, 5.2, 5.3];
+
+            for time in hits {
+                core.press(DrumInput::LeftDon, time);
+            }
+
+            core.advance(10.0);
+            (core.results().score(), core.health().value())
+        };
+
+        // 5 roll hits, 3 big roll hits, and 4 balloon hits which pop it
+        assert_eq!(
+            play(RulesConfig::CLASSIC),
+            (5 * 300 + 3 * 360 + 4 * 300 + 5000, 0)
+        );
+        assert_eq!(play(RulesConfig::MODERN), (5 * 100 + 3 * 200 + 4 * 100, 0));
+
+        // Rolls only fill the gauge under rules that say so
+        let custom = RulesConfig {
+            roll_hit_health: 10,
+            balloon_pop_health: 100,
+            ..RulesConfig::MODERN
+        };
+        assert_eq!(play(custom).1, 12 * 10 + 100);
+    }
+
+    #[test]
+    fn test_note_scores() {
+        let notes = NoteChart {
+            gogo_regions: vec![GogoRegion {
+                start: 2.5,
+                end: 4.0,
+            }],
+            ..chart(&[
+                (NoteType::Don, 1.0),
+                (NoteType::BigDon, 2.0),
+                (NoteType::Kat, 3.0),
+                (NoteType::BigKat, 3.5),
+            ])
+        };
+
+        let play = |rules: RulesConfig| {
+            let mut core = GameplayCore::new(&notes, 3).with_rules(rules);
+            core.press(DrumInput::LeftDon, 1.0);
+            core.press(DrumInput::LeftDon, 2.0);
+            core.press(DrumInput::LeftKat, 3.0);
+            // An ok, as it's late
+            let [good, ok, _] = *core.timing_windows();
+            core.press(DrumInput::LeftKat, 3.5 + (good + ok) / 2.0);
+            core.results().score()
+        };
+
+        // The good is worth a quarter of the total each, and the ok an eighth
+        assert_eq!(play(RulesConfig::MODERN), 250000 * 3 + 125000);
+
+        // The big notes are doubled and the notes in go-go time are worth a fifth more
+        assert_eq!(play(RulesConfig::CLASSIC), 700 + 1400 + 840 + 840);
+    }
+
+    #[test]
+    fn test_note_inside_roll_takes_priority() {
+        // A don in the middle of a drumroll, hit early, on time, and late
+        for (offset, judgement) in [
+            (-0.05, NoteJudgement::Ok),
+            (0.0, NoteJudgement::Good),
+            (0.09, NoteJudgement::Bad),
+        ] {
+            let mut core = GameplayCore::new(
+                &chart(&[(NoteType::Roll(2.0), 1.0), (NoteType::Don, 2.0)]),
+                3,
+            );
+
+            // The roll gets the presses before the note comes into range
+            assert!(matches!(
+                core.press(DrumInput::LeftDon, 1.5)[..],
+                [GameplayEvent::Drumroll {
+                    note: 0,
+                    hits: 1,
+                    ..
+                }]
+            ));
+
+            let events = core.press(DrumInput::RightDon, 2.0 + offset);
+            assert!(
+                matches!(
+                    events[..],
+                    [GameplayEvent::Hit { note: 1, judgement: j, .. }] if j == judgement
+                ),
+                "offset {offset}: {events:?}"
+            );
+
+            // A press of the other colour still goes to the roll while the note is in range, and
+            // once the note is hit the roll gets every press
+            assert!(matches!(
+                core.press(DrumInput::LeftKat, 2.0 + offset)[..],
+                [GameplayEvent::Drumroll {
+                    note: 0,
+                    hits: 2,
+                    ..
+                }]
+            ));
+            assert!(matches!(
+                core.press(DrumInput::LeftDon, 2.0 + offset + 0.01)[..],
+                [GameplayEvent::Drumroll {
+                    note: 0,
+                    hits: 3,
+                    ..
+                }]
+            ));
+
+            // The note isn't missed when the roll ends
+            assert_eq!(core.advance(4.0), vec![]);
+            assert_eq!(core.results().misses(), 0);
+            assert_eq!(core.results().drumrolls(), 3);
+            assert_eq!(core.results().rolls()[0].hits(), 3);
+        }
+    }
+
+    #[test]
+    fn test_note_inside_roll_can_be_missed() {
+        let mut core = GameplayCore::new(
+            &chart(&[
+                (NoteType::Roll(2.0), 1.0),
+                (NoteType::Don, 1.5),
+                (NoteType::Kat, 2.0),
+            ]),
+            3,
+        );
+
+        // The don goes by without being hit, while the roll is still going
+        assert_eq!(core.advance(1.7), vec![GameplayEvent::Miss { note: 1 }]);
+
+        // The kat can still be hit afterwards, and the roll is still there
+        assert!(matches!(
+            core.press(DrumInput::LeftKat, 2.0)[..],
+            [GameplayEvent::Hit { note: 2, .. }]
+        ));
+        assert!(matches!(
+            core.press(DrumInput::LeftDon, 2.5)[..],
+            [GameplayEvent::Drumroll { note: 0, .. }]
+        ));
+
+        assert_eq!(core.advance(4.0), vec![]);
+        assert_eq!(core.results().misses(), 1);
+        assert_eq!(core.results().goods(), 1);
+        assert_eq!(core.results().current_combo(), 1);
+    }
+
+    #[test]
+    fn test_note_inside_balloon_takes_priority() {
+        let mut core = GameplayCore::new(
+            &chart(&[(NoteType::BalloonRoll(2.0, 5), 1.0), (NoteType::Don, 2.0)]),
+            3,
+        );
+
+        assert!(matches!(
+            core.press(DrumInput::LeftDon, 2.0)[..],
+            [GameplayEvent::Hit { note: 1, .. }]
+        ));
+        assert!(matches!(
+            core.press(DrumInput::RightDon, 2.01)[..],
+            [GameplayEvent::BalloonHit {
+                note: 0,
+                hits_left: 4,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_judgements_are_the_same_late_in_a_song() {
+        // Presses 5μs inside and outside the edges of each window (25, 75 and 108ms on oni)
+        let offsets = [
+            0.024995, 0.025005, -0.024995, -0.025005, 0.074995, 0.075005, -0.074995, -0.107995,
+            0.108005,
+        ];
+
+        // The notes are a fraction of a second apart, so that their times can't be held exactly
+        let note_time = |start: f64, i: usize| start + i as f64 * 1.123;
+
+        let judgements_from = |start: f64| {
+            let notes = (0..offsets.len())
+                .map(|i| (NoteType::Don, note_time(start, i)))
+                .collect::<Vec<_>>();
+            let mut core = GameplayCore::new(&chart(&notes), 3);
+
+            offsets
+                .iter()
+                .enumerate()
+                .map(|(i, offset)| {
+                    let time = note_time(start, i) + offset;
+                    let mut events = core.advance(time);
+                    events.extend(core.press(DrumInput::LeftDon, time));
+
+                    events.iter().find_map(|event| match event {
+                        GameplayEvent::Hit { judgement, .. } => Some(*judgement),
+                        _ => None,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let early = judgements_from(10.0);
+        assert_eq!(
+            early,
+            [
+                Some(NoteJudgement::Good),
+                Some(NoteJudgement::Ok),
+                Some(NoteJudgement::Good),
+                Some(NoteJudgement::Ok),
+                Some(NoteJudgement::Ok),
+                Some(NoteJudgement::Bad),
+                Some(NoteJudgement::Ok),
+                Some(NoteJudgement::Bad),
+                None,
+            ]
+        );
+
+        // Fifteen minutes in, an f32 can only tell times apart to within about 60μs, which would be
+        // enough to push these presses into the wrong window if either the clock or the notes used
+        // one.
+        assert_eq!(judgements_from(900.0), early);
+    }
+
+    #[test]
+    fn test_inputs_are_judged_at_their_own_time() {
+        // The drum is hit right on the note, but the hit is only handled on a frame 50ms later,
+        // by which time the fixed updates (one every millisecond) have taken the core up to that
+        // frame
+        let judge = |press_time: f64| {
+            let mut core = GameplayCore::new(&chart(&[(NoteType::Don, 1.0)]), 3);
+            let mut events = Vec::new();
+            for step in 0..=1050 {
+                events.extend(core.advance(step as f64 / 1000.0));
+            }
+            events.extend(core.press(DrumInput::LeftDon, press_time));
+
+            events.into_iter().find_map(|event| match event {
+                GameplayEvent::Hit {
+                    judgement, offset, ..
+                } => Some((judgement, offset)),
+                _ => None,
+            })
+        };
+
+        // Timed by when it was made, it's exactly on the note
+        assert_eq!(judge(1.0), Some((NoteJudgement::Good, 0.0)));
+
+        // Timed by when the frame ran, it would have been late
+        assert!(matches!(judge(1.05), Some((NoteJudgement::Ok, _))));
+    }
+
+    /// A layout with round numbers: notes move 100 pixels a second across a field from 0 to 1100,
+    /// and are hit at 100.
+    const LAYOUT: NoteLayout = NoteLayout {
+        hit_x: 100.,
+        y: 50.,
+        field_start: 0.,
+        field_end: 1100.,
+        velocity: 100.,
+        note_radius: 10.,
+        big_note_radius: 20.,
+        balloon_reach: 40.,
+    };
+
+    fn interval(note_type: NoteType, scroll_speed: f32, sudden: Option<f32>) -> (f32, f32) {
+        let note = Note {
+            note_type,
+            time: 20.0,
+            scroll_speed,
+            vertical_speed: 0.0,
+        };
+
+        let settings = VisibilitySettings {
+            sudden,
+            ..Default::default()
+        };
+        visibility_interval(&note, &LAYOUT, &settings)
+    }
+
+    fn assert_interval(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-4 && (actual.1 - expected.1).abs() < 1e-4,
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+
+    #[test]
+    fn test_visibility_of_notes() {
+        // The note's left edge reaches the end of the field 10.1 seconds early, and its right edge
+        // leaves the start 1.1 seconds late
+        assert_interval(interval(NoteType::Don, 1.0, None), (9.9, 21.1));
+        assert_interval(interval(NoteType::BigKat, 1.0, None), (9.8, 21.2));
+        // Twice as fast, on screen for half as long
+        assert_interval(interval(NoteType::Kat, 2.0, None), (14.95, 20.55));
+    }
+
+    #[test]
+    fn test_visibility_with_sudden() {
+        // Notes only appear once they're 300 pixels away
+        assert_interval(interval(NoteType::Don, 1.0, Some(300.)), (17.0, 21.1));
+        assert_interval(interval(NoteType::Don, 0.5, Some(300.)), (14.0, 22.2));
+        // SUDDEN can't make notes appear before they'd be on screen anyway. Coming from the left,
+        // there's only 110 pixels between the start of the field and the hit position.
+        assert_interval(interval(NoteType::Don, 1.0, Some(5000.)), (9.9, 21.1));
+        assert_interval(interval(NoteType::Don, -1.0, Some(300.)), (18.9, 30.1));
+    }
+
+    #[test]
+    fn test_visibility_with_hidden() {
+        let settings = VisibilitySettings {
+            hidden: Some(300.),
+            ..Default::default()
+        };
+        let note = |note_type, scroll_speed| Note {
+            note_type,
+            time: 20.0,
+            scroll_speed,
+            vertical_speed: 0.0,
+        };
+
+        // Notes are gone once they're 300 pixels away, whichever way they come from
+        let hidden =
+            |note_type, speed| visibility_interval(&note(note_type, speed), &LAYOUT, &settings);
+        assert_interval(hidden(NoteType::Don, 1.0), (9.9, 17.0));
+        assert_interval(hidden(NoteType::Don, -1.0), (18.9, 18.9));
+        assert_interval(hidden(NoteType::Kat, 2.0), (14.95, 18.5));
+
+        // ...and fade out over the last stretch before that
+        assert_eq!(settings.opacity(1000.), 1.0);
+        assert_eq!(settings.opacity(300. + HIDDEN_FADE_LENGTH / 2.), 0.5);
+        assert_eq!(settings.opacity(100.), 0.0);
+        assert_eq!(VisibilitySettings::default().opacity(0.), 1.0);
+    }
+
+    #[test]
+    fn test_visibility_with_negative_and_zero_scroll() {
+        // Coming from the left, the note enters at the start of the field and leaves at the end
+        assert_interval(interval(NoteType::Don, -1.0, None), (18.9, 30.1));
+
+        // Notes that don't move sit at the hit position forever
+        assert_eq!(
+            interval(NoteType::Don, 0.0, None),
+            (f32::NEG_INFINITY, f32::INFINITY)
+        );
+
+        // ...unless that's off the screen
+        let offscreen = NoteLayout {
+            field_start: 500.,
+            ..LAYOUT
+        };
+        let note = Note {
+            note_type: NoteType::Don,
+            time: 20.0,
+            scroll_speed: 0.0,
+            vertical_speed: 0.0,
+        };
+        let (appear, vanish) = visibility_interval(&note, &offscreen, &Default::default());
+        assert!(!(appear..vanish).contains(&20.0));
+    }
+
+    #[test]
+    fn test_visibility_of_rolls() {
+        // The body is 200 pixels long, so the roll stays until its end has left the field
+        assert_interval(interval(NoteType::Roll(2.0), 1.0, None), (9.9, 23.0));
+        // Going backwards, the body trails to the left, so it takes longer to leave at the end
+        assert_interval(interval(NoteType::BigRoll(2.0), -1.0, None), (18.9, 32.0));
+        // A roll too short for its body to poke out of its head is like any other note
+        assert_interval(interval(NoteType::Roll(0.05), 1.0, None), (9.9, 21.1));
+    }
+
+    #[test]
+    fn test_visibility_of_balloons() {
+        // The balloon waits at the hit position for 3 seconds, then leaves like a note would. Its
+        // sprite reaches 40 pixels to the right, so it takes longer to leave than a don.
+        assert_interval(
+            interval(NoteType::BalloonRoll(3.0, 10), 1.0, None),
+            (9.9, 24.4),
+        );
+        assert_interval(
+            interval(NoteType::BalloonRoll(3.0, 10), 1.0, Some(300.)),
+            (17.0, 24.4),
+        );
+    }
+
+    #[test]
+    fn test_visibility_of_barlines() {
+        let barline = Barline {
+            time: 20.0,
+            scroll_speed: 1.0,
+            vertical_speed: 0.0,
+        };
+
+        assert_interval(
+            barline_visibility_interval(&barline, &LAYOUT, &Default::default()),
+            (10.0, 21.0),
+        );
+    }
+
+    #[test]
+    fn test_visibility_window() {
+        // The third note is slow, so it appears before the second and leaves after the fourth
+        let intervals = [(0.0, 2.0), (3.0, 5.0), (1.0, 9.0), (6.0, 8.0), (10.0, 12.0)];
+        let window = VisibilityWindow::new(intervals);
+
+        assert_eq!(window.range(-1.0), 0..0);
+        assert_eq!(window.range(0.5), 0..1);
+        assert_eq!(window.range(4.0), 1..3);
+        assert_eq!(window.range(8.5), 2..4);
+        assert_eq!(window.range(11.0), 4..5);
+        assert_eq!(window.range(20.0), 5..5);
+        assert_eq!(VisibilityWindow::new([]).range(0.0), 0..0);
+    }
+
+    #[test]
+    fn test_visibility_window_finds_every_visible_note() {
+        let mut rng = crate::rng::Rng::new(7);
+        let intervals: Vec<_> = (0..500)
+            .map(|i| {
+                let appear = i as f32 * 0.1 + rng.range(-3.0, 0.0);
+                (appear, appear + rng.range(0.5, 4.0))
+            })
+            .collect();
+        let window = VisibilityWindow::new(intervals.iter().copied());
+
+        for step in 0..600 {
+            let time = step as f32 * 0.1 - 5.0;
+            let range = window.range(time);
+
+            for (i, &(appear, vanish)) in intervals.iter().enumerate() {
+                if (appear..vanish).contains(&time) {
+                    assert!(range.contains(&i), "note {i} is visible at {time}");
+                }
+            }
+        }
+    }
+}
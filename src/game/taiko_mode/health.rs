@@ -0,0 +1,175 @@
+//! Keeping track of the soul gauge (the "health" of the player) over the course of a song.
+//!
+//! Unlike most rhythm games, health in taiko only matters at the end of the song: if the gauge is
+//! above the clear threshold when the song ends, the song is cleared. The exception is sudden
+//! death (see [FailBehaviour](crate::settings::FailBehaviour)), where the stage is failed as soon
+//! as the gauge runs out.
+use super::gameplay::NoteJudgement;
+
+pub type HealthInt = u32;
+
+/// The value of a completely full soul gauge.
+pub const MAX_HEALTH: HealthInt = 10000;
+
+/// Roughly what proportion of the notes in a chart need to be hit with a "good" judgement for the
+/// gauge to fill up, for each difficulty.
+const FILL_RATIO: [f32; 5] = [0.55, 0.65, 0.7, 0.75, 0.75];
+
+/// Returns the amount of health needed at the end of the song to clear a chart of the given
+/// difficulty.
+pub fn clear_threshold(difficulty: usize) -> HealthInt {
+    match difficulty {
+        0 => 6000,
+        1 | 2 => 7000,
+        _ => 8000,
+    }
+}
+
+/// Returns how much health a good, ok and bad (or missed) note are worth for a chart of the
+/// given difficulty and number of notes, in that order.
+pub fn judgement_hp_values(difficulty: usize, note_count: usize) -> [i32; 3] {
+    if note_count == 0 {
+        return [0; 3];
+    }
+
+    let ratio = FILL_RATIO.get(difficulty).copied().unwrap_or(0.75);
+    let good = (MAX_HEALTH as f32 / (note_count as f32 * ratio)).ceil() as i32;
+
+    [good, good / 2, -2 * good]
+}
+
+/// Splits a health value into how full the red (below the clear threshold) and gold (above the
+/// clear threshold) parts of the gauge are. Both values are between 0 and 1.
+pub fn gauge_fill(health: HealthInt, threshold: HealthInt) -> (f32, f32) {
+    let health = health.min(MAX_HEALTH);
+    let red = health.min(threshold) as f32 / threshold as f32;
+    let gold = health.saturating_sub(threshold) as f32 / (MAX_HEALTH - threshold) as f32;
+
+    (red, gold)
+}
+
+#[derive(Debug, Clone)]
+pub struct Health {
+    value: HealthInt,
+    threshold: HealthInt,
+    hp_values: [i32; 3],
+    /// Whether a bad or a miss has taken the gauge down to empty, which fails the stage in sudden
+    /// death
+    emptied: bool,
+}
+
+impl Health {
+    pub fn new(difficulty: usize, note_count: usize) -> Self {
+        Self {
+            value: 0,
+            threshold: clear_threshold(difficulty),
+            hp_values: judgement_hp_values(difficulty, note_count),
+            emptied: false,
+        }
+    }
+
+    /// Updates the health for a note judgement, where None means the note was missed.
+    pub fn apply_judgement(&mut self, judgement: Option<NoteJudgement>) {
+        let change = match judgement {
+            Some(NoteJudgement::Good) => self.hp_values[0],
+            Some(NoteJudgement::Ok) => self.hp_values[1],
+            Some(NoteJudgement::Bad) | None => self.hp_values[2],
+        };
+
+        let before = self.value;
+        self.value = self.value.saturating_add_signed(change).min(MAX_HEALTH);
+        // The gauge starts out empty, so it only counts once there was something to lose
+        self.emptied |= before > 0 && self.value == 0;
+    }
+
+    /// Fills the gauge by a set amount, e.g. for hitting a drumroll under rules where that counts
+    /// (see [RulesConfig](crate::settings::RulesConfig)).
+    pub fn add(&mut self, amount: HealthInt) {
+        self.value = self.value.saturating_add(amount).min(MAX_HEALTH);
+    }
+
+    pub fn value(&self) -> HealthInt {
+        self.value
+    }
+
+    pub fn threshold(&self) -> HealthInt {
+        self.threshold
+    }
+
+    /// Whether a bad or a miss has ever taken the gauge from above zero down to empty. The gauge
+    /// filling up again afterwards doesn't change this.
+    pub fn was_emptied(&self) -> bool {
+        self.emptied
+    }
+
+    /// Whether the gauge is full enough to clear the song, if it were to end now.
+    pub fn is_clear(&self) -> bool {
+        self.value >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_health_clamps() {
+        let mut health = Health::new(3, 10);
+
+        health.apply_judgement(None);
+        assert_eq!(health.value(), 0);
+
+        for _ in 0..20 {
+            health.apply_judgement(Some(NoteJudgement::Good));
+        }
+
+        assert_eq!(health.value(), MAX_HEALTH);
+    }
+
+    #[test]
+    fn test_full_combo_clears() {
+        for difficulty in 0..5 {
+            let mut health = Health::new(difficulty, 300);
+
+            for _ in 0..300 {
+                health.apply_judgement(Some(NoteJudgement::Good));
+            }
+
+            assert_eq!(health.value(), MAX_HEALTH);
+        }
+    }
+
+    #[test]
+    fn test_was_emptied() {
+        let mut health = Health::new(3, 10);
+        for _ in 0..3 {
+            health.apply_judgement(Some(NoteJudgement::Good));
+        }
+        health.apply_judgement(Some(NoteJudgement::Bad));
+        assert!(health.value() > 0);
+        assert!(!health.was_emptied());
+
+        health.apply_judgement(None);
+        assert_eq!(health.value(), 0);
+        assert!(health.was_emptied());
+
+        health.apply_judgement(Some(NoteJudgement::Good));
+        assert!(health.was_emptied());
+
+        // Missing while the gauge is still empty at the start of a song doesn't
+        let mut health = Health::new(3, 10);
+        health.apply_judgement(None);
+        health.apply_judgement(Some(NoteJudgement::Bad));
+        assert_eq!(health.value(), 0);
+        assert!(!health.was_emptied());
+    }
+
+    #[test]
+    fn test_gauge_fill() {
+        assert_eq!(gauge_fill(0, 8000), (0.0, 0.0));
+        assert_eq!(gauge_fill(4000, 8000), (0.5, 0.0));
+        assert_eq!(gauge_fill(8000, 8000), (1.0, 0.0));
+        assert_eq!(gauge_fill(9000, 8000), (1.0, 0.5));
+        assert_eq!(gauge_fill(MAX_HEALTH, 8000), (1.0, 1.0));
+    }
+}
@@ -1,5 +1,23 @@
+mod clock;
+mod dancers;
+mod effects;
+mod gameplay;
+mod health;
+mod mods;
 mod note;
+mod preview;
 mod scene;
+mod scoring;
+mod simulate;
+mod stats;
 mod ui;
+mod versus;
 
-pub use scene::{PlayResult, TaikoMode};
+pub use gameplay::{timing_windows, PlayResult, ScoreInt};
+pub use health::{clear_threshold, MAX_HEALTH};
+pub use mods::Mods;
+pub use preview::NotePreview;
+pub use scene::{ReplaySetup, TaikoMode};
+pub use simulate::{autoplay_inputs, simulate, TimedInput, Verification};
+pub use stats::{robust_median, HandStats, HitErrorStats};
+pub use versus::VersusMode;
@@ -0,0 +1,205 @@
+//! Mods the player can pick to change how a chart plays. HIDDEN and SUDDEN change when notes can be
+//! seen, and INVERSE and SHUFFLE change which colour the notes are.
+//!
+//! The colours are changed in the chart itself before anything is made from it (see
+//! [Mods::apply]), so the notes on screen, the notes that are judged and autoplay all agree.
+//! SHUFFLE is seeded, and the seed is kept with the play's replay so that the replay gets the same
+//! notes.
+use serde::{Deserialize, Serialize};
+
+use super::gameplay::VisibilitySettings;
+use crate::notechart_parser::{NoteChart, NoteType};
+use crate::rng::Rng;
+
+/// How far from the receptacle notes have disappeared by with HIDDEN, in pixels.
+const HIDDEN_DISTANCE: f32 = 350.;
+
+/// How far from the receptacle notes appear with SUDDEN, in pixels.
+const SUDDEN_DISTANCE: f32 = 650.;
+
+/// The mods a chart is played with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mods {
+    /// Notes fade out before they reach the receptacle.
+    pub hidden: bool,
+    /// Notes only appear once they're close to the receptacle.
+    pub sudden: bool,
+    /// Every don is a kat, and every kat is a don.
+    pub inverse: bool,
+    /// If set, every don and kat is randomly a don or a kat, picked with this seed.
+    pub shuffle: Option<u32>,
+}
+
+impl Mods {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The names of the mods that are on, for listing with the play's results.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.hidden, "Hidden"),
+            (self.sudden, "Sudden"),
+            (self.inverse, "Inverse"),
+            (self.shuffle.is_some(), "Shuffle"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect()
+    }
+
+    /// The same mods, but shuffling with a new seed if they shuffle at all, so that each play with
+    /// SHUFFLE gets different notes.
+    pub fn reshuffled(self, seed: u32) -> Self {
+        Self {
+            shuffle: self.shuffle.map(|_| seed),
+            ..self
+        }
+    }
+
+    /// When notes can be seen with these mods.
+    pub fn visibility(&self) -> VisibilitySettings {
+        VisibilitySettings {
+            sudden: self.sudden.then_some(SUDDEN_DISTANCE),
+            hidden: self.hidden.then_some(HIDDEN_DISTANCE),
+        }
+    }
+
+    /// Whether the mods change the chart's notes (rather than just how they're shown).
+    pub fn changes_notes(&self) -> bool {
+        self.inverse || self.shuffle.is_some()
+    }
+
+    /// Returns the chart with its notes changed by the mods, including the notes of every branch.
+    /// Drumrolls and balloons are left as they are.
+    pub fn apply(&self, chart: &NoteChart) -> NoteChart {
+        let mut chart = chart.clone();
+        let mut rng = self.shuffle.map(|seed| Rng::new(seed as u64));
+
+        let branch_notes = chart
+            .branch_sections
+            .iter_mut()
+            .flat_map(|section| section.branches.iter_mut())
+            .flat_map(|branch| branch.notes.iter_mut());
+
+        for note in chart.notes.iter_mut().chain(branch_notes) {
+            if self.inverse {
+                note.note_type = inverted(note.note_type);
+            }
+
+            // Swapping half the notes at random leaves each one a random colour
+            if let Some(rng) = &mut rng {
+                if rng.next_u64() & 1 == 1 {
+                    note.note_type = inverted(note.note_type);
+                }
+            }
+        }
+
+        chart
+    }
+}
+
+/// The note in the other colour, or the note itself if it isn't a don or kat.
+fn inverted(note_type: NoteType) -> NoteType {
+    match note_type {
+        NoteType::Don => NoteType::Kat,
+        NoteType::Kat => NoteType::Don,
+        NoteType::BigDon => NoteType::BigKat,
+        NoteType::BigKat => NoteType::BigDon,
+        NoteType::CoopDon => NoteType::CoopKat,
+        NoteType::CoopKat => NoteType::CoopDon,
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::Note;
+
+    fn chart(note_types: &[NoteType]) -> NoteChart {
+        NoteChart {
+            notes: note_types
+                .iter()
+                .enumerate()
+                .map(|(i, &note_type)| Note {
+                    note_type,
+                    time: i as f64,
+                    scroll_speed: 1.0,
+                    vertical_speed: 0.0,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn note_types(chart: &NoteChart) -> Vec<NoteType> {
+        chart.notes.iter().map(|note| note.note_type).collect()
+    }
+
+    #[test]
+    fn test_inverse() {
+        let original = chart(&[
+            NoteType::Don,
+            NoteType::BigKat,
+            NoteType::Roll(1.0),
+            NoteType::BalloonRoll(1.0, 5),
+        ]);
+        let inverse = Mods {
+            inverse: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            note_types(&inverse.apply(&original)),
+            [
+                NoteType::Kat,
+                NoteType::BigDon,
+                NoteType::Roll(1.0),
+                NoteType::BalloonRoll(1.0, 5)
+            ]
+        );
+        assert_eq!(
+            note_types(&Mods::default().apply(&original)),
+            note_types(&original)
+        );
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let original = chart(&[NoteType::Don; 64]);
+        let shuffle = |seed| Mods {
+            shuffle: Some(seed),
+            ..Default::default()
+        };
+
+        // The same seed always gives the same notes, and a different one different notes
+        let shuffled = note_types(&shuffle(1).apply(&original));
+        assert_eq!(shuffled, note_types(&shuffle(1).apply(&original)));
+        assert_ne!(shuffled, note_types(&shuffle(2).apply(&original)));
+
+        // Some notes are changed, but not all of them
+        assert!(shuffled.contains(&NoteType::Don));
+        assert!(shuffled.contains(&NoteType::Kat));
+
+        // Times are left alone
+        let times = |chart: &NoteChart| chart.notes.iter().map(|n| n.time).collect::<Vec<_>>();
+        assert_eq!(times(&shuffle(1).apply(&original)), times(&original));
+    }
+
+    #[test]
+    fn test_names() {
+        let mods = Mods {
+            hidden: true,
+            shuffle: Some(0),
+            ..Default::default()
+        };
+
+        assert_eq!(mods.names(), ["Hidden", "Shuffle"]);
+        assert!(Mods::default().names().is_empty());
+        assert!(Mods::default().is_empty());
+        assert_eq!(mods.reshuffled(5).shuffle, Some(5));
+        assert_eq!(Mods::default().reshuffled(5).shuffle, None);
+    }
+}
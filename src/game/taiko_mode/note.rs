@@ -1,6 +1,8 @@
 //! Defines structs for drawing notes and barlines to the screen
+use std::collections::HashSet;
+use std::rc::Rc;
+
 use lyon::lyon_tessellation::TessellationError;
-use winit::keyboard::PhysicalKey;
 
 use crate::notechart_parser::NoteType;
 use crate::notechart_parser::{Barline, Note};
@@ -10,42 +12,183 @@ use crate::{game::TextureCache, render::shapes::ShapeBuilder};
 
 use crate::render::{
     shapes::{Shape, SolidColour},
-    texture::Sprite,
-    Renderable,
+    texture::{Sprite, Texture, NO_TINT},
+    Renderable, VIRTUAL_WIDTH,
 };
-use crate::settings::{settings, SETTINGS};
 
-use super::ui::{LEFT_PANEL_WIDTH, NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y};
+use super::gameplay::{
+    barline_visibility_interval, is_playable, visibility_interval, NoteLayout, VisibilitySettings,
+};
+use super::ui::{mirror_x, LEFT_PANEL_WIDTH, NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y};
 
-const VELOCITY: f32 = (1920. - NOTE_HIT_X) / 2.;
+const VELOCITY: f32 = (VIRTUAL_WIDTH - NOTE_HIT_X) / 2.;
 const ROLL_COLOUR: [f32; 4] = [1., 195. / 255., 44. / 255., 1.];
 
+/// How big ghost notes are compared to normal ones (see [ghost_layout]).
+const GHOST_SCALE: f32 = 0.4;
+/// How opaque ghost notes are.
+const GHOST_ALPHA: f32 = 0.45;
+/// The height ghost notes travel along, in a lane along the top of the note field.
+const GHOST_LANE_Y: f32 = NOTE_FIELD_Y + 32.;
+
+/// Where notes are drawn, for working out where they are and when they're on screen (see
+/// [visibility_interval]).
+///
+/// In a mirrored playfield the receptacle and left panel are flipped over to the right of the
+/// screen, and notes travel from left to right. This is only a change in where things are drawn:
+/// notes are hit at exactly the same times either way.
+// TODO: another hardcoded resolution to get rid of
+pub fn note_layout(mirrored: bool) -> NoteLayout {
+    let (field_start, field_end) = if mirrored {
+        (0., VIRTUAL_WIDTH - LEFT_PANEL_WIDTH)
+    } else {
+        (LEFT_PANEL_WIDTH, VIRTUAL_WIDTH)
+    };
+
+    NoteLayout {
+        hit_x: mirror_x(NOTE_HIT_X, mirrored),
+        y: NOTE_Y,
+        field_start,
+        field_end,
+        velocity: if mirrored { -VELOCITY } else { VELOCITY },
+        note_radius: 50.,
+        big_note_radius: 75.,
+        // The balloon's notehead is 50 pixels from the left of its 250 pixel wide sprite. The
+        // sprite isn't flipped in a mirrored playfield, so it always reaches to the right.
+        balloon_reach: 200.,
+    }
+}
+
+/// Where ghost notes are drawn: the same as [note_layout], but shrunk down into a lane along the
+/// top of the note field, so they pass over the receptacle above the notes being played.
+pub fn ghost_layout(mirrored: bool) -> NoteLayout {
+    let layout = note_layout(mirrored);
+
+    NoteLayout {
+        y: GHOST_LANE_Y,
+        note_radius: layout.note_radius * GHOST_SCALE,
+        big_note_radius: layout.big_note_radius * GHOST_SCALE,
+        balloon_reach: layout.balloon_reach * GHOST_SCALE,
+        ..layout
+    }
+}
+
 // Nice expressive aliases for the indices we'll use for note judgements
 pub const GOOD: usize = 0;
 pub const OK: usize = 1;
 pub const BAD: usize = 2;
 
-// I have to credit OpenTaiko as that's where I got these values.
-// (and also for inspiring me to give making my own simulator a red-hot go)
-pub const EASY_NORMAL_TIMING: [f32; 3] = [0.042, 0.108, 0.125];
-pub const HARD_EXTREME_TIMING: [f32; 3] = [0.025, 0.075, 0.108];
+/// Returns the filenames of the textures needed to draw a note of the given type.
+///
+/// This is the one place where note types are mapped to textures, so that everything a chart
+/// needs can be loaded before the song starts (see [chart_textures]).
+pub fn note_textures(note_type: NoteType) -> &'static [&'static str] {
+    match note_type {
+        NoteType::Don => &["don.png"],
+        NoteType::Kat => &["kat.png"],
+        NoteType::BigDon | NoteType::CoopDon => &["big_don.png"],
+        NoteType::BigKat | NoteType::CoopKat => &["big_kat.png"],
+        NoteType::Roll(_) | NoteType::BigRoll(_) => &["drumroll_start.png"],
+        NoteType::BalloonRoll(..) => &["balloon 1.png"],
+        // TODO: Special rolls aren't drawn yet
+        NoteType::SpecialRoll(..) => &[],
+    }
+}
 
-/// Takes a list of notes in a song and creates visual representations for all of them.
+/// Returns the set of every texture needed to draw the given notes.
+pub fn chart_textures(notes: &[Note]) -> HashSet<&'static str> {
+    notes
+        .iter()
+        .flat_map(|note| note_textures(note.note_type))
+        .copied()
+        .collect()
+}
+
+/// Takes a list of notes in a song and creates visual representations for all of them, with
+/// their scroll speeds multiplied by the given note speed, and drawn in the given layout according
+/// to the given visibility settings.
+///
+/// Notes that aren't playable are skipped, so that the index of each note is the same as in the
+/// [GameplayCore](super::gameplay::GameplayCore).
 pub fn create_notes(
     renderer: &Renderer,
     textures: &mut TextureCache,
     notes: &[Note],
+    note_speed: f32,
+    layout: &NoteLayout,
+    visibility: &VisibilitySettings,
 ) -> Vec<TaikoModeNote> {
     notes
         .iter()
-        .filter_map(|note| TaikoModeNote::new(renderer, note, textures))
+        .filter(|note| is_playable(note.note_type))
+        .filter_map(|note| {
+            let note = Note {
+                scroll_speed: note.scroll_speed * note_speed,
+                vertical_speed: note.vertical_speed * note_speed,
+                ..*note
+            };
+
+            TaikoModeNote::new(renderer, &note, textures, layout, visibility)
+        })
+        .collect()
+}
+
+/// Whether a note is shown as a ghost note. Only notes that are hit once are: the ghosts are there
+/// to show the rhythm of a harder chart, and rolls would only cover it up.
+pub fn is_ghost(note_type: NoteType) -> bool {
+    matches!(
+        note_type,
+        NoteType::Don
+            | NoteType::Kat
+            | NoteType::BigDon
+            | NoteType::BigKat
+            | NoteType::CoopDon
+            | NoteType::CoopKat
+    )
+}
+
+/// Takes the notes of another difficulty of the song and creates faint, shrunken copies of them to
+/// show alongside the chart being played (see [GhostNote]). Like [create_notes], their scroll
+/// speeds are multiplied by the given note speed.
+pub fn create_ghost_notes(
+    renderer: &Renderer,
+    textures: &mut TextureCache,
+    notes: &[Note],
+    note_speed: f32,
+    layout: &NoteLayout,
+    visibility: &VisibilitySettings,
+) -> Vec<GhostNote> {
+    notes
+        .iter()
+        .filter(|note| is_ghost(note.note_type))
+        .filter_map(|note| {
+            let note = Note {
+                scroll_speed: note.scroll_speed * note_speed,
+                vertical_speed: note.vertical_speed * note_speed,
+                ..*note
+            };
+
+            GhostNote::new(renderer, &note, textures, layout, visibility)
+        })
         .collect()
 }
 
-/// Takes a list of barlines in a song and creates visual representations for all of them.
-pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<TaikoModeBarline> {
+/// Takes a list of barlines in a song and creates visual representations for all of them, with
+/// their scroll speeds multiplied by the given note speed.
+pub fn create_barlines(
+    renderer: &Renderer,
+    barlines: &[Barline],
+    note_speed: f32,
+    layout: &NoteLayout,
+    visibility: &VisibilitySettings,
+) -> Vec<TaikoModeBarline> {
     barlines
         .iter()
+        .map(|barline| Barline {
+            scroll_speed: barline.scroll_speed * note_speed,
+            vertical_speed: barline.vertical_speed * note_speed,
+            ..*barline
+        })
         .map(|barline| {
             let visual_line = ShapeBuilder::new()
                 .filled_rectangle(
@@ -55,8 +198,9 @@ pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<Tai
                 )
                 .expect("Error creating barline shape")
                 .position([
-                    x_position_of_note(barline.time, 0., barline.scroll_speed),
-                    NOTE_FIELD_Y,
+                    x_position_of_note(layout, barline.time, 0., barline.scroll_speed),
+                    y_position_of_note(layout, barline.time, 0., barline.vertical_speed)
+                        - NOTE_FIELD_HEIGHT / 2.0,
                     0.,
                 ])
                 .build(&renderer.device);
@@ -65,90 +209,64 @@ pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<Tai
                 visual_line,
                 time: barline.time,
                 scroll_speed: barline.scroll_speed,
+                vertical_speed: barline.vertical_speed,
+                layout: *layout,
+                visibility: barline_visibility_interval(&barline, layout, visibility),
             }
         })
         .collect()
 }
 
 /// Where on the screen a note should be drawn given the current time of the song, when the note
-/// should be hit and how fast it travels.
-pub fn x_position_of_note(current_time: f32, note_time: f32, scroll_speed: f32) -> f32 {
-    NOTE_HIT_X + VELOCITY * (note_time - current_time) * scroll_speed
-}
-
-fn drumroll_visual_length(scroll_speed: f32, length_of_time: f32) -> f32 {
-    scroll_speed * length_of_time * VELOCITY
-}
-
-// I wonder if these two types could fit into the parser module
-// They're obviously pretty important but, it seems they're not that useful in the parser module
-// itself, since that module has the more general NoteType enum.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum NoteColour {
-    Don,
-    Kat,
-}
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub(crate) struct BasicNoteType {
-    colour: NoteColour,
-    big: bool,
-}
-
-impl BasicNoteType {
-    fn is_hit_by(&self, key: PhysicalKey) -> bool {
-        let settings = SETTINGS.read().unwrap();
-        match self.colour {
-            NoteColour::Don => settings.key_is_don(key),
-            NoteColour::Kat => settings.key_is_kat(key),
-        }
-    }
+/// should be hit and how fast it travels. In a mirrored layout, notes come from the other side of
+/// the receptacle, as do notes with a negative scroll speed.
+///
+/// The times are how far the track has scrolled rather than the time itself, which only differs in
+/// charts that scroll by beat (see
+/// [NoteChart::scroll_time](crate::notechart_parser::NoteChart::scroll_time)). Notes are judged by
+/// when they're actually hit either way.
+pub fn x_position_of_note(
+    layout: &NoteLayout,
+    current_time: f32,
+    note_time: f32,
+    scroll_speed: f32,
+) -> f32 {
+    layout.hit_x + layout.velocity * (note_time - current_time) * scroll_speed
 }
 
-impl TryFrom<NoteType> for BasicNoteType {
-    type Error = ();
-
-    fn try_from(value: NoteType) -> Result<Self, Self::Error> {
-        match value {
-            NoteType::Don => Ok(Self {
-                colour: NoteColour::Don,
-                big: false,
-            }),
-            NoteType::Kat => Ok(Self {
-                colour: NoteColour::Kat,
-                big: false,
-            }),
-            NoteType::BigDon => Ok(Self {
-                colour: NoteColour::Don,
-                big: true,
-            }),
-            NoteType::BigKat => Ok(Self {
-                colour: NoteColour::Kat,
-                big: true,
-            }),
-            _ => Err(()),
-        }
-    }
+/// The height on the screen a note should be drawn at, like [x_position_of_note] but for how fast
+/// it moves down the screen (see [Note::vertical_speed]). Mirroring the layout doesn't turn this
+/// upside down.
+pub fn y_position_of_note(
+    layout: &NoteLayout,
+    current_time: f32,
+    note_time: f32,
+    vertical_speed: f32,
+) -> f32 {
+    layout.y - layout.velocity.abs() * (note_time - current_time) * vertical_speed
 }
 
 /// The "Inner" taiko mode Note type is an enum containing data and behaviour specific to the note
 /// type.
 #[derive(Debug)]
 pub(crate) enum NoteInner {
+    /// A don or kat. There can be hundreds of these on screen at once, so rather than each having
+    /// its own sprite they're drawn together in a [SpriteBatch](crate::render::texture::SpriteBatch)
+    /// by the [NoteField](super::ui::NoteField), and only keep track of where and how they should
+    /// be drawn.
     Note {
-        sprite: Sprite,
-        kind: BasicNoteType,
+        texture: Rc<Texture>,
+        /// The centre of the note, and its depth
+        position: [f32; 3],
+        tint: [f32; 4],
         is_hit: bool,
     },
     Roll {
         start_sprite: Sprite,
         body_sprite: Shape,
-        big: bool,
-        duration: f32,
     },
     Balloon {
         sprite: Sprite,
-        hit_target: u32,
         hits_left: u32,
         duration: f32,
         started: bool,
@@ -158,8 +276,32 @@ pub(crate) enum NoteInner {
 #[derive(Debug)]
 pub struct TaikoModeNote {
     pub(crate) note: NoteInner,
+    /// The type of note this was created from, so that it can be recreated if need be
+    note_type: NoteType,
+    time: f32,
+    scroll_speed: f32,
+    vertical_speed: f32,
+    /// Where the note is drawn
+    layout: NoteLayout,
+    /// When the note appears on screen and leaves it again (see [visibility_interval])
+    visibility: (f32, f32),
+    /// The settings the visibility was worked out with, which also say how much the note has
+    /// faded out with HIDDEN
+    visibility_settings: VisibilitySettings,
+}
+
+/// A note from another difficulty of the song, drawn small and see-through so the player can get
+/// to know a harder chart while playing an easier one. Ghost notes can't be hit: they just pass
+/// over the receptacle and carry on.
+#[derive(Debug)]
+pub struct GhostNote {
+    sprite: Sprite,
+    note_type: NoteType,
     time: f32,
     scroll_speed: f32,
+    vertical_speed: f32,
+    layout: NoteLayout,
+    visibility: (f32, f32),
 }
 
 #[derive(Debug)]
@@ -167,21 +309,33 @@ pub struct TaikoModeBarline {
     visual_line: Shape,
     time: f32,
     scroll_speed: f32,
+    vertical_speed: f32,
+    layout: NoteLayout,
+    visibility: (f32, f32),
 }
 
 impl NoteInner {
-    fn new(renderer: &Renderer, note: &Note, textures: &mut TextureCache) -> Option<Self> {
+    fn new(
+        renderer: &Renderer,
+        note: &Note,
+        layout: &NoteLayout,
+        textures: &mut TextureCache,
+    ) -> Option<Self> {
         let note_type = note.note_type;
-        let pixel_vel = VELOCITY * note.scroll_speed;
+        let pixel_vel = layout.velocity * note.scroll_speed;
 
         let mut get_texture = |filename| {
             textures
                 .get(&renderer.device, &renderer.queue, filename)
                 .unwrap()
         };
+        // The body trails behind the head, so it reaches to the left when notes travel to the left
+        // and to the right when they travel to the right.
         let create_roll_body = |length: f32, height: f32| -> Result<Shape, TessellationError> {
             const OUTLINE_WIDTH: f32 = 3.;
-            let dx = -height / 2.;
+            let direction = length.signum();
+            let outline_width = OUTLINE_WIDTH * direction;
+            let dx = -direction * height / 2.;
             let dy = -height / 2.;
 
             Ok(ShapeBuilder::new()
@@ -199,8 +353,8 @@ impl NoteInner {
                 )?
                 // Inside
                 .filled_rectangle(
-                    [OUTLINE_WIDTH, OUTLINE_WIDTH + dy],
-                    [length - OUTLINE_WIDTH + dx, height - OUTLINE_WIDTH + dy],
+                    [outline_width, OUTLINE_WIDTH + dy],
+                    [length - outline_width + dx, height - OUTLINE_WIDTH + dy],
                     SolidColour::new(ROLL_COLOUR),
                 )?
                 .filled_circle(
@@ -217,67 +371,58 @@ impl NoteInner {
             | NoteType::BigDon
             | NoteType::CoopDon
             | NoteType::BigKat
-            | NoteType::CoopKat => {
-                let sprite_name = match note_type {
-                    NoteType::Don => "don.png",
-                    NoteType::Kat => "kat.png",
-                    NoteType::BigDon | NoteType::CoopDon => "big_don.png",
-                    NoteType::BigKat | NoteType::CoopKat => "big_kat.png",
-                    _ => unreachable!(),
-                };
-
-                Self::Note {
-                    sprite: SpriteBuilder::new(get_texture(sprite_name))
-                        .centre()
-                        .depth(Some(0.))
-                        .build(renderer),
-                    kind: note_type.try_into().unwrap(),
-                    is_hit: false,
-                }
-            }
+            | NoteType::CoopKat => Self::Note {
+                texture: get_texture(note_textures(note_type)[0]),
+                position: [0.; 3],
+                tint: NO_TINT,
+                is_hit: false,
+            },
 
             NoteType::Roll(length) | NoteType::BigRoll(length) => {
-                let start = SpriteBuilder::new(get_texture("drumroll_start.png"))
+                let start = SpriteBuilder::new(get_texture(note_textures(note_type)[0]))
                     .centre()
                     .depth(Some(0.))
                     .build(renderer);
 
                 let body_length = pixel_vel * length;
-                let body = create_roll_body(body_length, 100.0).ok()?;
+                let body =
+                    create_roll_body(body_length, 100.0).expect("Error creating drumroll shape");
 
                 NoteInner::Roll {
                     start_sprite: start,
                     body_sprite: body,
-                    duration: length,
-                    big: matches!(note_type, NoteType::BigRoll(_)),
                 }
             }
 
             NoteType::BalloonRoll(duration, hit_target) => {
                 Self::Balloon {
-                    sprite: SpriteBuilder::new(get_texture("balloon 1.png"))
+                    sprite: SpriteBuilder::new(get_texture(note_textures(note_type)[0]))
                         .depth(Some(0.))
                         // The notehead is centred at [50, 50].
                         .origin([50., 50.])
                         .build(renderer),
-                    hit_target,
                     hits_left: hit_target,
                     duration,
                     started: false,
                 }
             }
 
-            _ => return None,
+            // TODO: Special rolls aren't drawn yet
+            NoteType::SpecialRoll(..) => return None,
         };
 
         Some(result)
     }
 
     /// Sets the position of the note. The note will be centred at that position.
-    fn set_x_position(&mut self, x: f32, depth: f32, renderer: &Renderer) {
-        let position = [x, NOTE_Y];
+    fn set_position(&mut self, position: [f32; 2], depth: f32, renderer: &Renderer) {
         match self {
-            NoteInner::Note { sprite, .. } | NoteInner::Balloon { sprite, .. } => {
+            NoteInner::Note {
+                position: note_position,
+                ..
+            } => *note_position = [position[0], position[1], depth],
+
+            NoteInner::Balloon { sprite, .. } => {
                 sprite.set_position(position, renderer);
                 sprite.set_depth(Some(depth), renderer);
             }
@@ -294,18 +439,20 @@ impl NoteInner {
         }
     }
 
-    fn x_position_for_time(
+    /// Where the note is drawn at the given time, or None if it isn't drawn at all because it's
+    /// been hit or popped. `scroll_speed` is how fast the note moves across and down the screen.
+    fn position_for_time(
         &self,
+        layout: &NoteLayout,
         current_time: f32,
         note_time: f32,
-        scroll_speed: f32,
-    ) -> Option<f32> {
-        match &self {
+        [scroll_speed, vertical_speed]: [f32; 2],
+    ) -> Option<[f32; 2]> {
+        // The time the note is drawn as if it's hit at
+        let drawn_time = match &self {
             NoteInner::Note { is_hit, .. } if *is_hit => None,
 
-            NoteInner::Roll { .. } | NoteInner::Note { .. } => {
-                Some(x_position_of_note(current_time, note_time, scroll_speed))
-            }
+            NoteInner::Roll { .. } | NoteInner::Note { .. } => Some(note_time),
 
             NoteInner::Balloon {
                 hits_left,
@@ -318,41 +465,57 @@ impl NoteInner {
                     None
                 } else if current_time < note_time {
                     // Before it is active, draw it like any other note
-                    Some(x_position_of_note(current_time, note_time, scroll_speed))
+                    Some(note_time)
                 } else if current_time > note_time + *duration {
                     // After it is active, if it hasn't been started, draw it
                     // if it was started, it will disappear, so don't do anything
-                    (!*has_been_started).then_some(x_position_of_note(
-                        current_time,
-                        note_time + *duration,
-                        scroll_speed,
-                    ))
+                    (!*has_been_started).then_some(note_time + *duration)
                 } else {
                     // The balloon is currently active so draw it on the receptacle
-                    Some(NOTE_HIT_X)
+                    Some(current_time)
                 }
             }
-        }
+        }?;
+
+        Some([
+            x_position_of_note(layout, current_time, drawn_time, scroll_speed),
+            y_position_of_note(layout, current_time, drawn_time, vertical_speed),
+        ])
     }
 
     fn set_position_for_time(
         &mut self,
+        layout: &NoteLayout,
         current_time: f32,
         note_time: f32,
-        scroll_speed: f32,
+        scroll_speed: [f32; 2],
         renderer: &Renderer,
     ) {
-        let Some(x_position) = self.x_position_for_time(current_time, note_time, scroll_speed)
+        let Some(position) = self.position_for_time(layout, current_time, note_time, scroll_speed)
         else {
             return;
         };
 
-        self.set_x_position(x_position, note_time, renderer);
+        self.set_position(position, note_time, renderer);
     }
 
-    /// Whether this note is a don/kat note that awards judgement and must be hit.
-    fn is_don_or_kat(&self) -> bool {
-        matches!(self, NoteInner::Note { .. },)
+    /// Makes the whole note see-through, from 0 (invisible) to 1 (opaque).
+    fn set_opacity(&mut self, opacity: f32, renderer: &Renderer) {
+        let tint = [1., 1., 1., opacity];
+
+        match self {
+            NoteInner::Note {
+                tint: note_tint, ..
+            } => *note_tint = tint,
+            NoteInner::Balloon { sprite, .. } => sprite.set_tint(tint, renderer),
+            NoteInner::Roll {
+                start_sprite,
+                body_sprite,
+            } => {
+                start_sprite.set_tint(tint, renderer);
+                body_sprite.set_tint(tint, renderer);
+            }
+        }
     }
 }
 
@@ -363,11 +526,8 @@ impl Renderable for NoteInner {
         render_pass: &mut wgpu::RenderPass<'pass>,
     ) {
         match self {
-            NoteInner::Note { sprite, is_hit, .. } => {
-                if !is_hit {
-                    sprite.render(renderer, render_pass)
-                }
-            }
+            // Drawn in a batch with the other notes instead (see NoteInner::Note)
+            NoteInner::Note { .. } => {}
             NoteInner::Balloon {
                 sprite, started, ..
             } => {
@@ -389,220 +549,309 @@ impl Renderable for NoteInner {
     }
 }
 
-/// Different ways a note can respond to a keypress
-/// See [TaikoModeNote::receive_keypress]
-#[derive(Debug, Copy, Clone)]
-pub enum NoteKeypressReaction {
-    /// Don was pressed but this note is Kat, or vice versa
-    /// Basically, do absolutely nothing.
-    WrongColour,
-    /// The keypress is too early, so the note is not yet able to be hit.
-    ///
-    /// *This variant is more important than WrongColour*. If a keypress is both too early and the
-    /// wrong colour, this is the one you should return, since the calling code uses this variant
-    /// to determine where to stop calling [TaikoModeNote::receive_keypress]
-    TooEarly,
-    /// The note was hit, with the given time offset
-    ///
-    /// The offset is calculated as input_time - note_time. That is to say, it is *relative to the
-    /// note time*. For example, if you hit 15ms before you should have, the offset will be -0.015,
-    /// that is to say, 0.015 seconds *early*.
-    Hit { offset: f32 },
-    /// The note was hit, and is a drumroll.
-    /// Since drumrolls can be big or small, and can be hit with either don or kat, we return the
-    /// note type so that we can display the correct flying note.
-    Drumroll { roll_note: BasicNoteType },
-    /// The note was hit, and is a balloon.
-    BalloonRoll { hits_left: u32, hit_target: u32 },
-    /// The note cannot be hit anymore.
-    TooLate,
-}
-
 impl TaikoModeNote {
-    pub fn new(renderer: &Renderer, note: &Note, textures: &mut TextureCache) -> Option<Self> {
+    pub fn new(
+        renderer: &Renderer,
+        note: &Note,
+        textures: &mut TextureCache,
+        layout: &NoteLayout,
+        visibility: &VisibilitySettings,
+    ) -> Option<Self> {
         Some(Self {
-            note: NoteInner::new(renderer, note, textures)?,
+            note: NoteInner::new(renderer, note, layout, textures)?,
+            note_type: note.note_type,
             scroll_speed: note.scroll_speed,
-            time: note.time,
+            vertical_speed: note.vertical_speed,
+            time: note.time as f32,
+            layout: *layout,
+            visibility: visibility_interval(note, layout, visibility),
+            visibility_settings: *visibility,
         })
     }
 
-    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
-        self.note
-            .set_position_for_time(note_adjusted_time, self.time, self.scroll_speed, renderer)
-    }
-
-    /// Whether this note is a don/kat note that awards judgement and must be hit.
-    pub fn is_don_or_kat(&self) -> bool {
-        self.note.is_don_or_kat()
-    }
+    /// Rebuilds the note's sprites and shapes from scratch, keeping track of whether it has been
+    /// hit. Returns false if the note couldn't be rebuilt.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        renderer: &Renderer,
+        textures: &mut TextureCache,
+        visibility: &VisibilitySettings,
+    ) -> bool {
+        let note = Note {
+            note_type: self.note_type,
+            time: self.time as f64,
+            scroll_speed: self.scroll_speed,
+            vertical_speed: self.vertical_speed,
+        };
 
-    pub fn visible(&self, note_adjusted_time: f32) -> bool {
-        let Some(x_position) =
-            self.note
-                .x_position_for_time(note_adjusted_time, self.time, self.scroll_speed)
-        else {
-            // If there is no possible x position, we're not going to display it anyway.
+        let Some(mut new_note) = NoteInner::new(renderer, &note, &self.layout, textures) else {
             return false;
         };
-        let (rel_start, rel_end) = self.relative_bounding_box();
 
-        let start_x = rel_start[0] + x_position;
-        let end_x = rel_end[0] + x_position;
+        match (&mut new_note, &self.note) {
+            (NoteInner::Note { is_hit, .. }, NoteInner::Note { is_hit: old, .. }) => {
+                *is_hit = *old;
+            }
+
+            (
+                NoteInner::Balloon {
+                    hits_left, started, ..
+                },
+                NoteInner::Balloon {
+                    hits_left: old_hits_left,
+                    started: old_started,
+                    ..
+                },
+            ) => {
+                *hits_left = *old_hits_left;
+                *started = *old_started;
+            }
+
+            _ => {}
+        }
 
-        // TODO: seriously dont use hard coded resolution
-        start_x < 1920. && end_x >= LEFT_PANEL_WIDTH
+        self.note = new_note;
+        self.visibility = visibility_interval(&note, &self.layout, visibility);
+        self.visibility_settings = *visibility;
+        true
     }
 
-    /// Reacts to a keypress.
-    pub fn receive_keypress(
-        &mut self,
-        key: PhysicalKey,
-        time: f32,
-        timing_windows: &[f32; 3],
-    ) -> NoteKeypressReaction {
-        // Before this function was called, we should have checked that the keypress is actually
-        // don or kat.
-        if !settings().key_is_don_or_kat(key) {
-            panic!(
-                "Keycode was passed to TaikoModeNote::receive_keypress that was not don or kat."
-            );
+    pub fn note_type(&self) -> NoteType {
+        self.note_type
+    }
+
+    /// When the note finishes: the end of a drumroll or balloon, or just when it's hit otherwise.
+    pub fn end_time(&self) -> f32 {
+        match self.note_type {
+            NoteType::Roll(duration)
+            | NoteType::BigRoll(duration)
+            | NoteType::BalloonRoll(duration, _)
+            | NoteType::SpecialRoll(duration, _) => self.time + duration,
+            _ => self.time,
         }
+    }
 
-        if !self.is_hittable(time, timing_windows) {
-            return NoteKeypressReaction::TooLate;
+    /// Hides a don or kat note once it has been hit.
+    pub fn set_hit(&mut self) {
+        if let NoteInner::Note { is_hit, .. } = &mut self.note {
+            *is_hit = true;
         }
+    }
 
+    /// Puts the note back how it was before the song started: not hit, and with a balloon's hits
+    /// all still to go. Nothing is rebuilt, so this is cheap enough to do to every note at once.
+    pub fn reset(&mut self) {
         match &mut self.note {
-            NoteInner::Note { kind, is_hit, .. } => {
-                if self.time - timing_windows[BAD] > time {
-                    // If the earliest the note could ever be hit is later (greater than) the current
-                    // time, then we are too early.
-                    NoteKeypressReaction::TooEarly
-                } else if kind.is_hit_by(key) {
-                    // We know the note is not too late (hittable), we know the note is not
-                    // too early, so this means the note is hit! Return the timing difference.
-                    *is_hit = true;
-                    NoteKeypressReaction::Hit {
-                        offset: time - self.time,
-                    }
-                } else {
-                    NoteKeypressReaction::WrongColour
-                }
-            }
-
-            NoteInner::Roll { duration, big, .. } => {
-                let relative_time = time - self.time;
-                if relative_time < 0.0 {
-                    // This is before the drumroll
-                    NoteKeypressReaction::TooEarly
-                } else if relative_time >= *duration {
-                    // This is after
-                    NoteKeypressReaction::TooLate
-                } else {
-                    // This is just right
-                    let settings = SETTINGS.read().unwrap();
-                    let colour = if settings.key_is_don(key) {
-                        NoteColour::Don
-                    } else {
-                        // We already checked that the key was either don or kat.
-                        NoteColour::Kat
-                    };
-
-                    let roll_note = BasicNoteType { colour, big: *big };
-
-                    NoteKeypressReaction::Drumroll { roll_note }
-                }
-            }
-
+            NoteInner::Note { is_hit, .. } => *is_hit = false,
             NoteInner::Balloon {
-                duration,
-                started: has_been_started,
-                hits_left,
-                hit_target,
-                ..
+                hits_left, started, ..
             } => {
-                if self.time > time {
-                    NoteKeypressReaction::TooEarly
-                } else if self.time + *duration < time || *hits_left == 0 {
-                    NoteKeypressReaction::TooLate
-                } else if settings().key_is_don(key) {
-                    *hits_left -= 1;
-                    *has_been_started = true;
-                    NoteKeypressReaction::BalloonRoll {
-                        hits_left: *hits_left,
-                        hit_target: *hit_target,
-                    }
-                } else {
-                    NoteKeypressReaction::WrongColour
+                if let NoteType::BalloonRoll(_, hit_target) = self.note_type {
+                    *hits_left = hit_target;
                 }
+                *started = false;
             }
+            NoteInner::Roll { .. } => {}
         }
     }
 
-    /// Whether the note is (or will at some point be) hittable.
-    ///
-    /// When checking if a note has been hit by the player, we start checking from the first
-    /// hittable note. If the note can be hit now or at some point in the future, it is considered
-    /// "hittable". If it is past its time, however, it is not hittable.
-    pub fn is_hittable(&self, time: f32, timing_windows: &[f32; 3]) -> bool {
-        match self.note {
-            NoteInner::Note { is_hit, .. } => {
-                // If the note is hit, obviously it won't be hittable again.
-                // If the latest the note could ever be hit is later than the current time, then
-                // there's still a chance it's hittable.
-                !is_hit && self.time + timing_windows[BAD] > time
-            }
-            NoteInner::Roll { duration, .. } => self.time + duration > time,
-            NoteInner::Balloon {
-                duration,
-                hits_left,
-                ..
-            } => hits_left > 0 && self.time + duration > time,
+    /// Updates how many more times a balloon has to be hit before it pops.
+    pub fn set_balloon_hits_left(&mut self, hits: u32) {
+        if let NoteInner::Balloon {
+            hits_left, started, ..
+        } = &mut self.note
+        {
+            *hits_left = hits;
+            *started = true;
+        }
+    }
+
+    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
+        self.note.set_position_for_time(
+            &self.layout,
+            note_adjusted_time,
+            self.time,
+            [self.scroll_speed, self.vertical_speed],
+            renderer,
+        );
+
+        // Only HIDDEN fades notes, so there's no need to touch the tint otherwise
+        if self.visibility_settings.hidden.is_some() {
+            let x = x_position_of_note(
+                &self.layout,
+                note_adjusted_time,
+                self.time,
+                self.scroll_speed,
+            );
+            let y = y_position_of_note(
+                &self.layout,
+                note_adjusted_time,
+                self.time,
+                self.vertical_speed,
+            );
+            let opacity = self
+                .visibility_settings
+                .opacity((x - self.layout.hit_x).hypot(y - self.layout.y));
+            self.note.set_opacity(opacity, renderer);
         }
     }
 
-    fn relative_bounding_box(&self) -> ([f32; 2], [f32; 2]) {
+    /// When the note appears on screen and leaves it again (see [visibility_interval]).
+    pub fn visibility(&self) -> (f32, f32) {
+        self.visibility
+    }
+
+    /// Whether the note is on screen at the given time. Notes that have been hit (or popped) are
+    /// never on screen.
+    pub fn visible(&self, note_adjusted_time: f32) -> bool {
+        let (appear, vanish) = self.visibility;
+
+        (appear..vanish).contains(&note_adjusted_time)
+            && self
+                .note
+                .position_for_time(
+                    &self.layout,
+                    note_adjusted_time,
+                    self.time,
+                    [self.scroll_speed, self.vertical_speed],
+                )
+                .is_some()
+    }
+
+    /// For a don or kat that hasn't been hit, the texture it's drawn with, along with its position
+    /// (the centre of the note, and its depth) and tint, so that it can be drawn in a batch with
+    /// the other notes. Other notes are drawn on their own, so this is None for them.
+    pub fn batched_sprite(&self) -> Option<(&Rc<Texture>, [f32; 3], [f32; 4])> {
         match &self.note {
-            NoteInner::Note { sprite, .. } => sprite.relative_bounding_box(),
-            NoteInner::Balloon { sprite, .. } => sprite.relative_bounding_box(),
-            NoteInner::Roll {
-                start_sprite,
-                duration: length_of_time,
-                ..
-            } => {
-                let (head_start, head_fin) = start_sprite.relative_bounding_box();
+            NoteInner::Note {
+                texture,
+                position,
+                tint,
+                is_hit: false,
+            } => Some((texture, *position, *tint)),
+            _ => None,
+        }
+    }
+}
 
-                let start = head_start;
-                let end = [
-                    head_fin[0] + drumroll_visual_length(self.scroll_speed, *length_of_time),
-                    head_fin[1],
-                ];
+impl GhostNote {
+    /// Creates a ghost note, or returns None if the note isn't shown as a ghost (see [is_ghost]).
+    pub fn new(
+        renderer: &Renderer,
+        note: &Note,
+        textures: &mut TextureCache,
+        layout: &NoteLayout,
+        visibility: &VisibilitySettings,
+    ) -> Option<Self> {
+        if !is_ghost(note.note_type) {
+            return None;
+        }
 
-                (start, end)
-            }
+        let texture = textures
+            .get(
+                &renderer.device,
+                &renderer.queue,
+                note_textures(note.note_type)[0],
+            )
+            .ok()?;
+        let sprite = SpriteBuilder::new(texture)
+            .centre()
+            .scale(GHOST_SCALE)
+            .build(renderer);
+        sprite.set_tint([1., 1., 1., GHOST_ALPHA], renderer);
+
+        Some(Self {
+            sprite,
+            note_type: note.note_type,
+            time: note.time as f32,
+            scroll_speed: note.scroll_speed,
+            vertical_speed: note.vertical_speed,
+            layout: *layout,
+            visibility: visibility_interval(note, layout, visibility),
+        })
+    }
+
+    /// The note this ghost was created from, with its scroll speed already multiplied by the note
+    /// speed, so that it can be recreated if need be.
+    pub fn note(&self) -> Note {
+        Note {
+            note_type: self.note_type,
+            time: self.time as f64,
+            scroll_speed: self.scroll_speed,
+            vertical_speed: self.vertical_speed,
         }
     }
+
+    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
+        let x = x_position_of_note(
+            &self.layout,
+            note_adjusted_time,
+            self.time,
+            self.scroll_speed,
+        );
+        let y = y_position_of_note(
+            &self.layout,
+            note_adjusted_time,
+            self.time,
+            self.vertical_speed,
+        );
+        self.sprite.set_position([x, y], renderer);
+    }
+
+    /// When the ghost appears on screen and leaves it again (see [visibility_interval]).
+    pub fn visibility(&self) -> (f32, f32) {
+        self.visibility
+    }
+
+    /// Whether the ghost is on screen at the given time.
+    pub fn visible(&self, note_adjusted_time: f32) -> bool {
+        let (appear, vanish) = self.visibility;
+        (appear..vanish).contains(&note_adjusted_time)
+    }
 }
 
 impl TaikoModeBarline {
     pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
         self.visual_line.set_position(
             [
-                x_position_of_note(note_adjusted_time, self.time, self.scroll_speed),
-                NOTE_FIELD_Y,
+                x_position_of_note(
+                    &self.layout,
+                    note_adjusted_time,
+                    self.time,
+                    self.scroll_speed,
+                ),
+                y_position_of_note(
+                    &self.layout,
+                    note_adjusted_time,
+                    self.time,
+                    self.vertical_speed,
+                ) - NOTE_FIELD_HEIGHT / 2.0,
                 0.0,
             ],
             renderer,
         );
     }
 
-    pub fn time(&self) -> f32 {
-        self.time
+    /// The barline this was created from, with its scroll speeds already multiplied by the note
+    /// speed, so that it can be recreated if need be.
+    pub fn barline(&self) -> Barline {
+        Barline {
+            time: self.time,
+            scroll_speed: self.scroll_speed,
+            vertical_speed: self.vertical_speed,
+        }
+    }
+
+    /// When the barline appears on screen and leaves it again (see
+    /// [barline_visibility_interval]).
+    pub fn visibility(&self) -> (f32, f32) {
+        self.visibility
     }
 
-    pub fn scroll_speed(&self) -> f32 {
-        self.scroll_speed
+    /// Whether the barline is on screen at the given time.
+    pub fn visible(&self, note_adjusted_time: f32) -> bool {
+        let (appear, vanish) = self.visibility;
+        (appear..vanish).contains(&note_adjusted_time)
     }
 }
 
@@ -616,6 +865,16 @@ impl Renderable for TaikoModeNote {
     }
 }
 
+impl Renderable for GhostNote {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.sprite.render(renderer, render_pass);
+    }
+}
+
 impl Renderable for TaikoModeBarline {
     fn render<'pass>(
         &'pass self,
@@ -625,3 +884,219 @@ impl Renderable for TaikoModeBarline {
         self.visual_line.render(renderer, render_pass);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::SPRITES_PATH;
+    use crate::render::VIRTUAL_HEIGHT;
+
+    const ALL_NOTE_TYPES: [NoteType; 10] = [
+        NoteType::Don,
+        NoteType::Kat,
+        NoteType::BigDon,
+        NoteType::BigKat,
+        NoteType::Roll(1.0),
+        NoteType::BigRoll(1.0),
+        NoteType::BalloonRoll(1.0, 5),
+        NoteType::SpecialRoll(1.0, 5),
+        NoteType::CoopDon,
+        NoteType::CoopKat,
+    ];
+
+    /// Doesn't do anything, but will stop compiling if a new note type is added without updating
+    /// [ALL_NOTE_TYPES].
+    #[allow(dead_code)]
+    fn all_note_types_is_exhaustive(note_type: NoteType) {
+        match note_type {
+            NoteType::Don
+            | NoteType::Kat
+            | NoteType::BigDon
+            | NoteType::BigKat
+            | NoteType::Roll(_)
+            | NoteType::BigRoll(_)
+            | NoteType::BalloonRoll(..)
+            | NoteType::SpecialRoll(..)
+            | NoteType::CoopDon
+            | NoteType::CoopKat => {}
+        }
+    }
+
+    #[test]
+    fn test_note_textures_cover_every_note_type() {
+        for note_type in ALL_NOTE_TYPES {
+            let textures = note_textures(note_type);
+
+            // Special rolls are the only note type that isn't drawn yet
+            if !matches!(note_type, NoteType::SpecialRoll(..)) {
+                assert!(!textures.is_empty(), "{note_type:?} has no textures");
+            }
+
+            for texture in textures {
+                assert!(
+                    std::path::Path::new(SPRITES_PATH).join(texture).is_file(),
+                    "texture {texture:?} for {note_type:?} doesn't exist"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_chart_textures() {
+        let notes = [NoteType::Don, NoteType::Don, NoteType::BalloonRoll(1.0, 5)]
+            .into_iter()
+            .map(|note_type| Note {
+                note_type,
+                time: 0.0,
+                scroll_speed: 1.0,
+                vertical_speed: 0.0,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            chart_textures(&notes),
+            HashSet::from(["don.png", "balloon 1.png"])
+        );
+    }
+
+    #[test]
+    fn test_ghost_notes() {
+        // Only notes that are hit once become ghosts
+        let ghosts = ALL_NOTE_TYPES
+            .into_iter()
+            .filter(|note_type| is_ghost(*note_type))
+            .count();
+        assert_eq!(ghosts, 6);
+        assert!(!is_ghost(NoteType::Roll(1.0)));
+        assert!(!is_ghost(NoteType::BalloonRoll(1.0, 5)));
+
+        for mirrored in [false, true] {
+            let normal = note_layout(mirrored);
+            let ghost = ghost_layout(mirrored);
+
+            // Ghosts are in a lane above the notes, but move in time with them
+            assert!(ghost.y < normal.y);
+            assert!(ghost.y - ghost.big_note_radius >= NOTE_FIELD_Y);
+            assert_eq!(
+                x_position_of_note(&ghost, 9.0, 10.0, 1.0),
+                x_position_of_note(&normal, 9.0, 10.0, 1.0)
+            );
+
+            // Being smaller, they come on screen a little later and leave a little sooner
+            let note = Note {
+                note_type: NoteType::Don,
+                time: 20.0,
+                scroll_speed: 1.0,
+                vertical_speed: 0.0,
+            };
+            let settings = VisibilitySettings::default();
+            let (appear, vanish) = visibility_interval(&note, &normal, &settings);
+            let (ghost_appear, ghost_vanish) = visibility_interval(&note, &ghost, &settings);
+            assert!(appear < ghost_appear && ghost_vanish < vanish);
+        }
+    }
+
+    #[test]
+    fn test_mirrored_note_positions() {
+        let normal = note_layout(false);
+        let mirrored = note_layout(true);
+
+        for (current_time, scroll_speed) in [(8.0, 1.0), (10.0, 1.0), (11.5, 2.0), (9.0, -0.5)] {
+            let x = x_position_of_note(&normal, current_time, 10.0, scroll_speed);
+            let mirrored_x = x_position_of_note(&mirrored, current_time, 10.0, scroll_speed);
+
+            // The mirrored note is where the normal one would be, flipped across the screen
+            assert!(
+                (mirrored_x - (VIRTUAL_WIDTH - x)).abs() < 1e-3,
+                "{x} {mirrored_x}"
+            );
+        }
+
+        // Both are on the receptacle when they should be hit
+        assert_eq!(
+            x_position_of_note(&mirrored, 10.0, 10.0, 1.0),
+            mirrored.hit_x
+        );
+        assert_eq!(mirrored.hit_x, VIRTUAL_WIDTH - NOTE_HIT_X);
+
+        // Notes come from the left, and carry on to the right after they've gone by
+        assert!(x_position_of_note(&mirrored, 9.0, 10.0, 1.0) < mirrored.hit_x);
+        assert!(x_position_of_note(&mirrored, 11.0, 10.0, 1.0) > mirrored.hit_x);
+        // ...unless the chart makes them go backwards
+        assert!(x_position_of_note(&mirrored, 9.0, 10.0, -1.0) > mirrored.hit_x);
+    }
+
+    #[test]
+    fn test_mirrored_visibility() {
+        let normal = note_layout(false);
+        let mirrored = note_layout(true);
+        let settings = VisibilitySettings::default();
+
+        // The mirrored field is the same shape as the normal one, so things are on screen for
+        // exactly the same times. Roll bodies trail the other way, so they do too.
+        for note_type in [
+            NoteType::Don,
+            NoteType::BigKat,
+            NoteType::Roll(2.0),
+            NoteType::BigRoll(0.05),
+        ] {
+            for scroll_speed in [1.0, 2.5, -1.0, 0.0] {
+                let note = Note {
+                    note_type,
+                    time: 20.0,
+                    scroll_speed,
+                    vertical_speed: 0.0,
+                };
+
+                assert_eq!(
+                    visibility_interval(&note, &normal, &settings),
+                    visibility_interval(&note, &mirrored, &settings),
+                    "{note_type:?} at {scroll_speed}"
+                );
+            }
+        }
+
+        // The note enters at the left edge of the screen, and leaves the field at the panel
+        let note = Note {
+            note_type: NoteType::Don,
+            time: 20.0,
+            scroll_speed: 1.0,
+            vertical_speed: 0.0,
+        };
+        let (appear, vanish) = visibility_interval(&note, &mirrored, &settings);
+        let right_edge_at = |time| x_position_of_note(&mirrored, time, 20.0, 1.0) + 50.;
+        let left_edge_at = |time| x_position_of_note(&mirrored, time, 20.0, 1.0) - 50.;
+        assert!((right_edge_at(appear) - mirrored.field_start).abs() < 1e-2);
+        assert!((left_edge_at(vanish) - mirrored.field_end).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_vertical_notes() {
+        let layout = note_layout(false);
+        let settings = VisibilitySettings::default();
+
+        // Positive speeds come from above, and carry on downwards
+        assert_eq!(y_position_of_note(&layout, 10.0, 10.0, 2.0), layout.y);
+        assert!(y_position_of_note(&layout, 9.0, 10.0, 2.0) < layout.y);
+        assert!(y_position_of_note(&layout, 11.0, 10.0, 2.0) > layout.y);
+        assert!(y_position_of_note(&layout, 9.0, 10.0, -2.0) > layout.y);
+
+        let note = |vertical_speed| Note {
+            note_type: NoteType::Don,
+            time: 20.0,
+            scroll_speed: 0.0,
+            vertical_speed,
+        };
+
+        // A note that only moves vertically comes in at the top of the screen
+        let (appear, vanish) = visibility_interval(&note(1.0), &layout, &settings);
+        let bottom_edge_at = |time| y_position_of_note(&layout, time, 20.0, 1.0) + 50.;
+        let top_edge_at = |time| y_position_of_note(&layout, time, 20.0, 1.0) - 50.;
+        assert!(bottom_edge_at(appear).abs() < 1e-2);
+        assert!((top_edge_at(vanish) - VIRTUAL_HEIGHT).abs() < 1e-2);
+
+        // Moving faster, it's on screen for less time
+        let (fast_appear, fast_vanish) = visibility_interval(&note(4.0), &layout, &settings);
+        assert!(fast_appear > appear && fast_vanish < vanish);
+    }
+}
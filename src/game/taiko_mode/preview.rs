@@ -0,0 +1,206 @@
+//! A preview of the note field, for showing what gameplay will look like without playing a song.
+use crate::game::{RenderContext, TextureCache};
+use crate::notechart_parser::{Barline, Note, NoteType};
+use crate::render::Renderer;
+use crate::settings::{settings, settings_revision};
+
+use super::gameplay::VisibilitySettings;
+use super::note::{
+    chart_textures, create_barlines, create_notes, note_layout, TaikoModeBarline, TaikoModeNote,
+};
+use super::ui::NoteField;
+
+/// The notes of the preview, one string per measure, written like a TJA file: 1 and 2 are don
+/// and kat, 3 and 4 are big don and big kat, and 5 starts a drumroll that 8 ends.
+const PREVIEW_PATTERN: [&str; 4] = [
+    "1020102010201120",
+    "3000400010201020",
+    "5000000080001020",
+    "1011201020103000",
+];
+
+/// How long a measure of the preview lasts, in seconds (4/4 at 120 BPM).
+const MEASURE_LENGTH: f32 = 2.0;
+
+/// How long it takes for the preview to loop, in seconds.
+const LOOP_LENGTH: f32 = MEASURE_LENGTH * PREVIEW_PATTERN.len() as f32;
+
+/// Builds the notes and barlines of the preview pattern, written out twice so that the second
+/// copy can scroll onto the screen while the first one is still leaving it.
+fn preview_chart() -> (Vec<Note>, Vec<Barline>) {
+    let mut notes = Vec::new();
+    let mut barlines = Vec::new();
+    let mut roll_start = None;
+
+    for (i, measure) in PREVIEW_PATTERN
+        .iter()
+        .cycle()
+        .take(PREVIEW_PATTERN.len() * 2)
+        .enumerate()
+    {
+        let measure_start = i as f32 * MEASURE_LENGTH;
+        let division = MEASURE_LENGTH / measure.len() as f32;
+
+        barlines.push(Barline {
+            time: measure_start,
+            scroll_speed: 1.0,
+            vertical_speed: 0.0,
+        });
+
+        for (j, c) in measure.chars().enumerate() {
+            let time = measure_start + j as f32 * division;
+
+            let note_type = match c {
+                '1' => NoteType::Don,
+                '2' => NoteType::Kat,
+                '3' => NoteType::BigDon,
+                '4' => NoteType::BigKat,
+                '5' => {
+                    roll_start = Some(time);
+                    continue;
+                }
+                '8' => match roll_start.take() {
+                    Some(start) => {
+                        notes.push(Note {
+                            note_type: NoteType::Roll(time - start),
+                            time: start as f64,
+                            scroll_speed: 1.0,
+                            vertical_speed: 0.0,
+                        });
+                        continue;
+                    }
+                    None => continue,
+                },
+                _ => continue,
+            };
+
+            notes.push(Note {
+                note_type,
+                time: time as f64,
+                scroll_speed: 1.0,
+                vertical_speed: 0.0,
+            });
+        }
+    }
+
+    (notes, barlines)
+}
+
+/// A note field with a short pattern of notes scrolling across it forever, drawn with the current
+/// visual settings. It's purely for show: nothing can be hit, and no audio is played.
+///
+/// The preview is drawn in the same place and at the same size as the note field in taiko mode,
+/// since there's no way to scale things down yet.
+pub struct NotePreview {
+    note_field: NoteField,
+    notes: Vec<TaikoModeNote>,
+    barlines: Vec<TaikoModeBarline>,
+    /// The settings revision the notes were built for
+    revision: u64,
+    /// How far through the loop the preview is, in seconds
+    time: f32,
+}
+
+impl NotePreview {
+    pub fn new(renderer: &mut Renderer, textures: &mut TextureCache) -> anyhow::Result<Self> {
+        let mut preview = Self {
+            note_field: NoteField::new(renderer, settings().visual.mirror_playfield)?,
+            notes: Vec::new(),
+            barlines: Vec::new(),
+            revision: settings_revision(),
+            time: 0.0,
+        };
+
+        preview.build_notes(renderer, textures)?;
+        Ok(preview)
+    }
+
+    fn build_notes(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        let (notes, barlines) = preview_chart();
+        let settings = settings();
+        let visual = &settings.visual;
+
+        textures.preload(&renderer.device, &renderer.queue, chart_textures(&notes))?;
+
+        let visibility = VisibilitySettings::default();
+        let layout = note_layout(visual.mirror_playfield);
+
+        self.note_field = NoteField::new(renderer, visual.mirror_playfield)?;
+        self.notes = create_notes(
+            renderer,
+            textures,
+            &notes,
+            visual.note_speed,
+            &layout,
+            &visibility,
+        );
+        self.barlines =
+            create_barlines(renderer, &barlines, visual.note_speed, &layout, &visibility);
+        Ok(())
+    }
+
+    /// Moves the notes along. If the settings have changed since the notes were built, they (and
+    /// the note field, in case it's been mirrored) are built again so that the changes show up
+    /// straight away.
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        let revision = settings_revision();
+        if revision != self.revision {
+            self.revision = revision;
+            self.build_notes(renderer, textures)?;
+        }
+
+        self.time = (self.time + delta_time) % LOOP_LENGTH;
+        Ok(())
+    }
+
+    pub fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+        // The first copy of the pattern plays from 0 to LOOP_LENGTH, and by the time it loops
+        // around, the second copy is exactly where the first one started.
+        let time = self.time;
+
+        for note in self.notes.iter_mut().filter(|note| note.visible(time)) {
+            note.update_position(ctx.renderer, time);
+        }
+
+        for barline in self.barlines.iter_mut() {
+            barline.update_position(ctx.renderer, time);
+        }
+
+        let notes = self.notes.iter().filter(|note| note.visible(time));
+        self.note_field
+            .render(ctx, notes, self.barlines.iter(), std::iter::empty());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preview_chart_loops() {
+        let (notes, barlines) = preview_chart();
+
+        assert_eq!(barlines.len(), PREVIEW_PATTERN.len() * 2);
+        assert_eq!(notes.len() % 2, 0);
+
+        // The second half of the chart is the first half shifted by exactly one loop
+        let (first, second) = notes.split_at(notes.len() / 2);
+        for (a, b) in first.iter().zip(second) {
+            assert_eq!(a.note_type, b.note_type);
+            assert!((b.time - a.time - LOOP_LENGTH as f64).abs() < 1e-4);
+        }
+
+        assert!(notes
+            .iter()
+            .any(|note| matches!(note.note_type, NoteType::Roll(_))));
+    }
+}
@@ -1,399 +1,2240 @@
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use kira::manager::AudioManager;
+use egui::RichText;
 use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle};
-use kira::sound::PlaybackState;
-use kira::tween::Tween;
+use kira::sound::{PlaybackRate, PlaybackState};
+use kira::tween::{Tween, Value};
 use winit::event::{ElementState, WindowEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
+use super::clock::SkipDetector;
+use super::dancers::Dancers;
+use super::effects::Effects;
+use super::gameplay::{
+    BasicNoteType, GameplayCore, GameplayEvent, NoteColour, PlayState, VisibilitySettings,
+    VisibilityWindow,
+};
+use super::mods::Mods;
 use super::note::{
-    create_barlines, create_notes, NoteInner, NoteKeypressReaction, TaikoModeBarline,
-    TaikoModeNote, BAD, EASY_NORMAL_TIMING, GOOD, HARD_EXTREME_TIMING, OK,
+    chart_textures, create_barlines, create_ghost_notes, create_notes, ghost_layout, note_layout,
+    GhostNote, TaikoModeBarline, TaikoModeNote,
+};
+use super::simulate::{autoplay_inputs, verify, TimedInput};
+use super::ui::{
+    BalloonDisplay, ComboDisplay, DensityStrip, DrumrollDisplay, Header, HealthBar, InputDisplay,
+    JudgementText, NoteField, ScoreDisplay, StreamReadout, TimingMeter,
 };
-use super::ui::{BalloonDisplay, Header, JudgementText, NoteField};
+use crate::audio::AudioManager;
+use crate::difficulty::DifficultyInfo;
+use crate::game::chart_settings::{
+    ChartDisplay, ChartOverrides, ChartSettings, CHART_SETTINGS_PATH,
+};
+use crate::game::presence::Presence;
+use crate::game::replay::{Replay, REPLAYS_DIR};
 use crate::game::score_screen::ScoreScreen;
-use crate::game::taiko_mode::note::x_position_of_note;
-use crate::game::{Context, GameState, RenderContext, StateTransition, TextureCache};
+use crate::game::scores::{
+    record_play, BestRecord, Crown, JudgementCounts, PlayRecord, ScoreDatabase, SCORES_PATH,
+};
+use crate::game::ui_elements::{offset_profile_combo_box, percentage_slider};
+use crate::game::{
+    Context, GameState, KeyboardState, RenderContext, SoundEffect, StateTransition, TextureCache,
+    TransitionStyle,
+};
 use crate::render::texture::SpriteBuilder;
-use crate::settings::{settings, SETTINGS};
+use crate::render::waveform::Peaks;
+use crate::rng::Rng;
+use crate::settings::{
+    key_name, save_settings, settings, settings_mut, DrumInput, FailBehaviour, HudSettings,
+    JudgementStyle, KeyMap, RulesConfig, RulesPreset, SettingsSnapshot, VisualSettings,
+};
 use crate::{
-    notechart_parser::Song,
+    notechart_parser::{
+        chart_hash, Barline, Branch, ChartHash, Note, NoteChart, Song, TJAParseWarning,
+    },
     render::{
         shapes::{Shape, ShapeBuilder, SolidColour},
         texture::Sprite,
-        Renderer,
+        Renderer, VIRTUAL_HEIGHT, VIRTUAL_WIDTH,
     },
 };
 
-pub type ScoreInt = u64;
+pub struct TaikoMode {
+    /// The song being played, kept so that the play can be watched again (see [ReplaySetup]).
+    song: Song,
+    /// The song's audio, kept for the same reason.
+    song_data: StaticSoundData,
+    song_name: String,
+    /// The star level of the difficulty being played
+    star_level: u8,
+    // UI Stuff
+    background: Sprite,
+    // TODO: Give sprites a colour tint
+    background_dim: Shape,
+    /// Darkens the screen while the song is paused
+    pause_dim: Shape,
+    /// The dancers below the note field, if the skin has any
+    dancers: Option<Dancers>,
+    header: Header,
+    note_field: NoteField,
+    balloon_display: BalloonDisplay,
+    drumroll_display: DrumrollDisplay,
+    /// The explosions at the receptacle when notes are hit, and other short-lived effects
+    effects: Effects,
+    health_bar: HealthBar,
+    score_display: ScoreDisplay,
+    combo_display: ComboDisplay,
+
+    /// A handle to the audio of the song
+    song_handle: StaticSoundHandle,
+    /// How many seconds of the chart each second of the song's audio lasts for. Stretched audio is
+    /// already longer or shorter than the song, but audio with a different playback rate isn't.
+    audio_scale: f64,
+    /// How long the song is, in seconds of the chart
+    song_length: f32,
+    /// When the chart is over (see [GameplayCore::end_time]), if it has anything to play.
+    chart_end: Option<f64>,
+    /// The chart being played, kept so the notes can be reset when jumping around the song
+    chart: NoteChart,
+    /// Shows where the hard parts of the chart are when practising, and can be clicked on to jump
+    /// to them
+    density_strip: Option<DensityStrip>,
+    /// Whether the song is being practised (see [TaikoMode::with_practice]).
+    practice: bool,
+    /// The part of the song being looped over when practising, in the same time as
+    /// [TaikoMode::song_time]. It's only looped once both ends have been set.
+    loop_start: Option<f32>,
+    loop_end: Option<f32>,
+    // Record the global offset, so we don't need to keep querying the settings. The offset profile
+    // can be switched from the pause menu, so this is read again whenever the settings change.
+    global_offset: f32,
+    /// The offset profile shown in the header
+    offset_profile: Option<String>,
+
+    /// The instant the song started.
+    ///
+    /// Even though the song handle keeps track of the position through the song, that value is
+    /// choppy and using it for the position of the notes will cause the notes to stutter. So we
+    /// need to keep track of the time ourselves.
+    start_time: Instant,
+    started: bool,
+    difficulty: usize,
+    /// How fast the song is being played, e.g. 0.75 for three quarters of the normal speed. Every
+    /// time in the chart, including the timing windows, is in the time of the notes (see
+    /// [TaikoMode::note_time]), so they all speed up and slow down with it.
+    rate: f32,
+    /// Where every random-looking effect in the scene gets its numbers from. It's seeded from the
+    /// chart, so the effects play out the same way whenever the same play is shown.
+    rng: Rng,
+
+    /// The moment the song was paused, if it is paused (or counting down to resume).
+    paused_at: Option<Instant>,
+    /// The moment the countdown to resume the song started.
+    countdown_start: Instant,
+    /// Which option of the pause menu is highlighted, as an index into [pause_options].
+    pause_selection: usize,
+    /// An option of the pause menu that was clicked on with the mouse.
+    clicked_pause_option: Option<PauseOption>,
+    /// Whether the settings were changed from the pause menu, and need to be saved.
+    settings_changed: bool,
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum NoteJudgement {
-    Bad,
-    Ok,
-    Good,
+    /// The hash of the chart being played, which its display overrides are stored under.
+    chart_hash: ChartHash,
+    /// The display settings this chart overrides, which can be changed from the pause menu.
+    chart_overrides: ChartOverrides,
+    /// Whether the chart's overrides were changed (from the pause menu, or by moving its offset),
+    /// and need to be saved.
+    chart_overrides_changed: bool,
+    /// The moment the chart's offset was last moved, so that the player can be shown what it is.
+    chart_offset_changed_at: Option<Instant>,
+    /// The background dim and HUD opacity the scene is currently drawn with.
+    display: ChartDisplay,
+
+    /// The scene's copy of the settings. This is refreshed once a frame, so that handling a
+    /// keypress doesn't have to go through the settings lock.
+    settings: SettingsSnapshot,
+    /// The settings the HUD is currently drawn with. These are worked out again whenever the
+    /// settings change, so changes made in the pause menu show up straight away.
+    hud: HudSettings,
+
+    notes: Vec<TaikoModeNote>,
+    barlines: Vec<TaikoModeBarline>,
+    /// The branched section and branch each barline is in, if any, so that only the barlines of
+    /// the branch being played are shown.
+    barline_branches: Vec<Option<(usize, Branch)>>,
+    /// Notes from a harder difficulty of the song, shown faintly above the chart being played (see
+    /// [TaikoMode::with_ghost_notes]).
+    ghost_notes: Vec<GhostNote>,
+    /// Which notes, barlines and ghost notes could be on screen at any time, so that only those
+    /// are looked at while drawing. These have to be updated whenever any of them are made again.
+    note_window: VisibilityWindow,
+    barline_window: VisibilityWindow,
+    ghost_window: VisibilityWindow,
+    /// The difficulty the ghost notes are from, if they're being shown.
+    ghost_difficulty: Option<usize>,
+    /// The settings the notes' and barlines' times on screen were worked out with
+    visibility: VisibilitySettings,
+    /// Whether the playfield is flipped, with notes travelling from left to right (see
+    /// [note_layout]). This is only read from the settings when the song starts, as changing it
+    /// partway through would move everything around.
+    mirrored: bool,
+    /// The rules drumrolls and balloons are scored by, which are also only read when the song
+    /// starts so that the whole play is scored the same way.
+    rules: RulesConfig,
+    /// The preset the rules came from, for showing on the results screen.
+    rules_preset: RulesPreset,
+
+    // Note scoring/input handling
+    /// Judges inputs and keeps track of the player's performance. At the end of the song, the
+    /// play result will be passed to the score screen.
+    core: GameplayCore,
+    note_judgement_text: JudgementText,
+    timing_meter: TimingMeter,
+
+    // Streamer mode
+    input_display: InputDisplay,
+    stream_readout: StreamReadout,
+
+    /// Problems with the chart that were worked around to load it, shown at the start of the song.
+    chart_warnings: Vec<TJAParseWarning>,
+
+    /// The start of the measure the player bookmarked, in the time of the notes. The song can be
+    /// retried from here from the pause menu.
+    bookmark: Option<f32>,
+    /// The moment the bookmark was last set, so that the player can be shown it was.
+    bookmarked_at: Option<Instant>,
+    /// Whether the song was restarted from the bookmark. A play that skipped part of the song
+    /// doesn't count, so it's labelled as practice in the results.
+    retried_from_bookmark: bool,
+    /// How long [RETRY_KEY] has been held for, if it's being held to restart the song.
+    retry_held: Option<f32>,
+
+    /// Notices when the audio skips, so that the note clock can be put back in line with it.
+    skip_detector: SkipDetector,
+    /// The moment the note clock was last resynced, so that the player can be shown it was.
+    resynced_at: Option<Instant>,
+
+    /// Every input that was judged, so that the result can be checked against
+    /// [simulate](super::simulate::simulate) at the end. This is None once the player has jumped
+    /// around the song, as the inputs can't be played back from the start any more.
+    inputs: Option<Vec<TimedInput>>,
+    /// Inputs that are played back instead of taken from the player, and how many of them have
+    /// been played. These are a replay's inputs when watching one, or autoplay's.
+    playback: Option<(Vec<TimedInput>, usize)>,
+    /// The replay being watched, if this is one.
+    replay: Option<Replay>,
+    /// Whether the game is playing the chart by itself (see [TaikoMode::with_autoplay]).
+    autoplay: bool,
+    /// Whether the stage is failed as soon as the gauge runs out (see
+    /// [FailBehaviour::SuddenDeath]). Like the rules, this is only read when the song starts.
+    sudden_death: bool,
+    /// The mods the chart is played with (see [TaikoMode::with_mods]).
+    mods: Mods,
 }
 
-impl NoteJudgement {
-    fn from_offset(offset: f32, timing_windows: &[f32; 3]) -> Option<Self> {
-        let abs_offset = offset.abs();
-        if abs_offset < timing_windows[GOOD] {
-            Some(Self::Good)
-        } else if abs_offset < timing_windows[OK] {
-            Some(Self::Ok)
-        } else if abs_offset < timing_windows[BAD] {
-            Some(Self::Bad)
-        } else {
-            None
-        }
+/// Everything needed to watch a play again straight after it, from the score screen.
+pub struct ReplaySetup {
+    song: Song,
+    song_data: StaticSoundData,
+    replay: Replay,
+}
+
+impl ReplaySetup {
+    /// Creates the scene that plays the replay.
+    pub fn start(&self, ctx: &mut Context) -> anyhow::Result<TaikoMode> {
+        Ok(TaikoMode::new(
+            &self.song,
+            self.song_data.clone(),
+            self.replay.difficulty,
+            self.replay.rate,
+            ctx,
+        )?
+        .with_mods(self.replay.mods, ctx.renderer, ctx.textures)
+        .with_replay(self.replay.clone()))
     }
 }
 
-impl NoteJudgement {
-    pub fn index(&self) -> usize {
+/// The options in the pause menu. Not all of them are always shown (see [pause_options]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PauseOption {
+    Resume,
+    Restart,
+    RetryFromBookmark,
+    Help,
+    Quit,
+}
+
+impl PauseOption {
+    fn label(self) -> &'static str {
         match self {
-            NoteJudgement::Bad => BAD,
-            NoteJudgement::Ok => OK,
-            NoteJudgement::Good => GOOD,
+            PauseOption::Resume => "Resume",
+            PauseOption::Restart => "Restart from beginning",
+            PauseOption::RetryFromBookmark => "Retry from bookmark",
+            PauseOption::Help => "Help",
+            PauseOption::Quit => "Quit",
         }
     }
 }
 
-/// A record containing statistics about how the player has done.
-///
-/// This struct will slowly collate data as the game progresses, and will be passed to the score
-/// screen at the end.
-///
-/// Contains more information than is usually collected in taiko games. I want this sim to be able
-/// to display a bunch of interesting gameplay statistics, and all that will be stored here.
-#[derive(Clone, Default, Debug)]
-pub struct PlayResult {
-    /// A vector containing the judgements for every note recorded.
-    /// A None value indicates a miss.
-    judgements: Vec<Option<NoteJudgement>>,
-    drumrolls: u64,
-    score: ScoreInt,
-    current_combo: usize,
-    max_combo: usize,
-    /// For all the notes that were hit (good, okay, or bad), records the difference between when
-    /// the note was hit and when the note should have been hit.
-    hit_errors: Vec<f32>,
-}
+/// The options in the pause menu, in the order they're shown. Retrying from the bookmark is only
+/// there once a bookmark has been set.
+fn pause_options(bookmarked: bool) -> Vec<PauseOption> {
+    let mut options = vec![PauseOption::Resume, PauseOption::Restart];
 
-impl PlayResult {
-    pub fn new() -> Self {
-        Self::default()
+    if bookmarked {
+        options.push(PauseOption::RetryFromBookmark);
     }
 
-    fn current_combo(&self) -> usize {
-        self.current_combo
-    }
+    options.extend([PauseOption::Help, PauseOption::Quit]);
+    options
+}
 
-    fn push_judgement(&mut self, judgement: Option<NoteJudgement>) {
-        self.judgements.push(judgement);
+/// Navigates a pause menu with the keyboard (the kat keys or arrow keys to move, the don keys or
+/// enter to choose), and returns the option that was chosen, if any. Escape resumes.
+pub(super) fn pause_menu_input(
+    keyboard: &mut KeyboardState,
+    key_mappings: &KeyMap,
+    options: &[PauseOption],
+    selection: &mut usize,
+) -> Option<PauseOption> {
+    let up = [key_mappings.left_kat, PhysicalKey::Code(KeyCode::ArrowUp)];
+    let down = [
+        key_mappings.right_kat,
+        PhysicalKey::Code(KeyCode::ArrowDown),
+    ];
+    let choose = [
+        key_mappings.left_don,
+        key_mappings.right_don,
+        PhysicalKey::Code(KeyCode::Enter),
+    ];
 
-        if matches!(
-            judgement,
-            Some(NoteJudgement::Good) | Some(NoteJudgement::Ok)
-        ) {
-            self.current_combo += 1;
-            self.max_combo = std::cmp::max(self.current_combo, self.max_combo);
-        } else {
-            self.current_combo = 0;
-        }
-    }
+    let mut just_pressed = |keys: &[PhysicalKey]| {
+        keys.iter().any(|&key| {
+            let pressed = keyboard.is_just_pressed(key);
+            keyboard.consume_key(key);
+            pressed
+        })
+    };
 
-    fn count_for_judgement(&self, judgement: Option<NoteJudgement>) -> usize {
-        self.judgements.iter().filter(|j| **j == judgement).count()
+    if just_pressed(&up) {
+        *selection = selection.saturating_sub(1);
     }
 
-    pub fn goods(&self) -> usize {
-        self.count_for_judgement(Some(NoteJudgement::Good))
+    if just_pressed(&down) {
+        *selection = (*selection + 1).min(options.len() - 1);
     }
 
-    pub fn okays(&self) -> usize {
-        self.count_for_judgement(Some(NoteJudgement::Ok))
+    if just_pressed(&choose) {
+        options.get(*selection).copied()
+    } else if just_pressed(&[PhysicalKey::Code(KeyCode::Escape)]) {
+        Some(PauseOption::Resume)
+    } else {
+        None
     }
+}
 
-    pub fn bads(&self) -> usize {
-        self.count_for_judgement(Some(NoteJudgement::Bad))
-    }
+/// Shows the options of a pause menu as buttons, with the selected one highlighted, and returns
+/// the one that was clicked on, if any.
+pub(super) fn pause_menu_buttons(
+    ui: &mut egui::Ui,
+    options: &[PauseOption],
+    selection: usize,
+) -> Option<PauseOption> {
+    let mut clicked = None;
 
-    pub fn misses(&self) -> usize {
-        self.count_for_judgement(None)
+    for (i, &option) in options.iter().enumerate() {
+        let mut text = RichText::new(option.label()).size(30.0);
+        if i == selection {
+            text = text.color(egui::Color32::YELLOW);
+        }
+
+        if ui.button(text).clicked() {
+            clicked = Some(option);
+        }
     }
 
-    pub fn drumrolls(&self) -> u64 {
-        self.drumrolls
+    clicked
+}
+
+/// The key that bookmarks the current measure.
+const BOOKMARK_KEY: KeyCode = KeyCode::F3;
+
+/// The keys that set the start and end of the loop when practising, and clear it.
+const LOOP_START_KEY: KeyCode = KeyCode::F4;
+const LOOP_END_KEY: KeyCode = KeyCode::F5;
+const CLEAR_LOOP_KEY: KeyCode = KeyCode::F6;
+
+/// The keys that zoom the practice waveform out and in.
+const WAVEFORM_ZOOM_OUT_KEY: KeyCode = KeyCode::F7;
+const WAVEFORM_ZOOM_IN_KEY: KeyCode = KeyCode::F8;
+
+/// The key that's held to start the song again from the top, and how long it has to be held for
+/// in seconds, so that it isn't pressed by accident.
+const RETRY_KEY: KeyCode = KeyCode::KeyR;
+const RETRY_HOLD_TIME: f32 = 1.0;
+
+/// How long the player is shown that they set a bookmark for, in seconds.
+const BOOKMARK_MESSAGE_DURATION: f32 = 2.0;
+
+/// The keys that move the chart's own offset (see [ChartOverrides::offset]) earlier and later,
+/// how far each press moves it in milliseconds, and how far it can go either way.
+const OFFSET_EARLIER_KEY: KeyCode = KeyCode::Minus;
+const OFFSET_LATER_KEY: KeyCode = KeyCode::Equal;
+const CHART_OFFSET_STEP: f32 = 5.0;
+const MAX_CHART_OFFSET: f32 = 200.0;
+
+/// How long the chart's offset is shown for after it's changed, in seconds.
+const OFFSET_MESSAGE_DURATION: f32 = 2.0;
+
+/// How long the player is shown that the notes were resynced to the audio for, in seconds.
+const RESYNC_MESSAGE_DURATION: f32 = 2.0;
+
+/// How long the song fades out for once it's over, in seconds.
+pub(super) const SONG_FADE_OUT: f32 = 1.0;
+
+/// How long the countdown before the song resumes lasts, in seconds.
+const RESUME_COUNTDOWN: f32 = 3.0;
+
+/// How long problems with the chart are shown for at the start of the song, in seconds.
+const CHART_WARNINGS_DURATION: f64 = 6.0;
+
+/// How dark the screen goes behind the pause menu.
+const PAUSE_DIM: f32 = 0.6;
+
+/// Formats a time in the song as minutes and seconds, e.g. "1:05".
+fn clock_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub(super) fn create_background(
+    renderer: &Renderer,
+    textures: &mut TextureCache,
+) -> anyhow::Result<(Sprite, Shape)> {
+    let bg_texture = textures.get(&renderer.device, &renderer.queue, "song_select_bg.jpg")?;
+    let background = SpriteBuilder::new(bg_texture).build(renderer);
+
+    let background_dim = ShapeBuilder::new()
+        .filled_rectangle(
+            [0., 0.],
+            [VIRTUAL_WIDTH, VIRTUAL_HEIGHT],
+            SolidColour::new([0., 0., 0., 1.]),
+        )?
+        .build(&renderer.device);
+
+    Ok((background, background_dim))
+}
+
+/// The shape that darkens the whole screen behind the pause menu.
+pub(super) fn create_pause_dim(renderer: &Renderer) -> anyhow::Result<Shape> {
+    Ok(ShapeBuilder::new()
+        .filled_rectangle(
+            [0., 0.],
+            [VIRTUAL_WIDTH, VIRTUAL_HEIGHT],
+            SolidColour::new([0., 0., 0., PAUSE_DIM]),
+        )?
+        .build(&renderer.device))
+}
+
+/// Moves a chart's offset (see [ChartOverrides::offset]) one step earlier or later. An offset that
+/// gets back to 0 is cleared, so that the chart stops overriding anything.
+fn nudge_chart_offset(offset: Option<f32>, later: bool) -> Option<f32> {
+    let step = if later {
+        CHART_OFFSET_STEP
+    } else {
+        -CHART_OFFSET_STEP
+    };
+    let offset = (offset.unwrap_or(0.0) + step).clamp(-MAX_CHART_OFFSET, MAX_CHART_OFFSET);
+
+    // Round off any error from adding up steps, so the offset can land back on exactly 0
+    let offset = (offset / CHART_OFFSET_STEP).round() * CHART_OFFSET_STEP;
+    (offset != 0.0).then_some(offset)
+}
+
+/// Keeps track of [RETRY_KEY] being held, where `held` is how long it's been held for, and returns
+/// true once it's been held for long enough to restart the song. It has to be let go of and
+/// pressed again to restart again.
+fn hold_to_retry(held: &mut Option<f32>, keyboard: &KeyboardState, delta_time: f32) -> bool {
+    let key = PhysicalKey::Code(RETRY_KEY);
+    if keyboard.is_just_pressed(key) {
+        *held = Some(0.0);
+    } else if !keyboard.is_pressed(key) {
+        *held = None;
     }
 
-    pub fn max_combo(&self) -> usize {
-        self.max_combo
+    let Some(time) = held.as_mut() else {
+        return false;
+    };
+
+    *time += delta_time;
+    if *time >= RETRY_HOLD_TIME {
+        *held = None;
+        true
+    } else {
+        false
     }
 }
 
-pub struct TaikoMode {
-    song_name: String,
-    // UI Stuff
-    background: Sprite,
-    // TODO: Give sprites a colour tint
-    background_dim: Shape,
-    header: Header,
-    note_field: NoteField,
-    balloon_display: BalloonDisplay,
-
-    /// A handle to the audio of the song
-    song_handle: StaticSoundHandle,
-    // Record the global offset, so we don't need to keep querying the settings
-    // This is fine bc the settings will never change mid-song but if that's ever possible, we'd
-    // need to update this every time the setting changed.
-    global_offset: f32,
+/// Whether a play counts towards the player's scores. Practice plays (see
+/// [TaikoMode::with_practice], or retried from a bookmark) don't, as they don't say how the player
+/// does on the chart. Neither do plays whose inputs were played back rather than made by the
+/// player: replays being watched, as they were saved already, and autoplay.
+fn is_recorded(practised: bool, played_back: bool) -> bool {
+    !practised && !played_back
+}
 
-    /// The instant the song started.
-    ///
-    /// Even though the song handle keeps track of the position through the song, that value is
-    /// choppy and using it for the position of the notes will cause the notes to stutter. So we
-    /// need to keep track of the time ourselves.
-    start_time: Instant,
-    started: bool,
-    difficulty: usize,
+/// Whether the song is over and the results should be shown: once the chart is over (see
+/// [GameplayCore::end_time]) and the outro has played, or as soon as the audio stops after the
+/// chart is over. Charts with nothing to play are over when the audio stops.
+///
+/// Times are in the time of the notes, as is the outro delay.
+pub(super) fn song_is_over(
+    note_time: f64,
+    chart_end: Option<f64>,
+    audio_stopped: bool,
+    outro_delay: f64,
+) -> bool {
+    match chart_end {
+        Some(end) if audio_stopped => note_time >= end,
+        Some(end) => note_time >= end + outro_delay,
+        None => audio_stopped,
+    }
+}
 
-    notes: Vec<TaikoModeNote>,
-    barlines: Vec<TaikoModeBarline>,
+/// Every note of a chart, including the notes of every branch, in the order the gameplay core
+/// keeps them in (see [GameplayCore::new]).
+pub(super) fn every_note(chart: &NoteChart) -> Vec<Note> {
+    chart
+        .all_notes()
+        .into_iter()
+        .map(|(note, _)| note)
+        .collect()
+}
 
-    // Note scoring/input handling
-    /// The index of the next note to be played
-    next_note_index: usize,
-    /// The percentage the soul gauge is filled
-    soul_gauge: f32,
-    note_judgement_text: JudgementText,
+/// Every note of a chart as it's drawn, in the same order as [every_note]. They're placed by how
+/// far the track will have scrolled when they're hit, rather than when that is, which only makes a
+/// difference in charts that scroll by beat (see [NoteChart::scroll_time]).
+pub(super) fn every_drawn_note(chart: &NoteChart) -> Vec<Note> {
+    every_note(chart)
+        .into_iter()
+        .map(|note| chart.scrolled_note(note))
+        .collect()
+}
 
-    /// An ongoing record of the player's performance.
-    /// At the end of the song, this will be passed to the score screen.
-    results: PlayResult,
+/// Every barline of a chart as it's drawn (see [every_drawn_note]), along with where they are in
+/// the same way as [NoteChart::all_barlines].
+pub(super) fn every_drawn_barline(
+    chart: &NoteChart,
+) -> (Vec<Barline>, Vec<Option<(usize, Branch)>>) {
+    chart
+        .all_barlines()
+        .into_iter()
+        .map(|(barline, branch)| (chart.scrolled_barline(barline), branch))
+        .unzip()
 }
 
 impl TaikoMode {
+    /// Creates the scene for playing the given difficulty of a song.
+    ///
+    /// If the song is being played at a different rate, `song_data` should already be set up to
+    /// play at that rate (either stretched or with its playback rate changed), and `rate` tells the
+    /// scene how much faster or slower the notes should come.
     pub fn new(
         song: &Song,
         song_data: StaticSoundData,
-        audio_manager: &mut AudioManager,
         difficulty: usize,
-        renderer: &mut Renderer,
-        textures: &mut TextureCache,
+        rate: f32,
+        ctx: &mut Context,
     ) -> anyhow::Result<Self> {
-        let bg_texture = textures.get(&renderer.device, &renderer.queue, "song_select_bg.jpg")?;
-        let background = SpriteBuilder::new(bg_texture).build(renderer);
-
-        let background_dim = ShapeBuilder::new()
-            .filled_rectangle(
-                [0., 0.],
-                [1920., 1080.],
-                SolidColour::new([0., 0., 0., 0.6]),
-            )?
-            .build(&renderer.device);
-
-        let mut song_handle = audio_manager.play(song_data)?;
+        let renderer = &mut *ctx.renderer;
+        let textures = &mut *ctx.textures;
+        let (background, background_dim) = create_background(renderer, textures)?;
+
+        let playback_rate_changed = !matches!(
+            song_data.settings.playback_rate,
+            Value::Fixed(PlaybackRate::Factor(factor)) if factor == 1.0
+        );
+        let audio_scale = if playback_rate_changed {
+            1.0
+        } else {
+            rate as f64
+        };
+        let song_length = (song_data.duration().as_secs_f64() * audio_scale) as f32;
+
+        let song_data = song_data.with_modified_settings(|settings| {
+            settings
+                .output_destination(ctx.sounds.music_track())
+                .volume(song_volume(song))
+        });
+        let mut song_handle = ctx.audio.play(song_data.clone())?;
         // We want to start the song once the scene is actually loaded
         song_handle.pause(Tween::default())?;
 
-        let track = &song.difficulties[difficulty]
+        let course = song.difficulties[difficulty]
             .as_ref()
-            .expect("Difficulty doesn't exist!")
-            .chart;
+            .expect("Difficulty doesn't exist!");
+        let track = &course.chart;
+        let notes = every_drawn_note(track);
+        let (barlines, barline_branches) = every_drawn_barline(track);
+
+        let hash = chart_hash(track);
+        log::info!("playing {} (chart {hash})", song.title);
+        let mut rng = Rng::from_bytes(&hash.digest);
 
-        Ok(Self {
-            song_name: song.title.clone(),
+        // Load every texture the chart needs now, so that nothing has to be loaded from disk once
+        // the song has started.
+        textures.preload(&renderer.device, &renderer.queue, chart_textures(&notes))?;
+
+        let rules_preset = settings().game.rules_preset;
+        let rules = settings().game.rules();
+        let core = GameplayCore::new(track, difficulty).with_rules(rules);
+        let note_speed = settings().visual.note_speed;
+        let offset_profile = settings().game.offset_profile_label();
+        // Notes are always shown in full unless HIDDEN or SUDDEN are picked (see with_mods)
+        let visibility = VisibilitySettings::default();
+        let mirrored = settings().visual.mirror_playfield;
+        let layout = note_layout(mirrored);
+
+        let chart_overrides = ChartSettings::load(CHART_SETTINGS_PATH)
+            .map(|chart_settings| chart_settings.get(&hash))
+            .unwrap_or_else(|e| {
+                log::error!("couldn't load per-chart settings: {e}");
+                ChartOverrides::default()
+            });
+        let display = ChartDisplay::resolve(&chart_overrides, &settings().visual);
+
+        let song_name = song
+            .title_in(settings().game.title_language.as_deref())
+            .to_string();
+
+        let mut scene = Self {
+            song: song.clone(),
+            song_data,
+            star_level: course.star_level,
             background,
             background_dim,
-            header: Header::new(renderer, &song.title)?,
-            note_field: NoteField::new(renderer)?,
-            balloon_display: BalloonDisplay::new(textures, renderer)?,
+            pause_dim: create_pause_dim(renderer)?,
+            dancers: Dancers::new(renderer, textures, song.bpm),
+            header: Header::new(renderer, &song_name, offset_profile.as_deref(), mirrored)?,
+            song_name,
+            note_field: NoteField::new(renderer, mirrored)?,
+            balloon_display: BalloonDisplay::new(textures, renderer, rng.fork(), mirrored)?,
+            drumroll_display: DrumrollDisplay::new(renderer, mirrored)?,
+            effects: Effects::new(renderer, textures, rng.fork(), mirrored)?,
+            health_bar: HealthBar::new(renderer, core.health().threshold(), mirrored)?,
+            score_display: ScoreDisplay::new(renderer, mirrored),
+            combo_display: ComboDisplay::new(renderer, rng.fork(), mirrored),
             song_handle,
+            audio_scale,
+            song_length,
+            chart_end: core.end_time(),
+            chart: track.clone(),
+            density_strip: None,
+            practice: false,
+            loop_start: None,
+            loop_end: None,
             started: false,
             start_time: Instant::now(),
-            global_offset: SETTINGS.read().unwrap().game.global_note_offset / 1000.0,
+            global_offset: settings().game.note_offset() / 1000.0,
+            offset_profile,
             difficulty,
-            notes: create_notes(renderer, textures, &track.notes),
-            barlines: create_barlines(renderer, &track.barlines),
-            next_note_index: 0,
-            soul_gauge: 0.0,
-            note_judgement_text: JudgementText::new(renderer),
-            results: PlayResult::new(),
+            rate,
+            rng,
+            paused_at: None,
+            countdown_start: Instant::now(),
+            pause_selection: 0,
+            clicked_pause_option: None,
+            settings_changed: false,
+            chart_hash: hash,
+            chart_overrides,
+            chart_overrides_changed: false,
+            chart_offset_changed_at: None,
+            display,
+            settings: SettingsSnapshot::new(),
+            hud: HudSettings::current(),
+            notes: create_notes(renderer, textures, &notes, note_speed, &layout, &visibility),
+            barlines: create_barlines(renderer, &barlines, note_speed, &layout, &visibility),
+            barline_branches,
+            ghost_notes: Vec::new(),
+            note_window: VisibilityWindow::default(),
+            barline_window: VisibilityWindow::default(),
+            ghost_window: VisibilityWindow::default(),
+            ghost_difficulty: None,
+            visibility,
+            mirrored,
+            rules,
+            rules_preset,
+            note_judgement_text: JudgementText::new(renderer, mirrored)?,
+            timing_meter: TimingMeter::new(
+                renderer,
+                &core.timing_windows().map(|w| w as f32),
+                mirrored,
+            )?,
+            input_display: InputDisplay::new(renderer)?,
+            stream_readout: StreamReadout::new(renderer),
+            core,
+            chart_warnings: Vec::new(),
+            bookmark: None,
+            bookmarked_at: None,
+            retried_from_bookmark: false,
+            retry_held: None,
+            inputs: Some(Vec::new()),
+            playback: None,
+            replay: None,
+            autoplay: false,
+            sudden_death: settings().game.fail_behaviour == FailBehaviour::SuddenDeath,
+            mods: Mods::default(),
+            skip_detector: SkipDetector::default(),
+            resynced_at: None,
+        };
+
+        scene.apply_display(renderer);
+        scene.update_visibility_windows();
+        Ok(scene)
+    }
+
+    /// Works out the [VisibilityWindow]s again, after the notes, barlines or ghost notes have been
+    /// made again.
+    fn update_visibility_windows(&mut self) {
+        self.note_window = VisibilityWindow::new(self.notes.iter().map(TaikoModeNote::visibility));
+        self.barline_window =
+            VisibilityWindow::new(self.barlines.iter().map(TaikoModeBarline::visibility));
+        self.ghost_window =
+            VisibilityWindow::new(self.ghost_notes.iter().map(GhostNote::visibility));
+    }
+
+    /// Shows the given problems with the chart for the first few seconds of the song, e.g. the
+    /// mistakes that were skipped over to load it in lenient mode.
+    pub fn with_chart_warnings(mut self, warnings: Vec<TJAParseWarning>) -> Self {
+        self.chart_warnings = warnings;
+        self
+    }
+
+    /// Plays the chart with the given mods. INVERSE and SHUFFLE change the chart itself, so
+    /// everything made from it is made again, and HIDDEN and SUDDEN change when notes are shown.
+    ///
+    /// This should come before any other builder, as they work from the chart. A replay has to be
+    /// watched with the mods it was played with, or its inputs won't line up with the notes.
+    pub fn with_mods(
+        mut self,
+        mods: Mods,
+        renderer: &Renderer,
+        textures: &mut TextureCache,
+    ) -> Self {
+        if mods.is_empty() {
+            return self;
+        }
+
+        if mods.changes_notes() {
+            self.chart = mods.apply(&self.chart);
+            self.core = GameplayCore::new(&self.chart, self.difficulty).with_rules(self.rules);
+
+            // Changing the colour of the notes can need textures the chart didn't
+            let notes = every_note(&self.chart);
+            if let Err(e) =
+                textures.preload(&renderer.device, &renderer.queue, chart_textures(&notes))
+            {
+                log::error!("couldn't load the textures for the mods: {e}");
+                return self;
+            }
+        }
+
+        self.visibility = mods.visibility();
+        let layout = note_layout(self.mirrored);
+        self.notes = create_notes(
+            renderer,
+            textures,
+            &every_drawn_note(&self.chart),
+            settings().visual.note_speed,
+            &layout,
+            &self.visibility,
+        );
+
+        // The scroll speeds of the barlines already include the note speed
+        let barlines = self
+            .barlines
+            .iter()
+            .map(TaikoModeBarline::barline)
+            .collect::<Vec<_>>();
+        self.barlines = create_barlines(renderer, &barlines, 1.0, &layout, &self.visibility);
+        self.update_visibility_windows();
+
+        self.mods = mods;
+        self
+    }
+
+    /// Plays back a replay of the chart instead of taking the player's inputs. The replay's
+    /// inputs are judged by the rules it was played with, and the play isn't recorded.
+    pub fn with_replay(mut self, replay: Replay) -> Self {
+        let mut inputs = replay.inputs.clone();
+        inputs.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        self.rules = replay.rules;
+        self.rules_preset = replay.rules_preset;
+        self.core = GameplayCore::new(&self.chart, self.difficulty).with_rules(self.rules);
+        self.inputs = None;
+        self.playback = Some((inputs, 0));
+        self.replay = Some(replay);
+        // Replays are always watched to the end, as it isn't known whether they were sudden death
+        self.sudden_death = false;
+        self
+    }
+
+    /// Has the game play the chart by itself, hitting every note perfectly (see
+    /// [autoplay_inputs]), which is handy for checking a chart or recording a video of it. The
+    /// player's inputs are ignored, and the play isn't recorded.
+    pub fn with_autoplay(mut self) -> Self {
+        self.inputs = None;
+        self.playback = Some((autoplay_inputs(&self.chart, self.difficulty), 0));
+        self.autoplay = true;
+        self
+    }
+
+    /// Practises the song: a strip showing the whole chart is shown along the bottom, which can be
+    /// clicked on to jump to any part of it, with a close-up of the song's waveform above it, and
+    /// a part of the song can be looped over with [LOOP_START_KEY] and [LOOP_END_KEY]. Practice
+    /// plays aren't recorded.
+    pub fn with_practice(mut self, renderer: &mut Renderer) -> Self {
+        if self.density_strip.is_none() {
+            let peaks = Arc::new(Peaks::new(&self.song_data));
+
+            match DensityStrip::new(renderer, &self.chart, self.song_length, peaks) {
+                Ok(strip) => self.density_strip = Some(strip),
+                Err(e) => log::error!("couldn't create the density strip: {e}"),
+            }
+        }
+
+        self.practice = true;
+        self
+    }
+
+    /// Shows the notes of another difficulty of the song as ghosts above the chart being played,
+    /// to help the player get to know it. The ghosts can't be hit, and don't affect the results
+    /// other than being listed as a modifier.
+    ///
+    /// If the song doesn't have that difficulty, or its notes can't be drawn, the song is played
+    /// without ghosts.
+    pub fn with_ghost_notes(
+        mut self,
+        song: &Song,
+        difficulty: usize,
+        renderer: &Renderer,
+        textures: &mut TextureCache,
+    ) -> Self {
+        let Some(course) = song.difficulties.get(difficulty).and_then(Option::as_ref) else {
+            log::warn!(
+                "{} has no {} chart, so there are no ghost notes",
+                song.title,
+                DifficultyInfo::name_of(difficulty)
+            );
+            return self;
+        };
+
+        // The ghosts move along with the track of the chart being played
+        let notes = course
+            .chart
+            .notes
+            .iter()
+            .map(|&note| self.chart.scrolled_note(note))
+            .collect::<Vec<_>>();
+        if let Err(e) = textures.preload(&renderer.device, &renderer.queue, chart_textures(&notes))
+        {
+            log::error!("couldn't load the textures for ghost notes: {e}");
+            return self;
+        }
+
+        self.ghost_notes = create_ghost_notes(
+            renderer,
+            textures,
+            &notes,
+            settings().visual.note_speed,
+            &ghost_layout(self.mirrored),
+            &self.visibility,
+        );
+        self.ghost_difficulty = Some(difficulty);
+        self.update_visibility_windows();
+        self
+    }
+
+    /// Rebuilds every sprite, shape and text in the scene, without affecting the state of the game.
+    fn rebuild_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        let mirrored = self.mirrored;
+        (self.background, self.background_dim) = create_background(renderer, textures)?;
+        self.pause_dim = create_pause_dim(renderer)?;
+        self.dancers = Dancers::new(renderer, textures, self.song.bpm);
+        self.header = Header::new(
+            renderer,
+            &self.song_name,
+            self.offset_profile.as_deref(),
+            mirrored,
+        )?;
+        self.note_field = NoteField::new(renderer, mirrored)?;
+        self.balloon_display = BalloonDisplay::new(textures, renderer, self.rng.fork(), mirrored)?;
+        self.drumroll_display = DrumrollDisplay::new(renderer, mirrored)?;
+        self.effects = Effects::new(renderer, textures, self.rng.fork(), mirrored)?;
+        self.health_bar = HealthBar::new(renderer, self.core.health().threshold(), mirrored)?;
+        self.score_display = ScoreDisplay::new(renderer, mirrored);
+        self.combo_display = ComboDisplay::new(renderer, self.rng.fork(), mirrored);
+        self.note_judgement_text = JudgementText::new(renderer, mirrored)?;
+        self.timing_meter = TimingMeter::new(
+            renderer,
+            &self.core.timing_windows().map(|w| w as f32),
+            mirrored,
+        )?;
+        self.input_display = InputDisplay::new(renderer)?;
+        self.stream_readout = StreamReadout::new(renderer);
+        self.apply_display(renderer);
+
+        if let Some(strip) = self.density_strip.take() {
+            self.density_strip = Some(DensityStrip::new(
+                renderer,
+                &self.chart,
+                self.song_length,
+                Arc::clone(strip.peaks()),
+            )?);
+        }
+
+        for note in self.notes.iter_mut() {
+            if !note.recreate_gpu_resources(renderer, textures, &self.visibility) {
+                return Err(anyhow::format_err!("couldn't recreate note"));
+            }
+        }
+
+        let barlines = self
+            .barlines
+            .iter()
+            .map(TaikoModeBarline::barline)
+            .collect::<Vec<_>>();
+        // The scroll speeds of the barlines already include the note speed
+        self.barlines = create_barlines(
+            renderer,
+            &barlines,
+            1.0,
+            &note_layout(self.mirrored),
+            &self.visibility,
+        );
+
+        // ...and so do the ghost notes'
+        let ghosts = self
+            .ghost_notes
+            .iter()
+            .map(GhostNote::note)
+            .collect::<Vec<_>>();
+        self.ghost_notes = create_ghost_notes(
+            renderer,
+            textures,
+            &ghosts,
+            1.0,
+            &ghost_layout(self.mirrored),
+            &self.visibility,
+        );
+        self.update_visibility_windows();
+
+        // The texture cache has been emptied, so it'll have forgotten that we're in the middle of
+        // a song.
+        textures.set_gameplay_active(self.started);
+
+        Ok(())
+    }
+
+    /// The offset the notes are played with in seconds: the global offset, plus the chart's own
+    /// offset if it has one.
+    fn offset(&self) -> f64 {
+        (self.global_offset + self.chart_overrides.offset.unwrap_or(0.0) / 1000.0) as f64
+    }
+
+    /// Returns what time it is with respect to the notes and offset. The clock stops while the
+    /// game is paused.
+    ///
+    /// When playing at a different rate, the notes move through the chart at that rate, but the
+    /// offset is a real amount of time, so it's taken off before scaling.
+    ///
+    /// This is an f64 so that judgements are just as precise at the end of a long song as they are
+    /// at the start. It's only narrowed to an f32 for positioning things on screen.
+    fn note_time(&self) -> f64 {
+        self.note_time_at(Instant::now())
+    }
+
+    /// What the note time was (or will be) at the given moment. If the game is paused, the clock
+    /// stopped when it was paused.
+    fn note_time_at(&self, at: Instant) -> f64 {
+        let at = self.paused_at.map_or(at, |paused_at| paused_at.min(at));
+        let elapsed = match at.checked_duration_since(self.start_time) {
+            Some(elapsed) => elapsed.as_secs_f64(),
+            None => -self.start_time.duration_since(at).as_secs_f64(),
+        };
+
+        (elapsed - self.offset()) * self.rate as f64
+    }
+
+    /// How far the track has scrolled at the current note time, which is the time the notes are
+    /// drawn at (see [every_drawn_note]).
+    fn scroll_time(&self) -> f32 {
+        self.chart.scroll_time(self.note_time() as f32)
+    }
+
+    /// Where the song's audio is up to, in the same time as the notes but without the offset.
+    fn song_time(&self) -> f64 {
+        self.note_time() + self.offset() * self.rate as f64
+    }
+
+    /// The replay of the play that just finished, if its inputs were kept (see
+    /// [TaikoMode::inputs]). If this is a replay being watched, it's that replay.
+    fn finished_replay(&self, played_at: i64) -> Option<Replay> {
+        if let Some(replay) = &self.replay {
+            return Some(replay.clone());
+        }
+
+        // Autoplay's inputs aren't kept, as they'd be the same every time
+        self.inputs.as_ref().map(|inputs| Replay {
+            chart: self.chart_hash.to_string(),
+            difficulty: self.difficulty,
+            rate: self.rate,
+            rules: self.rules,
+            rules_preset: self.rules_preset,
+            mods: self.mods,
+            played_at,
+            inputs: inputs.clone(),
         })
     }
 
-    /// Returns what time it is with respect to the notes and global offset.
-    fn note_time(&self) -> f32 {
-        self.start_time.elapsed().as_secs_f32() - self.global_offset
+    /// Whether the play counts towards the player's scores (see [is_recorded]).
+    fn is_recorded(&self) -> bool {
+        is_recorded(
+            self.practice || self.retried_from_bookmark,
+            self.playback.is_some(),
+        )
+    }
+
+    /// The best the player has done on the chart, from the plays recorded so far.
+    fn high_score(&self) -> Option<BestRecord> {
+        ScoreDatabase::load(SCORES_PATH)
+            .inspect_err(|e| log::error!("couldn't load scores: {e}"))
+            .ok()?
+            .best_record(&self.chart_hash.to_string(), self.difficulty)
+    }
+
+    /// Saves the play to the score database, along with its replay if it has one, if the play
+    /// counts (see [TaikoMode::is_recorded]).
+    fn record_play(&self, played_at: i64, replay: Option<&Replay>) {
+        if !self.is_recorded() {
+            return;
+        }
+
+        let replay = replay.and_then(|replay| {
+            replay
+                .save(REPLAYS_DIR)
+                .inspect_err(|e| log::error!("couldn't save the replay: {e}"))
+                .ok()
+        });
+
+        let play = PlayRecord {
+            chart: self.chart_hash.to_string(),
+            difficulty: self.difficulty,
+            star_level: self.star_level,
+            score: self.core.results().score(),
+            cleared: self.core.health().is_clear(),
+            full_combo: self.core.results().is_full_combo(),
+            max_combo: self.core.results().max_combo(),
+            judgements: Some(JudgementCounts::of(self.core.results())),
+            played_at,
+            rules: Some(self.rules_preset),
+            rate: (self.rate != 1.0).then_some(self.rate),
+            mods: self.mods,
+            crown: Some(Crown::of(
+                self.core.results(),
+                self.core.health().is_clear(),
+            )),
+            replay,
+        };
+
+        if let Err(e) = record_play(play) {
+            log::error!("couldn't save the play: {e}");
+        }
+    }
+
+    /// Puts the note clock back in line with the audio if the audio has skipped, e.g. because the
+    /// audio thread couldn't keep up. Otherwise the notes would be off from the music for the rest
+    /// of the song.
+    fn resync_after_skips(&mut self) {
+        let position = self.song_handle.position();
+
+        // The audio doesn't report a position until it's actually started playing
+        if position <= 0.0 || self.song_handle.state() != PlaybackState::Playing {
+            return;
+        }
+
+        let rate = self.rate as f64;
+        let audio_time = position * self.audio_scale / rate;
+        let clock_time = self.song_time() / rate;
+
+        if let Some(correction) = self.skip_detector.observe(audio_time, clock_time) {
+            log::warn!(
+                "the audio skipped by {:.0}ms, resyncing the notes",
+                correction * 1000.0
+            );
+
+            // Moving the start of the clock back moves the clock forwards
+            let amount = Duration::from_secs_f64(correction.abs());
+            if correction < 0.0 {
+                self.start_time += amount;
+            } else if let Some(start_time) = self.start_time.checked_sub(amount) {
+                self.start_time = start_time;
+            }
+
+            self.resynced_at = Some(Instant::now());
+        }
+    }
+
+    /// Jumps to a time in the song (see [TaikoMode::song_time]). The notes are all reset and
+    /// judging starts again from there, so this is only for practising.
+    fn seek(&mut self, time: f64, renderer: &mut Renderer) {
+        let time = time.clamp(0.0, self.song_length as f64);
+        self.inputs = None;
+
+        if let Err(e) = self.song_handle.seek_to(time / self.audio_scale) {
+            log::error!("couldn't seek the song: {e}");
+            return;
+        }
+
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        let elapsed = Duration::from_secs_f64(time / self.rate as f64);
+        self.start_time = now.checked_sub(elapsed).unwrap_or(now);
+        self.skip_detector.reset();
+
+        let state = self.core.state();
+        self.core = GameplayCore::new(&self.chart, self.difficulty).with_rules(self.rules);
+        self.core.skip_to(self.note_time());
+
+        match state {
+            PlayState::Playing => {}
+            PlayState::Paused => self.core.pause(),
+            PlayState::Countdown => self.core.start_countdown(),
+        }
+
+        let now = self.note_time();
+        if let Some((inputs, next)) = self.playback.as_mut() {
+            *next = inputs.partition_point(|input| input.time < now);
+        }
+
+        self.effects.clear();
+        self.combo_display.clear();
+        self.drumroll_display.clear(renderer);
+        for note in self.notes.iter_mut() {
+            note.reset();
+        }
+    }
+
+    /// Starts the song again from the top, or from a time partway through it (see
+    /// [TaikoMode::seek]). Starting partway through is practice, and the results will say so.
+    /// Starting from the top is a whole new play, so its inputs are kept again.
+    fn restart(&mut self, from: Option<f32>, renderer: &mut Renderer) {
+        self.retried_from_bookmark = from.is_some();
+        self.seek(from.unwrap_or(0.0) as f64, renderer);
+
+        if from.is_none() && self.playback.is_none() {
+            self.inputs = Some(Vec::new());
+        }
+    }
+
+    /// Moves the chart's offset one step earlier or later. It's saved along with the chart's other
+    /// overrides, and used whenever the chart is played from then on.
+    fn nudge_offset(&mut self, later: bool) {
+        self.chart_overrides.offset = nudge_chart_offset(self.chart_overrides.offset, later);
+        self.chart_overrides_changed = true;
+        self.chart_offset_changed_at = Some(Instant::now());
+    }
+
+    /// Bookmarks the measure that's being played, so that it can be retried from later.
+    fn set_bookmark(&mut self) {
+        self.bookmark = Some(self.chart.measure_start(self.note_time() as f32));
+        self.bookmarked_at = Some(Instant::now());
+    }
+
+    /// Starts the loop at the start of the measure that's being played. If that's after the end
+    /// of the loop, the end is cleared so it can be set again.
+    fn set_loop_start(&mut self) {
+        let start = self.chart.measure_start(self.song_time() as f32);
+        self.loop_start = Some(start);
+        self.loop_end = self.loop_end.filter(|&end| end > start);
+    }
+
+    /// Ends the loop at the end of the measure that's being played (or the end of the song). If
+    /// that's before the start of the loop, the start is cleared so it can be set again.
+    fn set_loop_end(&mut self) {
+        let end = self
+            .chart
+            .measure_end(self.song_time() as f32)
+            .unwrap_or(self.song_length);
+        self.loop_end = Some(end);
+        self.loop_start = self.loop_start.filter(|&start| start < end);
+    }
+
+    /// Jumps back to the start of the loop once the song reaches the end of it.
+    fn loop_back(&mut self, renderer: &mut Renderer) {
+        if let Some((start, end)) = self.loop_start.zip(self.loop_end) {
+            if self.song_time() >= end as f64 {
+                self.seek(start as f64, renderer);
+            }
+        }
+    }
+
+    /// Updates the visuals to reflect what happened to the notes.
+    fn apply_events(&mut self, events: Vec<GameplayEvent>, renderer: &mut Renderer) {
+        for event in events {
+            match event {
+                GameplayEvent::Hit {
+                    note,
+                    judgement,
+                    offset,
+                    big,
+                } => {
+                    self.notes[note].set_hit();
+                    let colour = if self.notes[note].note_type().is_don() {
+                        NoteColour::Don
+                    } else {
+                        NoteColour::Kat
+                    };
+                    self.effects.hit(BasicNoteType { colour, big }, judgement);
+                    self.note_judgement_text
+                        .display_judgement(judgement, &self.hud);
+                    self.timing_meter.hit(offset, judgement, renderer);
+                }
+                GameplayEvent::BalloonHit {
+                    note,
+                    hits_left,
+                    hit_target,
+                } => {
+                    self.notes[note].set_balloon_hits_left(hits_left);
+                    self.balloon_display.hit(hits_left, hit_target, renderer);
+                }
+                GameplayEvent::BalloonMissed { .. } => self.balloon_display.discard(),
+                GameplayEvent::Drumroll {
+                    note,
+                    roll_note,
+                    hits,
+                } => {
+                    self.effects.drumroll(roll_note);
+                    self.drumroll_display
+                        .hit(hits, self.notes[note].end_time(), renderer);
+                }
+                GameplayEvent::Miss { .. } | GameplayEvent::BranchChanged { .. } => {}
+            }
+        }
+    }
+
+    /// Judges the inputs being played back (see [TaikoMode::playback]) that are due by the note
+    /// time `now`, as if the player had just made them.
+    fn play_back_inputs(&mut self, now: f64, ctx: &mut Context) {
+        if self.core.state() != PlayState::Playing {
+            return;
+        }
+
+        let due = match self.playback.as_mut() {
+            Some((inputs, next)) => {
+                let start = *next;
+                *next += inputs[start..].partition_point(|input| input.time <= now);
+                inputs[start..*next].to_vec()
+            }
+            None => return,
+        };
+
+        for TimedInput { time, input } in due {
+            // Notes are missed between inputs just like they were in the play (see
+            // [simulate](super::simulate::simulate))
+            let events = self.core.advance(time);
+            self.apply_events(events, ctx.renderer);
+
+            let events = self.core.press(input, time);
+            self.drum_feedback(input, &events, ctx);
+            self.apply_events(events, ctx.renderer);
+        }
+    }
+
+    /// Plays the drum's sound and shows that it was hit, whether or not the hit was judged. This
+    /// happens even during the countdown after resuming, so that the player can get back into the
+    /// rhythm. `events` is what the hit did, which decides which sound is played (see
+    /// [hit_sound]).
+    fn drum_feedback(&mut self, input: DrumInput, events: &[GameplayEvent], ctx: &mut Context) {
+        ctx.sounds.play_scaled(
+            ctx.audio,
+            hit_sound(input, events),
+            self.song.effects_volume / 100.0,
+        );
+
+        if self.hud.streamer_mode {
+            self.input_display.press(input, ctx.renderer);
+        }
+    }
+
+    /// Stops the song and opens the pause menu.
+    ///
+    /// Whatever keys were just pressed are cleared, so that the press that paused the game (or a
+    /// drum hit in the same frame) can't also pick an option in the pause menu.
+    ///
+    /// If the game was counting down to resume, it goes back to being paused, and counts as having
+    /// been paused since it was first paused.
+    fn pause(&mut self, keyboard: &mut KeyboardState) {
+        self.paused_at.get_or_insert_with(Instant::now);
+        self.song_handle.pause(Tween::default()).unwrap();
+        self.core.pause();
+        self.pause_selection = 0;
+        self.clicked_pause_option = None;
+        keyboard.clear_edges();
+    }
+
+    /// Closes the pause menu and starts counting down to when the song resumes.
+    fn start_countdown(&mut self, keyboard: &mut KeyboardState) {
+        self.save_changed_settings();
+        self.countdown_start = Instant::now();
+        self.core.start_countdown();
+        keyboard.clear_edges();
     }
 
-    /// Returns the timing windows to use for the song's difficulty.
-    fn timing_windows(&self) -> &'static [f32; 3] {
-        match self.difficulty {
-            0 | 1 => &EASY_NORMAL_TIMING,
-            _ => &HARD_EXTREME_TIMING,
+    /// Continues the song after the countdown has finished.
+    fn resume(&mut self, keyboard: &mut KeyboardState) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.start_time += paused_at.elapsed();
         }
+
+        self.song_handle.resume(Tween::default()).unwrap();
+        self.core.resume();
+        self.skip_detector.reset();
+        keyboard.clear_edges();
     }
 
-    /// Considers the next note to have been missed. Updates the index of the next note, and adds a
-    /// miss to the play result if appropriate.
-    fn skip_next_note(&mut self) {
-        if let Some(note) = self.notes.get(self.next_note_index) {
-            self.next_note_index += 1;
+    /// Saves the settings (and the chart's overrides) if they were changed in the pause menu.
+    fn save_changed_settings(&mut self) {
+        if std::mem::take(&mut self.settings_changed) {
+            if let Err(e) = save_settings() {
+                log::error!("couldn't save settings: {e}");
+            }
+        }
 
-            if note.is_don_or_kat() {
-                self.results.push_judgement(None);
-            } else if matches!(note.note, NoteInner::Balloon { .. }) {
-                self.balloon_display.discard();
+        if std::mem::take(&mut self.chart_overrides_changed) {
+            // Read the file again rather than keeping it around, so that only this chart's
+            // overrides are written
+            let result = ChartSettings::load(CHART_SETTINGS_PATH).and_then(|mut chart_settings| {
+                chart_settings.set(&self.chart_hash, self.chart_overrides);
+                chart_settings.save(CHART_SETTINGS_PATH)
+            });
+
+            if let Err(e) = result {
+                log::error!("couldn't save per-chart settings: {e}");
             }
         }
     }
+
+    /// Darkens the background and fades the HUD according to the current display settings.
+    fn apply_display(&self, renderer: &Renderer) {
+        self.background_dim
+            .set_tint([1.0, 1.0, 1.0, self.display.background_dim], renderer);
+        self.header.set_opacity(self.display.hud_opacity, renderer);
+        self.note_field
+            .set_opacity(self.display.hud_opacity, renderer);
+    }
+
+    /// Shows the settings that can be changed mid-song, and applies any changes straight away.
+    fn quick_settings_ui(&mut self, ui: &mut egui::Ui) {
+        let mut visual = settings().visual.clone();
+        let mut profile = settings().game.active_offset_profile;
+
+        ui.collapsing(RichText::new("Quick settings").size(20.0), |ui| {
+            offset_profile_combo_box(ui, &settings().game.offset_profiles, &mut profile);
+
+            ui.horizontal(|ui| {
+                ui.label("Judgements:");
+                ui.radio_value(&mut visual.judgement_style, JudgementStyle::Text, "Text");
+                ui.radio_value(&mut visual.judgement_style, JudgementStyle::Burst, "Burst");
+            });
+
+            ui.checkbox(&mut visual.timing_meter, "Timing meter");
+            ui.checkbox(&mut visual.streamer_mode, "Streamer mode");
+            ui.checkbox(&mut visual.streamer_readout, "Streamer mode combo readout");
+
+            ui.add_space(10.0);
+            ui.label("For this chart:");
+            self.chart_overrides_ui(ui, &visual);
+        });
+
+        if visual != settings().visual {
+            settings_mut().visual = visual;
+            self.settings_changed = true;
+        }
+
+        if profile != settings().game.active_offset_profile {
+            settings_mut().game.switch_offset_profile(profile, None);
+            self.settings_changed = true;
+        }
+    }
+
+    /// Sliders for the settings this chart overrides. A setting that isn't overridden shows
+    /// the global setting, and only becomes an override once it's moved.
+    fn chart_overrides_ui(&mut self, ui: &mut egui::Ui, visual: &VisualSettings) {
+        let mut overrides = self.chart_overrides;
+        let display = ChartDisplay::resolve(&overrides, visual);
+
+        let mut background_dim = display.background_dim;
+        if ui
+            .add(percentage_slider(&mut background_dim, "Background dim"))
+            .changed()
+        {
+            overrides.background_dim = Some(background_dim);
+        }
+
+        let mut hud_opacity = display.hud_opacity;
+        if ui
+            .add(percentage_slider(&mut hud_opacity, "HUD opacity"))
+            .changed()
+        {
+            overrides.hud_opacity = Some(hud_opacity);
+        }
+
+        let mut offset = overrides.offset.unwrap_or(0.0);
+        if ui
+            .add(
+                egui::Slider::new(&mut offset, -MAX_CHART_OFFSET..=MAX_CHART_OFFSET)
+                    .step_by(CHART_OFFSET_STEP as f64)
+                    .suffix("ms")
+                    .text("Offset"),
+            )
+            .on_hover_text(format!(
+                "Added to the global offset, for charts that are off-sync. It can also be moved \
+                with {} and {} while playing",
+                key_name(PhysicalKey::Code(OFFSET_EARLIER_KEY)),
+                key_name(PhysicalKey::Code(OFFSET_LATER_KEY)),
+            ))
+            .changed()
+        {
+            overrides.offset = (offset != 0.0).then_some(offset);
+        }
+
+        if ui
+            .add_enabled(
+                !overrides.is_empty(),
+                egui::Button::new("Use global settings"),
+            )
+            .clicked()
+        {
+            overrides = ChartOverrides::default();
+        }
+
+        if overrides != self.chart_overrides {
+            self.chart_overrides = overrides;
+            self.chart_overrides_changed = true;
+        }
+    }
+
+    /// Switches to the offset in the settings, if it's changed.
+    fn update_offset(&mut self, renderer: &mut Renderer) {
+        let game = &self.settings.game;
+        let profile = game.offset_profile_label();
+        self.global_offset = game.note_offset() / 1000.0;
+
+        if profile != self.offset_profile {
+            self.offset_profile = profile;
+
+            match Header::new(
+                renderer,
+                &self.song_name,
+                self.offset_profile.as_deref(),
+                self.mirrored,
+            ) {
+                Ok(header) => {
+                    header.set_opacity(self.display.hud_opacity, renderer);
+                    self.header = header;
+                }
+                Err(e) => log::error!("couldn't rebuild the header: {e}"),
+            }
+        }
+    }
+
+    /// Navigates the pause menu with the keyboard (see [pause_menu_input]) or the mouse, and
+    /// returns the option that was chosen, if any.
+    fn pause_menu_input(&mut self, keyboard: &mut KeyboardState) -> Option<PauseOption> {
+        let options = pause_options(self.bookmark.is_some());
+
+        pause_menu_input(
+            keyboard,
+            &self.settings.game.key_mappings,
+            &options,
+            &mut self.pause_selection,
+        )
+        .or_else(|| self.clicked_pause_option.take())
+    }
 }
 
 impl GameState for TaikoMode {
+    fn fixed_update(&mut self, ctx: &mut Context, time: Instant) {
+        if !self.started {
+            return;
+        }
+
+        // Once the song is past the end of a loop, nothing more is judged until the next update
+        // takes it back round (see [TaikoMode::loop_back])
+        let now = self.note_time_at(time);
+        let song_time = now + self.offset() * self.rate as f64;
+        if let Some((_, end)) = self.loop_start.zip(self.loop_end) {
+            if song_time >= end as f64 {
+                return;
+            }
+        }
+
+        self.play_back_inputs(now, ctx);
+        let events = self.core.advance(now);
+        self.apply_events(events, ctx.renderer);
+    }
+
     fn update(&mut self, ctx: &mut Context, delta_time: f32) -> StateTransition {
+        if self.settings.refresh() {
+            self.hud = HudSettings::from(&self.settings.visual);
+            self.update_offset(ctx.renderer);
+        }
+
+        // The display can change either from the global settings or from the chart's overrides
+        let display = ChartDisplay::resolve(&self.chart_overrides, &self.settings.visual);
+        if display != self.display {
+            self.display = display;
+            self.apply_display(ctx.renderer);
+        }
+
+        // This is done before checking whether the song has finished, so that a loop that ends
+        // with the song goes back round rather than ending it. The song doesn't end at all while
+        // it's looping.
+        if self.started && self.core.state() == PlayState::Playing {
+            self.loop_back(ctx.renderer);
+        }
+
+        let looping = self.loop_start.is_some() && self.loop_end.is_some();
+        let song_over = !looping
+            && song_is_over(
+                self.note_time(),
+                self.chart_end,
+                self.song_handle.state() == PlaybackState::Stopped,
+                (self.settings.game.outro_delay * self.rate) as f64,
+            );
+        let stage_failed = self.sudden_death && self.core.health().was_emptied();
+
         if !self.started {
             self.song_handle.resume(Default::default()).unwrap();
             self.started = true;
             self.start_time = Instant::now();
-        } else if self.song_handle.state() == PlaybackState::Stopped {
-            return StateTransition::Swap(Box::new(ScoreScreen::new(
-                ctx,
-                self.song_name.clone(),
-                self.results.clone(),
-            )));
+            ctx.textures.set_gameplay_active(true);
+        } else if self.core.state() == PlayState::Playing && (song_over || stage_failed) {
+            ctx.textures.set_gameplay_active(false);
+            let fade_out = Tween {
+                duration: Duration::from_secs_f32(SONG_FADE_OUT),
+                ..Default::default()
+            };
+            if let Err(e) = self.song_handle.stop(fade_out) {
+                log::error!("couldn't stop the song: {e}");
+            }
+
+            let mut modifiers = Vec::new();
+            if self.rate != 1.0 {
+                modifiers.push(format!("x{}", self.rate));
+            }
+            if self.practice || self.retried_from_bookmark {
+                modifiers.push("Practice".to_string());
+            }
+            if let Some(difficulty) = self.ghost_difficulty {
+                modifiers.push(format!("Ghost {}", DifficultyInfo::name_of(difficulty)));
+            }
+            if self.replay.is_some() {
+                modifiers.push("Replay".to_string());
+            }
+            if self.autoplay {
+                modifiers.push("Auto".to_string());
+            }
+            if self.sudden_death {
+                modifiers.push("Sudden death".to_string());
+            }
+            modifiers.extend(self.mods.names().into_iter().map(str::to_string));
+
+            // The chart's offset can be moved mid-song without ever pausing
+            self.save_changed_settings();
+
+            let played_at = chrono::Utc::now().timestamp();
+            let replay = self.finished_replay(played_at);
+            // The high score is read before this play is saved, so that it can be compared with
+            let high_score = self.high_score();
+            self.record_play(played_at, replay.as_ref());
+
+            // Playing the inputs again would carry on past where the stage was failed, so a failed
+            // stage can't be verified
+            let verification = self
+                .inputs
+                .as_ref()
+                .filter(|_| !stage_failed)
+                .map(|inputs| {
+                    verify(
+                        &self.chart,
+                        self.difficulty,
+                        &self.rules,
+                        inputs,
+                        self.core.results(),
+                    )
+                });
+
+            return StateTransition::Swap(Box::new(
+                ScoreScreen::new(
+                    ctx.renderer,
+                    self.song_name.clone(),
+                    self.difficulty,
+                    self.core.results().clone(),
+                    self.core.health().is_clear(),
+                    self.rng.fork(),
+                )
+                .with_modifiers(modifiers)
+                .with_rules(self.rules_preset)
+                .with_high_score(high_score, self.is_recorded())
+                .with_audio_skips(self.skip_detector.skips())
+                .with_verification(verification)
+                .with_stage_failed(stage_failed, ctx.renderer)
+                .with_replay(replay.map(|replay| ReplaySetup {
+                    song: self.song.clone(),
+                    song_data: self.song_data.clone(),
+                    replay,
+                })),
+            ));
         }
 
         self.note_judgement_text.update(ctx.renderer);
+        self.timing_meter.update(ctx.renderer);
         self.balloon_display.update(delta_time);
 
-        let time = self.note_time();
-        // Advance our position in the list of notes as far as we can go
-        while let Some(note) = self.notes.get(self.next_note_index) {
-            if note.is_hittable(time, self.timing_windows()) {
-                break;
+        let time = self.note_time() as f32;
+        if let Some(dancers) = self.dancers.as_mut() {
+            dancers.update(&self.chart, time, delta_time * self.rate, ctx.renderer);
+        }
+        // The end of the drumroll comes from the note as it's drawn
+        self.drumroll_display
+            .update(self.scroll_time(), delta_time, ctx.renderer);
+        self.effects.update(delta_time, ctx.renderer);
+
+        if let Some(time) = self
+            .density_strip
+            .as_ref()
+            .and_then(|strip| strip.clicked_time(ctx.mouse))
+        {
+            self.seek(time as f64, ctx.renderer);
+        }
+
+        if self.core.state() == PlayState::Playing {
+            self.resync_after_skips();
+        }
+
+        let health = self.core.health();
+        self.health_bar
+            .set_health(health.value(), health.threshold(), ctx.renderer);
+        self.health_bar.update(ctx.renderer);
+        self.score_display
+            .set_score(self.core.results().score(), ctx.renderer);
+
+        let combo = self.core.results().current_combo();
+        if self.combo_display.set_combo(combo, ctx.renderer) {
+            ctx.sounds.play(ctx.audio, SoundEffect::ComboMilestone);
+        }
+        self.combo_display.update(delta_time, ctx.renderer);
+
+        if self.hud.streamer_mode {
+            self.input_display.update(ctx.renderer);
+
+            if self.hud.streamer_readout {
+                self.stream_readout.set_values(
+                    self.core.results().current_combo(),
+                    self.core.results().accuracy(),
+                    ctx.renderer,
+                );
+            }
+        }
+
+        match self.core.state() {
+            PlayState::Playing => {
+                if ctx
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(KeyCode::Escape))
+                {
+                    self.pause(ctx.keyboard);
+                } else if ctx
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(BOOKMARK_KEY))
+                {
+                    self.set_bookmark();
+                } else if ctx
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(OFFSET_EARLIER_KEY))
+                {
+                    self.nudge_offset(false);
+                } else if ctx
+                    .keyboard
+                    .is_just_pressed(PhysicalKey::Code(OFFSET_LATER_KEY))
+                {
+                    self.nudge_offset(true);
+                } else if hold_to_retry(&mut self.retry_held, ctx.keyboard, delta_time) {
+                    self.restart(None, ctx.renderer);
+                } else if self.practice {
+                    if ctx
+                        .keyboard
+                        .is_just_pressed(PhysicalKey::Code(LOOP_START_KEY))
+                    {
+                        self.set_loop_start();
+                    } else if ctx
+                        .keyboard
+                        .is_just_pressed(PhysicalKey::Code(LOOP_END_KEY))
+                    {
+                        self.set_loop_end();
+                    } else if ctx
+                        .keyboard
+                        .is_just_pressed(PhysicalKey::Code(CLEAR_LOOP_KEY))
+                    {
+                        self.loop_start = None;
+                        self.loop_end = None;
+                    } else if let Some(strip) = self.density_strip.as_mut() {
+                        for (key, zoom_in) in
+                            [(WAVEFORM_ZOOM_OUT_KEY, false), (WAVEFORM_ZOOM_IN_KEY, true)]
+                        {
+                            if ctx.keyboard.is_just_pressed(PhysicalKey::Code(key)) {
+                                strip.zoom_waveform(zoom_in, ctx.renderer);
+                            }
+                        }
+                    }
+                }
             }
 
-            self.skip_next_note();
+            PlayState::Paused => match self.pause_menu_input(ctx.keyboard) {
+                Some(PauseOption::Resume) => self.start_countdown(ctx.keyboard),
+                Some(PauseOption::RetryFromBookmark) => {
+                    self.restart(self.bookmark, ctx.renderer);
+                    self.start_countdown(ctx.keyboard);
+                }
+                Some(PauseOption::Restart) => {
+                    self.restart(None, ctx.renderer);
+                    self.start_countdown(ctx.keyboard);
+                }
+                Some(PauseOption::Help) => ctx.help.request(),
+                Some(PauseOption::Quit) => {
+                    self.save_changed_settings();
+                    self.song_handle.stop(Default::default()).unwrap();
+                    ctx.textures.set_gameplay_active(false);
+                    return StateTransition::Pop;
+                }
+                _ => {}
+            },
+
+            PlayState::Countdown => {
+                if self.countdown_start.elapsed().as_secs_f32() >= RESUME_COUNTDOWN {
+                    self.resume(ctx.keyboard);
+                }
+            }
         }
 
-        if ctx.keyboard.is_pressed(PhysicalKey::Code(KeyCode::Escape)) {
-            self.song_handle.stop(Default::default()).unwrap();
-            StateTransition::Pop
-        } else {
-            StateTransition::Continue
+        StateTransition::Continue
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        match self.core.state() {
+            PlayState::Playing => {
+                if !self.chart_warnings.is_empty()
+                    && self.note_time() < CHART_WARNINGS_DURATION * self.rate as f64
+                {
+                    egui::Area::new("chart warnings".into())
+                        .anchor(egui::Align2::CENTER_TOP, [0.0, 20.0])
+                        .interactable(false)
+                        .show(&ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label(RichText::new("This chart has problems:").strong());
+
+                                for warning in self.chart_warnings.iter() {
+                                    ui.label(warning.to_string());
+                                }
+                            });
+                        });
+                }
+
+                let playback_label = if self.replay.is_some() {
+                    Some("Watching a replay")
+                } else if self.autoplay {
+                    Some("Autoplay")
+                } else {
+                    None
+                };
+
+                if let Some(label) = playback_label {
+                    egui::Area::new("playback banner".into())
+                        .anchor(egui::Align2::RIGHT_TOP, [-20.0, 150.0])
+                        .interactable(false)
+                        .show(&ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label(label);
+                            });
+                        });
+                }
+
+                if let Some((bookmark, bookmarked_at)) = self.bookmark.zip(self.bookmarked_at) {
+                    if bookmarked_at.elapsed().as_secs_f32() < BOOKMARK_MESSAGE_DURATION {
+                        egui::Area::new("bookmark message".into())
+                            .anchor(egui::Align2::RIGHT_TOP, [-20.0, 200.0])
+                            .interactable(false)
+                            .show(&ctx, |ui| {
+                                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                    ui.label(format!(
+                                        "Bookmarked the measure at {}",
+                                        clock_time(bookmark)
+                                    ));
+                                });
+                            });
+                    }
+                }
+
+                if let Some(changed_at) = self.chart_offset_changed_at {
+                    if changed_at.elapsed().as_secs_f32() < OFFSET_MESSAGE_DURATION {
+                        let offset = self.chart_overrides.offset.unwrap_or(0.0);
+
+                        egui::Area::new("chart offset message".into())
+                            .anchor(egui::Align2::RIGHT_TOP, [-20.0, 250.0])
+                            .interactable(false)
+                            .show(&ctx, |ui| {
+                                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                    ui.label(format!("Offset for this chart: {offset:+.0}ms"));
+                                });
+                            });
+                    }
+                }
+
+                if self.practice {
+                    let marker = |time: Option<f32>, key| {
+                        time.map_or_else(
+                            || format!("({} to set)", key_name(PhysicalKey::Code(key))),
+                            clock_time,
+                        )
+                    };
+
+                    egui::Area::new("loop markers".into())
+                        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -100.0])
+                        .interactable(false)
+                        .show(&ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label(format!(
+                                    "Loop from {} to {} ({} to clear)",
+                                    marker(self.loop_start, LOOP_START_KEY),
+                                    marker(self.loop_end, LOOP_END_KEY),
+                                    key_name(PhysicalKey::Code(CLEAR_LOOP_KEY)),
+                                ));
+                            });
+                        });
+                }
+
+                if let Some(held) = self.retry_held {
+                    egui::Area::new("retry progress".into())
+                        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                        .interactable(false)
+                        .show(&ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label("Restarting...");
+                                ui.add(
+                                    egui::ProgressBar::new(held / RETRY_HOLD_TIME)
+                                        .desired_width(200.0),
+                                );
+                            });
+                        });
+                }
+
+                if self
+                    .resynced_at
+                    .is_some_and(|at| at.elapsed().as_secs_f32() < RESYNC_MESSAGE_DURATION)
+                {
+                    egui::Area::new("resync message".into())
+                        .anchor(egui::Align2::RIGHT_TOP, [-20.0, 250.0])
+                        .interactable(false)
+                        .show(&ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label("The audio skipped, so the notes were resynced");
+                            });
+                        });
+                }
+            }
+
+            PlayState::Paused => {
+                egui::Area::new("pause menu".into())
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(&ctx, |ui| {
+                        ui.label(RichText::new("Paused").size(50.0));
+                        ui.add_space(30.0);
+
+                        let options = pause_options(self.bookmark.is_some());
+                        if let Some(option) = pause_menu_buttons(ui, &options, self.pause_selection)
+                        {
+                            self.clicked_pause_option = Some(option);
+                        }
+
+                        ui.add_space(30.0);
+                        self.quick_settings_ui(ui);
+                    });
+            }
+
+            PlayState::Countdown => {
+                let remaining = RESUME_COUNTDOWN - self.countdown_start.elapsed().as_secs_f32();
+
+                egui::Area::new("resume countdown".into())
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(&ctx, |ui| {
+                        ui.label(
+                            RichText::new(format!("{}", remaining.ceil().max(1.0))).size(80.0),
+                        );
+                    });
+            }
         }
     }
 
     fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
         // Update the positions of all the notes that are currently visible.
-        let time = self.note_time();
+        let time = self.scroll_time();
+        let song_time = self.song_time() as f32;
 
-        let on_screen_notes = self.notes.iter_mut().filter(|note| note.visible(time));
+        let note_range = self.note_window.range(time);
+        let barline_range = self.barline_window.range(time);
+        let ghost_range = self.ghost_window.range(time);
 
-        for note in on_screen_notes {
+        for note in self.notes[note_range.clone()]
+            .iter_mut()
+            .filter(|note| note.visible(time))
+        {
             note.update_position(ctx.renderer, time);
         }
 
-        let on_screen_barlines = self.barlines.iter_mut().filter(|barline| {
-            let pos = x_position_of_note(time, barline.time(), barline.scroll_speed());
-            (0.0..1920.0).contains(&pos)
-        });
-
-        for barline in on_screen_barlines {
+        for barline in self.barlines[barline_range.clone()]
+            .iter_mut()
+            .filter(|barline| barline.visible(time))
+        {
             barline.update_position(ctx.renderer, time);
         }
 
+        for ghost in self.ghost_notes[ghost_range.clone()]
+            .iter_mut()
+            .filter(|ghost| ghost.visible(time))
+        {
+            ghost.update_position(ctx.renderer, time);
+        }
+
         ctx.render(&self.background);
         ctx.render(&self.background_dim);
+
+        if let Some(dancers) = &self.dancers {
+            ctx.render(dancers);
+        }
+
         self.header.render(ctx);
+        ctx.render(&self.health_bar);
 
-        let notes = self.notes.iter().filter(|note| note.visible(time));
+        // Only the notes and barlines of the branch being played are shown
+        let core = &self.core;
+        let notes = self.notes[note_range.clone()]
+            .iter()
+            .zip(note_range)
+            .filter(|(note, i)| note.visible(time) && core.is_in_play(*i))
+            .map(|(note, _)| note);
 
-        let barlines = self.barlines.iter().filter(|barline| {
-            let pos = x_position_of_note(time, barline.time(), barline.scroll_speed());
-            // TODO: another hardcoded resolution to get rid of
-            (0.0..190.0).contains(&pos)
-        });
+        let barlines = self.barlines[barline_range.clone()]
+            .iter()
+            .zip(&self.barline_branches[barline_range])
+            .filter(|(barline, branch)| barline.visible(time) && core.is_on_played_branch(**branch))
+            .map(|(barline, _)| barline);
+
+        let ghosts = self.ghost_notes[ghost_range]
+            .iter()
+            .filter(|ghost| ghost.visible(time));
 
-        self.note_field.render(ctx, notes, barlines);
+        self.note_field.render(ctx, notes, barlines, ghosts);
+        ctx.render(&self.effects);
+        ctx.render(&self.score_display);
+        ctx.render(&self.combo_display);
         ctx.render(&self.note_judgement_text);
+
+        if self.hud.timing_meter {
+            ctx.render(&self.timing_meter);
+        }
         ctx.render(&self.balloon_display);
+        ctx.render(&self.drumroll_display);
+
+        if let Some(strip) = self.density_strip.as_mut() {
+            strip.set_time(song_time, ctx.renderer);
+            strip.set_loop(self.loop_start, self.loop_end, ctx.renderer);
+            ctx.render(&*strip);
+        }
+
+        if self.hud.streamer_mode {
+            ctx.render(&self.input_display);
+
+            if self.hud.streamer_readout {
+                ctx.render(&self.stream_readout);
+            }
+        }
+
+        // The pause menu is drawn on top of this
+        if self.core.state() == PlayState::Paused {
+            ctx.render(&self.pause_dim);
+        }
+    }
+
+    fn recreate_gpu_resources(&mut self, ctx: &mut Context) -> bool {
+        match self.rebuild_gpu_resources(ctx.renderer, ctx.textures) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("couldn't recreate taiko mode scene: {e}");
+                false
+            }
+        }
+    }
+
+    fn overlay_opened(&mut self, ctx: &mut Context) {
+        if self.core.state() != PlayState::Paused {
+            self.pause(ctx.keyboard);
+        }
+    }
+
+    fn focus_lost(&mut self, ctx: &mut Context) {
+        if self.core.state() != PlayState::Paused {
+            self.pause(ctx.keyboard);
+        }
+    }
+
+    fn difficulty(&self) -> Option<usize> {
+        Some(self.difficulty)
+    }
+
+    fn presence(&self) -> Presence {
+        Presence::Playing {
+            title: self.song_name.clone(),
+            difficulty: self.difficulty,
+            level: self.star_level,
+            progress: self.song_time() as f32 / self.song_length,
+        }
+    }
+
+    fn transition_style(&self) -> TransitionStyle {
+        // The song starts as soon as the scene is updated, so it waits until the old screen has
+        // completely gone
+        TransitionStyle::FadeToBlack
     }
 
     fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
         // We handle the note input keyboard events the moment they are received for extra accuracy
-        if let &WindowEvent::KeyboardInput { event, .. } = &event {
-            let mut note_index = self.next_note_index;
+        if let &WindowEvent::KeyboardInput {
+            event,
+            is_synthetic,
+            ..
+        } = &event
+        {
             let key = event.physical_key;
 
             // Keys have this annoying tendency to repeat presses when held down,
-            // so we gotta ensure it's not being held down.
-            let pressed = event.state == ElementState::Pressed && !ctx.keyboard.is_pressed(key);
-
-            if settings().key_is_don_or_kat(key) && pressed {
-                let time = self.note_time();
-                let timing_windows = self.timing_windows();
-
-                // We now have to go through all the notes starting from the next one, and see if
-                // any of them react to this keypress. If any of them react, or any of them are too
-                // far away to react, then we stop.
-                loop {
-                    // If there's no next note, we don't need to react.
-                    let Some(next_note) = self.notes.get_mut(note_index) else {
-                        break;
-                    };
+            // so we gotta ensure it's not being held down. Keys held while the window was
+            // unfocused only repeat (or get synthetic presses when it's focused again), so they
+            // don't count until they're pressed again for real.
+            let pressed = event.state == ElementState::Pressed
+                && !event.repeat
+                && !is_synthetic
+                && !ctx.keyboard.is_pressed(key);
+            let input = self.settings.game.key_mappings.drum_input(key);
 
-                    let reaction = next_note.receive_keypress(key, time, timing_windows);
-                    match reaction {
-                        // If it's the wrong colour, we'll keep checking to see if there's
-                        // a note of the right colour in scope.
-                        NoteKeypressReaction::WrongColour => {}
+            // The drum can't be played while inputs are being played back
+            if let Some(input) = input.filter(|_| pressed && self.playback.is_none()) {
+                // The core ignores the press if the game is paused or counting down to resume.
+                // The press is timed by when it arrived, not when it's being handled.
+                let time = self.note_time_at(ctx.event_time);
+                if self.core.state() == PlayState::Playing {
+                    if let Some(inputs) = self.inputs.as_mut() {
+                        inputs.push(TimedInput { time, input });
+                    }
+                }
 
-                        NoteKeypressReaction::TooEarly => {
-                            // Now we're only looking at notes that are unhittable, so stop here.
-                            break;
-                        }
-                        NoteKeypressReaction::Hit { offset } => {
-                            let judgement =
-                                NoteJudgement::from_offset(offset, self.timing_windows()).unwrap();
-                            self.note_judgement_text.display_judgement(judgement);
+                let events = self.core.press(input, time);
+                self.drum_feedback(input, &events, ctx);
+                self.apply_events(events, ctx.renderer);
+            }
+        }
+    }
+}
 
-                            self.results.push_judgement(Some(judgement));
-                            self.results.hit_errors.push(offset);
+/// How loud a song's audio should be played, going by its `SONGVOL`.
+pub(super) fn song_volume(song: &Song) -> f64 {
+    (song.song_volume.max(0.0) / 100.0) as f64
+}
 
-                            self.next_note_index = note_index + 1;
+/// The sound the drum makes when it's hit with the given input, going by what the hit did: popping
+/// a balloon, hitting a big note (or big drumroll), or anything else.
+pub(super) fn hit_sound(input: DrumInput, events: &[GameplayEvent]) -> SoundEffect {
+    let popped = events
+        .iter()
+        .any(|event| matches!(event, GameplayEvent::BalloonHit { hits_left: 0, .. }));
 
-                            // Ensure you only ever hit one note at a time
-                            break;
-                        }
-                        NoteKeypressReaction::Drumroll { .. } => {
-                            self.results.drumrolls += 1;
-                            break;
-                        }
-                        NoteKeypressReaction::BalloonRoll {
-                            hits_left,
-                            hit_target,
-                        } => {
-                            self.results.drumrolls += 1;
-                            self.balloon_display
-                                .hit(hits_left, hit_target, &mut ctx.renderer);
-
-                            if hits_left == 0 {
-                                self.next_note_index = note_index + 1;
-                            }
-                            break;
-                        }
-                        NoteKeypressReaction::TooLate => {
-                            self.skip_next_note();
-                        }
-                    }
+    if popped {
+        return SoundEffect::BalloonPop;
+    }
 
-                    note_index += 1;
-                }
-            }
-        }
+    let big = events.iter().any(|event| match event {
+        GameplayEvent::Hit { big, .. } => *big,
+        GameplayEvent::Drumroll { roll_note, .. } => roll_note.big,
+        _ => false,
+    });
+
+    match (input.is_don(), big) {
+        (true, false) => SoundEffect::Don,
+        (true, true) => SoundEffect::BigDon,
+        (false, false) => SoundEffect::Kat,
+        (false, true) => SoundEffect::BigKat,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::taiko_mode::gameplay::NoteJudgement;
+
+    #[test]
+    fn test_song_is_over() {
+        // The audio carries on long after the chart
+        assert!(!song_is_over(10.5, Some(10.0), false, 2.0));
+        assert!(song_is_over(12.0, Some(10.0), false, 2.0));
+
+        // The audio ends before the chart, which has to be played out silently
+        assert!(!song_is_over(9.0, Some(10.0), true, 2.0));
+        assert!(song_is_over(10.0, Some(10.0), true, 2.0));
+
+        assert!(!song_is_over(100.0, None, false, 2.0));
+        assert!(song_is_over(0.0, None, true, 2.0));
+    }
+
+    #[test]
+    fn test_is_recorded() {
+        assert!(is_recorded(false, false));
+        assert!(!is_recorded(true, false));
+
+        // Autoplay plays its inputs back like a replay, so its plays aren't recorded either
+        assert!(!is_recorded(false, true));
+    }
+
+    #[test]
+    fn test_hold_to_retry() {
+        let key = PhysicalKey::Code(RETRY_KEY);
+        let mut keyboard = KeyboardState::default();
+        let mut held = None;
+
+        // Tapping the key isn't enough
+        keyboard.set_pressed(key, true);
+        assert!(!hold_to_retry(&mut held, &keyboard, 0.1));
+        keyboard.end_frame();
+        keyboard.set_pressed(key, false);
+        assert!(!hold_to_retry(&mut held, &keyboard, 0.1));
+        assert_eq!(held, None);
+
+        // Holding it is, once
+        keyboard.end_frame();
+        keyboard.set_pressed(key, true);
+        assert!(!hold_to_retry(&mut held, &keyboard, 0.0));
+        keyboard.end_frame();
+        assert!(!hold_to_retry(&mut held, &keyboard, RETRY_HOLD_TIME / 2.0));
+        assert!(hold_to_retry(&mut held, &keyboard, RETRY_HOLD_TIME / 2.0));
+        assert!(!hold_to_retry(&mut held, &keyboard, RETRY_HOLD_TIME));
+        assert_eq!(held, None);
+    }
+
+    #[test]
+    fn test_pause_options() {
+        // Restarting is always there, even without a bookmark
+        assert_eq!(
+            pause_options(false),
+            [
+                PauseOption::Resume,
+                PauseOption::Restart,
+                PauseOption::Help,
+                PauseOption::Quit
+            ]
+        );
+        assert_eq!(
+            pause_options(true),
+            [
+                PauseOption::Resume,
+                PauseOption::Restart,
+                PauseOption::RetryFromBookmark,
+                PauseOption::Help,
+                PauseOption::Quit
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nudge_chart_offset() {
+        assert_eq!(nudge_chart_offset(None, true), Some(5.0));
+        assert_eq!(nudge_chart_offset(None, false), Some(-5.0));
+        assert_eq!(nudge_chart_offset(Some(12.0), true), Some(15.0));
+
+        // Getting back to 0 clears the offset
+        assert_eq!(nudge_chart_offset(Some(5.0), false), None);
+
+        // It can't go past the limit either way
+        assert_eq!(
+            nudge_chart_offset(Some(MAX_CHART_OFFSET), true),
+            Some(MAX_CHART_OFFSET)
+        );
+        assert_eq!(
+            nudge_chart_offset(Some(-MAX_CHART_OFFSET), false),
+            Some(-MAX_CHART_OFFSET)
+        );
+    }
+
+    #[test]
+    fn test_hit_sound() {
+        let hit = |big| GameplayEvent::Hit {
+            note: 0,
+            judgement: NoteJudgement::Good,
+            offset: 0.0,
+            big,
+        };
+
+        // Hitting nothing still makes a sound
+        assert_eq!(hit_sound(DrumInput::LeftDon, &[]), SoundEffect::Don);
+        assert_eq!(hit_sound(DrumInput::RightKat, &[]), SoundEffect::Kat);
+        assert_eq!(
+            hit_sound(DrumInput::LeftDon, &[hit(false)]),
+            SoundEffect::Don
+        );
+        assert_eq!(
+            hit_sound(DrumInput::LeftDon, &[hit(true)]),
+            SoundEffect::BigDon
+        );
+        assert_eq!(
+            hit_sound(DrumInput::LeftKat, &[hit(true)]),
+            SoundEffect::BigKat
+        );
+
+        let roll = GameplayEvent::Drumroll {
+            note: 0,
+            roll_note: BasicNoteType {
+                colour: NoteColour::Kat,
+                big: true,
+            },
+            hits: 1,
+        };
+        assert_eq!(hit_sound(DrumInput::RightKat, &[roll]), SoundEffect::BigKat);
+
+        let balloon = |hits_left| GameplayEvent::BalloonHit {
+            note: 0,
+            hits_left,
+            hit_target: 5,
+        };
+        assert_eq!(
+            hit_sound(DrumInput::RightDon, &[balloon(2)]),
+            SoundEffect::Don
+        );
+        assert_eq!(
+            hit_sound(DrumInput::RightDon, &[balloon(0)]),
+            SoundEffect::BalloonPop
+        );
     }
 }
@@ -0,0 +1,174 @@
+//! How many points hitting a don or kat note is worth.
+//!
+//! There are two ways of scoring notes (see [NoteScoring]), which come from different generations
+//! of the arcade game:
+//!
+//! - Shin-uchi scoring (AC16 onwards) splits [SHIN_UCHI_TOTAL] points evenly between every note
+//!   of the chart, so a full combo of goods always scores the same however long the chart is. The
+//!   combo, big notes and go-go time make no difference.
+//! - Combo scoring (AC15 and earlier) gives each note a base score for its difficulty, plus a bonus
+//!   that grows as the combo passes each of [COMBO_STEPS]. Big notes are worth twice as much, and
+//!   notes in go-go time are worth 1.2 times as much.
+//!
+//! Either way, an ok is worth half a good and a bad is worth nothing. Scores are always rounded down
+//! to a multiple of 10, like in the arcade game. Drumrolls and balloons are scored separately, by
+//! the points in [RulesConfig](crate::settings::RulesConfig).
+use super::gameplay::{NoteJudgement, ScoreInt};
+use crate::settings::NoteScoring;
+
+/// What a full combo of goods is worth with shin-uchi scoring, not counting drumrolls and balloons.
+pub const SHIN_UCHI_TOTAL: ScoreInt = 1_000_000;
+
+/// The combos at which notes start being worth more with combo scoring, and how many times the
+/// difficulty's combo bonus (see [COMBO_BASE_SCORES]) is added to each note from then on.
+pub const COMBO_STEPS: [(usize, ScoreInt); 4] = [(10, 1), (30, 2), (50, 4), (100, 8)];
+
+/// The base score of a good and the combo bonus for each difficulty with combo scoring. These are
+/// roughly what charts of each difficulty were given in AC15, where each chart set its own.
+const COMBO_BASE_SCORES: [(ScoreInt, ScoreInt); 5] =
+    [(400, 100), (500, 120), (600, 150), (700, 180), (700, 180)];
+
+fn round_down(score: ScoreInt) -> ScoreInt {
+    score / 10 * 10
+}
+
+/// Works out the points for each hit on a note of a chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteScorer {
+    scoring: NoteScoring,
+    /// The points for a good, before any combo bonus or multipliers
+    base: ScoreInt,
+    /// The points added to a note for each combo step reached
+    combo_bonus: ScoreInt,
+}
+
+impl NoteScorer {
+    /// Creates a scorer for a chart of the given difficulty with `note_count` don and kat notes.
+    pub fn new(scoring: NoteScoring, difficulty: usize, note_count: usize) -> Self {
+        match scoring {
+            NoteScoring::ShinUchi => {
+                let base = if note_count == 0 {
+                    0
+                } else {
+                    // Rounded up, so that a full combo doesn't fall short of the total
+                    SHIN_UCHI_TOTAL.div_ceil(note_count as ScoreInt * 10) * 10
+                };
+
+                Self {
+                    scoring,
+                    base,
+                    combo_bonus: 0,
+                }
+            }
+
+            NoteScoring::Combo => {
+                let (base, combo_bonus) = COMBO_BASE_SCORES
+                    .get(difficulty)
+                    .copied()
+                    .unwrap_or(COMBO_BASE_SCORES[3]);
+
+                Self {
+                    scoring,
+                    base,
+                    combo_bonus,
+                }
+            }
+        }
+    }
+
+    /// The points for hitting a note. `combo` is the combo including this hit.
+    pub fn score(&self, judgement: NoteJudgement, big: bool, gogo: bool, combo: usize) -> ScoreInt {
+        let good = match self.scoring {
+            NoteScoring::ShinUchi => self.base,
+            NoteScoring::Combo => self.base + self.combo_bonus * combo_steps(combo),
+        };
+
+        let mut score = match judgement {
+            NoteJudgement::Good => good,
+            NoteJudgement::Ok => round_down(good / 2),
+            NoteJudgement::Bad => return 0,
+        };
+
+        if self.scoring == NoteScoring::Combo {
+            if big {
+                score *= 2;
+            }
+
+            if gogo {
+                score = round_down(score * 6 / 5);
+            }
+        }
+
+        score
+    }
+}
+
+/// How many times the combo bonus is added to a note hit at the given combo.
+fn combo_steps(combo: usize) -> ScoreInt {
+    COMBO_STEPS
+        .iter()
+        .rev()
+        .find(|(reached, _)| combo >= *reached)
+        .map_or(0, |(_, steps)| *steps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shin_uchi() {
+        let scorer = NoteScorer::new(NoteScoring::ShinUchi, 3, 300);
+
+        // 1,000,000 / 300 is 3333.3, rounded up to 3340
+        assert_eq!(scorer.score(NoteJudgement::Good, false, false, 1), 3340);
+        assert_eq!(scorer.score(NoteJudgement::Ok, false, false, 1), 1670);
+        assert_eq!(scorer.score(NoteJudgement::Bad, false, false, 1), 0);
+
+        // Nothing else makes a difference
+        assert_eq!(scorer.score(NoteJudgement::Good, true, true, 250), 3340);
+
+        // A full combo is worth at least the total
+        let full_combo: ScoreInt = (1..=300)
+            .map(|combo| scorer.score(NoteJudgement::Good, false, false, combo))
+            .sum();
+        assert!(full_combo >= SHIN_UCHI_TOTAL);
+
+        let empty = NoteScorer::new(NoteScoring::ShinUchi, 3, 0);
+        assert_eq!(empty.score(NoteJudgement::Good, false, false, 1), 0);
+    }
+
+    #[test]
+    fn test_combo_steps() {
+        assert_eq!(combo_steps(0), 0);
+        assert_eq!(combo_steps(9), 0);
+        assert_eq!(combo_steps(10), 1);
+        assert_eq!(combo_steps(29), 1);
+        assert_eq!(combo_steps(30), 2);
+        assert_eq!(combo_steps(50), 4);
+        assert_eq!(combo_steps(99), 4);
+        assert_eq!(combo_steps(100), 8);
+        assert_eq!(combo_steps(1000), 8);
+    }
+
+    #[test]
+    fn test_combo_scoring() {
+        let scorer = NoteScorer::new(NoteScoring::Combo, 3, 300);
+
+        assert_eq!(scorer.score(NoteJudgement::Good, false, false, 1), 700);
+        assert_eq!(
+            scorer.score(NoteJudgement::Good, false, false, 30),
+            700 + 2 * 180
+        );
+        assert_eq!(scorer.score(NoteJudgement::Ok, false, false, 30), 530);
+        assert_eq!(scorer.score(NoteJudgement::Good, true, false, 1), 1400);
+        assert_eq!(scorer.score(NoteJudgement::Good, false, true, 1), 840);
+        // Big notes in go-go time get both, and oks are halved before either
+        assert_eq!(scorer.score(NoteJudgement::Ok, true, true, 100), 2560);
+        assert_eq!(scorer.score(NoteJudgement::Bad, true, true, 100), 0);
+
+        // Easier difficulties are worth less
+        let easy = NoteScorer::new(NoteScoring::Combo, 0, 300);
+        assert_eq!(easy.score(NoteJudgement::Good, false, false, 10), 500);
+    }
+}
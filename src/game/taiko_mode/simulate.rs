@@ -0,0 +1,478 @@
+//! Playing a chart without the game: feeding a list of inputs through the rules and seeing what
+//! result they get.
+//!
+//! [simulate] is the reference for what a set of inputs scores on a chart, so anything that checks
+//! or replays a play should go through it rather than judging notes some other way. It uses exactly
+//! the same [GameplayCore] as the gameplay scene, and doesn't depend on frame timing, so the same
+//! inputs always give the same result. Changing what it returns for a given chart and inputs
+//! changes the score of every recorded play, so it should be treated as a breaking change.
+use serde::{Deserialize, Serialize};
+
+use crate::notechart_parser::{Branch, NoteChart, NoteType};
+use crate::settings::{DrumInput, RulesConfig};
+
+use super::gameplay::{is_playable, timing_windows, GameplayCore, PlayResult};
+use super::note::BAD;
+
+/// How many times a second autoplay hits drumrolls.
+const AUTOPLAY_ROLL_RATE: f64 = 20.0;
+
+/// A drum input, and when it was made in seconds (in the same time as the notes of the chart).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimedInput {
+    pub time: f64,
+    pub input: DrumInput,
+}
+
+/// Plays through a chart with the given inputs and rules, returning the result.
+///
+/// The inputs don't have to be in order: they're sorted by time first, and inputs at the same time
+/// are judged in the order they were given. Every note left after the last input is missed.
+pub fn simulate(
+    chart: &NoteChart,
+    difficulty: usize,
+    rules: &RulesConfig,
+    inputs: &[TimedInput],
+) -> PlayResult {
+    let mut inputs = inputs.to_vec();
+    inputs.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut core = GameplayCore::new(chart, difficulty).with_rules(*rules);
+
+    for TimedInput { time, input } in inputs {
+        // In the game, time passes between inputs and misses notes along the way
+        core.advance(time);
+        core.press(input, time);
+    }
+
+    core.advance(f64::INFINITY);
+    core.results().clone()
+}
+
+/// Whether a play's result is what its inputs actually score.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    Verified,
+    /// Playing the inputs again gave a different result. Lists which parts of the result differ
+    /// (see [PlayResult::differences]).
+    Mismatch(Vec<&'static str>),
+}
+
+/// Plays the inputs again through [simulate] and checks that they give the `claimed` result. The
+/// rules should be the ones the play was scored by.
+///
+/// A mismatch means either the result or the inputs have been changed since the play, or the rules
+/// have changed in a way that changes how the play is judged.
+pub fn verify(
+    chart: &NoteChart,
+    difficulty: usize,
+    rules: &RulesConfig,
+    inputs: &[TimedInput],
+    claimed: &PlayResult,
+) -> Verification {
+    let differences = claimed.differences(&simulate(chart, difficulty, rules, inputs));
+
+    if differences.is_empty() {
+        Verification::Verified
+    } else {
+        Verification::Mismatch(differences)
+    }
+}
+
+/// The inputs of a perfect player: every don and kat hit exactly on time, every balloon popped and
+/// every drumroll hit [AUTOPLAY_ROLL_RATE] times a second.
+///
+/// Drumroll hits that would land close enough to a note to hit it are left out, so that the roll
+/// doesn't take a hit meant for the note (or the other way around).
+///
+/// In charts with branches, the notes of whichever branch playing perfectly leads to are played
+/// (see [autoplay_branches]).
+pub fn autoplay_inputs(chart: &NoteChart, difficulty: usize) -> Vec<TimedInput> {
+    let branches = autoplay_branches(chart, difficulty);
+    perfect_inputs(&chart.on_branches(|section| branches[section]), difficulty)
+}
+
+/// The branch a perfect player ends up on in each of a chart's branched sections. Each branch
+/// depends on how the ones before it were played, so they're worked out one at a time, by playing
+/// up to each decision.
+fn autoplay_branches(chart: &NoteChart, difficulty: usize) -> Vec<Branch> {
+    let mut branches: Vec<Branch> = Vec::new();
+
+    for (index, section) in chart.branch_sections.iter().enumerate() {
+        let decision_time = section.decision_time;
+        // The sections after this one don't matter, as nothing in them is played before the
+        // decision
+        let played =
+            chart.on_branches(|section| branches.get(section).copied().unwrap_or_default());
+
+        let mut core = GameplayCore::new(chart, difficulty);
+        let inputs = perfect_inputs(&played, difficulty);
+
+        for TimedInput { time, input } in inputs.into_iter().filter(|i| i.time < decision_time) {
+            core.advance(time);
+            core.press(input, time);
+        }
+
+        core.advance(decision_time);
+        branches.push(
+            Branch::ALL
+                .into_iter()
+                .find(|&branch| core.is_on_played_branch(Some((index, branch))))
+                .unwrap(),
+        );
+    }
+
+    branches
+}
+
+/// The inputs of a perfect player (see [autoplay_inputs]) on a chart with no branches.
+fn perfect_inputs(chart: &NoteChart, difficulty: usize) -> Vec<TimedInput> {
+    let bad_window = timing_windows(difficulty)[BAD];
+    let notes = chart
+        .notes
+        .iter()
+        .filter(|note| is_playable(note.note_type))
+        .collect::<Vec<_>>();
+
+    let near_note = |time: f64| {
+        notes
+            .iter()
+            .any(|note| !note.note_type.is_roll() && (note.time - time).abs() < bad_window)
+    };
+
+    let mut inputs = Vec::new();
+
+    for note in notes.iter() {
+        let time = note.time;
+
+        match note.note_type {
+            NoteType::Roll(duration) | NoteType::BigRoll(duration) => {
+                let hits = (duration as f64 * AUTOPLAY_ROLL_RATE).ceil() as usize;
+
+                inputs.extend(
+                    (0..hits)
+                        .map(|i| time + i as f64 / AUTOPLAY_ROLL_RATE)
+                        .filter(|&time| !near_note(time))
+                        .map(|time| TimedInput {
+                            time,
+                            input: DrumInput::LeftDon,
+                        }),
+                );
+            }
+
+            NoteType::BalloonRoll(duration, hit_target) => {
+                // Spread the hits out over the balloon, but never past the end of it
+                let gap =
+                    (duration as f64 / hit_target.max(1) as f64).min(1.0 / AUTOPLAY_ROLL_RATE);
+
+                inputs.extend(
+                    (0..hit_target)
+                        .map(|i| time + i as f64 * gap)
+                        .filter(|&time| !near_note(time))
+                        .map(|time| TimedInput {
+                            time,
+                            input: DrumInput::LeftDon,
+                        }),
+                );
+            }
+
+            note_type => inputs.push(TimedInput {
+                time,
+                input: if note_type.is_don() {
+                    DrumInput::LeftDon
+                } else {
+                    DrumInput::LeftKat
+                },
+            }),
+        }
+    }
+
+    inputs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::{
+        decode_chart, parse_tja_file_with_options, Note, ParseOptions, ScrollMode,
+    };
+    use crate::rng::Rng;
+
+    fn note(note_type: NoteType, time: f64) -> Note {
+        Note {
+            note_type,
+            time,
+            scroll_speed: 1.0,
+            vertical_speed: 0.0,
+        }
+    }
+
+    /// How many don and kat notes there are on the branches autoplay ends up on.
+    fn don_and_kat_count(chart: &NoteChart, difficulty: usize) -> usize {
+        let branches = autoplay_branches(chart, difficulty);
+
+        chart
+            .on_branches(|section| branches[section])
+            .notes
+            .iter()
+            .filter(|note| note.note_type.is_don() || note.note_type.is_kat())
+            .count()
+    }
+
+    /// Checks that autoplay gets every note in a chart on time.
+    fn assert_autoplay_is_perfect(chart: &NoteChart, difficulty: usize, name: &str) {
+        let result = simulate(
+            chart,
+            difficulty,
+            &RulesConfig::default(),
+            &autoplay_inputs(chart, difficulty),
+        );
+
+        assert_eq!(result.misses(), 0, "{name}");
+        assert_eq!(
+            result.goods(),
+            don_and_kat_count(chart, difficulty),
+            "{name}"
+        );
+    }
+
+    #[test]
+    fn test_simulate_matches_the_inputs() {
+        let chart = NoteChart {
+            notes: vec![
+                note(NoteType::Don, 1.0),
+                note(NoteType::Kat, 2.0),
+                note(NoteType::Don, 3.0),
+                note(NoteType::BalloonRoll(1.0, 2), 4.0),
+            ],
+            barlines: vec![],
+            gogo_regions: vec![],
+            branch_sections: vec![],
+            bpm_changes: vec![],
+            delays: vec![],
+            scroll_mode: ScrollMode::Normal,
+        };
+
+        let inputs = [
+            // Out of order, to check they get sorted
+            TimedInput {
+                time: 2.05,
+                input: DrumInput::RightKat,
+            },
+            TimedInput {
+                time: 1.0,
+                input: DrumInput::LeftDon,
+            },
+            TimedInput {
+                time: 4.5,
+                input: DrumInput::LeftDon,
+            },
+        ];
+
+        let result = simulate(&chart, 3, &RulesConfig::default(), &inputs);
+        assert_eq!(result.goods(), 1);
+        assert_eq!(result.okays(), 1);
+        assert_eq!(result.misses(), 1);
+        assert_eq!(result.drumrolls(), 1);
+
+        // The same inputs always give the same result
+        let again = simulate(&chart, 3, &RulesConfig::default(), &inputs);
+        assert_eq!(again.score(), result.score());
+        assert_eq!(again.rolls(), result.rolls());
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut rng = Rng::new(2501);
+        let notes = (0..40)
+            .map(|i| {
+                let note_type = match i % 10 {
+                    4 => NoteType::Roll(0.5),
+                    9 => NoteType::BalloonRoll(0.5, 5),
+                    _ => *rng.choose(&[NoteType::Don, NoteType::Kat]).unwrap(),
+                };
+                note(note_type, 1.0 + i as f64 * 0.75)
+            })
+            .collect();
+        let chart = NoteChart {
+            notes,
+            barlines: vec![],
+            gogo_regions: vec![],
+            branch_sections: vec![],
+            bpm_changes: vec![],
+            delays: vec![],
+            scroll_mode: ScrollMode::Normal,
+        };
+
+        // A sloppy player, who hits with the wrong hand, early and late, and sometimes not at all
+        let mut inputs = Vec::new();
+        for input in autoplay_inputs(&chart, 3) {
+            if rng.range(0.0, 1.0) < 0.125 {
+                continue;
+            }
+
+            inputs.push(TimedInput {
+                time: input.time + rng.range(-0.12, 0.12) as f64,
+                input: *rng
+                    .choose(&[
+                        DrumInput::LeftDon,
+                        DrumInput::RightDon,
+                        DrumInput::LeftKat,
+                        DrumInput::RightKat,
+                    ])
+                    .unwrap(),
+            });
+        }
+
+        // The game judges inputs as they come in between frames, rather than all at once
+        let mut inputs_by_time = inputs.clone();
+        inputs_by_time.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let mut core = GameplayCore::new(&chart, 3);
+        let mut pending = inputs_by_time.iter().peekable();
+        let mut frame_time = 0.0;
+
+        while frame_time < 40.0 {
+            while let Some(input) = pending.next_if(|input| input.time < frame_time) {
+                core.press(input.input, input.time);
+            }
+
+            core.advance(frame_time);
+            frame_time += 1.0 / 60.0;
+        }
+
+        core.advance(f64::INFINITY);
+        let played = core.results().clone();
+        let rules = RulesConfig::default();
+
+        assert_eq!(
+            verify(&chart, 3, &rules, &inputs, &played),
+            Verification::Verified
+        );
+
+        // A result the inputs don't add up to isn't verified
+        assert!(matches!(
+            verify(&chart, 3, &rules, &[], &played),
+            Verification::Mismatch(_)
+        ));
+
+        // As does playing a different difficulty, with different timing windows
+        let Verification::Mismatch(differences) = verify(&chart, 0, &rules, &inputs, &played)
+        else {
+            panic!("a different difficulty gave the same result");
+        };
+        assert!(differences.contains(&"judgements"));
+
+        // And scoring the rolls by different rules
+        assert_eq!(
+            verify(&chart, 3, &RulesConfig::CLASSIC, &inputs, &played),
+            Verification::Mismatch(vec!["score"])
+        );
+    }
+
+    #[test]
+    fn test_autoplay_follows_branches() {
+        let song = crate::notechart_parser::parse_tja_file(
+            "TITLE:Branches\nBPM:120\nWAVE:song.ogg\nBALLOON:4\nCOURSE:Oni\nLEVEL:5\n#START\n\
+             1111,\n1111,\n#BRANCHSTART p,60,90\n#N\n1,\n#E\n2,\n#M\n3,\n#BRANCHEND\n\
+             7008,\n1,\n#BRANCHSTART r,10,40\n#N\n1,\n#E\n2,\n#M\n3,\n#END\n",
+        )
+        .unwrap();
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+        // Perfect accuracy is enough for master, but four balloon hits aren't enough drumrolls
+        // for more than normal
+        assert_eq!(
+            autoplay_branches(chart, 3),
+            vec![Branch::Master, Branch::Normal]
+        );
+        assert_autoplay_is_perfect(chart, 3, "branches");
+    }
+
+    #[test]
+    fn test_autoplay_on_fixture_charts() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tja");
+
+        for path in std::fs::read_dir(fixtures).unwrap() {
+            let path = path.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "tja") {
+                continue;
+            }
+
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let contents = decode_chart(&std::fs::read(&path).unwrap());
+
+            let options = ParseOptions {
+                lenient: name.starts_with("lenient_"),
+                ..Default::default()
+            };
+
+            let Ok(song) = parse_tja_file_with_options(&contents, &options) else {
+                continue;
+            };
+
+            for (difficulty, course) in song.difficulties.iter().enumerate() {
+                let Some(course) = course else {
+                    continue;
+                };
+
+                // A negative delay can put notes before ones that came earlier in the chart. The
+                // game goes through notes in chart order, so those can't be hit by anyone.
+                if !course.chart.notes.is_sorted_by(|a, b| a.time <= b.time) {
+                    continue;
+                }
+
+                assert_autoplay_is_perfect(&course.chart, difficulty, &name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_autoplay_on_random_charts() {
+        let mut rng = Rng::new(2492);
+        let note_types = [
+            NoteType::Don,
+            NoteType::Kat,
+            NoteType::BigDon,
+            NoteType::BigKat,
+            NoteType::Roll(0.0),
+            NoteType::BalloonRoll(0.0, 0),
+        ];
+
+        for _ in 0..200 {
+            let mut notes = Vec::new();
+            let mut time = rng.range(0.0, 2.0);
+
+            for _ in 0..50 {
+                let note_type = match *rng.choose(&note_types).unwrap() {
+                    NoteType::Roll(_) => NoteType::Roll(rng.range(0.1, 2.0)),
+                    NoteType::BalloonRoll(..) => {
+                        NoteType::BalloonRoll(rng.range(0.2, 2.0), (rng.next_u64() % 20) as u32 + 1)
+                    }
+                    note_type => note_type,
+                };
+
+                notes.push(note(note_type, time as f64));
+
+                // Notes can be as close as 1/16ths at 240 BPM, and rolls end before the next note
+                time += match note_type {
+                    NoteType::Roll(duration) | NoteType::BalloonRoll(duration, _) => duration,
+                    _ => 0.0,
+                } + rng.range(0.0625, 1.0);
+            }
+
+            let chart = NoteChart {
+                notes,
+                barlines: vec![],
+                gogo_regions: vec![],
+                branch_sections: vec![],
+                bpm_changes: vec![],
+                delays: vec![],
+                scroll_mode: ScrollMode::Normal,
+            };
+
+            for difficulty in 0..5 {
+                assert_autoplay_is_perfect(&chart, difficulty, &format!("{chart:?}"));
+            }
+        }
+    }
+}
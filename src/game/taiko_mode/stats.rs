@@ -0,0 +1,512 @@
+//! Running statistics computed over the course of a song.
+use crate::settings::DrumInput;
+
+/// An exponentially weighted moving average.
+///
+/// Each new sample moves the average towards it by a fixed fraction (the smoothing factor), so
+/// recent samples count for more than older ones and the average slowly follows any drift.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialAverage {
+    smoothing: f32,
+    value: Option<f32>,
+}
+
+impl ExponentialAverage {
+    /// Creates a new average with the given smoothing factor, which should be between 0 and 1.
+    /// Smaller values make the average move more slowly.
+    pub fn new(smoothing: f32) -> Self {
+        Self {
+            smoothing: smoothing.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Adds a new sample to the average. The first sample becomes the average outright.
+    pub fn push(&mut self, sample: f32) {
+        self.value = Some(match self.value {
+            Some(value) => value + (sample - value) * self.smoothing,
+            None => sample,
+        });
+    }
+
+    /// Returns the current average, or None if there haven't been any samples yet.
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+}
+
+/// Only this many consecutive hits are considered when working out the best speed of a roll, so
+/// that a short burst of fast hits counts even if the rest of the roll was slower.
+const BEST_WINDOW_HITS: usize = 8;
+
+/// Rolls with fewer hits than this are too short to give a meaningful best speed.
+const MIN_RATED_HITS: usize = 3;
+
+/// A hit on a don or kat note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitRecord {
+    /// When the note should have been hit, in seconds.
+    pub time: f64,
+    /// The difference between when the note was hit and when it should have been hit, in seconds.
+    /// Negative offsets are early.
+    pub offset: f32,
+    /// The input the note was hit with.
+    pub input: DrumInput,
+}
+
+/// A record of every hit on a single drumroll or balloon.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RollRecord {
+    /// When the roll starts, in seconds.
+    pub start_time: f64,
+    /// When each hit on the roll happened, in order.
+    pub hit_times: Vec<f64>,
+    /// The input each hit was made with, in the same order as `hit_times`.
+    pub hit_inputs: Vec<DrumInput>,
+}
+
+impl RollRecord {
+    pub fn new(start_time: f64) -> Self {
+        Self {
+            start_time,
+            hit_times: Vec::new(),
+            hit_inputs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, time: f64, input: DrumInput) {
+        self.hit_times.push(time);
+        self.hit_inputs.push(input);
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hit_times.len()
+    }
+
+    /// The time between the first and last hits.
+    fn duration(&self) -> f64 {
+        match (self.hit_times.first(), self.hit_times.last()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0.0,
+        }
+    }
+
+    /// How many hits per second the roll was hit at, or None if the roll wasn't hit enough times
+    /// to tell.
+    pub fn hits_per_second(&self) -> Option<f32> {
+        let duration = self.duration();
+        (self.hits() >= 2 && duration > 0.0).then(|| ((self.hits() - 1) as f64 / duration) as f32)
+    }
+
+    /// The fastest speed the roll was hit at over any [BEST_WINDOW_HITS] consecutive hits, in hits
+    /// per second.
+    fn best_window_rate(&self) -> Option<f32> {
+        best_window_rate(&self.hit_times)
+    }
+
+    /// The fastest speed the roll was hit at with any one input on its own, in hits per second.
+    fn best_single_input_rate(&self) -> Option<f32> {
+        [
+            DrumInput::LeftDon,
+            DrumInput::RightDon,
+            DrumInput::LeftKat,
+            DrumInput::RightKat,
+        ]
+        .into_iter()
+        .filter_map(|input| {
+            let times = self
+                .hit_times
+                .iter()
+                .zip(self.hit_inputs.iter())
+                .filter(|(_, &hit_input)| hit_input == input)
+                .map(|(&time, _)| time)
+                .collect::<Vec<_>>();
+
+            best_window_rate(&times)
+        })
+        .reduce(f32::max)
+    }
+}
+
+/// The fastest rate of any [BEST_WINDOW_HITS] consecutive hits out of the given hit times, in hits
+/// per second.
+fn best_window_rate(hit_times: &[f64]) -> Option<f32> {
+    if hit_times.len() < MIN_RATED_HITS {
+        return None;
+    }
+
+    let window = hit_times.len().min(BEST_WINDOW_HITS);
+    hit_times
+        .windows(window)
+        .map(|hits| hits[window - 1] - hits[0])
+        .filter(|&duration| duration > 0.0)
+        .map(|duration| ((window - 1) as f64 / duration) as f32)
+        .reduce(f32::max)
+}
+
+/// The average speed over every roll, in hits per second.
+///
+/// This is the total number of hits (after the first hit of each roll) divided by the total time
+/// spent rolling, so longer rolls count for more than short ones.
+pub fn mean_roll_rate(rolls: &[RollRecord]) -> Option<f32> {
+    let (intervals, duration) = rolls
+        .iter()
+        .filter(|roll| roll.hits() >= 2)
+        .fold((0, 0.0), |(intervals, duration), roll| {
+            (intervals + roll.hits() - 1, duration + roll.duration())
+        });
+
+    (duration > 0.0).then(|| (intervals as f64 / duration) as f32)
+}
+
+/// The fastest speed any roll was hit at, in hits per second.
+pub fn best_roll_rate(rolls: &[RollRecord]) -> Option<f32> {
+    rolls
+        .iter()
+        .filter_map(RollRecord::best_window_rate)
+        .reduce(f32::max)
+}
+
+/// The fastest any roll was hit with a single input, in hits per second.
+///
+/// Nobody can hit one key nearly as fast as they can roll with both hands, so a very high rate here
+/// means a key was probably stuck or being pressed by a macro.
+pub fn best_single_input_roll_rate(rolls: &[RollRecord]) -> Option<f32> {
+    rolls
+        .iter()
+        .filter_map(RollRecord::best_single_input_rate)
+        .reduce(f32::max)
+}
+
+/// How the player shared out their hits between their hands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HandStats {
+    /// The percentage of hits that were made with the other hand to the hit before them.
+    pub alternation: f32,
+    /// The most hits in a row that were made with the same hand.
+    pub longest_streak: usize,
+}
+
+/// Works out how the given hits were shared between hands, or None if there were too few hits to
+/// tell.
+pub fn hand_stats(inputs: impl IntoIterator<Item = DrumInput>) -> Option<HandStats> {
+    let mut hits = 0;
+    let mut switches = 0;
+    let mut streak = 0;
+    let mut longest_streak = 0;
+    let mut last_hand = None;
+
+    for input in inputs {
+        let hand = input.is_left();
+        hits += 1;
+
+        if last_hand == Some(hand) {
+            streak += 1;
+        } else {
+            if last_hand.is_some() {
+                switches += 1;
+            }
+            streak = 1;
+        }
+
+        longest_streak = longest_streak.max(streak);
+        last_hand = Some(hand);
+    }
+
+    (hits >= 2).then(|| HandStats {
+        alternation: switches as f32 / (hits - 1) as f32 * 100.0,
+        longest_streak,
+    })
+}
+
+/// Samples further than this many (scaled) median absolute deviations from the median are thrown
+/// away as outliers by [robust_median].
+const OUTLIER_DEVIATIONS: f64 = 3.0;
+
+/// Scales the median absolute deviation so that it's comparable to a standard deviation, for
+/// normally distributed samples.
+const MAD_SCALE: f64 = 1.4826;
+
+/// How early or late the player hit notes over a play, and how consistently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitErrorStats {
+    /// The mean offset of the hits, in milliseconds. Negative is early.
+    pub mean: f32,
+    /// The median offset of the hits, in milliseconds.
+    pub median: f32,
+    /// Ten times the standard deviation of the offsets in milliseconds, as it's measured in osu!.
+    /// The lower it is, the more consistent the timing was.
+    pub unstable_rate: f32,
+}
+
+/// Works out the timing statistics of a play's hits, or None if nothing was hit.
+pub fn hit_error_stats(hits: &[HitRecord]) -> Option<HitErrorStats> {
+    let mut offsets = hits
+        .iter()
+        .map(|hit| hit.offset as f64 * 1000.0)
+        .collect::<Vec<_>>();
+    offsets.sort_by(f64::total_cmp);
+
+    if offsets.is_empty() {
+        return None;
+    }
+
+    let count = offsets.len() as f64;
+    let mean = offsets.iter().sum::<f64>() / count;
+    let variance = offsets.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count;
+
+    Some(HitErrorStats {
+        mean: mean as f32,
+        median: median(&offsets) as f32,
+        unstable_rate: (variance.sqrt() * 10.0) as f32,
+    })
+}
+
+/// The middle of a set of samples, found in a way that isn't thrown off by a few bad ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RobustMedian {
+    /// The median of the samples that weren't outliers.
+    pub median: f64,
+    /// How spread out the samples that weren't outliers are, as a scaled median absolute
+    /// deviation (which is about the same as the standard deviation).
+    pub spread: f64,
+    /// How many samples were used.
+    pub kept: usize,
+    /// How many samples were thrown away as outliers.
+    pub rejected: usize,
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let middle = sorted.len() / 2;
+
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Works out the median of some samples after throwing away outliers, which are samples too far
+/// from the median compared to how far the rest of the samples are (see [OUTLIER_DEVIATIONS]).
+/// Returns None if there are no samples.
+///
+/// This is for measurements made by a person, like taps along to a song, where most samples are
+/// close together but the odd one is way off because of a missed or doubled tap.
+pub fn robust_median(samples: &[f64]) -> Option<RobustMedian> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let centre = median(&sorted);
+    let mut deviations = sorted
+        .iter()
+        .map(|x| (x - centre).abs())
+        .collect::<Vec<_>>();
+    deviations.sort_by(f64::total_cmp);
+    let limit = median(&deviations) * MAD_SCALE * OUTLIER_DEVIATIONS;
+
+    let kept = sorted
+        .iter()
+        .copied()
+        .filter(|x| (x - centre).abs() <= limit)
+        .collect::<Vec<_>>();
+
+    let kept_centre = median(&kept);
+    let mut kept_deviations = kept
+        .iter()
+        .map(|x| (x - kept_centre).abs())
+        .collect::<Vec<_>>();
+    kept_deviations.sort_by(f64::total_cmp);
+
+    Some(RobustMedian {
+        median: kept_centre,
+        spread: median(&kept_deviations) * MAD_SCALE,
+        kept: kept.len(),
+        rejected: sorted.len() - kept.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hit_error_stats() {
+        assert_eq!(hit_error_stats(&[]), None);
+
+        let hits = [-0.02, 0.0, 0.01, 0.03].map(|offset| HitRecord {
+            time: 0.0,
+            offset,
+            input: DrumInput::LeftDon,
+        });
+        let stats = hit_error_stats(&hits).unwrap();
+
+        assert_close(stats.mean, 5.0);
+        assert_close(stats.median, 5.0);
+        // The offsets are 25, 5, 5 and 25ms from the mean, for a standard deviation of 18.03ms
+        assert!((stats.unstable_rate - 180.28).abs() < 0.01, "{stats:?}");
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_first_sample_is_average() {
+        let mut average = ExponentialAverage::new(0.1);
+        assert_eq!(average.value(), None);
+
+        average.push(0.02);
+        assert_close(average.value().unwrap(), 0.02);
+    }
+
+    #[test]
+    fn test_average_decays_towards_samples() {
+        let mut average = ExponentialAverage::new(0.5);
+        average.push(0.0);
+        average.push(1.0);
+        assert_close(average.value().unwrap(), 0.5);
+        average.push(1.0);
+        assert_close(average.value().unwrap(), 0.75);
+        average.push(-1.0);
+        assert_close(average.value().unwrap(), -0.125);
+    }
+
+    #[test]
+    fn test_average_converges() {
+        let mut average = ExponentialAverage::new(0.1);
+        average.push(0.0);
+
+        for _ in 0..200 {
+            average.push(-0.01);
+        }
+
+        assert_close(average.value().unwrap(), -0.01);
+    }
+
+    fn roll(start_time: f64, hit_times: &[f64]) -> RollRecord {
+        // Hit alternately with each hand
+        let hit_inputs = (0..hit_times.len())
+            .map(|i| match i % 2 {
+                0 => DrumInput::LeftDon,
+                _ => DrumInput::RightDon,
+            })
+            .collect();
+
+        RollRecord {
+            start_time,
+            hit_times: hit_times.to_vec(),
+            hit_inputs,
+        }
+    }
+
+    #[test]
+    fn test_roll_hits_per_second() {
+        assert_eq!(roll(0.0, &[]).hits_per_second(), None);
+        assert_eq!(roll(0.0, &[1.0]).hits_per_second(), None);
+        assert_close(
+            roll(0.0, &[1.0, 1.1, 1.2, 1.3, 1.4])
+                .hits_per_second()
+                .unwrap(),
+            10.0,
+        );
+    }
+
+    #[test]
+    fn test_mean_roll_rate() {
+        assert_eq!(mean_roll_rate(&[]), None);
+
+        // 10 hits/s for 1 second and 20 hits/s for 0.5 seconds
+        let slow = roll(0.0, &(0..=10).map(|i| i as f64 * 0.1).collect::<Vec<_>>());
+        let fast = roll(
+            5.0,
+            &(0..=10).map(|i| 5.0 + i as f64 * 0.05).collect::<Vec<_>>(),
+        );
+        let single_hit = roll(9.0, &[9.0]);
+
+        assert_close(
+            mean_roll_rate(&[slow, fast, single_hit]).unwrap(),
+            20.0 / 1.5,
+        );
+    }
+
+    #[test]
+    fn test_best_roll_rate() {
+        assert_eq!(best_roll_rate(&[roll(0.0, &[0.0, 0.01])]), None);
+
+        // A slow roll with a short burst of fast hits in the middle
+        let mut hits = (0..10).map(|i| i as f64 * 0.2).collect::<Vec<_>>();
+        let burst_start = hits[hits.len() - 1];
+        hits.extend((1..=BEST_WINDOW_HITS).map(|i| burst_start + i as f64 * 0.05));
+        let burst = roll(0.0, &hits);
+
+        let short = roll(5.0, &[5.0, 5.1, 5.2]);
+
+        assert_close(best_roll_rate(&[burst, short]).unwrap(), 20.0);
+    }
+
+    #[test]
+    fn test_best_single_input_roll_rate() {
+        assert_eq!(best_single_input_roll_rate(&[]), None);
+
+        // 20 hits a second with both hands is 10 hits a second from each key
+        let hits = (0..20).map(|i| i as f64 * 0.05).collect::<Vec<_>>();
+        let alternating = roll(0.0, &hits);
+        assert_close(
+            best_single_input_roll_rate(std::slice::from_ref(&alternating)).unwrap(),
+            10.0,
+        );
+
+        // The same roll all on one key
+        let mashed = RollRecord {
+            hit_inputs: vec![DrumInput::LeftKat; hits.len()],
+            ..alternating.clone()
+        };
+        assert_close(
+            best_single_input_roll_rate(&[alternating, mashed]).unwrap(),
+            20.0,
+        );
+    }
+
+    #[test]
+    fn test_hand_stats() {
+        use DrumInput::*;
+
+        assert_eq!(hand_stats([]), None);
+        assert_eq!(hand_stats([LeftDon]), None);
+
+        let stats = hand_stats([LeftDon, RightKat, LeftKat, RightDon, RightDon]).unwrap();
+        assert_close(stats.alternation, 75.0);
+        assert_eq!(stats.longest_streak, 2);
+
+        // Dons and kats on the same side are the same hand
+        let stats = hand_stats([LeftDon, LeftKat, LeftDon, RightDon]).unwrap();
+        assert_close(stats.alternation, 100.0 / 3.0);
+        assert_eq!(stats.longest_streak, 3);
+    }
+
+    #[test]
+    fn test_robust_median() {
+        assert_eq!(robust_median(&[]), None);
+
+        let single = robust_median(&[0.5]).unwrap();
+        assert_eq!(single.median, 0.5);
+        assert_eq!(single.kept, 1);
+
+        // A missed tap (twice as long) and a doubled one (half as long) are thrown away
+        let samples = [0.5, 0.51, 0.49, 1.0, 0.5, 0.25, 0.505, 0.495];
+        let result = robust_median(&samples).unwrap();
+        assert_eq!(result.rejected, 2);
+        assert_eq!(result.kept, 6);
+        assert!((result.median - 0.5).abs() < 1e-9);
+        assert!(result.spread > 0.0 && result.spread < 0.02);
+
+        // The middle of an even number of samples is halfway between the two middle ones
+        let even = robust_median(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(even.median, 2.5);
+        assert_eq!(even.rejected, 0);
+    }
+}
@@ -1,26 +1,30 @@
-use crate::game::taiko_mode::scene::NoteJudgement;
-use crate::game::{RenderContext, TextureCache};
-use crate::render::shapes::{LinearGradient, Shape, ShapeBuilder, SolidColour};
-use crate::render::text::BuildTextWithRenderer;
-use crate::render::texture::{AnimatedSprite, AnimatedSpriteBuilder, Frame, Sprite, SpriteBuilder};
-use crate::render::{rgb, Renderable, Renderer};
-use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
+use crate::game::taiko_mode::gameplay::{NoteJudgement, ScoreInt};
+use crate::game::{MouseState, RenderContext, TextureCache};
+use crate::notechart_parser::{note_density, NoteChart};
+use crate::render::particles::{Particles, SpawnConfig};
+use crate::render::shapes::{lerp_colour, LinearGradient, Shape, ShapeBuilder, SolidColour};
+use crate::render::text::{BuildTextWithRenderer, TrackedText};
+use crate::render::texture::{
+    AnimatedSprite, AnimatedSpriteBuilder, Frame, Sprite, SpriteBatch, SpriteBuilder,
+};
+use crate::render::waveform::{Peaks, Waveform, WaveformView};
+use crate::render::{rgb, Renderable, Renderer, VIRTUAL_WIDTH};
+use crate::rng::Rng;
+use crate::settings::{DrumInput, HudSettings, JudgementStyle};
+use crate::skin::skin;
+use kaku::{FontSize, HorizontalAlignment, TextBuilder, VerticalAlignment};
 use lyon::geom::point;
-use lyon::lyon_tessellation::{BuffersBuilder, StrokeOptions};
+use lyon::lyon_tessellation::{BuffersBuilder, FillOptions, StrokeOptions};
 use lyon::path::Path;
+use std::f32::consts::PI;
+use std::sync::Arc;
 use std::time::Instant;
 use wgpu::RenderPass;
+use winit::event::MouseButton;
 
-use super::note::{TaikoModeBarline, TaikoModeNote};
-
-// Colours
-pub const HEADER_TOP_COL: [f32; 4] = [30. / 255., 67. / 255., 198. / 255., 1.];
-pub const HEADER_BOTTOM_COL: [f32; 4] = [150. / 255., 90. / 255., 225. / 255., 1.];
-pub const NOTE_FIELD_COL: [f32; 4] = [45. / 255., 45. / 255., 45. / 255., 1.];
-pub const CREAM: [f32; 4] = [1., 235. / 255., 206. / 255., 1.];
-pub const RECEPTACLE_COL: [f32; 4] = [0.26, 0.26, 0.26, 1.0];
-pub const LEFT_PANEL_TOP_COL: [f32; 4] = [1., 73. / 255., 73. / 255., 1.];
-pub const LEFT_PANEL_BOTTOM_COL: [f32; 4] = [229. / 255., 41. / 255., 41. / 255., 1.];
+use super::health::{gauge_fill, HealthInt, MAX_HEALTH};
+use super::note::{GhostNote, TaikoModeBarline, TaikoModeNote, BAD, GOOD, OK};
+use super::stats::ExponentialAverage;
 
 // Positions.
 // TODO: Replace this system something more sophisticated that respects resolution
@@ -34,20 +38,45 @@ pub const NOTE_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT / 2.0;
 pub const NOTE_FIELD_HEIGHT: f32 = 232.;
 pub const LEFT_PANEL_WIDTH: f32 = 480.;
 
+/// Flips an x position over to the other side of the screen if the playfield is mirrored (see
+/// [note_layout](super::note::note_layout)).
+pub fn mirror_x(x: f32, mirrored: bool) -> f32 {
+    if mirrored {
+        VIRTUAL_WIDTH - x
+    } else {
+        x
+    }
+}
+
 pub struct Header {
     background: Shape,
-    title: Text,
+    title: TrackedText,
+    /// The offset profile in use, if there is one
+    offset_profile: Option<TrackedText>,
 }
 
 impl Header {
-    pub fn new(renderer: &mut Renderer, title: &str) -> anyhow::Result<Self> {
+    pub fn new(
+        renderer: &mut Renderer,
+        title: &str,
+        offset_profile: Option<&str>,
+        mirrored: bool,
+    ) -> anyhow::Result<Self> {
+        // The text goes on the opposite side to the receptacle, out of the way of the balloon
+        let (text_x, text_align) = if mirrored {
+            (40., HorizontalAlignment::Left)
+        } else {
+            (1880., HorizontalAlignment::Right)
+        };
+
+        let colours = skin().colours;
         let background = ShapeBuilder::new()
             .filled_rectangle(
                 [0., 0.],
-                [1920., HEADER_HEIGHT],
+                [VIRTUAL_WIDTH, HEADER_HEIGHT],
                 LinearGradient::new(
-                    HEADER_TOP_COL,
-                    HEADER_BOTTOM_COL,
+                    colours.header_top.0,
+                    colours.header_bottom.0,
                     [0., 0.],
                     [0., HEADER_HEIGHT],
                 )
@@ -55,92 +84,158 @@ impl Header {
             )?
             .build(&renderer.device);
 
-        let title = TextBuilder::new(title, renderer.font("mochiy pop one"), [1880., 20.])
-            .horizontal_align(HorizontalAlignment::Right)
+        let title = TextBuilder::new(title, renderer.font("mochiy pop one"), [text_x, 20.])
+            .horizontal_align(text_align)
             .vertical_align(VerticalAlignment::Top)
             .font_size(Some(FontSize::Px(80.)))
             .color([1.0; 4])
             .outlined([0., 0., 0., 1.], 5.)
             .build_text(renderer);
 
-        Ok(Self { background, title })
+        let offset_profile = offset_profile.map(|profile| {
+            TextBuilder::new(
+                format!("Offset: {profile}"),
+                renderer.font("mochiy pop one"),
+                [text_x, 130.],
+            )
+            .horizontal_align(text_align)
+            .vertical_align(VerticalAlignment::Top)
+            .font_size(Some(FontSize::Px(28.)))
+            .color([1.0; 4])
+            .outlined([0., 0., 0., 1.], 3.)
+            .build_text(renderer)
+        });
+
+        Ok(Self {
+            background,
+            title,
+            offset_profile,
+        })
+    }
+
+    /// Makes the background of the header see-through, from 0 (invisible) to 1. The text stays
+    /// fully visible so the song can still be read.
+    pub fn set_opacity(&self, opacity: f32, renderer: &Renderer) {
+        self.background.set_tint([1.0, 1.0, 1.0, opacity], renderer);
     }
 
     pub fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
         ctx.render(&self.background);
         ctx.render(&self.title);
+
+        if let Some(offset_profile) = &self.offset_profile {
+            ctx.render(offset_profile);
+        }
     }
 }
 
 pub struct NoteField {
     field: Shape,
     left_panel: Shape,
+    /// The dons and kats on screen, in one batch for each GPU texture they're drawn with. The
+    /// note textures are usually all on the same atlas, so every note is drawn in one draw call.
+    note_batches: Vec<SpriteBatch>,
 }
 
 impl NoteField {
-    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+    pub fn new(renderer: &mut Renderer, mirrored: bool) -> anyhow::Result<Self> {
+        let hit_x = mirror_x(NOTE_HIT_X, mirrored);
+        let colours = skin().colours;
         let field = ShapeBuilder::new()
             // Background
             .filled_rectangle(
                 [0., NOTE_FIELD_Y],
-                [1920., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
-                SolidColour::new(NOTE_FIELD_COL),
+                [VIRTUAL_WIDTH, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                SolidColour::new(colours.note_field.0),
             )?
             // Top spacer
             .filled_rectangle(
                 [0., HEADER_HEIGHT],
-                [1920., HEADER_HEIGHT + SPACER_WIDTH],
-                SolidColour::new(CREAM),
+                [VIRTUAL_WIDTH, HEADER_HEIGHT + SPACER_WIDTH],
+                SolidColour::new(colours.spacer.0),
             )?
             // Bottom spacer
             .filled_rectangle(
                 [0., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
-                [1920., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + SPACER_WIDTH],
-                SolidColour::new(CREAM),
+                [
+                    VIRTUAL_WIDTH,
+                    NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + SPACER_WIDTH,
+                ],
+                SolidColour::new(colours.spacer.0),
             )?
             // Note recepticle
             .stroke_shape(|tess, out| {
                 let mut path = Path::builder();
-                path.begin(point(NOTE_HIT_X, NOTE_Y - NOTE_FIELD_HEIGHT / 2.0));
-                path.line_to(point(NOTE_HIT_X, NOTE_Y + NOTE_FIELD_HEIGHT / 2.0));
+                path.begin(point(hit_x, NOTE_Y - NOTE_FIELD_HEIGHT / 2.0));
+                path.line_to(point(hit_x, NOTE_Y + NOTE_FIELD_HEIGHT / 2.0));
                 path.end(false);
 
                 let options = StrokeOptions::DEFAULT.with_line_width(4.0);
-                let mut builder = BuffersBuilder::new(out, SolidColour::new(RECEPTACLE_COL));
+                let mut builder = BuffersBuilder::new(out, SolidColour::new(colours.receptacle.0));
 
                 // A line that shows exactly where notes should be hit
                 tess.tessellate_path(&path.build(), &options, &mut builder)?;
 
                 // The outline of a small note
-                tess.tessellate_circle(point(NOTE_HIT_X, NOTE_Y), 50.0, &options, &mut builder)?;
+                tess.tessellate_circle(point(hit_x, NOTE_Y), 50.0, &options, &mut builder)?;
 
                 // The outline of a large note
-                tess.tessellate_circle(point(NOTE_HIT_X, NOTE_Y), 75.0, &options, &mut builder)?;
+                tess.tessellate_circle(point(hit_x, NOTE_Y), 75.0, &options, &mut builder)?;
 
                 Ok(())
             })?
             .build(&renderer.device);
 
+        // The left edge of the panel, and of the black line along the side facing the field
+        let (panel_x, line_x) = if mirrored {
+            (
+                VIRTUAL_WIDTH - LEFT_PANEL_WIDTH,
+                VIRTUAL_WIDTH - LEFT_PANEL_WIDTH - 3.,
+            )
+        } else {
+            (0.0, LEFT_PANEL_WIDTH)
+        };
+
         let left_panel = ShapeBuilder::new()
             .filled_rectangle(
-                [0.0, NOTE_FIELD_Y],
-                [LEFT_PANEL_WIDTH, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                [panel_x, NOTE_FIELD_Y],
+                [panel_x + LEFT_PANEL_WIDTH, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
                 LinearGradient::new(
-                    LEFT_PANEL_TOP_COL,
-                    LEFT_PANEL_BOTTOM_COL,
+                    colours.left_panel_top.0,
+                    colours.left_panel_bottom.0,
                     [0.0, NOTE_FIELD_Y],
                     [0.0, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
                 )
                 .ok_or(anyhow::format_err!("couldnt construct linear gradient"))?,
             )?
             .filled_rectangle(
-                [LEFT_PANEL_WIDTH, NOTE_FIELD_Y],
-                [LEFT_PANEL_WIDTH + 3., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                [line_x, NOTE_FIELD_Y],
+                [line_x + 3., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
                 SolidColour::new([0., 0., 0., 1.]),
             )?
             .build(&renderer.device);
 
-        Ok(Self { field, left_panel })
+        Ok(Self {
+            field,
+            left_panel,
+            note_batches: Vec::new(),
+        })
+    }
+
+    /// Moves the note field down by the given amount, e.g. to make room for another player's
+    /// above it.
+    pub fn with_y_offset(self, y_offset: f32, renderer: &Renderer) -> Self {
+        self.field.set_position([0., y_offset, 0.], renderer);
+        self.left_panel.set_position([0., y_offset, 0.], renderer);
+        self
+    }
+
+    /// Makes the note field and side panel see-through, from 0 (invisible) to 1. Notes are drawn
+    /// separately, so they aren't affected.
+    pub fn set_opacity(&self, opacity: f32, renderer: &Renderer) {
+        let tint = [1.0, 1.0, 1.0, opacity];
+        self.field.set_tint(tint, renderer);
+        self.left_panel.set_tint(tint, renderer);
     }
 
     pub fn render<'pass>(
@@ -148,6 +243,7 @@ impl NoteField {
         ctx: &mut RenderContext<'_, 'pass>,
         notes: impl Iterator<Item = &'pass TaikoModeNote>,
         barlines: impl Iterator<Item = &'pass TaikoModeBarline>,
+        ghosts: impl Iterator<Item = &'pass GhostNote>,
     ) {
         ctx.render(&self.field);
 
@@ -157,8 +253,48 @@ impl NoteField {
             ctx.render(b);
         }
 
+        // Ghost notes don't use the depth buffer, so drawing them first keeps them underneath
+        for g in ghosts {
+            ctx.render(g);
+        }
+
+        // Dons and kats are gathered up into batches, and everything else is drawn as it is. Notes
+        // use the depth buffer, so the batches being drawn after the other notes doesn't change
+        // which notes end up on top.
+        let batches = &mut self.note_batches;
+        for batch in batches.iter_mut() {
+            batch.clear();
+        }
+
         for n in notes {
-            ctx.render(n);
+            let Some((texture, position, tint)) = n.batched_sprite() else {
+                ctx.render(n);
+                continue;
+            };
+
+            let index = match batches.iter().position(|batch| batch.can_draw(texture)) {
+                Some(index) => index,
+                None => {
+                    batches.push(SpriteBatch::new(ctx.renderer, texture.clone(), true));
+                    batches.len() - 1
+                }
+            };
+
+            batches[index].push(
+                texture,
+                texture.centre(),
+                [position[0], position[1]],
+                position[2],
+                tint,
+            );
+        }
+
+        for batch in batches.iter_mut() {
+            batch.upload(ctx.renderer);
+        }
+
+        for batch in batches.iter() {
+            ctx.render(batch);
         }
 
         ctx.render(&self.left_panel);
@@ -174,30 +310,125 @@ const JUDGEMENT_TEXT_OK_COLOUR: [f32; 4] = [1.; 4];
 const JUDGEMENT_TEXT_OK_OUTLINE_COLOUR: [f32; 4] = [21. / 255., 21. / 255., 21. / 255., 1.];
 const JUDGEMENT_TEXT_BAD_COLOUR: [f32; 4] = [46. / 255., 103. / 255., 209. / 255., 1.];
 const JUDGEMENT_TEXT_BAD_OUTLINE_COLOUR: [f32; 4] = [0., 0., 0., 1.];
+const JUDGEMENT_BURST_BOUNCE_HEIGHT: f32 = 25.;
+const JUDGEMENT_BURST_RADIUS: f32 = 45.;
+const JUDGEMENT_BURST_POINTS: usize = 8;
+
+/// What the judgement text looks like at a moment in its animation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct JudgementFrame {
+    text_y: f32,
+    /// How opaque the burst behind the text is, or None if there's no burst.
+    burst_alpha: Option<f32>,
+}
+
+/// A judgement that is being shown, along with the style it's shown in.
+#[derive(Debug, Clone, Copy)]
+struct ShownJudgement {
+    /// The index of the judgement (see [NoteJudgement::index])
+    index: usize,
+    style: JudgementStyle,
+    shown_at: Instant,
+}
+
+impl ShownJudgement {
+    fn new(judgement: NoteJudgement, hud: &HudSettings) -> Self {
+        Self {
+            index: judgement.index(),
+            style: hud.judgement_style,
+            shown_at: Instant::now(),
+        }
+    }
+
+    /// Returns what the judgement should look like the given number of seconds after it was
+    /// shown, or None if it should have disappeared.
+    fn frame(&self, elapsed: f32) -> Option<JudgementFrame> {
+        if elapsed > JUDGEMENT_TEXT_DISPLAY_TIME {
+            return None;
+        }
+
+        let progress = elapsed / JUDGEMENT_TEXT_DISPLAY_TIME;
+
+        Some(match self.style {
+            JudgementStyle::Text => JudgementFrame {
+                text_y: JUDGEMENT_TEXT_Y + JUDGEMENT_TEXT_FLOAT_DIST * (progress * 1.5 + 1.).ln(),
+                burst_alpha: None,
+            },
+            JudgementStyle::Burst => JudgementFrame {
+                // A quick hop upwards that settles back down
+                text_y: JUDGEMENT_TEXT_Y
+                    - JUDGEMENT_BURST_BOUNCE_HEIGHT * (progress * PI).sin() * (1. - progress),
+                burst_alpha: Some(1. - progress),
+            },
+        })
+    }
+}
+
+/// Builds the burst shown behind the judgement text in the [JudgementStyle::Burst] style.
+fn judgement_burst(colour: [f32; 4], x: f32, renderer: &Renderer) -> anyhow::Result<Shape> {
+    Ok(ShapeBuilder::new()
+        .position([x, JUDGEMENT_TEXT_Y + 18., 0.])
+        .filled_shape(|tess, out| {
+            // A star with long thin points
+            let mut path = Path::builder();
+
+            for i in 0..JUDGEMENT_BURST_POINTS * 2 {
+                let angle = i as f32 * PI / JUDGEMENT_BURST_POINTS as f32;
+                let radius = if i % 2 == 0 {
+                    JUDGEMENT_BURST_RADIUS
+                } else {
+                    JUDGEMENT_BURST_RADIUS / 3.
+                };
+
+                let vertex = point(radius * angle.sin(), -radius * angle.cos());
+                if i == 0 {
+                    path.begin(vertex);
+                } else {
+                    path.line_to(vertex);
+                }
+            }
+
+            path.end(true);
+
+            tess.tessellate_path(
+                &path.build(),
+                &FillOptions::DEFAULT,
+                &mut BuffersBuilder::new(out, SolidColour::new(colour)),
+            )?;
+
+            Ok(())
+        })?
+        .build(&renderer.device))
+}
 
 // TODO: Japanese localisation
 /// A UI element that displays some text indicating how well the player hit the last note.
-/// The text is displayed for a short time while moving upwards, and becomes transparent as it ages.
+///
+/// Depending on the [JudgementStyle] in the HUD settings at the time of the judgement, the text
+/// either floats upwards, or bounces with a burst of colour behind it that fades away.
 pub struct JudgementText {
-    judgement_sprites: [Text; 3],
-    /// Contains the index of the current sprite, and the moment it was instantiated, or None if
-    /// there's no currently visible sprite.
-    current_sprite: Option<(usize, Instant)>,
+    judgement_sprites: [TrackedText; 3],
+    bursts: [Shape; 3],
+    /// The judgement that is currently visible, if any
+    current: Option<ShownJudgement>,
+    /// Whether the burst of the current judgement should be drawn
+    show_burst: bool,
+    /// Where the judgements are shown, above the receptacle
+    x: f32,
+    /// How far the judgements are moved down (see [JudgementText::with_y_offset])
+    y_offset: f32,
 }
 
 impl JudgementText {
-    pub fn new(renderer: &mut Renderer) -> Self {
+    pub fn new(renderer: &mut Renderer, mirrored: bool) -> anyhow::Result<Self> {
+        let x = mirror_x(NOTE_HIT_X, mirrored);
         let mut build_judgement_text = |text, colour, outline_colour| {
-            TextBuilder::new(
-                text,
-                renderer.font("mochiy pop one"),
-                [NOTE_HIT_X, JUDGEMENT_TEXT_Y],
-            )
-            .font_size(Some(FontSize::Px(30.)))
-            .horizontal_align(HorizontalAlignment::Center)
-            .color(colour)
-            .outlined(outline_colour, 3.)
-            .build_text(renderer)
+            TextBuilder::new(text, renderer.font("mochiy pop one"), [x, JUDGEMENT_TEXT_Y])
+                .font_size(Some(FontSize::Px(30.)))
+                .horizontal_align(HorizontalAlignment::Center)
+                .color(colour)
+                .outlined(outline_colour, 3.)
+                .build_text(renderer)
         };
 
         let judgement_sprites = [
@@ -218,81 +449,151 @@ impl JudgementText {
             ),
         ];
 
-        Self {
+        let bursts = [
+            judgement_burst(JUDGEMENT_TEXT_GOOD_COLOUR, x, renderer)?,
+            judgement_burst(JUDGEMENT_TEXT_OK_COLOUR, x, renderer)?,
+            judgement_burst(JUDGEMENT_TEXT_BAD_COLOUR, x, renderer)?,
+        ];
+
+        Ok(Self {
             judgement_sprites,
-            current_sprite: None,
+            bursts,
+            current: None,
+            show_burst: false,
+            x,
+            y_offset: 0.,
+        })
+    }
+
+    /// Moves the judgements down by the given amount, to go with a [NoteField] that's been moved.
+    pub fn with_y_offset(mut self, y_offset: f32, renderer: &Renderer) -> Self {
+        for burst in self.bursts.iter() {
+            burst.set_position([self.x, JUDGEMENT_TEXT_Y + 18. + y_offset, 0.], renderer);
         }
+
+        self.y_offset = y_offset;
+        self
     }
 
-    pub fn display_judgement(&mut self, judgement: NoteJudgement) {
-        let index = judgement.index();
-        self.current_sprite = Some((index, Instant::now()));
+    /// Shows a judgement, in the style given by the HUD settings.
+    pub fn display_judgement(&mut self, judgement: NoteJudgement, hud: &HudSettings) {
+        self.current = Some(ShownJudgement::new(judgement, hud));
     }
 
     pub fn update(&mut self, renderer: &Renderer) {
-        if let Some((index, instant)) = self.current_sprite {
-            let elapsed = instant.elapsed().as_secs_f32();
-            if elapsed > JUDGEMENT_TEXT_DISPLAY_TIME {
-                // Time's up, so just disappear
-                self.current_sprite = None;
-                return;
-            }
+        let Some(current) = self.current else {
+            return;
+        };
+
+        let Some(frame) = current.frame(current.shown_at.elapsed().as_secs_f32()) else {
+            // Time's up, so just disappear
+            self.current = None;
+            return;
+        };
+
+        // This sets the position of the text relative to the starting position
+        self.judgement_sprites[current.index]
+            .set_position([self.x, frame.text_y + self.y_offset], &renderer.queue);
+        // TODO: set the transparency of the text too
 
-            let progress = elapsed / JUDGEMENT_TEXT_DISPLAY_TIME;
-            let y = JUDGEMENT_TEXT_Y + JUDGEMENT_TEXT_FLOAT_DIST * (progress * 1.5 + 1.).ln();
-            // This sets the position of the text relative to the starting position
-            self.judgement_sprites[index].set_position([NOTE_HIT_X, y], &renderer.queue);
-            // TODO: set transparency using a colour tint
+        self.show_burst = frame.burst_alpha.is_some();
+        if let Some(alpha) = frame.burst_alpha {
+            self.bursts[current.index].set_tint([1., 1., 1., alpha], renderer);
         }
     }
 }
 
 impl Renderable for JudgementText {
     fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
-        if let Some((index, _)) = self.current_sprite {
-            self.judgement_sprites[index].render(renderer, render_pass);
+        if let Some(current) = self.current {
+            if self.show_burst {
+                self.bursts[current.index].render(renderer, render_pass);
+            }
+
+            self.judgement_sprites[current.index].render(renderer, render_pass);
         }
     }
 }
 
+/// The most pieces of popped balloon that can be on screen at once.
+const BALLOON_POP_BUDGET: usize = 64;
+
+/// The burst of rubber pieces when a balloon is popped.
+const BALLOON_POP: SpawnConfig = SpawnConfig {
+    count: 24,
+    origin: [NOTE_HIT_X, NOTE_Y - 60.],
+    spread: [30., 30.],
+    direction: -PI / 2.,
+    angle_spread: PI,
+    speed: 200.0..600.0,
+    lifetime: 0.3..0.6,
+    width: 8.0..16.0,
+    height: 6.0..10.0,
+    spin: 12.,
+    flip_speed: 0.0..0.0,
+    gravity: 1200.,
+    drag: 2.,
+    colours: &[
+        rgb!(0xF0, 0x34, 0x2C),
+        rgb!(0xFF, 0x8E, 0x4B),
+        rgb!(0xFF, 0xFF, 0xFF),
+    ],
+};
+
 /// Displays the progress of a balloon roll as it is being played
 /// visually, it appears to blow up a balloon, while showing how many hits are left
 pub struct BalloonDisplay {
     bg_bubble: Sprite,
-    drumroll_message: Text,
-    roll_number_text: Text,
+    drumroll_message: TrackedText,
+    roll_number_text: TrackedText,
     balloon_sprite: AnimatedSprite,
+    pop_particles: Particles,
+    /// Where the balloon is, on the receptacle
+    hit_x: f32,
     displaying: bool,
 }
 
 impl BalloonDisplay {
-    pub fn new(textures: &mut TextureCache, renderer: &mut Renderer) -> anyhow::Result<Self> {
+    pub fn new(
+        textures: &mut TextureCache,
+        renderer: &mut Renderer,
+        rng: Rng,
+        mirrored: bool,
+    ) -> anyhow::Result<Self> {
+        // The speech bubble isn't flipped in a mirrored playfield, so it stays in the same place
+        // relative to the receptacle.
+        let hit_x = mirror_x(NOTE_HIT_X, mirrored);
+
         // TODO: These are hard coded positions! Bad!
         let bg_bubble = SpriteBuilder::new(textures.get(
             &renderer.device,
             &renderer.queue,
             "balloon speech bubble.png",
         )?)
-        .position([575., 130.])
+        .position([hit_x - 115., 130.])
         .build(renderer);
 
-        let drumroll_message =
-            TextBuilder::new("Drumroll!", renderer.font("mplus bold"), [765., 190.])
-                .color([1.; 4])
-                .font_size(Some(FontSize::Px(40.)))
+        let drumroll_message = TextBuilder::new(
+            "Drumroll!",
+            renderer.font("mplus bold"),
+            [hit_x + 75., 190.],
+        )
+        .color([1.; 4])
+        .font_size(Some(FontSize::Px(40.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        let roll_number_text =
+            TextBuilder::new("0", renderer.font("mochiy pop one"), [hit_x + 75., 240.])
+                .color(rgb!(0xFF, 0x8E, 0x4B))
+                .font_size(Some(FontSize::Px(80.)))
                 .horizontal_align(HorizontalAlignment::Center)
                 .vertical_align(VerticalAlignment::Top)
-                .outlined([0., 0., 0., 1.], 3.)
+                .outlined(rgb!(0x60, 0x2B, 0x0C), 3.)
                 .build_text(renderer);
 
-        let roll_number_text = TextBuilder::new("0", renderer.font("mochiy pop one"), [765., 240.])
-            .color(rgb!(0xFF, 0x8E, 0x4B))
-            .font_size(Some(FontSize::Px(80.)))
-            .horizontal_align(HorizontalAlignment::Center)
-            .vertical_align(VerticalAlignment::Top)
-            .outlined(rgb!(0x60, 0x2B, 0x0C), 3.)
-            .build_text(renderer);
-
         let balloon_sprite = AnimatedSpriteBuilder::new(vec![
             Frame::new(
                 textures.get(&renderer.device, &renderer.queue, "balloon 1.png")?,
@@ -307,7 +608,7 @@ impl BalloonDisplay {
                 [50., 150.],
             ),
         ])
-        .position([NOTE_HIT_X, NOTE_Y])
+        .position([hit_x, NOTE_Y])
         .build(renderer);
 
         Ok(Self {
@@ -315,6 +616,8 @@ impl BalloonDisplay {
             drumroll_message,
             balloon_sprite,
             roll_number_text,
+            pop_particles: Particles::new(renderer, BALLOON_POP_BUDGET, rng),
+            hit_x,
             displaying: false,
         })
     }
@@ -332,7 +635,7 @@ impl BalloonDisplay {
         }
 
         if hits_left == 0 {
-            self.displaying = false;
+            self.pop();
         }
 
         self.roll_number_text.set_text(
@@ -357,13 +660,16 @@ impl BalloonDisplay {
 
     /// Plays the animation for popping the balloon
     fn pop(&mut self) {
-        // TODO
+        self.pop_particles.spawn(&SpawnConfig {
+            origin: [self.hit_x, BALLOON_POP.origin[1]],
+            ..BALLOON_POP
+        });
         self.displaying = false;
     }
 
     /// Updates the animated sprites
-    pub fn update(&mut self, _delta_time: f32) {
-        // TODO
+    pub fn update(&mut self, delta_time: f32) {
+        self.pop_particles.update(delta_time);
     }
 }
 
@@ -375,5 +681,1644 @@ impl Renderable for BalloonDisplay {
             self.drumroll_message.render(renderer, render_pass);
             self.roll_number_text.render(renderer, render_pass);
         }
+
+        self.pop_particles.render(renderer, render_pass);
+    }
+}
+
+const DRUMROLL_DISPLAY_OFFSET: f32 = 75.;
+const DRUMROLL_DISPLAY_Y: f32 = 150.;
+const DRUMROLL_BUBBLE_HALF_WIDTH: f32 = 125.;
+const DRUMROLL_BUBBLE_HEIGHT: f32 = 140.;
+const DRUMROLL_BUBBLE_COLOUR: [f32; 4] = rgb!(0xFF, 0xF6, 0xE4);
+const DRUMROLL_BUBBLE_OUTLINE_COLOUR: [f32; 4] = rgb!(0x60, 0x2B, 0x0C);
+const DRUMROLL_FADE_TIME: f32 = 0.3;
+const DRUMROLL_FADE_RISE: f32 = 20.;
+
+/// How far along a [DrumrollDisplay] is: shown while its drumroll is being played, then fading
+/// away once it's over.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct DrumrollFade {
+    /// When the drumroll being counted ends, or None if nothing is being shown
+    end_time: Option<f32>,
+    /// How long ago the drumroll ended, while the bubble is fading away
+    since_end: Option<f32>,
+}
+
+impl DrumrollFade {
+    /// Starts showing a drumroll that ends at `end_time`. Returns whether the last one was still
+    /// fading away, in which case the bubble has to be put back where it started.
+    fn start(&mut self, end_time: f32) -> bool {
+        let was_fading = self.since_end.take().is_some();
+        self.end_time = Some(end_time);
+        was_fading
+    }
+
+    fn is_shown(&self) -> bool {
+        self.end_time.is_some()
+    }
+
+    /// Moves the fade on to the given time in the song (in the same time as the notes), and
+    /// returns how far the bubble should have faded, from 0 (where it starts) to 1 (gone), if it
+    /// has changed. Once it's gone, it's put back at 0 ready for the next drumroll.
+    fn advance(&mut self, note_time: f32, delta_time: f32) -> Option<f32> {
+        match self.end_time {
+            Some(end_time) if note_time >= end_time => {}
+            _ => return None,
+        }
+
+        let since_end = self
+            .since_end
+            .map_or(0., |since_end| since_end + delta_time);
+        if since_end >= DRUMROLL_FADE_TIME {
+            *self = Self::default();
+            return Some(0.);
+        }
+
+        self.since_end = Some(since_end);
+        Some(since_end / DRUMROLL_FADE_TIME)
+    }
+}
+
+/// Counts the hits of a drumroll while it's being played, in a speech bubble above the
+/// receptacle like the [BalloonDisplay]. Once the drumroll is over, the bubble floats up and fades
+/// away.
+pub struct DrumrollDisplay {
+    bubble: Shape,
+    drumroll_message: TrackedText,
+    hits_text: TrackedText,
+    fade: DrumrollFade,
+    /// Where the bubble is centred
+    x: f32,
+}
+
+impl DrumrollDisplay {
+    pub fn new(renderer: &mut Renderer, mirrored: bool) -> anyhow::Result<Self> {
+        let hit_x = mirror_x(NOTE_HIT_X, mirrored);
+        // Like the balloon's speech bubble, this isn't flipped in a mirrored playfield
+        let x = hit_x + DRUMROLL_DISPLAY_OFFSET;
+        let bottom = DRUMROLL_DISPLAY_Y + DRUMROLL_BUBBLE_HEIGHT;
+
+        let bubble = ShapeBuilder::new()
+            .filled_roundrect(
+                [x - DRUMROLL_BUBBLE_HALF_WIDTH, DRUMROLL_DISPLAY_Y],
+                [x + DRUMROLL_BUBBLE_HALF_WIDTH, bottom],
+                30.,
+                SolidColour::new(DRUMROLL_BUBBLE_COLOUR),
+            )?
+            .stroke_roundrect(
+                [x - DRUMROLL_BUBBLE_HALF_WIDTH, DRUMROLL_DISPLAY_Y],
+                [x + DRUMROLL_BUBBLE_HALF_WIDTH, bottom],
+                30.,
+                SolidColour::new(DRUMROLL_BUBBLE_OUTLINE_COLOUR),
+                4.,
+            )?
+            // The tail, pointing down at the receptacle
+            .filled_shape(|tess, out| {
+                let mut path = Path::builder();
+                path.begin(point(hit_x - 10., bottom - 2.));
+                path.line_to(point(hit_x + 30., bottom - 2.));
+                path.line_to(point(hit_x, bottom + 30.));
+                path.end(true);
+
+                tess.tessellate_path(
+                    &path.build(),
+                    &FillOptions::DEFAULT,
+                    &mut BuffersBuilder::new(out, SolidColour::new(DRUMROLL_BUBBLE_COLOUR)),
+                )?;
+                Ok(())
+            })?
+            .build(&renderer.device);
+
+        let drumroll_message = TextBuilder::new(
+            "Drumroll!",
+            renderer.font("mplus bold"),
+            [x, DRUMROLL_DISPLAY_Y + 10.],
+        )
+        .color([1.; 4])
+        .font_size(Some(FontSize::Px(36.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        let hits_text = TextBuilder::new(
+            "0",
+            renderer.font("mochiy pop one"),
+            [x, DRUMROLL_DISPLAY_Y + 50.],
+        )
+        .color(rgb!(0xFF, 0x8E, 0x4B))
+        .font_size(Some(FontSize::Px(70.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .outlined(DRUMROLL_BUBBLE_OUTLINE_COLOUR, 3.)
+        .build_text(renderer);
+
+        Ok(Self {
+            bubble,
+            drumroll_message,
+            hits_text,
+            fade: DrumrollFade::default(),
+            x,
+        })
+    }
+
+    /// Shows how many times the drumroll has been hit. `end_time` is when the drumroll ends, in
+    /// the same time as the notes, which is when the counter starts to fade away.
+    pub fn hit(&mut self, hits: usize, end_time: f32, renderer: &mut Renderer) {
+        self.hits_text.set_text(
+            hits.to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        // A drumroll can start while the last one is still fading away
+        if self.fade.start(end_time) {
+            self.move_up(0., renderer);
+        }
+    }
+
+    /// Hides the counter straight away, e.g. after seeking.
+    pub fn clear(&mut self, renderer: &Renderer) {
+        if self.fade.since_end.is_some() {
+            self.move_up(0., renderer);
+        }
+
+        self.fade = DrumrollFade::default();
+    }
+
+    /// Fades the counter away once the drumroll is over. `note_time` is the time in the song, in
+    /// the same time as the notes.
+    pub fn update(&mut self, note_time: f32, delta_time: f32, renderer: &Renderer) {
+        if let Some(progress) = self.fade.advance(note_time, delta_time) {
+            self.move_up(progress, renderer);
+        }
+    }
+
+    /// Floats the bubble up and fades it, from 0 (where it starts) to 1 (gone).
+    fn move_up(&mut self, progress: f32, renderer: &Renderer) {
+        let rise = DRUMROLL_FADE_RISE * progress;
+
+        self.bubble.set_position([0., -rise, 0.], renderer);
+        self.bubble.set_tint([1., 1., 1., 1. - progress], renderer);
+        self.drumroll_message
+            .set_position([self.x, DRUMROLL_DISPLAY_Y + 10. - rise], &renderer.queue);
+        self.hits_text
+            .set_position([self.x, DRUMROLL_DISPLAY_Y + 50. - rise], &renderer.queue);
+    }
+}
+
+impl Renderable for DrumrollDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if self.fade.is_shown() {
+            self.bubble.render(renderer, render_pass);
+            self.drumroll_message.render(renderer, render_pass);
+            self.hits_text.render(renderer, render_pass);
+        }
+    }
+}
+
+const HEALTH_BAR_SEGMENTS: usize = 50;
+const HEALTH_BAR_X: f32 = 1000.;
+const HEALTH_BAR_Y: f32 = HEADER_HEIGHT - 70.;
+const HEALTH_BAR_WIDTH: f32 = 880.;
+const HEALTH_BAR_HEIGHT: f32 = 40.;
+const HEALTH_BAR_BORDER: f32 = 4.;
+const HEALTH_BAR_SEGMENT_GAP: f32 = 2.;
+const HEALTH_BAR_EMPTY_COLOUR: [f32; 4] = rgb!(0x40, 0x40, 0x40);
+const HEALTH_BAR_EMPTY_GOLD_COLOUR: [f32; 4] = rgb!(0x5C, 0x4E, 0x24);
+const HEALTH_BAR_RED_COLOUR: [f32; 4] = rgb!(0xF0, 0x34, 0x2C);
+const HEALTH_BAR_GOLD_COLOUR: [f32; 4] = rgb!(0xFF, 0xC8, 0x1E);
+const HEALTH_BAR_SHIMMER_SPEED: f32 = 6.;
+const HEALTH_BAR_SHIMMER_WAVELENGTH: f32 = 12.;
+const CLEAR_SPARKLE_TIME: f32 = 0.8;
+const CLEAR_SPARKLE_STARS: usize = 8;
+const CLEAR_SPARKLE_DISTANCE: f32 = 60.;
+
+/// The soul gauge, which shows how much health the player has.
+///
+/// The gauge is split into segments. Segments below the clear threshold are filled in red, and
+/// segments above it are filled in gold. When the gauge is completely full, it shimmers, and when
+/// the player first crosses the clear threshold a small "clear!" sparkle is played.
+pub struct HealthBar {
+    border: Shape,
+    segments: Vec<Shape>,
+    /// The index of the first segment above the clear threshold
+    threshold_segment: usize,
+    /// The health that is currently being displayed
+    displayed: Option<HealthInt>,
+    full_since: Option<Instant>,
+
+    sparkle_stars: Vec<Shape>,
+    clear_text: TrackedText,
+    sparkle_start: Option<Instant>,
+    /// The left edge of the gauge
+    x: f32,
+    /// The top edge of the gauge
+    y: f32,
+}
+
+impl HealthBar {
+    pub fn new(
+        renderer: &mut Renderer,
+        threshold: HealthInt,
+        mirrored: bool,
+    ) -> anyhow::Result<Self> {
+        // The gauge moves over to the other side with the header's text, but still fills up from
+        // left to right
+        let x = if mirrored {
+            VIRTUAL_WIDTH - HEALTH_BAR_X - HEALTH_BAR_WIDTH
+        } else {
+            HEALTH_BAR_X
+        };
+        let border = ShapeBuilder::new()
+            .filled_roundrect(
+                [x - HEALTH_BAR_BORDER, HEALTH_BAR_Y - HEALTH_BAR_BORDER],
+                [
+                    x + HEALTH_BAR_WIDTH + HEALTH_BAR_BORDER,
+                    HEALTH_BAR_Y + HEALTH_BAR_HEIGHT + HEALTH_BAR_BORDER,
+                ],
+                HEALTH_BAR_BORDER * 2.,
+                SolidColour::new([0., 0., 0., 1.]),
+            )?
+            .build(&renderer.device);
+
+        let segment_width = HEALTH_BAR_WIDTH / HEALTH_BAR_SEGMENTS as f32;
+        let threshold_segment = (threshold as usize * HEALTH_BAR_SEGMENTS) / MAX_HEALTH as usize;
+
+        let segments = (0..HEALTH_BAR_SEGMENTS)
+            .map(|i| -> anyhow::Result<Shape> {
+                // The segments are white so that they can be coloured with a tint.
+                let segment = ShapeBuilder::new()
+                    .filled_rectangle(
+                        [0., 0.],
+                        [segment_width - HEALTH_BAR_SEGMENT_GAP, HEALTH_BAR_HEIGHT],
+                        SolidColour::new([1.; 4]),
+                    )?
+                    .position([x + i as f32 * segment_width, HEALTH_BAR_Y, 0.])
+                    .build(&renderer.device);
+
+                let colour = if i < threshold_segment {
+                    HEALTH_BAR_EMPTY_COLOUR
+                } else {
+                    HEALTH_BAR_EMPTY_GOLD_COLOUR
+                };
+                segment.set_tint(colour, renderer);
+
+                Ok(segment)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let sparkle_stars = (0..CLEAR_SPARKLE_STARS)
+            .map(|_| -> anyhow::Result<Shape> {
+                Ok(ShapeBuilder::new()
+                    .filled_shape(|tess, out| {
+                        // A little four pointed star
+                        let mut path = Path::builder();
+                        path.begin(point(0., -12.));
+                        path.line_to(point(3., -3.));
+                        path.line_to(point(12., 0.));
+                        path.line_to(point(3., 3.));
+                        path.line_to(point(0., 12.));
+                        path.line_to(point(-3., 3.));
+                        path.line_to(point(-12., 0.));
+                        path.line_to(point(-3., -3.));
+                        path.end(true);
+
+                        tess.tessellate_path(
+                            &path.build(),
+                            &FillOptions::DEFAULT,
+                            &mut BuffersBuilder::new(out, SolidColour::new([1.; 4])),
+                        )?;
+
+                        Ok(())
+                    })?
+                    .build(&renderer.device))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let clear_text = TextBuilder::new(
+            "clear!",
+            renderer.font("mochiy pop one"),
+            [x + threshold_segment as f32 * segment_width, HEALTH_BAR_Y],
+        )
+        .font_size(Some(FontSize::Px(36.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Bottom)
+        .color(HEALTH_BAR_GOLD_COLOUR)
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        Ok(Self {
+            border,
+            segments,
+            threshold_segment,
+            displayed: None,
+            full_since: None,
+            sparkle_stars,
+            clear_text,
+            sparkle_start: None,
+            x,
+            y: HEALTH_BAR_Y,
+        })
+    }
+
+    /// Moves the gauge down by the given amount from its place in the header, e.g. to put a second
+    /// player's gauge somewhere else.
+    pub fn with_y_offset(mut self, y_offset: f32, renderer: &Renderer) -> Self {
+        let segment_width = HEALTH_BAR_WIDTH / HEALTH_BAR_SEGMENTS as f32;
+        self.y = HEALTH_BAR_Y + y_offset;
+        self.border.set_position([0., y_offset, 0.], renderer);
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            segment.set_position([self.x + i as f32 * segment_width, self.y, 0.], renderer);
+        }
+
+        self
+    }
+
+    /// The point at the top of the gauge where the clear threshold is.
+    fn threshold_position(&self) -> [f32; 2] {
+        let segment_width = HEALTH_BAR_WIDTH / HEALTH_BAR_SEGMENTS as f32;
+
+        [
+            self.x + self.threshold_segment as f32 * segment_width,
+            self.y,
+        ]
+    }
+
+    /// Sets the health that the gauge should display.
+    pub fn set_health(&mut self, health: HealthInt, threshold: HealthInt, renderer: &Renderer) {
+        if self.displayed == Some(health) {
+            return;
+        }
+
+        let crossed_threshold = self
+            .displayed
+            .is_some_and(|displayed| displayed < threshold)
+            && health >= threshold;
+
+        if crossed_threshold && self.sparkle_start.is_none() {
+            self.sparkle_start = Some(Instant::now());
+        }
+
+        if health >= MAX_HEALTH {
+            self.full_since.get_or_insert_with(Instant::now);
+        } else {
+            self.full_since = None;
+        }
+
+        let (red_fill, gold_fill) = gauge_fill(health, threshold);
+        let red_segments = (red_fill * self.threshold_segment as f32).round() as usize;
+        let gold_segments =
+            (gold_fill * (HEALTH_BAR_SEGMENTS - self.threshold_segment) as f32).round() as usize;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let colour = if i < self.threshold_segment {
+                if i < red_segments {
+                    HEALTH_BAR_RED_COLOUR
+                } else {
+                    HEALTH_BAR_EMPTY_COLOUR
+                }
+            } else if i - self.threshold_segment < gold_segments {
+                HEALTH_BAR_GOLD_COLOUR
+            } else {
+                HEALTH_BAR_EMPTY_GOLD_COLOUR
+            };
+
+            segment.set_tint(colour, renderer);
+        }
+
+        self.displayed = Some(health);
+    }
+
+    /// Updates the shimmer and sparkle animations.
+    pub fn update(&mut self, renderer: &Renderer) {
+        if let Some(full_since) = self.full_since {
+            let time = full_since.elapsed().as_secs_f32();
+
+            // A band of light that travels along the gold part of the gauge
+            for (i, segment) in self.segments[self.threshold_segment..].iter().enumerate() {
+                let phase = time * HEALTH_BAR_SHIMMER_SPEED
+                    - i as f32 / HEALTH_BAR_SHIMMER_WAVELENGTH * std::f32::consts::TAU;
+                let brightness = 0.5 * phase.sin().max(0.).powi(4);
+
+                let mut colour = HEALTH_BAR_GOLD_COLOUR;
+                for channel in &mut colour[..3] {
+                    *channel += (1. - *channel) * brightness;
+                }
+
+                segment.set_tint(colour, renderer);
+            }
+        }
+
+        if let Some(sparkle_start) = self.sparkle_start {
+            let t = sparkle_start.elapsed().as_secs_f32() / CLEAR_SPARKLE_TIME;
+
+            if t > 1. {
+                self.sparkle_start = None;
+                return;
+            }
+
+            let [x, y] = self.threshold_position();
+            let distance = CLEAR_SPARKLE_DISTANCE * (1. - (1. - t).powi(2));
+            let alpha = 1. - t * t;
+
+            for (i, star) in self.sparkle_stars.iter().enumerate() {
+                let angle = i as f32 / CLEAR_SPARKLE_STARS as f32 * std::f32::consts::TAU;
+                let position = [
+                    x + angle.cos() * distance,
+                    y + HEALTH_BAR_HEIGHT / 2. + angle.sin() * distance,
+                    0.,
+                ];
+
+                star.set_position(position, renderer);
+                star.set_tint([1., 0.95, 0.6, alpha], renderer);
+            }
+
+            self.clear_text
+                .set_position([x, y - 10. - 20. * t], &renderer.queue);
+        }
+    }
+}
+
+impl Renderable for HealthBar {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        self.border.render(renderer, render_pass);
+
+        for segment in self.segments.iter() {
+            segment.render(renderer, render_pass);
+        }
+
+        if self.sparkle_start.is_some() {
+            for star in self.sparkle_stars.iter() {
+                star.render(renderer, render_pass);
+            }
+
+            self.clear_text.render(renderer, render_pass);
+        }
+    }
+}
+
+const TIMING_METER_TICKS: usize = 32;
+const TIMING_METER_X: f32 = NOTE_HIT_X;
+const TIMING_METER_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + SPACER_WIDTH + 30.;
+const TIMING_METER_HALF_WIDTH: f32 = 200.;
+const TIMING_METER_HEIGHT: f32 = 12.;
+const TIMING_METER_TICK_HEIGHT: f32 = 32.;
+const TIMING_METER_FADE_TIME: f32 = 3.0;
+const TIMING_METER_AVERAGE_SMOOTHING: f32 = 0.1;
+
+/// A bar underneath the receptacle that shows how early or late each note was hit.
+///
+/// The bar spans the bad timing window on either side of the centre, with the ok and good windows
+/// shaded inside it. Each hit leaves a tick at its offset which fades out over a few seconds, and a
+/// marker shows a moving average of recent offsets so that drift is easy to see.
+pub struct TimingMeter {
+    background: Shape,
+    /// Pixels per second of offset
+    scale: f32,
+    ticks: Vec<Shape>,
+    /// The time each tick was placed, and its colour. None if the tick isn't being used.
+    tick_states: Vec<Option<(Instant, [f32; 4])>>,
+    next_tick: usize,
+    average_marker: Shape,
+    average: ExponentialAverage,
+    /// The centre of the meter, which is underneath the receptacle
+    centre_x: f32,
+}
+
+impl TimingMeter {
+    pub fn new(
+        renderer: &Renderer,
+        timing_windows: &[f32; 3],
+        mirrored: bool,
+    ) -> anyhow::Result<Self> {
+        let centre_x = mirror_x(TIMING_METER_X, mirrored);
+        let scale = TIMING_METER_HALF_WIDTH / timing_windows[BAD];
+        let window = |index: usize| timing_windows[index] * scale;
+        let top = TIMING_METER_Y - TIMING_METER_HEIGHT / 2.;
+        let bottom = TIMING_METER_Y + TIMING_METER_HEIGHT / 2.;
+
+        let background = ShapeBuilder::new()
+            .filled_rectangle(
+                [centre_x - window(BAD) - 3., top - 3.],
+                [centre_x + window(BAD) + 3., bottom + 3.],
+                SolidColour::new([0., 0., 0., 0.8]),
+            )?
+            .filled_rectangle(
+                [centre_x - window(BAD), top],
+                [centre_x + window(BAD), bottom],
+                SolidColour::new(JUDGEMENT_TEXT_BAD_COLOUR),
+            )?
+            .filled_rectangle(
+                [centre_x - window(OK), top],
+                [centre_x + window(OK), bottom],
+                SolidColour::new(JUDGEMENT_TEXT_OK_COLOUR),
+            )?
+            .filled_rectangle(
+                [centre_x - window(GOOD), top],
+                [centre_x + window(GOOD), bottom],
+                SolidColour::new(JUDGEMENT_TEXT_GOOD_COLOUR),
+            )?
+            .filled_rectangle(
+                [centre_x - 1., top - 6.],
+                [centre_x + 1., bottom + 6.],
+                SolidColour::new([1.; 4]),
+            )?
+            .build(&renderer.device);
+
+        // The ticks are white so that they can be coloured with a tint.
+        let ticks = (0..TIMING_METER_TICKS)
+            .map(|_| -> anyhow::Result<Shape> {
+                Ok(ShapeBuilder::new()
+                    .filled_rectangle(
+                        [-1.5, -TIMING_METER_TICK_HEIGHT / 2.],
+                        [1.5, TIMING_METER_TICK_HEIGHT / 2.],
+                        SolidColour::new([1.; 4]),
+                    )?
+                    .build(&renderer.device))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let average_marker = ShapeBuilder::new()
+            .filled_shape(|tess, out| {
+                // A little triangle pointing down at the bar
+                let mut path = Path::builder();
+                path.begin(point(0., -TIMING_METER_TICK_HEIGHT / 2.));
+                path.line_to(point(-9., -TIMING_METER_TICK_HEIGHT / 2. - 14.));
+                path.line_to(point(9., -TIMING_METER_TICK_HEIGHT / 2. - 14.));
+                path.end(true);
+
+                tess.tessellate_path(
+                    &path.build(),
+                    &FillOptions::DEFAULT,
+                    &mut BuffersBuilder::new(out, SolidColour::new([1.; 4])),
+                )?;
+
+                Ok(())
+            })?
+            .position([centre_x, TIMING_METER_Y, 0.])
+            .build(&renderer.device);
+
+        Ok(Self {
+            background,
+            scale,
+            ticks,
+            tick_states: vec![None; TIMING_METER_TICKS],
+            next_tick: 0,
+            average_marker,
+            average: ExponentialAverage::new(TIMING_METER_AVERAGE_SMOOTHING),
+            centre_x,
+        })
+    }
+
+    fn offset_position(&self, offset: f32) -> f32 {
+        let offset = (offset * self.scale).clamp(-TIMING_METER_HALF_WIDTH, TIMING_METER_HALF_WIDTH);
+        self.centre_x + offset
+    }
+
+    /// Records a hit with the given offset (in seconds, negative meaning early).
+    pub fn hit(&mut self, offset: f32, judgement: NoteJudgement, renderer: &Renderer) {
+        let colour = match judgement {
+            NoteJudgement::Good => JUDGEMENT_TEXT_GOOD_COLOUR,
+            NoteJudgement::Ok => JUDGEMENT_TEXT_OK_COLOUR,
+            NoteJudgement::Bad => JUDGEMENT_TEXT_BAD_COLOUR,
+        };
+
+        // The oldest tick gets reused
+        let tick = &self.ticks[self.next_tick];
+        tick.set_position([self.offset_position(offset), TIMING_METER_Y, 0.], renderer);
+        tick.set_tint(colour, renderer);
+        self.tick_states[self.next_tick] = Some((Instant::now(), colour));
+        self.next_tick = (self.next_tick + 1) % TIMING_METER_TICKS;
+
+        self.average.push(offset);
+        if let Some(average) = self.average.value() {
+            self.average_marker.set_position(
+                [self.offset_position(average), TIMING_METER_Y, 0.],
+                renderer,
+            );
+        }
+    }
+
+    /// Fades out the ticks as they get older.
+    pub fn update(&mut self, renderer: &Renderer) {
+        for (tick, state) in self.ticks.iter().zip(self.tick_states.iter_mut()) {
+            let Some((time, colour)) = *state else {
+                continue;
+            };
+
+            let progress = time.elapsed().as_secs_f32() / TIMING_METER_FADE_TIME;
+
+            if progress > 1. {
+                *state = None;
+            } else {
+                tick.set_tint([colour[0], colour[1], colour[2], 1. - progress], renderer);
+            }
+        }
+    }
+}
+
+impl Renderable for TimingMeter {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        self.background.render(renderer, render_pass);
+
+        for (tick, state) in self.ticks.iter().zip(self.tick_states.iter()) {
+            if state.is_some() {
+                tick.render(renderer, render_pass);
+            }
+        }
+
+        if self.average.value().is_some() {
+            self.average_marker.render(renderer, render_pass);
+        }
+    }
+}
+
+const INPUT_DISPLAY_LENGTH: usize = 12;
+const INPUT_DISPLAY_FADE_TIME: f32 = 2.0;
+const INPUT_DISPLAY_X: f32 = 50.;
+const INPUT_DISPLAY_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + SPACER_WIDTH + 40.;
+const INPUT_DISPLAY_ROW_HEIGHT: f32 = 36.;
+const INPUT_DISPLAY_SIDE_OFFSET: f32 = 34.;
+const INPUT_DISPLAY_TEXT_X: f32 = INPUT_DISPLAY_X + INPUT_DISPLAY_SIDE_OFFSET + 30.;
+const INPUT_DISPLAY_RADIUS: f32 = 14.;
+
+struct InputDisplaySlot {
+    don: Shape,
+    kat: Shape,
+    interval_text: TrackedText,
+    input: Option<(DrumInput, Instant)>,
+}
+
+/// A fighting-game style input display, showing a column of the most recent drum inputs.
+///
+/// The newest input is at the top, and each row shows which side of the drum was hit along with the
+/// time since the previous input. Inputs fade out after a couple of seconds. The slots are all
+/// created up front and reused, so pressing keys never allocates anything on the GPU.
+pub struct InputDisplay {
+    slots: Vec<InputDisplaySlot>,
+    /// The index of the slot the next input will be written into
+    next: usize,
+    last_input: Option<Instant>,
+}
+
+impl InputDisplay {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let build_circle = |colour, renderer: &Renderer| -> anyhow::Result<Shape> {
+            Ok(ShapeBuilder::new()
+                .filled_circle([0., 0.], INPUT_DISPLAY_RADIUS, SolidColour::new(colour))?
+                .stroke_circle(
+                    [0., 0.],
+                    INPUT_DISPLAY_RADIUS,
+                    SolidColour::new([0., 0., 0., 1.]),
+                    3.,
+                )?
+                .build(&renderer.device))
+        };
+
+        let colours = skin().colours;
+        let mut slots = Vec::with_capacity(INPUT_DISPLAY_LENGTH);
+
+        for _ in 0..INPUT_DISPLAY_LENGTH {
+            let don = build_circle(colours.don.0, renderer)?;
+            let kat = build_circle(colours.kat.0, renderer)?;
+            let interval_text = TextBuilder::new("", renderer.font("mplus bold"), [0., 0.])
+                .font_size(Some(FontSize::Px(22.)))
+                .vertical_align(VerticalAlignment::Middle)
+                .color([1.; 4])
+                .outlined([0., 0., 0., 1.], 2.)
+                .build_text(renderer);
+
+            slots.push(InputDisplaySlot {
+                don,
+                kat,
+                interval_text,
+                input: None,
+            });
+        }
+
+        Ok(Self {
+            slots,
+            next: 0,
+            last_input: None,
+        })
+    }
+
+    /// Adds a new input to the top of the display.
+    pub fn press(&mut self, input: DrumInput, renderer: &mut Renderer) {
+        let now = Instant::now();
+        let interval = match self.last_input {
+            Some(last) => format!("+{}ms", now.duration_since(last).as_millis()),
+            None => String::new(),
+        };
+
+        let slot = &mut self.slots[self.next];
+        slot.input = Some((input, now));
+        slot.interval_text.set_text(
+            interval,
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        self.next = (self.next + 1) % INPUT_DISPLAY_LENGTH;
+        self.last_input = Some(now);
+    }
+
+    /// Moves every input to its row and fades out the older ones.
+    pub fn update(&mut self, renderer: &Renderer) {
+        for row in 0..INPUT_DISPLAY_LENGTH {
+            let index = (self.next + INPUT_DISPLAY_LENGTH - 1 - row) % INPUT_DISPLAY_LENGTH;
+            let slot = &mut self.slots[index];
+
+            let Some((input, time)) = slot.input else {
+                continue;
+            };
+
+            let age = time.elapsed().as_secs_f32();
+
+            if age > INPUT_DISPLAY_FADE_TIME {
+                slot.input = None;
+                continue;
+            }
+
+            let alpha = 1. - (age / INPUT_DISPLAY_FADE_TIME).powi(2);
+            let shape = if input.is_don() { &slot.don } else { &slot.kat };
+            let x = if input.is_left() {
+                INPUT_DISPLAY_X
+            } else {
+                INPUT_DISPLAY_X + INPUT_DISPLAY_SIDE_OFFSET
+            };
+            let y = INPUT_DISPLAY_Y + row as f32 * INPUT_DISPLAY_ROW_HEIGHT;
+
+            shape.set_position([x, y, 0.], renderer);
+            shape.set_tint([1., 1., 1., alpha], renderer);
+            slot.interval_text
+                .set_position([INPUT_DISPLAY_TEXT_X, y], &renderer.queue);
+        }
+    }
+}
+
+impl Renderable for InputDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for slot in self.slots.iter() {
+            let Some((input, _)) = slot.input else {
+                continue;
+            };
+
+            if input.is_don() {
+                slot.don.render(renderer, render_pass);
+            } else {
+                slot.kat.render(renderer, render_pass);
+            }
+
+            slot.interval_text.render(renderer, render_pass);
+        }
+    }
+}
+
+const SCORE_DISPLAY_MARGIN: f32 = 30.;
+const SCORE_DISPLAY_Y: f32 = NOTE_FIELD_Y + 20.;
+
+/// The player's score so far, in the side panel of the note field.
+pub struct ScoreDisplay {
+    text: TrackedText,
+    /// The score that's currently being displayed
+    displayed: ScoreInt,
+    /// Where the text is anchored
+    x: f32,
+}
+
+impl ScoreDisplay {
+    pub fn new(renderer: &mut Renderer, mirrored: bool) -> Self {
+        // The score sits on the side of the panel away from the note field
+        let (x, align) = if mirrored {
+            (
+                VIRTUAL_WIDTH - SCORE_DISPLAY_MARGIN,
+                HorizontalAlignment::Right,
+            )
+        } else {
+            (SCORE_DISPLAY_MARGIN, HorizontalAlignment::Left)
+        };
+
+        let text = TextBuilder::new("0", renderer.font("mochiy pop one"), [x, SCORE_DISPLAY_Y])
+            .font_size(Some(FontSize::Px(50.)))
+            .horizontal_align(align)
+            .vertical_align(VerticalAlignment::Top)
+            .color([1.; 4])
+            .outlined([0., 0., 0., 1.], 4.)
+            .build_text(renderer);
+
+        Self {
+            text,
+            displayed: 0,
+            x,
+        }
+    }
+
+    /// Moves the score down by the given amount, to go with a [NoteField] that's been moved.
+    pub fn with_y_offset(mut self, y_offset: f32, renderer: &Renderer) -> Self {
+        self.text
+            .set_position([self.x, SCORE_DISPLAY_Y + y_offset], &renderer.queue);
+        self
+    }
+
+    /// Sets the score to display. The text is only rebuilt if the score has changed.
+    pub fn set_score(&mut self, score: ScoreInt, renderer: &mut Renderer) {
+        if self.displayed == score {
+            return;
+        }
+
+        self.text.set_text(
+            score.to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.displayed = score;
+    }
+}
+
+impl Renderable for ScoreDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        self.text.render(renderer, render_pass);
+    }
+}
+
+const COMBO_DISPLAY_X: f32 = LEFT_PANEL_WIDTH - 110.;
+const COMBO_DISPLAY_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT - 55.;
+/// The combo isn't shown until it's at least this high.
+const COMBO_DISPLAY_MIN: usize = 10;
+/// How long the combo takes to shrink back to its normal size after going up, in seconds.
+const COMBO_POP_TIME: f32 = 0.12;
+/// The sizes the combo is drawn at, from its normal size to the biggest it gets when it goes up.
+const COMBO_POP_SIZES: [f32; 4] = [64., 70., 76., 82.];
+const COMBO_MILESTONE_X: f32 = 1250.;
+const COMBO_MILESTONE_TIME: f32 = 1.2;
+const COMBO_MILESTONE_RISE: f32 = 40.;
+const COMBO_CONFETTI_BUDGET: usize = 96;
+
+/// The confetti that bursts out of the combo when it reaches a milestone.
+const COMBO_CONFETTI: SpawnConfig = SpawnConfig {
+    count: 40,
+    origin: [COMBO_DISPLAY_X, COMBO_DISPLAY_Y - 30.],
+    spread: [40., 20.],
+    direction: -PI / 2.,
+    angle_spread: PI * 0.6,
+    speed: 300.0..700.0,
+    lifetime: 0.6..1.0,
+    width: 8.0..14.0,
+    height: 5.0..8.0,
+    spin: 10.,
+    flip_speed: 4.0..10.0,
+    gravity: 1400.,
+    drag: 1.5,
+    colours: &[
+        JUDGEMENT_TEXT_GOOD_COLOUR,
+        rgb!(0xFF, 0xFF, 0xFF),
+        rgb!(0xF8, 0x48, 0x28),
+        rgb!(0x68, 0xC0, 0xC0),
+    ],
+};
+
+/// The milestone the combo reached in going from `previous` to `combo`, if it reached one. The
+/// milestones are 50, 100, then every hundred after that.
+fn combo_milestone(previous: usize, combo: usize) -> Option<usize> {
+    let highest = match combo {
+        0..=49 => return None,
+        50..=99 => 50,
+        _ => combo / 100 * 100,
+    };
+
+    (highest > previous).then_some(highest)
+}
+
+/// Which of [COMBO_POP_SIZES] the combo is drawn at, the given number of seconds after it last
+/// went up.
+fn combo_pop_size(elapsed: f32) -> usize {
+    let progress = (elapsed / COMBO_POP_TIME).clamp(0., 1.);
+    (((1. - progress) * COMBO_POP_SIZES.len() as f32) as usize).min(COMBO_POP_SIZES.len() - 1)
+}
+
+/// The player's combo, in the side panel of the note field. It pops when it goes up, and reaching
+/// a milestone (see [combo_milestone]) is celebrated with confetti and a message over the note
+/// field.
+pub struct ComboDisplay {
+    /// The combo at each of [COMBO_POP_SIZES]. They're all kept up to date, so popping doesn't
+    /// need any text to be rebuilt.
+    numbers: Vec<TrackedText>,
+    label: TrackedText,
+    milestone_text: TrackedText,
+    confetti: Particles,
+    /// The combo that's currently being displayed, or None if it was cleared
+    displayed: Option<usize>,
+    /// How long ago the combo last went up, in seconds
+    since_pop: f32,
+    /// How long ago the last milestone was reached, while it's still being celebrated
+    since_milestone: Option<f32>,
+    x: f32,
+    milestone_x: f32,
+}
+
+impl ComboDisplay {
+    pub fn new(renderer: &mut Renderer, rng: Rng, mirrored: bool) -> Self {
+        let x = mirror_x(COMBO_DISPLAY_X, mirrored);
+        let milestone_x = mirror_x(COMBO_MILESTONE_X, mirrored);
+
+        let numbers = COMBO_POP_SIZES
+            .iter()
+            .map(|&size| {
+                TextBuilder::new("0", renderer.font("mochiy pop one"), [x, COMBO_DISPLAY_Y])
+                    .font_size(Some(FontSize::Px(size)))
+                    .horizontal_align(HorizontalAlignment::Center)
+                    .vertical_align(VerticalAlignment::Bottom)
+                    .color([1.; 4])
+                    .outlined([0., 0., 0., 1.], 4.)
+                    .build_text(renderer)
+            })
+            .collect();
+
+        let label = TextBuilder::new(
+            "combo",
+            renderer.font("mochiy pop one"),
+            [x, COMBO_DISPLAY_Y + 5.],
+        )
+        .font_size(Some(FontSize::Px(26.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        let milestone_text =
+            TextBuilder::new("", renderer.font("mochiy pop one"), [milestone_x, NOTE_Y])
+                .font_size(Some(FontSize::Px(60.)))
+                .horizontal_align(HorizontalAlignment::Center)
+                .vertical_align(VerticalAlignment::Middle)
+                .color(JUDGEMENT_TEXT_GOOD_COLOUR)
+                .outlined(JUDGEMENT_TEXT_GOOD_OUTLINE_COLOUR, 4.)
+                .build_text(renderer);
+
+        Self {
+            numbers,
+            label,
+            milestone_text,
+            confetti: Particles::new(renderer, COMBO_CONFETTI_BUDGET, rng),
+            displayed: None,
+            since_pop: COMBO_POP_TIME,
+            since_milestone: None,
+            x,
+            milestone_x,
+        }
+    }
+
+    /// Sets the combo to display, popping it if it went up. Returns whether it reached a
+    /// milestone, so that the scene can play a sound for it.
+    pub fn set_combo(&mut self, combo: usize, renderer: &mut Renderer) -> bool {
+        if self.displayed == Some(combo) {
+            return false;
+        }
+
+        for number in &mut self.numbers {
+            number.set_text(
+                combo.to_string(),
+                &renderer.device,
+                &renderer.queue,
+                &mut renderer.text_renderer,
+            );
+        }
+
+        // A combo that's just been cleared is set without any fuss
+        let Some(previous) = self.displayed.replace(combo) else {
+            return false;
+        };
+
+        if combo < previous {
+            return false;
+        }
+
+        self.since_pop = 0.;
+
+        let Some(milestone) = combo_milestone(previous, combo) else {
+            return false;
+        };
+
+        self.milestone_text.set_text(
+            format!("{milestone} combo!"),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.since_milestone = Some(0.);
+        self.confetti.spawn(&SpawnConfig {
+            origin: [self.x, COMBO_CONFETTI.origin[1]],
+            ..COMBO_CONFETTI
+        });
+
+        true
+    }
+
+    /// Stops any animations, and makes the next combo be set without popping, e.g. after
+    /// seeking.
+    pub fn clear(&mut self) {
+        self.displayed = None;
+        self.since_pop = COMBO_POP_TIME;
+        self.since_milestone = None;
+    }
+
+    pub fn update(&mut self, delta_time: f32, renderer: &Renderer) {
+        self.since_pop += delta_time;
+        self.confetti.update(delta_time);
+
+        let Some(since_milestone) = self.since_milestone.as_mut() else {
+            return;
+        };
+
+        *since_milestone += delta_time;
+        if *since_milestone >= COMBO_MILESTONE_TIME {
+            self.since_milestone = None;
+            return;
+        }
+
+        let progress = *since_milestone / COMBO_MILESTONE_TIME;
+        self.milestone_text.set_position(
+            [self.milestone_x, NOTE_Y - COMBO_MILESTONE_RISE * progress],
+            &renderer.queue,
+        );
+    }
+}
+
+impl Renderable for ComboDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if self
+            .displayed
+            .is_some_and(|combo| combo >= COMBO_DISPLAY_MIN)
+        {
+            self.numbers[combo_pop_size(self.since_pop)].render(renderer, render_pass);
+            self.label.render(renderer, render_pass);
+        }
+
+        if self.since_milestone.is_some() {
+            self.milestone_text.render(renderer, render_pass);
+        }
+
+        self.confetti.render(renderer, render_pass);
+    }
+}
+
+const STREAM_READOUT_X: f32 = 1880.;
+const STREAM_READOUT_Y: f32 = 1000.;
+
+/// A large combo and accuracy readout, shown in streamer mode so that viewers can easily see how
+/// the play is going.
+pub struct StreamReadout {
+    combo_text: TrackedText,
+    accuracy_text: TrackedText,
+    /// The combo and accuracy that are currently being displayed
+    displayed: Option<(usize, f32)>,
+}
+
+impl StreamReadout {
+    pub fn new(renderer: &mut Renderer) -> Self {
+        let combo_text = TextBuilder::new(
+            "",
+            renderer.font("mochiy pop one"),
+            [STREAM_READOUT_X, STREAM_READOUT_Y - 80.],
+        )
+        .font_size(Some(FontSize::Px(60.)))
+        .horizontal_align(HorizontalAlignment::Right)
+        .vertical_align(VerticalAlignment::Bottom)
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 4.)
+        .build_text(renderer);
+
+        let accuracy_text = TextBuilder::new(
+            "",
+            renderer.font("mochiy pop one"),
+            [STREAM_READOUT_X, STREAM_READOUT_Y],
+        )
+        .font_size(Some(FontSize::Px(60.)))
+        .horizontal_align(HorizontalAlignment::Right)
+        .vertical_align(VerticalAlignment::Bottom)
+        .color(JUDGEMENT_TEXT_GOOD_COLOUR)
+        .outlined(JUDGEMENT_TEXT_GOOD_OUTLINE_COLOUR, 4.)
+        .build_text(renderer);
+
+        Self {
+            combo_text,
+            accuracy_text,
+            displayed: None,
+        }
+    }
+
+    /// Sets the values to display. The text is only rebuilt if the values have changed.
+    pub fn set_values(&mut self, combo: usize, accuracy: f32, renderer: &mut Renderer) {
+        if self.displayed == Some((combo, accuracy)) {
+            return;
+        }
+
+        self.combo_text.set_text(
+            format!("{combo} combo"),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        self.accuracy_text.set_text(
+            format!("{accuracy:.2}%"),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        self.displayed = Some((combo, accuracy));
+    }
+}
+
+impl Renderable for StreamReadout {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        self.combo_text.render(renderer, render_pass);
+        self.accuracy_text.render(renderer, render_pass);
+    }
+}
+
+const DENSITY_STRIP_COLUMNS: usize = 200;
+const DENSITY_STRIP_X: f32 = 240.;
+const DENSITY_STRIP_Y: f32 = 1000.;
+const DENSITY_STRIP_WIDTH: f32 = 1440.;
+const DENSITY_STRIP_HEIGHT: f32 = 40.;
+const DENSITY_STRIP_BORDER: f32 = 4.;
+const DENSITY_STRIP_GOGO_HEIGHT: f32 = 6.;
+const DENSITY_STRIP_PLAYHEAD_WIDTH: f32 = 4.;
+const DENSITY_STRIP_EMPTY_COLOUR: [f32; 4] = rgb!(0x40, 0x40, 0x40);
+const DENSITY_STRIP_LOW_COLOUR: [f32; 4] = rgb!(0x3C, 0xC8, 0x50);
+const DENSITY_STRIP_HIGH_COLOUR: [f32; 4] = rgb!(0xF0, 0x34, 0x2C);
+const DENSITY_STRIP_GOGO_COLOUR: [f32; 4] = rgb!(0xFF, 0x96, 0x1E);
+const DENSITY_STRIP_LOOP_COLOUR: [f32; 4] = rgb!(0x40, 0xB0, 0xFF);
+
+/// The close-up of the song's waveform shown above the density strip, which follows the playhead.
+const WAVEFORM_HEIGHT: f32 = 60.;
+const WAVEFORM_Y: f32 = DENSITY_STRIP_Y - DENSITY_STRIP_BORDER * 3. - WAVEFORM_HEIGHT;
+const WAVEFORM_COLOUR: [f32; 4] = rgb!(0x80, 0xC8, 0xFF);
+/// How many seconds of the song the waveform shows before it's zoomed.
+const WAVEFORM_SPAN: f64 = 6.;
+/// How far across the waveform the playhead is kept.
+const WAVEFORM_PLAYHEAD_X: f32 = DENSITY_STRIP_WIDTH / 4.;
+/// How much each press of the zoom keys zooms the waveform in or out.
+const WAVEFORM_ZOOM_STEP: f64 = 1.5;
+
+/// The colour of a column of the density strip, from green for the sparsest notes to red for the
+/// densest part of the chart. Columns without any notes are left grey, so that e.g. a long intro
+/// doesn't look like an easy part of the chart.
+fn density_colour(density: f32, max_density: f32) -> [f32; 4] {
+    if density <= 0.0 || max_density <= 0.0 {
+        DENSITY_STRIP_EMPTY_COLOUR
+    } else {
+        lerp_colour(
+            DENSITY_STRIP_LOW_COLOUR,
+            DENSITY_STRIP_HIGH_COLOUR,
+            density / max_density,
+        )
+    }
+}
+
+/// A strip along the bottom of the screen showing how dense the notes are over the whole song,
+/// with go-go time underlined and a playhead showing where the song is up to. Clicking on it jumps
+/// to that part of the song, so that the hard parts can be practised. The start and end of the
+/// part being looped are marked on it too.
+pub struct DensityStrip {
+    strip: Shape,
+    playhead: Shape,
+    loop_start: Shape,
+    loop_end: Shape,
+    /// Which of the loop markers are shown
+    loop_shown: [bool; 2],
+    waveform: Waveform,
+    waveform_playhead: Shape,
+    /// How long the song is, in seconds
+    length: f32,
+}
+
+impl DensityStrip {
+    pub fn new(
+        renderer: &mut Renderer,
+        chart: &NoteChart,
+        length: f32,
+        peaks: Arc<Peaks>,
+    ) -> anyhow::Result<Self> {
+        let density = note_density(chart, length, DENSITY_STRIP_COLUMNS);
+        let max_density = density.iter().copied().fold(0.0, f32::max);
+        let column_width = DENSITY_STRIP_WIDTH / DENSITY_STRIP_COLUMNS as f32;
+        let x_of =
+            |time: f32| DENSITY_STRIP_X + (time / length).clamp(0.0, 1.0) * DENSITY_STRIP_WIDTH;
+
+        let mut strip = ShapeBuilder::new()
+            .filled_rectangle(
+                [
+                    DENSITY_STRIP_X - DENSITY_STRIP_BORDER,
+                    DENSITY_STRIP_Y - DENSITY_STRIP_BORDER,
+                ],
+                [
+                    DENSITY_STRIP_X + DENSITY_STRIP_WIDTH + DENSITY_STRIP_BORDER,
+                    DENSITY_STRIP_Y
+                        + DENSITY_STRIP_HEIGHT
+                        + DENSITY_STRIP_GOGO_HEIGHT
+                        + DENSITY_STRIP_BORDER * 2.,
+                ],
+                SolidColour::new([0., 0., 0., 1.]),
+            )?
+            .filled_rectangle(
+                [
+                    DENSITY_STRIP_X - DENSITY_STRIP_BORDER,
+                    WAVEFORM_Y - DENSITY_STRIP_BORDER,
+                ],
+                [
+                    DENSITY_STRIP_X + DENSITY_STRIP_WIDTH + DENSITY_STRIP_BORDER,
+                    WAVEFORM_Y + WAVEFORM_HEIGHT + DENSITY_STRIP_BORDER,
+                ],
+                SolidColour::new([0., 0., 0., 1.]),
+            )?;
+
+        for (i, &density) in density.iter().enumerate() {
+            let x = DENSITY_STRIP_X + i as f32 * column_width;
+
+            strip = strip.filled_rectangle(
+                [x, DENSITY_STRIP_Y],
+                [x + column_width, DENSITY_STRIP_Y + DENSITY_STRIP_HEIGHT],
+                SolidColour::new(density_colour(density, max_density)),
+            )?;
+        }
+
+        let gogo_y = DENSITY_STRIP_Y + DENSITY_STRIP_HEIGHT + DENSITY_STRIP_BORDER;
+
+        for region in chart.gogo_regions.iter() {
+            strip = strip.filled_rectangle(
+                [x_of(region.start), gogo_y],
+                [x_of(region.end), gogo_y + DENSITY_STRIP_GOGO_HEIGHT],
+                SolidColour::new(DENSITY_STRIP_GOGO_COLOUR),
+            )?;
+        }
+
+        let marker = |colour| -> anyhow::Result<Shape> {
+            Ok(ShapeBuilder::new()
+                .filled_rectangle(
+                    [-DENSITY_STRIP_PLAYHEAD_WIDTH / 2., -DENSITY_STRIP_BORDER],
+                    [
+                        DENSITY_STRIP_PLAYHEAD_WIDTH / 2.,
+                        DENSITY_STRIP_HEIGHT + DENSITY_STRIP_BORDER,
+                    ],
+                    SolidColour::new(colour),
+                )?
+                .position([DENSITY_STRIP_X, DENSITY_STRIP_Y, 0.])
+                .build(&renderer.device))
+        };
+
+        let mut waveform = Waveform::new(
+            peaks,
+            WaveformView {
+                start: -(WAVEFORM_PLAYHEAD_X as f64) * WAVEFORM_SPAN / DENSITY_STRIP_WIDTH as f64,
+                seconds_per_pixel: WAVEFORM_SPAN / DENSITY_STRIP_WIDTH as f64,
+            },
+            [DENSITY_STRIP_X, WAVEFORM_Y, 0.],
+            [DENSITY_STRIP_WIDTH, WAVEFORM_HEIGHT],
+            renderer,
+        )?;
+        waveform.set_colour(WAVEFORM_COLOUR, renderer);
+
+        let waveform_playhead = ShapeBuilder::new()
+            .filled_rectangle(
+                [-DENSITY_STRIP_PLAYHEAD_WIDTH / 2., 0.],
+                [DENSITY_STRIP_PLAYHEAD_WIDTH / 2., WAVEFORM_HEIGHT],
+                SolidColour::new([1.; 4]),
+            )?
+            .position([DENSITY_STRIP_X + WAVEFORM_PLAYHEAD_X, WAVEFORM_Y, 0.])
+            .build(&renderer.device);
+
+        Ok(Self {
+            strip: strip.build(&renderer.device),
+            playhead: marker([1.; 4])?,
+            loop_start: marker(DENSITY_STRIP_LOOP_COLOUR)?,
+            loop_end: marker(DENSITY_STRIP_LOOP_COLOUR)?,
+            loop_shown: [false; 2],
+            waveform,
+            waveform_playhead,
+            length,
+        })
+    }
+
+    fn x_of(&self, time: f32) -> f32 {
+        DENSITY_STRIP_X + (time / self.length).clamp(0.0, 1.0) * DENSITY_STRIP_WIDTH
+    }
+
+    /// The song's audio, as shown in the waveform.
+    pub fn peaks(&self) -> &Arc<Peaks> {
+        self.waveform.peaks()
+    }
+
+    /// Moves the playhead to the given time in the song, scrolling the waveform along with it.
+    pub fn set_time(&mut self, time: f32, renderer: &Renderer) {
+        self.playhead
+            .set_position([self.x_of(time), DENSITY_STRIP_Y, 0.], renderer);
+
+        let view = self.waveform.view();
+        let start = time as f64 - WAVEFORM_PLAYHEAD_X as f64 * view.seconds_per_pixel;
+
+        if let Err(e) = self.waveform.scroll_to(start, renderer) {
+            log::error!("couldn't draw the waveform: {e}");
+        }
+    }
+
+    /// Zooms the waveform in or out around the playhead.
+    pub fn zoom_waveform(&mut self, zoom_in: bool, renderer: &Renderer) {
+        let factor = if zoom_in {
+            WAVEFORM_ZOOM_STEP
+        } else {
+            WAVEFORM_ZOOM_STEP.recip()
+        };
+
+        if let Err(e) = self.waveform.zoom(factor, WAVEFORM_PLAYHEAD_X, renderer) {
+            log::error!("couldn't draw the waveform: {e}");
+        }
+    }
+
+    /// Marks the start and end of the part of the song being looped, where they've been set.
+    pub fn set_loop(&mut self, start: Option<f32>, end: Option<f32>, renderer: &Renderer) {
+        for (i, (marker, time)) in [(&self.loop_start, start), (&self.loop_end, end)]
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(time) = time {
+                marker.set_position([self.x_of(time), DENSITY_STRIP_Y, 0.], renderer);
+            }
+
+            self.loop_shown[i] = time.is_some();
+        }
+    }
+
+    /// The time in the song that was just clicked on, if the strip was clicked.
+    pub fn clicked_time(&self, mouse: &MouseState) -> Option<f32> {
+        if !mouse.is_just_pressed(MouseButton::Left) {
+            return None;
+        }
+
+        let (x, y) = mouse.cursor_pos()?;
+        let on_strip = (DENSITY_STRIP_X..=DENSITY_STRIP_X + DENSITY_STRIP_WIDTH).contains(&x)
+            && (DENSITY_STRIP_Y..=DENSITY_STRIP_Y + DENSITY_STRIP_HEIGHT).contains(&y);
+
+        on_strip.then(|| (x - DENSITY_STRIP_X) / DENSITY_STRIP_WIDTH * self.length)
+    }
+}
+
+impl Renderable for DensityStrip {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        self.strip.render(renderer, render_pass);
+        self.waveform.render(renderer, render_pass);
+        self.waveform_playhead.render(renderer, render_pass);
+
+        for (marker, shown) in [&self.loop_start, &self.loop_end]
+            .into_iter()
+            .zip(self.loop_shown)
+        {
+            if shown {
+                marker.render(renderer, render_pass);
+            }
+        }
+
+        self.playhead.render(renderer, render_pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::VisualSettings;
+
+    #[test]
+    fn test_density_colour() {
+        assert_eq!(density_colour(0.0, 10.0), DENSITY_STRIP_EMPTY_COLOUR);
+        assert_eq!(density_colour(10.0, 10.0), DENSITY_STRIP_HIGH_COLOUR);
+
+        // Even the sparsest notes are coloured, so they stand out from empty parts of the song
+        let sparse = density_colour(0.01, 10.0);
+        assert_ne!(sparse, DENSITY_STRIP_EMPTY_COLOUR);
+        assert!(sparse[1] > sparse[0]);
+
+        // A chart with no notes at all is all grey
+        assert_eq!(density_colour(0.0, 0.0), DENSITY_STRIP_EMPTY_COLOUR);
+    }
+
+    #[test]
+    fn test_combo_milestones() {
+        assert_eq!(combo_milestone(0, 49), None);
+        assert_eq!(combo_milestone(49, 50), Some(50));
+        assert_eq!(combo_milestone(50, 51), None);
+        assert_eq!(combo_milestone(99, 100), Some(100));
+        assert_eq!(combo_milestone(100, 150), None);
+        assert_eq!(combo_milestone(199, 200), Some(200));
+        // Hitting more than one note in a frame doesn't skip past a milestone
+        assert_eq!(combo_milestone(298, 301), Some(300));
+    }
+
+    #[test]
+    fn test_combo_pop_size() {
+        assert_eq!(combo_pop_size(0.), COMBO_POP_SIZES.len() - 1);
+        assert_eq!(combo_pop_size(COMBO_POP_TIME), 0);
+        assert_eq!(combo_pop_size(10.), 0);
+    }
+
+    #[test]
+    fn test_drumroll_fade() {
+        let mut fade = DrumrollFade::default();
+        assert!(!fade.is_shown());
+
+        // The counter stays put while the drumroll is going
+        assert!(!fade.start(2.0));
+        assert!(fade.is_shown());
+        assert_eq!(fade.advance(1.5, 0.1), None);
+
+        // Then fades away once it's over
+        assert_eq!(fade.advance(2.0, 0.1), Some(0.));
+        let progress = fade.advance(2.1, 0.1).unwrap();
+        assert!(progress > 0. && progress < 1.);
+
+        // A drumroll that starts while it's fading brings it back
+        assert!(fade.start(5.0));
+        assert_eq!(fade.advance(2.2, 0.1), None);
+        assert!(fade.is_shown());
+
+        // Once it's faded all the way it's hidden, and back where it started
+        fade.advance(5.0, 0.1);
+        assert_eq!(fade.advance(5.5, DRUMROLL_FADE_TIME), Some(0.));
+        assert!(!fade.is_shown());
+        assert_eq!(fade.advance(6.0, 0.1), None);
+    }
+
+    #[test]
+    fn test_judgement_style_changes_between_judgements() {
+        let mut hud = HudSettings::from(&VisualSettings::default());
+        assert_eq!(hud.judgement_style, JudgementStyle::Text);
+
+        let first = ShownJudgement::new(NoteJudgement::Good, &hud);
+
+        // The setting is changed (e.g. from the pause menu) between two judgements
+        hud.judgement_style = JudgementStyle::Burst;
+        let second = ShownJudgement::new(NoteJudgement::Ok, &hud);
+
+        // The first judgement floats up with no burst
+        let frame = first.frame(JUDGEMENT_TEXT_DISPLAY_TIME / 2.).unwrap();
+        assert_eq!(frame.burst_alpha, None);
+        assert!(frame.text_y < JUDGEMENT_TEXT_Y);
+
+        // The second one has a burst that fades out, and bounces back to where it started
+        let frame = second.frame(JUDGEMENT_TEXT_DISPLAY_TIME / 2.).unwrap();
+        assert!(frame
+            .burst_alpha
+            .is_some_and(|alpha| alpha > 0. && alpha < 1.));
+        assert!(frame.text_y < JUDGEMENT_TEXT_Y);
+
+        let frame = second.frame(JUDGEMENT_TEXT_DISPLAY_TIME).unwrap();
+        assert_eq!(frame.burst_alpha, Some(0.));
+        assert!((frame.text_y - JUDGEMENT_TEXT_Y).abs() < 1e-3);
+
+        // Both disappear once their time is up
+        assert_eq!(first.frame(JUDGEMENT_TEXT_DISPLAY_TIME + 0.1), None);
+        assert_eq!(second.frame(JUDGEMENT_TEXT_DISPLAY_TIME + 0.1), None);
+    }
+}
+
+#[cfg(all(test, feature = "visual-tests"))]
+mod visual_test {
+    use super::*;
+    use crate::game::taiko_mode::gameplay::VisibilitySettings;
+    use crate::game::taiko_mode::health::clear_threshold;
+    use crate::game::taiko_mode::note::{
+        chart_textures, create_barlines, create_ghost_notes, create_notes, ghost_layout,
+        note_layout,
+    };
+    use crate::notechart_parser::parse_tja_file;
+    use crate::render::visual_test::{assert_matches_golden, VisualTest};
+
+    /// How far into the chart the note field is drawn, in seconds.
+    const TIME: f32 = 1.0;
+    const NOTE_SPEED: f32 = 1.0;
+
+    /// The note field as it looks while playing, with the Oni course's notes and the Normal
+    /// course's as ghosts.
+    struct NoteFieldScene {
+        header: Header,
+        note_field: NoteField,
+        health_bar: HealthBar,
+        notes: Vec<TaikoModeNote>,
+        barlines: Vec<TaikoModeBarline>,
+        ghosts: Vec<GhostNote>,
+    }
+
+    fn render_note_field(mirrored: bool) -> image::RgbaImage {
+        let song = parse_tja_file(include_str!(
+            "../../../tests/fixtures/visual/note_field.tja"
+        ))
+        .unwrap();
+        let oni = &song.difficulties[3].as_ref().unwrap().chart;
+        let normal = &song.difficulties[1].as_ref().unwrap().chart;
+
+        let mut test = VisualTest::new();
+        let VisualTest {
+            renderer, textures, ..
+        } = &mut test;
+
+        textures
+            .preload(
+                &renderer.device,
+                &renderer.queue,
+                chart_textures(&oni.notes),
+            )
+            .unwrap();
+
+        let visibility = VisibilitySettings::default();
+        let threshold = clear_threshold(3);
+        let mut health_bar = HealthBar::new(renderer, threshold, mirrored).unwrap();
+        health_bar.set_health(
+            threshold + (MAX_HEALTH - threshold) / 2,
+            threshold,
+            renderer,
+        );
+
+        let mut scene = NoteFieldScene {
+            header: Header::new(renderer, &song.title, Some("Headphones"), mirrored).unwrap(),
+            note_field: NoteField::new(renderer, mirrored).unwrap(),
+            health_bar,
+            notes: create_notes(
+                renderer,
+                textures,
+                &oni.notes,
+                NOTE_SPEED,
+                &note_layout(mirrored),
+                &visibility,
+            ),
+            barlines: create_barlines(
+                renderer,
+                &oni.barlines,
+                NOTE_SPEED,
+                &note_layout(mirrored),
+                &visibility,
+            ),
+            ghosts: create_ghost_notes(
+                renderer,
+                textures,
+                &normal.notes,
+                NOTE_SPEED,
+                &ghost_layout(mirrored),
+                &visibility,
+            ),
+        };
+
+        test.render(
+            &mut scene,
+            |_, _| {},
+            |scene, ctx| {
+                let NoteFieldScene {
+                    header,
+                    note_field,
+                    health_bar,
+                    notes,
+                    barlines,
+                    ghosts,
+                } = scene;
+
+                for note in notes.iter_mut().filter(|note| note.visible(TIME)) {
+                    note.update_position(ctx.renderer, TIME);
+                }
+
+                for barline in barlines.iter_mut().filter(|barline| barline.visible(TIME)) {
+                    barline.update_position(ctx.renderer, TIME);
+                }
+
+                for ghost in ghosts.iter_mut().filter(|ghost| ghost.visible(TIME)) {
+                    ghost.update_position(ctx.renderer, TIME);
+                }
+
+                header.render(ctx);
+                ctx.render(health_bar);
+                note_field.render(
+                    ctx,
+                    notes.iter().filter(|note| note.visible(TIME)),
+                    barlines.iter().filter(|barline| barline.visible(TIME)),
+                    ghosts.iter().filter(|ghost| ghost.visible(TIME)),
+                );
+            },
+        )
+    }
+
+    #[test]
+    fn test_note_field() {
+        assert_matches_golden("note_field", &render_note_field(false));
+    }
+
+    #[test]
+    fn test_note_field_mirrored() {
+        assert_matches_golden("note_field_mirrored", &render_note_field(true));
     }
 }
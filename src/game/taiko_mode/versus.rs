@@ -0,0 +1,649 @@
+//! Two players playing a song together on the same keyboard, each with their own note field, soul
+//! gauge and score. Player 1's note field is where it always is, and player 2's is just below it.
+//!
+//! If the difficulty has separate charts for each player (`#START P1` and `#START P2`), each
+//! player plays their own. Otherwise they both play the same chart. Versus plays aren't recorded,
+//! since the scores are kept per chart rather than per player.
+use std::time::{Duration, Instant};
+
+use egui::RichText;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle};
+use kira::sound::PlaybackState;
+use kira::tween::Tween;
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use super::gameplay::{
+    GameplayCore, GameplayEvent, NoteLayout, PlayResult, PlayState, VisibilitySettings,
+};
+use super::note::{
+    chart_textures, create_barlines, create_notes, note_layout, TaikoModeBarline, TaikoModeNote,
+};
+use super::scene::{
+    create_background, create_pause_dim, every_drawn_barline, every_drawn_note, hit_sound,
+    pause_menu_buttons, pause_menu_input, song_is_over, song_volume, PauseOption, SONG_FADE_OUT,
+};
+use super::ui::{
+    Header, HealthBar, JudgementText, NoteField, ScoreDisplay, NOTE_FIELD_HEIGHT, SPACER_WIDTH,
+};
+use crate::audio::AudioManager;
+use crate::difficulty::DifficultyInfo;
+use crate::game::presence::Presence;
+use crate::game::{Context, GameState, RenderContext, StateTransition, TextureCache};
+use crate::notechart_parser::{Branch, NoteChart, Song};
+use crate::render::{shapes::Shape, texture::Sprite, Renderer};
+use crate::settings::{settings, DrumInput, HudSettings, KeyMap, RulesConfig, SettingsSnapshot};
+
+/// How far player 2's note field is below player 1's.
+const PLAYER_SPACING: f32 = NOTE_FIELD_HEIGHT + SPACER_WIDTH;
+
+/// How far player 2's soul gauge is moved down from where player 1's is in the header, so that it
+/// sits just below player 2's note field.
+const PLAYER2_GAUGE_OFFSET: f32 = 600.;
+
+/// The options in the pause menu, in the order they're shown.
+const PAUSE_OPTIONS: [PauseOption; 3] =
+    [PauseOption::Resume, PauseOption::Restart, PauseOption::Quit];
+
+/// Everything belonging to one of the players: their notes, how they're doing, and the parts of the
+/// screen showing it.
+struct PlayerSide {
+    core: GameplayCore,
+    /// The chart being played, for working out how far its track has scrolled
+    chart: NoteChart,
+    notes: Vec<TaikoModeNote>,
+    barlines: Vec<TaikoModeBarline>,
+    barline_branches: Vec<Option<(usize, Branch)>>,
+    note_field: NoteField,
+    health_bar: HealthBar,
+    score_display: ScoreDisplay,
+    judgement_text: JudgementText,
+}
+
+impl PlayerSide {
+    /// Sets up the given player (0 or 1) to play a chart.
+    fn new(
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+        chart: &NoteChart,
+        difficulty: usize,
+        player: usize,
+    ) -> anyhow::Result<Self> {
+        let notes = every_drawn_note(chart);
+        let (barlines, barline_branches) = every_drawn_barline(chart);
+        textures.preload(&renderer.device, &renderer.queue, chart_textures(&notes))?;
+
+        let core = GameplayCore::new(chart, difficulty).with_rules(settings().game.rules());
+        let note_speed = settings().visual.note_speed;
+        let mirrored = settings().visual.mirror_playfield;
+        let visibility = VisibilitySettings::default();
+
+        let y_offset = player as f32 * PLAYER_SPACING;
+        let layout = note_layout(mirrored);
+        let layout = NoteLayout {
+            y: layout.y + y_offset,
+            ..layout
+        };
+
+        let gauge_offset = if player == 0 {
+            0.
+        } else {
+            PLAYER2_GAUGE_OFFSET
+        };
+
+        Ok(Self {
+            notes: create_notes(renderer, textures, &notes, note_speed, &layout, &visibility),
+            barlines: create_barlines(renderer, &barlines, note_speed, &layout, &visibility),
+            barline_branches,
+            note_field: NoteField::new(renderer, mirrored)?.with_y_offset(y_offset, renderer),
+            health_bar: HealthBar::new(renderer, core.health().threshold(), mirrored)?
+                .with_y_offset(gauge_offset, renderer),
+            score_display: ScoreDisplay::new(renderer, mirrored).with_y_offset(y_offset, renderer),
+            judgement_text: JudgementText::new(renderer, mirrored)?
+                .with_y_offset(y_offset, renderer),
+            core,
+            chart: chart.clone(),
+        })
+    }
+
+    /// Updates the visuals to reflect what happened to the notes.
+    fn apply_events(&mut self, events: Vec<GameplayEvent>, hud: &HudSettings) {
+        for event in events {
+            match event {
+                GameplayEvent::Hit {
+                    note, judgement, ..
+                } => {
+                    self.notes[note].set_hit();
+                    self.judgement_text.display_judgement(judgement, hud);
+                }
+                GameplayEvent::BalloonHit {
+                    note, hits_left, ..
+                } => self.notes[note].set_balloon_hits_left(hits_left),
+                GameplayEvent::BalloonMissed { .. }
+                | GameplayEvent::Miss { .. }
+                | GameplayEvent::Drumroll { .. }
+                | GameplayEvent::BranchChanged { .. } => {}
+            }
+        }
+    }
+
+    /// Resets the player's notes and score, to play the chart again from the start.
+    fn reset(&mut self, difficulty: usize, rules: RulesConfig) {
+        let state = self.core.state();
+        self.core = GameplayCore::new(&self.chart, difficulty).with_rules(rules);
+        if state == PlayState::Paused {
+            self.core.pause();
+        }
+
+        for note in self.notes.iter_mut() {
+            note.reset();
+        }
+    }
+
+    fn update(&mut self, renderer: &mut Renderer) {
+        let health = self.core.health();
+        self.health_bar
+            .set_health(health.value(), health.threshold(), renderer);
+        self.health_bar.update(renderer);
+        self.score_display
+            .set_score(self.core.results().score(), renderer);
+        self.judgement_text.update(renderer);
+    }
+
+    /// Draws the player's side at the given note time.
+    fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>, time: f32) {
+        let time = self.chart.scroll_time(time);
+
+        for note in self.notes.iter_mut().filter(|note| note.visible(time)) {
+            note.update_position(ctx.renderer, time);
+        }
+
+        for barline in self.barlines.iter_mut().filter(|b| b.visible(time)) {
+            barline.update_position(ctx.renderer, time);
+        }
+
+        ctx.render(&self.health_bar);
+
+        // Only the notes and barlines of the branch being played are shown
+        let core = &self.core;
+        let notes = self
+            .notes
+            .iter()
+            .enumerate()
+            .filter(|(i, note)| note.visible(time) && core.is_in_play(*i))
+            .map(|(_, note)| note);
+
+        let barlines = self
+            .barlines
+            .iter()
+            .zip(self.barline_branches.iter())
+            .filter(|(barline, branch)| barline.visible(time) && core.is_on_played_branch(**branch))
+            .map(|(barline, _)| barline);
+
+        self.note_field
+            .render(ctx, notes, barlines, std::iter::empty());
+        ctx.render(&self.score_display);
+        ctx.render(&self.judgement_text);
+    }
+}
+
+/// Which player a key belongs to (0 or 1), and the drum input it's mapped to for them. Player 1's
+/// keys are checked first, in case both players have the same key.
+fn player_input(keys: [&KeyMap; 2], key: PhysicalKey) -> Option<(usize, DrumInput)> {
+    keys.iter()
+        .enumerate()
+        .find_map(|(player, keys)| Some((player, keys.drum_input(key)?)))
+}
+
+/// The player (0 or 1) with the higher score, or None if it's a draw.
+fn winner(results: [&PlayResult; 2]) -> Option<usize> {
+    let [p1, p2] = results.map(PlayResult::score);
+
+    match p1.cmp(&p2) {
+        std::cmp::Ordering::Greater => Some(0),
+        std::cmp::Ordering::Less => Some(1),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// What's shown of how a player did once the song has ended, as (label, value) pairs.
+fn result_rows(player: &PlayerSide) -> [(&'static str, String); 7] {
+    let results = player.core.results();
+    let cleared = if player.core.health().is_clear() {
+        "Yes"
+    } else {
+        "No"
+    };
+
+    [
+        ("Score", results.score().to_string()),
+        ("Good", results.goods().to_string()),
+        ("Ok", results.okays().to_string()),
+        ("Bad", results.bads().to_string()),
+        ("Max combo", results.max_combo().to_string()),
+        ("Drumroll", results.drumrolls().to_string()),
+        ("Cleared", cleared.to_string()),
+    ]
+}
+
+/// The gameplay scene for two players.
+pub struct VersusMode {
+    song_name: String,
+    star_level: u8,
+    difficulty: usize,
+    background: Sprite,
+    background_dim: Shape,
+    /// Darkens the screen while the song is paused
+    pause_dim: Shape,
+    header: Header,
+    song_handle: StaticSoundHandle,
+    song_length: f32,
+    /// When both players' charts are over (see [GameplayCore::end_time]).
+    chart_end: Option<f64>,
+    /// How loud the drum is for this song, as a percentage (see [Song::effects_volume])
+    effects_volume: f32,
+    players: [PlayerSide; 2],
+    started: bool,
+    start_time: Instant,
+    /// When the song was paused, if it is. Escape pauses the song, and pressing it again while
+    /// paused resumes it.
+    paused_at: Option<Instant>,
+    /// Which option of the pause menu is highlighted, as an index into [PAUSE_OPTIONS].
+    pause_selection: usize,
+    /// An option of the pause menu that was clicked on with the mouse.
+    clicked_pause_option: Option<PauseOption>,
+    global_offset: f32,
+    settings: SettingsSnapshot,
+    hud: HudSettings,
+    /// Whether the song has ended, and the results are being shown
+    finished: bool,
+}
+
+impl VersusMode {
+    /// Creates the scene for two players playing the given difficulty of a song.
+    pub fn new(
+        song: &Song,
+        song_data: StaticSoundData,
+        difficulty: usize,
+        ctx: &mut Context,
+    ) -> anyhow::Result<Self> {
+        let renderer = &mut *ctx.renderer;
+        let textures = &mut *ctx.textures;
+        let (background, background_dim) = create_background(renderer, textures)?;
+        background_dim.set_tint([1.0, 1.0, 1.0, settings().visual.background_dim], renderer);
+
+        let song_length = song_data.duration().as_secs_f32();
+        let song_data = song_data.with_modified_settings(|settings| {
+            settings
+                .output_destination(ctx.sounds.music_track())
+                .volume(song_volume(song))
+        });
+        let mut song_handle = ctx.audio.play(song_data)?;
+        // We want to start the song once the scene is actually loaded
+        song_handle.pause(Tween::default())?;
+
+        let course = song.difficulties[difficulty]
+            .as_ref()
+            .expect("Difficulty doesn't exist!");
+        let [p1_chart, p2_chart] = course
+            .player_charts
+            .as_ref()
+            .map_or([&course.chart, &course.chart], |[p1, p2]| [p1, p2]);
+
+        log::info!("playing {} with two players", song.title);
+        let mirrored = settings().visual.mirror_playfield;
+        let players = [
+            PlayerSide::new(renderer, textures, p1_chart, difficulty, 0)?,
+            PlayerSide::new(renderer, textures, p2_chart, difficulty, 1)?,
+        ];
+        let chart_end = players
+            .iter()
+            .filter_map(|player| player.core.end_time())
+            .max_by(f64::total_cmp);
+
+        let song_name = song
+            .title_in(settings().game.title_language.as_deref())
+            .to_string();
+
+        Ok(Self {
+            star_level: course.star_level,
+            difficulty,
+            background,
+            background_dim,
+            pause_dim: create_pause_dim(renderer)?,
+            header: Header::new(renderer, &song_name, None, mirrored)?,
+            song_name,
+            song_handle,
+            song_length,
+            chart_end,
+            effects_volume: song.effects_volume,
+            players,
+            started: false,
+            start_time: Instant::now(),
+            paused_at: None,
+            pause_selection: 0,
+            clicked_pause_option: None,
+            global_offset: settings().game.note_offset() / 1000.0,
+            settings: SettingsSnapshot::new(),
+            hud: HudSettings::current(),
+            finished: false,
+        })
+    }
+
+    /// Returns what time it is with respect to the notes and global offset, like
+    /// [TaikoMode::note_time](super::TaikoMode). The clock stops while the game is paused.
+    fn note_time(&self) -> f64 {
+        self.note_time_at(Instant::now())
+    }
+
+    /// What the note time was (or will be) at the given moment.
+    fn note_time_at(&self, at: Instant) -> f64 {
+        let at = self.paused_at.map_or(at, |paused_at| paused_at.min(at));
+        let elapsed = match at.checked_duration_since(self.start_time) {
+            Some(elapsed) => elapsed.as_secs_f64(),
+            None => -self.start_time.duration_since(at).as_secs_f64(),
+        };
+
+        elapsed - self.global_offset as f64
+    }
+
+    fn pause(&mut self) {
+        if self.finished || self.paused_at.is_some() {
+            return;
+        }
+
+        self.paused_at = Some(Instant::now());
+        self.song_handle.pause(Tween::default()).unwrap();
+        self.pause_selection = 0;
+        self.clicked_pause_option = None;
+
+        for player in self.players.iter_mut() {
+            player.core.pause();
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.start_time += paused_at.elapsed();
+        }
+
+        self.song_handle.resume(Tween::default()).unwrap();
+
+        for player in self.players.iter_mut() {
+            player.core.resume();
+        }
+    }
+
+    /// Starts the song again from the beginning, with both players' notes and scores reset, the
+    /// same way as [TaikoMode::restart](super::TaikoMode) does for one player.
+    fn restart(&mut self) {
+        if let Err(e) = self.song_handle.seek_to(0.0) {
+            log::error!("couldn't restart the song: {e}");
+            return;
+        }
+
+        self.start_time = self.paused_at.unwrap_or_else(Instant::now);
+
+        let rules = self.settings.game.rules();
+        for player in self.players.iter_mut() {
+            player.reset(self.difficulty, rules);
+        }
+    }
+
+    fn results(&self) -> [&PlayResult; 2] {
+        [
+            self.players[0].core.results(),
+            self.players[1].core.results(),
+        ]
+    }
+}
+
+impl GameState for VersusMode {
+    fn fixed_update(&mut self, _ctx: &mut Context, time: Instant) {
+        if !self.started || self.finished {
+            return;
+        }
+
+        let now = self.note_time_at(time);
+        for player in self.players.iter_mut() {
+            let events = player.core.advance(now);
+            player.apply_events(events, &self.hud);
+        }
+    }
+
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        if self.settings.refresh() {
+            self.hud = HudSettings::from(&self.settings.visual);
+        }
+
+        let escape = ctx
+            .keyboard
+            .is_just_pressed(PhysicalKey::Code(KeyCode::Escape));
+        let enter = ctx
+            .keyboard
+            .is_just_pressed(PhysicalKey::Code(KeyCode::Enter));
+
+        if !self.started {
+            self.song_handle.resume(Default::default()).unwrap();
+            self.started = true;
+            self.start_time = Instant::now();
+            ctx.textures.set_gameplay_active(true);
+        } else if !self.finished
+            && self.paused_at.is_none()
+            && song_is_over(
+                self.note_time(),
+                self.chart_end,
+                self.song_handle.state() == PlaybackState::Stopped,
+                self.settings.game.outro_delay as f64,
+            )
+        {
+            self.finished = true;
+            let fade_out = Tween {
+                duration: Duration::from_secs_f32(SONG_FADE_OUT),
+                ..Default::default()
+            };
+            if let Err(e) = self.song_handle.stop(fade_out) {
+                log::error!("couldn't stop the song: {e}");
+            }
+            ctx.textures.set_gameplay_active(false);
+        }
+
+        if self.finished {
+            if escape || enter {
+                return StateTransition::Pop;
+            }
+
+            return StateTransition::Continue;
+        }
+
+        for player in self.players.iter_mut() {
+            player.update(ctx.renderer);
+        }
+
+        if self.paused_at.is_some() {
+            let chosen = pause_menu_input(
+                ctx.keyboard,
+                &self.settings.game.key_mappings,
+                &PAUSE_OPTIONS,
+                &mut self.pause_selection,
+            )
+            .or_else(|| self.clicked_pause_option.take());
+
+            match chosen {
+                Some(PauseOption::Resume) => self.resume(),
+                Some(PauseOption::Restart) => {
+                    self.restart();
+                    self.resume();
+                }
+                Some(PauseOption::Quit) => {
+                    self.song_handle.stop(Default::default()).unwrap();
+                    ctx.textures.set_gameplay_active(false);
+                    return StateTransition::Pop;
+                }
+                _ => {}
+            }
+        } else if escape {
+            self.pause();
+        }
+
+        StateTransition::Continue
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        if self.paused_at.is_some() {
+            egui::Area::new("pause menu".into())
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(&ctx, |ui| {
+                    ui.label(RichText::new("Paused").size(50.0));
+                    ui.add_space(30.0);
+
+                    if let Some(option) =
+                        pause_menu_buttons(ui, &PAUSE_OPTIONS, self.pause_selection)
+                    {
+                        self.clicked_pause_option = Some(option);
+                    }
+                });
+        }
+
+        if !self.finished {
+            return;
+        }
+
+        let winner = winner(self.results());
+
+        egui::Window::new("Results")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .collapsible(false)
+            .resizable(false)
+            .show(&ctx, |ui| {
+                let heading = match winner {
+                    Some(player) => format!("Player {} wins!", player + 1),
+                    None => "It's a draw!".to_string(),
+                };
+                ui.label(RichText::new(heading).size(30.0).strong());
+                ui.label(format!(
+                    "{} ({})",
+                    self.song_name,
+                    DifficultyInfo::name_of(self.difficulty)
+                ));
+
+                egui::Grid::new("versus results")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        ui.label(RichText::new("Player 1").strong());
+                        ui.label(RichText::new("Player 2").strong());
+                        ui.end_row();
+
+                        let [p1, p2] = self.players.each_ref().map(result_rows);
+                        for ((label, p1), (_, p2)) in p1.into_iter().zip(p2) {
+                            ui.label(label);
+                            ui.label(p1);
+                            ui.label(p2);
+                            ui.end_row();
+                        }
+                    });
+
+                ui.label("Press Enter or Esc to go back");
+            });
+    }
+
+    fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+        let time = self.note_time() as f32;
+
+        ctx.render(&self.background);
+        ctx.render(&self.background_dim);
+        self.header.render(ctx);
+
+        for player in self.players.iter_mut() {
+            player.render(ctx, time);
+        }
+
+        if self.paused_at.is_some() {
+            ctx.render(&self.pause_dim);
+        }
+    }
+
+    fn overlay_opened(&mut self, _ctx: &mut Context) {
+        self.pause();
+    }
+
+    fn focus_lost(&mut self, _ctx: &mut Context) {
+        self.pause();
+    }
+
+    fn difficulty(&self) -> Option<usize> {
+        Some(self.difficulty)
+    }
+
+    fn presence(&self) -> Presence {
+        Presence::Playing {
+            title: self.song_name.clone(),
+            difficulty: self.difficulty,
+            level: self.star_level,
+            progress: (self.note_time() as f32 / self.song_length).min(1.0),
+        }
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        // Drum hits are judged the moment they arrive, like in [TaikoMode](super::TaikoMode)
+        if let &WindowEvent::KeyboardInput {
+            event,
+            is_synthetic,
+            ..
+        } = &event
+        {
+            let key = event.physical_key;
+            let pressed = event.state == ElementState::Pressed
+                && !event.repeat
+                && !is_synthetic
+                && !ctx.keyboard.is_pressed(key);
+
+            let keys = [
+                &self.settings.game.key_mappings,
+                &self.settings.game.player2_key_mappings,
+            ];
+
+            if let Some((player, input)) = player_input(keys, key).filter(|_| pressed) {
+                let time = self.note_time_at(ctx.event_time);
+                let side = &mut self.players[player];
+                let events = side.core.press(input, time);
+                ctx.sounds.play_scaled(
+                    ctx.audio,
+                    hit_sound(input, &events),
+                    self.effects_volume / 100.0,
+                );
+                side.apply_events(events, &self.hud);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_player_input() {
+        let p1 = KeyMap::default();
+        let mut p2 = KeyMap::player2();
+        let key = |code| PhysicalKey::Code(code);
+
+        assert_eq!(
+            player_input([&p1, &p2], key(KeyCode::KeyF)),
+            Some((0, DrumInput::LeftDon))
+        );
+        assert_eq!(
+            player_input([&p1, &p2], key(KeyCode::NumpadEnter)),
+            Some((1, DrumInput::RightKat))
+        );
+        assert_eq!(player_input([&p1, &p2], key(KeyCode::KeyA)), None);
+
+        // A key both players have plays for player 1
+        p2.bind(DrumInput::LeftKat, key(KeyCode::KeyJ));
+        assert_eq!(
+            player_input([&p1, &p2], key(KeyCode::KeyJ)),
+            Some((0, DrumInput::RightDon))
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Work that's done on background threads, like scanning the songs folder or loading a song's
+//! audio, so that the game keeps running while it happens.
+//!
+//! A task is started with [Tasks::spawn], which gives back a [Task] handle for its result. The
+//! state that started it polls the handle each frame until the result arrives. While it runs, the
+//! task can report how far along it is, which the state can show however it likes, and every
+//! running task is listed in the "Background tasks" debug window.
+//!
+//! Tasks that take a while should check whether they've been cancelled every so often (see
+//! [TaskContext::check_cancelled]). A task is cancelled when its handle is dropped, as nothing
+//! would ever see the result.
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// The error a task gives back when it stops early because it was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the task was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// What's shared between a task's thread, its handle and the list of running tasks.
+#[derive(Debug, Default)]
+struct TaskStatus {
+    name: String,
+    /// How far through the task is, from 0 to 1, and what it's doing at the moment.
+    progress: Mutex<(f32, String)>,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+/// Given to a task while it runs, for reporting its progress and checking whether it's been
+/// cancelled.
+#[derive(Debug, Default)]
+pub struct TaskContext {
+    status: Arc<TaskStatus>,
+}
+
+impl TaskContext {
+    /// Records how far through the task is, from 0 to 1, and a short description of what it's
+    /// doing.
+    pub fn set_progress(&self, fraction: f32, label: impl Into<String>) {
+        *self.status.progress.lock().unwrap() = (fraction.clamp(0.0, 1.0), label.into());
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.status.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns a [Cancelled] error if the task has been cancelled, so that it can stop with `?`.
+    pub fn check_cancelled(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A handle to a task running in the background, which its result can be collected from.
+///
+/// Dropping the handle cancels the task.
+pub struct Task<T> {
+    status: Arc<TaskStatus>,
+    receiver: Receiver<anyhow::Result<T>>,
+}
+
+impl<T> Task<T> {
+    /// Returns the task's result if it's finished. The result is only returned once, so the handle
+    /// should be dropped after it's been collected.
+    pub fn poll(&self) -> Option<anyhow::Result<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(anyhow::format_err!(
+                "the {} task stopped without a result",
+                self.status.name
+            ))),
+        }
+    }
+
+    /// How far through the task is, from 0 to 1, and what it's doing at the moment.
+    pub fn progress(&self) -> (f32, String) {
+        self.status.progress.lock().unwrap().clone()
+    }
+
+    /// Asks the task to stop. It stops the next time it checks, and gives back a [Cancelled]
+    /// error.
+    pub fn cancel(&self) {
+        self.status.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// A task in the list of running tasks.
+struct RunningTask {
+    status: Arc<TaskStatus>,
+    started: Instant,
+}
+
+/// Starts tasks and keeps track of the ones that are running. The game owns one of these, and
+/// states get to it through their [Context](super::Context).
+#[derive(Default)]
+pub struct Tasks {
+    running: Vec<RunningTask>,
+}
+
+impl Tasks {
+    /// Starts running `work` on its own thread. Its result (or an error, if it panics) is sent
+    /// back to the returned handle.
+    pub fn spawn<T, F>(&mut self, name: impl Into<String>, work: F) -> Task<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&TaskContext) -> anyhow::Result<T> + Send + 'static,
+    {
+        let status = Arc::new(TaskStatus {
+            name: name.into(),
+            ..Default::default()
+        });
+        let (sender, receiver) = mpsc::channel();
+        let context = TaskContext {
+            status: Arc::clone(&status),
+        };
+
+        let spawned = std::thread::Builder::new()
+            .name(status.name.clone())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| work(&context)))
+                    .unwrap_or_else(|payload| {
+                        Err(anyhow::format_err!(
+                            "the {} task panicked: {}",
+                            context.status.name,
+                            panic_message(payload.as_ref())
+                        ))
+                    });
+
+                context.status.finished.store(true, Ordering::Relaxed);
+                // If the handle was dropped, nobody needs the result anymore
+                let _ = sender.send(result);
+            });
+
+        match spawned {
+            Ok(_) => self.running.push(RunningTask {
+                status: Arc::clone(&status),
+                started: Instant::now(),
+            }),
+            // The sender was dropped along with the closure, so the handle gives an error
+            Err(e) => log::error!("couldn't start the {} task: {e}", status.name),
+        }
+
+        Task { status, receiver }
+    }
+
+    /// Forgets about tasks that have finished. Called once a frame.
+    pub fn update(&mut self) {
+        self.running
+            .retain(|task| !task.status.finished.load(Ordering::Relaxed));
+    }
+
+    /// A window listing the tasks that are running, and how far along they are.
+    pub fn ui(&self, ctx: &egui::Context) {
+        egui::Window::new(format!("Background tasks ({})", self.running.len()))
+            .id(egui::Id::new("background tasks"))
+            .default_open(false)
+            .default_pos([10.0, 110.0])
+            .show(ctx, |ui| {
+                if self.running.is_empty() {
+                    ui.label("Nothing is running.");
+                }
+
+                for task in self.running.iter() {
+                    let (fraction, label) = task.status.progress.lock().unwrap().clone();
+                    let mut heading = format!(
+                        "{} ({:.1}s)",
+                        task.status.name,
+                        task.started.elapsed().as_secs_f32()
+                    );
+
+                    if task.status.cancelled.load(Ordering::Relaxed) {
+                        heading.push_str(", cancelling");
+                    }
+
+                    ui.label(heading);
+                    ui.add(egui::ProgressBar::new(fraction).text(label));
+                }
+            });
+    }
+}
+
+/// The message a thread panicked with, if it's a string.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown error"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    /// Waits for a task to finish, failing the test if it takes too long.
+    fn wait<T>(task: &Task<T>) -> anyhow::Result<T> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(result) = task.poll() {
+                return result;
+            }
+
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the task never finished"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_task_completes() {
+        let mut tasks = Tasks::default();
+        let task = tasks.spawn("add", |ctx| {
+            ctx.set_progress(0.5, "halfway");
+            Ok(1 + 1)
+        });
+
+        assert_eq!(wait(&task).unwrap(), 2);
+        assert_eq!(task.progress(), (0.5, "halfway".to_string()));
+
+        // Finished tasks are forgotten
+        tasks.update();
+        assert!(tasks.running.is_empty());
+    }
+
+    #[test]
+    fn test_task_cancellation() {
+        let mut tasks = Tasks::default();
+        let task = tasks.spawn("wait forever", |ctx| loop {
+            ctx.check_cancelled()?;
+            std::thread::sleep(Duration::from_millis(1));
+        });
+
+        assert!(task.poll().is_none());
+        tasks.update();
+        assert_eq!(tasks.running.len(), 1);
+
+        task.cancel();
+        let error: anyhow::Result<()> = wait(&task);
+        assert!(error.unwrap_err().is::<Cancelled>());
+
+        // Dropping a handle cancels its task too
+        let (sender, receiver) = mpsc::channel();
+        let task = tasks.spawn("wait for drop", move |ctx| {
+            while !ctx.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            sender.send(()).unwrap();
+            Ok(())
+        });
+
+        drop(task);
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn test_panic_in_task_is_an_error() {
+        let mut tasks = Tasks::default();
+        let task: Task<()> = tasks.spawn("panic", |_| panic!("something went wrong"));
+
+        let error = wait(&task).unwrap_err();
+        assert!(!error.is::<Cancelled>());
+        assert_eq!(
+            error.to_string(),
+            "the panic task panicked: something went wrong"
+        );
+
+        // Other tasks still run as normal afterwards
+        let task = tasks.spawn("after panic", |_| Ok("fine"));
+        assert_eq!(wait(&task).unwrap(), "fine");
+    }
+}
@@ -0,0 +1,264 @@
+//! Changing the speed of a song without changing its pitch, for practising at a slower rate.
+//!
+//! Simply changing the playback rate of a song also changes its pitch, which makes vocals sound
+//! strange and throws off players who play by ear. Instead, the song can be stretched with WSOLA
+//! (waveform similarity overlap-add): the output is built out of overlapping windows of the input,
+//! each taken from roughly where it should be in the stretched song, but nudged so that it lines
+//! up with the window before it. This keeps the pitch the same at the cost of some artifacts.
+//!
+//! Stretching a whole song takes a moment, so it's done as part of loading the song in a
+//! background task (see [stretch_song]), and the results are kept in memory for the rest of the
+//! session in case the song is played again at the same rate.
+use std::sync::Mutex;
+
+use kira::dsp::Frame;
+use kira::sound::static_sound::StaticSoundData;
+use lazy_static::lazy_static;
+
+/// The length of each window of audio, in frames.
+const WINDOW_LENGTH: usize = 2048;
+/// The distance between the start of each window in the output, in frames.
+const OUTPUT_HOP: usize = WINDOW_LENGTH / 2;
+/// How far a window may be moved from where it should be to line up with the previous window.
+const SEARCH_RANGE: isize = 256;
+/// The distance between each position checked while lining up windows.
+const SEARCH_STEP: usize = 2;
+/// How many frames are compared when checking how well two windows line up.
+const COMPARE_LENGTH: usize = 256;
+/// How many stretched songs are kept in memory at once.
+const MAX_CACHED_SONGS: usize = 4;
+
+/// The number of frames a sound of `input_len` frames will have once it is played at `rate`.
+pub fn stretched_len(input_len: usize, rate: f32) -> usize {
+    (input_len as f64 / rate as f64).round() as usize
+}
+
+fn mono(frame: Frame) -> f32 {
+    (frame.left + frame.right) / 2.
+}
+
+/// Finds the position near `target` in the input where a window would sound most like a
+/// continuation of the audio starting at `natural`.
+fn best_position(input: &[Frame], natural: usize, target: usize) -> usize {
+    let min = (target as isize - SEARCH_RANGE).max(0) as usize;
+    let max = (target as isize + SEARCH_RANGE)
+        .min(input.len().saturating_sub(COMPARE_LENGTH) as isize)
+        .max(min as isize) as usize;
+
+    let reference = |i: usize| input.get(natural + i).copied().map_or(0., mono);
+
+    let mut best = (target.clamp(min, max), f32::MIN);
+
+    for position in (min..=max).step_by(SEARCH_STEP) {
+        let mut correlation = 0.;
+        let mut energy = 0.;
+
+        for i in 0..COMPARE_LENGTH {
+            let sample = input.get(position + i).copied().map_or(0., mono);
+            correlation += sample * reference(i);
+            energy += sample * sample;
+        }
+
+        let similarity = correlation / energy.sqrt().max(1e-6);
+        if similarity > best.1 {
+            best = (position, similarity);
+        }
+    }
+
+    best.0
+}
+
+/// Stretches audio so that it plays at the given rate without changing its pitch. A rate of 0.5
+/// means the result is twice as long. The result always has exactly [stretched_len] frames.
+///
+/// `progress` is called every so often with how far through the stretch we are, from 0 to 1.
+pub fn stretch(input: &[Frame], rate: f32, mut progress: impl FnMut(f32)) -> Vec<Frame> {
+    let output_len = stretched_len(input.len(), rate);
+
+    if input.is_empty() || output_len == 0 {
+        return Vec::new();
+    }
+
+    if rate == 1.0 {
+        return input.to_vec();
+    }
+
+    // A periodic Hann window, which sums to exactly 1 when overlapped by half its length
+    let window = (0..WINDOW_LENGTH)
+        .map(|i| 0.5 - 0.5 * (2. * std::f32::consts::PI * i as f32 / WINDOW_LENGTH as f32).cos())
+        .collect::<Vec<_>>();
+
+    let mut output = vec![Frame::ZERO; output_len + WINDOW_LENGTH];
+    let mut weights = vec![0f32; output_len + WINDOW_LENGTH];
+    let mut previous_position = 0;
+
+    for output_position in (0..output_len).step_by(OUTPUT_HOP) {
+        let target = (output_position as f64 * rate as f64).round() as usize;
+
+        let input_position = if output_position == 0 {
+            0
+        } else {
+            best_position(input, previous_position + OUTPUT_HOP, target)
+        };
+
+        for (i, weight) in window.iter().enumerate() {
+            let sample = input
+                .get(input_position + i)
+                .copied()
+                .unwrap_or(Frame::ZERO);
+
+            output[output_position + i] += sample * *weight;
+            weights[output_position + i] += weight;
+        }
+
+        previous_position = input_position;
+        progress(output_position as f32 / output_len as f32);
+    }
+
+    output.truncate(output_len);
+
+    // The very start of the output is only covered by half a window, so make up for that
+    for (frame, weight) in output.iter_mut().zip(weights) {
+        if weight > 1e-3 {
+            *frame *= 1. / weight;
+        }
+    }
+
+    progress(1.);
+    output
+}
+
+/// Identifies a song stretched to a particular rate. Rates are stored in thousandths so they can
+/// be compared exactly.
+type StretchKey = (String, u32);
+
+fn stretch_key(path: &str, rate: f32) -> StretchKey {
+    (path.to_string(), (rate * 1000.).round() as u32)
+}
+
+lazy_static! {
+    /// Songs that have been stretched this session, with the most recently used last.
+    static ref STRETCH_CACHE: Mutex<Vec<(StretchKey, StaticSoundData)>> = Mutex::new(Vec::new());
+}
+
+/// Returns the song at the given path stretched to the given rate, if it was stretched earlier in
+/// the session.
+pub fn cached_stretch(path: &str, rate: f32) -> Option<StaticSoundData> {
+    let key = stretch_key(path, rate);
+    let mut cache = STRETCH_CACHE.lock().unwrap();
+
+    let index = cache.iter().position(|(k, _)| *k == key)?;
+    let entry = cache.remove(index);
+    let data = entry.1.clone();
+    cache.push(entry);
+    Some(data)
+}
+
+fn cache_stretch(key: StretchKey, data: StaticSoundData) {
+    let mut cache = STRETCH_CACHE.lock().unwrap();
+    cache.retain(|(k, _)| *k != key);
+
+    if cache.len() >= MAX_CACHED_SONGS {
+        cache.remove(0);
+    }
+
+    cache.push((key, data));
+}
+
+/// Stretches a song (loaded from `path`) to the given rate, reporting the progress along the way.
+/// This takes a while, so it should be run in the background. Once it's done, the stretched song
+/// will also be available from [cached_stretch].
+pub fn stretch_song(
+    path: &str,
+    data: &StaticSoundData,
+    rate: f32,
+    progress: impl FnMut(f32),
+) -> StaticSoundData {
+    let frames = stretch(&data.frames, rate, progress);
+
+    let stretched = StaticSoundData {
+        sample_rate: data.sample_rate,
+        frames: frames.into(),
+        settings: data.settings,
+    };
+
+    cache_stretch(stretch_key(path, rate), stretched.clone());
+    stretched
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 44100;
+
+    fn sine(frequency: f32, seconds: f32) -> Vec<Frame> {
+        (0..(SAMPLE_RATE as f32 * seconds) as usize)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                Frame::from_mono((2. * std::f32::consts::PI * frequency * t).sin() * 0.5)
+            })
+            .collect()
+    }
+
+    /// Estimates the frequency of a sound by counting how often it crosses zero, ignoring the
+    /// edges where the windows don't fully overlap.
+    fn frequency(frames: &[Frame]) -> f32 {
+        let middle = &frames[WINDOW_LENGTH..frames.len() - WINDOW_LENGTH];
+        let crossings = middle
+            .windows(2)
+            .filter(|pair| (pair[0].left < 0.) != (pair[1].left < 0.))
+            .count();
+
+        crossings as f32 / 2. / (middle.len() as f32 / SAMPLE_RATE as f32)
+    }
+
+    #[test]
+    fn test_stretched_len() {
+        assert_eq!(stretched_len(44100, 1.0), 44100);
+        assert_eq!(stretched_len(44100, 0.75), 58800);
+        assert_eq!(stretched_len(44100, 0.5), 88200);
+        assert_eq!(stretched_len(44100, 1.5), 29400);
+        assert_eq!(stretched_len(10, 0.3), 33);
+        assert_eq!(stretched_len(0, 0.75), 0);
+    }
+
+    #[test]
+    fn test_stretch_length_matches() {
+        let input = sine(440., 1.);
+
+        for rate in [0.5, 0.75, 0.9, 1.0, 1.25] {
+            let mut last_progress = 0.;
+            let output = stretch(&input, rate, |p| {
+                assert!(p >= last_progress);
+                last_progress = p;
+            });
+
+            assert_eq!(
+                output.len(),
+                stretched_len(input.len(), rate),
+                "rate {rate}"
+            );
+            assert_eq!(last_progress, if rate == 1.0 { 0. } else { 1. });
+        }
+
+        assert!(stretch(&[], 0.75, |_| {}).is_empty());
+    }
+
+    #[test]
+    fn test_stretch_keeps_pitch() {
+        let input = sine(440., 1.);
+        let output = stretch(&input, 0.75, |_| {});
+
+        let input_frequency = frequency(&input);
+        let output_frequency = frequency(&output);
+        assert!(
+            (input_frequency - output_frequency).abs() < 440. * 0.02,
+            "{input_frequency} Hz became {output_frequency} Hz"
+        );
+
+        // A sine wave should come out about as loud as it went in
+        let peak = output.iter().map(|f| f.left.abs()).fold(0., f32::max);
+        assert!((peak - 0.5).abs() < 0.05, "peak is {peak}");
+    }
+}
@@ -0,0 +1,84 @@
+//! Runs the game's timing logic in fixed steps, however fast the game is drawn.
+//!
+//! Frames can come at 30Hz or 300Hz, and a slow one can take a good while. Anything that has to
+//! happen at a particular time, like a note being missed or an input from a replay being judged,
+//! is done in [GameState::fixed_update](super::GameState::fixed_update) instead, which is called
+//! once for every step due since the last frame and told exactly when that step was due. Drawing
+//! still goes by the clock at the time of drawing, so notes move smoothly between steps.
+use std::time::{Duration, Instant};
+
+/// How far apart the steps are: 1000 steps a second.
+pub const FIXED_TIMESTEP: Duration = Duration::from_millis(1);
+
+/// The furthest the steps are allowed to fall behind. If a frame takes longer than this (e.g.
+/// because the window was being dragged), the steps that were missed beyond it are skipped rather
+/// than all being run at once.
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// Keeps track of which steps have been run.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    step: Duration,
+    /// When the next step is due
+    next: Instant,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, start: Instant) -> Self {
+        Self { step, next: start }
+    }
+
+    /// The times of every step that's due by `now`, in order. Each step is only given out once.
+    pub fn due(&mut self, now: Instant) -> Vec<Instant> {
+        if now.saturating_duration_since(self.next) > MAX_CATCH_UP {
+            self.next = now.checked_sub(MAX_CATCH_UP).unwrap_or(now);
+        }
+
+        let mut steps = Vec::new();
+        while self.next <= now {
+            steps.push(self.next);
+            self.next += self.step;
+        }
+
+        steps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_steps_are_evenly_spaced() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10), start);
+
+        // A frame shorter than a step might not have any steps in it
+        assert_eq!(timestep.due(start + Duration::from_millis(5)).len(), 1);
+        assert!(timestep.due(start + Duration::from_millis(9)).is_empty());
+
+        // Steps that are due are given out however long the frames are, and never twice
+        let steps = timestep.due(start + Duration::from_millis(45));
+        let expected: Vec<_> = (1..=4)
+            .map(|i| start + Duration::from_millis(10 * i))
+            .collect();
+        assert_eq!(steps, expected);
+        assert_eq!(
+            timestep.due(start + Duration::from_millis(50)),
+            [start + Duration::from_millis(50)]
+        );
+    }
+
+    #[test]
+    fn test_long_frames_skip_steps() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(FIXED_TIMESTEP, start);
+
+        let now = start + Duration::from_secs(5);
+        let steps = timestep.due(now);
+
+        assert_eq!(steps.len(), 251);
+        assert_eq!(steps.first(), Some(&(now - MAX_CATCH_UP)));
+        assert_eq!(steps.last(), Some(&now));
+    }
+}
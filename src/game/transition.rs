@@ -0,0 +1,210 @@
+//! Animations between one state and the next.
+//!
+//! When the state changes, the screen as it was just before the change is captured into a texture
+//! (see [Game::render_transition_capture](super::Game::render_transition_capture)), and that frame
+//! is animated away on top of the new state. While a transition is playing, the new state isn't
+//! updated and doesn't get any input, so it starts from the moment it can actually be seen.
+use crate::render::texture::Sprite;
+use crate::render::{Renderable, Renderer};
+
+/// How long a transition lasts, in seconds.
+pub const TRANSITION_TIME: f32 = 0.25;
+
+/// How a state wants to be switched to and from (see
+/// [GameState::transition_style](super::GameState::transition_style)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransitionStyle {
+    /// Switch straight away, without any animation or delay.
+    Cut,
+    /// The old screen fades out over the new one.
+    Crossfade,
+    /// The old screen fades out to black, and then the new state starts. This is for states that
+    /// start playing something straight away, like gameplay.
+    FadeToBlack,
+    /// The old screen slides off to the side, uncovering the new one.
+    #[default]
+    Slide,
+}
+
+/// Whether a transition is going into a new state or back out of one. Slides go the other way when
+/// going back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionDirection {
+    Forwards,
+    Backwards,
+}
+
+/// How the captured frame of the old state should be drawn at some point in a transition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OverlayFrame {
+    /// How far the frame has moved to the side, as a fraction of its width.
+    offset: f32,
+    /// The colour the frame is tinted with.
+    tint: [f32; 4],
+}
+
+/// Starts slowly, speeds up and slows down again at the end.
+fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Starts fast and slows down towards the end.
+fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - t.clamp(0.0, 1.0)).powi(3)
+}
+
+/// Where the old frame is and how it's tinted, `t` of the way through a transition.
+fn overlay_at(style: TransitionStyle, direction: TransitionDirection, t: f32) -> OverlayFrame {
+    let still = OverlayFrame {
+        offset: 0.0,
+        tint: [1.0; 4],
+    };
+
+    match style {
+        TransitionStyle::Cut => OverlayFrame {
+            tint: [0.0; 4],
+            ..still
+        },
+        TransitionStyle::Crossfade => OverlayFrame {
+            tint: [1.0, 1.0, 1.0, 1.0 - ease_in_out(t)],
+            ..still
+        },
+        TransitionStyle::FadeToBlack => {
+            let brightness = 1.0 - ease_in_out(t);
+            OverlayFrame {
+                tint: [brightness, brightness, brightness, 1.0],
+                ..still
+            }
+        }
+        TransitionStyle::Slide => OverlayFrame {
+            offset: match direction {
+                TransitionDirection::Forwards => -ease_out_cubic(t),
+                TransitionDirection::Backwards => ease_out_cubic(t),
+            },
+            ..still
+        },
+    }
+}
+
+/// A transition that's playing, with the captured frame it's animating.
+#[derive(Debug)]
+pub struct Transition {
+    style: TransitionStyle,
+    direction: TransitionDirection,
+    elapsed: f32,
+    capture: Sprite,
+    width: f32,
+}
+
+impl Transition {
+    /// Starts animating away `capture`, a frame of the old state the size of the virtual screen.
+    pub fn new(
+        style: TransitionStyle,
+        direction: TransitionDirection,
+        capture: Sprite,
+        width: f32,
+    ) -> Self {
+        Self {
+            style,
+            direction,
+            elapsed: 0.0,
+            capture,
+            width,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32, renderer: &Renderer) {
+        self.elapsed += delta_time;
+
+        let frame = overlay_at(self.style, self.direction, self.progress());
+        self.capture
+            .set_position([frame.offset * self.width, 0.0], renderer);
+        self.capture.set_tint(frame.tint, renderer);
+    }
+
+    /// How far through the transition is, from 0 to 1.
+    fn progress(&self) -> f32 {
+        (self.elapsed / TRANSITION_TIME).clamp(0.0, 1.0)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= TRANSITION_TIME
+    }
+
+    /// Whether the new state should be drawn underneath the old frame yet.
+    pub fn shows_new_state(&self) -> bool {
+        self.style != TransitionStyle::FadeToBlack
+    }
+}
+
+impl Renderable for Transition {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.capture.render(renderer, render_pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STYLES: [TransitionStyle; 3] = [
+        TransitionStyle::Crossfade,
+        TransitionStyle::FadeToBlack,
+        TransitionStyle::Slide,
+    ];
+
+    /// Whether the old frame can't be seen at all any more.
+    fn is_gone(frame: OverlayFrame) -> bool {
+        frame.offset.abs() >= 1.0 || frame.tint[3] <= 0.0 || frame.tint[..3] == [0.0; 3]
+    }
+
+    #[test]
+    fn test_transitions_start_from_the_old_frame() {
+        for style in STYLES {
+            for direction in [
+                TransitionDirection::Forwards,
+                TransitionDirection::Backwards,
+            ] {
+                let frame = overlay_at(style, direction, 0.0);
+                assert_eq!(frame.offset, 0.0, "{style:?}");
+                assert_eq!(frame.tint, [1.0; 4], "{style:?}");
+
+                assert!(is_gone(overlay_at(style, direction, 1.0)), "{style:?}");
+                assert!(!is_gone(overlay_at(style, direction, 0.5)), "{style:?}");
+            }
+        }
+
+        assert!(is_gone(overlay_at(
+            TransitionStyle::Cut,
+            TransitionDirection::Forwards,
+            0.0
+        )));
+    }
+
+    #[test]
+    fn test_slides_go_back_the_way_they_came() {
+        let forwards = overlay_at(TransitionStyle::Slide, TransitionDirection::Forwards, 0.5);
+        let backwards = overlay_at(TransitionStyle::Slide, TransitionDirection::Backwards, 0.5);
+
+        assert!(forwards.offset < 0.0);
+        assert_eq!(forwards.offset, -backwards.offset);
+    }
+
+    #[test]
+    fn test_easing() {
+        for ease in [ease_in_out, ease_out_cubic] {
+            assert_eq!(ease(0.0), 0.0);
+            assert_eq!(ease(1.0), 1.0);
+            assert_eq!(ease(-1.0), 0.0);
+            assert_eq!(ease(2.0), 1.0);
+
+            let samples = (0..=20).map(|i| ease(i as f32 / 20.0)).collect::<Vec<_>>();
+            assert!(samples.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+}
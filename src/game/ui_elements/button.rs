@@ -1,9 +1,9 @@
 use crate::game::Context;
 use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
-use crate::render::text::BuildTextWithRenderer;
+use crate::render::text::{BuildTextWithRenderer, TrackedText};
 use crate::render::Renderer;
 use crate::render::{rgb, Renderable};
-use kaku::{FontSize, Text, TextBuilder};
+use kaku::{FontSize, TextBuilder};
 use winit::event::MouseButton;
 
 pub struct Button {
@@ -14,7 +14,7 @@ pub struct Button {
     outline: Shape,
     hover_outline: Shape,
     hover_overlay: Shape,
-    text: Text,
+    text: TrackedText,
     shadow: Shape,
 }
 
@@ -0,0 +1,268 @@
+use kaku::{FontSize, HorizontalAlignment, TextBuilder, VerticalAlignment};
+
+use crate::difficulty::DIFFICULTIES;
+use crate::notechart_parser::{estimate_disagrees, Song};
+use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
+use crate::render::text::{BuildTextWithRenderer, TrackedText};
+use crate::render::{rgb, Renderable, Renderer};
+use crate::settings::settings_revision;
+
+const BADGES_X: f32 = 560.;
+const BADGES_Y: f32 = 860.;
+const BADGE_SIZE: [f32; 2] = [230., 160.];
+const BADGE_SPACING: f32 = 250.;
+const BADGE_RADIUS: f32 = 16.;
+
+/// The colour of the estimated level when it disagrees with the declared level.
+const DISAGREEING_ESTIMATE_COLOUR: [f32; 4] = rgb!(0xFF, 0xD8, 0x3A);
+
+fn badge_position(difficulty: usize) -> [f32; 2] {
+    [BADGES_X + difficulty as f32 * BADGE_SPACING, BADGES_Y]
+}
+
+/// The visuals for a single difficulty of a song.
+struct DifficultyBadge {
+    difficulty: usize,
+    background: Shape,
+    name: TrackedText,
+    level: TrackedText,
+    estimate: TrackedText,
+    designer: Option<TrackedText>,
+    soflan: Option<(Shape, TrackedText)>,
+}
+
+/// A row of badges showing the difficulties of the highlighted song, along with their level (and
+/// the level estimated from the chart), charter and whether the chart has any BPM or scroll
+/// changes.
+///
+/// Building all the text is expensive, so the badges are only rebuilt when the highlighted song
+/// (or the settings) change. Changing the selected difficulty just moves the selection outline.
+pub struct DifficultyBadges {
+    /// The song id and settings revision the badges were built for
+    key: Option<(usize, u64)>,
+    badges: Vec<DifficultyBadge>,
+    selection_outline: Shape,
+    selected: Option<usize>,
+}
+
+impl DifficultyBadges {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let selection_outline = ShapeBuilder::new()
+            .stroke_roundrect(
+                [-4., -4.],
+                [BADGE_SIZE[0] + 4., BADGE_SIZE[1] + 4.],
+                BADGE_RADIUS + 4.,
+                SolidColour::new([1.; 4]),
+                5.,
+            )?
+            .build(&renderer.device);
+
+        Ok(Self {
+            key: None,
+            badges: Vec::new(),
+            selection_outline,
+            selected: None,
+        })
+    }
+
+    /// Updates the badges to show the given song (and its id), with the given difficulty selected.
+    /// Does nothing if the song and selected difficulty haven't changed since the last call.
+    pub fn update(
+        &mut self,
+        song: Option<(usize, &Song)>,
+        selected_difficulty: usize,
+        renderer: &mut Renderer,
+    ) -> anyhow::Result<()> {
+        let key = song.map(|(id, _)| (id, settings_revision()));
+
+        if key != self.key {
+            self.key = key;
+            self.selected = None;
+            self.badges = match song {
+                Some((_, song)) => build_badges(song, renderer)?,
+                None => Vec::new(),
+            };
+        }
+
+        let selected = self
+            .badges
+            .iter()
+            .any(|badge| badge.difficulty == selected_difficulty)
+            .then_some(selected_difficulty);
+
+        if selected != self.selected {
+            if let Some(difficulty) = selected {
+                let [x, y] = badge_position(difficulty);
+                self.selection_outline.set_position([x, y, 0.], renderer);
+            }
+
+            self.selected = selected;
+        }
+
+        Ok(())
+    }
+}
+
+fn build_badges(song: &Song, renderer: &mut Renderer) -> anyhow::Result<Vec<DifficultyBadge>> {
+    let mut badges = Vec::new();
+
+    for (i, difficulty) in song
+        .difficulties
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| d.as_ref().map(|d| (i, d)))
+    {
+        let [x, y] = badge_position(i);
+        let centre_x = x + BADGE_SIZE[0] / 2.;
+
+        let background = ShapeBuilder::new()
+            .position([x, y, 0.])
+            .filled_roundrect(
+                [0., 0.],
+                BADGE_SIZE,
+                BADGE_RADIUS,
+                SolidColour::new(DIFFICULTIES[i].colour),
+            )?
+            .stroke_roundrect(
+                [0., 0.],
+                BADGE_SIZE,
+                BADGE_RADIUS,
+                SolidColour::new([0., 0., 0., 1.]),
+                3.,
+            )?
+            .build(&renderer.device);
+
+        let name = TextBuilder::new(
+            DIFFICULTIES[i].name,
+            renderer.font("mplus bold"),
+            [centre_x, y + 12.],
+        )
+        .font_size(Some(FontSize::Px(30.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        let level = TextBuilder::new(
+            format!("★{}", difficulty.star_level),
+            renderer.font("mplus bold"),
+            [centre_x, y + BADGE_SIZE[1] / 2. - 5.],
+        )
+        .font_size(Some(FontSize::Px(44.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Middle)
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        // If the estimate is far enough off from the declared level, the declared level is
+        // probably wrong, so make that stand out.
+        let disagrees = estimate_disagrees(difficulty.star_level, difficulty.estimated_level);
+        let estimate = TextBuilder::new(
+            format!(
+                "{}{:.1} (est.)",
+                if disagrees { "! " } else { "" },
+                difficulty.estimated_level
+            ),
+            renderer.font("mplus regular"),
+            [centre_x, y + BADGE_SIZE[1] - 40.],
+        )
+        .font_size(Some(FontSize::Px(18.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Middle)
+        .color(if disagrees {
+            DISAGREEING_ESTIMATE_COLOUR
+        } else {
+            [1.; 4]
+        })
+        .outlined([0., 0., 0., 1.], 2.)
+        .build_text(renderer);
+
+        let designer = difficulty.notes_designer.as_ref().map(|designer| {
+            TextBuilder::new(
+                designer,
+                renderer.font("mplus regular"),
+                [centre_x, y + BADGE_SIZE[1] - 10.],
+            )
+            .font_size(Some(FontSize::Px(18.)))
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Bottom)
+            .color([1.; 4])
+            .outlined([0., 0., 0., 1.], 2.)
+            .build_text(renderer)
+        });
+
+        let soflan = if difficulty.chart.has_scroll_changes() {
+            let tag_position = [x + BADGE_SIZE[0] - 36., y - 12.];
+
+            let tag = ShapeBuilder::new()
+                .position([tag_position[0], tag_position[1], 0.])
+                .filled_roundrect(
+                    [-32., -12.],
+                    [32., 12.],
+                    8.,
+                    SolidColour::new(rgb!(0xFF, 0xD8, 0x3A)),
+                )?
+                .stroke_roundrect(
+                    [-32., -12.],
+                    [32., 12.],
+                    8.,
+                    SolidColour::new([0., 0., 0., 1.]),
+                    2.,
+                )?
+                .build(&renderer.device);
+
+            let text = TextBuilder::new("BPM", renderer.font("mplus bold"), tag_position)
+                .font_size(Some(FontSize::Px(16.)))
+                .horizontal_align(HorizontalAlignment::Center)
+                .vertical_align(VerticalAlignment::Middle)
+                .color([0., 0., 0., 1.])
+                .build_text(renderer);
+
+            Some((tag, text))
+        } else {
+            None
+        };
+
+        badges.push(DifficultyBadge {
+            difficulty: i,
+            background,
+            name,
+            level,
+            estimate,
+            designer,
+            soflan,
+        });
+    }
+
+    Ok(badges)
+}
+
+impl Renderable for DifficultyBadges {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        for badge in self.badges.iter() {
+            badge.background.render(renderer, render_pass);
+            badge.name.render(renderer, render_pass);
+            badge.level.render(renderer, render_pass);
+            badge.estimate.render(renderer, render_pass);
+
+            if let Some(designer) = badge.designer.as_ref() {
+                designer.render(renderer, render_pass);
+            }
+
+            if let Some((tag, text)) = badge.soflan.as_ref() {
+                tag.render(renderer, render_pass);
+                text.render(renderer, render_pass);
+            }
+        }
+
+        if self.selected.is_some() {
+            self.selection_outline.render(renderer, render_pass);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Reordering a list by dragging its entries around.
+use egui::{Pos2, Rect};
+
+/// Keeps track of an entry being dragged around a vertical list, and where it would go if it were
+/// dropped.
+///
+/// Positions in the list are described by "gaps": gap `i` is just before entry `i`, and gap `len`
+/// is after the last entry. The widget showing the list is in charge of hit-testing, by passing
+/// the screen rects of its entries to [DragReorder::hover].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DragReorder {
+    dragging: Option<usize>,
+    gap: Option<usize>,
+}
+
+impl DragReorder {
+    /// Starts dragging the entry at `index`.
+    pub fn start(&mut self, index: usize) {
+        self.dragging = Some(index);
+        self.gap = None;
+    }
+
+    /// The entry currently being dragged, if any.
+    pub fn dragging(&self) -> Option<usize> {
+        self.dragging
+    }
+
+    /// Updates which gap the dragged entry would be dropped into, given where the pointer is and
+    /// the rects of the entries (in order).
+    pub fn hover(&mut self, pointer: Pos2, rows: &[Rect]) {
+        if self.dragging.is_some() {
+            self.gap = Some(gap_at(pointer.y, rows));
+        }
+    }
+
+    /// The gap an insertion marker should be shown at, if any. Nothing is shown for the gaps either
+    /// side of the dragged entry, as dropping it there wouldn't move it.
+    pub fn insertion_marker(&self) -> Option<usize> {
+        let (from, gap) = (self.dragging?, self.gap?);
+        (gap != from && gap != from + 1).then_some(gap)
+    }
+
+    /// Drops the dragged entry, returning the index it was dragged from and the gap it was dropped
+    /// into.
+    pub fn finish(&mut self) -> Option<(usize, usize)> {
+        let result = self.dragging.zip(self.gap);
+        self.cancel();
+        result
+    }
+
+    pub fn cancel(&mut self) {
+        self.dragging = None;
+        self.gap = None;
+    }
+}
+
+/// Which gap a pointer at height `y` is closest to. Rows are split in half, with the top half
+/// belonging to the gap above and the bottom half to the gap below.
+pub fn gap_at(y: f32, rows: &[Rect]) -> usize {
+    rows.iter().take_while(|row| y >= row.center().y).count()
+}
+
+/// The height an insertion marker should be drawn at for a gap: halfway between the rows either
+/// side of it, or at the edge of the list for the first and last gaps.
+pub fn marker_y(gap: usize, rows: &[Rect]) -> Option<f32> {
+    match (gap.checked_sub(1).and_then(|i| rows.get(i)), rows.get(gap)) {
+        (Some(above), Some(below)) => Some((above.bottom() + below.top()) / 2.0),
+        (Some(above), None) => Some(above.bottom()),
+        (None, Some(below)) => Some(below.top()),
+        (None, None) => None,
+    }
+}
+
+/// Moves the item at `from` into `gap`, returning its new index.
+pub fn move_item<T>(items: &mut Vec<T>, from: usize, gap: usize) -> usize {
+    let gap = gap.min(items.len());
+    // Taking the item out shifts everything after it up by one
+    let to = if gap > from { gap - 1 } else { gap };
+
+    let item = items.remove(from);
+    items.insert(to, item);
+    to
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows(count: usize) -> Vec<Rect> {
+        (0..count)
+            .map(|i| Rect::from_min_size(Pos2::new(0.0, i as f32 * 20.0), egui::vec2(100.0, 20.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_move_item() {
+        let items = vec!['a', 'b', 'c', 'd'];
+
+        let mut moved = items.clone();
+        assert_eq!(move_item(&mut moved, 0, 4), 3);
+        assert_eq!(moved, vec!['b', 'c', 'd', 'a']);
+
+        let mut moved = items.clone();
+        assert_eq!(move_item(&mut moved, 3, 0), 0);
+        assert_eq!(moved, vec!['d', 'a', 'b', 'c']);
+
+        let mut moved = items.clone();
+        assert_eq!(move_item(&mut moved, 1, 3), 2);
+        assert_eq!(moved, vec!['a', 'c', 'b', 'd']);
+
+        // The gaps either side of an item leave it where it is
+        for gap in [2, 3] {
+            let mut moved = items.clone();
+            assert_eq!(move_item(&mut moved, 2, gap), 2);
+            assert_eq!(moved, items);
+        }
+
+        // Gaps past the end are clamped
+        let mut moved = items.clone();
+        assert_eq!(move_item(&mut moved, 0, 10), 3);
+        assert_eq!(moved, vec!['b', 'c', 'd', 'a']);
+    }
+
+    #[test]
+    fn test_gap_at() {
+        let rows = rows(3);
+
+        assert_eq!(gap_at(-50.0, &rows), 0);
+        assert_eq!(gap_at(5.0, &rows), 0);
+        assert_eq!(gap_at(15.0, &rows), 1);
+        assert_eq!(gap_at(25.0, &rows), 1);
+        assert_eq!(gap_at(55.0, &rows), 3);
+        assert_eq!(gap_at(500.0, &rows), 3);
+        assert_eq!(gap_at(10.0, &[]), 0);
+    }
+
+    #[test]
+    fn test_marker_y() {
+        let rows = rows(2);
+
+        assert_eq!(marker_y(0, &rows), Some(0.0));
+        assert_eq!(marker_y(1, &rows), Some(20.0));
+        assert_eq!(marker_y(2, &rows), Some(40.0));
+        assert_eq!(marker_y(3, &rows), None);
+        assert_eq!(marker_y(0, &[]), None);
+    }
+
+    #[test]
+    fn test_drag_state() {
+        let rows = rows(4);
+        let mut drag = DragReorder::default();
+
+        // Hovering does nothing until something is being dragged
+        drag.hover(Pos2::new(0.0, 75.0), &rows);
+        assert_eq!(drag.finish(), None);
+
+        drag.start(1);
+        assert_eq!(drag.dragging(), Some(1));
+
+        drag.hover(Pos2::new(0.0, 25.0), &rows);
+        assert_eq!(drag.insertion_marker(), None);
+
+        drag.hover(Pos2::new(0.0, 75.0), &rows);
+        assert_eq!(drag.insertion_marker(), Some(4));
+
+        assert_eq!(drag.finish(), Some((1, 4)));
+        assert_eq!(drag.dragging(), None);
+        assert_eq!(drag.insertion_marker(), None);
+    }
+}
@@ -1,2 +1,12 @@
 mod button;
+mod difficulty_badges;
+mod drag;
+mod offset_profiles;
+mod percentage;
+mod tap_tempo;
 pub use button::*;
+pub use difficulty_badges::*;
+pub use drag::*;
+pub use offset_profiles::*;
+pub use percentage::*;
+pub use tap_tempo::*;
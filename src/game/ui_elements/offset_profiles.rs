@@ -0,0 +1,33 @@
+//! Choosing between offset profiles.
+use crate::settings::OffsetProfile;
+
+/// What the global offset is called when it's shown alongside the offset profiles.
+const GLOBAL_OFFSET_NAME: &str = "Global offset";
+
+/// A combo box for choosing which offset profile is active, or none of them.
+pub fn offset_profile_combo_box(
+    ui: &mut egui::Ui,
+    profiles: &[OffsetProfile],
+    active: &mut Option<usize>,
+) -> egui::Response {
+    let name = |profile: Option<usize>| {
+        profile
+            .and_then(|i| profiles.get(i))
+            .map_or(GLOBAL_OFFSET_NAME, |profile| profile.name.as_str())
+    };
+
+    egui::ComboBox::from_label("Offset profile")
+        .selected_text(name(*active))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(active, None, GLOBAL_OFFSET_NAME);
+
+            for (i, profile) in profiles.iter().enumerate() {
+                ui.selectable_value(
+                    active,
+                    Some(i),
+                    format!("{} ({:+} ms)", profile.name, profile.offset),
+                );
+            }
+        })
+        .response
+}
@@ -0,0 +1,16 @@
+//! Sliders for settings that go from 0 to 1, shown as percentages.
+
+/// A slider for a value from 0 to 1, which is shown (and can be typed in) as a percentage.
+pub fn percentage_slider<'a>(value: &'a mut f32, text: &str) -> egui::Slider<'a> {
+    egui::Slider::new(value, 0.0..=1.0)
+        .step_by(0.05)
+        .custom_formatter(|value, _| format!("{:.0}%", value * 100.0))
+        .custom_parser(|text| {
+            text.trim()
+                .trim_end_matches('%')
+                .parse::<f64>()
+                .ok()
+                .map(|percent| percent / 100.0)
+        })
+        .text(text)
+}
@@ -0,0 +1,338 @@
+//! Working out the BPM of a song by tapping along to it.
+use std::time::Instant;
+
+use egui::{Color32, RichText};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::game::taiko_mode::robust_median;
+use crate::settings::key_name;
+
+/// The key that's tapped along to the song while the tap tempo tool is open.
+pub const TAP_KEY: KeyCode = KeyCode::KeyT;
+
+/// If there's a gap this long between taps (in seconds), the next tap starts a new measurement.
+const TAP_TIMEOUT: f64 = 2.0;
+
+/// Only the most recent taps are kept, so that old mistakes stop counting eventually.
+const MAX_TAPS: usize = 64;
+
+/// The fewest gaps between taps that a tempo will be worked out from.
+const MIN_INTERVALS: usize = 3;
+
+/// A measured BPM is flagged as different to the declared one if it's further off than this, as a
+/// fraction of the declared BPM.
+pub const BPM_TOLERANCE: f64 = 0.01;
+
+/// How much a tempo worked out from taps can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl Confidence {
+    fn label(&self) -> RichText {
+        match self {
+            Confidence::Low => RichText::new("low confidence").color(Color32::LIGHT_RED),
+            Confidence::Medium => RichText::new("medium confidence").color(Color32::YELLOW),
+            Confidence::High => RichText::new("high confidence").color(Color32::LIGHT_GREEN),
+        }
+    }
+}
+
+/// A tempo worked out from a series of taps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoEstimate {
+    pub bpm: f64,
+    pub confidence: Confidence,
+    /// How many gaps between taps the tempo was worked out from.
+    pub intervals: usize,
+    /// How many gaps were thrown away, e.g. because a tap was missed.
+    pub rejected: usize,
+}
+
+/// Works out the tempo of a series of taps (in seconds, in order), from the median gap between
+/// them. Gaps that are way off the rest, like from missed or doubled taps, are ignored. Returns None
+/// if there aren't enough taps yet.
+///
+/// The confidence comes from how precisely the median gap is known: a high confidence estimate is
+/// good enough to tell whether a chart's BPM is off by more than [BPM_TOLERANCE].
+pub fn estimate_tempo(taps: &[f64]) -> Option<TempoEstimate> {
+    let intervals = taps
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .filter(|&interval| interval > 0.0)
+        .collect::<Vec<_>>();
+
+    if intervals.len() < MIN_INTERVALS {
+        return None;
+    }
+
+    let interval = robust_median(&intervals)?;
+    let relative_error = interval.spread / (interval.kept as f64).sqrt() / interval.median;
+
+    let confidence = if interval.kept < 2 * MIN_INTERVALS {
+        Confidence::Low
+    } else if relative_error < BPM_TOLERANCE / 2.0 {
+        Confidence::High
+    } else if relative_error < BPM_TOLERANCE {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+
+    Some(TempoEstimate {
+        bpm: 60.0 / interval.median,
+        confidence,
+        intervals: interval.kept,
+        rejected: interval.rejected,
+    })
+}
+
+/// How a tapped BPM compares to the BPM a chart declares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BpmComparison {
+    /// What the tapped BPM had to be multiplied by to get closest to the declared one. People
+    /// often tap along at half or double the tempo, so this is 0.5, 1 or 2.
+    pub multiple: f64,
+    /// How far the (multiplied) tapped BPM is from the declared one, as a fraction of the declared
+    /// BPM.
+    pub difference: f64,
+}
+
+impl BpmComparison {
+    pub fn agrees(&self) -> bool {
+        self.difference.abs() <= BPM_TOLERANCE
+    }
+}
+
+/// Compares a tapped BPM to a declared one, allowing for the taps being at half or double speed.
+pub fn compare_bpm(tapped: f64, declared: f64) -> BpmComparison {
+    [1.0, 0.5, 2.0]
+        .into_iter()
+        .map(|multiple| BpmComparison {
+            multiple,
+            difference: (tapped * multiple - declared) / declared,
+        })
+        .min_by(|a, b| a.difference.abs().total_cmp(&b.difference.abs()))
+        .unwrap()
+}
+
+/// A tool for measuring the tempo of whatever is playing, by tapping [TAP_KEY] (or clicking a
+/// button) along to the beat.
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    /// The times of the taps in the current measurement, in seconds since `start`.
+    taps: Vec<f64>,
+    start: Option<Instant>,
+}
+
+impl TapTempo {
+    /// Records a tap at the given moment. Taps should be recorded as soon as the key is pressed,
+    /// rather than once a frame, so that the gaps between them are accurate.
+    pub fn tap(&mut self, at: Instant) {
+        let start = *self.start.get_or_insert(at);
+        let time = at.saturating_duration_since(start).as_secs_f64();
+
+        if self
+            .taps
+            .last()
+            .is_some_and(|&last| time - last > TAP_TIMEOUT)
+        {
+            self.taps.clear();
+        }
+
+        self.taps.push(time);
+
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.taps.clear();
+        self.start = None;
+    }
+
+    /// Shows the measured tempo, with the half and double tempos in case the taps were at the wrong
+    /// speed. If the BPM of a chart is given, it's compared against the measured one, and if
+    /// `can_apply` is true there's a button to use the measured BPM instead. Returns the BPM to use
+    /// if that button was clicked.
+    pub fn ui(&mut self, ui: &mut egui::Ui, declared: Option<f32>, can_apply: bool) -> Option<f64> {
+        let mut apply = None;
+
+        ui.label(format!(
+            "Tap {} (or the button) along to the beat.",
+            key_name(PhysicalKey::Code(TAP_KEY))
+        ));
+
+        ui.horizontal(|ui| {
+            if ui.button("Tap").clicked() {
+                self.tap(Instant::now());
+            }
+
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+
+            ui.label(format!("{} taps", self.taps.len()));
+        });
+
+        let Some(estimate) = estimate_tempo(&self.taps) else {
+            ui.label(format!(
+                "Keep tapping: at least {} taps are needed",
+                MIN_INTERVALS + 1
+            ));
+            return None;
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(
+                RichText::new(format!("{:.2} BPM", estimate.bpm))
+                    .size(24.0)
+                    .strong(),
+            );
+            ui.label(estimate.confidence.label());
+        });
+
+        ui.label(format!(
+            "Half: {:.2}, double: {:.2}",
+            estimate.bpm / 2.0,
+            estimate.bpm * 2.0
+        ));
+
+        ui.label(format!(
+            "From {} gaps between taps, ignoring {} stray ones",
+            estimate.intervals, estimate.rejected
+        ));
+
+        if let Some(declared) = declared {
+            let comparison = compare_bpm(estimate.bpm, declared as f64);
+            let text = if comparison.multiple < 1.0 {
+                format!("half of the tapped tempo is {declared} BPM")
+            } else if comparison.multiple > 1.0 {
+                format!("double the tapped tempo is {declared} BPM")
+            } else {
+                format!("the chart says {declared} BPM")
+            };
+
+            if comparison.agrees() {
+                ui.label(format!("Matches: {text}"));
+            } else {
+                ui.label(
+                    RichText::new(format!(
+                        "{:+.1}% off: {text}",
+                        comparison.difference * 100.0
+                    ))
+                    .color(Color32::YELLOW),
+                );
+            }
+        }
+
+        if can_apply {
+            let bpm = (estimate.bpm * 100.0).round() / 100.0;
+
+            ui.horizontal(|ui| {
+                for (label, multiple) in [("Use", 1.0), ("Use half", 0.5), ("Use double", 2.0)] {
+                    if ui
+                        .button(format!("{label} ({:.2})", bpm * multiple))
+                        .clicked()
+                    {
+                        apply = Some(bpm * multiple);
+                    }
+                }
+            });
+        }
+
+        apply
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn taps(interval: f64, count: usize) -> Vec<f64> {
+        (0..count).map(|i| i as f64 * interval).collect()
+    }
+
+    #[test]
+    fn test_estimate_tempo() {
+        assert_eq!(estimate_tempo(&taps(0.5, 3)), None);
+
+        let steady = estimate_tempo(&taps(0.5, 20)).unwrap();
+        assert!((steady.bpm - 120.0).abs() < 1e-9);
+        assert_eq!(steady.confidence, Confidence::High);
+
+        // A few taps aren't enough to be sure, however steady they are
+        assert_eq!(
+            estimate_tempo(&taps(0.5, 4)).unwrap().confidence,
+            Confidence::Low
+        );
+    }
+
+    #[test]
+    fn test_estimate_tempo_ignores_stray_taps() {
+        // Taps at 150 BPM with some wobble, one missed tap and one extra tap
+        let mut times = Vec::new();
+        let mut time = 0.0;
+        for i in 0..24 {
+            if i == 10 {
+                // Missed, so the next gap is twice as long
+                time += 0.4;
+                continue;
+            }
+            if i == 16 {
+                times.push(time - 0.1);
+            }
+
+            let wobble = [0.0, 0.003, 0.0, -0.003][i % 4];
+            times.push(time + wobble);
+            time += 0.4;
+        }
+
+        let estimate = estimate_tempo(&times).unwrap();
+        assert!((estimate.bpm - 150.0).abs() < 1.0, "{estimate:?}");
+        assert!(estimate.rejected >= 3, "{estimate:?}");
+        assert!(estimate.confidence >= Confidence::Medium, "{estimate:?}");
+
+        // Erratic tapping isn't trusted
+        let erratic = [0.0, 0.3, 0.9, 1.3, 1.5, 2.1, 2.3, 2.9, 3.4, 3.6];
+        assert_eq!(
+            estimate_tempo(&erratic).unwrap().confidence,
+            Confidence::Low
+        );
+    }
+
+    #[test]
+    fn test_compare_bpm() {
+        let same = compare_bpm(120.5, 120.0);
+        assert_eq!(same.multiple, 1.0);
+        assert!(same.agrees());
+
+        let off = compare_bpm(122.0, 120.0);
+        assert!(!off.agrees());
+        assert!((off.difference - 1.0 / 60.0).abs() < 1e-9);
+
+        // Tapping every other beat, or on every half beat
+        assert_eq!(compare_bpm(90.0, 180.0).multiple, 2.0);
+        assert!(compare_bpm(90.0, 180.0).agrees());
+        assert_eq!(compare_bpm(360.5, 180.0).multiple, 0.5);
+        assert!(compare_bpm(360.5, 180.0).agrees());
+    }
+
+    #[test]
+    fn test_taps_after_a_pause_start_again() {
+        let start = Instant::now();
+        let mut tempo = TapTempo::default();
+
+        for i in 0..5 {
+            tempo.tap(start + std::time::Duration::from_millis(i * 500));
+        }
+        assert_eq!(tempo.taps.len(), 5);
+
+        tempo.tap(start + std::time::Duration::from_secs(10));
+        assert_eq!(tempo.taps.len(), 1);
+    }
+}
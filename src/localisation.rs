@@ -0,0 +1,116 @@
+//! Looking up the text shown to the player, so that it can be translated.
+//!
+//! Each language is a toml file in [LANGUAGES_PATH], named after its language code (e.g.
+//! `en.toml`). Text is looked up by its key, which is the path to it in the file: `help.title` is
+//! the `title` key in the `[help]` table. English is built into the game, and is used for any key
+//! the chosen language doesn't have.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+/// The folder the language files are kept in.
+pub const LANGUAGES_PATH: &str = "assets/lang";
+
+lazy_static! {
+    static ref ENGLISH: HashMap<String, String> =
+        parse_language(include_str!("../assets/lang/en.toml"))
+            .expect("the english language file should be valid");
+    /// The text of the chosen language, if it isn't english.
+    static ref LANGUAGE: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// Reads a language file into a map from each key to its text.
+fn parse_language(contents: &str) -> anyhow::Result<HashMap<String, String>> {
+    fn flatten(prefix: &str, table: toml::Table, strings: &mut HashMap<String, String>) {
+        for (key, value) in table {
+            let key = if prefix.is_empty() {
+                key
+            } else {
+                format!("{prefix}.{key}")
+            };
+
+            match value {
+                toml::Value::String(text) => {
+                    strings.insert(key, text);
+                }
+                toml::Value::Table(table) => flatten(&key, table, &mut *strings),
+                _ => log::warn!("language key {key} isn't text, ignoring it"),
+            }
+        }
+    }
+
+    let mut strings = HashMap::new();
+    flatten("", toml::from_str(contents)?, &mut strings);
+    Ok(strings)
+}
+
+/// Switches to the language with the given code. Text that has already been shown won't change
+/// until it's looked up again.
+pub fn load_language(code: &str) -> anyhow::Result<()> {
+    let strings = if code == "en" {
+        HashMap::new()
+    } else {
+        parse_language(&std::fs::read_to_string(format!(
+            "{LANGUAGES_PATH}/{code}.toml"
+        ))?)?
+    };
+
+    *LANGUAGE.write().unwrap() = strings;
+    Ok(())
+}
+
+/// Returns the text with the given key in the current language. If there's no such key, the key
+/// itself is returned, so that it's obvious what's missing.
+pub fn tr(key: &str) -> String {
+    LANGUAGE
+        .read()
+        .unwrap()
+        .get(key)
+        .or_else(|| ENGLISH.get(key))
+        .cloned()
+        .unwrap_or_else(|| {
+            log::warn!("no text for language key {key}");
+            key.to_string()
+        })
+}
+
+/// Like [tr], but replaces each `{name}` in the text with the value given for that name.
+pub fn tr_with(key: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+    args.iter().fold(tr(key), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), &value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_language() {
+        let strings = parse_language(
+            "
+            [help]
+            title = \"Hilfe\"
+
+            [help.controls]
+            title = \"Steuerung\"
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings["help.title"], "Hilfe");
+        assert_eq!(strings["help.controls.title"], "Steuerung");
+    }
+
+    #[test]
+    fn test_tr() {
+        assert_eq!(tr("difficulty.oni"), "Oni");
+        assert_eq!(tr("no.such.key"), "no.such.key");
+        assert_eq!(
+            tr_with("help.page", &[("page", &2), ("pages", &4)]),
+            "2 / 4"
+        );
+    }
+}
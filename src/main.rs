@@ -1,8 +1,13 @@
 mod app;
+mod audio;
+mod difficulty;
 mod game;
+mod localisation;
 mod notechart_parser;
 mod render;
+mod rng;
 mod settings;
+mod skin;
 
 use app::TaikoApp;
 use winit::event_loop::EventLoop;
@@ -10,6 +15,18 @@ use winit::event_loop::EventLoop;
 fn main() {
     settings::read_settings();
 
+    if let Some(language) = settings::settings().game.language.clone() {
+        if let Err(e) = localisation::load_language(&language) {
+            eprintln!("Couldn't load language \"{language}\": {e}. Continuing in english...");
+        }
+    }
+
+    if let Some(skin) = settings::settings().visual.skin.clone() {
+        if let Err(e) = skin::load_skin(Some(&skin)) {
+            eprintln!("Couldn't load skin \"{skin}\": {e}. Continuing with the default skin...");
+        }
+    }
+
     let event_loop = EventLoop::new().expect("Couldn't construct window event loop!");
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     event_loop.run_app(&mut TaikoApp::new()).unwrap()
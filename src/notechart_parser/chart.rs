@@ -3,20 +3,30 @@
 //!
 //! The types in here so far are very minimal, and can't handle all of the
 //! possible songs that are defined in taiko, yet. Eventually I plan on
-//! accommodating these variations (such as diverging difficulty, different
-//! tracks for different players etc).
+//! accommodating these variations (such as different tracks for different
+//! players etc).
 //!
 //! Note that times are generally represented in seconds. Unless specified,
 //! that is the unit the time values will be in.
+//!
+//! These types can be serialized, which is used to compare parsed songs against the expected
+//! results in the parser tests.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::difficulty::DifficultyInfo;
 
-use std::collections::HashMap;
+use super::TJAParseWarning;
 
 const DEFAULT_BPM: f32 = 120.0;
 
 /// The type of note (e.g., Don, Ka, Balloon etc)
 ///
 /// Drumroll variants also contain a float value indicating how long the drumroll continues for.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum NoteType {
     Don,
     Kat,
@@ -54,29 +64,118 @@ impl NoteType {
 ///
 /// A note has a type, the time (from the song start) that it has
 /// to be hit on, and a constant speed.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub struct Note {
     pub note_type: NoteType,
-    pub time: f32,
+    pub time: f64,
     /// The scroll speed as a multiple of the default speed.
     ///
     /// Default speed is such that at 120bpm, exactly one bar of notes is displayed on the screen.
     /// This will automatically be scaled with frame rate, so default scroll for notes at 240bpm
     /// will be 2.0.
     pub scroll_speed: f32,
+    /// How fast the note moves down the screen towards the judgement line, in the same units as
+    /// `scroll_speed`. This comes from the imaginary part of a complex scroll speed (e.g. `#SCROLL
+    /// 1+2i`), and is usually 0. Notes with a positive speed come from above, since the imaginary
+    /// axis points up.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub vertical_speed: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub struct Barline {
     pub time: f32,
     pub scroll_speed: f32,
+    /// How fast the barline moves down the screen, like [Note::vertical_speed].
+    #[serde(skip_serializing_if = "is_zero")]
+    pub vertical_speed: f32,
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+/// A stretch of a chart in "go-go time" (between `#GOGOSTART` and `#GOGOEND`), in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GogoRegion {
+    pub start: f32,
+    pub end: f32,
+}
+
+/// A change in the tempo of a chart (from `#BPMCHANGE` in a TJA file), at a time in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BpmChange {
+    pub time: f32,
+    pub bpm: f32,
+}
+
+/// A pause in a chart (from `#DELAY` in a TJA file), at a time in seconds. Everything after it is
+/// pushed back by its length.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Delay {
+    pub time: f32,
+    pub length: f32,
+}
+
+/// How the notes of a chart move towards the judgement line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum ScrollMode {
+    /// Each note moves at its own speed, set by the tempo and scroll speed it was written at, and
+    /// notes keep moving through a delay.
+    #[default]
+    Normal,
+    /// `#BMSCROLL`: notes are placed by beat, like in BMS games. The whole track speeds up and
+    /// slows down with the tempo and stops during a delay, and `#SCROLL` is ignored.
+    Bms,
+    /// `#HBSCROLL`: like [ScrollMode::Bms], but `#SCROLL` still changes how fast each note moves.
+    Hbs,
+}
+
+impl ScrollMode {
+    pub fn is_normal(&self) -> bool {
+        *self == ScrollMode::Normal
+    }
+}
+
+/// Which side of song select a song is shown on (`SIDE` in a TJA file). In the arcade, the
+/// "extra" side is the one with the hidden ura charts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SongSide {
+    Normal,
+    Extra,
+    #[default]
+    Both,
+}
+
+/// The languages a song's title can be translated into in its chart, by the code that ends the
+/// metadata giving the translation (e.g. `TITLEEN` and `SUBTITLEEN` for english), along with the
+/// name of each language.
+pub const TITLE_LANGUAGES: [(&str, &str); 5] = [
+    ("EN", "English"),
+    ("JA", "Japanese"),
+    ("CN", "Chinese (simplified)"),
+    ("TW", "Chinese (traditional)"),
+    ("KO", "Korean"),
+];
+
+/// A song's title and subtitle in another language. Whichever of them isn't translated is shown as
+/// it is in the original.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalisedTitle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
 }
 
 /// The data for a song, including its metadata and difficulties/note tracks.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Song {
     pub title: String,
     pub subtitle: Option<String>,
+    /// The title and subtitle in other languages, by language code (one of [TITLE_LANGUAGES]).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub localised_titles: BTreeMap<String, LocalisedTitle>,
     pub audio_filename: String,
     pub bpm: f32,
     /// The offset of the notes in seconds.
@@ -85,7 +184,50 @@ pub struct Song {
     pub offset: f32,
     /// The time that the song preview should start from.
     pub demostart: f32,
+    /// How loud the song's audio should be played, as a percentage (`SONGVOL`).
+    pub song_volume: f32,
+    /// How loud the drum should be while the song is played, as a percentage (`SEVOL`).
+    pub effects_volume: f32,
+    pub side: SongSide,
+    /// The genre the song is filed under in song select (`GENRE`), if it has one.
+    pub genre: Option<String>,
+    #[serde(serialize_with = "serialize_difficulties")]
     pub difficulties: [Option<Difficulty>; 5],
+    /// The TJA file the song was read from, if it was read from one. This is where the editor
+    /// saves changes to.
+    #[serde(skip)]
+    pub chart_path: Option<PathBuf>,
+    /// Problems with the chart that were worked around while parsing it, like mistakes that were
+    /// worked around in lenient mode, or courses that were left out for having no notes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<TJAParseWarning>,
+    /// Whether only the song's metadata has been read, and not its notes. The difficulties of
+    /// songs like this have their levels, but empty charts, so the whole chart file has to be read
+    /// again before they can be played or edited.
+    #[serde(skip)]
+    pub metadata_only: bool,
+    /// The hash of each difficulty's chart, for songs that only have their metadata, so that their
+    /// scores can be found without reading their charts (see [Song::course_hash]).
+    #[serde(skip)]
+    pub chart_hashes: Vec<Option<String>>,
+}
+
+/// Serializes the difficulties of a song as a map from the name of each difficulty to its chart,
+/// leaving out the difficulties the song doesn't have.
+fn serialize_difficulties<S: Serializer>(
+    difficulties: &[Option<Difficulty>; 5],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        difficulties
+            .iter()
+            .enumerate()
+            .filter_map(|(i, difficulty)| {
+                difficulty
+                    .as_ref()
+                    .map(|difficulty| (DifficultyInfo::name_of(i), difficulty))
+            }),
+    )
 }
 
 impl Default for Song {
@@ -93,33 +235,410 @@ impl Default for Song {
         Self {
             title: "".to_string(),
             subtitle: None,
+            localised_titles: BTreeMap::new(),
             audio_filename: "".to_string(),
             bpm: DEFAULT_BPM,
             offset: 0.0,
             demostart: 0.0,
+            song_volume: 100.0,
+            effects_volume: 100.0,
+            side: SongSide::Both,
+            genre: None,
             difficulties: [None, None, None, None, None],
+            chart_path: None,
+            warnings: Vec::new(),
+            metadata_only: false,
+            chart_hashes: Vec::new(),
         }
     }
 }
 
+impl Song {
+    /// The song's title in the given language (see [TITLE_LANGUAGES]), or its original title if
+    /// it hasn't been translated into that language or no language is given.
+    pub fn title_in(&self, language: Option<&str>) -> &str {
+        self.localised(language)
+            .and_then(|localised| localised.title.as_deref())
+            .unwrap_or(&self.title)
+    }
+
+    /// The song's subtitle in the given language, like [Song::title_in].
+    pub fn subtitle_in(&self, language: Option<&str>) -> Option<&str> {
+        self.localised(language)
+            .and_then(|localised| localised.subtitle.as_deref())
+            .or(self.subtitle.as_deref())
+    }
+
+    fn localised(&self, language: Option<&str>) -> Option<&LocalisedTitle> {
+        self.localised_titles.get(language?)
+    }
+
+    /// Every title and subtitle the song has, in every language.
+    pub fn all_titles(&self) -> impl Iterator<Item = &str> {
+        let localised = self
+            .localised_titles
+            .values()
+            .flat_map(|localised| [&localised.title, &localised.subtitle]);
+
+        std::iter::once(self.title.as_str()).chain(
+            [&self.subtitle]
+                .into_iter()
+                .chain(localised)
+                .filter_map(Option::as_deref),
+        )
+    }
+
+    /// The hash of one difficulty's chart (see [chart_hash](super::chart_hash)), if the song has
+    /// that difficulty.
+    pub fn course_hash(&self, difficulty: usize) -> Option<String> {
+        if self.metadata_only {
+            return self.chart_hashes.get(difficulty).cloned().flatten();
+        }
+
+        let course = self.difficulties.get(difficulty)?.as_ref()?;
+        Some(super::chart_hash(&course.chart).to_string())
+    }
+}
+
 /// A single difficulty setting and its associated chart.
 ///
-/// TODO: currently this cannot handle "Diverge Notes". see [NoteChart]
-/// for details. It also cannot handle multiple tracks for different
-/// players.
-#[derive(Debug, Clone)]
+/// Some TJA files give separate charts for each player of a difficulty (`#START P1` and
+/// `#START P2`), for playing together. Those are kept in [Difficulty::player_charts], and the
+/// chart played alone is player 1's, unless there's also one for a single player.
+#[derive(Debug, Clone, Serialize)]
 pub struct Difficulty {
     pub star_level: u8,
+    /// The star level estimated from the notes of the chart (see [estimate_difficulty]). This is
+    /// worked out once when the chart is parsed, since it has to go through every note.
+    ///
+    /// [estimate_difficulty]: super::estimate_difficulty
+    pub estimated_level: f32,
+    /// The person who charted this difficulty, if given (`NOTESDESIGNER`).
+    pub notes_designer: Option<String>,
     pub chart: NoteChart,
+    /// The charts for player 1 and player 2 when playing together, if the difficulty has them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_charts: Option<[NoteChart; 2]>,
+}
+
+/// One of the three versions of a branched section of a chart, from easiest to hardest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum Branch {
+    #[default]
+    Normal,
+    Expert,
+    Master,
+}
+
+impl Branch {
+    pub const ALL: [Branch; 3] = [Branch::Normal, Branch::Expert, Branch::Master];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Branch::Normal => "Normal",
+            Branch::Expert => "Expert",
+            Branch::Master => "Master",
+        }
+    }
+}
+
+/// What decides which branch of a section is played, and how well the player has to do to get
+/// the expert and master branches. Anything below the expert threshold gets the normal branch.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum BranchCondition {
+    /// The player's accuracy as a percentage (`p` in a TJA file), where a Good counts for a full
+    /// note and an Ok counts for half a note.
+    Accuracy { expert: f32, master: f32 },
+    /// The number of times drumrolls and balloons were hit (`r` in a TJA file).
+    Drumrolls { expert: f32, master: f32 },
+}
+
+/// The notes and barlines of one branch of a [BranchSection].
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct BranchNotes {
+    pub notes: Vec<Note>,
+    pub barlines: Vec<Barline>,
+}
+
+/// A part of a chart (between `#BRANCHSTART` and `#BRANCHEND`) that has different notes depending
+/// on how well the player has been doing ("diverge notes").
+#[derive(Debug, Clone, Serialize)]
+pub struct BranchSection {
+    pub condition: BranchCondition,
+    /// When the section starts.
+    pub start: f64,
+    /// When the branch is chosen, which is one measure before the section starts.
+    pub decision_time: f64,
+    /// The start of the part of the chart the player's performance is judged on, i.e. the last
+    /// `#SECTION` command or the start of the chart. Only notes between this and the decision
+    /// time count.
+    pub measured_from: f64,
+    /// The notes of each branch, indexed by [Branch] (normal, expert, master).
+    pub branches: [BranchNotes; 3],
 }
 
 /// The notes for a single difficulty setting.
 ///
-/// TODO: Currently, this is just a linear stream of notes. Eventually
-/// we will have to handle songs with multiple streams that switch
-/// depending on the player's performance ("diverge notes").
-#[derive(Default, Debug, Clone)]
+/// Notes in branched sections aren't in `notes` or `barlines`, but in the branches of each
+/// section. Use [NoteChart::all_notes] to go through all of them at once.
+#[derive(Default, Debug, Clone, Serialize)]
 pub struct NoteChart {
     pub notes: Vec<Note>,
     pub barlines: Vec<Barline>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub gogo_regions: Vec<GogoRegion>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub branch_sections: Vec<BranchSection>,
+    /// The tempo of the chart over time, in order, starting with the tempo it starts at. The
+    /// tempo changes of every branch are mixed in together, since branches almost always share
+    /// theirs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bpm_changes: Vec<BpmChange>,
+    /// Every delay in the chart, in order, with those of every branch mixed in like the tempo
+    /// changes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub delays: Vec<Delay>,
+    #[serde(skip_serializing_if = "ScrollMode::is_normal")]
+    pub scroll_mode: ScrollMode,
+}
+
+impl NoteChart {
+    /// Whether the notes in the chart don't all move at the same speed, i.e. the chart has BPM
+    /// or scroll speed changes (known as "soflan").
+    pub fn has_scroll_changes(&self) -> bool {
+        // When the whole track moves by beat, tempo changes and delays change its speed instead
+        if !self.scroll_mode.is_normal()
+            && (!self.delays.is_empty()
+                || self
+                    .bpm_changes
+                    .windows(2)
+                    .any(|pair| pair[0].bpm != pair[1].bpm))
+        {
+            return true;
+        }
+
+        let mut speeds = self.notes.iter().map(|note| note.scroll_speed);
+
+        match speeds.next() {
+            Some(first) => speeds.any(|speed| speed != first),
+            None => false,
+        }
+    }
+
+    /// The tempo of the chart at the given time, or None if the chart doesn't say what it is.
+    pub fn bpm_at(&self, time: f32) -> Option<f32> {
+        let index = self
+            .bpm_changes
+            .partition_point(|change| change.time <= time);
+
+        // Anything before the first change is at the tempo the chart starts at
+        self.bpm_changes
+            .get(index.saturating_sub(1))
+            .map(|change| change.bpm)
+    }
+
+    /// How far the track has scrolled by the given time, which is what notes are placed by when
+    /// they're drawn. Normally this is just the time itself, but when the track moves by beat (see
+    /// [ScrollMode]) it goes faster or slower with the tempo, and stops during delays. At 120 BPM,
+    /// it goes at the same rate as time does.
+    pub fn scroll_time(&self, time: f32) -> f32 {
+        let Some(first) = self.bpm_changes.first() else {
+            return time;
+        };
+
+        if self.scroll_mode.is_normal() {
+            return time;
+        }
+
+        // How long the track is stopped for between two times
+        let stopped = |start: f32, end: f32| -> f32 {
+            self.delays
+                .iter()
+                .map(|delay| {
+                    let overlap = end.min(delay.time + delay.length) - start.max(delay.time);
+                    overlap.max(0.0)
+                })
+                .sum()
+        };
+
+        // Anything before the chart starts moves at the tempo it starts at
+        if time < first.time {
+            return first.time + (time - first.time) * first.bpm / DEFAULT_BPM;
+        }
+
+        let mut scroll_time = first.time;
+        for (i, change) in self.bpm_changes.iter().enumerate() {
+            let end = self
+                .bpm_changes
+                .get(i + 1)
+                .map_or(time, |next| next.time.min(time));
+
+            if end > change.time {
+                let moving = end - change.time - stopped(change.time, end);
+                scroll_time += moving * change.bpm / DEFAULT_BPM;
+            }
+        }
+
+        scroll_time
+    }
+
+    /// A note as it's drawn, with its time (and length, if it's a roll) in
+    /// [scroll time](NoteChart::scroll_time).
+    pub fn scrolled_note(&self, note: Note) -> Note {
+        let time = self.scroll_time(note.time as f32);
+        let length = |length: f32| self.scroll_time(note.time as f32 + length) - time;
+
+        let note_type = match note.note_type {
+            NoteType::Roll(duration) => NoteType::Roll(length(duration)),
+            NoteType::BigRoll(duration) => NoteType::BigRoll(length(duration)),
+            NoteType::BalloonRoll(duration, hits) => NoteType::BalloonRoll(length(duration), hits),
+            NoteType::SpecialRoll(duration, hits) => NoteType::SpecialRoll(length(duration), hits),
+            note_type => note_type,
+        };
+
+        Note {
+            note_type,
+            time: time as f64,
+            ..note
+        }
+    }
+
+    /// A barline as it's drawn, at its [scroll time](NoteChart::scroll_time).
+    pub fn scrolled_barline(&self, barline: Barline) -> Barline {
+        Barline {
+            time: self.scroll_time(barline.time),
+            ..barline
+        }
+    }
+
+    /// Whether the given time is in go-go time.
+    pub fn is_gogo(&self, time: f32) -> bool {
+        self.gogo_regions
+            .iter()
+            .any(|region| (region.start..region.end).contains(&time))
+    }
+
+    /// Every note in the chart in order of time, including the notes of every branch (see
+    /// [merge_branches]). Each note
+    /// comes with the index of the branched section and the branch it's in, or None if it's played
+    /// whichever branch the player is on.
+    pub fn all_notes(&self) -> Vec<(Note, Option<(usize, Branch)>)> {
+        merge_branches(&self.notes, &self.branch_sections, |branch| &branch.notes)
+            .into_iter()
+            .map(|(note, tag)| (*note, tag))
+            .collect()
+    }
+
+    /// The chart as it's played when the player is on the given branch the whole way through, with
+    /// no branched sections left in it.
+    pub fn on_branch(&self, branch: Branch) -> NoteChart {
+        self.on_branches(|_| branch)
+    }
+
+    /// The chart as it's played when the player is on the branch `branch_of` gives for each
+    /// branched section (by its index), with no branched sections left in it.
+    pub fn on_branches(&self, branch_of: impl Fn(usize) -> Branch) -> NoteChart {
+        let taken = |tag: &Option<(usize, Branch)>| {
+            tag.is_none_or(|(section, branch)| branch_of(section) == branch)
+        };
+
+        NoteChart {
+            notes: self
+                .all_notes()
+                .into_iter()
+                .filter(|(_, tag)| taken(tag))
+                .map(|(note, _)| note)
+                .collect(),
+            barlines: self
+                .all_barlines()
+                .into_iter()
+                .filter(|(_, tag)| taken(tag))
+                .map(|(barline, _)| barline)
+                .collect(),
+            gogo_regions: self.gogo_regions.clone(),
+            bpm_changes: self.bpm_changes.clone(),
+            delays: self.delays.clone(),
+            scroll_mode: self.scroll_mode,
+            branch_sections: Vec::new(),
+        }
+    }
+
+    /// Every barline in the chart in order of time, including the barlines of every branch, along
+    /// with where they are in the same way as [NoteChart::all_notes].
+    pub fn all_barlines(&self) -> Vec<(Barline, Option<(usize, Branch)>)> {
+        merge_branches(&self.barlines, &self.branch_sections, |branch| {
+            &branch.barlines
+        })
+        .into_iter()
+        .map(|(barline, tag)| (*barline, tag))
+        .collect()
+    }
+
+    /// The time of the start of the measure playing at `time`, i.e. the last barline at or before
+    /// it. Measures with their barline hidden count as part of the measure before them, and
+    /// anything before the first barline is snapped to the start of the song.
+    pub fn measure_start(&self, time: f32) -> f32 {
+        self.barlines
+            .iter()
+            .map(|barline| barline.time)
+            .filter(|&start| start <= time)
+            .max_by(f32::total_cmp)
+            .unwrap_or(0.0)
+    }
+
+    /// The time of the end of the measure playing at `time`, i.e. the first barline after it, or
+    /// None if it's in the last measure. As with [NoteChart::measure_start], measures with their
+    /// barline hidden count as part of the measure before them.
+    pub fn measure_end(&self, time: f32) -> Option<f32> {
+        self.barlines
+            .iter()
+            .map(|barline| barline.time)
+            .filter(|&end| end > time)
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// Something in a chart that happens at a point in time.
+trait Timed {
+    fn time(&self) -> f32;
+}
+
+impl Timed for Note {
+    fn time(&self) -> f32 {
+        self.time as f32
+    }
+}
+
+impl Timed for Barline {
+    fn time(&self) -> f32 {
+        self.time
+    }
+}
+
+/// Puts the common items of a chart together with the items of every branch, sorted by time. The
+/// sort is stable, so items at the same time stay in the order they were written in. Charts with
+/// no branches are left in the order they were written in, even if (e.g. because of a negative
+/// `#DELAY`) that isn't quite in order of time.
+fn merge_branches<'a, T: Timed>(
+    common: &'a [T],
+    sections: &'a [BranchSection],
+    items: impl Fn(&'a BranchNotes) -> &'a Vec<T>,
+) -> Vec<(&'a T, Option<(usize, Branch)>)> {
+    let mut merged: Vec<_> = common.iter().map(|item| (item, None)).collect();
+
+    for (index, section) in sections.iter().enumerate() {
+        for (branch, notes) in Branch::ALL.into_iter().zip(section.branches.iter()) {
+            merged.extend(
+                items(notes)
+                    .iter()
+                    .map(|item| (item, Some((index, branch)))),
+            );
+        }
+    }
+
+    if !sections.is_empty() {
+        merged.sort_by(|(a, _), (b, _)| a.time().total_cmp(&b.time()));
+    }
+
+    merged
 }
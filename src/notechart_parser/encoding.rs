@@ -0,0 +1,85 @@
+//! Working out how a chart file's text is encoded.
+//!
+//! TJA files don't say what encoding they're in. Newer ones are mostly UTF-8, but plenty of older
+//! ones were written in Shift-JIS (or GB18030, for charts made in China), which comes out as
+//! nonsense, or not at all, when read as UTF-8.
+use encoding_rs::{Encoding, GB18030, SHIFT_JIS, UTF_8};
+
+/// The encodings tried, in order, for files without a byte order mark. UTF-8 goes first, as text
+/// in another encoding is very unlikely to also be valid UTF-8.
+const FALLBACK_ENCODINGS: [&Encoding; 3] = [UTF_8, SHIFT_JIS, GB18030];
+
+/// Decodes the contents of a chart file.
+///
+/// If the file starts with a byte order mark, that decides the encoding. Otherwise it's the first
+/// of [FALLBACK_ENCODINGS] that the whole file is valid in. If it isn't valid in any of them, it's
+/// read as UTF-8, with anything that isn't valid replaced.
+pub fn decode_chart(bytes: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return text.into_owned();
+    }
+
+    FALLBACK_ENCODINGS
+        .iter()
+        .find_map(|encoding| encoding.decode_without_bom_handling_and_without_replacement(bytes))
+        .unwrap_or_else(|| String::from_utf8_lossy(bytes))
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_utf8() {
+        let text = "TITLE:さいたま2000\nBPM:200\n";
+
+        assert_eq!(decode_chart(text.as_bytes()), text);
+
+        // The byte order mark isn't part of the text
+        let with_bom = [b"\xEF\xBB\xBF", text.as_bytes()].concat();
+        assert_eq!(decode_chart(&with_bom), text);
+    }
+
+    #[test]
+    fn test_utf16() {
+        let text = "TITLE:太鼓\n";
+        let bytes: Vec<u8> = b"\xFF\xFE"
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+
+        // The byte order mark says that it's UTF-16, which wouldn't be tried otherwise
+        assert_eq!(decode_chart(&bytes), text);
+    }
+
+    #[test]
+    fn test_shift_jis() {
+        let text = "TITLE:夏祭り\nSUBTITLE:--ジッタリン・ジン\n";
+        let (bytes, _, unmappable) = SHIFT_JIS.encode(text);
+        assert!(!unmappable);
+
+        assert_eq!(decode_chart(&bytes), text);
+    }
+
+    #[test]
+    fn test_gb18030() {
+        // This isn't valid Shift-JIS, so it has to be read as GB18030
+        let text = "TITLE:第一章 Ā\n";
+        let (bytes, _, unmappable) = GB18030.encode(text);
+        assert!(!unmappable);
+        assert!(SHIFT_JIS
+            .decode_without_bom_handling_and_without_replacement(&bytes)
+            .is_none());
+
+        assert_eq!(decode_chart(&bytes), text);
+    }
+
+    #[test]
+    fn test_invalid_text_is_replaced() {
+        // A lone 0xFF isn't valid in any of the encodings
+        assert_eq!(decode_chart(b"TITLE:\xFF\n"), "TITLE:\u{FFFD}\n");
+    }
+}
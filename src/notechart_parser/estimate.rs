@@ -0,0 +1,130 @@
+//! Estimating how difficult a chart is from its notes alone.
+//!
+//! The star level a chart declares (`LEVEL`) is chosen by its charter, and isn't always accurate.
+//! The estimate here is a rough sanity check for that level, built from a few simple measures of
+//! how hard the chart is to play:
+//!
+//! - The average density of notes over the whole chart
+//! - The peak density of notes over a short window
+//! - The proportion of notes that come very quickly after the previous one (e.g. 1/16 notes at a
+//!   high BPM)
+//! - How much the scroll speed changes over the chart ("soflan")
+//!
+//! The measures are combined and scaled to roughly match the 1 to 10 star scale.
+
+use super::NoteChart;
+
+/// The length of the window used to find the peak density of a chart, in seconds.
+const PEAK_WINDOW: f32 = 2.0;
+
+/// Notes closer together than this (in seconds) count as fast notes. This is about the length of
+/// a 1/16 note at 160 BPM.
+const FAST_INTERVAL: f32 = 0.095;
+
+/// How much each measure contributes to the raw difficulty of a chart.
+const AVERAGE_DENSITY_WEIGHT: f32 = 0.6;
+const PEAK_DENSITY_WEIGHT: f32 = 0.25;
+const FAST_NOTE_WEIGHT: f32 = 4.0;
+
+/// The raw difficulty is mapped onto the star scale logarithmically, since each extra star takes
+/// a bigger jump in density than the last. These were fitted to charts with known levels.
+const STAR_SCALE: f32 = 4.3;
+const STAR_OFFSET: f32 = -0.3;
+
+/// The most that soflan can multiply the raw difficulty by.
+const MAX_SOFLAN_FACTOR: f32 = 1.2;
+
+/// The lowest and highest estimates that will be given.
+pub const MIN_ESTIMATE: f32 = 1.0;
+pub const MAX_ESTIMATE: f32 = 10.0;
+
+/// If the estimated level of a chart and its declared level differ by more than this, the
+/// declared level is probably wrong.
+pub const ESTIMATE_TOLERANCE: f32 = 2.0;
+
+/// Estimates the star level of a chart from its notes, between [MIN_ESTIMATE] and
+/// [MAX_ESTIMATE].
+pub fn estimate_difficulty(chart: &NoteChart) -> f32 {
+    let times = hit_times(chart).collect::<Vec<_>>();
+
+    if times.len() < 2 {
+        return MIN_ESTIMATE;
+    }
+
+    let length = (times[times.len() - 1] - times[0]).max(PEAK_WINDOW);
+    let average_density = times.len() as f32 / length;
+
+    // The most notes that fall within any window, starting the window on each note
+    let mut peak_notes = 0;
+    let mut window_end = 0;
+    for (start, &time) in times.iter().enumerate() {
+        while window_end < times.len() && times[window_end] < time + PEAK_WINDOW {
+            window_end += 1;
+        }
+
+        peak_notes = peak_notes.max(window_end - start);
+    }
+    let peak_density = peak_notes as f32 / PEAK_WINDOW;
+
+    let fast_notes = times
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] < FAST_INTERVAL)
+        .count();
+    let fast_ratio = fast_notes as f32 / (times.len() - 1) as f32;
+
+    let raw = AVERAGE_DENSITY_WEIGHT * average_density
+        + PEAK_DENSITY_WEIGHT * peak_density
+        + FAST_NOTE_WEIGHT * fast_ratio;
+
+    let estimate = STAR_SCALE * (raw * soflan_factor(chart)).ln() + STAR_OFFSET;
+    estimate.clamp(MIN_ESTIMATE, MAX_ESTIMATE)
+}
+
+/// The times of the notes that count towards how hard a chart is. Only don and kat notes are
+/// counted: rolls don't need to be hit with any precision, so they don't add much difficulty.
+fn hit_times(chart: &NoteChart) -> impl Iterator<Item = f32> + '_ {
+    chart
+        .notes
+        .iter()
+        .filter(|note| !note.note_type.is_roll())
+        .map(|note| note.time as f32)
+}
+
+/// How many notes per second there are in each of `columns` equal slices of the first `length`
+/// seconds of a chart, for showing where its hard parts are. Notes are counted the same way as for
+/// [estimate_difficulty].
+pub fn note_density(chart: &NoteChart, length: f32, columns: usize) -> Vec<f32> {
+    let mut density = vec![0.0; columns];
+    if columns == 0 || length <= 0.0 {
+        return density;
+    }
+
+    let column_length = length / columns as f32;
+
+    for time in hit_times(chart).filter(|time| (0.0..length).contains(time)) {
+        let column = ((time / column_length) as usize).min(columns - 1);
+        density[column] += 1.0 / column_length;
+    }
+
+    density
+}
+
+/// How much harder the scroll speed changes in a chart make it, as a multiplier between 1 and
+/// [MAX_SOFLAN_FACTOR].
+fn soflan_factor(chart: &NoteChart) -> f32 {
+    let speeds = chart.notes.iter().map(|note| note.scroll_speed.abs());
+    let slowest = speeds.clone().fold(f32::INFINITY, f32::min);
+    let fastest = speeds.fold(0.0, f32::max);
+
+    if !chart.has_scroll_changes() || slowest <= 0.0 {
+        return 1.0;
+    }
+
+    // Every doubling in speed between the slowest and fastest notes adds 5%
+    (1.0 + 0.05 * (fastest / slowest).log2()).min(MAX_SOFLAN_FACTOR)
+}
+
+/// Whether the estimated level of a chart disagrees with its declared level.
+pub fn estimate_disagrees(declared_level: u8, estimate: f32) -> bool {
+    (declared_level as f32 - estimate).abs() > ESTIMATE_TOLERANCE
+}
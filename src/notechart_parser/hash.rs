@@ -0,0 +1,119 @@
+//! Hashing charts, so that anything stored about a chart (scores, offsets, play counts) can be
+//! found again the next time it's loaded.
+//!
+//! Since all of that data is keyed by the hash, the hash of a chart must never change unless the
+//! chart itself does. In particular, the hash only depends on the notes: comments, whitespace and
+//! metadata like the title or level don't affect it.
+//!
+//! # Version 1
+//!
+//! The input to the hash is built from the notes of a single difficulty as follows:
+//!
+//! 1. Each note is turned into a triple of `(time, type id, duration)`:
+//!    - `time` is the time of the note (an f64, in seconds) in whole microseconds, rounded to the
+//!      nearest. Since note times include the `OFFSET` of the song, changing the offset changes
+//!      the hash too.
+//!    - `type id` is the number the note is written as in a TJA file: 1 to 7 and 9 for don, kat,
+//!      big don, big kat, drumroll, big drumroll, balloon and special roll, then 10 and 11 for the
+//!      co-op don and kat notes (`A` and `B`).
+//!    - `duration` is the length of a drumroll or balloon in whole microseconds (rounded to the
+//!      nearest), and 0 for every other note.
+//! 2. The triples are sorted (by time, then type id, then duration).
+//! 3. The input is the ASCII string `taiko chart v1\n`, followed by each triple written as three
+//!    decimal integers separated by commas and terminated by a newline, e.g. `0,1,0\n`.
+//!
+//! The hash is the SHA-256 digest of that input.
+//!
+//! # Changing the hash
+//!
+//! If the hash input ever has to change (e.g. to account for branches), it must be done as a new
+//! version, leaving the existing versions exactly as they are. Anything that stores data keyed by
+//! a hash must then migrate it, by computing the old version of the hash for every chart (see
+//! [hash_with_version]) and re-keying the entries that match it with the new version.
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use super::{NoteChart, NoteType};
+
+/// The version of the hash that new data should be stored with.
+pub const CURRENT_HASH_VERSION: u32 = 1;
+
+/// The hash of a chart, along with the version of the hashing spec it was made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChartHash {
+    pub version: u32,
+    pub digest: [u8; 32],
+}
+
+impl fmt::Display for ChartHash {
+    /// Formats the hash as its version followed by the digest in hex, e.g. `v1:0a1b...`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}:", self.version)?;
+
+        for byte in self.digest {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hashes a chart with the current version of the hash.
+pub fn chart_hash(chart: &NoteChart) -> ChartHash {
+    hash_with_version(chart, CURRENT_HASH_VERSION).expect("the current hash version should exist")
+}
+
+/// Hashes a chart with the given version of the hash, or returns None if there's no such version.
+pub fn hash_with_version(chart: &NoteChart, version: u32) -> Option<ChartHash> {
+    match version {
+        1 => Some(hash_v1(chart)),
+        _ => None,
+    }
+}
+
+/// Hashes a chart with version 1 of the hash. See the [module documentation](self) for the spec.
+pub fn hash_v1(chart: &NoteChart) -> ChartHash {
+    fn microseconds(seconds: f64) -> i64 {
+        (seconds * 1_000_000.0).round() as i64
+    }
+
+    let mut triples = chart
+        .notes
+        .iter()
+        .map(|note| {
+            let (type_id, duration) = match note.note_type {
+                NoteType::Don => (1, 0.0),
+                NoteType::Kat => (2, 0.0),
+                NoteType::BigDon => (3, 0.0),
+                NoteType::BigKat => (4, 0.0),
+                NoteType::Roll(duration) => (5, duration),
+                NoteType::BigRoll(duration) => (6, duration),
+                NoteType::BalloonRoll(duration, _) => (7, duration),
+                NoteType::SpecialRoll(duration, _) => (9, duration),
+                NoteType::CoopDon => (10, 0.0),
+                NoteType::CoopKat => (11, 0.0),
+            };
+
+            (
+                microseconds(note.time),
+                type_id,
+                microseconds(duration as f64),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    triples.sort_unstable();
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"taiko chart v1\n");
+
+    for (time, type_id, duration) in triples {
+        hasher.update(format!("{time},{type_id},{duration}\n").as_bytes());
+    }
+
+    ChartHash {
+        version: 1,
+        digest: hasher.finalize().into(),
+    }
+}
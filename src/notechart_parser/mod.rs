@@ -1,6 +1,17 @@
 mod chart;
+mod encoding;
+mod estimate;
+mod hash;
+mod osu_parser;
+#[cfg(test)]
 mod test;
 mod tja_parser;
+mod tja_writer;
 
 pub use chart::*;
+pub use encoding::*;
+pub use estimate::*;
+pub use hash::*;
+pub use osu_parser::*;
 pub use tja_parser::*;
+pub use tja_writer::*;
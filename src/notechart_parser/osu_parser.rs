@@ -0,0 +1,872 @@
+//! Reading osu!taiko beatmaps (`.osu` files).
+//!
+//! A beatmap set is a folder with one `.osu` file for each difficulty, all playing the same audio.
+//! Each file is read with [parse_osu_file], and then the set is put together into a [Song] with
+//! [song_from_beatmaps], which works out which of the five difficulties each beatmap should be.
+//!
+//! Only beatmaps made for osu!taiko are read. osu! converts beatmaps from its other modes with rules
+//! of its own, which aren't copied here.
+//!
+//! Times in a `.osu` file are in milliseconds from the start of the audio, so they only need to be
+//! converted to seconds. Notes are coloured by their hitsounds: whistles and claps are kats, and
+//! finishes make a note big. Sliders are drumrolls and spinners are balloons.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::chart::{
+    Barline, BpmChange, Difficulty, GogoRegion, Note, NoteChart, NoteType, ScrollMode, Song,
+};
+use super::estimate::{estimate_difficulty, MAX_ESTIMATE, MIN_ESTIMATE};
+
+/// The line every beatmap starts with, followed by the version of the format.
+const HEADER: &str = "osu file format v";
+
+/// The number osu! uses for its taiko mode.
+const TAIKO_MODE: u8 = 1;
+
+const DEFAULT_SLIDER_MULTIPLIER: f64 = 1.4;
+const DEFAULT_OVERALL_DIFFICULTY: f64 = 5.0;
+
+/// The most and least an inherited timing point can multiply the slider velocity by.
+const MIN_VELOCITY: f64 = 0.1;
+const MAX_VELOCITY: f64 = 10.0;
+
+/// How many hits a spinner needs for each second it lasts, on top of the rate given by the overall
+/// difficulty. This is what osu! uses.
+const SPINNER_HIT_MULTIPLIER: f64 = 1.65;
+
+/// Hitsound flags, which decide the colour and size of a note.
+const WHISTLE: u32 = 1 << 1;
+const FINISH: u32 = 1 << 2;
+const CLAP: u32 = 1 << 3;
+
+/// Hit object type flags.
+const CIRCLE: u32 = 1 << 0;
+const SLIDER: u32 = 1 << 1;
+const SPINNER: u32 = 1 << 3;
+
+/// Names difficulties are commonly given in beatmap sets, and the difficulty they are. The first
+/// one found in a beatmap's name is used, so longer names that contain a shorter one come first.
+const DIFFICULTY_NAMES: [(&str, usize); 10] = [
+    ("inner oni", 4),
+    ("ura", 4),
+    ("edit", 4),
+    ("kantan", 0),
+    ("easy", 0),
+    ("futsuu", 1),
+    ("normal", 1),
+    ("muzukashii", 2),
+    ("hard", 2),
+    ("oni", 3),
+];
+
+/// Types of errors that can be encountered while parsing a `.osu` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OsuParseErrorKind {
+    /// The file doesn't start with the `osu file format` header.
+    NotABeatmap,
+    SyntaxError,
+    /// A value the beatmap needs is missing. Contains the name of the value.
+    MissingValue(&'static str),
+    /// The beatmap was made for another of osu!'s modes. Contains the number of the mode.
+    UnsupportedMode(u8),
+    /// The beatmap has no uninherited timing points, so there's no way to tell its BPM.
+    NoTimingPoints,
+}
+
+impl OsuParseErrorKind {
+    /// A short description of the kind of error, without any of the details. Useful for grouping
+    /// errors together.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OsuParseErrorKind::NotABeatmap => "not an osu! beatmap",
+            OsuParseErrorKind::SyntaxError => "syntax error",
+            OsuParseErrorKind::MissingValue(_) => "missing beatmap value",
+            OsuParseErrorKind::UnsupportedMode(_) => "beatmap isn't for osu!taiko",
+            OsuParseErrorKind::NoTimingPoints => "beatmap has no timing points",
+        }
+    }
+}
+
+/// An error that can be encountered while parsing a `.osu` file, and the line it's on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OsuParseError {
+    pub kind: OsuParseErrorKind,
+    pub line: usize,
+}
+
+impl std::fmt::Display for OsuParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            OsuParseErrorKind::NotABeatmap => f.write_str("the file isn't an osu! beatmap")?,
+            OsuParseErrorKind::SyntaxError => f.write_str("syntax error")?,
+            OsuParseErrorKind::MissingValue(key) => {
+                f.write_fmt(format_args!("value needed for the beatmap: \"{key}\""))?
+            }
+            OsuParseErrorKind::UnsupportedMode(mode) => {
+                let mode = match mode {
+                    0 => "osu!standard",
+                    2 => "osu!catch",
+                    3 => "osu!mania",
+                    _ => "an unknown mode",
+                };
+
+                f.write_fmt(format_args!(
+                    "the beatmap is for {mode}, only osu!taiko beatmaps are supported"
+                ))?
+            }
+            OsuParseErrorKind::NoTimingPoints => {
+                f.write_str("the beatmap has no uninherited timing points")?
+            }
+        }
+
+        f.write_fmt(format_args!(" (at line {})", self.line + 1))
+    }
+}
+
+impl std::error::Error for OsuParseError {}
+
+/// One difficulty of a beatmap set, as read from its `.osu` file.
+#[derive(Debug, Clone)]
+pub struct OsuBeatmap {
+    pub title: String,
+    pub artist: Option<String>,
+    pub audio_filename: String,
+    /// When the song preview starts, in seconds.
+    pub preview_time: f32,
+    /// The name of the difficulty, e.g. "Inner Oni".
+    pub version: String,
+    /// The BPM of the first timing point.
+    pub bpm: f32,
+    /// When the first timing point is, in seconds.
+    pub first_beat: f32,
+    pub difficulty: Difficulty,
+}
+
+/// A line in one of the `.osu` sections that's a list of values, e.g. `[HitObjects]`.
+struct ListLine<'a> {
+    line: usize,
+    fields: Vec<&'a str>,
+}
+
+impl<'a> ListLine<'a> {
+    fn new(line: usize, text: &'a str) -> Self {
+        Self {
+            line,
+            fields: text.split(',').map(str::trim).collect(),
+        }
+    }
+
+    fn error(&self) -> OsuParseError {
+        OsuParseError {
+            kind: OsuParseErrorKind::SyntaxError,
+            line: self.line,
+        }
+    }
+
+    /// Parses the field at `index`, which has to be there.
+    fn get<T: FromStr>(&self, index: usize) -> Result<T, OsuParseError> {
+        self.fields
+            .get(index)
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(|| self.error())
+    }
+
+    /// Parses the field at `index`, or gives `default` if the line is too short to have it.
+    fn get_or<T: FromStr>(&self, index: usize, default: T) -> Result<T, OsuParseError> {
+        match self.fields.get(index) {
+            Some(_) => self.get(index),
+            None => Ok(default),
+        }
+    }
+
+    /// Parses a time or length at `index`, which has to be finite.
+    fn get_finite(&self, index: usize) -> Result<f64, OsuParseError> {
+        Some(self.get::<f64>(index)?)
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| self.error())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimingPoint {
+    /// In milliseconds.
+    time: f64,
+    /// For uninherited points, the length of a beat in milliseconds. For inherited points, the
+    /// slider velocity as a negative percentage (e.g. -50 doubles it).
+    beat_length: f64,
+    /// Beats in a measure.
+    meter: u32,
+    uninherited: bool,
+    kiai: bool,
+    /// Whether the barline at the start of this point's first measure is hidden.
+    omit_first_barline: bool,
+}
+
+impl TimingPoint {
+    fn parse(line: &ListLine) -> Result<Self, OsuParseError> {
+        let beat_length = line.get_finite(1)?;
+        let uninherited = line.get_or::<u8>(6, 1)? == 1;
+        let effects = line.get_or::<u32>(7, 0)?;
+
+        if uninherited && beat_length <= 0.0 {
+            return Err(line.error());
+        }
+
+        Ok(Self {
+            time: line.get_finite(0)?,
+            beat_length,
+            meter: line.get_or::<u32>(2, 4)?.max(1),
+            uninherited,
+            kiai: effects & 1 != 0,
+            omit_first_barline: effects & (1 << 3) != 0,
+        })
+    }
+}
+
+/// The timing in effect from one timing point to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimingSection {
+    start: f64,
+    beat_length: f64,
+    /// The slider velocity multiplier.
+    velocity: f64,
+    kiai: bool,
+}
+
+impl TimingSection {
+    /// How fast notes in this section scroll, as a multiple of the default speed (see
+    /// [Note::scroll_speed]).
+    fn scroll_speed(&self) -> f32 {
+        (60000.0 / self.beat_length / 120.0 * self.velocity) as f32
+    }
+}
+
+/// Works out the timing at every timing point. Each uninherited point sets the beat length and
+/// resets the velocity, and each inherited point changes the velocity.
+fn timing_sections(points: &[TimingPoint]) -> Vec<TimingSection> {
+    let mut sections = Vec::with_capacity(points.len());
+    let mut beat_length = points
+        .iter()
+        .find(|point| point.uninherited)
+        .map_or(500.0, |point| point.beat_length);
+
+    for point in points {
+        let velocity = if point.uninherited {
+            beat_length = point.beat_length;
+            1.0
+        } else if point.beat_length < 0.0 {
+            (-100.0 / point.beat_length).clamp(MIN_VELOCITY, MAX_VELOCITY)
+        } else {
+            1.0
+        };
+
+        sections.push(TimingSection {
+            start: point.time,
+            beat_length,
+            velocity,
+            kiai: point.kiai,
+        });
+    }
+
+    sections
+}
+
+/// The timing in effect at `time`. Anything before the first timing point uses the first one.
+fn section_at(sections: &[TimingSection], time: f64) -> &TimingSection {
+    let index = sections
+        .partition_point(|section| section.start <= time)
+        .saturating_sub(1);
+    &sections[index]
+}
+
+/// A linear interpolation between three values depending on the overall difficulty, the way osu!
+/// scales things with difficulty: `min` at 0, `mid` at 5 and `max` at 10.
+fn difficulty_range(overall_difficulty: f64, min: f64, mid: f64, max: f64) -> f64 {
+    if overall_difficulty > 5.0 {
+        mid + (max - mid) * (overall_difficulty - 5.0) / 5.0
+    } else {
+        mid - (mid - min) * (5.0 - overall_difficulty) / 5.0
+    }
+}
+
+/// Reads a hit object into a note.
+fn hit_object(
+    line: &ListLine,
+    sections: &[TimingSection],
+    slider_multiplier: f64,
+    overall_difficulty: f64,
+) -> Result<Note, OsuParseError> {
+    let time = line.get_finite(2)?;
+    let object_type = line.get::<u32>(3)?;
+    let hitsound = line.get_or::<u32>(4, 0)?;
+    let section = section_at(sections, time);
+
+    let big = hitsound & FINISH != 0;
+    let kat = hitsound & (WHISTLE | CLAP) != 0;
+
+    let note_type = if object_type & CIRCLE != 0 {
+        match (kat, big) {
+            (false, false) => NoteType::Don,
+            (true, false) => NoteType::Kat,
+            (false, true) => NoteType::BigDon,
+            (true, true) => NoteType::BigKat,
+        }
+    } else if object_type & SLIDER != 0 {
+        let slides = line.get_or::<u32>(6, 1)?.max(1);
+        let length = line.get_finite(7)?;
+        let duration = length / (slider_multiplier * 100.0 * section.velocity)
+            * section.beat_length
+            * slides as f64
+            / 1000.0;
+
+        if big {
+            NoteType::BigRoll(duration.max(0.0) as f32)
+        } else {
+            NoteType::Roll(duration.max(0.0) as f32)
+        }
+    } else if object_type & SPINNER != 0 {
+        let duration = ((line.get_finite(5)? - time) / 1000.0).max(0.0);
+        let hits_per_second =
+            difficulty_range(overall_difficulty, 3.0, 5.0, 7.5) * SPINNER_HIT_MULTIPLIER;
+
+        NoteType::BalloonRoll(
+            duration as f32,
+            ((duration * hits_per_second) as u32).max(1),
+        )
+    } else {
+        return Err(line.error());
+    };
+
+    Ok(Note {
+        note_type,
+        time: time / 1000.0,
+        scroll_speed: section.scroll_speed(),
+        vertical_speed: 0.0,
+    })
+}
+
+/// Puts a barline at the start of every measure, from the first timing point until `end` (in
+/// milliseconds). Each uninherited timing point starts a new measure.
+fn barlines(points: &[TimingPoint], sections: &[TimingSection], end: f64) -> Vec<Barline> {
+    let uninherited = points
+        .iter()
+        .filter(|point| point.uninherited)
+        .collect::<Vec<_>>();
+    let mut barlines = Vec::new();
+
+    for (i, point) in uninherited.iter().enumerate() {
+        let until = uninherited.get(i + 1).map_or(end, |next| next.time);
+        let measure = point.beat_length * point.meter as f64;
+        let mut measures = if point.omit_first_barline { 1 } else { 0 };
+
+        loop {
+            let time = point.time + measure * measures as f64;
+            // The last section goes up to the end of the beatmap, and the others stop just
+            // before the next one starts
+            let past_end = if i + 1 == uninherited.len() {
+                time > until
+            } else {
+                time >= until
+            };
+
+            if past_end {
+                break;
+            }
+
+            barlines.push(Barline {
+                time: (time / 1000.0) as f32,
+                scroll_speed: section_at(sections, time).scroll_speed(),
+                vertical_speed: 0.0,
+            });
+            measures += 1;
+        }
+    }
+
+    barlines
+}
+
+/// The stretches of the beatmap in kiai time, which are go-go time in taiko. A stretch still going
+/// at the end of the beatmap ends at `end` (in milliseconds).
+fn gogo_regions(sections: &[TimingSection], end: f64) -> Vec<GogoRegion> {
+    let mut regions = Vec::new();
+    let mut start = None;
+
+    for section in sections {
+        match (section.kiai, start) {
+            (true, None) => start = Some(section.start),
+            (false, Some(from)) => {
+                regions.push(GogoRegion {
+                    start: (from / 1000.0) as f32,
+                    end: (section.start / 1000.0) as f32,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(from) = start.filter(|&from| from < end) {
+        regions.push(GogoRegion {
+            start: (from / 1000.0) as f32,
+            end: (end / 1000.0) as f32,
+        });
+    }
+
+    regions
+}
+
+/// The tempo of the beatmap over time. Inherited timing points don't change the tempo, so sections
+/// that carry on at the same one are left out.
+fn bpm_changes(sections: &[TimingSection]) -> Vec<BpmChange> {
+    let mut changes: Vec<BpmChange> = Vec::new();
+
+    for section in sections {
+        let bpm = (60000.0 / section.beat_length) as f32;
+
+        if changes.last().is_none_or(|last| last.bpm != bpm) {
+            changes.push(BpmChange {
+                time: (section.start / 1000.0) as f32,
+                bpm,
+            });
+        }
+    }
+
+    changes
+}
+
+/// Parses a `.osu` file into an [OsuBeatmap], which is one difficulty of a song. See
+/// [song_from_beatmaps] to put a set of them together into a song.
+pub fn parse_osu_file(input: &str) -> Result<OsuBeatmap, OsuParseError> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.strip_prefix('\u{feff}').unwrap_or(line).trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with("//"));
+
+    match lines.next() {
+        Some((_, line)) if line.starts_with(HEADER) => {}
+        first => {
+            return Err(OsuParseError {
+                kind: OsuParseErrorKind::NotABeatmap,
+                line: first.map_or(0, |(i, _)| i),
+            })
+        }
+    }
+
+    // Values from the sections made of `Key: value` pairs, by section
+    let mut values: HashMap<&str, HashMap<&str, (usize, &str)>> = HashMap::new();
+    let mut timing_points = Vec::new();
+    let mut hit_objects = Vec::new();
+    let mut section = "";
+
+    for (i, line) in lines {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+
+        match section {
+            "TimingPoints" => timing_points.push(TimingPoint::parse(&ListLine::new(i, line))?),
+            "HitObjects" => hit_objects.push(ListLine::new(i, line)),
+            "General" | "Metadata" | "Difficulty" => {
+                let (key, value) = line.split_once(':').ok_or(OsuParseError {
+                    kind: OsuParseErrorKind::SyntaxError,
+                    line: i,
+                })?;
+
+                values
+                    .entry(section)
+                    .or_default()
+                    .insert(key.trim(), (i, value.trim()));
+            }
+            // Nothing is needed from the other sections (e.g. colours or the storyboard)
+            _ => {}
+        }
+    }
+
+    let value = |section: &str, key: &str| {
+        values
+            .get(section)
+            .and_then(|values| values.get(key))
+            .copied()
+            .filter(|(_, value)| !value.is_empty())
+    };
+
+    let required = |section: &str, key: &'static str| {
+        value(section, key)
+            .map(|(_, value)| value.to_string())
+            .ok_or(OsuParseError {
+                kind: OsuParseErrorKind::MissingValue(key),
+                line: 0,
+            })
+    };
+
+    let parsed = |section: &str, key: &str, default: f64| match value(section, key) {
+        Some((line, value)) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or(OsuParseError {
+                kind: OsuParseErrorKind::SyntaxError,
+                line,
+            }),
+        None => Ok(default),
+    };
+
+    if let Some((line, mode)) = value("General", "Mode") {
+        let mode = mode.parse::<u8>().map_err(|_| OsuParseError {
+            kind: OsuParseErrorKind::SyntaxError,
+            line,
+        })?;
+
+        if mode != TAIKO_MODE {
+            return Err(OsuParseError {
+                kind: OsuParseErrorKind::UnsupportedMode(mode),
+                line,
+            });
+        }
+    } else {
+        // Beatmaps without a mode are for osu!standard
+        return Err(OsuParseError {
+            kind: OsuParseErrorKind::UnsupportedMode(0),
+            line: 0,
+        });
+    }
+
+    let audio_filename = required("General", "AudioFilename")?;
+    let title = value("Metadata", "TitleUnicode")
+        .map(|(_, title)| title.to_string())
+        .map_or_else(|| required("Metadata", "Title"), Ok)?;
+    let artist = value("Metadata", "ArtistUnicode")
+        .or_else(|| value("Metadata", "Artist"))
+        .map(|(_, artist)| artist.to_string());
+    let version = value("Metadata", "Version").map_or("", |(_, version)| version);
+    let preview_time = parsed("General", "PreviewTime", -1.0)?;
+    let slider_multiplier = parsed("Difficulty", "SliderMultiplier", DEFAULT_SLIDER_MULTIPLIER)?;
+    let overall_difficulty = parsed(
+        "Difficulty",
+        "OverallDifficulty",
+        DEFAULT_OVERALL_DIFFICULTY,
+    )?;
+
+    // Points at the same time stay in the order they were given, so an inherited point straight
+    // after an uninherited one changes its velocity
+    timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let first_timing_point = timing_points
+        .iter()
+        .find(|point| point.uninherited)
+        .copied()
+        .ok_or(OsuParseError {
+            kind: OsuParseErrorKind::NoTimingPoints,
+            line: 0,
+        })?;
+
+    let sections = timing_sections(&timing_points);
+
+    let mut notes = hit_objects
+        .iter()
+        .map(|line| hit_object(line, &sections, slider_multiplier, overall_difficulty))
+        .collect::<Result<Vec<_>, _>>()?;
+    notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    // The chart ends when the last note does
+    let end = notes
+        .iter()
+        .map(|note| {
+            let length = match note.note_type {
+                NoteType::Roll(length)
+                | NoteType::BigRoll(length)
+                | NoteType::BalloonRoll(length, _)
+                | NoteType::SpecialRoll(length, _) => length,
+                _ => 0.0,
+            };
+
+            (note.time + length as f64) * 1000.0
+        })
+        .fold(first_timing_point.time, f64::max);
+
+    let chart = NoteChart {
+        barlines: barlines(&timing_points, &sections, end),
+        gogo_regions: gogo_regions(&sections, end),
+        bpm_changes: bpm_changes(&sections),
+        notes,
+        branch_sections: Vec::new(),
+        delays: Vec::new(),
+        scroll_mode: ScrollMode::Normal,
+    };
+    let estimated_level = estimate_difficulty(&chart);
+
+    Ok(OsuBeatmap {
+        title,
+        artist,
+        audio_filename,
+        preview_time: (preview_time.max(0.0) / 1000.0) as f32,
+        version: version.to_string(),
+        bpm: (60000.0 / first_timing_point.beat_length) as f32,
+        first_beat: (first_timing_point.time / 1000.0) as f32,
+        difficulty: Difficulty {
+            // osu! doesn't have star levels like taiko does, so the estimate is used instead
+            star_level: estimated_level.round().clamp(MIN_ESTIMATE, MAX_ESTIMATE) as u8,
+            estimated_level,
+            notes_designer: value("Metadata", "Creator").map(|(_, creator)| creator.to_string()),
+            chart,
+            player_charts: None,
+        },
+    })
+}
+
+/// Which difficulty a beatmap's name says it is, if it says.
+fn difficulty_from_version(version: &str) -> Option<usize> {
+    let version = version.to_lowercase();
+
+    DIFFICULTY_NAMES
+        .iter()
+        .find(|(name, _)| version.contains(name))
+        .map(|&(_, difficulty)| difficulty)
+}
+
+/// Puts the beatmaps of a set together into a song. The song's details are taken from the easiest
+/// beatmap.
+///
+/// Beatmaps named after a difficulty (e.g. "Muzukashii" or "Inner Oni") are used for that
+/// difficulty. The rest fill whichever difficulties are left, in order of how hard they're
+/// estimated to be. Beatmaps with no notes, or that there's no difficulty left for, are left out,
+/// and their names are returned alongside the song.
+pub fn song_from_beatmaps(mut beatmaps: Vec<OsuBeatmap>) -> (Song, Vec<String>) {
+    beatmaps.sort_by(|a, b| {
+        a.difficulty
+            .estimated_level
+            .total_cmp(&b.difficulty.estimated_level)
+    });
+
+    let mut song = match beatmaps.first() {
+        Some(first) => Song {
+            title: first.title.clone(),
+            subtitle: first.artist.clone(),
+            audio_filename: first.audio_filename.clone(),
+            bpm: first.bpm,
+            // The offset is only used to line the editor's measures up with the notes, since the
+            // times of the notes are already from the start of the audio
+            offset: -first.first_beat,
+            demostart: first.preview_time,
+            ..Default::default()
+        },
+        None => Song::default(),
+    };
+
+    let mut left_out = Vec::new();
+    let mut unnamed = Vec::new();
+
+    for beatmap in beatmaps {
+        if beatmap.difficulty.chart.notes.is_empty() {
+            left_out.push(beatmap.version);
+            continue;
+        }
+
+        match difficulty_from_version(&beatmap.version) {
+            Some(difficulty) if song.difficulties[difficulty].is_none() => {
+                song.difficulties[difficulty] = Some(beatmap.difficulty);
+            }
+            _ => unnamed.push(beatmap),
+        }
+    }
+
+    let mut unnamed = unnamed.into_iter();
+
+    for slot in song.difficulties.iter_mut().filter(|slot| slot.is_none()) {
+        match unnamed.next() {
+            Some(beatmap) => *slot = Some(beatmap.difficulty),
+            None => break,
+        }
+    }
+
+    left_out.extend(unnamed.map(|beatmap| beatmap.version));
+
+    (song, left_out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A beatmap at 120 BPM (so each beat is half a second), with whatever hit objects are given.
+    fn beatmap(version: &str, timing_points: &str, hit_objects: &str) -> String {
+        format!(
+            "osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+PreviewTime: 1500
+Mode: 1
+
+[Metadata]
+Title:Romanised title
+TitleUnicode:タイトル
+Artist:Artist
+Creator:Mapper
+Version:{version}
+
+[Difficulty]
+OverallDifficulty:5
+SliderMultiplier:1.4
+
+[TimingPoints]
+{timing_points}
+
+[HitObjects]
+{hit_objects}
+"
+        )
+    }
+
+    const TIMING: &str = "1000,500,4,1,0,100,1,0";
+
+    #[test]
+    fn test_notes() {
+        let beatmap = parse_osu_file(&beatmap(
+            "Oni",
+            TIMING,
+            "256,192,1000,1,0,0:0:0:0:
+256,192,1500,1,2,0:0:0:0:
+256,192,2000,5,4,0:0:0:0:
+256,192,2500,1,12,0:0:0:0:
+256,192,3000,2,0,L|400:192,2,140
+256,192,5000,12,0,7000,0:0:0:0:",
+        ))
+        .unwrap();
+
+        assert_eq!(beatmap.title, "タイトル");
+        assert_eq!(beatmap.artist.as_deref(), Some("Artist"));
+        assert_eq!(beatmap.audio_filename, "audio.mp3");
+        assert_eq!(beatmap.preview_time, 1.5);
+        assert_eq!(beatmap.bpm, 120.0);
+        assert_eq!(beatmap.difficulty.notes_designer.as_deref(), Some("Mapper"));
+
+        let notes = &beatmap.difficulty.chart.notes;
+        let types = notes.iter().map(|note| note.note_type).collect::<Vec<_>>();
+        // A slider 140 pixels long at velocity 1 is one beat long, and this one goes there and back
+        // again. The spinner lasts 2 seconds, at 5 * 1.65 hits a second.
+        assert_eq!(
+            types,
+            vec![
+                NoteType::Don,
+                NoteType::Kat,
+                NoteType::BigDon,
+                NoteType::BigKat,
+                NoteType::Roll(1.0),
+                NoteType::BalloonRoll(2.0, 16),
+            ]
+        );
+        assert_eq!(notes[1].time, 1.5);
+        assert!(notes.iter().all(|note| note.scroll_speed == 1.0));
+    }
+
+    #[test]
+    fn test_timing_points() {
+        let beatmap = parse_osu_file(&beatmap(
+            "Oni",
+            // Half the velocity with kiai on from 3s, then 240 BPM in 3/4 with kiai off from 5s
+            "1000,500,4,1,0,100,1,0
+3000,-200,4,1,0,100,0,1
+5000,250,3,1,0,100,1,0",
+            "256,192,1000,1,0,0:0:0:0:
+256,192,3000,1,0,0:0:0:0:
+256,192,5000,1,0,0:0:0:0:
+256,192,6500,1,0,0:0:0:0:",
+        ))
+        .unwrap();
+        let chart = &beatmap.difficulty.chart;
+
+        let speeds = chart
+            .notes
+            .iter()
+            .map(|note| note.scroll_speed)
+            .collect::<Vec<_>>();
+        assert_eq!(speeds, vec![1.0, 0.5, 2.0, 2.0]);
+
+        let barlines = chart
+            .barlines
+            .iter()
+            .map(|barline| barline.time)
+            .collect::<Vec<_>>();
+        assert_eq!(barlines, vec![1.0, 3.0, 5.0, 5.75, 6.5]);
+
+        assert_eq!(
+            chart.gogo_regions,
+            vec![GogoRegion {
+                start: 3.0,
+                end: 5.0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| parse_osu_file(input).unwrap_err();
+
+        assert_eq!(
+            error("TITLE:A tja file").kind,
+            OsuParseErrorKind::NotABeatmap
+        );
+        assert_eq!(
+            error(&beatmap("Oni", TIMING, "").replace("Mode: 1", "Mode: 3")),
+            OsuParseError {
+                kind: OsuParseErrorKind::UnsupportedMode(3),
+                line: 5
+            }
+        );
+        assert_eq!(
+            error(&beatmap("Oni", "", "")).kind,
+            OsuParseErrorKind::NoTimingPoints
+        );
+        assert_eq!(
+            error(&beatmap("Oni", TIMING, "256,192,nope,1,0")),
+            OsuParseError {
+                kind: OsuParseErrorKind::SyntaxError,
+                line: 22
+            }
+        );
+        assert_eq!(
+            error(&beatmap("Oni", TIMING, "").replace("AudioFilename: audio.mp3", "")).kind,
+            OsuParseErrorKind::MissingValue("AudioFilename")
+        );
+    }
+
+    #[test]
+    fn test_song_from_beatmaps() {
+        // The notes are spread over 8 seconds, so beatmaps with more notes are harder
+        let parse = |version: &str, notes: usize| {
+            let hit_objects = (0..notes)
+                .map(|i| format!("256,192,{},1,0,0:0:0:0:", 1000 + i * 8000 / notes))
+                .collect::<Vec<_>>()
+                .join("\n");
+            parse_osu_file(&beatmap(version, TIMING, &hit_objects)).unwrap()
+        };
+
+        let (song, left_out) = song_from_beatmaps(vec![
+            parse("Inner Oni", 200),
+            parse("Kantan", 10),
+            parse("Taiko's Muzukashii", 50),
+            parse("Collab", 100),
+            parse("Extra", 150),
+            parse("Another Kantan", 20),
+            parse("Empty", 0),
+        ]);
+
+        let has = |i: usize| song.difficulties[i].as_ref().map(|d| d.chart.notes.len());
+        assert_eq!(has(0), Some(10));
+        // Unnamed beatmaps fill the gaps, easiest first
+        assert_eq!(has(1), Some(20));
+        assert_eq!(has(2), Some(50));
+        assert_eq!(has(3), Some(100));
+        assert_eq!(has(4), Some(200));
+        assert_eq!(left_out, vec!["Empty", "Extra"]);
+
+        assert_eq!(song.title, "タイトル");
+        assert_eq!(song.subtitle.as_deref(), Some("Artist"));
+        assert_eq!(song.offset, -1.0);
+        assert_eq!(song.demostart, 1.5);
+    }
+}
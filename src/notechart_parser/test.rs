@@ -45,3 +45,1114 @@ fn test_real_tja_file_succeeds() {
     println!("{:?}", res);
     assert!(res.is_ok());
 }
+
+fn delayed_roll_track(roll: char) -> String {
+    // At 120bpm each of these measures lasts 2 seconds, so each note lasts a quarter second.
+    // The roll starts at 0s and ends 1.75 seconds later (not counting the delay).
+    format!(
+        "TITLE:Delay test
+BPM:120
+WAVE:test.ogg
+BALLOON:5
+COURSE:Oni
+LEVEL:10
+
+#START
+{roll}000
+#DELAY 1
+0008,
+1,
+#END
+"
+    )
+}
+
+fn assert_close(a: impl Into<f64>, b: f64) {
+    let a = a.into();
+    assert!((a - b).abs() < 1e-4, "expected {b}, got {a}");
+}
+
+#[test]
+fn test_delay_inside_roll_preserves_duration() {
+    let song = parse_tja_file(&delayed_roll_track('5')).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    assert_eq!(chart.notes.len(), 2);
+    assert_close(chart.notes[0].time, 0.0);
+    assert!(matches!(chart.notes[0].note_type, NoteType::Roll(d) if (d - 1.75).abs() < 1e-4));
+    // The notes after the roll are still pushed back by the delay
+    assert_close(chart.notes[1].time, 3.0);
+}
+
+#[test]
+fn test_delay_inside_balloon_preserves_duration() {
+    let song = parse_tja_file(&delayed_roll_track('7')).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    assert!(matches!(
+        chart.notes[0].note_type,
+        NoteType::BalloonRoll(d, 5) if (d - 1.75).abs() < 1e-4
+    ));
+}
+
+#[test]
+fn test_delay_extends_rolls_option() {
+    let options = ParseOptions {
+        delay_extends_rolls: true,
+        ..Default::default()
+    };
+
+    let song = parse_tja_file_with_options(&delayed_roll_track('5'), &options).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert!(matches!(chart.notes[0].note_type, NoteType::Roll(d) if (d - 2.75).abs() < 1e-4));
+
+    let song = parse_tja_file_with_options(&delayed_roll_track('7'), &options).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert!(matches!(
+        chart.notes[0].note_type,
+        NoteType::BalloonRoll(d, 5) if (d - 2.75).abs() < 1e-4
+    ));
+}
+
+#[test]
+fn test_delay_barlines() {
+    let song = parse_tja_file(&delayed_roll_track('5')).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    let barlines: Vec<f32> = chart.barlines.iter().map(|b| b.time).collect();
+
+    // The delay happened in the first measure, so every barline after it is pushed back
+    assert_eq!(barlines.len(), 3);
+    assert_close(barlines[0], 0.0);
+    assert_close(barlines[1], 3.0);
+    assert_close(barlines[2], 5.0);
+
+    // A delay inside an empty measure should still push back the next barline
+    let empty_measure = "TITLE:Delay test
+BPM:120
+WAVE:test.ogg
+COURSE:Oni
+LEVEL:10
+
+#START
+#DELAY 0.5
+,
+1,
+#END
+";
+
+    let song = parse_tja_file(empty_measure).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    assert_close(chart.barlines[1].time, 2.5);
+    assert_close(chart.notes[0].time, 2.5);
+}
+
+#[test]
+fn test_notes_designer() {
+    let track = "TITLE:Designers
+BPM:120
+WAVE:song.ogg
+NOTESDESIGNER3:luna
+
+COURSE:Hard
+LEVEL:6
+#START
+1,
+#END
+
+COURSE:Oni
+LEVEL:8
+#START
+1,
+#END
+
+NOTESDESIGNER:someone else
+COURSE:Easy
+LEVEL:2
+#START
+1,
+#END
+";
+
+    let song = parse_tja_file(track).unwrap();
+    let designer = |i: usize| {
+        song.difficulties[i]
+            .as_ref()
+            .unwrap()
+            .notes_designer
+            .clone()
+    };
+
+    assert_eq!(designer(2), None);
+    assert_eq!(designer(3), Some("luna".to_string()));
+    assert_eq!(designer(0), Some("someone else".to_string()));
+}
+
+#[test]
+fn test_has_scroll_changes() {
+    let track = |body: &str| {
+        format!(
+            "TITLE:Soflan
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:8
+#START
+{body}
+#END
+"
+        )
+    };
+
+    let chart = |body: &str| {
+        parse_tja_file(&track(body)).unwrap().difficulties[3]
+            .take()
+            .unwrap()
+            .chart
+    };
+
+    assert!(!chart("1111,\n1111,").has_scroll_changes());
+    assert!(chart("1111,\n#BPMCHANGE 240\n1111,").has_scroll_changes());
+    assert!(chart("1111,\n#SCROLL 0.5\n1111,").has_scroll_changes());
+}
+
+#[test]
+fn test_gogo_regions() {
+    let track = "TITLE:Gogo\nBPM:120\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n\
+                 1111,\n#GOGOSTART\n1111,\n#GOGOEND\n1111,\n#GOGOSTART\n11\n#GOGOSTART\n11,\n#END\n";
+    let song = parse_tja_file(track).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    // A go-go time that isn't ended lasts until the end of the chart, and starting one that's
+    // already started does nothing
+    assert_eq!(
+        chart.gogo_regions,
+        vec![
+            GogoRegion {
+                start: 2.0,
+                end: 4.0
+            },
+            GogoRegion {
+                start: 6.0,
+                end: 8.0
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_song_metadata() {
+    let track = |metadata: &str| {
+        format!("TITLE:Metadata\nBPM:120\nWAVE:test.ogg\n{metadata}\nCOURSE:Oni\nLEVEL:5\n\n#START\n1111,\n#END\n")
+    };
+
+    let song = parse_tja_file(&track("SONGVOL:80\nSEVOL:50\nSIDE:Ex\nGENRE:Game Music")).unwrap();
+    assert_eq!(song.song_volume, 80.0);
+    assert_eq!(song.effects_volume, 50.0);
+    assert_eq!(song.side, SongSide::Extra);
+    assert_eq!(song.genre.as_deref(), Some("Game Music"));
+
+    // Everything is optional, and an empty genre is the same as no genre
+    let song = parse_tja_file(&track("GENRE:")).unwrap();
+    assert_eq!(song.song_volume, 100.0);
+    assert_eq!(song.effects_volume, 100.0);
+    assert_eq!(song.side, SongSide::Both);
+    assert_eq!(song.genre, None);
+
+    assert_eq!(
+        parse_tja_file(&track("SIDE:1")).unwrap().side,
+        SongSide::Normal
+    );
+    assert!(parse_tja_file(&track("SIDE:Left")).is_err());
+    assert!(parse_tja_file(&track("SONGVOL:loud")).is_err());
+}
+
+#[test]
+fn test_localised_titles() {
+    let song = parse_tja_file(
+        "TITLE:夏祭り\nSUBTITLE:--Jitterin'Jinn\nTITLEEN:Natsu Matsuri\nTITLECN:夏日祭典\n\
+         SUBTITLECN:\nTITLEKO:\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n1111,\n#END\n",
+    )
+    .unwrap();
+
+    // Empty translations are left out
+    assert_eq!(
+        song.localised_titles.keys().collect::<Vec<_>>(),
+        ["cn", "en"]
+    );
+
+    assert_eq!(song.title_in(Some("en")), "Natsu Matsuri");
+    assert_eq!(song.title_in(Some("cn")), "夏日祭典");
+    // Anything that isn't translated is shown as it is
+    assert_eq!(song.subtitle_in(Some("en")), Some("--Jitterin'Jinn"));
+    assert_eq!(song.title_in(Some("ko")), "夏祭り");
+    assert_eq!(song.title_in(None), "夏祭り");
+
+    assert_eq!(
+        song.all_titles().collect::<Vec<_>>(),
+        ["夏祭り", "--Jitterin'Jinn", "夏日祭典", "Natsu Matsuri"]
+    );
+}
+
+#[test]
+fn test_bpm_changes() {
+    let track = "TITLE:Tempo\nBPM:120\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n\
+                 1111,\n#BPMCHANGE 240\n1111,\n#BPMCHANGE 60\n1111,\n#END\n";
+    let song = parse_tja_file(track).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    assert_eq!(
+        chart.bpm_changes,
+        vec![
+            BpmChange {
+                time: 0.0,
+                bpm: 120.0
+            },
+            BpmChange {
+                time: 2.0,
+                bpm: 240.0
+            },
+            BpmChange {
+                time: 3.0,
+                bpm: 60.0
+            },
+        ]
+    );
+
+    assert_eq!(chart.bpm_at(-1.0), Some(120.0));
+    assert_eq!(chart.bpm_at(2.0), Some(240.0));
+    assert_eq!(chart.bpm_at(2.5), Some(240.0));
+    assert_eq!(chart.bpm_at(10.0), Some(60.0));
+    assert_eq!(NoteChart::default().bpm_at(0.0), None);
+}
+
+#[test]
+fn test_scroll_modes() {
+    let chart = |mode: &str| {
+        let track = format!(
+            "TITLE:Beat scroll\nBPM:120\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n\
+             {mode}\n1111,\n#SCROLL 3\n#BPMCHANGE 240\n1111,\n#DELAY 1\n1,\n#END\n"
+        );
+        let song = parse_tja_file(&track).unwrap();
+        song.difficulties[3].as_ref().unwrap().chart.clone()
+    };
+    let speeds = |chart: &NoteChart| {
+        chart
+            .notes
+            .iter()
+            .map(|note| note.scroll_speed)
+            .collect::<Vec<_>>()
+    };
+
+    // Scroll speed changes are ignored, and the tempo is left to the track
+    let bms = chart("#BMSCROLL");
+    assert_eq!(bms.scroll_mode, ScrollMode::Bms);
+    assert_eq!(speeds(&bms), [1.0; 9]);
+    assert_eq!(
+        bms.delays,
+        [Delay {
+            time: 3.0,
+            length: 1.0
+        }]
+    );
+
+    // The track moves twice as fast at 240 BPM, so a beat is always the same distance
+    assert_close(bms.scroll_time(1.0), 1.0);
+    assert_close(bms.scroll_time(2.5), 3.0);
+    assert_close(bms.scroll_time(2.75) - bms.scroll_time(2.5), 0.5);
+    // And stops during the delay
+    assert_close(bms.scroll_time(3.5), 4.0);
+    assert_close(bms.scroll_time(4.0), bms.scroll_time(3.0) as f64);
+    assert_close(bms.scroll_time(4.5), 5.0);
+
+    let scrolled = bms.scrolled_note(bms.notes[8]);
+    assert_close(scrolled.time, 4.0);
+    assert_eq!(scrolled.scroll_speed, 1.0);
+
+    // Scroll speed changes still count, but without the tempo
+    let hbs = chart("#HBSCROLL");
+    assert_eq!(hbs.scroll_mode, ScrollMode::Hbs);
+    assert_eq!(speeds(&hbs), [1.0, 1.0, 1.0, 1.0, 3.0, 3.0, 3.0, 3.0, 3.0]);
+    assert_close(hbs.scroll_time(2.5), 3.0);
+
+    // Normally the track is just the time, and the speeds have the tempo in them
+    let normal = chart("");
+    assert_eq!(normal.scroll_mode, ScrollMode::Normal);
+    assert_eq!(
+        speeds(&normal),
+        [1.0, 1.0, 1.0, 1.0, 6.0, 6.0, 6.0, 6.0, 6.0]
+    );
+    assert_eq!(normal.scroll_time(3.5), 3.5);
+}
+
+#[test]
+fn test_complex_scroll() {
+    let track = "TITLE:Complex scroll\nBPM:120\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n\
+                 1,\n#SCROLL 1.5i\n1,\n#SCROLL 0.5+2i\n1,\n#SCROLL -i\n1,\n#SCROLL 2-1e-1i\n1,\n\
+                 #BPMCHANGE 240\n#SCROLL 1+i\n1,\n#END\n";
+    let song = parse_tja_file(track).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    let speeds: Vec<_> = chart
+        .notes
+        .iter()
+        .map(|note| [note.scroll_speed, note.vertical_speed])
+        .collect();
+    // Both parts are scaled by the tempo
+    assert_eq!(
+        speeds,
+        [
+            [1.0, 0.0],
+            [0.0, 1.5],
+            [0.5, 2.0],
+            [0.0, -1.0],
+            [2.0, -0.1],
+            [2.0, 2.0]
+        ]
+    );
+    // Barlines move the same way as the notes in the measure before them
+    assert_eq!(chart.barlines[3].scroll_speed, 0.5);
+    assert_eq!(chart.barlines[3].vertical_speed, 2.0);
+
+    // Anything else still isn't a number
+    for scroll in ["1+2j", "i+1", "1++2i", "2ii"] {
+        let track = track.replace("1.5i", scroll);
+        assert!(parse_tja_file(&track).is_err(), "{scroll}");
+    }
+}
+
+#[test]
+fn test_measure_start() {
+    // Measures are two seconds long at 120 BPM
+    let chart = constant_chart(120, "1111", 4);
+
+    assert_eq!(chart.measure_start(-1.0), 0.0);
+    assert_eq!(chart.measure_start(0.0), 0.0);
+    assert_eq!(chart.measure_start(3.9), 2.0);
+    assert_eq!(chart.measure_start(4.0), 4.0);
+    assert_eq!(chart.measure_start(100.0), 8.0);
+
+    assert_eq!(chart.measure_end(0.0), Some(2.0));
+    assert_eq!(chart.measure_end(3.9), Some(4.0));
+    assert_eq!(chart.measure_end(4.0), Some(6.0));
+    assert_eq!(chart.measure_end(100.0), None);
+
+    let track = "TITLE:Barlines\nBPM:120\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n\
+                 11\n#BARLINEOFF\n11,\n#BARLINEON\n1111,\n1111,\n#END\n";
+    let song = parse_tja_file(track).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    // A measure without a barline belongs to the one before it
+    assert_eq!(chart.measure_start(3.0), 0.0);
+    assert_eq!(chart.measure_start(5.0), 4.0);
+    assert_eq!(chart.measure_end(1.0), Some(4.0));
+}
+
+#[test]
+fn test_note_density() {
+    // Two notes a second for 16 seconds
+    let chart = constant_chart(120, "1111", 8);
+
+    let density = note_density(&chart, 32.0, 4);
+    assert_eq!(density, vec![2.0, 2.0, 0.0, 0.0]);
+
+    // Notes outside of the length aren't counted
+    let density = note_density(&chart, 4.0, 2);
+    assert_eq!(density, vec![2.0, 2.0]);
+
+    assert_eq!(note_density(&chart, 0.0, 4), vec![0.0; 4]);
+    assert!(note_density(&chart, 32.0, 0).is_empty());
+}
+
+/// A chart of don notes at a constant BPM, where each measure is written out in full.
+fn constant_chart(bpm: u32, measure: &str, measures: usize) -> NoteChart {
+    let track = format!(
+        "TITLE:Test\nBPM:{bpm}\nWAVE:test.ogg\n\nCOURSE:Oni\nLEVEL:10\n\n#START\n{}#END\n",
+        format!("{measure},\n").repeat(measures)
+    );
+
+    parse_tja_file(&track).unwrap().difficulties[3]
+        .take()
+        .unwrap()
+        .chart
+}
+
+#[test]
+fn test_estimate_matches_real_charts() {
+    let song = parse_tja_file(include_str!("./Ready to.tja")).unwrap();
+
+    // The declared levels of this song are accepted as being accurate, so the estimate should be
+    // close to them.
+    let expected_ranges = [
+        (0, 1.0..=2.5),
+        (1, 2.0..=4.0),
+        (2, 4.0..=6.5),
+        (3, 6.5..=9.0),
+    ];
+
+    for (difficulty, range) in expected_ranges {
+        let difficulty = song.difficulties[difficulty].as_ref().unwrap();
+        let estimate = estimate_difficulty(&difficulty.chart);
+
+        assert!(
+            range.contains(&estimate),
+            "estimate for level {} was {estimate}",
+            difficulty.star_level
+        );
+        assert!(!estimate_disagrees(difficulty.star_level, estimate));
+    }
+}
+
+#[test]
+fn test_estimate_extremes() {
+    assert_eq!(estimate_difficulty(&NoteChart::default()), MIN_ESTIMATE);
+
+    // Quarter notes at 100 BPM are about as easy as it gets
+    let easy = estimate_difficulty(&constant_chart(100, "1000", 32));
+    assert!((1.0..=2.5).contains(&easy), "easy estimate was {easy}");
+
+    // Nonstop 1/16 notes at 200 BPM are about as hard as it gets
+    let hard = estimate_difficulty(&constant_chart(200, "1212121212121212", 32));
+    assert!((9.0..=10.0).contains(&hard), "hard estimate was {hard}");
+    assert!(estimate_disagrees(3, hard));
+}
+
+#[test]
+fn test_estimate_increases_with_density() {
+    let estimates = ["1010", "1111", "11111111", "1111111111111111"]
+        .map(|measure| estimate_difficulty(&constant_chart(150, measure, 32)));
+
+    assert!(
+        estimates.windows(2).all(|pair| pair[0] < pair[1]),
+        "{estimates:?}"
+    );
+}
+
+/// A short track for the chart hash tests, with a bit of every kind of note in it. The metadata
+/// and formatting can be changed through the arguments, but the notes always stay the same.
+fn hash_track(title: &str, level: u32, separator: &str) -> String {
+    format!(
+        "TITLE:{title}
+BPM:142
+WAVE:POP TEAM EPIC.ogg
+OFFSET:-0.5
+BALLOON:10
+COURSE:Oni
+LEVEL:{level}
+
+#START
+{separator}
+1120,
+3400,
+5008,
+7008,
+1010202011102020,
+#END
+"
+    )
+}
+
+fn hash_of(tja: &str) -> ChartHash {
+    let song = parse_tja_file(tja).unwrap();
+    chart_hash(&song.difficulties[3].as_ref().unwrap().chart)
+}
+
+#[test]
+fn test_chart_hash_golden() {
+    // These hashes must never change: anything stored about a chart is keyed by them. If this
+    // test fails, either the parser now produces different notes for these charts, or the hash
+    // input has changed without a new version. See the documentation of the hash module.
+    let song = parse_tja_file(include_str!("./Ready to.tja")).unwrap();
+    let hashes = song
+        .difficulties
+        .iter()
+        .map(|difficulty| {
+            difficulty
+                .as_ref()
+                .map(|difficulty| chart_hash(&difficulty.chart).to_string())
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        hashes,
+        [
+            Some("v1:bf64cbc94a350857914c0678a181239c7147d0e9207e97cf397a871fb891bddd"),
+            Some("v1:37a183d061ea99de22f0fb2318dbf7ba3eb53327cfb0cbc1e513f2a285d0382c"),
+            Some("v1:4cd8328e586340dfb6e863dd7e27f6f8d8ca49f414115d8142dfbb8128a9e742"),
+            Some("v1:eb286c8ca683af96f1632ee7aa3ef7817923d579e5c22b1e25b4a46661fb213f"),
+            None,
+        ]
+        .map(|hash| hash.map(str::to_string))
+    );
+}
+
+#[test]
+fn test_chart_hash_versions() {
+    let song = parse_tja_file(include_str!("./Ready to.tja")).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    assert_eq!(chart_hash(chart).version, CURRENT_HASH_VERSION);
+    assert_eq!(
+        hash_with_version(chart, CURRENT_HASH_VERSION),
+        Some(chart_hash(chart))
+    );
+    assert_eq!(hash_with_version(chart, 1), Some(hash_v1(chart)));
+    assert_eq!(hash_with_version(chart, 0), None);
+}
+
+#[test]
+fn test_chart_hash_ignores_formatting_and_metadata() {
+    let original = hash_of(&hash_track("POP TEAM EPIC", 8, ""));
+
+    let commented = hash_track("POP TEAM EPIC", 8, "// a comment\n\n")
+        .replace("1120,", "1120, // another comment");
+    let spaced = hash_track("POP TEAM EPIC", 8, "\n\n").replace("3400,", "  \t3400,  ");
+    let retitled = hash_track("POPUTEPIPIC", 8, "");
+    let relevelled = hash_track("POP TEAM EPIC", 10, "");
+    let more_metadata = hash_track("POP TEAM EPIC", 8, "").replace(
+        "BPM:142",
+        "SUBTITLE:--Sumire Uesaka\nDEMOSTART:30.5\nBPM:142",
+    );
+    let designer =
+        hash_track("POP TEAM EPIC", 8, "").replace("LEVEL:8", "LEVEL:8\nNOTESDESIGNER3:someone");
+    let rewaved = hash_track("POP TEAM EPIC", 8, "").replace("POP TEAM EPIC.ogg", "song.mp3");
+
+    for tja in [
+        commented,
+        spaced,
+        retitled,
+        relevelled,
+        more_metadata,
+        designer,
+        rewaved,
+    ] {
+        assert_eq!(hash_of(&tja), original, "hash changed for:\n{tja}");
+    }
+}
+
+#[test]
+fn test_chart_hash_detects_note_changes() {
+    let original = hash_of(&hash_track("POP TEAM EPIC", 8, ""));
+
+    let changes = [
+        // A don becomes a kat
+        ("1120,", "1220,"),
+        // A note is added
+        ("3400,", "3440,"),
+        // A note is removed
+        ("3400,", "3000,"),
+        // A drumroll gets longer
+        ("5008,", "5000,\n0008,"),
+        // Everything moves a little later
+        ("OFFSET:-0.5", "OFFSET:-0.501"),
+    ];
+
+    for (from, to) in changes {
+        let tja = hash_track("POP TEAM EPIC", 8, "").replacen(from, to, 1);
+        assert_ne!(hash_of(&tja), original, "hash didn't change for:\n{tja}");
+    }
+}
+
+#[test]
+fn test_duplicate_course_error_uses_difficulty_names() {
+    for (course, name) in [("Oni", "Oni"), ("Edit", "Ura"), ("2", "Hard")] {
+        let track = format!(
+            "TITLE:POP TEAM EPIC
+BPM:142
+WAVE:POP TEAM EPIC.ogg
+
+COURSE:{course}
+LEVEL:1
+#START
+1,
+#END
+
+COURSE:{course}
+LEVEL:2
+#START
+1,
+#END
+"
+        );
+
+        let error = parse_tja_file(&track).unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TJAParseErrorKind::MultipleTracksSameDifficulty(_)
+        ));
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("multiple courses defined for {name} difficulty")),
+            "{error}"
+        );
+    }
+}
+
+/// The folder with the fixture charts. See the README in there for how fixtures work.
+const FIXTURES_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tja");
+
+/// How far apart two floats in a parsed song can be and still count as the same.
+const FIXTURE_TOLERANCE: f64 = 1e-4;
+
+/// Reads and parses a chart the same way songs are loaded in the game, returning the parsed song
+/// or the error message. Charts whose names start with `lenient_` are parsed in lenient mode.
+fn parse_fixture(path: &std::path::Path) -> Result<toml::Value, String> {
+    let contents = decode_chart(&std::fs::read(path).map_err(|e| e.to_string())?);
+    let options = ParseOptions {
+        lenient: path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("lenient_")),
+        ..Default::default()
+    };
+
+    let song = parse_tja_file_with_options(&contents, &options).map_err(|e| e.to_string())?;
+    Ok(toml::Value::try_from(&song).expect("songs should serialize to toml"))
+}
+
+/// Checks that two values are the same, apart from floats that are within [FIXTURE_TOLERANCE] of
+/// each other. Returns the path to the first difference, if there is one.
+fn compare_values(path: &str, actual: &toml::Value, expected: &toml::Value) -> Result<(), String> {
+    use toml::Value;
+
+    match (actual, expected) {
+        (Value::Float(a), Value::Float(b)) if (a - b).abs() <= FIXTURE_TOLERANCE => Ok(()),
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => a
+            .iter()
+            .zip(b)
+            .enumerate()
+            .try_for_each(|(i, (a, b))| compare_values(&format!("{path}[{i}]"), a, b)),
+        (Value::Table(a), Value::Table(b)) if a.keys().eq(b.keys()) => a
+            .iter()
+            .zip(b.values())
+            .try_for_each(|((key, a), b)| compare_values(&format!("{path}.{key}"), a, b)),
+        (a, b) if a == b => Ok(()),
+        (a, b) => Err(format!("at {path}: got {a}, expected {b}")),
+    }
+}
+
+/// Parses every chart in the fixtures folder, and checks the result against the expected song (in
+/// a `.toml` file of the same name) or the expected error (in a `.error` file).
+///
+/// Run with `UPDATE_FIXTURES=1` to write the expected results from what the parser currently
+/// does, instead of checking them. Make sure to look over what changed before committing them!
+#[test]
+fn test_fixtures() {
+    let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+
+    let mut charts = std::fs::read_dir(FIXTURES_PATH)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tja"))
+        .collect::<Vec<_>>();
+    charts.sort();
+    assert!(!charts.is_empty(), "no fixtures found in {FIXTURES_PATH}");
+
+    let mut failures = Vec::new();
+
+    for chart in charts {
+        let golden_path = chart.with_extension("toml");
+        let error_path = chart.with_extension("error");
+        let result = parse_fixture(&chart);
+
+        if update {
+            let _ = std::fs::remove_file(&golden_path);
+            let _ = std::fs::remove_file(&error_path);
+
+            match &result {
+                Ok(song) => std::fs::write(&golden_path, toml::to_string(song).unwrap()),
+                Err(message) => std::fs::write(&error_path, format!("{message}\n")),
+            }
+            .unwrap();
+
+            continue;
+        }
+
+        let name = chart.file_name().unwrap().to_string_lossy().into_owned();
+
+        let outcome = match (
+            result,
+            std::fs::read_to_string(&golden_path),
+            std::fs::read_to_string(&error_path),
+        ) {
+            (Ok(song), Ok(golden), Err(_)) => {
+                let expected = toml::from_str::<toml::Value>(&golden).unwrap();
+                compare_values("song", &song, &expected)
+            }
+            (Err(message), Err(_), Ok(expected)) if message == expected.trim_end() => Ok(()),
+            (Err(message), Err(_), Ok(expected)) => Err(format!(
+                "expected error \"{}\", got \"{message}\"",
+                expected.trim_end()
+            )),
+            (Ok(_), Err(_), Ok(expected)) => Err(format!(
+                "expected error \"{}\", but it parsed",
+                expected.trim_end()
+            )),
+            (Err(message), Ok(_), Err(_)) => {
+                Err(format!("expected it to parse, got \"{message}\""))
+            }
+            _ => Err("there should be exactly one .toml or .error file for the chart".to_string()),
+        };
+
+        if let Err(e) = outcome {
+            failures.push(format!("{name}: {e}"));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_branches() {
+    let track = |body: &str| {
+        format!(
+            "TITLE:Branches
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:8
+#START
+{body}
+#END
+"
+        )
+    };
+
+    let song = parse_tja_file(&track(
+        "1111,
+#BRANCHSTART r,5,10
+#N
+1,
+#E
+#BPMCHANGE 240
+11,
+#M
+2222,
+#BRANCHEND
+1,
+#SECTION
+1,
+#BRANCHSTART p,50,75
+#N
+1,
+#M
+2,",
+    ))
+    .unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    let times = |notes: &[Note]| notes.iter().map(|note| note.time).collect::<Vec<_>>();
+
+    // Everything outside the branches is in the chart as normal, carrying on from the end of the
+    // last branch
+    assert_eq!(times(&chart.notes), vec![0.0, 0.5, 1.0, 1.5, 4.0, 6.0]);
+    assert_eq!(chart.branch_sections.len(), 2);
+
+    // Each branch starts from where the section starts, and changes in one don't affect the others
+    let first = &chart.branch_sections[0];
+    assert_eq!(
+        first.condition,
+        BranchCondition::Drumrolls {
+            expert: 5.0,
+            master: 10.0
+        }
+    );
+    assert_eq!(
+        (first.start, first.decision_time, first.measured_from),
+        (2.0, 0.0, 0.0)
+    );
+    let [normal, expert, master] = &first.branches;
+    assert_eq!(times(&normal.notes), vec![2.0]);
+    assert_eq!(times(&expert.notes), vec![2.0, 2.5]);
+    assert_eq!(expert.notes[0].scroll_speed, 2.0);
+    assert_eq!(times(&master.notes), vec![2.0, 2.5, 3.0, 3.5]);
+    assert_eq!(expert.barlines[0].time, 3.0);
+    assert_eq!(normal.barlines[0].time, 4.0);
+
+    // The second section starts counting from #SECTION, and a branch can be left out
+    let second = &chart.branch_sections[1];
+    assert_eq!(
+        (second.start, second.decision_time, second.measured_from),
+        (8.0, 6.0, 6.0)
+    );
+    assert!(second.branches[Branch::Expert as usize].notes.is_empty());
+
+    let all_notes = chart.all_notes();
+    assert_eq!(all_notes.len(), 15);
+    assert!(all_notes.is_sorted_by(|(a, _), (b, _)| a.time <= b.time));
+    assert_eq!(all_notes[4].1, Some((0, Branch::Normal)));
+    assert_eq!(all_notes.last().unwrap().1, Some((1, Branch::Master)));
+
+    assert_eq!(
+        times(&chart.on_branch(Branch::Expert).notes),
+        vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 4.0, 6.0]
+    );
+
+    for (body, kind) in [
+        (
+            "#BRANCHSTART s,1000,2000",
+            TJAParseErrorKind::Unsupported("score branch conditions"),
+        ),
+        ("#BRANCHSTART p,80", TJAParseErrorKind::CourseCommandError),
+        (
+            "1,
+#N
+1,",
+            TJAParseErrorKind::CourseCommandError,
+        ),
+        (
+            "#BRANCHSTART p,80,90
+#N
+5,
+#E
+8,",
+            TJAParseErrorKind::RollNotEnded,
+        ),
+    ] {
+        assert_eq!(
+            parse_tja_file(&track(body)).unwrap_err().kind,
+            kind,
+            "{body}"
+        );
+    }
+}
+
+#[test]
+fn test_timing_values_must_be_finite() {
+    let track = |metadata: &str, command: &str| {
+        format!(
+            "TITLE:Timing
+BPM:120
+WAVE:song.ogg
+{metadata}
+COURSE:Oni
+LEVEL:5
+#START
+1000,
+{command}
+1000,
+#END
+"
+        )
+    };
+
+    for command in [
+        "#BPMCHANGE 0",
+        "#BPMCHANGE inf",
+        "#DELAY NaN",
+        "#SCROLL -inf",
+    ] {
+        let error = parse_tja_file(&track("", command)).unwrap_err();
+        assert_eq!(
+            error.kind,
+            TJAParseErrorKind::CourseCommandError,
+            "{command}"
+        );
+        assert_eq!(error.line, 8, "{command}");
+    }
+
+    for metadata in ["OFFSET:inf", "HEADSCROLL:NaN"] {
+        let error = parse_tja_file(&track(metadata, "")).unwrap_err();
+        assert_eq!(error.kind, TJAParseErrorKind::InvalidMetadata, "{metadata}");
+        assert_eq!(error.line, 3, "{metadata}");
+    }
+}
+
+#[test]
+fn test_empty_courses_are_left_out() {
+    let track = "TITLE:Placeholders
+BPM:120
+WAVE:song.ogg
+
+COURSE:Hard
+LEVEL:4
+#START
+#SCROLL 2
+0000,
+#END
+
+COURSE:Oni
+LEVEL:8
+#START
+1,
+#END
+";
+
+    // Left out in both strict and lenient mode, with a warning either way
+    for lenient in [false, true] {
+        let options = ParseOptions {
+            lenient,
+            ..Default::default()
+        };
+
+        let song = parse_tja_file_with_options(track, &options).unwrap();
+        assert!(song.difficulties[2].is_none());
+        assert!(song.difficulties[3].is_some());
+        assert_eq!(
+            song.warnings,
+            [TJAParseWarning {
+                kind: TJAParseWarningKind::EmptyCourse(2),
+                line: 6,
+            }]
+        );
+        assert_eq!(
+            song.warnings[0].to_string(),
+            "the Hard course has no notes, so it was left out (at line 7)"
+        );
+    }
+
+    // Reading just the empty course gives a song without it
+    let options = ParseOptions {
+        courses: CourseMask::only(2),
+        ..Default::default()
+    };
+    let song = parse_tja_file_with_options(track, &options).unwrap();
+    assert!(song.difficulties.iter().all(Option::is_none));
+    assert_eq!(song.warnings.len(), 1);
+
+    // An empty course still counts when looking for two courses of the same difficulty
+    let duplicated = track.replace("COURSE:Oni", "COURSE:Hard");
+    assert!(matches!(
+        parse_tja_file(&duplicated).unwrap_err().kind,
+        TJAParseErrorKind::MultipleTracksSameDifficulty(2)
+    ));
+}
+
+#[test]
+fn test_player_charts() {
+    let track = "TITLE:Players
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:8
+
+#START P2
+2222,
+#END
+
+#START
+1000,
+#END
+
+#START P1
+1111,
+#END
+";
+
+    // Playing alone uses the single player course, wherever it is in the file
+    let song = parse_tja_file(track).unwrap();
+    let oni = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(oni.chart.notes.len(), 1);
+
+    let [p1, p2] = oni.player_charts.as_ref().unwrap();
+    assert!(p1.notes.iter().all(|note| note.note_type == NoteType::Don));
+    assert!(p2.notes.iter().all(|note| note.note_type == NoteType::Kat));
+    assert_eq!((p1.notes.len(), p2.notes.len()), (4, 4));
+
+    // With only one player's course, there's nothing to play together, so it's played alone
+    let p1_only = "TITLE:Players
+BPM:120
+WAVE:song.ogg
+LEVEL:8
+#START P1
+1111,
+#END
+";
+    let song = parse_tja_file(p1_only).unwrap();
+    let oni = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(oni.chart.notes.len(), 4);
+    assert!(oni.player_charts.is_none());
+
+    // Each player can only have one course of each difficulty
+    let duplicated = track.replace("#START P2", "#START P1");
+    assert!(matches!(
+        parse_tja_file(&duplicated).unwrap_err().kind,
+        TJAParseErrorKind::MultipleTracksSameDifficulty(3)
+    ));
+}
+
+#[test]
+fn test_course_mask() {
+    // Metadata between the courses has to apply to the right courses, whichever ones are skipped
+    let track = "TITLE:Courses
+BPM:120
+WAVE:song.ogg
+BALLOON:4
+
+COURSE:Easy
+LEVEL:1
+#START
+1000,
+#END
+
+COURSE:Normal
+LEVEL:3
+BPM:150
+#START
+70080000,
+#END
+
+COURSE:Hard
+LEVEL:5
+BALLOON:9
+#START
+1010,
+7008,
+#END
+
+COURSE:Oni
+LEVEL:8
+HEADSCROLL:2
+#START
+// Errors in skipped courses aren't noticed
+1111,
+#SOMETHING
+#END
+
+COURSE:Edit
+LEVEL:9
+#START
+11111111,
+#END
+";
+
+    let describe = |difficulty: &Option<Difficulty>| format!("{difficulty:?}");
+    let without_oni = track.replace("#SOMETHING\n", "");
+    let full = parse_tja_file(&without_oni).unwrap();
+
+    for difficulty in 0..5 {
+        let options = ParseOptions {
+            courses: CourseMask::only(difficulty),
+            ..Default::default()
+        };
+
+        let song = parse_tja_file_with_options(track, &options);
+
+        // The oni course has an error in it, so it's the only one that can't be read by itself
+        if difficulty == 3 {
+            assert!(song.is_err());
+            continue;
+        }
+
+        let song = song.unwrap();
+        for (i, course) in song.difficulties.iter().enumerate() {
+            if i == difficulty {
+                assert_eq!(describe(course), describe(&full.difficulties[i]));
+            } else {
+                assert!(course.is_none(), "course {i} wasn't skipped");
+            }
+        }
+    }
+
+    // Skipped courses still need to end
+    let unended = "TITLE:Unended\nWAVE:song.ogg\nCOURSE:Easy\nLEVEL:1\n#START\n1,\n";
+    let options = ParseOptions {
+        courses: CourseMask::only(3),
+        ..Default::default()
+    };
+    assert_eq!(
+        parse_tja_file_with_options(unended, &options)
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::ExpectedEndCommand
+    );
+}
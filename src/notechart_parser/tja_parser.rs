@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use lookahead::Lookahead;
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_while1},
+    bytes::complete::{is_not, tag, take_while, take_while1},
     character::complete::satisfy,
     combinator::{eof, map_res, opt, recognize},
     error::{FromExternalError, ParseError},
@@ -12,7 +12,14 @@ use nom::{
     Finish, IResult, Parser,
 };
 
-use super::chart::{Barline, Difficulty, Note, NoteChart, NoteType, Song};
+use crate::difficulty::DifficultyInfo;
+
+use super::chart::{
+    Barline, BpmChange, Branch, BranchCondition, BranchNotes, BranchSection, Delay, Difficulty,
+    GogoRegion, LocalisedTitle, Note, NoteChart, NoteType, ScrollMode, Song, SongSide,
+    TITLE_LANGUAGES,
+};
+use super::estimate::estimate_difficulty;
 /// Types of errors that can be encountered while parsing a TJA file. This is used in the
 /// [TJAParseError] struct.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -26,6 +33,38 @@ pub enum TJAParseErrorKind {
     MissingMetadataForSong(String),
     RollNotEnded,
     RollEndWithoutRoll,
+    /// The chart uses part of the format that isn't supported yet. Contains a description of
+    /// what that is.
+    Unsupported(&'static str),
+    /// A `#MEASURE` command with a zero in it, which would make measures take no time at all (or
+    /// divide by zero).
+    InvalidTimeSignature(u8, u8),
+    /// The times of the notes couldn't be worked out, e.g. because they overflowed. Most things
+    /// that could cause this are caught earlier with a more useful error.
+    InvalidTiming,
+}
+
+impl TJAParseErrorKind {
+    /// A short description of the kind of error, without any of the details. Useful for grouping
+    /// errors together.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TJAParseErrorKind::SyntaxError => "syntax error",
+            TJAParseErrorKind::CourseCommandError => "invalid song notation command",
+            TJAParseErrorKind::InvalidMetadata => "invalid song metadata",
+            TJAParseErrorKind::MultipleTracksSameDifficulty(_) => {
+                "multiple courses for a difficulty"
+            }
+            TJAParseErrorKind::ExpectedEndCommand => "expected #END command",
+            TJAParseErrorKind::MissingMetadataForCourse(_) => "missing metadata for a difficulty",
+            TJAParseErrorKind::MissingMetadataForSong(_) => "missing metadata for the song",
+            TJAParseErrorKind::RollNotEnded => "drumroll not ended",
+            TJAParseErrorKind::RollEndWithoutRoll => "drumroll end without preceding drumroll",
+            TJAParseErrorKind::Unsupported(_) => "unsupported feature",
+            TJAParseErrorKind::InvalidTimeSignature(_, _) => "invalid time signature",
+            TJAParseErrorKind::InvalidTiming => "invalid note timing",
+        }
+    }
 }
 
 /// An error that can be encountered while parsing a TJA file. Contains an enum for the kind of
@@ -45,14 +84,7 @@ impl std::fmt::Display for TJAParseError {
             }
             TJAParseErrorKind::InvalidMetadata => f.write_str("invalid song metadata")?,
             TJAParseErrorKind::MultipleTracksSameDifficulty(diff) => {
-                let difficulty = match diff {
-                    0 => "easy",
-                    1 => "normal",
-                    2 => "hard",
-                    3 => "extreme",
-                    4 => "extra extreme",
-                    _ => panic!("difficulty is out of range 0-4"),
-                };
+                let difficulty = DifficultyInfo::name_of(*diff);
 
                 f.write_fmt(format_args!(
                     "multiple courses defined for {difficulty} difficulty"
@@ -71,6 +103,18 @@ impl std::fmt::Display for TJAParseError {
             TJAParseErrorKind::RollEndWithoutRoll => {
                 f.write_str("drumroll end without preceding drumroll")?
             }
+            TJAParseErrorKind::Unsupported(feature) => {
+                f.write_fmt(format_args!("{feature} aren't supported yet"))?
+            }
+            TJAParseErrorKind::InvalidTimeSignature(numerator, denominator) => {
+                f.write_fmt(format_args!(
+                    "invalid time signature {numerator}/{denominator}, both numbers must be at \
+                     least 1"
+                ))?
+            }
+            TJAParseErrorKind::InvalidTiming => {
+                f.write_str("couldn't work out when the notes should be hit")?
+            }
         }
 
         f.write_fmt(format_args!(" (at line {})", self.line + 1))
@@ -79,6 +123,69 @@ impl std::fmt::Display for TJAParseError {
 
 impl std::error::Error for TJAParseError {}
 
+impl TJAParseError {
+    /// The lines of the chart around the error, numbered, with the line the error is on marked.
+    pub fn excerpt(&self, source: &str) -> String {
+        const CONTEXT_LINES: usize = 2;
+
+        source
+            .lines()
+            .enumerate()
+            .skip(self.line.saturating_sub(CONTEXT_LINES))
+            .take_while(|(i, _)| *i <= self.line + CONTEXT_LINES)
+            .map(|(i, line)| {
+                let marker = if i == self.line { '>' } else { ' ' };
+                format!("{marker}{:>5} | {}", i + 1, line.trim_end())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Kinds of problems that are worked around instead of being errors while parsing. Most of these
+/// are only worked around in lenient mode (see [ParseOptions::lenient]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TJAParseWarningKind {
+    /// A `#MEASURE` command with a zero in it. The previous time signature is kept instead.
+    InvalidTimeSignature(u8, u8),
+    /// A course with no notes in it, only commands and empty measures (the given difficulty).
+    /// Some charts have these as placeholders for difficulties that haven't been charted yet.
+    /// There's nothing to play, so the course is left out of the song. This is never an error,
+    /// even outside of lenient mode.
+    EmptyCourse(usize),
+}
+
+/// A problem with a TJA file that was worked around while parsing it, and the line it was on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TJAParseWarning {
+    pub kind: TJAParseWarningKind,
+    pub line: usize,
+}
+
+impl std::fmt::Display for TJAParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            TJAParseWarningKind::InvalidTimeSignature(numerator, denominator) => {
+                f.write_fmt(format_args!(
+                    "invalid time signature {numerator}/{denominator}, keeping the previous one"
+                ))?
+            }
+            TJAParseWarningKind::EmptyCourse(difficulty) => f.write_fmt(format_args!(
+                "the {} course has no notes, so it was left out",
+                DifficultyInfo::name_of(*difficulty)
+            ))?,
+        }
+
+        f.write_fmt(format_args!(" (at line {})", self.line + 1))
+    }
+}
+
+impl serde::Serialize for TJAParseWarning {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl<I> From<nom::error::Error<I>> for TJAParseErrorKind {
     fn from(_value: nom::error::Error<I>) -> Self {
         TJAParseErrorKind::SyntaxError
@@ -113,12 +220,80 @@ enum CourseCommand<'a> {
     BpmChange(f32),
     Measure(u8, u8),
     Delay(f32),
-    Scroll(f32),
+    /// The real and imaginary parts of the scroll speed. The imaginary part makes notes move up or
+    /// down the screen, and is usually 0.
+    Scroll([f32; 2]),
     GogoStart,
     GogoEnd,
     BarlineOff,
     BarlineOn,
-    // TODO: Commands for diverge notes
+    BranchStart(BranchCondition),
+    /// `#N`, `#E` or `#M`, which start the notes of a branch.
+    Branch(Branch),
+    BranchEnd,
+    /// Starts counting the player's performance for the next branch from here.
+    Section,
+    /// `#BMSCROLL` or `#HBSCROLL`, which changes how the notes move for the whole course, wherever
+    /// it is in the course.
+    ScrollMode(ScrollMode),
+}
+
+/// Parses the argument of a `#BRANCHSTART` command, e.g. `p,80,90`.
+fn branch_condition(arg: &str) -> Result<BranchCondition, TJAParseErrorKind> {
+    let [kind, expert, master] = arg
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| TJAParseErrorKind::CourseCommandError)?;
+
+    let expert = timing_arg(expert)?;
+    let master = timing_arg(master)?;
+
+    match kind {
+        "p" => Ok(BranchCondition::Accuracy { expert, master }),
+        "r" => Ok(BranchCondition::Drumrolls { expert, master }),
+        "s" => Err(TJAParseErrorKind::Unsupported("score branch conditions")),
+        _ => Err(TJAParseErrorKind::CourseCommandError),
+    }
+}
+
+/// Parses a number that's used to work out the timing of notes, so it has to be finite.
+fn timing_arg(arg: &str) -> Result<f32, TJAParseErrorKind> {
+    arg.parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or(TJAParseErrorKind::CourseCommandError)
+}
+
+/// Parses a scroll speed, which can be a complex number like `1.5i` or `0.5+2i`. Returns the real
+/// and imaginary parts.
+fn scroll_arg(arg: &str) -> Result<[f32; 2], TJAParseErrorKind> {
+    let Some(arg) = arg.strip_suffix('i') else {
+        return Ok([timing_arg(arg)?, 0.0]);
+    };
+
+    // The imaginary part starts at the last sign, unless that's the sign at the start or of an
+    // exponent (e.g. `1e-3i`)
+    let split = arg
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| i > 0 && matches!(c, '+' | '-') && !arg[..i].ends_with(['e', 'E']))
+        .map(|(i, _)| i);
+
+    let (real, imaginary) = match split {
+        Some(i) => (timing_arg(&arg[..i])?, &arg[i..]),
+        None => (0.0, arg),
+    };
+
+    // Just `i` is the same as `1i`
+    let imaginary = match imaginary {
+        "" | "+" => 1.0,
+        "-" => -1.0,
+        imaginary => timing_arg(imaginary)?,
+    };
+
+    Ok([real, imaginary])
 }
 
 impl<'a> CourseCommand<'a> {
@@ -132,7 +307,13 @@ impl<'a> CourseCommand<'a> {
             "END" => panic!("fatal error parsing song command: end command should have been handled seperately!"),
             "LYRIC" => CourseCommand::Lyric(arg_res?),
             "BPMCHANGE" => {
-                CourseCommand::BpmChange(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?)
+                let bpm = timing_arg(arg_res?)?;
+
+                if bpm == 0.0 {
+                    return Err(TJAParseErrorKind::CourseCommandError);
+                }
+
+                CourseCommand::BpmChange(bpm)
             }
             "MEASURE" => {
                 let (_, (numerator, denominator)) =
@@ -140,11 +321,12 @@ impl<'a> CourseCommand<'a> {
 
                 CourseCommand::Measure(numerator, denominator)
             }
-            "DELAY" => CourseCommand::Delay(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?),
-            "SCROLL" => {
-                CourseCommand::Scroll(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?)
-            }
-            "GOGOSTART" | "GOGOEND" | "BARLINEOFF" | "BARLINEON" => {
+            "DELAY" => CourseCommand::Delay(timing_arg(arg_res?)?),
+            "SCROLL" => CourseCommand::Scroll(scroll_arg(arg_res?)?),
+            "BRANCHSTART" => CourseCommand::BranchStart(branch_condition(arg_res?)?),
+            "LEVELHOLD" => return Err(TJAParseErrorKind::Unsupported("level holds")),
+            "GOGOSTART" | "GOGOEND" | "BARLINEOFF" | "BARLINEON" | "BRANCHEND" | "SECTION"
+            | "BMSCROLL" | "HBSCROLL" | "N" | "E" | "M" => {
                 // These dont take any arguments, so ensure there is no arg
                 if arg.is_some() {
                     return Err(TJAParseErrorKind::CourseCommandError);
@@ -155,6 +337,13 @@ impl<'a> CourseCommand<'a> {
                     "GOGOEND" => CourseCommand::GogoEnd,
                     "BARLINEOFF" => CourseCommand::BarlineOff,
                     "BARLINEON" => CourseCommand::BarlineOn,
+                    "BRANCHEND" => CourseCommand::BranchEnd,
+                    "SECTION" => CourseCommand::Section,
+                    "BMSCROLL" => CourseCommand::ScrollMode(ScrollMode::Bms),
+                    "HBSCROLL" => CourseCommand::ScrollMode(ScrollMode::Hbs),
+                    "N" => CourseCommand::Branch(Branch::Normal),
+                    "E" => CourseCommand::Branch(Branch::Expert),
+                    "M" => CourseCommand::Branch(Branch::Master),
                     _ => unreachable!(),
                 }
             }
@@ -226,11 +415,14 @@ fn time_signature(i: &str) -> IResult<&str, (u8, u8), TJAParseErrorKind> {
     separated_pair(integer::<u8>, tag("/"), integer::<u8>)(i)
 }
 
-/// Parses a metadata pair in the form `KEY:value`. The key must be made up entirely of uppercase
-/// letters.
+/// Parses a metadata pair in the form `KEY:value`. The key must start with an uppercase letter
+/// and be made up entirely of uppercase letters and digits (e.g. `NOTESDESIGNER3`).
 fn metadata_pair(input: &str) -> IResult<&str, (&str, &str)> {
     separated_pair(
-        take_while1(|c| ('A'..='Z').contains(&c)),
+        recognize(pair(
+            satisfy(|c| c.is_ascii_uppercase()),
+            take_while(|c: char| c.is_ascii_uppercase() || c.is_ascii_digit()),
+        )),
         tag(":"),
         opt(is_not("\r\n")).map(|value| value.unwrap_or("")),
     )(input)
@@ -349,8 +541,12 @@ fn course_item(input: &str) -> IResult<&str, CourseItem, TJAParseErrorKind> {
 /// Preprocess the lines that define a course and turn them into a vector of [CourseItem]s.
 /// This is necessary because we may need to look ahead while we're iterating through these items
 /// and constructing the difficulty.
+///
+/// Time signatures are checked here, as it's the last point where we know which line they're on.
 fn process_course<'a>(
     lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    options: &ParseOptions,
+    warnings: &mut Vec<TJAParseWarning>,
 ) -> Result<Vec<CourseItem<'a>>, TJAParseError> {
     // Needed for returning a line number error if we ever run out of lines
     let mut line_num = 0;
@@ -361,6 +557,22 @@ fn process_course<'a>(
 
         match parse(course_item)(line).map_err(|e| TJAParseError { kind: e, line: i })? {
             CourseItem::EndCommand => return Ok(res),
+            CourseItem::Command(CourseCommand::Measure(numerator, denominator))
+                if numerator == 0 || denominator == 0 =>
+            {
+                if options.lenient {
+                    // Leaving the command out keeps the previous time signature
+                    warnings.push(TJAParseWarning {
+                        kind: TJAParseWarningKind::InvalidTimeSignature(numerator, denominator),
+                        line: i,
+                    });
+                } else {
+                    return Err(TJAParseError {
+                        kind: TJAParseErrorKind::InvalidTimeSignature(numerator, denominator),
+                        line: i,
+                    });
+                }
+            }
             item => res.push(item),
         }
     }
@@ -371,6 +583,26 @@ fn process_course<'a>(
     })
 }
 
+/// Skips over the lines of a course without parsing them, up to and including its `#END`.
+fn skip_course<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(), TJAParseError> {
+    let mut line_num = 0;
+
+    for (i, line) in lines {
+        line_num = i;
+
+        if parse(end_command)(line).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(TJAParseError {
+        kind: TJAParseErrorKind::ExpectedEndCommand,
+        line: line_num,
+    })
+}
+
 fn get_parsed_metadata<'a, T: std::str::FromStr>(
     metadata: &HashMap<&'a str, (usize, &'a str)>,
     key: &'a str,
@@ -459,10 +691,98 @@ fn notes_in_next_measure<'a, I: Iterator<Item = CourseItem<'a>>>(iter: &mut Look
     num_notes
 }
 
+/// How long each note in a measure lasts. A measure without any notes has nothing to divide its
+/// length between, so this is zero instead of dividing by zero.
+fn note_length(seconds_per_measure: f64, notes_in_measure: usize) -> f64 {
+    if notes_in_measure == 0 {
+        0.0
+    } else {
+        seconds_per_measure / notes_in_measure as f64
+    }
+}
+
+/// How long a measure with the given time signature is at the given tempo, in seconds.
+fn measure_length(signature: f32, bpm: f32) -> f64 {
+    60.0 * signature as f64 * 4.0 / bpm as f64
+}
+
+/// Checks that a number from the metadata can be used to work out when notes happen: it has to be
+/// finite, and if `nonzero` is set, it can't be zero either.
+fn check_timing_metadata(
+    metadata: &HashMap<&str, (usize, &str)>,
+    key: &str,
+    value: f32,
+    nonzero: bool,
+    course_line: usize,
+) -> Result<f32, TJAParseError> {
+    if value.is_finite() && !(nonzero && value == 0.0) {
+        Ok(value)
+    } else {
+        Err(TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: metadata.get(key).map_or(course_line, |&(i, _)| i),
+        })
+    }
+}
+
+/// Whether every time in a chart is a real number.
+fn chart_timing_is_finite(chart: &NoteChart) -> bool {
+    let note_is_finite = |note: &Note| {
+        let duration = match note.note_type {
+            NoteType::Roll(duration)
+            | NoteType::BigRoll(duration)
+            | NoteType::BalloonRoll(duration, _)
+            | NoteType::SpecialRoll(duration, _) => duration,
+            _ => 0.0,
+        };
+
+        note.time.is_finite()
+            && note.scroll_speed.is_finite()
+            && note.vertical_speed.is_finite()
+            && duration.is_finite()
+    };
+
+    chart
+        .all_notes()
+        .iter()
+        .all(|(note, _)| note_is_finite(note))
+        && chart.all_barlines().iter().all(|(barline, _)| {
+            barline.time.is_finite()
+                && barline.scroll_speed.is_finite()
+                && barline.vertical_speed.is_finite()
+        })
+        && chart
+            .gogo_regions
+            .iter()
+            .all(|region| region.start.is_finite() && region.end.is_finite())
+        && chart.branch_sections.iter().all(|section| {
+            section.start.is_finite()
+                && section.decision_time.is_finite()
+                && section.measured_from.is_finite()
+        })
+}
+
+/// Where the timing of a course was at the start of a branched section. Each branch starts from
+/// here, since they're all played at the same time.
+#[derive(Debug, Clone, Copy)]
+struct BranchStartState {
+    time: f64,
+    measure_start_time: f64,
+    measure_delay: f64,
+    total_delay: f64,
+    bpm: f32,
+    signature: f32,
+    scroll_speed: [f32; 2],
+    unscaled_scroll: [f32; 2],
+    barline_on: bool,
+    gogo_start: Option<f64>,
+}
+
 fn construct_difficulty(
     items: Vec<CourseItem<'_>>,
     metadata: &HashMap<&str, (usize, &str)>,
     course_line_number: usize,
+    options: &ParseOptions,
 ) -> Result<Difficulty, TJAParseError> {
     let mut chart = NoteChart::default();
 
@@ -479,6 +799,16 @@ fn construct_difficulty(
     let init_scroll_speed =
         get_parsed_metadata::<f32>(metadata, "HEADSCROLL", Some(1.0), Some(course_line_number))?;
 
+    bpm = check_timing_metadata(metadata, "BPM", bpm, true, course_line_number)?;
+    let offset = check_timing_metadata(metadata, "OFFSET", offset, false, course_line_number)?;
+    let init_scroll_speed = check_timing_metadata(
+        metadata,
+        "HEADSCROLL",
+        init_scroll_speed,
+        false,
+        course_line_number,
+    )?;
+
     // If the number of balloons in the course is nonzero, we have to store
     // how many hits it takes to complete each one. This is the BALLOON metadata
     let balloons = metadata
@@ -493,23 +823,65 @@ fn construct_difficulty(
 
     let mut balloon_index = 0;
 
-    let mut unscaled_scroll = init_scroll_speed;
-    let mut scroll_speed = init_scroll_speed * bpm / DEFAULT_BPM;
+    // The first scroll mode command decides how the whole course moves
+    let scroll_mode = items
+        .iter()
+        .find_map(|item| match item {
+            CourseItem::Command(CourseCommand::ScrollMode(mode)) => Some(*mode),
+            _ => None,
+        })
+        .unwrap_or_default();
+    chart.scroll_mode = scroll_mode;
+
+    // How fast notes move across and up the screen with the given scroll speed at the given
+    // tempo. When the track moves by beat, the tempo is taken into account by how fast the whole
+    // track moves instead.
+    let note_speed = |[horizontal, vertical]: [f32; 2], bpm: f32| match scroll_mode {
+        ScrollMode::Normal => [horizontal * bpm / DEFAULT_BPM, vertical * bpm / DEFAULT_BPM],
+        ScrollMode::Bms => [init_scroll_speed, 0.0],
+        ScrollMode::Hbs => [horizontal, vertical],
+    };
+    let barline = |time: f64, [scroll_speed, vertical_speed]: [f32; 2]| Barline {
+        time: time as f32,
+        scroll_speed,
+        vertical_speed,
+    };
+
+    let mut unscaled_scroll = [init_scroll_speed, 0.0];
+    let mut scroll_speed = note_speed([init_scroll_speed, 0.0], bpm);
 
     let mut items_iter = lookahead::lookahead(items);
     let mut notes_in_measure = notes_in_next_measure(&mut items_iter);
-    let mut seconds_per_measure = 60.0 * signature * 4.0 / bpm;
+    let mut seconds_per_measure = measure_length(signature, bpm);
 
-    let mut seconds_per_note = if notes_in_measure == 0 {
-        0.0
-    } else {
-        seconds_per_measure / notes_in_measure as f32
-    };
+    let mut seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
 
-    let mut time = -offset;
+    // Times are kept in f64 while they're added up, so that notes late in a long song are still
+    // exactly where they should be
+    let mut time = -offset as f64;
     let mut measure_start_time = time;
-    let mut barlines = vec![Barline { time, scroll_speed }];
+    let mut previous_measure_start_time = time;
+    // The total amount of time added by #DELAY commands so far, and the amount added since the
+    // start of the current measure.
+    let mut total_delay = 0.0;
+    let mut measure_delay = 0.0;
+    let mut barlines = vec![(barline(time, scroll_speed), None)];
     let mut barline_on = true;
+    let mut gogo_start = None;
+    let mut gogo_regions = Vec::new();
+    let mut bpm_changes = vec![BpmChange {
+        time: time as f32,
+        bpm,
+    }];
+    let mut delays = Vec::new();
+
+    // The branched sections so far, and the state at the start of the one that's open, if any.
+    // Notes and barlines are tagged with the section and branch they're in (if any) as they're
+    // read, and sorted into the sections at the end.
+    let mut branch_sections: Vec<BranchSection> = Vec::new();
+    let mut branch_start: Option<BranchStartState> = None;
+    let mut current_branch: Option<(usize, Branch)> = None;
+    let mut section_start = time;
 
     let mut notes = Vec::new();
 
@@ -518,25 +890,116 @@ fn construct_difficulty(
             CourseItem::Command(command) => match command {
                 CourseCommand::BpmChange(new_bpm) => {
                     bpm = new_bpm;
-                    seconds_per_measure = 60.0 * signature * 4.0 / bpm;
-                    seconds_per_note = seconds_per_measure / notes_in_measure as f32;
-                    scroll_speed = init_scroll_speed * (unscaled_scroll) * bpm / DEFAULT_BPM
+
+                    // Each branch usually has the same tempo changes as the others
+                    let change = BpmChange {
+                        time: time as f32,
+                        bpm,
+                    };
+                    if !bpm_changes.contains(&change) {
+                        bpm_changes.push(change);
+                    }
+
+                    seconds_per_measure = measure_length(signature, bpm);
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+                    scroll_speed = note_speed(unscaled_scroll.map(|s| init_scroll_speed * s), bpm);
                 }
                 CourseCommand::Measure(num, den) => {
                     signature = num as f32 / den as f32;
-                    seconds_per_measure = 60.0 * signature * 4.0 / bpm;
-                    seconds_per_note = seconds_per_measure / notes_in_measure as f32;
+                    seconds_per_measure = measure_length(signature, bpm);
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+                }
+                CourseCommand::Delay(t) => {
+                    // Each branch usually has the same delays as the others, like tempo changes
+                    let delay = Delay {
+                        time: time as f32,
+                        length: t,
+                    };
+                    if !delays.contains(&delay) {
+                        delays.push(delay);
+                    }
+
+                    time += t as f64;
+                    total_delay += t as f64;
+                    measure_delay += t as f64;
                 }
-                CourseCommand::Delay(t) => time += t,
                 CourseCommand::Scroll(s) => {
-                    scroll_speed = init_scroll_speed * (s) * bpm / DEFAULT_BPM;
+                    scroll_speed = note_speed(s.map(|s| init_scroll_speed * s), bpm);
                     unscaled_scroll = s;
                 }
-                CourseCommand::GogoStart => {}
-                CourseCommand::GogoEnd => {}
+                CourseCommand::GogoStart => {
+                    gogo_start.get_or_insert(time);
+                }
+                CourseCommand::GogoEnd => {
+                    if let Some(start) = gogo_start.take() {
+                        let region = GogoRegion {
+                            start: start as f32,
+                            end: time as f32,
+                        };
+
+                        // Each branch usually has the same go-go time as the others
+                        if !gogo_regions.contains(&region) {
+                            gogo_regions.push(region);
+                        }
+                    }
+                }
                 CourseCommand::BarlineOff => barline_on = false,
                 CourseCommand::BarlineOn => barline_on = true,
-                _ => {}
+                CourseCommand::BranchStart(condition) => {
+                    // A new section ends the one before it, if it wasn't ended already
+                    branch_start = Some(BranchStartState {
+                        time,
+                        measure_start_time,
+                        measure_delay,
+                        total_delay,
+                        bpm,
+                        signature,
+                        scroll_speed,
+                        unscaled_scroll,
+                        barline_on,
+                        gogo_start,
+                    });
+                    current_branch = None;
+
+                    branch_sections.push(BranchSection {
+                        condition,
+                        start: time,
+                        decision_time: previous_measure_start_time,
+                        measured_from: section_start,
+                        branches: Default::default(),
+                    });
+                }
+                CourseCommand::Branch(branch) => {
+                    let Some(state) = branch_start else {
+                        return Err(TJAParseError {
+                            kind: TJAParseErrorKind::CourseCommandError,
+                            line: course_line_number,
+                        });
+                    };
+
+                    time = state.time;
+                    measure_start_time = state.measure_start_time;
+                    measure_delay = state.measure_delay;
+                    total_delay = state.total_delay;
+                    bpm = state.bpm;
+                    signature = state.signature;
+                    scroll_speed = state.scroll_speed;
+                    unscaled_scroll = state.unscaled_scroll;
+                    barline_on = state.barline_on;
+                    gogo_start = state.gogo_start;
+
+                    seconds_per_measure = measure_length(signature, bpm);
+                    notes_in_measure = notes_in_next_measure(&mut items_iter);
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+                    current_branch = Some((branch_sections.len() - 1, branch));
+                }
+                // The chart carries on from the end of the last branch
+                CourseCommand::BranchEnd => {
+                    branch_start = None;
+                    current_branch = None;
+                }
+                CourseCommand::Section => section_start = time,
+                CourseCommand::Lyric(_) | CourseCommand::ScrollMode(_) => {}
             },
             CourseItem::Notes {
                 notes: new_notes,
@@ -600,7 +1063,13 @@ fn construct_difficulty(
                                 }
                             };
 
-                            Ok((note_type, time + seconds_per_note * i as f32, scroll_speed))
+                            Ok((
+                                note_type,
+                                time + seconds_per_note * i as f64,
+                                scroll_speed,
+                                total_delay,
+                                current_branch,
+                            ))
                         })
                     })
                     .collect::<Result<Vec<_>, _>>()?;
@@ -608,30 +1077,29 @@ fn construct_difficulty(
                 notes.extend(new_notes);
                 // Update the current time. We didn't have to do this for each note
                 // because they're evenly spaced.
-                let elapsed_time = num_notes as f32 * seconds_per_note;
+                let elapsed_time = num_notes as f64 * seconds_per_note;
                 time += elapsed_time;
 
                 if end_measure {
                     if notes_in_measure == 0 {
                         // Make sure that even if we've had no notes we're still at
-                        // the next measure
-                        time = measure_start_time + seconds_per_measure;
+                        // the next measure. Any delays in this measure still push the
+                        // next barline back.
+                        time = measure_start_time + seconds_per_measure + measure_delay;
                     }
 
+                    previous_measure_start_time = measure_start_time;
                     measure_start_time = time;
+                    measure_delay = 0.0;
 
                     if barline_on {
-                        barlines.push(Barline { time, scroll_speed });
+                        barlines.push((barline(time, scroll_speed), current_branch));
                     }
 
                     // Recalculate our measure-based variables
                     notes_in_measure = notes_in_next_measure(&mut items_iter);
 
-                    seconds_per_note = if notes_in_measure == 0 {
-                        0.0
-                    } else {
-                        seconds_per_measure / notes_in_measure as f32
-                    };
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
                 }
             }
 
@@ -644,28 +1112,33 @@ fn construct_difficulty(
     let mut track_notes = Vec::with_capacity(notes.len());
     let mut notes = notes.into_iter().peekable();
 
-    while let Some((note_type, time, scroll_speed)) = notes.next() {
+    while let Some((note_type, time, [scroll_speed, vertical_speed], delay, branch)) = notes.next()
+    {
         use TJANoteType::*;
 
         // If the next note is a drum roll, look ahead to find where it ends
         let roll_time = if matches!(note_type, Roll | BigRoll | BalloonRoll(_) | SpecialRoll(_)) {
             // Annoyingly, special rolls can be ended by other special rolls.
             // So we have to do some extra logic.
-            let next = notes.peek().ok_or(TJAParseError {
-                kind: TJAParseErrorKind::RollNotEnded,
-                line: course_line_number,
-            })?;
+            // A roll has to end in the same branch it started in
+            let next = notes
+                .peek()
+                .filter(|next| next.4 == branch)
+                .ok_or(TJAParseError {
+                    kind: TJAParseErrorKind::RollNotEnded,
+                    line: course_line_number,
+                })?;
 
-            let next_time = if matches!(note_type, SpecialRoll(_)) {
+            let (next_time, next_delay) = if matches!(note_type, SpecialRoll(_)) {
                 let next_type = next.0;
 
                 if matches!(next_type, SpecialRoll(_)) {
-                    next.1
+                    (next.1, next.3)
                 } else if next_type == RollEnd {
-                    let time = next.1;
+                    let end = (next.1, next.3);
                     let _ = next; // So that notes isn't borrowed and we can call the next line
                     notes.next();
-                    time
+                    end
                 } else {
                     return Err(TJAParseError {
                         kind: TJAParseErrorKind::RollNotEnded,
@@ -673,10 +1146,7 @@ fn construct_difficulty(
                     });
                 }
             } else {
-                let (next_type, next_time, _) = notes.next().ok_or(TJAParseError {
-                    kind: TJAParseErrorKind::RollNotEnded,
-                    line: course_line_number,
-                })?;
+                let (next_type, next_time, _, next_delay, _) = notes.next().unwrap();
 
                 if next_type != RollEnd {
                     return Err(TJAParseError {
@@ -685,10 +1155,16 @@ fn construct_difficulty(
                     });
                 }
 
-                next_time
+                (next_time, next_delay)
             };
 
-            Some(next_time - time)
+            if options.delay_extends_rolls {
+                Some((next_time - time) as f32)
+            } else {
+                // A delay in the middle of a roll shifts the end of the roll along with
+                // everything after it, but the roll itself keeps its musical length.
+                Some((next_time - time - (next_delay - delay)) as f32)
+            }
         } else {
             None
         };
@@ -712,20 +1188,132 @@ fn construct_difficulty(
             }
         };
 
-        track_notes.push(Note {
-            note_type,
-            time,
-            scroll_speed,
-        });
+        track_notes.push((
+            Note {
+                note_type,
+                time,
+                scroll_speed,
+                vertical_speed,
+            },
+            branch,
+        ));
+    }
+
+    // Sort everything in a branch into its section
+    fn branch_notes(
+        sections: &mut [BranchSection],
+        branch: Option<(usize, Branch)>,
+    ) -> Option<&mut BranchNotes> {
+        branch.map(|(section, branch)| &mut sections[section].branches[branch as usize])
+    }
+
+    for (note, branch) in track_notes {
+        match branch_notes(&mut branch_sections, branch) {
+            Some(BranchNotes { notes, .. }) => notes.push(note),
+            None => chart.notes.push(note),
+        }
+    }
+
+    for (barline, branch) in barlines {
+        match branch_notes(&mut branch_sections, branch) {
+            Some(BranchNotes { barlines, .. }) => barlines.push(barline),
+            None => chart.barlines.push(barline),
+        }
     }
 
-    chart.notes = track_notes;
     chart.notes.shrink_to_fit();
+    chart.branch_sections = branch_sections;
 
     let star_level = get_parsed_metadata::<u8>(metadata, "LEVEL", None, Some(course_line_number))?;
-    chart.barlines = barlines;
 
-    Ok(Difficulty { star_level, chart })
+    // Go-go time that's never ended lasts until the end of the chart
+    if let Some(start) = gogo_start {
+        gogo_regions.push(GogoRegion {
+            start: start as f32,
+            end: time as f32,
+        });
+    }
+    chart.gogo_regions = gogo_regions;
+
+    // Changes in later branches can come before the end of earlier ones
+    bpm_changes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    chart.bpm_changes = bpm_changes;
+    delays.sort_by(|a, b| a.time.total_cmp(&b.time));
+    chart.delays = delays;
+
+    // Everything above should make this impossible, but a NaN getting into the game would cause
+    // problems that are very hard to track back to the chart, so it's checked again here.
+    let timing_is_finite = chart_timing_is_finite(&chart);
+    debug_assert!(timing_is_finite, "chart has non-finite times: {chart:?}");
+
+    if !timing_is_finite {
+        return Err(TJAParseError {
+            kind: TJAParseErrorKind::InvalidTiming,
+            line: course_line_number,
+        });
+    }
+
+    // Charts with branches are rated by the master branch, the hardest way through them
+    let estimated_level = if chart.branch_sections.is_empty() {
+        estimate_difficulty(&chart)
+    } else {
+        estimate_difficulty(&chart.on_branch(Branch::Master))
+    };
+
+    Ok(Difficulty {
+        star_level,
+        estimated_level,
+        notes_designer: None,
+        chart,
+        player_charts: None,
+    })
+}
+
+/// A set of difficulties, by their index in [Song::difficulties].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CourseMask(u8);
+
+impl CourseMask {
+    pub const ALL: CourseMask = CourseMask(0b11111);
+
+    /// Just the one difficulty.
+    pub fn only(difficulty: usize) -> Self {
+        Self(1 << difficulty)
+    }
+
+    pub fn contains(&self, difficulty: usize) -> bool {
+        self.0 & (1 << difficulty) != 0
+    }
+}
+
+impl Default for CourseMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Options that change how a TJA file is interpreted.
+///
+/// Different simulators disagree on some of the finer details of the format, so these allow
+/// choosing the behaviour a particular chart was written for. The default options match
+/// TJAPlayer.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// If true, a `#DELAY` between the start and end of a drumroll or balloon makes the roll last
+    /// longer by the length of the delay. By default, the roll keeps its length and the whole roll
+    /// is shifted along with the rest of the notes.
+    pub delay_extends_rolls: bool,
+    /// If true, some mistakes in the chart are worked around instead of stopping it from loading,
+    /// and each one is recorded in [Song::warnings]. So far this only covers `#MEASURE` commands
+    /// with a zero in them, which are ignored so the previous time signature is kept.
+    ///
+    /// Courses with no notes to hit are left out whether or not this is set (see
+    /// [TJAParseWarningKind::EmptyCourse]).
+    pub lenient: bool,
+    /// Which courses to build charts for. The rest are skipped over without being parsed, so
+    /// they're left as `None` in the song, and any errors in them aren't found. By default, every
+    /// course is built.
+    pub courses: CourseMask,
 }
 
 /// Parses a TJA file into a [Song] struct.
@@ -734,6 +1322,16 @@ fn construct_difficulty(
 /// but it does require that the TJA file is. See [TJAParseErrorKind] to see the errors that
 /// can be encountered while parsing.
 pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
+    parse_tja_file_with_options(input, &ParseOptions::default())
+}
+
+/// Parses a TJA file into a [Song] struct, using the given [ParseOptions].
+///
+/// See [parse_tja_file].
+pub fn parse_tja_file_with_options(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<Song, TJAParseError> {
     // Preprocess lines (get rid of comments, empty lines, extra space etc)
     let mut lines = input.lines().enumerate().filter_map(|(i, line)| {
         // This seems to be necessary as a lot of tja files have the utf-16 alignment character at
@@ -757,6 +1355,12 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
 
     let mut metadata = HashMap::new();
     let mut difficulties: [Option<Difficulty>; 5] = [None, None, None, None, None];
+    // The courses for each player of a difficulty (`#START P1` and `#START P2`)
+    let mut player_courses: [[Option<Difficulty>; 2]; 5] = Default::default();
+    // Which courses have been seen so far, including ones that were skipped or left out. Each
+    // difficulty can have a course for a single player and one for each of the two players.
+    let mut seen_courses = [[false; 3]; 5];
+    let mut warnings = Vec::new();
 
     while let Some((i, line)) = lines.next() {
         if let Ok((key, value)) = parse(metadata_pair)(line) {
@@ -764,10 +1368,11 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
         } else {
             match parse(start_command)(line) {
                 Ok(player) => {
-                    // TODO: actually deal with the player argument lol
-                    if player.is_some() {
-                        unimplemented!()
-                    }
+                    let side = match player {
+                        None => 0,
+                        Some(Player::Player1) => 1,
+                        Some(Player::Player2) => 2,
+                    };
 
                     let difficulty_level = match metadata.get("COURSE") {
                         Some(&(line, course)) => match course {
@@ -789,16 +1394,45 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
                     };
 
                     // If there is already a course for this difficulty, thats an error
-                    if difficulties[difficulty_level].is_some() {
+                    if seen_courses[difficulty_level][side] {
                         return Err(TJAParseError {
                             kind: TJAParseErrorKind::MultipleTracksSameDifficulty(difficulty_level),
                             line: i + 1,
                         });
                     }
 
-                    let items = process_course(&mut lines)?;
-                    let difficulty = construct_difficulty(items, &metadata, i + 1)?;
-                    difficulties[difficulty_level] = Some(difficulty);
+                    seen_courses[difficulty_level][side] = true;
+
+                    if !options.courses.contains(difficulty_level) {
+                        skip_course(&mut lines)?;
+                        continue;
+                    }
+
+                    let items = process_course(&mut lines, options, &mut warnings)?;
+                    let mut difficulty = construct_difficulty(items, &metadata, i + 1, options)?;
+
+                    if difficulty.chart.all_notes().is_empty() {
+                        warnings.push(TJAParseWarning {
+                            kind: TJAParseWarningKind::EmptyCourse(difficulty_level),
+                            line: i,
+                        });
+                        continue;
+                    }
+
+                    // The charter can be given for each difficulty with NOTESDESIGNER0 to
+                    // NOTESDESIGNER4, or with a plain NOTESDESIGNER inside the course.
+                    difficulty.notes_designer = metadata
+                        .get(format!("NOTESDESIGNER{difficulty_level}").as_str())
+                        .or_else(|| metadata.get("NOTESDESIGNER"))
+                        .map(|&(_, designer)| designer.to_string())
+                        .filter(|designer| !designer.is_empty());
+
+                    match player {
+                        None => difficulties[difficulty_level] = Some(difficulty),
+                        Some(_) => {
+                            player_courses[difficulty_level][side - 1] = Some(difficulty);
+                        }
+                    }
                 }
 
                 // The reason we return the error that the start_command function returned, is that
@@ -809,22 +1443,79 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
         }
     }
 
+    for (difficulty, [p1, p2]) in difficulties.iter_mut().zip(player_courses) {
+        *difficulty = match (difficulty.take(), p1, p2) {
+            (single, Some(p1), Some(p2)) => {
+                // Playing alone is player 1's chart, unless there's one just for that
+                let player_charts = [p1.chart.clone(), p2.chart];
+                Some(Difficulty {
+                    player_charts: Some(player_charts),
+                    ..single.unwrap_or(p1)
+                })
+            }
+            (single, p1, p2) => single.or(p1).or(p2),
+        };
+    }
+
     // Now get the rest of the metadata needed for the song.
     let title = get_metadata_owned(&metadata, "TITLE", None, None)?;
     let subtitle = get_metadata_owned(&metadata, "SUBTITLE", None, None).ok();
+    let localised_titles = TITLE_LANGUAGES
+        .iter()
+        .filter_map(|(code, _)| {
+            let translation = |key: &str| {
+                metadata
+                    .get(format!("{key}{code}").as_str())
+                    .map(|&(_, text)| text.to_string())
+                    .filter(|text| !text.is_empty())
+            };
+            let localised = LocalisedTitle {
+                title: translation("TITLE"),
+                subtitle: translation("SUBTITLE"),
+            };
+
+            (localised != LocalisedTitle::default()).then(|| (code.to_lowercase(), localised))
+        })
+        .collect();
     let audio_filename = get_metadata_owned(&metadata, "WAVE", None, None)?;
     let demostart = get_parsed_metadata::<f32>(&metadata, "DEMOSTART", Some(0.0), None)?;
     let offset = get_parsed_metadata::<f32>(&metadata, "OFFSET", Some(0.0), None)?;
     let bpm = get_parsed_metadata::<f32>(&metadata, "BPM", Some(120.0), None)?;
+    let song_volume = get_parsed_metadata::<f32>(&metadata, "SONGVOL", Some(100.0), None)?;
+    let effects_volume = get_parsed_metadata::<f32>(&metadata, "SEVOL", Some(100.0), None)?;
+    let genre = get_metadata_owned(&metadata, "GENRE", None, None)
+        .ok()
+        .filter(|genre| !genre.is_empty());
+
+    let side = match metadata.get("SIDE") {
+        Some(&(_, "Normal" | "1")) => SongSide::Normal,
+        Some(&(_, "Ex" | "2")) => SongSide::Extra,
+        Some(&(_, "Both" | "3")) | None => SongSide::Both,
+        Some(&(line, _)) => {
+            return Err(TJAParseError {
+                kind: TJAParseErrorKind::InvalidMetadata,
+                line,
+            })
+        }
+    };
 
     Ok(Song {
         title,
         subtitle,
+        localised_titles,
         audio_filename,
         demostart,
+        song_volume,
+        effects_volume,
+        side,
+        genre,
         bpm,
         offset,
         difficulties,
+        chart_path: None,
+        warnings,
+        metadata_only: false,
+        chart_hashes: Vec::new(),
     })
 }
 
@@ -865,6 +1556,27 @@ mod test {
             })
         );
     }
+    #[test]
+    fn test_error_excerpt() {
+        let source = "TITLE:Excerpt\nWAVE:song.ogg\n#START\n1000,\n#MEASURE 0/4\n1000,\n#END\n";
+        let error = TJAParseError {
+            kind: TJAParseErrorKind::InvalidTimeSignature(0, 4),
+            line: 4,
+        };
+
+        assert_eq!(
+            error.excerpt(source),
+            "     3 | #START\n     4 | 1000,\n>    5 | #MEASURE 0/4\n     6 | 1000,\n     7 | #END"
+        );
+
+        // Errors near the start of the file show as much as there is
+        let error = TJAParseError { line: 0, ..error };
+        assert_eq!(
+            error.excerpt(source),
+            ">    1 | TITLE:Excerpt\n     2 | WAVE:song.ogg\n     3 | #START"
+        );
+    }
+
     #[test]
     fn test_course_item() {
         assert_eq!(
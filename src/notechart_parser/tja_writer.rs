@@ -0,0 +1,786 @@
+//! Writing songs back out as TJA files.
+//!
+//! Notes are written into the measures marked out by the chart's barlines, with `#BPMCHANGE`,
+//! `#SCROLL`, `#MEASURE` and `#GOGOSTART`/`#GOGOEND` commands wherever the chart needs them. The
+//! commands are worked out from the times and speeds of the notes, so they won't always be the same
+//! ones the chart was read from, but they make the same chart. A chart whose track moves by beat
+//! starts with `#BMSCROLL` or `#HBSCROLL`. Delays, branches and separate charts for each player
+//! can't be written yet, and are an error rather than being written out wrong.
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{bail, format_err};
+
+use super::{Difficulty, NoteChart, NoteType, ScrollMode, Song, SongSide};
+use crate::difficulty::DifficultyInfo;
+
+/// The finest division of a measure a note can be written at.
+const MAX_DIVISION: u32 = 192;
+/// How far (in beats) something may be from where it's written and still count as being there.
+/// Note times are f32s, so they're rarely exact.
+const TOLERANCE: f64 = 0.001;
+/// The denominators time signatures are written with, in order of preference.
+const SIGNATURE_DENOMINATORS: [u32; 5] = [4, 8, 16, 32, 64];
+
+/// The names of the difficulties in the COURSE metadata, by index.
+const COURSE_NAMES: [&str; 5] = ["Easy", "Normal", "Hard", "Oni", "Edit"];
+
+/// Writes a song as the contents of a TJA file.
+///
+/// The audio file is written as just its file name, as the TJA file is expected to be saved in the
+/// same folder as the audio.
+pub fn write_tja(song: &Song) -> anyhow::Result<String> {
+    let mut output = String::new();
+    let audio_filename = Path::new(&song.audio_filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    writeln!(output, "TITLE:{}", song.title)?;
+    if let Some(subtitle) = &song.subtitle {
+        writeln!(output, "SUBTITLE:{subtitle}")?;
+    }
+    for (code, localised) in song.localised_titles.iter() {
+        let code = code.to_uppercase();
+        if let Some(title) = &localised.title {
+            writeln!(output, "TITLE{code}:{title}")?;
+        }
+        if let Some(subtitle) = &localised.subtitle {
+            writeln!(output, "SUBTITLE{code}:{subtitle}")?;
+        }
+    }
+    writeln!(output, "BPM:{}", song.bpm)?;
+    writeln!(output, "WAVE:{audio_filename}")?;
+    writeln!(output, "OFFSET:{}", song.offset)?;
+    writeln!(output, "DEMOSTART:{}", song.demostart)?;
+    if let Some(genre) = &song.genre {
+        writeln!(output, "GENRE:{genre}")?;
+    }
+    // The rest are only written when they aren't the default, to keep the file tidy
+    if song.song_volume != 100.0 {
+        writeln!(output, "SONGVOL:{}", song.song_volume)?;
+    }
+    if song.effects_volume != 100.0 {
+        writeln!(output, "SEVOL:{}", song.effects_volume)?;
+    }
+    match song.side {
+        SongSide::Normal => writeln!(output, "SIDE:Normal")?,
+        SongSide::Extra => writeln!(output, "SIDE:Ex")?,
+        SongSide::Both => {}
+    }
+
+    for (index, difficulty) in song.difficulties.iter().enumerate() {
+        if let Some(difficulty) = difficulty {
+            write_course(&mut output, song, index, difficulty).map_err(|e| {
+                format_err!(
+                    "couldn't write the {} chart: {e}",
+                    DifficultyInfo::name_of(index)
+                )
+            })?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Turns times in a chart into beats from the start of the chart, going by its BPM changes.
+struct Tempo {
+    /// The time (in seconds from the start of the chart), beat and BPM that each tempo starts at
+    segments: Vec<(f64, f64, f64)>,
+}
+
+impl Tempo {
+    fn new(song: &Song, chart: &NoteChart) -> anyhow::Result<Self> {
+        let mut segments = vec![(0.0, 0.0, song.bpm as f64)];
+
+        for change in chart.bpm_changes.iter() {
+            let time = (change.time + song.offset) as f64;
+            let bpm = change.bpm as f64;
+            let (last_time, last_beat, last_bpm) = *segments.last().unwrap();
+
+            if time < -TOLERANCE {
+                bail!("there are BPM changes before the start of the chart");
+            } else if time - last_time < 1e-6 {
+                segments.last_mut().unwrap().2 = bpm;
+            } else {
+                let beat = last_beat + (time - last_time) * last_bpm / 60.0;
+                segments.push((time, beat, bpm));
+            }
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// How many beats into the chart the given time (in seconds from the start of the chart) is.
+    fn beat_at(&self, time: f64) -> f64 {
+        let index = self
+            .segments
+            .partition_point(|&(start, _, _)| start <= time)
+            .saturating_sub(1);
+        let (start, beat, bpm) = self.segments[index];
+
+        beat + (time - start) * bpm / 60.0
+    }
+}
+
+/// Something written in a measure.
+enum Item {
+    /// A note, or the end of a roll
+    Note(char),
+    Command(String),
+}
+
+/// Something written at a point in the chart.
+struct Event {
+    beat: f64,
+    /// Whether this goes at the very end of the measure that ends at `beat`, rather than the start
+    /// of the one after it. This is where commands that affect the barline between them go.
+    end_of_measure: bool,
+    item: Item,
+}
+
+impl Event {
+    fn new(beat: f64, item: Item) -> Self {
+        Self {
+            beat,
+            end_of_measure: false,
+            item,
+        }
+    }
+}
+
+fn write_course(
+    output: &mut String,
+    song: &Song,
+    index: usize,
+    difficulty: &Difficulty,
+) -> anyhow::Result<()> {
+    let chart = &difficulty.chart;
+    let tempo = Tempo::new(song, chart)?;
+    let beat_at = |time: f64| tempo.beat_at(time + song.offset as f64);
+
+    if !chart.branch_sections.is_empty() {
+        bail!("branches can't be written yet");
+    }
+
+    if difficulty.player_charts.is_some() {
+        bail!("separate charts for each player can't be written yet");
+    }
+
+    if chart.delays.iter().any(|delay| delay.length != 0.0) {
+        bail!("delays can't be written yet");
+    }
+
+    let mut events = Vec::new();
+    let mut balloons = Vec::new();
+
+    for note in chart.notes.iter() {
+        let (symbol, end) = match note.note_type {
+            NoteType::Don => ('1', None),
+            NoteType::Kat => ('2', None),
+            NoteType::BigDon => ('3', None),
+            NoteType::BigKat => ('4', None),
+            NoteType::Roll(length) => ('5', Some(length)),
+            NoteType::BigRoll(length) => ('6', Some(length)),
+            NoteType::BalloonRoll(length, hits) => {
+                balloons.push(hits);
+                ('7', Some(length))
+            }
+            NoteType::SpecialRoll(length, hits) => {
+                balloons.push(hits);
+                ('9', Some(length))
+            }
+            NoteType::CoopDon => ('A', None),
+            NoteType::CoopKat => ('B', None),
+        };
+
+        events.push(Event::new(beat_at(note.time), Item::Note(symbol)));
+
+        if let Some(length) = end {
+            events.push(Event::new(
+                beat_at(note.time + length as f64),
+                Item::Note('8'),
+            ));
+        }
+    }
+
+    if events.iter().any(|event| event.beat < -TOLERANCE) {
+        bail!("there are notes before the start of the chart");
+    }
+
+    // Each barline starts a measure, and the last one ends the chart. Hidden barlines just make
+    // the measures around them longer, which looks the same.
+    let mut measures: Vec<f64> = chart
+        .barlines
+        .iter()
+        .map(|b| beat_at(b.time as f64))
+        .collect();
+    if !measures.first().is_some_and(|&start| start <= TOLERANCE) {
+        measures.insert(0, 0.0);
+    }
+    if measures[0] < -TOLERANCE {
+        bail!("there are barlines before the start of the chart");
+    }
+    if measures
+        .windows(2)
+        .any(|pair| pair[1] - pair[0] < TOLERANCE)
+    {
+        bail!("there are measures with no length");
+    }
+
+    // Notes after the last barline go in more measures the same length as the last one
+    let last_note = events
+        .iter()
+        .map(|event| event.beat)
+        .fold(f64::MIN, f64::max);
+    let extra_length = match measures[..] {
+        [.., start, end] => end - start,
+        _ => 4.0,
+    };
+    while last_note >= measures.last().unwrap() - TOLERANCE {
+        measures.push(measures.last().unwrap() + extra_length);
+    }
+
+    let mut current_bpm = song.bpm;
+    for change in chart.bpm_changes.iter() {
+        if change.bpm != current_bpm {
+            let command = format!("#BPMCHANGE {}", change.bpm);
+            events.push(Event::new(
+                beat_at(change.time as f64),
+                Item::Command(command),
+            ));
+            current_bpm = change.bpm;
+        }
+    }
+
+    let head_scroll = write_scroll_changes(song, chart, beat_at, &mut events);
+
+    for region in chart.gogo_regions.iter() {
+        let command = |name: &str| Item::Command(name.to_string());
+        events.push(Event::new(
+            beat_at(region.start as f64),
+            command("#GOGOSTART"),
+        ));
+        events.push(Event::new(beat_at(region.end as f64), command("#GOGOEND")));
+    }
+
+    writeln!(output)?;
+    writeln!(output, "COURSE:{}", COURSE_NAMES[index])?;
+    writeln!(output, "LEVEL:{}", difficulty.star_level)?;
+    if let Some(designer) = &difficulty.notes_designer {
+        writeln!(output, "NOTESDESIGNER{index}:{designer}")?;
+    }
+    if head_scroll != 1.0 {
+        writeln!(output, "HEADSCROLL:{}", rounded(head_scroll))?;
+    }
+    if !balloons.is_empty() {
+        let list = balloons
+            .iter()
+            .map(|hits| hits.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(output, "BALLOON:{list}")?;
+    }
+    writeln!(output)?;
+    writeln!(output, "#START")?;
+    match chart.scroll_mode {
+        ScrollMode::Normal => {}
+        ScrollMode::Bms => writeln!(output, "#BMSCROLL")?,
+        ScrollMode::Hbs => writeln!(output, "#HBSCROLL")?,
+    }
+
+    let mut current_length = 4.0;
+
+    for pair in measures.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let length = end - start;
+
+        if (length - current_length).abs() > TOLERANCE {
+            writeln!(output, "#MEASURE {}", time_signature(length)?)?;
+            current_length = length;
+        }
+
+        let mut items = Vec::new();
+        let mut end_commands = Vec::new();
+
+        for event in events.iter() {
+            if event.end_of_measure {
+                if (event.beat - end).abs() <= TOLERANCE {
+                    end_commands.push(&event.item);
+                }
+            } else if event.beat >= start - TOLERANCE && event.beat < end - TOLERANCE {
+                items.push(((event.beat - start) / length, &event.item));
+            }
+        }
+
+        write_measure(output, &mut items, &end_commands, length)?;
+    }
+
+    // Anything at the very end of the chart, like the end of go-go time
+    let chart_end = *measures.last().unwrap();
+    for event in events.iter() {
+        if let Item::Command(command) = &event.item {
+            if !event.end_of_measure && event.beat >= chart_end - TOLERANCE {
+                writeln!(output, "{command}")?;
+            }
+        }
+    }
+
+    writeln!(output, "#END")?;
+    Ok(())
+}
+
+/// Adds a `#SCROLL` command wherever the scroll speed of the notes or barlines changes, and returns
+/// the scroll speed the chart starts at (`HEADSCROLL`), which the commands are relative to.
+///
+/// Notes and barlines keep their speed with the BPM already taken into account (unless the track
+/// moves by beat), so it's taken back out again here. A barline takes the speed from the end of
+/// the measure before it, before any commands at the start of the next one. Speeds that move notes
+/// vertically are written as complex numbers.
+fn write_scroll_changes(
+    song: &Song,
+    chart: &NoteChart,
+    beat_at: impl Fn(f64) -> f64,
+    events: &mut Vec<Event>,
+) -> f64 {
+    let bpm_at = |time: f32| chart.bpm_at(time).unwrap_or(song.bpm);
+    let bpm_before = |time: f32| {
+        let index = chart
+            .bpm_changes
+            .partition_point(|change| change.time < time);
+        index
+            .checked_sub(1)
+            .map_or(song.bpm, |index| chart.bpm_changes[index].bpm)
+    };
+    let unscaled = |speed: f32, bpm: f32| match chart.scroll_mode {
+        ScrollMode::Normal => speed as f64 * 120.0 / bpm as f64,
+        ScrollMode::Bms | ScrollMode::Hbs => speed as f64,
+    };
+
+    // The speed of the first barline can only be set with HEADSCROLL
+    let mut barlines = chart.barlines.iter().peekable();
+    let head_scroll = barlines
+        .next_if(|barline| beat_at(barline.time as f64).abs() <= TOLERANCE)
+        .map(|barline| unscaled(barline.scroll_speed, song.bpm))
+        .filter(|&speed| speed.abs() > 1e-6)
+        .unwrap_or(1.0);
+
+    // The speed of everything in the order the parser reads it in, and whether it's a barline
+    let mut speeds: Vec<(f64, bool, [f64; 2])> = barlines
+        .map(|barline| {
+            let bpm = bpm_before(barline.time);
+            let speed = [barline.scroll_speed, barline.vertical_speed];
+            (
+                beat_at(barline.time as f64),
+                true,
+                speed.map(|s| unscaled(s, bpm)),
+            )
+        })
+        .chain(chart.notes.iter().map(|note| {
+            let bpm = bpm_at(note.time as f32);
+            let speed = [note.scroll_speed, note.vertical_speed];
+            (beat_at(note.time), false, speed.map(|s| unscaled(s, bpm)))
+        }))
+        .collect();
+    speeds.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let changed =
+        |speed: f64, current: f64| (speed - current).abs() > 1e-4 * current.abs().max(1.0);
+    let mut current = [1.0, 0.0];
+    for (beat, end_of_measure, speed) in speeds {
+        let speed = speed.map(|s| s / head_scroll);
+
+        if changed(speed[0], current[0]) || changed(speed[1], current[1]) {
+            events.push(Event {
+                beat,
+                end_of_measure,
+                item: Item::Command(format!("#SCROLL {}", scroll_value(speed))),
+            });
+            current = speed;
+        }
+    }
+
+    head_scroll
+}
+
+/// Writes a number with at most 4 decimal places, so that speeds that have been multiplied and
+/// divided don't come out as things like 1.9999999.
+fn rounded(value: f64) -> f32 {
+    ((value * 10000.0).round() / 10000.0) as f32
+}
+
+/// Writes a scroll speed, as a complex number (e.g. `1+2i`) if it moves notes vertically.
+fn scroll_value([real, imaginary]: [f64; 2]) -> String {
+    let (real, imaginary) = (rounded(real), rounded(imaginary));
+
+    if imaginary == 0.0 {
+        real.to_string()
+    } else if real == 0.0 {
+        format!("{imaginary}i")
+    } else {
+        format!("{real}{imaginary:+}i")
+    }
+}
+
+/// The time signature of a measure that's `beats` beats long, e.g. 3.5 beats is `7/8`.
+fn time_signature(beats: f64) -> anyhow::Result<String> {
+    SIGNATURE_DENOMINATORS
+        .into_iter()
+        .chain(1..=64)
+        .find_map(|denominator| {
+            let numerator = beats * denominator as f64 / 4.0;
+            let whole = numerator.round();
+            let fits = (numerator - whole).abs() * 4.0 / denominator as f64 <= TOLERANCE;
+
+            (fits && (1.0..=255.0).contains(&whole))
+                .then(|| format!("{}/{denominator}", whole as u32))
+        })
+        .ok_or_else(|| {
+            format_err!(
+                "there's a measure {beats} beats long, which can't be written as a time signature \
+                 (delays can't be written yet)"
+            )
+        })
+}
+
+/// Writes out a single measure, given where each note and command falls in it (from 0 to 1), and
+/// the commands at the very end of it. The measure is split into the fewest equal parts that puts
+/// everything on one of them, and commands are written on lines of their own in between the notes.
+fn write_measure(
+    output: &mut String,
+    items: &mut [(f64, &Item)],
+    end_commands: &[&Item],
+    length: f64,
+) -> anyhow::Result<()> {
+    // Commands at the same point as a note come before it
+    items.sort_by(|a, b| {
+        a.0.total_cmp(&b.0)
+            .then(matches!(a.1, Item::Note(_)).cmp(&matches!(b.1, Item::Note(_))))
+    });
+
+    let on_grid = |division: u32| {
+        items.iter().all(|(position, _)| {
+            let steps = position * division as f64;
+            (steps - steps.round()).abs() * length / division as f64 <= TOLERANCE
+        })
+    };
+
+    let division = (1..=MAX_DIVISION)
+        .find(|&division| on_grid(division))
+        .ok_or_else(|| {
+            format_err!(
+                "there are notes that don't fit on a 1/{MAX_DIVISION} grid (delays can't be \
+                 written yet)"
+            )
+        })? as usize;
+
+    let mut symbols = vec!['0'; division];
+    let mut commands = vec![Vec::new(); division];
+
+    for (position, item) in items.iter() {
+        let step = ((position * division as f64).round() as usize).min(division - 1);
+
+        match item {
+            Item::Note(symbol) => symbols[step] = *symbol,
+            Item::Command(command) => commands[step].push(command.as_str()),
+        }
+    }
+
+    let mut line = String::new();
+    for (symbol, commands) in symbols.into_iter().zip(commands) {
+        if !commands.is_empty() && !line.is_empty() {
+            writeln!(output, "{line}")?;
+            line.clear();
+        }
+
+        for command in commands {
+            writeln!(output, "{command}")?;
+        }
+
+        line.push(symbol);
+    }
+
+    if !end_commands.is_empty() {
+        writeln!(output, "{line}")?;
+        line.clear();
+
+        for command in end_commands {
+            if let Item::Command(command) = command {
+                writeln!(output, "{command}")?;
+            }
+        }
+    }
+
+    writeln!(output, "{line},")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::parse_tja_file;
+
+    const SIMPLE_SONG: &str = "TITLE:Writer test
+SUBTITLE:--Someone
+TITLEJA:ライターテスト
+SUBTITLEEN:--Someone else
+BPM:150
+WAVE:song.ogg
+OFFSET:-1.5
+DEMOSTART:20
+GENRE:Variety
+SONGVOL:80
+SIDE:Ex
+COURSE:Hard
+LEVEL:6
+BALLOON:12
+
+#START
+1020304,
+500000000008,
+0,
+1,
+#END
+
+COURSE:Edit
+LEVEL:10
+NOTESDESIGNER4:Me
+
+#START
+11221122,
+70008000,
+#END
+";
+
+    #[test]
+    fn test_written_song_parses_the_same() {
+        let song = parse_tja_file(SIMPLE_SONG).unwrap();
+        let written = write_tja(&song).unwrap();
+        let reparsed = parse_tja_file(&written).unwrap();
+
+        assert_eq!(reparsed.title, song.title);
+        assert_eq!(reparsed.subtitle, song.subtitle);
+        assert_eq!(reparsed.localised_titles, song.localised_titles);
+        assert_eq!(reparsed.bpm, song.bpm);
+        assert_eq!(reparsed.offset, song.offset);
+        assert_eq!(reparsed.demostart, song.demostart);
+        assert_eq!(reparsed.genre, song.genre);
+        assert_eq!(reparsed.song_volume, song.song_volume);
+        assert_eq!(reparsed.effects_volume, song.effects_volume);
+        assert_eq!(reparsed.side, song.side);
+
+        for (a, b) in song.difficulties.iter().zip(reparsed.difficulties.iter()) {
+            match (a, b) {
+                (None, None) => {}
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.star_level, b.star_level);
+                    assert_eq!(a.notes_designer, b.notes_designer);
+                    assert_eq!(a.chart.barlines.len(), b.chart.barlines.len());
+                    assert_eq!(a.chart.notes.len(), b.chart.notes.len());
+
+                    for (a, b) in a.chart.notes.iter().zip(b.chart.notes.iter()) {
+                        assert!((a.time - b.time).abs() < 1e-4, "{a:?} became {b:?}");
+                        assert_eq!(
+                            std::mem::discriminant(&a.note_type),
+                            std::mem::discriminant(&b.note_type)
+                        );
+                    }
+                }
+                _ => panic!("a difficulty appeared or disappeared"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_measures_use_the_fewest_divisions() {
+        let song = parse_tja_file(SIMPLE_SONG).unwrap();
+        let written = write_tja(&song).unwrap();
+
+        // 1020304 has 7 divisions, which don't simplify
+        assert!(written.contains("\n1020304,\n"));
+        // The roll's end is on the 12th of 12, which can't be simplified either
+        assert!(written.contains("\n500000000008,\n"));
+        assert!(written.contains("\n0,\n1,\n#END"));
+        // 70008000 only needs 2 divisions
+        assert!(written.contains("\n78,\n"));
+        assert!(written.contains("BALLOON:12\n"));
+    }
+
+    #[test]
+    fn test_timing_commands_are_written() {
+        let song = parse_tja_file(
+            "TITLE:Soflan
+BPM:150
+WAVE:song.ogg
+OFFSET:-0.5
+COURSE:Oni
+LEVEL:9
+HEADSCROLL:1.5
+
+#START
+1111
+#SCROLL 4
+,
+#BPMCHANGE 200
+#SCROLL 2
+1010
+#BPMCHANGE 100
+1010,
+#MEASURE 3/4
+#GOGOSTART
+111,
+#MEASURE 7/8
+1111111,
+#SCROLL 0.5
+#GOGOEND
+11
+#SCROLL 1
+11,
+#MEASURE 4/4
+#BARLINEOFF
+1,
+#BARLINEON
+#SCROLL 3
+3,
+#END
+",
+        )
+        .unwrap();
+        let written = write_tja(&song).unwrap();
+        let reparsed = parse_tja_file(&written).unwrap();
+
+        let original = &song.difficulties[3].as_ref().unwrap().chart;
+        let chart = &reparsed.difficulties[3].as_ref().unwrap().chart;
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4 * a.abs().max(1.0);
+
+        assert_eq!(chart.notes.len(), original.notes.len());
+        for (a, b) in original.notes.iter().zip(chart.notes.iter()) {
+            assert!(
+                close(a.time as f32, b.time as f32),
+                "{a:?} became {b:?}\n{written}"
+            );
+            assert!(
+                close(a.scroll_speed, b.scroll_speed),
+                "{a:?} became {b:?}\n{written}"
+            );
+            assert_eq!(original.bpm_at(a.time as f32), chart.bpm_at(b.time as f32));
+        }
+
+        // The hidden barline is left out, which makes one long measure instead
+        assert_eq!(chart.barlines.len(), original.barlines.len());
+        for (a, b) in original.barlines.iter().zip(chart.barlines.iter()) {
+            assert!(close(a.time, b.time), "{a:?} became {b:?}\n{written}");
+            assert!(
+                close(a.scroll_speed, b.scroll_speed),
+                "{a:?} became {b:?}\n{written}"
+            );
+        }
+
+        assert_eq!(chart.gogo_regions.len(), 1);
+        assert!(close(
+            chart.gogo_regions[0].start,
+            original.gogo_regions[0].start
+        ));
+        assert!(close(
+            chart.gogo_regions[0].end,
+            original.gogo_regions[0].end
+        ));
+
+        assert!(written.contains("HEADSCROLL:1.5\n"));
+        assert!(written.contains("\n#MEASURE 7/8\n"));
+        assert!(written.contains("\n#MEASURE 8/4\n"));
+        // Both halves of the measure are two beats long, even though they take different times
+        assert!(written.contains("\n11\n#BPMCHANGE 100\n11,\n"));
+    }
+
+    #[test]
+    fn test_scroll_mode_is_written() {
+        let song = parse_tja_file(
+            "TITLE:Beat scroll
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:9
+
+#START
+#HBSCROLL
+1111,
+#BPMCHANGE 240
+#SCROLL 2
+1111,
+#END
+",
+        )
+        .unwrap();
+        let written = write_tja(&song).unwrap();
+        let reparsed = parse_tja_file(&written).unwrap();
+
+        let original = &song.difficulties[3].as_ref().unwrap().chart;
+        let chart = &reparsed.difficulties[3].as_ref().unwrap().chart;
+
+        assert!(written.contains("#START\n#HBSCROLL\n"));
+        assert_eq!(chart.scroll_mode, ScrollMode::Hbs);
+        // The speeds don't have the tempo in them, so it isn't taken back out
+        for (a, b) in original.notes.iter().zip(chart.notes.iter()) {
+            assert!((a.scroll_speed - b.scroll_speed).abs() < 1e-4, "{written}");
+        }
+    }
+
+    #[test]
+    fn test_complex_scroll_is_written() {
+        let song = parse_tja_file(
+            "TITLE:Complex scroll
+BPM:120
+WAVE:song.ogg
+COURSE:Oni
+LEVEL:9
+
+#START
+1,
+#SCROLL 0.5+2i
+1,
+#BPMCHANGE 240
+#SCROLL -1.5i
+1,
+#SCROLL 1
+1,
+#END
+",
+        )
+        .unwrap();
+        let written = write_tja(&song).unwrap();
+        let reparsed = parse_tja_file(&written).unwrap();
+
+        let original = &song.difficulties[3].as_ref().unwrap().chart;
+        let chart = &reparsed.difficulties[3].as_ref().unwrap().chart;
+
+        assert!(written.contains("#SCROLL 0.5+2i\n"), "{written}");
+        assert!(written.contains("#SCROLL -1.5i\n"), "{written}");
+        for (a, b) in original.notes.iter().zip(chart.notes.iter()) {
+            assert!((a.scroll_speed - b.scroll_speed).abs() < 1e-4, "{written}");
+            assert!(
+                (a.vertical_speed - b.vertical_speed).abs() < 1e-4,
+                "{written}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_delays_are_refused() {
+        let song = parse_tja_file(
+            "TITLE:Delay
+BPM:120
+WAVE:song.ogg
+LEVEL:1
+#START
+1,
+#DELAY 0.0123
+1,
+#END
+",
+        )
+        .unwrap();
+
+        assert!(write_tja(&song).is_err());
+    }
+}
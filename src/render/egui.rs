@@ -48,8 +48,12 @@ impl Egui {
     }
 
     pub fn begin_render(&mut self) {
-        self.platform
-            .update_time(self.start_time.elapsed().as_secs_f64());
+        self.begin_render_at(self.start_time.elapsed().as_secs_f64());
+    }
+
+    /// Starts a frame at the given time in seconds, as if that long had passed since egui started.
+    pub fn begin_render_at(&mut self, time: f64) {
+        self.platform.update_time(time);
         self.platform.begin_frame();
     }
 
@@ -63,9 +67,9 @@ impl Egui {
         queue: &wgpu::Queue,
         encoder: &mut wgpu::CommandEncoder,
         screen_descriptor: &ScreenDescriptor,
-        window: &Window,
+        window: Option<&Window>,
     ) -> Vec<egui::ClippedPrimitive> {
-        let full_output = self.platform.end_frame(Some(window));
+        let full_output = self.platform.end_frame(window);
         let paint_jobs = self
             .platform
             .context()
@@ -0,0 +1,208 @@
+//! One buffer on the GPU holding the instance of every [Sprite](super::texture::Sprite).
+//!
+//! Sprites move a lot (every note on screen moves every frame), and when each one had a buffer of
+//! its own, that meant hundreds of tiny `queue.write_buffer` calls a frame. Instead, each sprite is
+//! given a slot in the renderer's [SpriteInstances] when it's built. Moving or tinting a sprite
+//! only changes the copy of its slot kept on the CPU, and everything that changed during a frame is
+//! written to the GPU in one go just before the frame is submitted (see [SpriteInstances::flush]).
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::resources::{Allocation, ResourceUsage};
+use super::texture::SpriteInstance;
+
+/// How many instances the buffer has room for to begin with.
+const INITIAL_CAPACITY: usize = 1024;
+
+const INSTANCE_SIZE: usize = std::mem::size_of::<SpriteInstance>();
+
+/// The CPU side of the instance buffer.
+#[derive(Debug, Default)]
+struct Slots {
+    /// A copy of everything in the buffer
+    instances: Vec<SpriteInstance>,
+    /// Slots that were given out and have since been given back
+    free: Vec<usize>,
+    /// The slots that have changed since they were last written to the GPU
+    dirty: Option<Range<usize>>,
+}
+
+impl Slots {
+    fn allocate(&mut self, instance: SpriteInstance) -> usize {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.instances[index] = instance;
+                index
+            }
+            None => {
+                self.instances.push(instance);
+                self.instances.len() - 1
+            }
+        };
+
+        self.mark_dirty(index);
+        index
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(index)..dirty.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+
+    fn update(&mut self, index: usize, update: impl FnOnce(&mut SpriteInstance)) {
+        update(&mut self.instances[index]);
+        self.mark_dirty(index);
+    }
+}
+
+/// A sprite's slot in the instance buffer. The slot is given back when this is dropped.
+#[derive(Debug)]
+pub struct InstanceSlot {
+    index: usize,
+    slots: Rc<RefCell<Slots>>,
+}
+
+impl InstanceSlot {
+    /// Changes the instance in the slot. The change is written to the GPU at the end of the frame.
+    pub fn update(&self, update: impl FnOnce(&mut SpriteInstance)) {
+        self.slots.borrow_mut().update(self.index, update);
+    }
+}
+
+impl Drop for InstanceSlot {
+    fn drop(&mut self) {
+        self.slots.borrow_mut().free.push(self.index);
+    }
+}
+
+/// The instance buffer that every sprite is drawn from. See the [module documentation](self).
+#[derive(Debug)]
+pub struct SpriteInstances {
+    slots: Rc<RefCell<Slots>>,
+    buffer: wgpu::Buffer,
+    /// How many instances the buffer has room for
+    capacity: usize,
+    _allocation: Allocation,
+}
+
+impl SpriteInstances {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let (buffer, allocation) = Self::create_buffer(device, INITIAL_CAPACITY);
+
+        Self {
+            slots: Rc::default(),
+            buffer,
+            capacity: INITIAL_CAPACITY,
+            _allocation: allocation,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> (wgpu::Buffer, Allocation) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sprite instance buffer"),
+            size: (capacity * INSTANCE_SIZE) as _,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let allocation = Allocation::register(ResourceUsage::default().buffer(&buffer));
+
+        (buffer, allocation)
+    }
+
+    /// Gives out a slot holding the given instance.
+    pub fn allocate(&self, instance: SpriteInstance) -> InstanceSlot {
+        InstanceSlot {
+            index: self.slots.borrow_mut().allocate(instance),
+            slots: Rc::clone(&self.slots),
+        }
+    }
+
+    /// Makes the buffer bigger if more slots have been given out than it has room for. This has to
+    /// be done before anything is drawn in a frame, since the draws refer to the buffer.
+    pub fn prepare(&mut self, device: &wgpu::Device) {
+        let mut slots = self.slots.borrow_mut();
+        let len = slots.instances.len();
+
+        if len > self.capacity {
+            self.capacity = len.next_power_of_two();
+            (self.buffer, self._allocation) = Self::create_buffer(device, self.capacity);
+
+            // Everything has to be written to the new buffer
+            slots.dirty = Some(0..len);
+        }
+    }
+
+    /// Writes every slot that has changed to the GPU, in one write. This has to be done after the
+    /// frame is drawn, but before it's submitted.
+    pub fn flush(&self, queue: &wgpu::Queue) {
+        let mut slots = self.slots.borrow_mut();
+
+        let Some(dirty) = slots.dirty.take() else {
+            return;
+        };
+
+        // Slots given out since the buffer was last grown might not fit in it yet, but everything
+        // is written again when it grows at the start of the next frame
+        let written = dirty.start..dirty.end.min(self.capacity);
+
+        if !written.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                (written.start * INSTANCE_SIZE) as _,
+                bytemuck::cast_slice(&slots.instances[written]),
+            );
+        }
+    }
+
+    /// The part of the buffer holding the given slot, or None if the buffer hasn't grown big
+    /// enough for it yet (which only happens to sprites built in the middle of drawing a frame, and
+    /// only for that frame).
+    pub fn slice(&self, slot: &InstanceSlot) -> Option<wgpu::BufferSlice<'_>> {
+        let start = slot.index * INSTANCE_SIZE;
+        let end = start + INSTANCE_SIZE;
+
+        (slot.index < self.capacity).then(|| self.buffer.slice(start as u64..end as u64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_reused() {
+        let mut slots = Slots::default();
+
+        let first = slots.allocate(SpriteInstance::new([1., 0., 0.]));
+        let second = slots.allocate(SpriteInstance::new([2., 0., 0.]));
+        assert_eq!((first, second), (0, 1));
+
+        slots.free.push(first);
+        let third = slots.allocate(SpriteInstance::new([3., 0., 0.]));
+
+        assert_eq!(third, first);
+        assert_eq!(slots.instances.len(), 2);
+        assert_eq!(slots.instances[third].position, [3., 0., 0.]);
+    }
+
+    #[test]
+    fn test_dirty_range() {
+        let mut slots = Slots::default();
+        for i in 0..10 {
+            slots.allocate(SpriteInstance::new([i as f32, 0., 0.]));
+        }
+        slots.dirty = None;
+
+        slots.update(6, |instance| instance.tint = [0.; 4]);
+        assert_eq!(slots.dirty, Some(6..7));
+
+        // Only one range is kept, covering everything that changed
+        slots.update(2, |instance| instance.scale = 2.);
+        slots.update(4, |instance| instance.scale = 2.);
+        assert_eq!(slots.dirty, Some(2..7));
+        assert_eq!(slots.instances[2].scale, 2.);
+    }
+}
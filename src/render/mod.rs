@@ -1,3 +1,7 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use egui_wgpu::ScreenDescriptor;
 use kaku::{ab_glyph::FontVec, FontId, FontSize, SdfSettings, TextRendererBuilder};
@@ -8,6 +12,9 @@ use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::game::Game;
+use crate::settings::PresentMode;
+use crate::skin::skin;
+use instances::SpriteInstances;
 use shapes::ShapeVertex;
 use texture::TextureVertex;
 
@@ -41,11 +48,28 @@ pub(crate) use rgba;
 const SAMPLE_COUNT: u32 = 4;
 const CLEAR_COLOUR: wgpu::Color = wgpu::Color::BLACK;
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// The folder fonts are loaded from, unless the skin replaces them.
+const FONTS_PATH: &str = "assets/fonts";
+
+/// The format frames are drawn in when there's no window to draw to.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// The size of the screen everything is positioned on, whatever the size of the window. The
+/// renderer scales it to fit the window, leaving black bars along the sides or the top and bottom
+/// if the window is a different shape (see [Viewport::letterbox]).
+pub const VIRTUAL_WIDTH: f32 = 1920.;
+pub const VIRTUAL_HEIGHT: f32 = 1080.;
 
 mod egui;
+mod instances;
+pub mod particles;
+pub mod resources;
 pub mod shapes;
 pub mod text;
 pub mod texture;
+#[cfg(all(test, feature = "visual-tests"))]
+pub mod visual_test;
+pub mod waveform;
 
 /// A trait that allows objects to render themselves to the screen in any given render pass. If a
 /// type implements Renderable, then it is able to be rendered by the [RenderPassContext]'s render
@@ -64,30 +88,111 @@ struct ScreenUniform {
     matrix: [[f32; 4]; 4],
 }
 
+/// The part of the window the virtual screen (see [VIRTUAL_WIDTH]) is drawn in, in physical
+/// pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// The biggest area of a window of the given size that has the same shape as the virtual
+    /// screen, in the middle of the window.
+    pub fn letterbox(size: &PhysicalSize<u32>) -> Self {
+        let (window_width, window_height) = (size.width as f32, size.height as f32);
+        let scale = (window_width / VIRTUAL_WIDTH).min(window_height / VIRTUAL_HEIGHT);
+        let (width, height) = (VIRTUAL_WIDTH * scale, VIRTUAL_HEIGHT * scale);
+
+        Self {
+            x: (window_width - width) / 2.,
+            y: (window_height - height) / 2.,
+            width,
+            height,
+        }
+    }
+
+    /// Turns a position in the window (in physical pixels, like the cursor's position) into a
+    /// position on the virtual screen. Positions in the black bars end up off the edge of it.
+    pub fn to_virtual(self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            (x - self.x) * VIRTUAL_WIDTH / self.width,
+            (y - self.y) * VIRTUAL_HEIGHT / self.height,
+        )
+    }
+}
+
 pub struct Renderer {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub window: &'static Window,
+    /// The window being drawn to. This is None for a renderer that draws offscreen, which is only
+    /// used for testing.
+    window: Option<&'static Window>,
     size: PhysicalSize<u32>,
-    surface: wgpu::Surface<'static>,
+    /// Where the virtual screen is drawn in the window
+    viewport: Viewport,
+    surface: Option<wgpu::Surface<'static>>,
     config: wgpu::SurfaceConfiguration,
+    /// The present mode asked for in the settings, which might not be the one in use (see
+    /// [surface_present_mode])
+    present_mode: PresentMode,
+    /// The present modes the surface supports
+    supported_present_modes: Vec<wgpu::PresentMode>,
     msaa_view: Option<wgpu::TextureView>,
     depth_view: wgpu::TextureView,
-    screen_uniform: wgpu::Buffer,
     screen_bind_group: wgpu::BindGroup,
+    /// The instances of every sprite, which are written to the GPU once a frame
+    sprite_instances: SpriteInstances,
     pipeline_cache: Vec<(&'static str, wgpu::RenderPipeline)>,
     font_cache: Vec<(&'static str, FontId)>,
+    /// Set by wgpu (possibly from another thread) if the device is lost, e.g. because of a driver
+    /// update or crash.
+    device_lost: Arc<AtomicBool>,
 
     pub text_renderer: kaku::TextRenderer,
     egui_handler: egui::Egui,
 }
 
-// A matrix that turns pixel coordinates into wgpu screen coordinates.
-fn create_screen_uniform(size: &PhysicalSize<u32>) -> ScreenUniform {
-    let width = size.width as f32;
-    let height = size.height as f32;
-    let sx = 2.0 / width;
-    let sy = -2.0 / height;
+/// Creates a wgpu instance, along with a surface for the window to be drawn to if there is one.
+fn create_surface(
+    window: Option<&'static Window>,
+) -> anyhow::Result<(wgpu::Instance, Option<wgpu::Surface<'static>>)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+
+    let surface = window
+        .map(|window| instance.create_surface(window))
+        .transpose()?;
+
+    Ok((instance, surface))
+}
+
+/// The present mode to configure the surface with for the one in the settings, falling back to
+/// vsync (which is always supported) if the surface doesn't support it.
+fn surface_present_mode(mode: PresentMode, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    let wanted = match mode {
+        PresentMode::Vsync => return wgpu::PresentMode::AutoVsync,
+        PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+        PresentMode::Immediate => wgpu::PresentMode::Immediate,
+    };
+
+    if supported.contains(&wanted) {
+        wanted
+    } else {
+        log::warn!("{wanted:?} presentation isn't supported, so vsync will be used instead");
+        wgpu::PresentMode::AutoVsync
+    }
+}
+
+// A matrix that turns virtual screen coordinates into wgpu screen coordinates. The render pass's
+// viewport takes care of fitting them into the window.
+fn create_screen_uniform() -> ScreenUniform {
+    let sx = 2.0 / VIRTUAL_WIDTH;
+    let sy = -2.0 / VIRTUAL_HEIGHT;
 
     // Note that wgsl constructs matrices by *row*, not by column
     // which means this is the transpose of what it should be
@@ -102,6 +207,12 @@ fn create_screen_uniform(size: &PhysicalSize<u32>) -> ScreenUniform {
     }
 }
 
+/// How many physical pixels there are to each logical pixel of the window. Offscreen, they're the
+/// same.
+fn scale_factor(window: Option<&Window>) -> f64 {
+    window.map_or(1.0, Window::scale_factor)
+}
+
 // This is here just in case i need it again
 // but for a 2d application, z sorting is better as it preserves transparency
 // and every object has a constant fixed z value (flat)
@@ -232,24 +343,36 @@ macro_rules! include_shader {
 }
 
 impl Renderer {
-    pub fn new(window: &'static Window) -> anyhow::Result<Self> {
-        pollster::block_on(Self::new_async(window))
+    pub fn new(window: &'static Window, present_mode: PresentMode) -> anyhow::Result<Self> {
+        pollster::block_on(Self::new_async(
+            Some(window),
+            window.inner_size(),
+            present_mode,
+        ))
     }
 
-    async fn new_async(window: &'static Window) -> anyhow::Result<Self> {
-        let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
-
-        let surface = instance.create_surface(window)?;
+    /// Creates a renderer that draws to the given window, or offscreen if there isn't one.
+    async fn new_async(
+        window: Option<&'static Window>,
+        size: PhysicalSize<u32>,
+        present_mode: PresentMode,
+    ) -> anyhow::Result<Self> {
+        let (instance, surface) = create_surface(window)?;
+        Self::with_surface(instance, surface, window, size, present_mode).await
+    }
 
+    /// Creates a renderer that draws to a surface made by [create_surface].
+    async fn with_surface(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface<'static>>,
+        window: Option<&'static Window>,
+        size: PhysicalSize<u32>,
+        present_mode: PresentMode,
+    ) -> anyhow::Result<Self> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: Default::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 force_fallback_adapter: false,
             })
             .await
@@ -266,27 +389,53 @@ impl Renderer {
             )
             .await?;
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let device_lost_flag = Arc::clone(&device_lost);
+        device.set_device_lost_callback(move |reason, message| {
+            // The callback is also called when we drop the device ourselves, which is fine
+            if matches!(
+                reason,
+                wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::DeviceInvalid
+            ) {
+                log::error!("GPU device lost ({reason:?}): {message}");
+                device_lost_flag.store(true, Ordering::Relaxed);
+            }
+        });
 
-        let format = surface_capabilities
-            .formats
-            .iter()
-            .copied()
-            .find(|f| !f.is_srgb())
-            .unwrap_or(surface_capabilities.formats[0]);
+        let (format, alpha_mode, supported_present_modes) = match &surface {
+            Some(surface) => {
+                let surface_capabilities = surface.get_capabilities(&adapter);
+
+                let format = surface_capabilities
+                    .formats
+                    .iter()
+                    .copied()
+                    .find(|f| !f.is_srgb())
+                    .unwrap_or(surface_capabilities.formats[0]);
+
+                (
+                    format,
+                    surface_capabilities.alpha_modes[0],
+                    surface_capabilities.present_modes,
+                )
+            }
+            None => (OFFSCREEN_FORMAT, wgpu::CompositeAlphaMode::Auto, Vec::new()),
+        };
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::AutoVsync,
-            alpha_mode: surface_capabilities.alpha_modes[0],
+            present_mode: surface_present_mode(present_mode, &supported_present_modes),
+            alpha_mode,
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
-        surface.configure(&device, &config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &config);
+        }
 
         let msaa_view = if SAMPLE_COUNT > 1 {
             Some(create_msaa_texture(
@@ -301,7 +450,7 @@ impl Renderer {
 
         let screen_uniform = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Screen uniform buffer"),
-            contents: bytemuck::cast_slice(&[create_screen_uniform(&size)]),
+            contents: bytemuck::cast_slice(&[create_screen_uniform()]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
@@ -369,6 +518,21 @@ impl Renderer {
             SAMPLE_COUNT,
         );
 
+        let particle_shader =
+            device.create_shader_module(include_shader!("shaders/particle_shader.wgsl"));
+
+        let particle_pipeline = create_render_pipeline(
+            &device,
+            "particle pipeline",
+            &primitive_pipeline_layout,
+            config.format,
+            Some(DEPTH_FORMAT),
+            false,
+            &particles::vertex_layouts(),
+            &particle_shader,
+            SAMPLE_COUNT,
+        );
+
         let texture_shader =
             device.create_shader_module(include_shader!("shaders/texture_shader.wgsl"));
 
@@ -413,11 +577,11 @@ impl Renderer {
         );
 
         let depth_view = create_depth_texture(&device, &size);
-        let egui_handler = egui::Egui::new(&device, &config, window.scale_factor());
+        let egui_handler = egui::Egui::new(&device, &config, scale_factor(window));
 
         let mut font_cache = Vec::new();
         let mut text_renderer =
-            TextRendererBuilder::new(config.format, (config.width, config.height))
+            TextRendererBuilder::new(config.format, (VIRTUAL_WIDTH as u32, VIRTUAL_HEIGHT as u32))
                 .with_msaa_sample_count(SAMPLE_COUNT)
                 .with_depth(DEPTH_FORMAT)
                 .build(&device);
@@ -427,8 +591,10 @@ impl Renderer {
             ("mplus regular", "MPLUSRounded1c-Regular.ttf", 50.),
             ("mochiy pop one", "MochiyPopOne-Regular.ttf", 80.),
         ] {
-            let font_data =
-                FontVec::try_from_vec(std::fs::read(format!("assets/fonts/{filename}"))?)?;
+            let path = skin()
+                .font_path(filename)
+                .unwrap_or_else(|| Path::new(FONTS_PATH).join(filename));
+            let font_data = FontVec::try_from_vec(std::fs::read(path)?)?;
             let id = text_renderer.load_font_with_sdf(
                 font_data,
                 FontSize::Px(size),
@@ -437,32 +603,77 @@ impl Renderer {
             font_cache.push((font.to_string().leak() as &'static str, id));
         }
 
+        let sprite_instances = SpriteInstances::new(&device);
+
         Ok(Self {
             size,
+            viewport: Viewport::letterbox(&size),
             surface,
             config,
+            present_mode,
+            supported_present_modes,
             device,
             queue,
             window,
             msaa_view,
             depth_view,
-            screen_uniform,
             screen_bind_group,
+            sprite_instances,
             pipeline_cache: vec![
                 ("texture", texture_pipeline),
                 ("texture_depth", texture_pipeline_depth),
                 ("primitive", primitive_pipeline),
                 ("primitive_depth", primitive_pipeline_depth),
+                ("particle", particle_pipeline),
             ],
             font_cache,
             text_renderer,
             egui_handler,
+            device_lost,
         })
     }
 
+    /// Returns true if the GPU device has been lost, in which case the renderer needs to be
+    /// recreated with [Renderer::recreate].
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Throws away the device and everything created with it, and initialises the renderer from
+    /// scratch. All the pipelines, fonts and the text renderer are rebuilt, but anything else that
+    /// was created with the old device (sprites, shapes, text etc.) will have to be recreated by
+    /// whoever owns it.
+    ///
+    /// A window can only have one surface at a time, so the old surface is dropped before the new
+    /// one is created. If that fails, the error is returned and the rest of the renderer is left as
+    /// it was, but it has nothing to draw to until it's recreated successfully.
+    pub fn recreate(&mut self) -> anyhow::Result<()> {
+        let size = self.window.map_or(self.size, Window::inner_size);
+        self.surface = None;
+
+        let (instance, surface) = create_surface(self.window)?;
+        *self = pollster::block_on(Self::with_surface(
+            instance,
+            surface,
+            self.window,
+            size,
+            self.present_mode,
+        ))?;
+        Ok(())
+    }
+
     pub fn render(&mut self, app: &mut Game) -> Result<(), wgpu::SurfaceError> {
-        let texture = self.surface.get_current_texture()?;
+        let Some(surface) = &self.surface else {
+            // A window's renderer is only without a surface if recreating it failed
+            assert!(
+                self.window.is_some(),
+                "an offscreen renderer has no window to draw to"
+            );
+            return Err(wgpu::SurfaceError::Lost);
+        };
+        let texture = surface.get_current_texture()?;
         let view = texture.texture.create_view(&Default::default());
+        self.sprite_instances.prepare(&self.device);
 
         let mut encoder = self
             .device
@@ -476,7 +687,7 @@ impl Renderer {
 
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [self.size.width, self.size.height],
-            pixels_per_point: self.window.scale_factor() as _,
+            pixels_per_point: scale_factor(self.window) as _,
         };
 
         let paint_jobs = self.egui_handler.end_render(
@@ -484,9 +695,13 @@ impl Renderer {
             &self.queue,
             &mut encoder,
             &screen_descriptor,
-            &self.window,
+            self.window,
         );
 
+        if app.needs_transition_capture() {
+            self.capture_transition(app, &mut encoder);
+        }
+
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -513,6 +728,14 @@ impl Renderer {
             occlusion_query_set: None,
         });
 
+        let Viewport {
+            x,
+            y,
+            width,
+            height,
+        } = self.viewport;
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+
         // Rendering goes here...
         app.render(self, &mut render_pass);
 
@@ -521,18 +744,73 @@ impl Renderer {
 
         drop(render_pass);
 
+        self.sprite_instances.flush(&self.queue);
         self.queue.submit([encoder.finish()]);
         texture.present();
 
         Ok(())
     }
 
+    /// Draws the screen from before a state change into a texture the size of the virtual screen,
+    /// and hands it to the game to animate the change with.
+    fn capture_transition(&self, app: &mut Game, encoder: &mut wgpu::CommandEncoder) {
+        let size = (VIRTUAL_WIDTH as u32, VIRTUAL_HEIGHT as u32);
+        let capture = match texture::Texture::empty(
+            &self.device,
+            Some("transition capture"),
+            self.config.format,
+            size,
+        ) {
+            Ok(capture) => capture,
+            Err(e) => {
+                log::error!("couldn't capture the screen for a transition: {e}");
+                app.cancel_transition();
+                return;
+            }
+        };
+
+        // The pipelines all expect multisampling and a depth buffer, so the capture needs its own
+        let msaa_view = (SAMPLE_COUNT > 1)
+            .then(|| create_msaa_texture(&self.device, size, self.config.format, SAMPLE_COUNT));
+        let depth_view = create_depth_texture(&self.device, &PhysicalSize::new(size.0, size.1));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transition capture pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: msaa_view.as_ref().unwrap_or(capture.view()),
+                resolve_target: msaa_view.as_ref().map(|_| capture.view()),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(CLEAR_COLOUR),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        app.render_transition_capture(self, &mut render_pass);
+        drop(render_pass);
+
+        app.finish_transition_capture(capture, self);
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         if size.width > 0 && size.height > 0 {
             self.size = size;
             self.config.width = size.width;
             self.config.height = size.height;
-            self.surface.configure(&self.device, &self.config);
+
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
 
             self.depth_view = create_depth_texture(&self.device, &size);
 
@@ -545,16 +823,9 @@ impl Renderer {
                 ));
             }
 
-            // Resize the screen space transformation matrirx
-            let screen_uniform = create_screen_uniform(&size);
-            self.queue.write_buffer(
-                &self.screen_uniform,
-                0,
-                bytemuck::cast_slice(&[screen_uniform]),
-            );
-
-            self.text_renderer
-                .resize((size.width, size.height), &self.queue);
+            // Everything is still drawn at the virtual resolution, just in a different part of
+            // the window
+            self.viewport = Viewport::letterbox(&size);
         }
     }
 
@@ -570,6 +841,31 @@ impl Renderer {
         &self.size
     }
 
+    /// The window being drawn to, if there is one.
+    pub fn window(&self) -> Option<&'static Window> {
+        self.window
+    }
+
+    /// Changes how frames are shown on the screen (see [PresentMode]).
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        if present_mode == self.present_mode {
+            return;
+        }
+
+        self.present_mode = present_mode;
+        self.config.present_mode =
+            surface_present_mode(present_mode, &self.supported_present_modes);
+
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    /// Where the virtual screen is drawn in the window.
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
     pub fn pipeline(&self, name: &str) -> Option<&wgpu::RenderPipeline> {
         self.pipeline_cache.iter().find_map(
             |(n, pipeline)| {
@@ -590,3 +886,58 @@ impl Renderer {
             .1
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_letterbox() {
+        // The same shape as the virtual screen fills the whole window
+        let viewport = Viewport::letterbox(&PhysicalSize::new(1280, 720));
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 0.,
+                y: 0.,
+                width: 1280.,
+                height: 720.,
+            }
+        );
+        assert_eq!(viewport.to_virtual((640., 360.)), (960., 540.));
+
+        // A wider window gets bars on either side
+        let viewport = Viewport::letterbox(&PhysicalSize::new(2560, 1080));
+        assert_eq!(viewport.x, 320.);
+        assert_eq!(viewport.y, 0.);
+        assert_eq!(viewport.to_virtual((320., 0.)), (0., 0.));
+        assert_eq!(viewport.to_virtual((2240., 1080.)), (1920., 1080.));
+        assert!(viewport.to_virtual((0., 0.)).0 < 0.);
+
+        // A taller one gets bars on the top and bottom
+        let viewport = Viewport::letterbox(&PhysicalSize::new(960, 960));
+        assert_eq!(viewport.width, 960.);
+        assert_eq!(viewport.height, 540.);
+        assert_eq!(viewport.y, 210.);
+        assert_eq!(viewport.to_virtual((480., 480.)), (960., 540.));
+    }
+
+    #[test]
+    fn test_surface_present_mode() {
+        let supported = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox];
+
+        assert_eq!(
+            surface_present_mode(PresentMode::Mailbox, &supported),
+            wgpu::PresentMode::Mailbox
+        );
+        assert_eq!(
+            surface_present_mode(PresentMode::Vsync, &supported),
+            wgpu::PresentMode::AutoVsync
+        );
+        // Unsupported modes fall back to vsync
+        assert_eq!(
+            surface_present_mode(PresentMode::Immediate, &supported),
+            wgpu::PresentMode::AutoVsync
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! Lightweight particle effects, like confetti or the pieces of a popped balloon.
+//!
+//! A [Particles] system holds up to a fixed number of particles (its budget), which are simulated
+//! on the CPU and drawn as coloured quads in a single instanced draw call. Particles are added in
+//! bursts with [Particles::spawn], described by a [SpawnConfig]. All the randomness comes from the
+//! [Rng] the system was created with, so the same system fed the same bursts and frame times always
+//! plays out the same way.
+use std::f32::consts::TAU;
+use std::ops::Range;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::vertex_attr_array;
+
+use super::resources::{Allocation, ResourceUsage};
+use super::{Renderable, Renderer};
+use crate::rng::Rng;
+
+/// The fraction of a particle's life over which it fades out at the end.
+const FADE_FRACTION: f32 = 0.25;
+
+/// The corners of a quad one unit across, centred on the origin.
+const QUAD_CORNERS: [[f32; 2]; 4] = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+const QUAD_INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+/// Describes a burst of particles.
+///
+/// Everything given as a range is picked at random (uniformly) for each particle.
+#[derive(Debug, Clone)]
+pub struct SpawnConfig {
+    pub count: usize,
+    /// The centre of the area the particles start in.
+    pub origin: [f32; 2],
+    /// How far from the origin particles can start, horizontally and vertically.
+    pub spread: [f32; 2],
+    /// The direction particles are sent in, in radians clockwise from the right (so `-PI / 2` is
+    /// straight up).
+    pub direction: f32,
+    /// How far either side of `direction` each particle may go, in radians.
+    pub angle_spread: f32,
+    /// How fast particles start off, in pixels per second.
+    pub speed: Range<f32>,
+    /// How long each particle lasts, in seconds.
+    pub lifetime: Range<f32>,
+    pub width: Range<f32>,
+    pub height: Range<f32>,
+    /// The fastest particles spin either way, in radians per second.
+    pub spin: f32,
+    /// How fast particles turn over, in radians per second. Turning over squashes the particle
+    /// horizontally, which makes flat things like confetti look like they're fluttering.
+    pub flip_speed: Range<f32>,
+    /// Downwards acceleration, in pixels per second per second.
+    pub gravity: f32,
+    /// How much speed particles lose to air resistance, as a fraction per second.
+    pub drag: f32,
+    /// Each particle is one of these colours.
+    pub colours: &'static [[f32; 4]],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Particle {
+    position: [f32; 2],
+    velocity: [f32; 2],
+    size: [f32; 2],
+    rotation: f32,
+    spin: f32,
+    flip: f32,
+    flip_speed: f32,
+    gravity: f32,
+    drag: f32,
+    colour: [f32; 4],
+    age: f32,
+    lifetime: f32,
+}
+
+impl Particle {
+    fn instance(&self) -> ParticleInstance {
+        let fade_start = self.lifetime * (1. - FADE_FRACTION);
+        let alpha = if self.age > fade_start {
+            1. - (self.age - fade_start) / (self.lifetime - fade_start)
+        } else {
+            1.
+        };
+
+        let mut colour = self.colour;
+        colour[3] *= alpha.clamp(0., 1.);
+
+        ParticleInstance {
+            position: self.position,
+            size: [self.size[0] * self.flip.cos(), self.size[1]],
+            rotation: self.rotation,
+            colour,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    rotation: f32,
+    colour: [f32; 4],
+}
+
+impl ParticleInstance {
+    const ATTRS: &'static [wgpu::VertexAttribute] = &vertex_attr_array![
+        1 => Float32x2,
+        2 => Float32x2,
+        3 => Float32,
+        4 => Float32x4,
+    ];
+
+    fn vertex_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleInstance>() as _,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: Self::ATTRS,
+        }
+    }
+}
+
+const CORNER_ATTRS: &[wgpu::VertexAttribute] = &vertex_attr_array![0 => Float32x2];
+
+/// Returns the vertex buffer layouts the particle pipeline is built with.
+pub(super) fn vertex_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 2] {
+    [
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 2]>() as _,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: CORNER_ATTRS,
+        },
+        ParticleInstance::vertex_layout(),
+    ]
+}
+
+/// The CPU side of a particle system, which doesn't need a GPU to run.
+#[derive(Debug)]
+struct Simulation {
+    particles: Vec<Particle>,
+    budget: usize,
+    rng: Rng,
+}
+
+impl Simulation {
+    fn new(budget: usize, rng: Rng) -> Self {
+        Self {
+            particles: Vec::with_capacity(budget),
+            budget,
+            rng,
+        }
+    }
+
+    fn spawn(&mut self, config: &SpawnConfig) {
+        let count = config.count.min(self.budget - self.particles.len());
+        let rng = &mut self.rng;
+
+        for _ in 0..count {
+            let angle = config.direction + rng.range(-config.angle_spread, config.angle_spread);
+            let speed = rng.range(config.speed.start, config.speed.end);
+
+            self.particles.push(Particle {
+                position: [
+                    config.origin[0] + rng.range(-config.spread[0], config.spread[0]),
+                    config.origin[1] + rng.range(-config.spread[1], config.spread[1]),
+                ],
+                velocity: [angle.cos() * speed, angle.sin() * speed],
+                size: [
+                    rng.range(config.width.start, config.width.end),
+                    rng.range(config.height.start, config.height.end),
+                ],
+                rotation: rng.range(0., TAU),
+                spin: rng.range(-config.spin, config.spin),
+                flip: rng.range(0., TAU),
+                flip_speed: rng.range(config.flip_speed.start, config.flip_speed.end),
+                gravity: config.gravity,
+                drag: config.drag,
+                colour: rng.choose(config.colours).copied().unwrap_or([1.; 4]),
+                age: 0.,
+                lifetime: rng.range(config.lifetime.start, config.lifetime.end),
+            });
+        }
+    }
+
+    fn update(&mut self, delta_time: f32) {
+        for particle in self.particles.iter_mut() {
+            let damping = (1. - particle.drag * delta_time).max(0.);
+            particle.velocity[0] *= damping;
+            particle.velocity[1] = particle.velocity[1] * damping + particle.gravity * delta_time;
+
+            particle.position[0] += particle.velocity[0] * delta_time;
+            particle.position[1] += particle.velocity[1] * delta_time;
+            particle.rotation += particle.spin * delta_time;
+            particle.flip += particle.flip_speed * delta_time;
+            particle.age += delta_time;
+        }
+
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+    }
+}
+
+/// A particle system. See the [module documentation](self).
+pub struct Particles {
+    simulation: Simulation,
+    instances: Vec<ParticleInstance>,
+    vertex: wgpu::Buffer,
+    index: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    _allocation: Allocation,
+}
+
+impl Particles {
+    /// Creates an empty particle system that can hold up to `budget` particles at once.
+    pub fn new(renderer: &Renderer, budget: usize, rng: Rng) -> Self {
+        let (vertex, index, instance_buffer, allocation) = Self::create_buffers(renderer, budget);
+
+        Self {
+            simulation: Simulation::new(budget, rng),
+            instances: Vec::with_capacity(budget),
+            vertex,
+            index,
+            instance_buffer,
+            _allocation: allocation,
+        }
+    }
+
+    fn create_buffers(
+        renderer: &Renderer,
+        budget: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer, Allocation) {
+        let vertex = renderer.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("particle vertex buffer"),
+            contents: bytemuck::cast_slice(&QUAD_CORNERS),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index = renderer.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("particle index buffer"),
+            contents: bytemuck::cast_slice(&QUAD_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let instance = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle instance buffer"),
+            size: (budget.max(1) * std::mem::size_of::<ParticleInstance>()) as _,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let allocation = Allocation::register(
+            ResourceUsage::default()
+                .buffer(&vertex)
+                .buffer(&index)
+                .buffer(&instance),
+        );
+
+        (vertex, index, instance, allocation)
+    }
+
+    /// Recreates the buffers on the GPU (e.g. after the device was lost), keeping the particles as
+    /// they are.
+    pub fn recreate_gpu_resources(&mut self, renderer: &Renderer) {
+        (
+            self.vertex,
+            self.index,
+            self.instance_buffer,
+            self._allocation,
+        ) = Self::create_buffers(renderer, self.simulation.budget);
+    }
+
+    /// Adds a burst of particles. If that would go over the budget, only as many as fit are added.
+    pub fn spawn(&mut self, config: &SpawnConfig) {
+        self.simulation.spawn(config);
+    }
+
+    /// Moves every particle along by `delta_time` seconds, and removes the ones that have run out
+    /// of time.
+    pub fn update(&mut self, delta_time: f32) {
+        self.simulation.update(delta_time);
+
+        self.instances.clear();
+        self.instances
+            .extend(self.simulation.particles.iter().map(Particle::instance));
+    }
+}
+
+impl Renderable for Particles {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        renderer.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+
+        render_pass.set_pipeline(
+            renderer
+                .pipeline("particle")
+                .expect("particle render pipeline doesn't exist!"),
+        );
+        render_pass.set_bind_group(0, &renderer.screen_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..QUAD_INDICES.len() as _, 0, 0..self.instances.len() as _);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BURST: SpawnConfig = SpawnConfig {
+        count: 100,
+        origin: [500., 500.],
+        spread: [10., 10.],
+        direction: -std::f32::consts::FRAC_PI_2,
+        angle_spread: 0.5,
+        speed: 100.0..200.0,
+        lifetime: 1.0..2.0,
+        width: 5.0..10.0,
+        height: 5.0..10.0,
+        spin: 3.,
+        flip_speed: 0.0..5.0,
+        gravity: 500.,
+        drag: 0.5,
+        colours: &[[1., 0., 0., 1.], [0., 0., 1., 1.]],
+    };
+
+    #[test]
+    fn test_budget() {
+        let mut simulation = Simulation::new(250, Rng::new(0));
+
+        for _ in 0..3 {
+            simulation.spawn(&BURST);
+        }
+
+        assert_eq!(simulation.particles.len(), 250);
+
+        // Every particle lasts at most 2 seconds
+        simulation.update(2.0);
+        assert!(simulation.particles.is_empty());
+    }
+
+    #[test]
+    fn test_same_seed_same_particles() {
+        let run = |seed| {
+            let mut simulation = Simulation::new(500, Rng::new(seed));
+            simulation.spawn(&BURST);
+
+            for _ in 0..30 {
+                simulation.update(1. / 60.);
+            }
+
+            simulation.spawn(&BURST);
+            simulation.update(1. / 60.);
+            simulation.particles
+        };
+
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_particles_fade_out() {
+        let mut simulation = Simulation::new(1, Rng::new(0));
+        simulation.spawn(&SpawnConfig {
+            lifetime: 1.0..1.0,
+            ..BURST
+        });
+
+        assert_eq!(simulation.particles[0].instance().colour[3], 1.);
+
+        simulation.update(0.875);
+        let alpha = simulation.particles[0].instance().colour[3];
+        assert!((alpha - 0.5).abs() < 1e-4, "alpha is {alpha}");
+    }
+}
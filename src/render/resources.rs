@@ -0,0 +1,303 @@
+//! Rough accounting of how much GPU memory each game state is using.
+//!
+//! Whenever one of the major kinds of GPU object (textures, sprite batches, shapes, particles and
+//! text) is created, it registers how many buffers and textures it made and how big they are, and
+//! holds on to an [Allocation] that removes them again when it's dropped. Each registration is
+//! tagged with the name of whoever currently owns new resources, which the game sets to the state
+//! being updated (see [with_owner]).
+//!
+//! None of this is exact. Anything made with raw wgpu calls isn't counted, text can only be
+//! guessed at, and textures in the shared texture cache are counted against whichever state
+//! happened to load them first.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Who resources belong to when no owner has been set, i.e. the game itself.
+pub const DEFAULT_OWNER: &str = "Game";
+
+/// How many buffers and textures something has on the GPU, and how many bytes they take up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub buffers: u32,
+    pub buffer_bytes: u64,
+    pub textures: u32,
+    pub texture_bytes: u64,
+}
+
+impl ResourceUsage {
+    /// Adds a buffer to the usage.
+    pub fn buffer(self, buffer: &wgpu::Buffer) -> Self {
+        self.buffer_bytes(buffer.size())
+    }
+
+    /// Adds a buffer of the given size to the usage, for when the buffer itself isn't available.
+    pub fn buffer_bytes(mut self, bytes: u64) -> Self {
+        self.buffers += 1;
+        self.buffer_bytes += bytes;
+        self
+    }
+
+    /// Adds a texture to the usage.
+    pub fn texture(mut self, texture: &wgpu::Texture) -> Self {
+        self.textures += 1;
+        self.texture_bytes += texture_bytes(texture);
+        self
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.buffer_bytes + self.texture_bytes
+    }
+
+    fn add(&mut self, other: &ResourceUsage) {
+        self.buffers += other.buffers;
+        self.buffer_bytes += other.buffer_bytes;
+        self.textures += other.textures;
+        self.texture_bytes += other.texture_bytes;
+    }
+}
+
+/// Roughly how much memory a texture takes up, ignoring any padding the driver adds.
+fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    // Depth formats like Depth24Plus don't have a fixed size, so we guess
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+
+    let blocks = texture.width().div_ceil(block_width) as u64
+        * texture.height().div_ceil(block_height) as u64
+        * texture.depth_or_array_layers() as u64;
+
+    blocks * block_size as u64 * texture.sample_count() as u64
+}
+
+/// Formats a number of bytes to be readable, e.g. "1.50 MiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.2} {}", UNITS[unit])
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    owner: &'static str,
+    usage: ResourceUsage,
+}
+
+/// Keeps track of every registered [ResourceUsage] and who it belongs to.
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    owners: Vec<&'static str>,
+    entries: HashMap<u64, Entry>,
+}
+
+impl Registry {
+    fn owner(&self) -> &'static str {
+        self.owners.last().copied().unwrap_or(DEFAULT_OWNER)
+    }
+
+    fn register(&mut self, usage: ResourceUsage) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.insert(
+            id,
+            Entry {
+                owner: self.owner(),
+                usage,
+            },
+        );
+
+        id
+    }
+
+    fn release(&mut self, id: u64) {
+        self.entries.remove(&id);
+    }
+
+    /// Gives everything registered since `mark` (see [Registry::mark]) to a different owner.
+    fn reassign_since(&mut self, mark: u64, owner: &'static str) {
+        for (_, entry) in self.entries.iter_mut().filter(|(&id, _)| id >= mark) {
+            entry.owner = owner;
+        }
+    }
+
+    fn mark(&self) -> u64 {
+        self.next_id
+    }
+
+    /// The total usage of each owner, biggest first.
+    fn report(&self) -> Vec<(&'static str, ResourceUsage)> {
+        let mut totals: HashMap<&'static str, ResourceUsage> = HashMap::new();
+
+        for entry in self.entries.values() {
+            totals.entry(entry.owner).or_default().add(&entry.usage);
+        }
+
+        let mut report = totals.into_iter().collect::<Vec<_>>();
+        report.sort_by(|(a_owner, a), (b_owner, b)| {
+            b.total_bytes()
+                .cmp(&a.total_bytes())
+                .then(a_owner.cmp(b_owner))
+        });
+        report
+    }
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::default();
+}
+
+/// A registered set of GPU resources. Keep this alongside the resources it describes: when it's
+/// dropped, they stop being counted.
+#[derive(Debug)]
+#[must_use]
+pub struct Allocation {
+    id: u64,
+}
+
+impl Allocation {
+    /// Registers some resources against the current owner.
+    pub fn register(usage: ResourceUsage) -> Self {
+        Self {
+            id: REGISTRY.with_borrow_mut(|registry| registry.register(usage)),
+        }
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        // The registry may already be gone if this is dropped while the thread is shutting down
+        let _ = REGISTRY.try_with(|registry| registry.borrow_mut().release(self.id));
+    }
+}
+
+/// Runs a function with anything it registers belonging to `owner`.
+pub fn with_owner<T>(owner: &'static str, f: impl FnOnce() -> T) -> T {
+    REGISTRY.with_borrow_mut(|registry| registry.owners.push(owner));
+    let result = f();
+    REGISTRY.with_borrow_mut(|registry| registry.owners.pop());
+    result
+}
+
+/// Returns a mark that can be passed to [reassign_since].
+pub fn mark() -> u64 {
+    REGISTRY.with_borrow(Registry::mark)
+}
+
+/// Gives every resource registered since the mark was made to a different owner. This is for when
+/// one state creates another, as the new state's resources were registered while the old one was
+/// the owner.
+pub fn reassign_since(mark: u64, owner: &'static str) {
+    REGISTRY.with_borrow_mut(|registry| registry.reassign_since(mark, owner));
+}
+
+/// The total usage of each owner, biggest first.
+pub fn report() -> Vec<(&'static str, ResourceUsage)> {
+    REGISTRY.with_borrow(Registry::report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn usage(buffer_bytes: u64, texture_bytes: u64) -> ResourceUsage {
+        ResourceUsage {
+            buffers: 1,
+            buffer_bytes,
+            textures: 1,
+            texture_bytes,
+        }
+    }
+
+    #[test]
+    fn test_registry_totals() {
+        let mut registry = Registry::default();
+
+        let game = registry.register(usage(100, 0));
+        registry.owners.push("SongSelect");
+        registry.register(usage(10, 20));
+        let released = registry.register(usage(1000, 1000));
+        registry.register(ResourceUsage::default().buffer_bytes(5).buffer_bytes(5));
+        registry.owners.pop();
+        registry.release(released);
+
+        assert_eq!(
+            registry.report(),
+            vec![
+                (DEFAULT_OWNER, usage(100, 0)),
+                (
+                    "SongSelect",
+                    ResourceUsage {
+                        buffers: 3,
+                        buffer_bytes: 20,
+                        textures: 1,
+                        texture_bytes: 20,
+                    }
+                ),
+            ]
+        );
+
+        // Releasing the same thing twice doesn't take it away twice
+        registry.release(game);
+        registry.release(game);
+        assert_eq!(registry.report().len(), 1);
+        assert_eq!(registry.report()[0].1.total_bytes(), 40);
+    }
+
+    #[test]
+    fn test_reassign() {
+        let mut registry = Registry::default();
+        registry.owners.push("SongSelect");
+        registry.register(usage(10, 0));
+
+        // SongSelect creates the gameplay scene, which then gets its resources back
+        let mark = registry.mark();
+        registry.register(usage(20, 0));
+        registry.register(usage(0, 30));
+        registry.reassign_since(mark, "TaikoMode");
+
+        assert_eq!(
+            registry.report(),
+            vec![
+                (
+                    "TaikoMode",
+                    ResourceUsage {
+                        buffers: 2,
+                        buffer_bytes: 20,
+                        textures: 2,
+                        texture_bytes: 30,
+                    }
+                ),
+                ("SongSelect", usage(10, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allocations_release_on_drop() {
+        let allocation = with_owner("Test", || Allocation::register(usage(64, 0)));
+        assert!(report().iter().any(|&(owner, _)| owner == "Test"));
+
+        drop(allocation);
+        assert!(report().iter().all(|&(owner, _)| owner != "Test"));
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.50 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.00 MiB");
+    }
+}
@@ -0,0 +1,58 @@
+// Particle shader: Coloured quads, each scaled, rotated and positioned by its instance
+
+struct VertexInput {
+    @location(0) corner: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) colour: vec4<f32>,
+};
+
+struct Instance {
+    @location(1) position: vec2<f32>,
+    @location(2) size: vec2<f32>,
+    @location(3) rotation: f32,
+    @location(4) colour: vec4<f32>,
+};
+
+struct ScreenUniform {
+    mat0: vec4<f32>,
+    mat1: vec4<f32>,
+    mat2: vec4<f32>,
+    mat3: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> screen_uniform: ScreenUniform;
+
+fn quick_sigmoid(z: f32) -> f32 {
+    return 0.5 * ((z / (1.0 + abs(z))) + 1.0);
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: Instance) -> VertexOutput {
+    var out: VertexOutput;
+
+    let screen_matrix = mat4x4<f32>(
+        screen_uniform.mat0,
+        screen_uniform.mat1,
+        screen_uniform.mat2,
+        screen_uniform.mat3,
+    );
+
+    let corner = in.corner * instance.size;
+    let c = cos(instance.rotation);
+    let s = sin(instance.rotation);
+    let rotated = vec2<f32>(corner.x * c - corner.y * s, corner.x * s + corner.y * c);
+
+    out.clip_position = screen_matrix * vec4<f32>(rotated + instance.position, 0.0, 1.0);
+    out.clip_position.z = quick_sigmoid(out.clip_position.z);
+    out.colour = instance.colour;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.colour;
+}
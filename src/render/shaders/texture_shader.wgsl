@@ -8,10 +8,15 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coord: vec2<f32>,
+    @location(1) tint: vec4<f32>,
 };
 
 struct Instance {
     @location(2) world_position: vec3<f32>,
+    @location(3) tint: vec4<f32>,
+    @location(4) scale: f32,
+    // The part of the texture to draw: the top left corner, then the size, in texture coordinates
+    @location(5) region: vec4<f32>,
 };
 
 struct ScreenUniform {
@@ -39,9 +44,12 @@ fn vs_main(vert: VertexInput, inst: Instance) -> VertexOutput {
         screen_uniform.mat3,
     );
 
-    out.clip_position = screen_matrix * vec4<f32>(vert.position.xy + inst.world_position.xy, inst.world_position.z, 1.0);
+    // The vertices cover the whole texture, so they're shrunk down to the size of the region
+    let size = vert.position.xy * inst.region.zw;
+    out.clip_position = screen_matrix * vec4<f32>(size * inst.scale + inst.world_position.xy, inst.world_position.z, 1.0);
     out.clip_position.z = quick_sigmoid(out.clip_position.z);
-    out.tex_coord = vert.tex_coord;
+    out.tex_coord = inst.region.xy + vert.tex_coord * inst.region.zw;
+    out.tint = inst.tint;
     return out;
 }
 
@@ -58,5 +66,5 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         discard;
     }
 
-    return sample;
+    return sample * in.tint;
 }
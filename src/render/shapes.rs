@@ -22,6 +22,7 @@ use wgpu::{
     vertex_attr_array,
 };
 
+use super::resources::{Allocation, ResourceUsage};
 use super::{Renderable, Renderer, SpriteInstance};
 
 #[repr(C)]
@@ -115,7 +116,8 @@ impl LinearGradient {
     }
 }
 
-fn lerp_colour(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+/// Blends between two colours, from `a` when `t` is 0 to `b` when `t` is 1.
+pub fn lerp_colour(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
     let t = t.clamp(0.0, 1.0);
 
     [
@@ -162,6 +164,7 @@ pub struct Shape {
     instance: wgpu::Buffer,
     indices: u32,
     has_depth: bool,
+    _allocation: Allocation,
 }
 
 /// A builder for creating complicated shapes made up of multiple primitives
@@ -204,13 +207,19 @@ impl ShapeBuilder {
 
         let instance = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("primitive instance buffer"),
-            contents: bytemuck::cast_slice(&[SpriteInstance {
-                position: self.position,
-            }]),
+            contents: bytemuck::cast_slice(&[SpriteInstance::new(self.position)]),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
+        let allocation = Allocation::register(
+            ResourceUsage::default()
+                .buffer(&vertex)
+                .buffer(&index)
+                .buffer(&instance),
+        );
+
         Shape {
+            _allocation: allocation,
             vertex,
             index,
             instance,
@@ -450,11 +459,12 @@ impl Default for ShapeBuilder {
 impl Shape {
     /// Moves the whole shape to the given position.
     pub fn set_position(&self, position: [f32; 3], renderer: &Renderer) {
-        renderer.queue.write_buffer(
-            &self.instance,
-            0,
-            bytemuck::cast_slice(&[SpriteInstance { position }]),
-        );
+        SpriteInstance::write_position(&self.instance, position, &renderer.queue);
+    }
+
+    /// Sets the colour that every vertex of the shape is multiplied by.
+    pub fn set_tint(&self, tint: [f32; 4], renderer: &Renderer) {
+        SpriteInstance::write_tint(&self.instance, tint, &renderer.queue);
     }
 }
 
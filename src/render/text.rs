@@ -1,7 +1,14 @@
+use std::ops::{Deref, DerefMut};
+
 use kaku::{Text, TextBuilder};
 
+use super::resources::{Allocation, ResourceUsage};
 use super::{Renderable, Renderer};
 
+/// Roughly how much GPU memory a piece of text takes up. kaku doesn't tell us how big its buffers
+/// are, so this is a guess at a settings uniform and a line's worth of glyphs.
+const TEXT_BYTES_ESTIMATE: u64 = 2048;
+
 impl Renderable for Text {
     fn render<'pass>(
         &'pass self,
@@ -12,16 +19,53 @@ impl Renderable for Text {
     }
 }
 
+/// A [Text] that's counted in the GPU [resources](super::resources) of whoever built it.
+pub struct TrackedText {
+    text: Text,
+    _allocation: Allocation,
+}
+
+impl Deref for TrackedText {
+    type Target = Text;
+
+    fn deref(&self) -> &Text {
+        &self.text
+    }
+}
+
+impl DerefMut for TrackedText {
+    fn deref_mut(&mut self) -> &mut Text {
+        &mut self.text
+    }
+}
+
+impl Renderable for TrackedText {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass super::Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.text.render(renderer, render_pass);
+    }
+}
+
 pub(crate) trait BuildTextWithRenderer {
-    fn build_text(&self, renderer: &mut Renderer) -> Text;
+    fn build_text(&self, renderer: &mut Renderer) -> TrackedText;
 }
 
 impl BuildTextWithRenderer for TextBuilder {
-    fn build_text(&self, renderer: &mut Renderer) -> Text {
-        self.build(
+    fn build_text(&self, renderer: &mut Renderer) -> TrackedText {
+        let text = self.build(
             &renderer.device,
             &renderer.queue,
             &mut renderer.text_renderer,
-        )
+        );
+
+        TrackedText {
+            text,
+            _allocation: Allocation::register(
+                ResourceUsage::default().buffer_bytes(TEXT_BYTES_ESTIMATE),
+            ),
+        }
     }
 }
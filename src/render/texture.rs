@@ -1,12 +1,14 @@
 //! Various types used for drawing textures
 
-use image::GenericImageView;
+use image::{GenericImage, RgbaImage};
 use std::{path::Path, rc::Rc, sync::OnceLock};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     vertex_attr_array, RenderPass,
 };
 
+use super::instances::InstanceSlot;
+use super::resources::{Allocation, ResourceUsage};
 use super::{Renderable, Renderer};
 
 static TEXTURE_BIND_GROUP_LAYOUT: OnceLock<wgpu::BindGroupLayout> = OnceLock::new();
@@ -67,10 +69,49 @@ impl TextureVertex {
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
 pub struct SpriteInstance {
     pub position: [f32; 3],
+    /// A colour that every pixel of the sprite is multiplied by. Use white for no tint.
+    pub tint: [f32; 4],
+    /// How much bigger or smaller than its texture the sprite is drawn.
+    pub scale: f32,
+    /// The part of the GPU texture the sprite is drawn with, as `[x, y, width, height]` in texture
+    /// coordinates.
+    pub region: [f32; 4],
 }
 
+/// The tint for an instance that isn't tinted.
+pub const NO_TINT: [f32; 4] = [1.; 4];
+
+/// The region covering the whole of a GPU texture.
+pub const FULL_REGION: [f32; 4] = [0., 0., 1., 1.];
+
 impl SpriteInstance {
-    const ATTRS: &'static [wgpu::VertexAttribute] = &vertex_attr_array![2 => Float32x3];
+    const ATTRS: &'static [wgpu::VertexAttribute] =
+        &vertex_attr_array![2 => Float32x3, 3 => Float32x4, 4 => Float32, 5 => Float32x4];
+
+    /// Creates a new instance at the given position with no tint, at its normal size, covering
+    /// the whole of its texture
+    pub fn new(position: [f32; 3]) -> Self {
+        Self {
+            position,
+            tint: NO_TINT,
+            scale: 1.0,
+            region: FULL_REGION,
+        }
+    }
+
+    /// Writes only the position of an instance to an instance buffer, leaving the tint as is.
+    pub fn write_position(buffer: &wgpu::Buffer, position: [f32; 3], queue: &wgpu::Queue) {
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&position));
+    }
+
+    /// Writes only the tint of an instance to an instance buffer, leaving the position as is.
+    pub fn write_tint(buffer: &wgpu::Buffer, tint: [f32; 4], queue: &wgpu::Queue) {
+        queue.write_buffer(
+            buffer,
+            std::mem::size_of::<[f32; 3]>() as _,
+            bytemuck::cast_slice(&tint),
+        );
+    }
 
     /// Returns the vertex buffer layout describing this vertex
     pub fn vertex_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -82,13 +123,24 @@ impl SpriteInstance {
     }
 }
 
+/// The parts of a texture that live on the GPU. These are shared between an atlas and every
+/// texture packed into it.
+#[derive(Debug)]
+struct GpuTexture {
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    view: wgpu::TextureView,
+    _allocation: Allocation,
+}
+
 #[derive(Debug)]
 pub struct Texture {
-    pub bind_group: wgpu::BindGroup,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub view: wgpu::TextureView,
+    gpu: Rc<GpuTexture>,
     pub dimensions: (u32, u32),
+    /// The part of the GPU texture this texture covers, as `[x, y, width, height]` in texture
+    /// coordinates. This is [FULL_REGION] unless the texture is part of an atlas.
+    region: [f32; 4],
 }
 
 impl Texture {
@@ -146,7 +198,7 @@ impl Texture {
         format: wgpu::TextureFormat,
         size: (u32, u32),
     ) -> anyhow::Result<Self> {
-        let view = device.create_texture(&wgpu::TextureDescriptor {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size: wgpu::Extent3d {
                 width: size.0,
@@ -161,7 +213,7 @@ impl Texture {
             view_formats: &[],
         });
 
-        let view = view.create_view(&Default::default());
+        let view = texture.create_view(&Default::default());
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             mag_filter: wgpu::FilterMode::Linear,
@@ -184,12 +236,23 @@ impl Texture {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let allocation = Allocation::register(
+            ResourceUsage::default()
+                .texture(&texture)
+                .buffer(&vertex_buffer)
+                .buffer(&index_buffer),
+        );
+
         Ok(Self {
-            bind_group,
-            vertex_buffer,
-            index_buffer,
-            view,
+            gpu: Rc::new(GpuTexture {
+                bind_group,
+                vertex_buffer,
+                index_buffer,
+                view,
+                _allocation: allocation,
+            }),
             dimensions: size,
+            region: FULL_REGION,
         })
     }
 
@@ -199,10 +262,19 @@ impl Texture {
         queue: &wgpu::Queue,
     ) -> anyhow::Result<Self> {
         let name = path.as_ref().to_str().unwrap_or_default().to_string();
-        let image = image::load_from_memory(&std::fs::read(path)?)?;
+        let image = load_image(path)?;
 
-        let rgba = image.to_rgba8();
-        let dimensions = image.dimensions();
+        Ok(Self::from_image(&image, &name, device, queue))
+    }
+
+    /// Uploads an image to the GPU as a texture.
+    pub fn from_image(
+        rgba: &RgbaImage,
+        name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let dimensions = rgba.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -211,7 +283,7 @@ impl Texture {
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some(&name),
+            label: Some(name),
             size,
             mip_level_count: 1,
             sample_count: 1,
@@ -228,7 +300,7 @@ impl Texture {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(dimensions.0 * 4),
@@ -265,13 +337,200 @@ impl Texture {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        Ok(Self {
-            bind_group,
-            vertex_buffer,
-            index_buffer,
-            view,
+        let allocation = Allocation::register(
+            ResourceUsage::default()
+                .texture(&texture)
+                .buffer(&vertex_buffer)
+                .buffer(&index_buffer),
+        );
+
+        Self {
+            gpu: Rc::new(GpuTexture {
+                bind_group,
+                vertex_buffer,
+                index_buffer,
+                view,
+                _allocation: allocation,
+            }),
             dimensions,
-        })
+            region: FULL_REGION,
+        }
+    }
+
+    /// Returns a texture made of part of this one, `size` pixels big with its top left corner at
+    /// `position`. It shares this texture's GPU resources rather than copying them.
+    fn sub_texture(&self, position: (u32, u32), size: (u32, u32)) -> Self {
+        let (width, height) = (self.dimensions.0 as f32, self.dimensions.1 as f32);
+
+        Self {
+            gpu: Rc::clone(&self.gpu),
+            dimensions: size,
+            region: [
+                position.0 as f32 / width,
+                position.1 as f32 / height,
+                size.0 as f32 / width,
+                size.1 as f32 / height,
+            ],
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.gpu.view
+    }
+
+    /// The point in the middle of the texture, in pixels.
+    pub fn centre(&self) -> [f32; 2] {
+        [self.dimensions.0 as f32 / 2., self.dimensions.1 as f32 / 2.]
+    }
+
+    /// Whether the two textures are (parts of) the same GPU texture, e.g. two sprites on the same
+    /// atlas, and so can be drawn in the same [SpriteBatch].
+    pub fn shares_gpu_texture(&self, other: &Texture) -> bool {
+        Rc::ptr_eq(&self.gpu, &other.gpu)
+    }
+
+    /// Sets up a render pass to draw sprites with this texture. Only the instance buffer is left
+    /// to set.
+    fn bind<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+        has_depth: bool,
+    ) {
+        render_pass.set_pipeline(
+            renderer
+                .pipeline(if has_depth {
+                    "texture_depth"
+                } else {
+                    "texture"
+                })
+                .expect("texture render pipeline does not exist!"),
+        );
+        render_pass.set_vertex_buffer(0, self.gpu.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.gpu.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(0, &renderer.screen_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.gpu.bind_group, &[]);
+    }
+}
+
+/// Reads and decodes an image file.
+pub fn load_image<P: AsRef<Path>>(path: P) -> anyhow::Result<RgbaImage> {
+    Ok(image::load_from_memory(&std::fs::read(path)?)?.to_rgba8())
+}
+
+/// How many pixels are left empty around each image in an atlas, so that filtering doesn't bleed
+/// the edges of one image into the next.
+const ATLAS_PADDING: u32 = 2;
+
+/// Where each image is put in an atlas (or None if it didn't fit), and how big the atlas is.
+type AtlasPacking = (Vec<Option<(u32, u32)>>, (u32, u32));
+
+/// Works out where to put images of the given sizes in an atlas no more than `max_size` pixels
+/// across either way. The images are packed in rows ("shelves"), tallest first.
+///
+/// Returns the position of the top left corner of each image, or None for the images that didn't
+/// fit, along with how big the atlas has to be to hold them all.
+fn pack_atlas(sizes: &[(u32, u32)], max_size: u32) -> AtlasPacking {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut positions = vec![None; sizes.len()];
+    let mut atlas_size = (0, 0);
+    let (mut x, mut y) = (ATLAS_PADDING, ATLAS_PADDING);
+    let mut shelf_height = 0;
+
+    for i in order {
+        let (width, height) = sizes[i];
+
+        if x + width + ATLAS_PADDING > max_size && shelf_height > 0 {
+            // Start a new shelf
+            y += shelf_height + ATLAS_PADDING;
+            x = ATLAS_PADDING;
+            shelf_height = 0;
+        }
+
+        if x + width + ATLAS_PADDING > max_size || y + height + ATLAS_PADDING > max_size {
+            continue;
+        }
+
+        positions[i] = Some((x, y));
+        x += width + ATLAS_PADDING;
+        shelf_height = shelf_height.max(height);
+        atlas_size = (
+            atlas_size.0.max(x),
+            atlas_size.1.max(y + height + ATLAS_PADDING),
+        );
+    }
+
+    (positions, atlas_size)
+}
+
+/// Packs lots of small images into one texture on the GPU.
+///
+/// Each image comes back out as a [Texture] of its own that can be used anywhere any other
+/// texture can, but because they're all parts of the same GPU texture, sprites made with any of
+/// them can be drawn together in one [SpriteBatch]. Images too big to fit in the atlas are given a
+/// GPU texture of their own, so everything that's added comes back out.
+pub struct TextureAtlasBuilder<K> {
+    images: Vec<(K, RgbaImage)>,
+    max_size: u32,
+}
+
+impl<K> TextureAtlasBuilder<K> {
+    /// Creates an empty atlas that can be up to `max_size` pixels wide and tall.
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            images: Vec::new(),
+            max_size,
+        }
+    }
+
+    /// Adds an image to the atlas, to be given back with the given key.
+    pub fn add(&mut self, key: K, image: RgbaImage) {
+        self.images.push((key, image));
+    }
+
+    pub fn build(
+        self,
+        label: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<(K, Texture)> {
+        let sizes: Vec<_> = self
+            .images
+            .iter()
+            .map(|(_, image)| image.dimensions())
+            .collect();
+        let (positions, atlas_size) = pack_atlas(&sizes, self.max_size);
+
+        let atlas = (atlas_size.0 > 0).then(|| {
+            let mut atlas_image = RgbaImage::new(atlas_size.0, atlas_size.1);
+
+            for ((_, image), position) in self.images.iter().zip(&positions) {
+                if let Some((x, y)) = position {
+                    atlas_image
+                        .copy_from(image, *x, *y)
+                        .expect("images are packed inside the atlas");
+                }
+            }
+
+            Texture::from_image(&atlas_image, label, device, queue)
+        });
+
+        self.images
+            .into_iter()
+            .zip(positions)
+            .map(|((key, image), position)| {
+                let texture = match (&atlas, position) {
+                    (Some(atlas), Some(position)) => {
+                        atlas.sub_texture(position, image.dimensions())
+                    }
+                    _ => Texture::from_image(&image, label, device, queue),
+                };
+
+                (key, texture)
+            })
+            .collect()
     }
 }
 
@@ -291,14 +550,15 @@ impl Frame {
 struct SpriteInstanceController {
     position: [f32; 2],
     depth: Option<f32>,
-    instance_buffer: wgpu::Buffer,
+    scale: f32,
+    instance: InstanceSlot,
 }
 
 impl SpriteInstanceController {
     fn position_3d(&self, frame: &Frame) -> [f32; 3] {
         [
-            self.position[0] - frame.origin[0],
-            self.position[1] - frame.origin[1],
+            self.position[0] - frame.origin[0] * self.scale,
+            self.position[1] - frame.origin[1] * self.scale,
             self.depth.unwrap_or_default(),
         ]
     }
@@ -309,46 +569,29 @@ impl SpriteInstanceController {
         render_pass: &mut wgpu::RenderPass<'pass>,
         frame: &'pass Frame,
     ) {
-        render_pass.set_pipeline(
-            renderer
-                .pipeline(if self.depth.is_some() {
-                    "texture_depth"
-                } else {
-                    "texture"
-                })
-                .expect("texture render pipeline does not exist!"),
-        );
-        render_pass.set_vertex_buffer(0, frame.texture.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(
-            frame.texture.index_buffer.slice(..),
-            wgpu::IndexFormat::Uint16,
-        );
-        render_pass.set_bind_group(0, &renderer.screen_bind_group, &[]);
-        render_pass.set_bind_group(1, &frame.texture.bind_group, &[]);
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        let Some(instance) = renderer.sprite_instances.slice(&self.instance) else {
+            return;
+        };
+
+        frame
+            .texture
+            .bind(renderer, render_pass, self.depth.is_some());
+        render_pass.set_vertex_buffer(1, instance);
         render_pass.draw_indexed(0..6 as _, 0, 0..1);
     }
 
-    fn set_position(&mut self, position: [f32; 2], renderer: &Renderer, frame: &Frame) {
+    fn set_position(&mut self, position: [f32; 2], frame: &Frame) {
         self.position = position;
-        renderer.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[SpriteInstance {
-                position: self.position_3d(frame),
-            }]),
-        )
+        let position = self.position_3d(frame);
+        self.instance
+            .update(|instance| instance.position = position);
     }
 
-    fn set_depth(&mut self, depth: Option<f32>, renderer: &Renderer, frame: &Frame) {
+    fn set_depth(&mut self, depth: Option<f32>, frame: &Frame) {
         self.depth = depth;
-        renderer.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[SpriteInstance {
-                position: self.position_3d(frame),
-            }]),
-        )
+        let position = self.position_3d(frame);
+        self.instance
+            .update(|instance| instance.position = position);
     }
 }
 
@@ -358,33 +601,22 @@ pub struct Sprite {
     controller: SpriteInstanceController,
 }
 
+// Changes to sprites are written to the GPU by the renderer at the end of the frame (see
+// super::instances), so they don't actually need the renderer themselves.
 impl Sprite {
-    pub fn dimensions(&self) -> (u32, u32) {
-        self.frame.texture.dimensions
+    pub fn set_position(&mut self, position: [f32; 2], _renderer: &Renderer) {
+        self.controller.set_position(position, &self.frame)
     }
 
-    /// Returns the relative bounding box of the sprite.
-    ///
-    /// This will be two points, the top left corner and the bottom left corner of the box. This
-    /// box defines the bounds of the sprite *relative to its position*. So, if a sprite is 100x100
-    /// and centred, its bounding box will always be ([-50, -50], [50, 50]), regardless of its
-    /// actual position on the screen.
-    pub fn relative_bounding_box(&self) -> ([f32; 2], [f32; 2]) {
-        let dimensions = self.dimensions();
-        let (dx, dy) = (dimensions.0 as f32, dimensions.1 as f32);
-
-        let start = [-self.frame.origin[0], -self.frame.origin[1]];
-        let end = [start[0] + dx, start[1] + dy];
-        (start, end)
+    pub fn set_depth(&mut self, depth: Option<f32>, _renderer: &Renderer) {
+        self.controller.set_depth(depth, &self.frame)
     }
 
-    pub fn set_position(&mut self, position: [f32; 2], renderer: &Renderer) {
+    /// Sets the colour that every pixel of the sprite is multiplied by.
+    pub fn set_tint(&self, tint: [f32; 4], _renderer: &Renderer) {
         self.controller
-            .set_position(position, renderer, &self.frame)
-    }
-
-    pub fn set_depth(&mut self, depth: Option<f32>, renderer: &Renderer) {
-        self.controller.set_depth(depth, renderer, &self.frame)
+            .instance
+            .update(|instance| instance.tint = tint);
     }
 }
 
@@ -398,6 +630,142 @@ impl Renderable for Sprite {
     }
 }
 
+/// The fewest instances a [SpriteBatch]'s buffer has room for, so that a batch that only ever has
+/// a few sprites in it doesn't keep growing its buffer one at a time.
+const MIN_BATCH_CAPACITY: usize = 64;
+
+/// How many instances a batch's buffer should have room for to hold `len` of them.
+fn batch_capacity(len: usize) -> usize {
+    len.next_power_of_two().max(MIN_BATCH_CAPACITY)
+}
+
+/// Lots of sprites drawn with the same GPU texture, in a single instanced draw call.
+///
+/// Unlike a [Sprite], which has its own instance buffer that is written to every time it moves, a
+/// batch is refilled from scratch every frame: [clear](SpriteBatch::clear) it,
+/// [push](SpriteBatch::push) each sprite that should be drawn, then [upload](SpriteBatch::upload)
+/// them before rendering. The sprites can be drawn with any texture that
+/// [shares a GPU texture](Texture::shares_gpu_texture) with the batch's, so a batch made with one
+/// texture from an atlas (see [TextureAtlasBuilder]) can draw every texture on it.
+#[derive(Debug)]
+pub struct SpriteBatch {
+    texture: Rc<Texture>,
+    has_depth: bool,
+    instances: Vec<SpriteInstance>,
+    /// How many instances the instance buffer has room for
+    capacity: usize,
+    instance_buffer: wgpu::Buffer,
+    _allocation: Allocation,
+}
+
+impl SpriteBatch {
+    /// Creates an empty batch for drawing sprites with the given texture (or any other part of
+    /// the same GPU texture), which uses the depth buffer if `has_depth` is set.
+    pub fn new(renderer: &Renderer, texture: Rc<Texture>, has_depth: bool) -> Self {
+        let capacity = batch_capacity(0);
+        let (instance_buffer, allocation) = Self::create_instance_buffer(renderer, capacity);
+
+        Self {
+            texture,
+            has_depth,
+            instances: Vec::with_capacity(capacity),
+            capacity,
+            instance_buffer,
+            _allocation: allocation,
+        }
+    }
+
+    fn create_instance_buffer(renderer: &Renderer, capacity: usize) -> (wgpu::Buffer, Allocation) {
+        let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sprite batch instance buffer"),
+            size: (capacity * std::mem::size_of::<SpriteInstance>()) as _,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let allocation = Allocation::register(ResourceUsage::default().buffer(&buffer));
+
+        (buffer, allocation)
+    }
+
+    /// Whether sprites with the given texture can be drawn in this batch.
+    pub fn can_draw(&self, texture: &Texture) -> bool {
+        self.texture.shares_gpu_texture(texture)
+    }
+
+    /// Removes every sprite from the batch.
+    pub fn clear(&mut self) {
+        self.instances.clear();
+    }
+
+    /// Adds a sprite with the given texture to the batch, drawn with its origin (see
+    /// [SpriteBuilder::origin]) at the given position and multiplied by the given tint. The depth
+    /// is only used if the batch has one.
+    ///
+    /// Panics if the batch [can't draw](SpriteBatch::can_draw) the texture.
+    pub fn push(
+        &mut self,
+        texture: &Texture,
+        origin: [f32; 2],
+        position: [f32; 2],
+        depth: f32,
+        tint: [f32; 4],
+    ) {
+        assert!(
+            self.can_draw(texture),
+            "sprite batches can only draw one GPU texture"
+        );
+
+        self.instances.push(SpriteInstance {
+            position: [
+                position[0] - origin[0],
+                position[1] - origin[1],
+                if self.has_depth { depth } else { 0. },
+            ],
+            tint,
+            scale: 1.,
+            region: texture.region,
+        });
+    }
+
+    /// Writes the batch's sprites to the GPU, making the instance buffer bigger if they don't fit.
+    /// This has to be done after the sprites have changed for the changes to be drawn.
+    pub fn upload(&mut self, renderer: &Renderer) {
+        if self.instances.len() > self.capacity {
+            self.capacity = batch_capacity(self.instances.len());
+            (self.instance_buffer, self._allocation) =
+                Self::create_instance_buffer(renderer, self.capacity);
+        }
+
+        if !self.instances.is_empty() {
+            renderer.queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&self.instances),
+            );
+        }
+    }
+}
+
+impl Renderable for SpriteBatch {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        self.texture.bind(renderer, render_pass, self.has_depth);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.draw_indexed(
+            0..TEXTURE_INDICES.len() as _,
+            0,
+            0..self.instances.len() as _,
+        );
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum PlaybackState {
     Stopped,
@@ -425,14 +793,13 @@ impl AnimatedSprite {
         &self.frames[self.index]
     }
 
-    pub fn set_position(&mut self, position: [f32; 2], renderer: &Renderer) {
+    pub fn set_position(&mut self, position: [f32; 2], _renderer: &Renderer) {
         self.controller
-            .set_position(position, renderer, &self.frames[self.index])
+            .set_position(position, &self.frames[self.index])
     }
 
-    pub fn set_depth(&mut self, depth: Option<f32>, renderer: &Renderer) {
-        self.controller
-            .set_depth(depth, renderer, &self.frames[self.index])
+    pub fn set_depth(&mut self, depth: Option<f32>, _renderer: &Renderer) {
+        self.controller.set_depth(depth, &self.frames[self.index])
     }
 
     pub fn set_index(&mut self, index: usize, renderer: &Renderer) {
@@ -442,8 +809,17 @@ impl AnimatedSprite {
             self.frames.len()
         );
         self.index = index;
-        // Reset the position as the anchor point may have changed
-        self.set_position(self.controller.position, renderer)
+        // Reset the position as the anchor point may have changed, and the region as the frame
+        // may be somewhere else on the same atlas
+        self.set_position(self.controller.position, renderer);
+        let region = self.frames[index].texture.region;
+        self.controller
+            .instance
+            .update(|instance| instance.region = region);
+    }
+
+    pub fn set_playback_state(&mut self, state: PlaybackState) {
+        self.playback_state = state;
     }
 
     pub fn update(&mut self, delta_time: f32, renderer: &Renderer) {
@@ -479,6 +855,7 @@ pub struct SpriteBuilder {
     position: [f32; 2],
     depth: Option<f32>,
     origin: [f32; 2],
+    scale: f32,
 }
 
 impl SpriteBuilder {
@@ -488,6 +865,7 @@ impl SpriteBuilder {
             position: [0., 0.],
             depth: None,
             origin: [0., 0.],
+            scale: 1.,
         }
     }
 
@@ -523,30 +901,28 @@ impl SpriteBuilder {
     ///
     /// See [SpriteBuilder::origin]
     pub fn centre(mut self) -> Self {
-        let dimensions = self.texture.dimensions;
-        let centre = [dimensions.0 as f32 / 2., dimensions.1 as f32 / 2.];
-        self.origin = centre;
+        self.origin = self.texture.centre();
+        self
+    }
+
+    /// Draws the sprite bigger or smaller than its texture, by the given factor. The sprite is
+    /// scaled around its origin, so the origin stays at the sprite's position.
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
         self
     }
 
     pub fn build(self, renderer: &Renderer) -> Sprite {
         let instance = SpriteInstance {
-            position: [
-                self.position[0] - self.origin[0],
-                self.position[1] - self.origin[1],
+            scale: self.scale,
+            region: self.texture.region,
+            ..SpriteInstance::new([
+                self.position[0] - self.origin[0] * self.scale,
+                self.position[1] - self.origin[1] * self.scale,
                 self.depth.unwrap_or_default(),
-            ],
+            ])
         };
 
-        let instance_buffer =
-            renderer
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("sprite instance buffer"),
-                    contents: bytemuck::cast_slice(&[instance]),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
-
         Sprite {
             frame: Frame {
                 texture: self.texture,
@@ -555,7 +931,8 @@ impl SpriteBuilder {
             controller: SpriteInstanceController {
                 position: self.position,
                 depth: self.depth,
-                instance_buffer,
+                scale: self.scale,
+                instance: renderer.sprite_instances.allocate(instance),
             },
         }
     }
@@ -614,23 +991,16 @@ impl AnimatedSpriteBuilder {
     }
 
     pub fn build(self, renderer: &Renderer) -> AnimatedSprite {
+        let frame = &self.frames[self.index];
         let instance = SpriteInstance {
-            position: [
-                self.position[0] - self.frames[self.index].origin[0],
-                self.position[1] - self.frames[self.index].origin[1],
+            region: frame.texture.region,
+            ..SpriteInstance::new([
+                self.position[0] - frame.origin[0],
+                self.position[1] - frame.origin[1],
                 self.depth.unwrap_or_default(),
-            ],
+            ])
         };
 
-        let instance_buffer =
-            renderer
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("sprite instance buffer"),
-                    contents: bytemuck::cast_slice(&[instance]),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
-
         AnimatedSprite {
             frames: self.frames,
             index: self.index,
@@ -640,8 +1010,77 @@ impl AnimatedSpriteBuilder {
             controller: SpriteInstanceController {
                 position: self.position,
                 depth: self.depth,
-                instance_buffer,
+                scale: 1.,
+                instance: renderer.sprite_instances.allocate(instance),
             },
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Whether two packed images (or their padding) overlap.
+    fn overlaps(a: ((u32, u32), (u32, u32)), b: ((u32, u32), (u32, u32))) -> bool {
+        let (((ax, ay), (aw, ah)), ((bx, by), (bw, bh))) = (a, b);
+        ax < bx + bw + ATLAS_PADDING
+            && bx < ax + aw + ATLAS_PADDING
+            && ay < by + bh + ATLAS_PADDING
+            && by < ay + ah + ATLAS_PADDING
+    }
+
+    #[test]
+    fn test_pack_atlas() {
+        let sizes = [
+            (100, 100),
+            (150, 150),
+            (250, 100),
+            (450, 300),
+            (100, 100),
+            (380, 270),
+        ];
+        let (positions, atlas_size) = pack_atlas(&sizes, 1024);
+
+        let packed: Vec<_> = positions
+            .iter()
+            .zip(sizes)
+            .map(|(position, size)| (position.expect("every image fits"), size))
+            .collect();
+
+        for (i, &a) in packed.iter().enumerate() {
+            let ((x, y), (width, height)) = a;
+            assert!(x + width + ATLAS_PADDING <= atlas_size.0);
+            assert!(y + height + ATLAS_PADDING <= atlas_size.1);
+            assert!(atlas_size.0 <= 1024 && atlas_size.1 <= 1024);
+
+            for &b in &packed[i + 1..] {
+                assert!(!overlaps(a, b), "{a:?} and {b:?} overlap");
+            }
+        }
+    }
+
+    #[test]
+    fn test_pack_atlas_too_big() {
+        let (positions, atlas_size) = pack_atlas(&[(600, 10), (10, 10), (500, 520)], 512);
+
+        assert_eq!(
+            positions,
+            [None, Some((ATLAS_PADDING, ATLAS_PADDING)), None]
+        );
+        assert_eq!(atlas_size, (10 + ATLAS_PADDING * 2, 10 + ATLAS_PADDING * 2));
+
+        assert_eq!(pack_atlas(&[], 512), (vec![], (0, 0)));
+    }
+
+    #[test]
+    fn test_batch_capacity() {
+        assert_eq!(batch_capacity(0), MIN_BATCH_CAPACITY);
+        assert_eq!(batch_capacity(MIN_BATCH_CAPACITY), MIN_BATCH_CAPACITY);
+        assert_eq!(
+            batch_capacity(MIN_BATCH_CAPACITY + 1),
+            MIN_BATCH_CAPACITY * 2
+        );
+        assert_eq!(batch_capacity(1000), 1024);
+    }
+}
@@ -0,0 +1,364 @@
+//! Drawing scenes offscreen and comparing them with images of how they looked before (the
+//! "goldens" in [GOLDENS_DIR]), to catch changes to how things look that nobody meant to make.
+//!
+//! These tests need a GPU, so they only run with the `visual-tests` feature. A scene is drawn
+//! with [VisualTest::render] and checked with [assert_matches_golden]. Everything that goes into
+//! the scene should be fixed, so that it's drawn the same way every time: the time, any random
+//! numbers (use a seeded [Rng](crate::rng::Rng)), and the settings, which are left as the defaults.
+//! The fonts are the ones in `assets/fonts`, and egui's are built into egui.
+//!
+//! Run with `UPDATE_FIXTURES=1` to save what's drawn now as the goldens, instead of checking
+//! them. Make sure to look over what changed before committing them!
+use std::path::Path;
+
+use egui_wgpu::ScreenDescriptor;
+use image::{Rgba, RgbaImage};
+use winit::dpi::PhysicalSize;
+
+use super::{Renderer, CLEAR_COLOUR, SAMPLE_COUNT, VIRTUAL_HEIGHT, VIRTUAL_WIDTH};
+use crate::game::{KeyboardState, MouseState, RenderContext, TextureCache};
+use crate::settings::PresentMode;
+
+/// Where the golden images are kept.
+pub const GOLDENS_DIR: &str = "tests/goldens";
+
+/// Where the images of failed tests are saved, so that they can be looked at.
+const FAILURES_DIR: &str = "target/visual-tests";
+
+/// How far apart the channels of two pixels can be for the pixels to still count as the same.
+const CHANNEL_TOLERANCE: u8 = 4;
+
+/// The fraction of pixels that can differ from the golden image before the test fails. Different
+/// GPUs don't antialias edges in quite the same way, so a few pixels here and there are allowed.
+const MAX_DIFFERING: f64 = 0.001;
+
+/// How many frames of egui are run before the one that's drawn, so that windows have finished
+/// working out their size and fading in.
+const EGUI_FRAMES: usize = 10;
+
+/// The size scenes are drawn at, the same as the virtual screen so that nothing is letterboxed.
+const WIDTH: u32 = VIRTUAL_WIDTH as u32;
+const HEIGHT: u32 = VIRTUAL_HEIGHT as u32;
+
+impl Renderer {
+    /// Creates a renderer that draws offscreen rather than to a window.
+    pub fn headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        pollster::block_on(Self::new_async(
+            None,
+            PhysicalSize::new(width, height),
+            PresentMode::Vsync,
+        ))
+    }
+}
+
+/// Everything needed to set up and draw a scene offscreen.
+pub struct VisualTest {
+    pub renderer: Renderer,
+    pub textures: TextureCache,
+    keyboard: KeyboardState,
+    mouse: MouseState,
+}
+
+impl VisualTest {
+    pub fn new() -> Self {
+        Self {
+            renderer: Renderer::headless(WIDTH, HEIGHT).expect("couldn't create a renderer"),
+            textures: TextureCache::default(),
+            keyboard: KeyboardState::default(),
+            mouse: MouseState::default(),
+        }
+    }
+
+    /// Draws a scene, returning the image that would have been shown on screen. `ui` draws the
+    /// scene's egui windows (it's called for a few frames, see [EGUI_FRAMES]) and `draw` draws
+    /// everything else, in the same way as [GameState::render](crate::game::GameState::render).
+    pub fn render<S>(
+        &mut self,
+        scene: &mut S,
+        mut ui: impl FnMut(&mut S, &egui::Context),
+        draw: impl for<'pass> FnOnce(&'pass mut S, &mut RenderContext<'_, 'pass>),
+    ) -> RgbaImage {
+        let renderer = &mut self.renderer;
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [renderer.size.width, renderer.size.height],
+            pixels_per_point: 1.0,
+        };
+
+        let mut paint_jobs = Vec::new();
+
+        for frame in 0..EGUI_FRAMES {
+            let mut encoder =
+                renderer
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Visual test egui encoder"),
+                    });
+
+            renderer.egui_handler.begin_render_at(frame as f64 / 60.0);
+            ui(scene, &renderer.egui_handler.context());
+            paint_jobs = renderer.egui_handler.end_render(
+                &renderer.device,
+                &renderer.queue,
+                &mut encoder,
+                &screen_descriptor,
+                None,
+            );
+
+            renderer.queue.submit([encoder.finish()]);
+        }
+
+        renderer.sprite_instances.prepare(&renderer.device);
+
+        let renderer = &self.renderer;
+        let size = wgpu::Extent3d {
+            width: renderer.size.width,
+            height: renderer.size.height,
+            depth_or_array_layers: 1,
+        };
+
+        let target = renderer.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Visual test target"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: renderer.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = target.create_view(&Default::default());
+
+        let mut encoder = renderer
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Visual test encoder"),
+            });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Visual test pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: if SAMPLE_COUNT == 1 {
+                    &view
+                } else {
+                    renderer.msaa_view.as_ref().unwrap()
+                },
+                resolve_target: if SAMPLE_COUNT == 1 { None } else { Some(&view) },
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(CLEAR_COLOUR),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &renderer.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let mut ctx = RenderContext {
+            renderer,
+            textures: &mut self.textures,
+            keyboard: &self.keyboard,
+            mouse: &self.mouse,
+            render_pass: &mut render_pass,
+        };
+
+        draw(scene, &mut ctx);
+
+        renderer
+            .egui_handler
+            .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+
+        drop(render_pass);
+
+        renderer.sprite_instances.flush(&renderer.queue);
+        read_back(renderer, &target, size, encoder)
+    }
+}
+
+impl Default for VisualTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copies a texture back from the GPU into an image. The copy is added to `encoder`, which is then
+/// submitted.
+fn read_back(
+    renderer: &Renderer,
+    texture: &wgpu::Texture,
+    size: wgpu::Extent3d,
+    mut encoder: wgpu::CommandEncoder,
+) -> RgbaImage {
+    // Each row of the copy has to start on a multiple of the alignment
+    let row_bytes = size.width * 4;
+    let padded_row_bytes =
+        row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Visual test readback buffer"),
+        size: (padded_row_bytes * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+
+    renderer.queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("couldn't read the frame back from the GPU")
+    });
+    renderer.device.poll(wgpu::Maintain::Wait);
+
+    let data = slice.get_mapped_range();
+    let pixels = data
+        .chunks(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect();
+    drop(data);
+    buffer.unmap();
+
+    let mut image = RgbaImage::from_raw(size.width, size.height, pixels).unwrap();
+
+    // The window ignores alpha, so the images are made opaque to look like what's on screen
+    for pixel in image.pixels_mut() {
+        pixel[3] = u8::MAX;
+    }
+
+    image
+}
+
+/// Compares two images of the same size. Returns how many pixels differ by more than
+/// [CHANNEL_TOLERANCE], and an image of where they are: the differing pixels in red, on top of a
+/// faded copy of the expected image.
+fn diff(expected: &RgbaImage, actual: &RgbaImage) -> (usize, RgbaImage) {
+    let mut differing = 0;
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+
+    for ((expected, actual), out) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let same = expected
+            .0
+            .iter()
+            .zip(actual.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= CHANNEL_TOLERANCE);
+
+        *out = if same {
+            let [r, g, b, _] = expected.0.map(|c| c / 4);
+            Rgba([r, g, b, u8::MAX])
+        } else {
+            differing += 1;
+            Rgba([u8::MAX, 0, 0, u8::MAX])
+        };
+    }
+
+    (differing, diff)
+}
+
+/// Checks an image of a scene against the golden image with the given name. If they differ, the
+/// image and a diff (see [diff]) are saved to [FAILURES_DIR] and the test fails.
+pub fn assert_matches_golden(name: &str, actual: &RgbaImage) {
+    let golden = Path::new(GOLDENS_DIR).join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        std::fs::create_dir_all(GOLDENS_DIR).unwrap();
+        actual.save(&golden).unwrap();
+        return;
+    }
+
+    let expected = match image::open(&golden) {
+        Ok(expected) => expected.to_rgba8(),
+        Err(e) => panic!(
+            "couldn't read {}: {e}\nrun with UPDATE_FIXTURES=1 to create it",
+            golden.display()
+        ),
+    };
+
+    let failure = if expected.dimensions() != actual.dimensions() {
+        Some((
+            format!(
+                "it's {:?} but the golden is {:?}",
+                actual.dimensions(),
+                expected.dimensions()
+            ),
+            None,
+        ))
+    } else {
+        let (differing, diff) = diff(&expected, actual);
+        let total = expected.width() as usize * expected.height() as usize;
+
+        (differing as f64 > total as f64 * MAX_DIFFERING).then(|| {
+            (
+                format!("{differing} of {total} pixels are different"),
+                Some(diff),
+            )
+        })
+    };
+
+    let Some((reason, diff)) = failure else {
+        return;
+    };
+
+    std::fs::create_dir_all(FAILURES_DIR).unwrap();
+    let failed = Path::new(FAILURES_DIR).join(format!("{name}.png"));
+    actual.save(&failed).unwrap();
+
+    if let Some(diff) = diff {
+        diff.save(Path::new(FAILURES_DIR).join(format!("{name}-diff.png")))
+            .unwrap();
+    }
+
+    panic!(
+        "{name} doesn't look like {}: {reason}\nwhat was drawn is in {}, alongside a diff",
+        golden.display(),
+        failed.display()
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let mut actual = expected.clone();
+
+        // Within the tolerance
+        actual.put_pixel(0, 0, Rgba([100 + CHANNEL_TOLERANCE, 100, 96, 255]));
+        assert_eq!(diff(&expected, &actual).0, 0);
+
+        actual.put_pixel(1, 2, Rgba([100, 100 + CHANNEL_TOLERANCE + 1, 100, 255]));
+        let (differing, diff) = diff(&expected, &actual);
+        assert_eq!(differing, 1);
+        assert_eq!(*diff.get_pixel(1, 2), Rgba([255, 0, 0, 255]));
+        assert_eq!(*diff.get_pixel(0, 0), Rgba([25, 25, 25, 255]));
+    }
+}
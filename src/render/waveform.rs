@@ -0,0 +1,297 @@
+//! Drawing the shape of a song's audio.
+//!
+//! Decoding a song gives millions of samples, far too many to look at every time the waveform is
+//! drawn, so they're first boiled down into [Peaks]: the quietest and loudest sample in each few
+//! milliseconds of the song. A [Waveform] draws those peaks as a row of vertical lines, one for each
+//! couple of pixels, over whichever part of the song its [WaveformView] is looking at. The view
+//! can be scrolled along the song and zoomed in and out, which rebuilds the lines.
+use std::ops::Range;
+use std::sync::Arc;
+
+use kira::sound::static_sound::StaticSoundData;
+
+use super::shapes::{Shape, ShapeBuilder, SolidColour};
+use super::{Renderable, Renderer};
+
+/// How much of the song each bucket covers, in seconds.
+const BUCKET_LENGTH: f64 = 0.005;
+
+/// How many pixels wide each line of a [Waveform] is.
+const LINE_WIDTH: f32 = 2.0;
+
+/// The most zoomed in a view can be. Any further and one bucket would be drawn over many lines.
+const MIN_SECONDS_PER_PIXEL: f64 = BUCKET_LENGTH / LINE_WIDTH as f64;
+
+/// A song's audio, boiled down to the quietest and loudest sample in each short stretch of it.
+#[derive(Debug, Clone)]
+pub struct Peaks {
+    /// The lowest and highest sample in each bucket, with both channels mixed together
+    buckets: Vec<(f32, f32)>,
+    /// How long each bucket is, in seconds. This is only roughly [BUCKET_LENGTH], as each bucket
+    /// holds a whole number of frames.
+    bucket_length: f64,
+}
+
+impl Peaks {
+    pub fn new(sound_data: &StaticSoundData) -> Self {
+        let frames_per_bucket = (sound_data.sample_rate as f64 * BUCKET_LENGTH).max(1.0) as usize;
+
+        let buckets = sound_data
+            .frames
+            .chunks(frames_per_bucket)
+            .map(|frames| {
+                frames
+                    .iter()
+                    .map(|frame| (frame.left + frame.right) / 2.0)
+                    .fold((f32::MAX, f32::MIN), |(low, high), sample| {
+                        (low.min(sample), high.max(sample))
+                    })
+            })
+            .collect();
+
+        Self {
+            buckets,
+            bucket_length: frames_per_bucket as f64 / sound_data.sample_rate.max(1) as f64,
+        }
+    }
+
+    /// The lowest and highest sample between two times in the song, or None if there isn't any
+    /// audio then.
+    pub fn peak(&self, times: Range<f64>) -> Option<(f32, f32)> {
+        if times.end <= 0.0 {
+            return None;
+        }
+
+        let bucket_of = |time: f64| (time / self.bucket_length).max(0.0) as usize;
+        let start = bucket_of(times.start);
+        // Even a very short range covers the bucket it's in
+        let end = bucket_of(times.end).max(start + 1).min(self.buckets.len());
+
+        let buckets = self.buckets.get(start..end).filter(|b| !b.is_empty())?;
+        Some(
+            buckets
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(low, high), &(min, max)| {
+                    (low.min(min), high.max(max))
+                }),
+        )
+    }
+}
+
+/// Which part of the song a [Waveform] shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaveformView {
+    /// The time at the left edge of the waveform, in seconds. This can be negative, to leave room
+    /// before the song starts.
+    pub start: f64,
+    /// How much of the song each pixel covers, in seconds. Smaller is more zoomed in.
+    pub seconds_per_pixel: f64,
+}
+
+impl WaveformView {
+    /// The time in the song at the given distance from the left edge of the waveform.
+    pub fn time_at(&self, x: f32) -> f64 {
+        self.start + x as f64 * self.seconds_per_pixel
+    }
+
+    /// The view zoomed in by the given factor (or out, if it's less than 1), keeping whatever is
+    /// at `x` pixels from the left edge where it is.
+    pub fn zoomed(self, factor: f64, x: f32) -> Self {
+        let anchor = self.time_at(x);
+        let seconds_per_pixel = (self.seconds_per_pixel / factor).max(MIN_SECONDS_PER_PIXEL);
+
+        Self {
+            start: anchor - x as f64 * seconds_per_pixel,
+            seconds_per_pixel,
+        }
+    }
+
+    /// The part of the song covered by each line of a waveform of the given width, along with how
+    /// far from the left edge each line is.
+    fn lines(&self, width: f32) -> impl Iterator<Item = (f32, Range<f64>)> + '_ {
+        let count = (width / LINE_WIDTH).ceil() as usize;
+
+        (0..count).map(move |i| {
+            let x = i as f32 * LINE_WIDTH;
+            (x, self.time_at(x)..self.time_at(x + LINE_WIDTH))
+        })
+    }
+}
+
+/// A song's waveform, drawn with a shape on the GPU.
+///
+/// The shape only holds the lines that can be seen, so it's rebuilt whenever the view changes.
+/// Recolouring the waveform doesn't rebuild it.
+#[derive(Debug)]
+pub struct Waveform {
+    peaks: Arc<Peaks>,
+    view: WaveformView,
+    position: [f32; 3],
+    size: [f32; 2],
+    colour: [f32; 4],
+    shape: Shape,
+}
+
+impl Waveform {
+    /// Creates a waveform with its top left corner at the given position.
+    pub fn new(
+        peaks: Arc<Peaks>,
+        view: WaveformView,
+        position: [f32; 3],
+        size: [f32; 2],
+        renderer: &Renderer,
+    ) -> anyhow::Result<Self> {
+        let shape = build_shape(&peaks, view, position, size, &renderer.device)?;
+
+        Ok(Self {
+            peaks,
+            view,
+            position,
+            size,
+            colour: [1.0; 4],
+            shape,
+        })
+    }
+
+    pub fn peaks(&self) -> &Arc<Peaks> {
+        &self.peaks
+    }
+
+    pub fn view(&self) -> WaveformView {
+        self.view
+    }
+
+    /// Shows a different part of the song, rebuilding the waveform if the view has changed.
+    pub fn set_view(&mut self, view: WaveformView, renderer: &Renderer) -> anyhow::Result<()> {
+        if view == self.view {
+            return Ok(());
+        }
+
+        self.view = view;
+        self.shape = build_shape(
+            &self.peaks,
+            view,
+            self.position,
+            self.size,
+            &renderer.device,
+        )?;
+        self.shape.set_tint(self.colour, renderer);
+        Ok(())
+    }
+
+    /// Scrolls the waveform so that its left edge is at the given time in the song.
+    pub fn scroll_to(&mut self, start: f64, renderer: &Renderer) -> anyhow::Result<()> {
+        self.set_view(WaveformView { start, ..self.view }, renderer)
+    }
+
+    /// Zooms in by the given factor (or out, if it's less than 1) around the point `x` pixels from
+    /// the waveform's left edge.
+    pub fn zoom(&mut self, factor: f64, x: f32, renderer: &Renderer) -> anyhow::Result<()> {
+        self.set_view(self.view.zoomed(factor, x), renderer)
+    }
+
+    pub fn set_colour(&mut self, colour: [f32; 4], renderer: &Renderer) {
+        self.colour = colour;
+        self.shape.set_tint(colour, renderer);
+    }
+}
+
+/// Builds the lines of the waveform that can be seen in the view. The lines are white, so that the
+/// shape's tint gives them their colour.
+fn build_shape(
+    peaks: &Peaks,
+    view: WaveformView,
+    position: [f32; 3],
+    [width, height]: [f32; 2],
+    device: &wgpu::Device,
+) -> anyhow::Result<Shape> {
+    let y_of = |sample: f32| height / 2.0 - sample.clamp(-1.0, 1.0) * height / 2.0;
+    let mut builder = ShapeBuilder::new().position(position);
+
+    for (x, times) in view.lines(width) {
+        if let Some((low, high)) = peaks.peak(times) {
+            // Silence still gets a line, so that it can be seen where the song is
+            let bottom = y_of(low).max(y_of(high) + 1.0);
+
+            builder = builder.filled_rectangle(
+                [x, y_of(high)],
+                [(x + LINE_WIDTH).min(width), bottom],
+                SolidColour::new([1.0; 4]),
+            )?;
+        }
+    }
+
+    Ok(builder.build(device))
+}
+
+impl Renderable for Waveform {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.shape.render(renderer, render_pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use kira::dsp::Frame;
+
+    use super::*;
+
+    #[test]
+    fn test_peaks() {
+        // One second of silence, then one second of a square wave on one channel
+        let frames = (0..2000)
+            .map(|i| match i {
+                0..=999 => Frame::ZERO,
+                _ if i % 2 == 0 => Frame::new(0.8, 0.0),
+                _ => Frame::new(-0.8, 0.0),
+            })
+            .collect::<Vec<_>>();
+        let peaks = Peaks::new(&StaticSoundData {
+            sample_rate: 1000,
+            frames: Arc::from(frames),
+            settings: Default::default(),
+        });
+
+        assert_eq!(peaks.peak(0.0..0.5), Some((0.0, 0.0)));
+        assert_eq!(peaks.peak(1.2..1.5), Some((-0.4, 0.4)));
+        assert_eq!(peaks.peak(0.5..1.5), Some((-0.4, 0.4)));
+
+        // A range shorter than a bucket still finds the bucket it's in
+        assert_eq!(peaks.peak(1.2..1.2), Some((-0.4, 0.4)));
+
+        // There's nothing before or after the song
+        assert_eq!(peaks.peak(-1.0..-0.5), None);
+        assert_eq!(peaks.peak(3.0..4.0), None);
+    }
+
+    #[test]
+    fn test_view() {
+        let view = WaveformView {
+            start: -1.0,
+            seconds_per_pixel: 0.01,
+        };
+        assert_eq!(view.time_at(0.0), -1.0);
+        assert_eq!(view.time_at(500.0), 4.0);
+
+        // Zooming keeps the time under the given point where it was
+        let zoomed = view.zoomed(4.0, 200.0);
+        assert_eq!(zoomed.seconds_per_pixel, 0.0025);
+        assert!((zoomed.time_at(200.0) - 1.0).abs() < 1e-9);
+
+        // But can only go so far
+        assert_eq!(
+            view.zoomed(1e6, 0.0).seconds_per_pixel,
+            MIN_SECONDS_PER_PIXEL
+        );
+
+        // The lines cover the whole width, one after the other
+        let lines: Vec<_> = view.lines(5.0).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], (0.0, -1.0..-0.98));
+        assert_eq!(lines[2].0, 4.0);
+    }
+}
@@ -0,0 +1,98 @@
+//! Random numbers for anything in the game that should look random but play out the same way every
+//! time (e.g. so that a replay of a play looks exactly like the play itself).
+//!
+//! Randomness should always come from an [Rng] that was seeded from something stable, like the
+//! hash of the chart being played, rather than from the system clock. Anything that needs its own
+//! stream of numbers should [fork](Rng::fork) one off an existing generator, so that how many
+//! numbers it uses doesn't change the numbers anything else gets.
+
+/// A small, fast, seeded random number generator (SplitMix64). Not suitable for anything that has
+/// to be unpredictable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Creates a generator seeded from the first 8 bytes of a hash (or any other bytes). Missing
+    /// bytes count as zeros.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut seed = [0; 8];
+        let len = bytes.len().min(8);
+        seed[..len].copy_from_slice(&bytes[..len]);
+        Self::new(u64::from_le_bytes(seed))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in the range [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        // The top 24 bits are exactly representable as an f32
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a number in the range [min, max).
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Returns a random element of a slice, or None if it's empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            items.get((self.next_u64() % items.len() as u64) as usize)
+        }
+    }
+
+    /// Creates a new generator from this one, with its own independent stream of numbers.
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_numbers() {
+        let mut a = Rng::new(1234);
+        let mut b = Rng::new(1234);
+        let mut c = Rng::new(1235);
+
+        let a_numbers = (0..10).map(|_| a.next_u64()).collect::<Vec<_>>();
+        let b_numbers = (0..10).map(|_| b.next_u64()).collect::<Vec<_>>();
+        let c_numbers = (0..10).map(|_| c.next_u64()).collect::<Vec<_>>();
+
+        assert_eq!(a_numbers, b_numbers);
+        assert_ne!(a_numbers, c_numbers);
+        assert_eq!(Rng::from_bytes(&[1, 2]), Rng::new(0x0201));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(0);
+
+        for _ in 0..1000 {
+            let x = rng.next_f32();
+            assert!((0.0..1.0).contains(&x));
+
+            let y = rng.range(-5.0, 5.0);
+            assert!((-5.0..5.0).contains(&y));
+        }
+
+        assert_eq!(rng.choose::<u8>(&[]), None);
+        assert_eq!(rng.choose(&[7]), Some(&7));
+    }
+}
@@ -2,29 +2,152 @@
 //!
 //! The settings for lunataiko are stored in a toml file (by default `taiko_settings.toml`). Use
 //! the function [read_settings] to read this config from file.
-use std::ops::Deref;
-use std::sync::RwLock;
+//!
+//! The current settings are kept behind an [Arc], and changing them swaps in a new one. So reading
+//! the settings only takes the lock for as long as it takes to clone the Arc, and anything that
+//! reads them often (like gameplay, on every keypress) can keep a [SettingsSnapshot] instead and
+//! check [settings_revision] once a frame to see whether it needs a new one.
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
 
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
 /// The path to the settings file
 pub const SETTINGS_PATH: &str = "taiko_settings.toml";
 
-pub static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
-    visual: VisualSettings {
-        resolution: ResolutionState::BorderlessFullscreen,
-    },
-    game: GameSettings {
-        global_note_offset: 0.0,
-        key_mappings: KeyMap::default_mapping(),
-    },
-});
+lazy_static! {
+    static ref SETTINGS: RwLock<Arc<Settings>> = RwLock::new(Arc::new(Settings::default()));
+}
+
+static SETTINGS_REVISION: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current settings. Changes made after this is called don't affect what it returned,
+/// so it's fine to hold on to.
+pub fn settings() -> Arc<Settings> {
+    Arc::clone(&SETTINGS.read().unwrap())
+}
+
+/// Returns the settings to be changed. Once the returned guard is dropped, the changes are given
+/// to anyone reading the settings from then on, and the [settings_revision] is bumped.
+///
+/// Settings should always be changed through this function, so that anything caching values
+/// derived from the settings knows to rebuild them.
+pub fn settings_mut() -> SettingsWriteGuard {
+    SettingsWriteGuard(SETTINGS.write().unwrap())
+}
+
+/// A number that changes every time the settings might have been changed.
+pub fn settings_revision() -> u64 {
+    SETTINGS_REVISION.load(Ordering::Acquire)
+}
+
+/// Lets the settings be changed. See [settings_mut].
+pub struct SettingsWriteGuard(RwLockWriteGuard<'static, Arc<Settings>>);
+
+impl Deref for SettingsWriteGuard {
+    type Target = Settings;
+
+    fn deref(&self) -> &Settings {
+        &self.0
+    }
+}
+
+impl DerefMut for SettingsWriteGuard {
+    /// Anyone still holding the old settings keeps them, so the settings are copied the first time
+    /// they're changed through this guard.
+    fn deref_mut(&mut self) -> &mut Settings {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+impl Drop for SettingsWriteGuard {
+    fn drop(&mut self) {
+        // The lock is still held here, so anyone who sees the new revision and reads the settings
+        // will wait for the new ones.
+        SETTINGS_REVISION.fetch_add(1, Ordering::Release);
+    }
+}
 
-/// Convenience function that returns an immutable reference to [settings::SETTINGS].
-/// Panics if the settings haven't been initialised yet.
-pub fn settings() -> impl Deref<Target = Settings> {
-    SETTINGS.read().unwrap()
+/// A copy of the settings that's only updated when asked to, for code that reads the settings too
+/// often to go through the lock each time.
+#[derive(Debug, Clone)]
+pub struct SettingsSnapshot {
+    settings: Arc<Settings>,
+    revision: u64,
+}
+
+impl SettingsSnapshot {
+    pub fn new() -> Self {
+        // The revision is read first, so that if the settings change in between, the next refresh
+        // picks them up.
+        let revision = settings_revision();
+
+        Self {
+            settings: settings(),
+            revision,
+        }
+    }
+
+    /// Picks up any changes to the settings since the snapshot was taken. Returns whether there
+    /// were any, so that anything worked out from the settings can be worked out again.
+    pub fn refresh(&mut self) -> bool {
+        let revision = settings_revision();
+        if revision == self.revision {
+            return false;
+        }
+
+        self.revision = revision;
+        self.settings = settings();
+        true
+    }
+}
+
+impl Default for SettingsSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for SettingsSnapshot {
+    type Target = Settings;
+
+    fn deref(&self) -> &Settings {
+        &self.settings
+    }
+}
+
+/// A copy of the settings that affect the gameplay HUD.
+///
+/// This is cheap to copy, so HUD elements can be handed one each time they show something instead
+/// of holding on to the settings they were created with, which means changes (e.g. from the pause
+/// menu) show up straight away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HudSettings {
+    pub judgement_style: JudgementStyle,
+    pub timing_meter: bool,
+    pub streamer_mode: bool,
+    pub streamer_readout: bool,
+}
+
+impl HudSettings {
+    /// Reads the HUD settings from the current settings.
+    pub fn current() -> Self {
+        Self::from(&settings().visual)
+    }
+}
+
+impl From<&VisualSettings> for HudSettings {
+    fn from(visual: &VisualSettings) -> Self {
+        Self {
+            judgement_style: visual.judgement_style,
+            timing_meter: visual.timing_meter,
+            streamer_mode: visual.streamer_mode,
+            streamer_readout: visual.streamer_readout,
+        }
+    }
 }
 
 /// All the settings for the game
@@ -35,43 +158,219 @@ pub struct Settings {
     pub game: GameSettings,
 }
 
-impl Settings {
-    pub fn key_is_don(&self, key: PhysicalKey) -> bool {
-        key == self.game.key_mappings.left_don || key == self.game.key_mappings.right_don
+/// The size of the window when fullscreen is turned off with F11 (see
+/// [ResolutionState::toggle_fullscreen]).
+pub const DEFAULT_WINDOW_SIZE: (u32, u32) = (1280, 720);
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(tag = "mode", content = "resolution")]
+pub enum ResolutionState {
+    #[default]
+    BorderlessFullscreen,
+    Windowed(u32, u32),
+    /// Exclusive fullscreen, which changes the monitor's resolution to this one (or the closest it
+    /// supports).
+    Fullscreen(u32, u32),
+}
+
+impl ResolutionState {
+    pub fn is_fullscreen(&self) -> bool {
+        !matches!(self, ResolutionState::Windowed(..))
     }
 
-    pub fn key_is_kat(&self, key: PhysicalKey) -> bool {
-        key == self.game.key_mappings.left_kat || key == self.game.key_mappings.right_kat
+    /// Switches between borderless fullscreen and a window, for F11.
+    pub fn toggle_fullscreen(&self) -> Self {
+        if self.is_fullscreen() {
+            ResolutionState::Windowed(DEFAULT_WINDOW_SIZE.0, DEFAULT_WINDOW_SIZE.1)
+        } else {
+            ResolutionState::BorderlessFullscreen
+        }
     }
 
-    pub fn key_is_don_or_kat(&self, key: PhysicalKey) -> bool {
-        self.key_is_don(key) || self.key_is_kat(key)
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResolutionState::BorderlessFullscreen => "Borderless fullscreen",
+            ResolutionState::Windowed(..) => "Windowed",
+            ResolutionState::Fullscreen(..) => "Exclusive fullscreen",
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-#[serde(tag = "mode", content = "resolution")]
-pub enum ResolutionState {
+/// How finished frames are shown on the screen.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Waits for the screen to refresh before showing a frame, so that there's no tearing.
     #[default]
-    BorderlessFullscreen,
-    Windowed(u32, u32),
-    Fullscreen(u32, u32),
+    Vsync,
+    /// Shows the newest frame when the screen refreshes, so there's no tearing but less delay than
+    /// vsync. Not every system supports this, in which case vsync is used instead.
+    Mailbox,
+    /// Shows each frame straight away, which has the least delay but might tear.
+    Immediate,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+impl PresentMode {
+    pub const ALL: [PresentMode; 3] = [
+        PresentMode::Vsync,
+        PresentMode::Mailbox,
+        PresentMode::Immediate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PresentMode::Vsync => "Vsync",
+            PresentMode::Mailbox => "Mailbox",
+            PresentMode::Immediate => "Immediate (no vsync)",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct VisualSettings {
     pub resolution: ResolutionState,
+    /// The name of the monitor to go fullscreen on. If this isn't set, or there's no monitor with
+    /// this name, the one the window is already on is used.
+    pub monitor: Option<String>,
+    pub present_mode: PresentMode,
+    /// The name of the skin's folder in [SKINS_PATH](crate::skin::SKINS_PATH). If this isn't set,
+    /// the game's own look is used.
+    pub skin: Option<String>,
+    /// A clean presentation mode for streaming/recording. Hides the version text and debug
+    /// overlays, and shows an input display during gameplay instead.
+    pub streamer_mode: bool,
+    /// Whether to show the large combo/accuracy readout while in streamer mode.
+    pub streamer_readout: bool,
+    /// Whether to show a bar under the receptacle showing how early or late each note was hit.
+    pub timing_meter: bool,
+    /// How fast notes scroll, as a multiple of the speed the chart asks for.
+    pub note_speed: f32,
+    /// Flips the playfield, so that the receptacle and side panel are on the right and notes travel
+    /// from left to right.
+    pub mirror_playfield: bool,
+    /// How much the background is darkened during gameplay, from 0 (not at all) to 1 (completely
+    /// black). This can be overridden for each chart from the pause menu.
+    pub background_dim: f32,
+    /// How opaque the header and note field are during gameplay, from 0 to 1. This can be
+    /// overridden for each chart too.
+    pub hud_opacity: f32,
+    /// How the judgement of each note is shown.
+    pub judgement_style: JudgementStyle,
+}
+
+/// The ways the judgement of a note can be shown during gameplay.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JudgementStyle {
+    /// Text that appears as soon as the note is hit and floats upwards.
+    #[default]
+    Text,
+    /// Text that bounces up with a burst of colour behind it.
+    Burst,
+}
+
+impl Default for VisualSettings {
+    fn default() -> Self {
+        Self {
+            resolution: ResolutionState::default(),
+            monitor: None,
+            present_mode: PresentMode::default(),
+            skin: None,
+            streamer_mode: false,
+            streamer_readout: true,
+            timing_meter: false,
+            note_speed: 1.0,
+            mirror_playfield: false,
+            background_dim: 0.6,
+            hud_opacity: 1.0,
+            judgement_style: JudgementStyle::default(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct GameSettings {
+    /// How much later the notes should be, in milliseconds. This is used when no offset profile is
+    /// active.
     pub global_note_offset: f32,
+    /// Note offsets for different audio setups, so that switching between e.g. speakers and
+    /// bluetooth headphones doesn't mean calibrating again every time.
+    pub offset_profiles: Vec<OffsetProfile>,
+    /// The index of the offset profile in use, if any.
+    pub active_offset_profile: Option<usize>,
     pub key_mappings: KeyMap,
+    /// The keys for the second player in two player mode. These are kept apart from
+    /// [GameSettings::key_mappings] (see [GameSettings::bind_key]).
+    pub player2_key_mappings: KeyMap,
+    /// Whether songs played at a practice rate should be time-stretched to keep their pitch,
+    /// rather than just played faster or slower.
+    pub preserve_pitch: bool,
+    /// The code of the language to show text in (e.g. "ja"), which must have a file in
+    /// [LANGUAGES_PATH](crate::localisation::LANGUAGES_PATH). If this isn't set, the game is in
+    /// english.
+    pub language: Option<String>,
+    /// The code of the language song titles are shown in (e.g. "en", see
+    /// [TITLE_LANGUAGES](crate::notechart_parser::TITLE_LANGUAGES)), for songs whose charts have
+    /// their title in that language. If this isn't set, titles are shown as they're written.
+    pub title_language: Option<String>,
+    /// The size of the buffer audio is played in, in frames. Smaller buffers mean less audio
+    /// latency, but the sound might crackle if it's too small. If this isn't set, the audio
+    /// device's default is used.
+    pub audio_buffer_size: Option<u32>,
+    /// How loud everything is, from 0 (silent) to 1 (as loud as it was recorded).
+    pub master_volume: f32,
+    /// How loud songs and the menu music are, on top of the master volume.
+    pub music_volume: f32,
+    /// How loud sound effects are, on top of the master volume.
+    pub effects_volume: f32,
+    /// How loud each of the drum's sounds is during gameplay, on top of the sound effect volume.
+    pub hit_sound_volumes: HitSoundVolumes,
+    /// If any drumroll is hit faster than this with a single key (in hits per second), the results
+    /// warn that a key might be stuck or a macro might be in use. Nobody can hit one key anywhere
+    /// near this fast, so this is set well out of reach to avoid flagging real plays.
+    pub mash_warning_rate: f32,
+    /// Whether to show what's being played on Discord. This only does anything if the game was
+    /// built with the `discord` feature.
+    pub discord_presence: bool,
+    /// Which rules drumrolls and balloons are scored by.
+    pub rules_preset: RulesPreset,
+    /// The rules songs are played by when the preset is [RulesPreset::Custom].
+    pub custom_rules: RulesConfig,
+    /// What happens when the soul gauge runs out partway through a song.
+    pub fail_behaviour: FailBehaviour,
+    /// The folder songs are found in. Songs can be sorted into folders inside it, as deep as you
+    /// like.
+    pub songs_dir: String,
+    /// How long the song carries on after the chart is over before the results are shown, in
+    /// seconds. The results are shown as soon as the song's audio ends, if that's sooner.
+    pub outro_delay: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The volume of each sound the drum makes, from 0 (silent) to 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct HitSoundVolumes {
+    pub don: f32,
+    pub kat: f32,
+    /// Hits on big notes and big drumrolls
+    pub big_don: f32,
+    pub big_kat: f32,
+    pub balloon_pop: f32,
+}
+
+impl Default for HitSoundVolumes {
+    fn default() -> Self {
+        Self {
+            don: 1.0,
+            kat: 1.0,
+            big_don: 1.0,
+            big_kat: 1.0,
+            balloon_pop: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct KeyMap {
     pub left_don: PhysicalKey,
@@ -84,13 +383,381 @@ impl Default for GameSettings {
     fn default() -> Self {
         Self {
             global_note_offset: 0.0,
+            offset_profiles: Vec::new(),
+            active_offset_profile: None,
             key_mappings: KeyMap::default(),
+            player2_key_mappings: KeyMap::player2(),
+            preserve_pitch: true,
+            language: None,
+            title_language: None,
+            audio_buffer_size: None,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            effects_volume: 0.8,
+            hit_sound_volumes: HitSoundVolumes::default(),
+            mash_warning_rate: 30.0,
+            discord_presence: false,
+            rules_preset: RulesPreset::default(),
+            custom_rules: RulesConfig::default(),
+            fail_behaviour: FailBehaviour::default(),
+            songs_dir: "songs".to_string(),
+            outro_delay: 2.0,
         }
     }
 }
 
+/// The sets of rules for how notes, drumrolls and balloons count, which different generations of
+/// the arcade game don't agree on.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RulesPreset {
+    /// Like the older arcade games (up to around AC15): notes are worth more as the combo grows,
+    /// roll hits are worth a lot, and popping a balloon gives a big bonus.
+    Classic,
+    /// Like the newer arcade games: a full combo is worth 1,000,000 points, roll hits are worth a
+    /// little, and popping a balloon gives nothing extra.
+    #[default]
+    Modern,
+    /// The rules in [GameSettings::custom_rules].
+    Custom,
+}
+
+impl RulesPreset {
+    pub const ALL: [RulesPreset; 3] = [
+        RulesPreset::Classic,
+        RulesPreset::Modern,
+        RulesPreset::Custom,
+    ];
+
+    /// The name of the preset, as it should be shown to the player.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RulesPreset::Classic => "Classic (AC15)",
+            RulesPreset::Modern => "Modern",
+            RulesPreset::Custom => "Custom",
+        }
+    }
+}
+
+/// What happens when the soul gauge runs out partway through a song. Either way, a song that
+/// ends with the gauge below the clear line isn't cleared.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FailBehaviour {
+    /// The song is always played to the end, like in the arcade game.
+    #[default]
+    PlayOn,
+    /// The stage is failed, and the song ends, as soon as a bad or a miss takes the gauge down to
+    /// empty.
+    SuddenDeath,
+}
+
+impl FailBehaviour {
+    pub const ALL: [FailBehaviour; 2] = [FailBehaviour::PlayOn, FailBehaviour::SuddenDeath];
+
+    /// The name of the behaviour, as it should be shown to the player.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailBehaviour::PlayOn => "Play to the end",
+            FailBehaviour::SuddenDeath => "Sudden death",
+        }
+    }
+}
+
+/// How hitting don and kat notes is scored. The details are in taiko mode's `scoring` module.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NoteScoring {
+    /// Every note is worth the same, and a full combo is worth 1,000,000 points (AC16 onwards).
+    #[default]
+    ShinUchi,
+    /// Notes are worth more as the combo grows, and more in go-go time (AC15 and earlier).
+    Combo,
+}
+
+impl NoteScoring {
+    pub const ALL: [NoteScoring; 2] = [NoteScoring::ShinUchi, NoteScoring::Combo];
+
+    /// The name of the scoring, as it should be shown to the player.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteScoring::ShinUchi => "Shin-uchi",
+            NoteScoring::Combo => "Combo bonuses",
+        }
+    }
+}
+
+/// The numbers behind a set of rules (see [RulesPreset]). Anything that's 0 doesn't count at all,
+/// e.g. roll hits don't fill the soul gauge unless [RulesConfig::roll_hit_health] is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct RulesConfig {
+    /// How don and kat notes are scored.
+    pub note_scoring: NoteScoring,
+    /// Points for each hit on a drumroll.
+    pub roll_hit_score: u64,
+    /// Points for each hit on a big drumroll.
+    pub big_roll_hit_score: u64,
+    /// Points for each hit on a balloon.
+    pub balloon_hit_score: u64,
+    /// Extra points for popping a balloon.
+    pub balloon_pop_score: u64,
+    /// How much each hit on a drumroll or balloon fills the soul gauge, out of
+    /// [MAX_HEALTH](crate::game::taiko_mode::MAX_HEALTH).
+    pub roll_hit_health: u32,
+    /// How much popping a balloon fills the soul gauge.
+    pub balloon_pop_health: u32,
+}
+
+impl RulesConfig {
+    pub const CLASSIC: RulesConfig = RulesConfig {
+        note_scoring: NoteScoring::Combo,
+        roll_hit_score: 300,
+        big_roll_hit_score: 360,
+        balloon_hit_score: 300,
+        balloon_pop_score: 5000,
+        roll_hit_health: 0,
+        balloon_pop_health: 0,
+    };
+
+    pub const MODERN: RulesConfig = RulesConfig {
+        note_scoring: NoteScoring::ShinUchi,
+        roll_hit_score: 100,
+        big_roll_hit_score: 200,
+        balloon_hit_score: 100,
+        balloon_pop_score: 0,
+        roll_hit_health: 0,
+        balloon_pop_health: 0,
+    };
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self::MODERN
+    }
+}
+
+/// A named note offset for one audio setup.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OffsetProfile {
+    pub name: String,
+    /// How much later the notes should be, in milliseconds.
+    pub offset: f32,
+    /// The audio device this profile was last chosen for. When audio starts coming out of this
+    /// device, the player is asked whether to switch to this profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl GameSettings {
+    /// The offset profile in use, if any.
+    pub fn active_offset_profile(&self) -> Option<&OffsetProfile> {
+        self.offset_profiles.get(self.active_offset_profile?)
+    }
+
+    /// How much later the notes should be in milliseconds, going by the active offset profile or
+    /// the global offset if there isn't one.
+    pub fn note_offset(&self) -> f32 {
+        self.active_offset_profile()
+            .map_or(self.global_note_offset, |profile| profile.offset)
+    }
+
+    /// The offset that [GameSettings::note_offset] reads, so that changing the offset (e.g. by
+    /// calibrating) only changes the active profile.
+    pub fn note_offset_mut(&mut self) -> &mut f32 {
+        match self.active_offset_profile {
+            Some(i) if i < self.offset_profiles.len() => &mut self.offset_profiles[i].offset,
+            _ => &mut self.global_note_offset,
+        }
+    }
+
+    /// The keys of the given player (0 for the first player, 1 for the second).
+    pub fn player_key_mappings(&self, player: usize) -> &KeyMap {
+        if player == 0 {
+            &self.key_mappings
+        } else {
+            &self.player2_key_mappings
+        }
+    }
+
+    /// Maps a key to a drum input of the given player (see [KeyMap::bind]). If the other player
+    /// was using the key, they get this input's old key instead, so that a key never plays for
+    /// both players.
+    pub fn bind_key(&mut self, player: usize, input: DrumInput, key: PhysicalKey) {
+        let (keys, others) = if player == 0 {
+            (&mut self.key_mappings, &mut self.player2_key_mappings)
+        } else {
+            (&mut self.player2_key_mappings, &mut self.key_mappings)
+        };
+
+        if let Some(other) = others.drum_input(key) {
+            *others.key_mut(other) = keys.key(input);
+        }
+
+        keys.bind(input, key);
+    }
+
+    /// Describes the active offset profile, for showing during gameplay.
+    pub fn offset_profile_label(&self) -> Option<String> {
+        self.active_offset_profile()
+            .map(|profile| format!("{} ({:+} ms)", profile.name, profile.offset))
+    }
+
+    /// Switches to an offset profile (or the global offset, if None). If the player chose it
+    /// because of the audio device they're using, the profile remembers the device, which is taken
+    /// off any other profile.
+    pub fn switch_offset_profile(&mut self, profile: Option<usize>, device: Option<&str>) {
+        self.active_offset_profile = profile;
+
+        if let Some(device) = device {
+            for (i, other) in self.offset_profiles.iter_mut().enumerate() {
+                if Some(i) == profile {
+                    other.device = Some(device.to_string());
+                } else if other.device.as_deref() == Some(device) {
+                    other.device = None;
+                }
+            }
+        }
+    }
+
+    /// The profile that was last chosen for an audio device, if it isn't the active one already.
+    pub fn suggested_offset_profile(&self, device: &str) -> Option<usize> {
+        self.offset_profiles
+            .iter()
+            .position(|profile| profile.device.as_deref() == Some(device))
+            .filter(|&i| Some(i) != self.active_offset_profile)
+    }
+
+    /// Adds a profile starting with the offset currently in use, and returns its index.
+    pub fn new_offset_profile(&mut self) -> usize {
+        let name = (1..)
+            .map(|i| format!("Profile {i}"))
+            .find(|name| self.offset_profiles.iter().all(|p| &p.name != name))
+            .unwrap();
+
+        self.offset_profiles.push(OffsetProfile {
+            name,
+            offset: self.note_offset(),
+            device: None,
+        });
+        self.offset_profiles.len() - 1
+    }
+
+    /// The rules songs are played by, going by the chosen preset. This is worked out when a song
+    /// starts, so changing the rules partway through a song doesn't change how it's scored.
+    pub fn rules(&self) -> RulesConfig {
+        match self.rules_preset {
+            RulesPreset::Classic => RulesConfig::CLASSIC,
+            RulesPreset::Modern => RulesConfig::MODERN,
+            RulesPreset::Custom => self.custom_rules,
+        }
+    }
+
+    /// Deletes a profile. If it was the active one, the global offset is used again.
+    pub fn remove_offset_profile(&mut self, profile: usize) {
+        self.offset_profiles.remove(profile);
+
+        self.active_offset_profile = match self.active_offset_profile {
+            Some(i) if i == profile => None,
+            Some(i) if i > profile => Some(i - 1),
+            active => active,
+        };
+    }
+}
+
+/// One of the four inputs on the drum.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrumInput {
+    LeftDon,
+    RightDon,
+    LeftKat,
+    RightKat,
+}
+
+impl DrumInput {
+    pub const ALL: [DrumInput; 4] = [
+        DrumInput::LeftDon,
+        DrumInput::RightDon,
+        DrumInput::LeftKat,
+        DrumInput::RightKat,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DrumInput::LeftDon => "Left don",
+            DrumInput::RightDon => "Right don",
+            DrumInput::LeftKat => "Left kat",
+            DrumInput::RightKat => "Right kat",
+        }
+    }
+
+    pub fn is_don(&self) -> bool {
+        matches!(self, DrumInput::LeftDon | DrumInput::RightDon)
+    }
+
+    pub fn is_left(&self) -> bool {
+        matches!(self, DrumInput::LeftDon | DrumInput::LeftKat)
+    }
+}
+
 impl KeyMap {
-    const fn default_mapping() -> Self {
+    /// The default keys for the second player, on the number pad so that they're out of the way of
+    /// the first player's.
+    pub fn player2() -> Self {
+        Self {
+            left_don: PhysicalKey::Code(KeyCode::Numpad2),
+            right_don: PhysicalKey::Code(KeyCode::Numpad3),
+            left_kat: PhysicalKey::Code(KeyCode::Numpad1),
+            right_kat: PhysicalKey::Code(KeyCode::NumpadEnter),
+        }
+    }
+
+    /// Returns which drum input the given key is mapped to, if any.
+    pub fn drum_input(&self, key: PhysicalKey) -> Option<DrumInput> {
+        if key == self.left_don {
+            Some(DrumInput::LeftDon)
+        } else if key == self.right_don {
+            Some(DrumInput::RightDon)
+        } else if key == self.left_kat {
+            Some(DrumInput::LeftKat)
+        } else if key == self.right_kat {
+            Some(DrumInput::RightKat)
+        } else {
+            None
+        }
+    }
+
+    /// The key the given drum input is mapped to.
+    pub fn key(&self, input: DrumInput) -> PhysicalKey {
+        match input {
+            DrumInput::LeftDon => self.left_don,
+            DrumInput::RightDon => self.right_don,
+            DrumInput::LeftKat => self.left_kat,
+            DrumInput::RightKat => self.right_kat,
+        }
+    }
+
+    fn key_mut(&mut self, input: DrumInput) -> &mut PhysicalKey {
+        match input {
+            DrumInput::LeftDon => &mut self.left_don,
+            DrumInput::RightDon => &mut self.right_don,
+            DrumInput::LeftKat => &mut self.left_kat,
+            DrumInput::RightKat => &mut self.right_kat,
+        }
+    }
+
+    /// Maps a key to the given drum input. If the key was already mapped to a different input,
+    /// that input gets this one's old key instead, so that no key is ever mapped twice.
+    pub fn bind(&mut self, input: DrumInput, key: PhysicalKey) {
+        let old_key = self.key(input);
+
+        if let Some(other) = self.drum_input(key) {
+            *self.key_mut(other) = old_key;
+        }
+
+        *self.key_mut(input) = key;
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
         Self {
             left_don: PhysicalKey::Code(KeyCode::KeyF),
             right_don: PhysicalKey::Code(KeyCode::KeyJ),
@@ -100,10 +767,61 @@ impl KeyMap {
     }
 }
 
-impl Default for KeyMap {
-    fn default() -> Self {
-        Self::default_mapping()
-    }
+/// Returns the name of a key, as it should be shown to the player.
+///
+/// Keys without a more readable name are shown with the name winit gives them.
+pub fn key_name(key: PhysicalKey) -> String {
+    let code = match key {
+        PhysicalKey::Code(code) => code,
+        PhysicalKey::Unidentified(native) => return format!("{native:?}"),
+    };
+
+    let name = match code {
+        KeyCode::Space => "Space",
+        KeyCode::Enter => "Enter",
+        KeyCode::Escape => "Esc",
+        KeyCode::Tab => "Tab",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::ShiftLeft => "Left Shift",
+        KeyCode::ShiftRight => "Right Shift",
+        KeyCode::ControlLeft => "Left Ctrl",
+        KeyCode::ControlRight => "Right Ctrl",
+        KeyCode::AltLeft => "Left Alt",
+        KeyCode::AltRight => "Right Alt",
+        KeyCode::CapsLock => "Caps Lock",
+        KeyCode::ArrowUp => "Up",
+        KeyCode::ArrowDown => "Down",
+        KeyCode::ArrowLeft => "Left",
+        KeyCode::ArrowRight => "Right",
+        KeyCode::Comma => ",",
+        KeyCode::Period => ".",
+        KeyCode::Slash => "/",
+        KeyCode::Backslash => "\\",
+        KeyCode::Semicolon => ";",
+        KeyCode::Quote => "'",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Minus => "-",
+        KeyCode::Equal => "=",
+        KeyCode::Backquote => "`",
+        _ => {
+            // Letters, numbers and the numpad all have a prefix on their debug name (e.g. KeyA)
+            let debug_name = format!("{code:?}");
+
+            return if let Some(name) = debug_name
+                .strip_prefix("Key")
+                .or_else(|| debug_name.strip_prefix("Digit"))
+            {
+                name.to_string()
+            } else if let Some(name) = debug_name.strip_prefix("Numpad") {
+                format!("Numpad {name}")
+            } else {
+                debug_name
+            };
+        }
+    };
+
+    name.to_string()
 }
 
 /// Try to ead and deserialize settings from the settings path.
@@ -142,7 +860,14 @@ pub fn read_settings() {
         }
     });
 
-    *SETTINGS.write().unwrap() = settings;
+    *settings_mut() = settings;
+}
+
+/// Writes the current settings to the settings path.
+pub fn save_settings() -> anyhow::Result<()> {
+    let contents = toml::to_string(&*settings())?;
+    std::fs::write(SETTINGS_PATH, contents)?;
+    Ok(())
 }
 
 /// Tries to read and deserialize config from the settings path.
@@ -172,3 +897,172 @@ impl From<toml::de::Error> for SettingsError {
         Self::InvalidSettings
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        let name = |code| key_name(PhysicalKey::Code(code));
+
+        assert_eq!(name(KeyCode::KeyF), "F");
+        assert_eq!(name(KeyCode::Digit7), "7");
+        assert_eq!(name(KeyCode::Numpad4), "Numpad 4");
+        assert_eq!(name(KeyCode::NumpadEnter), "Numpad Enter");
+        assert_eq!(name(KeyCode::F1), "F1");
+        assert_eq!(name(KeyCode::Space), "Space");
+        assert_eq!(name(KeyCode::ShiftLeft), "Left Shift");
+        assert_eq!(name(KeyCode::ArrowRight), "Right");
+        assert_eq!(name(KeyCode::Semicolon), ";");
+        assert_eq!(name(KeyCode::Backslash), "\\");
+
+        // Anything else falls back to winit's name for it
+        assert_eq!(name(KeyCode::MediaPlayPause), "MediaPlayPause");
+        let native = winit::keyboard::NativeKeyCode::Xkb(300);
+        assert_eq!(
+            key_name(PhysicalKey::Unidentified(native)),
+            format!("{native:?}")
+        );
+    }
+
+    #[test]
+    fn test_bind_keys() {
+        let key = |code| PhysicalKey::Code(code);
+        let mut keys = KeyMap::default();
+
+        keys.bind(DrumInput::LeftDon, key(KeyCode::KeyV));
+        assert_eq!(keys.key(DrumInput::LeftDon), key(KeyCode::KeyV));
+        assert_eq!(keys.drum_input(key(KeyCode::KeyF)), None);
+
+        // Taking another input's key swaps them
+        keys.bind(DrumInput::RightKat, key(KeyCode::KeyV));
+        assert_eq!(keys.key(DrumInput::RightKat), key(KeyCode::KeyV));
+        assert_eq!(keys.key(DrumInput::LeftDon), key(KeyCode::KeyK));
+
+        // Binding the same key again changes nothing
+        let before = keys.clone();
+        keys.bind(DrumInput::RightKat, key(KeyCode::KeyV));
+        assert_eq!(keys, before);
+
+        // Taking the other player's key swaps it with them
+        let mut game = GameSettings::default();
+        game.bind_key(1, DrumInput::LeftDon, key(KeyCode::KeyF));
+        assert_eq!(
+            game.player2_key_mappings.key(DrumInput::LeftDon),
+            key(KeyCode::KeyF)
+        );
+        assert_eq!(
+            game.key_mappings.key(DrumInput::LeftDon),
+            key(KeyCode::Numpad2)
+        );
+        assert_eq!(
+            game.player_key_mappings(0).drum_input(key(KeyCode::KeyF)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_toggle_fullscreen() {
+        let windowed = ResolutionState::Windowed(DEFAULT_WINDOW_SIZE.0, DEFAULT_WINDOW_SIZE.1);
+
+        assert_eq!(
+            ResolutionState::BorderlessFullscreen.toggle_fullscreen(),
+            windowed
+        );
+        assert_eq!(
+            ResolutionState::Fullscreen(2560, 1440).toggle_fullscreen(),
+            windowed
+        );
+        assert_eq!(
+            ResolutionState::Windowed(800, 600).toggle_fullscreen(),
+            ResolutionState::BorderlessFullscreen
+        );
+    }
+
+    #[test]
+    fn test_snapshots_see_changes_once_refreshed() {
+        let mut snapshot = SettingsSnapshot::new();
+        let before = settings().game.language.clone();
+
+        // Nothing else touches the language after startup, so it's safe to change here
+        settings_mut().game.language = Some("snapshot-test".to_string());
+
+        assert_eq!(snapshot.game.language, before);
+        assert!(snapshot.refresh());
+        assert_eq!(snapshot.game.language.as_deref(), Some("snapshot-test"));
+
+        // Settings that were read before the change don't change with it
+        let held = settings();
+        settings_mut().game.language = before.clone();
+        assert_eq!(held.game.language.as_deref(), Some("snapshot-test"));
+        assert_eq!(settings().game.language, before);
+    }
+
+    #[test]
+    fn test_offset_profiles() {
+        let mut game = GameSettings {
+            global_note_offset: 5.0,
+            ..Default::default()
+        };
+        assert_eq!(game.note_offset(), 5.0);
+        assert_eq!(game.offset_profile_label(), None);
+
+        // New profiles start from the offset in use
+        let speakers = game.new_offset_profile();
+        let headphones = game.new_offset_profile();
+        assert_eq!(game.offset_profiles[headphones].name, "Profile 2");
+        assert_eq!(game.offset_profiles[headphones].offset, 5.0);
+
+        game.switch_offset_profile(Some(headphones), None);
+        *game.note_offset_mut() = 180.0;
+        assert_eq!(game.note_offset(), 180.0);
+        assert_eq!(game.global_note_offset, 5.0);
+        assert_eq!(
+            game.offset_profile_label().as_deref(),
+            Some("Profile 2 (+180 ms)")
+        );
+
+        // Choosing a profile for a device takes the device off any other profile
+        game.switch_offset_profile(Some(speakers), Some("BT"));
+        game.switch_offset_profile(Some(headphones), Some("BT"));
+        assert_eq!(game.offset_profiles[speakers].device, None);
+        assert_eq!(game.suggested_offset_profile("BT"), None);
+
+        game.switch_offset_profile(None, None);
+        assert_eq!(game.note_offset(), 5.0);
+        assert_eq!(game.suggested_offset_profile("BT"), Some(headphones));
+        assert_eq!(game.suggested_offset_profile("Speakers"), None);
+
+        // Choosing the global offset for a device forgets any profile chosen for it before
+        game.switch_offset_profile(None, Some("BT"));
+        assert_eq!(game.suggested_offset_profile("BT"), None);
+        game.switch_offset_profile(Some(headphones), Some("BT"));
+        game.switch_offset_profile(None, None);
+
+        // Removing a profile keeps the active one pointing at the same profile
+        game.switch_offset_profile(Some(headphones), None);
+        game.remove_offset_profile(speakers);
+        assert_eq!(game.active_offset_profile().unwrap().offset, 180.0);
+        game.remove_offset_profile(0);
+        assert_eq!(game.active_offset_profile, None);
+        assert_eq!(game.note_offset(), 5.0);
+    }
+
+    #[test]
+    fn test_offset_profiles_round_trip() {
+        let mut settings = Settings::default();
+        let profile = settings.game.new_offset_profile();
+        settings
+            .game
+            .switch_offset_profile(Some(profile), Some("Headphones"));
+
+        let saved = toml::to_string(&settings).unwrap();
+        let loaded: Settings = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded.game, settings.game);
+
+        // Settings from before profiles existed still load
+        let loaded: Settings = toml::from_str("[game]\nglobal_note_offset = 12.0\n").unwrap();
+        assert_eq!(loaded.game.note_offset(), 12.0);
+    }
+}
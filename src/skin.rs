@@ -0,0 +1,236 @@
+//! Skins, which change how the game looks without it having to be recompiled.
+//!
+//! Each skin is a folder in [SKINS_PATH] with a `skin.toml` manifest in it, like this:
+//!
+//! ```toml
+//! name = "Night"
+//!
+//! [textures]
+//! # The game's name for the texture, and the file to use instead (in the skin's folder)
+//! "don.png" = "notes/red.png"
+//!
+//! [fonts]
+//! "MochiyPopOne-Regular.ttf" = "fonts/MyFont.ttf"
+//!
+//! [colours]
+//! header_top = "#101030"
+//! don = "#FF4060"
+//! ```
+//!
+//! Anything the skin doesn't mention is left as it is. A texture or font the skin doesn't list
+//! is still taken from its folder if there's a file with the same name there, so a skin can be as
+//! simple as a folder of images. Fonts are loaded when the renderer is created, so a skin's fonts
+//! are only used once the game is restarted.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+
+use crate::render::rgb;
+
+/// The folder skins are kept in.
+pub const SKINS_PATH: &str = "skins";
+
+/// The name of the manifest in each skin's folder.
+pub const MANIFEST_FILE: &str = "skin.toml";
+
+lazy_static! {
+    static ref SKIN: RwLock<Arc<Skin>> = RwLock::new(Arc::new(Skin::default()));
+}
+
+/// A colour in a skin, written as `"#RRGGBB"` or `"#RRGGBBAA"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Colour(pub [f32; 4]);
+
+impl<'de> Deserialize<'de> for Colour {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_colour(&text)
+            .map(Colour)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_colour(text: &str) -> anyhow::Result<[f32; 4]> {
+    let hex = text
+        .strip_prefix('#')
+        .ok_or_else(|| anyhow::anyhow!("colour \"{text}\" should start with #"))?;
+
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        anyhow::bail!("colour \"{text}\" should be #RRGGBB or #RRGGBBAA");
+    }
+
+    let mut colour = [1.; 4];
+    for (channel, i) in colour.iter_mut().zip((0..hex.len()).step_by(2)) {
+        *channel = u8::from_str_radix(&hex[i..i + 2], 16)? as f32 / 255.;
+    }
+
+    Ok(colour)
+}
+
+/// The colours of the gameplay screen.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SkinColours {
+    pub header_top: Colour,
+    pub header_bottom: Colour,
+    pub note_field: Colour,
+    /// The lines above and below the note field
+    pub spacer: Colour,
+    pub receptacle: Colour,
+    pub left_panel_top: Colour,
+    pub left_panel_bottom: Colour,
+    /// The colour dons are shown in, e.g. in the input display
+    pub don: Colour,
+    pub kat: Colour,
+}
+
+impl Default for SkinColours {
+    fn default() -> Self {
+        Self {
+            header_top: Colour(rgb!(30, 67, 198)),
+            header_bottom: Colour(rgb!(150, 90, 225)),
+            note_field: Colour(rgb!(45, 45, 45)),
+            spacer: Colour(rgb!(255, 235, 206)),
+            receptacle: Colour([0.26, 0.26, 0.26, 1.0]),
+            left_panel_top: Colour(rgb!(255, 73, 73)),
+            left_panel_bottom: Colour(rgb!(229, 41, 41)),
+            don: Colour(rgb!(0xF8, 0x48, 0x28)),
+            kat: Colour(rgb!(0x68, 0xC0, 0xC0)),
+        }
+    }
+}
+
+/// A skin, as read from its manifest.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Skin {
+    pub name: String,
+    /// Replacement textures, from the file name the game asks for to a file in the skin's folder
+    textures: HashMap<String, PathBuf>,
+    /// Replacement fonts, in the same way
+    fonts: HashMap<String, PathBuf>,
+    pub colours: SkinColours,
+    /// The skin's folder. The default skin doesn't have one.
+    #[serde(skip)]
+    dir: Option<PathBuf>,
+}
+
+impl Skin {
+    /// Reads the skin in the given folder.
+    pub fn read(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut skin: Skin = toml::from_str(&std::fs::read_to_string(dir.join(MANIFEST_FILE))?)?;
+        skin.dir = Some(dir.to_path_buf());
+        Ok(skin)
+    }
+
+    /// The file the skin uses for the texture with the given file name, if it replaces it.
+    pub fn texture_path(&self, filename: &str) -> Option<PathBuf> {
+        self.replacement(&self.textures, filename)
+    }
+
+    /// The file the skin uses for the font with the given file name, if it replaces it.
+    pub fn font_path(&self, filename: &str) -> Option<PathBuf> {
+        self.replacement(&self.fonts, filename)
+    }
+
+    fn replacement(&self, files: &HashMap<String, PathBuf>, filename: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+
+        match files.get(filename) {
+            Some(path) => Some(dir.join(path)),
+            None => Some(dir.join(filename)).filter(|path| path.is_file()),
+        }
+    }
+}
+
+/// Returns the skin in use.
+pub fn skin() -> Arc<Skin> {
+    Arc::clone(&SKIN.read().unwrap())
+}
+
+/// Switches to the skin in the folder with the given name in [SKINS_PATH], or back to the default
+/// look if there isn't one. Anything that's already been drawn keeps the old look until it's
+/// created again.
+pub fn load_skin(name: Option<&str>) -> anyhow::Result<()> {
+    let skin = match name {
+        Some(name) => Skin::read(Path::new(SKINS_PATH).join(name))?,
+        None => Skin::default(),
+    };
+
+    *SKIN.write().unwrap() = Arc::new(skin);
+    Ok(())
+}
+
+/// The names of the folders in [SKINS_PATH] that have a skin in them.
+pub fn available_skins() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(SKINS_PATH) else {
+        return Vec::new();
+    };
+
+    let mut skins: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join(MANIFEST_FILE).is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+
+    skins.sort();
+    skins
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_colour() {
+        assert_eq!(parse_colour("#FF0000").unwrap(), [1., 0., 0., 1.]);
+        assert_eq!(parse_colour("#00ff0000").unwrap(), [0., 1., 0., 0.]);
+        assert!(parse_colour("FF0000").is_err());
+        assert!(parse_colour("#FF00").is_err());
+        assert!(parse_colour("#GG0000").is_err());
+    }
+
+    #[test]
+    fn test_read_skin() {
+        let dir = std::env::temp_dir().join(format!("taiko-skin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            "
+            name = \"Test\"
+
+            [textures]
+            \"don.png\" = \"notes/red.png\"
+
+            [colours]
+            don = \"#FF0000\"
+            ",
+        )
+        .unwrap();
+        std::fs::write(dir.join("kat.png"), []).unwrap();
+
+        let skin = Skin::read(&dir).unwrap();
+        let texture_paths = ["don.png", "kat.png", "big_don.png"].map(|t| skin.texture_path(t));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(skin.name, "Test");
+        assert_eq!(skin.colours.don, Colour([1., 0., 0., 1.]));
+        // Colours that aren't mentioned are left alone
+        assert_eq!(skin.colours.kat, SkinColours::default().kat);
+        assert_eq!(
+            texture_paths,
+            [
+                Some(dir.join("notes/red.png")),
+                Some(dir.join("kat.png")),
+                None
+            ]
+        );
+
+        // The default skin doesn't replace anything
+        assert_eq!(Skin::default().texture_path("don.png"), None);
+    }
+}